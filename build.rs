@@ -0,0 +1,119 @@
+//! Records how rusticsearch was built, this is reported by the node info API
+//!
+//! Each value can be given in an environment variable of the same name instead, for
+//! builds made outside of a git checkout.
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+
+
+/// Runs a command and returns the first line that it printed
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = match Command::new(program).args(args).output() {
+        Ok(output) => output,
+        Err(_) => return None,
+    };
+
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8(output.stdout).ok().and_then(|stdout| stdout.lines().next().map(|line| line.trim().to_string()))
+}
+
+
+/// Finds the version of a package in Cargo.lock
+fn locked_version(lock_file: &Path, package: &str) -> Option<String> {
+    let mut contents = String::new();
+    match File::open(lock_file) {
+        Ok(mut file) => {
+            if file.read_to_string(&mut contents).is_err() {
+                return None;
+            }
+        }
+        Err(_) => return None,
+    }
+
+    let name_line = format!("name = \"{}\"", package);
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == name_line {
+            let version_line = lines.next().unwrap_or("").trim();
+            if version_line.starts_with("version = \"") {
+                return Some(version_line["version = \"".len()..].trim_end_matches('"').to_string());
+            }
+        }
+    }
+
+    None
+}
+
+
+/// Finds the file of the branch that is checked out, if HEAD points at one
+///
+/// ".git/HEAD" only changes when another branch is checked out, committing writes
+/// to the branch's file (or to ".git/packed-refs" once the refs have been packed).
+fn head_ref(git_dir: &Path) -> Option<String> {
+    let mut contents = String::new();
+    match File::open(git_dir.join("HEAD")) {
+        Ok(mut file) => {
+            if file.read_to_string(&mut contents).is_err() {
+                return None;
+            }
+        }
+        Err(_) => return None,
+    }
+
+    let contents = contents.trim();
+    if contents.starts_with("ref: ") {
+        Some(contents["ref: ".len()..].to_string())
+    } else {
+        None
+    }
+}
+
+
+fn set_env(name: &str, value: Option<String>) {
+    println!("cargo:rerun-if-env-changed={}", name);
+
+    let value = env::var(name).ok().or(value).unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env={}={}", name, value);
+}
+
+
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let manifest_dir = Path::new(&manifest_dir);
+
+    // Rebuild when a commit is checked out or the dependencies change
+    let mut watched_paths = vec![".git/HEAD".to_string(), "Cargo.lock".to_string()];
+    if let Some(head_ref) = head_ref(&manifest_dir.join(".git")) {
+        watched_paths.push(format!(".git/{}", head_ref));
+        watched_paths.push(".git/packed-refs".to_string());
+    }
+
+    for path in watched_paths.iter() {
+        if manifest_dir.join(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+
+    set_env("RUSTICSEARCH_BUILD_HASH", command_output("git", &["rev-parse", "HEAD"]));
+    set_env("RUSTICSEARCH_RUSTC_VERSION", command_output(&rustc, &["--version"]));
+
+    // The version of the bundled RocksDB library follows the version of librocksdb-sys
+    set_env("RUSTICSEARCH_ROCKSDB_VERSION", locked_version(&manifest_dir.join("Cargo.lock"), "librocksdb-sys"));
+
+    // Cargo gives the enabled features as "CARGO_FEATURE_<NAME>" variables
+    let mut features = env::vars()
+        .filter(|&(ref name, _)| name.starts_with("CARGO_FEATURE_"))
+        .map(|(name, _)| name["CARGO_FEATURE_".len()..].to_lowercase().replace('_', "-"))
+        .collect::<Vec<_>>();
+    features.sort();
+    println!("cargo:rustc-env=RUSTICSEARCH_FEATURES={}", features.join(","));
+}
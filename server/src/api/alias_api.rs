@@ -1,4 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeSet};
+
+use chrono::Utc;
+
+use rusticsearch::cluster::metadata::state::ClusterStateError;
 
 use api::persistent;
 use api::iron::prelude::*;
@@ -56,9 +60,9 @@ pub fn view_get_alias(req: &mut Request) -> IronResult<Response> {
     let cluster_metadata = system.metadata.read().unwrap();
 
     // Get index
-    let index_ref = match cluster_metadata.names.find_canonical(index_name) {
-        Some(index_ref) => index_ref,
-        None => return Ok(json_response(status::NotFound, json!({}))),
+    let index_ref = match cluster_metadata.names.resolved().resolve_single_target(index_name, Utc::now()) {
+        Ok(index_ref) => index_ref,
+        Err(_) => return Ok(json_response(status::NotFound, json!({}))),
     };
 
     // Find alias
@@ -72,20 +76,33 @@ pub fn view_get_alias(req: &mut Request) -> IronResult<Response> {
 
 pub fn view_put_alias(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
+    let log = get_request_log!(req);
     let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
     let ref alias_name = read_path_parameter!(req, "alias").unwrap_or("");
 
     // Lock cluster metadata
     let mut cluster_metadata = system.metadata.write().unwrap();
 
+    // Point the alias at the indices in the cluster state
+    let index_refs = resolve_targets_or_404!(cluster_metadata, *index_selector);
+    let index_names = index_refs.iter().filter_map(|index_ref| cluster_metadata.indices.get(index_ref)).map(|index| index.canonical_name().to_string()).collect::<BTreeSet<String>>();
+    match cluster_metadata.state.update(|state| state.set_alias(alias_name.to_string(), index_names)) {
+        Ok(_) => {}
+        Err(ClusterStateError::Rejected(_)) => {
+            return Ok(json_response(status::Ok, json!({"acknowledged": false})));
+        }
+        Err(e) => {
+            return Ok(json_response(status::InternalServerError, json!({"message": String::from(e)})));
+        }
+    }
+
     // Insert alias into names registry
-    let index_refs = cluster_metadata.names.find(*index_selector);
     match cluster_metadata.names.insert_or_replace_alias(alias_name.to_string(), index_refs) {
         Ok(true) => {
-            info!(system.log, "created alias"; "index" => *index_selector, "alias" => *alias_name);
+            info!(log, "created alias"; "index" => *index_selector, "alias" => *alias_name);
         }
         Ok(false) => {
-            info!(system.log, "updated alias"; "index" => *index_selector, "alias" => *alias_name);
+            info!(log, "updated alias"; "index" => *index_selector, "alias" => *alias_name);
         }
         Err(_) => {
             // TODO
@@ -0,0 +1,155 @@
+use std::io::Read;
+use std::fs;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::json_response;
+use api::checkpoint_api::is_valid_checkpoint_name;
+
+
+/// Attaches an index from a directory that's already on the node, without copying it
+///
+/// The directory can be given as a "path" or as the name of a "checkpoint". The index
+/// is read only, it can be searched but not written to. Its files aren't managed by the
+/// node, so they're left as they are when the index is detached.
+pub fn view_post_attach(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_create_index(tenant.as_ref(), index_name));
+
+    // Load data from body
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "\"path\" or \"checkpoint\" is required"})));
+        }
+    };
+
+    // Find the directory
+    let path = match (data.get("path").and_then(|path| path.as_str()), data.get("checkpoint").and_then(|checkpoint| checkpoint.as_str())) {
+        (Some(path), None) => PathBuf::from(path),
+        (None, Some(checkpoint_name)) if is_valid_checkpoint_name(checkpoint_name) => {
+            let mut path = system.get_checkpoints_dir();
+            path.push(checkpoint_name);
+            path
+        }
+        (None, Some(checkpoint_name)) => {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid checkpoint name: {}", checkpoint_name)})));
+        }
+        _ => {
+            return Ok(json_response(status::BadRequest, json!({"message": "Either \"path\" or \"checkpoint\" is required"})));
+        }
+    };
+
+    let path = match fs::canonicalize(&path) {
+        Ok(path) => path,
+        Err(_) => {
+            return Ok(json_response(status::NotFound, json!({"message": format!("Directory not found: {}", path.display())})));
+        }
+    };
+
+    // The directories of the node's own indices are deleted along with them
+    if let Ok(indices_dir) = fs::canonicalize(system.get_indices_dir()) {
+        if path.starts_with(&indices_dir) {
+            return Ok(json_response(status::BadRequest, json!({"message": "Indices in the data directory can't be attached"})));
+        }
+    }
+
+    // Lock cluster metadata
+    let mut cluster_metadata = system.metadata.write().unwrap();
+
+    if cluster_metadata.names.find_canonical(index_name).is_some() {
+        return Ok(json_response(status::Conflict, json!({"message": format!("Index {} already exists", index_name)})));
+    }
+
+    let index_id = Uuid::new_v4();
+    let index = match system.load_attached_index(index_name.to_string(), index_id, &path) {
+        Ok(index) => index,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Unable to attach index: {}", error)})));
+        }
+    };
+
+    // Add the index to the cluster state, this replaces any alias with the same name
+    let alias_deleted = match cluster_metadata.state.update(|state| state.attach_index(index_name.to_string(), index_id, path.clone())) {
+        Ok(alias_deleted) => alias_deleted,
+        Err(e) => {
+            return Ok(json_response(status::InternalServerError, json!({"message": String::from(e)})));
+        }
+    };
+
+    let index_ref = cluster_metadata.insert_index(index);
+
+    cluster_metadata.names.delete_alias_whole(index_name).unwrap();
+    if alias_deleted {
+        info!(log, "deleted alias"; "alias" => format!("{}", index_name), "reason" => "replaced by index");
+    }
+
+    cluster_metadata.names.insert_canonical(index_name.to_string(), index_ref).unwrap();
+
+    info!(log, "attached index"; "index" => *index_name, "path" => format!("{}", path.display()));
+
+    Ok(json_response(status::Ok, json!({
+        "acknowledged": true,
+        "index": *index_name,
+        "path": path.to_string_lossy(),
+        "blocks": {"read_only": true},
+    })))
+}
+
+
+/// Removes an attached index from the node, leaving its files where they are
+pub fn view_post_detach(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_name));
+
+    // Lock cluster metadata
+    let mut cluster_metadata = system.metadata.write().unwrap();
+
+    // Only canonical names, so an alias can't detach more than was asked for
+    let index_ref = match cluster_metadata.names.find_canonical(index_name) {
+        Some(index_ref) => index_ref,
+        None => {
+            return Ok(json_response(status::NotFound, json!({"message": "Index not found"})));
+        }
+    };
+
+    if !cluster_metadata.state.state().attached.contains_key(*index_name) {
+        return Ok(json_response(status::BadRequest, json!({"message": format!("Index {} wasn't attached, delete it instead", index_name)})));
+    }
+
+    if let Err(e) = cluster_metadata.state.update(|state| state.remove_index(index_name)) {
+        return Ok(json_response(status::InternalServerError, json!({"message": String::from(e)})));
+    }
+
+    if let Some(index) = cluster_metadata.indices.remove(&index_ref) {
+        system.request_cache.invalidate_index(index.id());
+        system.detach_index(index);
+    }
+
+    cluster_metadata.names.delete_canonical(index_name, index_ref).unwrap();
+
+    // Delete aliases
+    let alias_names = cluster_metadata.names.iter_index_aliases(index_ref).map(|n| n.to_string()).collect::<Vec<String>>();
+    for alias_name in alias_names {
+        let alias_deleted = cluster_metadata.names.delete_alias(&alias_name, index_ref).unwrap();
+
+        if alias_deleted {
+            info!(log, "deleted alias"; "alias" => format!("{}", alias_name), "reason" => "no indices left");
+        }
+    }
+
+    info!(log, "detached index"; "index" => *index_name);
+
+    Ok(json_response(status::Ok, json!({"acknowledged": true})))
+}
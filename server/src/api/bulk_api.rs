@@ -0,0 +1,495 @@
+use std::io::BufReader;
+use std::collections::HashMap;
+
+use serde_json;
+use slog::Logger;
+
+use rusticsearch::document::DocumentSource;
+use rusticsearch::bulk_queue::BulkQueueFull;
+use rusticsearch::bulk_reader::{BulkReader, BulkReadError};
+use rusticsearch::index::Index;
+use rusticsearch::system::System;
+use rusticsearch::tenancy::Tenant;
+use rusticsearch::search::document::FieldValue;
+use rusticsearch::update::{Update, UpdateResult, UpdateError};
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::iron::headers::ContentLength;
+use api::utils::{json_response, too_many_requests_response};
+use api::router::Router;
+
+
+/// Builds the item for an action that failed, this doesn't stop the rest of the request
+fn bulk_error_item(mut item: serde_json::Value, status: u16, error_type: &str, reason: String) -> serde_json::Value {
+    item["status"] = json!(status);
+    item["error"] = json!({
+        "type": error_type,
+        "reason": reason,
+    });
+
+    item
+}
+
+
+/// Builds the item for the line that the rest of the request couldn't be read past
+fn bulk_read_error_item(error: &BulkReadError) -> HashMap<&'static str, serde_json::Value> {
+    let mut item = HashMap::new();
+    item.insert("error", bulk_error_item(json!({}), 400, "parse_exception", error.message()));
+    item
+}
+
+
+/// Runs an "index" or "create" action, returning the item for the response
+///
+/// "create" actions fail if a document with the same id already exists, "index" actions
+/// replace it.
+fn run_index_action(system: &System, log: &Logger, tenant: Option<&Tenant>, index: &Index, doc_type: &str, doc_id: &str, doc_json: &serde_json::Value, source_size: i64, create: bool) -> serde_json::Value {
+    let mut item = json!({
+        "_index": index.canonical_name(),
+        "_type": doc_type,
+        "_id": doc_id,
+    });
+
+    if index.is_read_only() {
+        return bulk_error_item(item, 403, "cluster_block_exception", format!("index {} is read only", index.canonical_name()));
+    }
+
+    let source = match doc_json.as_object() {
+        Some(source) => source,
+        None => return bulk_error_item(item, 400, "mapper_parsing_exception", "the document must be an object".to_string()),
+    };
+
+    // Add any new fields to the mapping, this must be done before the metadata is locked below
+    match index.add_dynamic_fields(doc_type, source) {
+        Ok(ref field_names) if !field_names.is_empty() => {
+            info!(log, "added dynamic fields"; "index" => index.canonical_name(), "mapping" => doc_type, "fields" => field_names.join(", "));
+        }
+        Ok(_) => {}
+        Err(e) => return bulk_error_item(item, 400, "mapper_parsing_exception", format!("Couldn't add fields to mapping: {}", e)),
+    }
+
+    let index_metadata = index.metadata.read().unwrap();
+
+    let doc = {
+        // Find mapping
+        let mapping = match index_metadata.mappings.get(doc_type) {
+            Some(mapping) => mapping,
+            None => return bulk_error_item(item, 404, "type_missing_exception", "Mapping not found".to_string()),
+        };
+
+        // Create document
+        let document_source = DocumentSource {
+            key: doc_id,
+            doc_type: doc_type,
+            data: source,
+        };
+
+        match document_source.prepare(mapping) {
+            Ok(doc) => doc,
+            Err(e) => return bulk_error_item(item, 400, "mapper_parsing_exception", format!("{:?}", e)),
+        }
+    };
+
+    let store = match index.store() {
+        Ok(store) => store,
+        Err(e) => return bulk_error_item(item, 503, "store_unavailable_exception", e),
+    };
+
+    // A document can't be created between the check for it below and the insert
+    let _update_guard = if create { Some(index.lock_updates()) } else { None };
+
+    let exists = match store.reader().contains_document_key(doc_id) {
+        Ok(exists) => exists,
+        Err(e) => return bulk_error_item(item, 500, "exception", format!("{}", e)),
+    };
+    if create && exists {
+        return bulk_error_item(item, 409, "version_conflict_engine_exception", format!("[{}][{}]: version conflict, document already exists", doc_type, doc_id));
+    }
+
+    // Check the tenant's quotas, replacing a document doesn't add to the number of documents
+    let new_docs = if exists { 0 } else { 1 };
+    if let Err(e) = system.tenancy.check_index_documents(tenant, index.canonical_name(), new_docs as u64, source_size as u64) {
+        return bulk_error_item(item, 403, "quota_exceeded_exception", String::from(e));
+    }
+
+    if let Err(e) = store.insert_or_update_document(&doc) {
+        return bulk_error_item(item, 500, "exception", format!("{:?}", e));
+    }
+
+    if let Err(e) = system.tenancy.record_documents(tenant, index.canonical_name(), new_docs, source_size) {
+        warn!(log, "failed to record tenant usage"; "index" => index.canonical_name(), "error" => e);
+    }
+    system.watcher.record_ingest(index.canonical_name());
+
+    item["status"] = json!(if exists { 200 } else { 201 });
+    item["result"] = json!(if exists { "updated" } else { "created" });
+    item
+}
+
+
+/// Runs a "delete" action, returning the item for the response
+///
+/// Deleting a document that doesn't exist isn't an error, the item has a 404 status
+/// and a "not_found" result.
+fn run_delete_action(system: &System, log: &Logger, tenant: Option<&Tenant>, index: &Index, doc_type: &str, doc_id: &str) -> serde_json::Value {
+    let mut item = json!({
+        "_index": index.canonical_name(),
+        "_type": doc_type,
+        "_id": doc_id,
+    });
+
+    if index.is_read_only() {
+        return bulk_error_item(item, 403, "cluster_block_exception", format!("index {} is read only", index.canonical_name()));
+    }
+
+    let store = match index.store() {
+        Ok(store) => store,
+        Err(e) => return bulk_error_item(item, 503, "store_unavailable_exception", e),
+    };
+
+    let found = match store.remove_document_by_key(doc_id) {
+        Ok(found) => found,
+        Err(e) => return bulk_error_item(item, 500, "exception", format!("{}", e)),
+    };
+
+    if found {
+        // The size of the document isn't known, so its storage is only given back when the index is deleted
+        if let Err(e) = system.tenancy.record_documents(tenant, index.canonical_name(), -1, 0) {
+            warn!(log, "failed to record tenant usage"; "index" => index.canonical_name(), "error" => e);
+        }
+    }
+
+    item["status"] = json!(if found { 200 } else { 404 });
+    item["result"] = json!(if found { "deleted" } else { "not_found" });
+    item
+}
+
+
+/// Runs an "update" action, returning the item for the response
+///
+/// The document's current source is read, changed and reindexed while the index's
+/// update lock is held, so concurrent updates to the same document (like two
+/// increments of a counter) can't overwrite each other.
+fn run_update_action(system: &System, log: &Logger, tenant: Option<&Tenant>, index: &Index, doc_type: &str, doc_id: &str, update_json: &serde_json::Value) -> serde_json::Value {
+    let mut item = json!({
+        "_index": index.canonical_name(),
+        "_type": doc_type,
+        "_id": doc_id,
+    });
+
+    if index.is_read_only() {
+        return bulk_error_item(item, 403, "cluster_block_exception", format!("index {} is read only", index.canonical_name()));
+    }
+
+    let update = match Update::parse(update_json) {
+        Ok(update) => update,
+        Err(e) => return bulk_error_item(item, 400, "action_request_validation_exception", e.message()),
+    };
+
+    let _update_guard = index.lock_updates();
+
+    // Read the current source of the document
+    let source = {
+        let store = match index.store() {
+            Ok(store) => store,
+            Err(e) => return bulk_error_item(item, 503, "store_unavailable_exception", e),
+        };
+        let index_reader = store.reader();
+
+        match index_reader.find_doc_id(doc_id) {
+            Ok(Some(doc_ref)) => {
+                let source_field = index_reader.schema().get_field_by_name("_source");
+                let source = match source_field.map(|field| index_reader.read_stored_field(field, doc_ref)) {
+                    Some(Ok(Some(FieldValue::String(source)))) => source,
+                    _ => return bulk_error_item(item, 400, "document_source_missing_exception", "the source of the document isn't stored".to_string()),
+                };
+
+                match serde_json::from_str(&source) {
+                    Ok(serde_json::Value::Object(source)) => Some(source),
+                    _ => return bulk_error_item(item, 500, "document_source_missing_exception", "the source of the document couldn't be read".to_string()),
+                }
+            }
+            Ok(None) => None,
+            Err(e) => return bulk_error_item(item, 500, "exception", format!("{}", e)),
+        }
+    };
+
+    let (source, created) = match update.apply(source) {
+        Ok(UpdateResult::Created(source)) => (source, true),
+        Ok(UpdateResult::Updated(source)) => (source, false),
+        Ok(UpdateResult::Noop) => {
+            item["status"] = json!(200);
+            item["result"] = json!("noop");
+            return item;
+        }
+        Err(UpdateError::DocumentMissing) => return bulk_error_item(item, 404, "document_missing_exception", UpdateError::DocumentMissing.message()),
+        Err(e) => return bulk_error_item(item, 400, "illegal_argument_exception", e.message()),
+    };
+
+    // Add any new fields to the mapping, this must be done before the metadata is locked below
+    match index.add_dynamic_fields(doc_type, &source) {
+        Ok(ref field_names) if !field_names.is_empty() => {
+            info!(log, "added dynamic fields"; "index" => index.canonical_name(), "mapping" => doc_type, "fields" => field_names.join(", "));
+        }
+        Ok(_) => {}
+        Err(e) => return bulk_error_item(item, 400, "mapper_parsing_exception", format!("Couldn't add fields to mapping: {}", e)),
+    }
+
+    let index_metadata = index.metadata.read().unwrap();
+
+    let doc = {
+        // Find mapping
+        let mapping = match index_metadata.mappings.get(doc_type) {
+            Some(mapping) => mapping,
+            None => return bulk_error_item(item, 404, "type_missing_exception", "Mapping not found".to_string()),
+        };
+
+        // Create document
+        let document_source = DocumentSource {
+            key: doc_id,
+            doc_type: doc_type,
+            data: &source,
+        };
+
+        match document_source.prepare(mapping) {
+            Ok(doc) => doc,
+            Err(e) => return bulk_error_item(item, 400, "mapper_parsing_exception", format!("{:?}", e)),
+        }
+    };
+
+    let store = match index.store() {
+        Ok(store) => store,
+        Err(e) => return bulk_error_item(item, 503, "store_unavailable_exception", e),
+    };
+
+    // Check the tenant's quotas, updating a document doesn't add to the number of documents
+    let new_docs = if created { 1 } else { 0 };
+    let source_size = serde_json::to_string(&source).unwrap().len() as i64;
+    if let Err(e) = system.tenancy.check_index_documents(tenant, index.canonical_name(), new_docs as u64, source_size as u64) {
+        return bulk_error_item(item, 403, "quota_exceeded_exception", String::from(e));
+    }
+
+    if let Err(e) = store.insert_or_update_document(&doc) {
+        return bulk_error_item(item, 500, "exception", format!("{:?}", e));
+    }
+
+    if let Err(e) = system.tenancy.record_documents(tenant, index.canonical_name(), new_docs, source_size) {
+        warn!(log, "failed to record tenant usage"; "index" => index.canonical_name(), "error" => e);
+    }
+    system.watcher.record_ingest(index.canonical_name());
+
+    item["status"] = json!(if created { 201 } else { 200 });
+    item["result"] = json!(if created { "created" } else { "updated" });
+    item
+}
+
+
+pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let tenant = get_tenant_or_401!(req, system);
+
+    // Lock cluster metedata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    // Reserve space in the bulk queue, this is held until the response is built
+    // The body is read as it's processed, so its size is taken from the Content-Length header.
+    // Bodies without one can't be accounted for, so they're rejected
+    let content_length = match req.headers.get::<ContentLength>() {
+        Some(length) => length.0 as usize,
+        None => {
+            return Ok(json_response(status::LengthRequired, json!({"message": "Bulk requests must have a Content-Length header"})));
+        }
+    };
+    let _permit = match system.bulk_queue.try_acquire(content_length) {
+        Ok(permit) => permit,
+        Err(BulkQueueFull { retry_after }) => {
+            warn!(log, "rejected bulk request, queue is full"; "retry_after" => retry_after.as_secs());
+            return Ok(too_many_requests_response(retry_after));
+        }
+    };
+
+    let mut items = Vec::new();
+    let mut errors = false;
+
+    // Actions are read from the body one at a time, so the whole body is never held in memory
+    for action in BulkReader::new(BufReader::new(&mut req.body)) {
+        let action = match action {
+            Ok(action) => action,
+            Err(error) => {
+                // Nothing after the bad line can be read, but the actions before it have already run
+                items.push(bulk_read_error_item(&error));
+                errors = true;
+                break;
+            }
+        };
+
+        let doc_id = action.params.get("_id").unwrap().as_str().unwrap();
+        let doc_type = action.params.get("_type").unwrap().as_str().unwrap();
+        let doc_index = action.params.get("_index").unwrap().as_str().unwrap();
+
+        match action.name.as_ref() {
+            "index" | "create" => {
+                let doc_json = action.source.unwrap_or(serde_json::Value::Null);
+
+                // Find index
+                let index = get_index_or_404!(cluster_metadata, doc_index);
+
+                let create = action.name == "create";
+                let index_item = run_index_action(system, &log, tenant.as_ref(), index, doc_type, doc_id, &doc_json, action.source_size as i64, create);
+                if index_item.get("error").is_some() {
+                    errors = true;
+                }
+
+                // Insert into "items" array
+                let mut item = HashMap::new();
+                item.insert(if create { "create" } else { "index" }, index_item);
+                items.push(item);
+            }
+            "delete" => {
+                // Find index
+                let index = get_index_or_404!(cluster_metadata, doc_index);
+
+                let delete_item = run_delete_action(system, &log, tenant.as_ref(), index, doc_type, doc_id);
+                if delete_item.get("error").is_some() {
+                    errors = true;
+                }
+
+                // Insert into "items" array
+                let mut item = HashMap::new();
+                item.insert("delete", delete_item);
+                items.push(item);
+            }
+            "update" => {
+                let update_json = action.source.unwrap_or(serde_json::Value::Null);
+
+                // Find index
+                let index = get_index_or_404!(cluster_metadata, doc_index);
+
+                let update_item = run_update_action(system, &log, tenant.as_ref(), index, doc_type, doc_id, &update_json);
+                if update_item.get("error").is_some() {
+                    errors = true;
+                }
+
+                // Insert into "items" array
+                let mut item = HashMap::new();
+                item.insert("update", update_item);
+                items.push(item);
+            }
+            _ => {
+                warn!(log, "unrecognised action! {}", action.name);
+            }
+        }
+    }
+
+    return Ok(json_response(status::Ok,
+                            json!({
+                                "took": items.len(),
+                                "errors": errors,
+                                "items": items,
+                            })));
+}
+
+
+pub fn view_post_index_bulk(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+
+    // Lock cluster metedata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    // Get index
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    check_writable_or_403!(index);
+
+    // Reserve space in the bulk queue, this is held until the response is built
+    // The body is read as it's processed, so its size is taken from the Content-Length header.
+    // Bodies without one can't be accounted for, so they're rejected
+    let content_length = match req.headers.get::<ContentLength>() {
+        Some(length) => length.0 as usize,
+        None => {
+            return Ok(json_response(status::LengthRequired, json!({"message": "Bulk requests must have a Content-Length header"})));
+        }
+    };
+    let _permit = match system.bulk_queue.try_acquire(content_length) {
+        Ok(permit) => permit,
+        Err(BulkQueueFull { retry_after }) => {
+            warn!(log, "rejected bulk request, queue is full"; "retry_after" => retry_after.as_secs());
+            return Ok(too_many_requests_response(retry_after));
+        }
+    };
+
+    let mut items = Vec::new();
+    let mut errors = false;
+
+    // Actions are read from the body one at a time, so the whole body is never held in memory
+    for action in BulkReader::new(BufReader::new(&mut req.body)) {
+        let action = match action {
+            Ok(action) => action,
+            Err(error) => {
+                // Nothing after the bad line can be read, but the actions before it have already run
+                items.push(bulk_read_error_item(&error));
+                errors = true;
+                break;
+            }
+        };
+
+        let doc_id = action.params.get("_id").unwrap().as_str().unwrap();
+        let doc_type = action.params.get("_type").unwrap().as_str().unwrap();
+
+        match action.name.as_ref() {
+            "index" | "create" => {
+                let doc_json = action.source.unwrap_or(serde_json::Value::Null);
+
+                let create = action.name == "create";
+                let index_item = run_index_action(system, &log, tenant.as_ref(), index, doc_type, doc_id, &doc_json, action.source_size as i64, create);
+                if index_item.get("error").is_some() {
+                    errors = true;
+                }
+
+                // Insert into "items" array
+                let mut item = HashMap::new();
+                item.insert(if create { "create" } else { "index" }, index_item);
+                items.push(item);
+            }
+            "delete" => {
+                let delete_item = run_delete_action(system, &log, tenant.as_ref(), index, doc_type, doc_id);
+                if delete_item.get("error").is_some() {
+                    errors = true;
+                }
+
+                // Insert into "items" array
+                let mut item = HashMap::new();
+                item.insert("delete", delete_item);
+                items.push(item);
+            }
+            "update" => {
+                let update_json = action.source.unwrap_or(serde_json::Value::Null);
+
+                let update_item = run_update_action(system, &log, tenant.as_ref(), index, doc_type, doc_id, &update_json);
+                if update_item.get("error").is_some() {
+                    errors = true;
+                }
+
+                // Insert into "items" array
+                let mut item = HashMap::new();
+                item.insert("update", update_item);
+                items.push(item);
+            }
+            _ => {
+                warn!(log, "unrecognised action! {}", action.name);
+            }
+        }
+    }
+
+    return Ok(json_response(status::Ok,
+                            json!({
+                                "took": items.len(),
+                                "errors": errors,
+                                "items": items,
+                            })));
+}
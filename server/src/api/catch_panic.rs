@@ -0,0 +1,103 @@
+//! Converts panics in request handlers into 500 responses
+//!
+//! Without this, a panic drops the connection without sending anything back. Each
+//! panic is given an id which is printed along with the stack trace, logged and
+//! returned in the response so they can all be matched up.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+
+use slog::Logger;
+use uuid::Uuid;
+
+use api::iron::prelude::*;
+use api::iron::{status, Handler, AroundMiddleware};
+use api::utils::json_response;
+use api::request_log::RequestLog;
+
+
+thread_local! {
+    /// The id of the last panic on this thread, set by the panic hook
+    static LAST_PANIC_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+
+/// Installs a panic hook that gives each panic an id
+///
+/// The id is printed before the message and stack trace of the default hook.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        let panic_id = Uuid::new_v4().to_string();
+        eprintln!("panic id: {}", panic_id);
+        LAST_PANIC_ID.with(|last_panic_id| *last_panic_id.borrow_mut() = Some(panic_id));
+
+        default_hook(info);
+    }));
+}
+
+
+fn panic_message(payload: &Box<Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+
+pub struct CatchPanic {
+    log: Logger,
+}
+
+
+impl CatchPanic {
+    pub fn new(log: Logger) -> CatchPanic {
+        CatchPanic {
+            log: log,
+        }
+    }
+}
+
+
+impl AroundMiddleware for CatchPanic {
+    fn around(self, handler: Box<Handler>) -> Box<Handler> {
+        Box::new(CatchPanicHandler {
+            log: self.log,
+            handler: handler,
+        })
+    }
+}
+
+
+struct CatchPanicHandler {
+    log: Logger,
+    handler: Box<Handler>,
+}
+
+
+impl Handler for CatchPanicHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.handler.handle(req))) {
+            Ok(result) => result,
+            Err(payload) => {
+                // The id is only missing if the hook hasn't been installed
+                let panic_id = LAST_PANIC_ID.with(|last_panic_id| last_panic_id.borrow_mut().take())
+                    .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+                // Log with the request's id if it has one
+                let log = req.extensions.get::<RequestLog>().cloned().unwrap_or_else(|| self.log.clone());
+                error!(log, "request handler panicked"; "panic_id" => &panic_id, "method" => format!("{}", req.method), "url" => format!("{}", req.url), "error" => panic_message(&payload));
+
+                Ok(json_response(status::InternalServerError, json!({
+                    "message": "Internal server error",
+                    "panic_id": panic_id,
+                })))
+            }
+        }
+    }
+}
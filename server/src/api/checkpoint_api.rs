@@ -0,0 +1,67 @@
+use std::fs;
+use std::time::Instant;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, millis_since};
+
+
+/// Checkpoints are written into a directory with this name, so it mustn't be able to
+/// point anywhere else
+pub fn is_valid_checkpoint_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(|c| c == '/' || c == '\\')
+}
+
+
+/// Writes a consistent copy of an index into the node's checkpoints directory
+///
+/// The copy can be archived, or attached to a node as a read-only index.
+pub fn view_post_checkpoint(req: &mut Request) -> IronResult<Response> {
+    let start_time = Instant::now();
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref checkpoint_name = read_path_parameter!(req, "checkpoint").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+
+    if !is_valid_checkpoint_name(checkpoint_name) {
+        return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid checkpoint name: {}", checkpoint_name)})));
+    }
+
+    let mut path = system.get_checkpoints_dir();
+    path.push(checkpoint_name);
+    if path.exists() {
+        return Ok(json_response(status::Conflict, json!({"message": format!("Checkpoint {} already exists", checkpoint_name)})));
+    }
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+
+    if let Err(error) = fs::create_dir_all(system.get_checkpoints_dir()) {
+        return Ok(json_response(status::InternalServerError, json!({"message": format!("Unable to create checkpoints directory: {}", error)})));
+    }
+
+    let keys_copied = match index.checkpoint(&path) {
+        Ok(keys_copied) => keys_copied,
+        Err(error) => {
+            // Don't leave a partial copy behind
+            let _ = fs::remove_dir_all(&path);
+            return Ok(json_response(status::InternalServerError, json!({"message": format!("Checkpoint failed: {}", error)})));
+        }
+    };
+
+    info!(log, "created checkpoint"; "index" => index.canonical_name(), "checkpoint" => *checkpoint_name, "keys" => keys_copied);
+
+    Ok(json_response(status::Ok, json!({
+        "acknowledged": true,
+        "index": index.canonical_name(),
+        "checkpoint": *checkpoint_name,
+        "path": path.to_string_lossy(),
+        "keys": keys_copied,
+        "took": millis_since(start_time),
+    })))
+}
@@ -0,0 +1,108 @@
+use std::io::Read;
+use std::collections::BTreeMap;
+
+use serde_json;
+use url::form_urlencoded;
+
+use rusticsearch::cluster::metadata::state::ClusterState;
+use rusticsearch::settings::SettingsError;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::utils::json_response;
+
+
+pub fn view_get_cluster_state(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let tenant = get_tenant_or_401!(req, system);
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+    let state = cluster_metadata.state.state();
+
+    // Tenants can only see their own indices and the aliases that point at them
+    let json = match tenant {
+        Some(tenant) => {
+            let filtered_state = ClusterState {
+                version: state.version,
+                indices: state.indices.iter().filter(|&(name, _)| tenant.can_access_index(name)).map(|(name, uuid)| (name.clone(), *uuid)).collect(),
+                attached: state.attached.iter().filter(|&(name, _)| tenant.can_access_index(name)).map(|(name, path)| (name.clone(), path.clone())).collect(),
+                aliases: state.aliases.iter().filter(|&(_, indices)| indices.iter().all(|name| tenant.can_access_index(name))).map(|(name, indices)| (name.clone(), indices.clone())).collect(),
+                templates: BTreeMap::new(),
+            };
+            filtered_state.to_json()
+        }
+        None => state.to_json(),
+    };
+
+    Ok(json_response(status::Ok, json))
+}
+
+
+/// Returns the persistent and transient node settings, and the defaults with
+/// "include_defaults=true"
+pub fn view_get_cluster_settings(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    get_tenant_or_401!(req, system);
+
+    let mut include_defaults = false;
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key == "include_defaults" {
+                include_defaults = value == "true";
+            }
+        }
+    }
+
+    let mut json = json!({
+        "persistent": system.settings.persistent(),
+        "transient": system.settings.transient(),
+    });
+
+    if include_defaults {
+        json["defaults"] = json!(system.settings.defaults());
+    }
+
+    Ok(json_response(status::Ok, json))
+}
+
+
+/// Changes node settings, they're used straight away
+pub fn view_put_cluster_settings(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let tenant = get_tenant_or_401!(req, system);
+
+    // Settings are shared by every tenant, so they can only be changed when tenancy is disabled
+    if tenant.is_some() {
+        return Ok(json_response(status::Forbidden, json!({"message": "API keys can't change node settings"})));
+    }
+
+    // Load data from body
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No settings given"})));
+        }
+    };
+
+    let (persistent, transient) = match system.settings.update(data.get("persistent"), data.get("transient")) {
+        Ok(changes) => changes,
+        Err(error @ SettingsError::SaveFailed(_)) => {
+            return Ok(json_response(status::InternalServerError, json!({"message": error.message()})));
+        }
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": error.message()})));
+        }
+    };
+    system.apply_settings();
+
+    info!(log, "updated node settings"; "persistent" => serde_json::to_string(&persistent).unwrap(), "transient" => serde_json::to_string(&transient).unwrap());
+
+    Ok(json_response(status::Ok, json!({
+        "acknowledged": true,
+        "persistent": persistent,
+        "transient": transient,
+    })))
+}
@@ -0,0 +1,71 @@
+//! Resolves date math in the index names of request paths
+//!
+//! The index (or comma separated list of indices) in the first segment of the path
+//! can use date math (eg, "<logs-{now/d}>"). These are resolved before the request is
+//! routed, so views only ever see the resolved names.
+
+use chrono::Utc;
+use url::percent_encoding::{percent_decode, utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+use rusticsearch::mapping::date_math::resolve_index_name;
+
+use api::iron::prelude::*;
+use api::iron::{status, Handler, AroundMiddleware, Url};
+use api::utils::json_response;
+
+
+pub struct DateMathIndexNames;
+
+
+impl AroundMiddleware for DateMathIndexNames {
+    fn around(self, handler: Box<Handler>) -> Box<Handler> {
+        Box::new(DateMathIndexNamesHandler {
+            handler: handler,
+        })
+    }
+}
+
+
+struct DateMathIndexNamesHandler {
+    handler: Box<Handler>,
+}
+
+
+impl Handler for DateMathIndexNamesHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let first_segment = percent_decode(req.url.path()[0].as_bytes()).decode_utf8().ok().map(|segment| segment.into_owned());
+
+        // Most requests don't use date math
+        let first_segment = match first_segment {
+            Some(ref first_segment) if first_segment.split(',').any(|name| name.starts_with('<')) => first_segment.clone(),
+            _ => return self.handler.handle(req),
+        };
+
+        // Resolve each name, all of them against the same time
+        let now = Utc::now();
+        let mut names = Vec::new();
+        for name in first_segment.split(',') {
+            match resolve_index_name(name, now) {
+                Ok(name) => names.push(name),
+                Err(error) => {
+                    return Ok(json_response(status::BadRequest, json!({
+                        "message": format!("Invalid date math in index name {:?}: {}", name, error.message()),
+                    })));
+                }
+            }
+        }
+
+        // Replace the first segment, the rest of the path is kept as it was sent
+        let mut path = format!("/{}", utf8_percent_encode(&names.join(","), PATH_SEGMENT_ENCODE_SET));
+        for segment in req.url.path().iter().skip(1) {
+            path.push('/');
+            path.push_str(segment);
+        }
+
+        let mut url = req.url.clone().into_generic_url();
+        url.set_path(&path);
+        req.url = Url::from_generic_url(url).expect("changing the path made the url invalid");
+
+        self.handler.handle(req)
+    }
+}
@@ -0,0 +1,310 @@
+use std::io::Read;
+
+use serde_json;
+use url::form_urlencoded;
+
+use chrono::Utc;
+use rusticsearch::document::DocumentSource;
+use rusticsearch::cluster::metadata::ClusterMetadata;
+use rusticsearch::system::System;
+use rusticsearch::tenancy::Tenant;
+use rusticsearch::index::get::GetMode;
+use rusticsearch::index::routing::SearchPreferenceParseError;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::json_response;
+use api::search_api::read_preference;
+
+
+/// Fetches a document by its id
+///
+/// By default the latest version of the document is returned ("realtime=true"). With
+/// "realtime=false" the document is found with a search instead, so it's the version
+/// that searches see, on the copy of the index chosen by the "preference" parameter.
+pub fn view_get_doc(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_name));
+
+    // Read URL parameters
+    let mut realtime = true;
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key == "realtime" {
+                realtime = match value.as_ref() {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Ok(json_response(status::BadRequest, json!({"message": "\"realtime\" must be true or false"}))),
+                };
+            }
+        }
+    }
+
+    let mode = if realtime {
+        GetMode::Realtime
+    } else {
+        match read_preference(req) {
+            Ok(preference) => GetMode::Search(preference),
+            Err(SearchPreferenceParseError::UnrecognisedPreference(preference)) => {
+                return Ok(json_response(status::BadRequest, json!({"message": format!("Unrecognised preference: {}", preference)})));
+            }
+        }
+    };
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+
+    // Check that the mapping exists
+    if !index.metadata.read().unwrap().mappings.contains_key(*mapping_name) {
+        return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
+    }
+
+    // Find document
+    let doc = match index.get_document(doc_key, &mode) {
+        Ok(doc) => doc,
+        Err(error) => {
+            return Ok(json_response(status::InternalServerError, json!({"message": format!("Couldn't read document: {}", error)})));
+        }
+    };
+
+    match doc {
+        // Documents of other types aren't returned
+        Some(ref doc) if doc.doc_type.as_ref().map(|doc_type| doc_type.as_str() == *mapping_name).unwrap_or(true) => {
+            Ok(json_response(status::Ok, json!({
+                "_index": index.canonical_name(),
+                "_type": mapping_name,
+                "_id": doc_key,
+                "found": true,
+                "_source": doc.source.clone().unwrap_or(serde_json::Value::Null),
+            })))
+        }
+        _ => {
+            Ok(json_response(status::NotFound, json!({
+                "_index": index.canonical_name(),
+                "_type": mapping_name,
+                "_id": doc_key,
+                "found": false,
+            })))
+        }
+    }
+}
+
+
+pub fn view_put_doc(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+
+    // Load data from body
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::NotFound, json!({"message": "No data"})));
+        }
+    };
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    check_writable_or_403!(index);
+
+    // Add any new fields to the mapping, this must be done before the metadata is locked below
+    match index.add_dynamic_fields(mapping_name, data.as_object().unwrap()) {
+        Ok(ref field_names) if !field_names.is_empty() => {
+            info!(log, "added dynamic fields"; "index" => *index_name, "mapping" => *mapping_name, "fields" => field_names.join(", "));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Couldn't add fields to mapping: {}", e)})));
+        }
+    }
+
+    let index_metadata = index.metadata.read().unwrap();
+
+    let (doc, source_size) = {
+        // Find mapping
+        let mapping = match index_metadata.mappings.get(*mapping_name) {
+            Some(mapping) => mapping,
+            None => {
+                return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
+            }
+        };
+
+        // Create document
+        let document_source = DocumentSource {
+            key: doc_key,
+            doc_type: mapping_name,
+            data: data.as_object().unwrap(),
+        };
+        (document_source.prepare(mapping).unwrap(), data.to_string().len() as i64)
+    };
+
+    let store = get_store_or_500!(index.store());
+
+    // Check the tenant's quotas, replacing a document doesn't add to the number of documents
+    let exists = match store.reader().contains_document_key(doc_key) {
+        Ok(exists) => exists,
+        Err(error) => {
+            return Ok(json_response(status::InternalServerError, json!({"message": format!("Couldn't read document index: {}", error)})));
+        }
+    };
+    let new_docs = if exists { 0 } else { 1 };
+    check_tenancy_or_403!(system.tenancy.check_index_documents(tenant.as_ref(), index.canonical_name(), new_docs as u64, source_size as u64));
+
+    store.insert_or_update_document(&doc).unwrap();
+
+    if let Err(e) = system.tenancy.record_documents(tenant.as_ref(), index.canonical_name(), new_docs, source_size) {
+        warn!(log, "failed to record tenant usage"; "index" => index.canonical_name(), "error" => e);
+    }
+
+    // TODO: {"_index":"wagtail","_type":"searchtests_searchtest","_id":"searchtests_searchtest:5378","_version":1,"created":true}
+    return Ok(json_response(status::Ok, json!({})));
+}
+
+
+pub fn view_delete_doc(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    let index_metadata = index.metadata.read().unwrap();
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+    check_writable_or_403!(index);
+
+    // Check that the mapping exists
+    if !index_metadata.mappings.contains_key(*mapping_name) {
+        return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
+    }
+
+    // Make sure the document exists
+    let store = get_store_or_500!(index.store());
+    match store.reader().contains_document_key(doc_key) {
+        Ok(true) => {}
+        Ok(false) => {
+            return Ok(json_response(status::NotFound, json!({"message": "Document not found"})));
+        }
+        Err(error) => {
+            return Ok(json_response(status::InternalServerError, json!({"message": format!("Couldn't read document index: {}", error)})));
+        }
+    }
+
+    // Delete document
+    store.remove_document_by_key(doc_key).unwrap();
+
+    // The size of the document isn't known, so its storage is only given back when the index is deleted
+    if let Err(e) = system.tenancy.record_documents(tenant.as_ref(), index.canonical_name(), -1, 0) {
+        warn!(log, "failed to record tenant usage"; "index" => index.canonical_name(), "error" => e);
+    }
+
+    return Ok(json_response(status::Ok, json!({})));
+}
+
+
+/// Fetches a document for a multi get request, errors are reported in the document's entry
+fn get_doc_json(system: &System, tenant: Option<&Tenant>, cluster_metadata: &ClusterMetadata, target: &str, doc_key: &str) -> serde_json::Value {
+    let index_ref = match cluster_metadata.names.resolved().resolve_single_target(target, Utc::now()) {
+        Ok(index_ref) => index_ref,
+        Err(error) => return json!({"_index": target, "_id": doc_key, "error": error.message()}),
+    };
+
+    let index = match cluster_metadata.indices.get(&index_ref) {
+        Some(index) => index,
+        None => return json!({"_index": target, "_id": doc_key, "error": "Index not found"}),
+    };
+
+    // An alias may refer to an index that the tenant can't use
+    if let Err(error) = system.tenancy.check_index_access(tenant, index.canonical_name()) {
+        return json!({"_index": index.canonical_name(), "_id": doc_key, "error": String::from(error)});
+    }
+
+    match index.get_document(doc_key, &GetMode::Realtime) {
+        Ok(Some(doc)) => {
+            json!({
+                "_index": index.canonical_name(),
+                "_type": doc.doc_type,
+                "_id": doc_key,
+                "found": true,
+                "_source": doc.source.unwrap_or(serde_json::Value::Null),
+            })
+        }
+        Ok(None) => json!({"_index": index.canonical_name(), "_id": doc_key, "found": false}),
+        Err(error) => json!({"_index": index.canonical_name(), "_id": doc_key, "error": error}),
+    }
+}
+
+
+/// Fetches several documents by their ids
+///
+/// ```json
+/// {"docs": [{"_index": "logs", "_id": "1"}, {"_index": "users", "_id": "2"}]}
+/// {"ids": ["1", "2"]}
+/// ```
+///
+/// Each "_index" is resolved like the target of any other request, so it can be an alias
+/// as long as it refers to a single index. It defaults to the index in the URL, which
+/// must be given to use "ids".
+pub fn view_post_mget(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let default_index = read_path_parameter!(req, "index").map(|index| index.to_string());
+    let tenant = get_tenant_or_401!(req, system);
+
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No documents given"})));
+        }
+    };
+
+    // Find the index and id of each document
+    let mut docs = Vec::new();
+    if let Some(docs_json) = data.get("docs").and_then(|docs| docs.as_array()) {
+        for doc_json in docs_json {
+            let index = doc_json.get("_index").and_then(|index| index.as_str()).map(|index| index.to_string()).or_else(|| default_index.clone());
+            let doc_key = doc_json.get("_id").and_then(|doc_key| doc_key.as_str());
+
+            match (index, doc_key) {
+                (Some(index), Some(doc_key)) => docs.push((index, doc_key.to_string())),
+                (None, _) => return Ok(json_response(status::BadRequest, json!({"message": "\"_index\" is required for every document"}))),
+                (_, None) => return Ok(json_response(status::BadRequest, json!({"message": "\"_id\" is required for every document"}))),
+            }
+        }
+    } else if let Some(ids_json) = data.get("ids").and_then(|ids| ids.as_array()) {
+        let index = match default_index {
+            Some(ref index) => index,
+            None => return Ok(json_response(status::BadRequest, json!({"message": "\"ids\" can only be used with an index in the URL"}))),
+        };
+
+        for id_json in ids_json {
+            match id_json.as_str() {
+                Some(doc_key) => docs.push((index.clone(), doc_key.to_string())),
+                None => return Ok(json_response(status::BadRequest, json!({"message": "\"ids\" must be a list of strings"}))),
+            }
+        }
+    } else {
+        return Ok(json_response(status::BadRequest, json!({"message": "\"docs\" or \"ids\" is required"})));
+    }
+
+    let cluster_metadata = system.metadata.read().unwrap();
+    let docs_json = docs.iter().map(|&(ref index, ref doc_key)| {
+        get_doc_json(system, tenant.as_ref(), &cluster_metadata, index, doc_key)
+    }).collect::<Vec<_>>();
+
+    Ok(json_response(status::Ok, json!({"docs": docs_json})))
+}
@@ -0,0 +1,336 @@
+use std::io::Read;
+
+use serde_json;
+use rusticsearch::search::backends::rocksdb::RocksDBStore;
+use uuid::Uuid;
+use chrono::Utc;
+
+use rusticsearch::index::Index;
+use rusticsearch::index::metadata::IndexMetadata;
+use rusticsearch::index::store_cache::IndexStore;
+use rusticsearch::index::metadata::parse::{parse as parse_index_metadata, parse_settings_update, check_unsupported_settings};
+use rusticsearch::index::metadata::file::SaveIndexMetadataError;
+use rusticsearch::query_parser::warnings::ParseWarnings;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, version_conflict_response, add_warning_headers, read_strict, strict_validation_response};
+
+
+pub fn view_get_index(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+
+    // Serialise index metadata
+    let mut json = {
+        match serde_json::to_value(&index.metadata) {
+            Ok(json) => json,
+            Err(_) => {
+                return Ok(json_response(status::InternalServerError, json!({
+                    "message": "unable to serialise index metadata"
+                })));
+            }
+        }
+    };
+
+    // Document counts
+    let store = get_store_or_500!(index.store());
+    let (doc_count, deleted_doc_count) = store.reader().doc_counts().unwrap();
+    json["docs"] = json!({
+        "count": doc_count,
+        "deleted": deleted_doc_count,
+    });
+
+    return Ok(json_response(status::Ok, json));
+}
+
+
+pub fn view_put_index(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+    let strict = read_strict(req);
+    let mut warnings = ParseWarnings::new();
+
+    // Lock cluster metadata
+    let mut cluster_metadata = system.metadata.write().unwrap();
+
+    // Find index
+    let index_ref = cluster_metadata.names.find_canonical(&index_name);
+
+    match index_ref {
+        Some(_) => {
+            // Update existing index
+            // TODO
+
+            info!(log, "updated index"; "index" => *index_name);
+        }
+        None => {
+            // Check that the tenant is allowed another index
+            check_tenancy_or_403!(system.tenancy.check_create_index(tenant.as_ref(), index_name));
+
+            // The data of a deleted index with the same name may not have been moved out of the way yet
+            let mut indices_dir = system.get_indices_dir();
+            indices_dir.push(index_name);
+            if indices_dir.exists() {
+                return Ok(json_response(status::Conflict, json!({"message": "An index with this name is still being deleted, try again later"})));
+            }
+
+            // Load metadata
+            // Settings that aren't supported are ignored, the client is warned about them
+            let mut metadata = IndexMetadata::with_analysis_registry(system.analysis.clone());
+            if let Some(data) = json_from_request_body!(req) {
+                check_unsupported_settings(&data, &mut warnings);
+                if strict {
+                    if let Some(error) = warnings.to_strict_error() {
+                        return Ok(strict_validation_response(error));
+                    }
+                }
+
+                if let Err(error) = parse_index_metadata(&mut metadata, data.clone()) {
+                    if strict {
+                        if let Some(pointer) = error.pointer(&data) {
+                            return Ok(strict_validation_response(json!({"message": format!("{:?}", error), "pointer": pointer})));
+                        }
+                    }
+
+                    // TODO: better error
+                    return Ok(json_response(status::BadRequest, json!({"message": "Couldn't parse index settings"})));
+                }
+            }
+
+            // Record when and by what the index was created
+            let index_id = Uuid::new_v4();
+            metadata.set_created(index_id, Utc::now());
+
+            // Add the index to the cluster state, this replaces any alias with the same name
+            let alias_deleted = match cluster_metadata.state.update(|state| state.insert_index(index_name.to_string(), index_id)) {
+                Ok(alias_deleted) => alias_deleted,
+                Err(e) => {
+                    return Ok(json_response(status::InternalServerError, json!({"message": String::from(e)})));
+                }
+            };
+
+            // Create index
+            let index = Index::new(index_id, index_name.clone().to_owned(), metadata, IndexStore::from_open_store(RocksDBStore::create(indices_dir).unwrap(), system.store_cache.clone()));
+            index.metadata.write().unwrap().save(index.metadata_path()).unwrap();
+            let index_ref = cluster_metadata.insert_index(index);
+
+            // If there's an alias with the new indexes name, delete it.
+            cluster_metadata.names.delete_alias_whole(index_name).unwrap();
+            if alias_deleted {
+                info!(log, "deleted alias"; "alias" => format!("{}", index_name), "reason" => "replaced by index");
+            }
+
+            // Register canonical name
+            cluster_metadata.names.insert_canonical(index_name.clone().to_owned(), index_ref).unwrap();
+
+            if let Err(e) = system.tenancy.record_index_created(tenant.as_ref(), index_name) {
+                warn!(log, "failed to record tenant usage"; "index" => *index_name, "error" => e);
+            }
+
+            info!(log, "created index"; "index" => *index_name);
+        }
+    }
+
+    return Ok(add_warning_headers(json_response(status::Ok, json!({"acknowledged": true})), &warnings));
+}
+
+
+pub fn view_delete_index(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Check that the API key can be used with these indices
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_selector));
+
+    // Lock cluster metadata
+    let mut cluster_metadata = system.metadata.write().unwrap();
+
+    // Aliases could refer to more indices than the caller expects, so they can't be deleted through
+    let resolved_names = cluster_metadata.names.resolved();
+    for name in index_selector.split(',') {
+        if resolved_names.find_canonical(name).is_none() && !resolved_names.find(name).is_empty() {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("[{}] is an alias, indices can't be deleted through aliases", name)})));
+        }
+    }
+
+    // Find the indices
+    let index_refs = resolve_targets_or_404!(cluster_metadata, *index_selector);
+    let index_names = index_refs.iter().filter_map(|index_ref| cluster_metadata.indices.get(index_ref)).map(|index| index.canonical_name().to_string()).collect::<Vec<_>>();
+    for index_name in index_names.iter() {
+        check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_name));
+
+        // The files of attached indices don't belong to the node, so they mustn't be deleted
+        if cluster_metadata.state.state().attached.contains_key(index_name) {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Index {} is attached, detach it instead", index_name)})));
+        }
+    }
+
+    // Remove the indices from the cluster state, all of them or none of them
+    let result = cluster_metadata.state.update(|state| {
+        for index_name in index_names.iter() {
+            state.remove_index(index_name)?;
+        }
+
+        Ok(())
+    });
+
+    if let Err(e) = result {
+        return Ok(json_response(status::InternalServerError, json!({"message": String::from(e)})));
+    }
+
+    // Remove indices
+    for index_ref in index_refs {
+        // Get the index name
+        let index_name = {
+            if let Some(index) = cluster_metadata.indices.get(&index_ref) {
+                index.canonical_name().to_string()
+            } else {
+                // Index doesn't exist
+                continue;
+            }
+        };
+
+        // Remove index from array, its files are deleted in the background once nothing is reading them
+        if let Some(index) = cluster_metadata.indices.remove(&index_ref) {
+            system.request_cache.invalidate_index(index.id());
+            let task_id = system.delete_index_data(index);
+            info!(log, "started deleting index data"; "index" => format!("{}", index_name), "task" => task_id);
+        }
+
+        // Delete canonical name
+        cluster_metadata.names.delete_canonical(&index_name, index_ref).unwrap();
+
+        // Give the index's usage back to its tenant
+        if let Err(e) = system.tenancy.record_index_deleted(&index_name) {
+            warn!(log, "failed to record tenant usage"; "index" => format!("{}", index_name), "error" => e);
+        }
+
+        info!(log, "deleted index"; "index" => index_name);
+
+        // Delete aliases
+        let alias_names = cluster_metadata.names.iter_index_aliases(index_ref).map(|n| n.to_string()).collect::<Vec<String>>();
+        for alias_name in alias_names {
+            let alias_deleted = cluster_metadata.names.delete_alias(&alias_name, index_ref).unwrap();
+
+            // If this was the only index being referenced by the alias, the alias would be deleted
+            if alias_deleted {
+                info!(log, "deleted alias"; "alias" => format!("{}", alias_name), "reason" => "no indices left");
+            }
+        }
+    }
+
+    return Ok(json_response(status::Ok, json!({"acknowledged": true})));
+}
+
+
+pub fn view_post_refresh_index(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Check that the API key can be used with these indices
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_selector));
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    // Find the indices
+    let index_refs = resolve_targets_or_404!(cluster_metadata, *index_selector);
+    let indices = index_refs.iter().filter_map(|index_ref| cluster_metadata.indices.get(index_ref)).collect::<Vec<_>>();
+    for index in indices.iter() {
+        check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+    }
+
+    // Documents are searchable as soon as they're written, so all there is to do is
+    // drop the cached responses of the indices
+    for index in indices {
+        system.request_cache.invalidate_index(index.id());
+    }
+
+    // TODO: {"_shards":{"total":10,"successful":5,"failed":0}}
+    return Ok(json_response(status::Ok, json!({"acknowledged": true})));
+}
+
+
+pub fn view_get_settings(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_name));
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+
+    let settings = match serde_json::to_value(&index.metadata) {
+        Ok(json) => json["settings"].clone(),
+        Err(_) => {
+            return Ok(json_response(status::InternalServerError, json!({
+                "message": "unable to serialise index metadata"
+            })));
+        }
+    };
+
+    let mut json = serde_json::Map::new();
+    json.insert(index.canonical_name().to_string(), json!({"settings": settings}));
+    return Ok(json_response(status::Ok, serde_json::Value::Object(json)));
+}
+
+
+pub fn view_put_settings(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_name));
+
+    // Load data from body
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No settings given"})));
+        }
+    };
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+    check_writable_or_403!(index);
+    let mut index_metadata = index.metadata.write().unwrap();
+
+    let previous_default_field = index_metadata.default_field.clone();
+    let previous_default_operator = index_metadata.default_operator;
+    let previous_max_result_window = index_metadata.max_result_window;
+    if let Err(error) = parse_settings_update(&mut index_metadata, &data) {
+        return Ok(json_response(status::BadRequest, json!({"message": format!("Couldn't update index settings: {:?}", error)})));
+    }
+
+    if let Err(error) = index_metadata.save(index.metadata_path()) {
+        // Put back the previous settings so memory matches what was saved
+        index_metadata.default_field = previous_default_field;
+        index_metadata.default_operator = previous_default_operator;
+        index_metadata.max_result_window = previous_max_result_window;
+
+        return Ok(match error {
+            SaveIndexMetadataError::VersionConflict{expected, actual} => version_conflict_response(expected, actual),
+            error => json_response(status::InternalServerError, json!({"message": String::from(error)})),
+        });
+    }
+
+    info!(log, "updated index settings"; "index" => index.canonical_name());
+
+    return Ok(json_response(status::Ok, json!({"acknowledged": true})));
+}
@@ -0,0 +1,175 @@
+use std::io::Read;
+use std::collections::HashMap;
+
+use serde_json;
+use url::form_urlencoded;
+use rusticsearch::search::schema::{FieldType, FieldFlags, FIELD_INDEXED, FIELD_STORED};
+
+use rusticsearch::mapping::MappingProperty;
+use rusticsearch::mapping::parse::parse as parse_mapping;
+use rusticsearch::index::metadata::file::SaveIndexMetadataError;
+use rusticsearch::json_pointer;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, version_conflict_response, read_strict, strict_validation_response};
+
+
+/// Reads the "metadata_version" parameter from the URL
+///
+/// If set, the request is rejected unless the index metadata is still at this version.
+fn read_metadata_version(req: &Request) -> Result<Option<u64>, String> {
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key == "metadata_version" {
+                return match value.parse() {
+                    Ok(version) => Ok(Some(version)),
+                    Err(_) => Err(value.into_owned()),
+                };
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+
+pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
+    let strict = read_strict(req);
+    let expected_version = match read_metadata_version(req) {
+        Ok(expected_version) => expected_version,
+        Err(value) => {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid metadata_version: {}", value)})));
+        }
+    };
+
+    // Lock cluster metadata
+    let mut cluster_metadata = system.metadata.write().unwrap();
+
+    // Get index
+    let index = get_index_or_404_mut!(cluster_metadata, *index_name);
+    check_writable_or_403!(index);
+
+    // Load data from body
+    let data = json_from_request_body!(req);
+
+    let data = match data {
+        Some(data) => data,
+        None => {
+            // TODO: Better error
+            return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
+        }
+    };
+
+    let data = data.as_object().unwrap().get(*mapping_name).unwrap();
+
+    // Insert mapping
+    let mapping_builder = match parse_mapping(&data) {
+        Ok(mapping_builder) => mapping_builder,
+        Err(error) => {
+            // In strict mode, the client is told which key wasn't recognised
+            if strict {
+                if let Some(pointer) = error.pointer(&data) {
+                    let pointer = json_pointer::join("", mapping_name) + &pointer;
+                    return Ok(strict_validation_response(json!({"message": format!("{:?}", error), "pointer": pointer})));
+                }
+            }
+
+            // TODO: Better error
+            return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
+        }
+    };
+    let mut index_metadata = index.metadata.write().unwrap();
+
+    // Don't change anything if the metadata has changed since the client last saw it
+    if let Some(expected_version) = expected_version {
+        if expected_version != index_metadata.version {
+            return Ok(version_conflict_response(expected_version, index_metadata.version));
+        }
+    }
+
+    let mut mapping = mapping_builder.build(&index_metadata);
+    //debug!("{:#?}", mapping);
+    let is_updating = index_metadata.mappings.contains_key(*mapping_name);
+
+    // Find list of new fields that need to be added to the store
+    let mut store = get_store_or_500!(index.store_mut());
+    let new_fields = {
+        let index_reader = store.reader();
+        let schema = index_reader.schema();
+        let mut new_fields: HashMap<String, (FieldType, FieldFlags)>  = HashMap::new();
+        for (name, property) in mapping.properties.iter() {
+            if let MappingProperty::Field(ref field_mapping) = *property {
+                let (field_type, field_flags) = field_mapping.schema_field();
+
+                // Check if this field already exists
+                if let Some(field_ref) = schema.get_field_by_name(&name) {
+                    let field_info = schema.get(&field_ref).expect("get_field_by_name returned an invalid FieldId");
+
+                    // Field already exists. Check for conflicting type or flags, otherwise ignore.
+                    if field_info.field_type == field_type && field_info.field_flags == field_flags {
+                        continue;
+                    } else {
+                        // Conflict!
+                        // TODO: Better error
+                        return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
+                    }
+                }
+
+                new_fields.insert(name.clone(), (field_type, field_flags));
+            }
+        }
+
+        new_fields
+    };
+
+    // Add new fields into the store
+    for (field_name, (field_type, field_flags)) in new_fields {
+        let indexed_yesno = if field_flags.contains(FIELD_INDEXED) { "yes" } else { "no" };
+        let stored_yesno = if field_flags.contains(FIELD_STORED) { "yes" } else { "no" };
+        info!(log, "adding field"; "index" => *index_name, "field" => &field_name, "type" => format!("{:?}", field_type), "indexed" => indexed_yesno, "stored" => stored_yesno);
+
+        store.add_field(field_name, field_type, field_flags).unwrap();
+    }
+
+    // Link the mapping
+    {
+        let index_reader = store.reader();
+        let schema = index_reader.schema();
+
+        for (name, property) in mapping.properties.iter_mut() {
+            if let MappingProperty::Field(ref mut field_mapping) = *property {
+                field_mapping.index_ref = schema.get_field_by_name(&name)
+            }
+        }
+    }
+
+    let previous_mapping = index_metadata.mappings.insert(mapping_name.clone().to_owned(), mapping);
+    if let Err(error) = index_metadata.save(index.metadata_path()) {
+        // Put back the mapping that was there before so memory matches what was saved
+        match previous_mapping {
+            Some(previous_mapping) => index_metadata.mappings.insert(mapping_name.clone().to_owned(), previous_mapping),
+            None => index_metadata.mappings.remove(*mapping_name),
+        };
+
+        return Ok(match error {
+            SaveIndexMetadataError::VersionConflict{expected, actual} => version_conflict_response(expected, actual),
+            error => json_response(status::InternalServerError, json!({"message": String::from(error)})),
+        });
+    }
+
+    if is_updating {
+        // TODO: New mapping should be merged with existing one
+        info!(log, "updated mapping"; "index" => *index_name, "mapping" => *mapping_name);
+    } else {
+        info!(log, "created mapping"; "index" => *index_name, "mapping" => *mapping_name);
+    }
+
+    return Ok(json_response(status::Ok, json!({"acknowledged": true, "metadata_version": index_metadata.version})));
+}
@@ -0,0 +1,140 @@
+extern crate iron;
+extern crate router;
+extern crate persistent;
+
+#[macro_use]
+mod utils;
+mod search_api;
+mod alias_api;
+mod document_api;
+mod index_api;
+mod mapping_api;
+mod bulk_api;
+mod stats_api;
+mod node_info_api;
+mod reindex_api;
+mod update_by_query_api;
+mod watcher_api;
+mod cluster_api;
+mod tasks_api;
+mod terms_api;
+mod segments_api;
+mod checkpoint_api;
+mod attach_api;
+mod catch_panic;
+mod request_log;
+mod date_math_names;
+
+use std::sync::Arc;
+
+use api::iron::prelude::*;
+use api::iron::status;
+use api::iron::typemap::Key;
+use api::router::Router;
+use api::utils::json_response;
+use api::catch_panic::{CatchPanic, install_panic_hook};
+use api::request_log::RequestIds;
+use api::date_math_names::DateMathIndexNames;
+
+use rusticsearch::system::System;
+use rusticsearch::VERSION;
+
+
+fn view_home(_: &mut Request) -> IronResult<Response> {
+    Ok(json_response(status::Ok, json!({
+        "cluster_name": "rusticsearch",
+        "version": {
+            "number": VERSION
+        }
+    })))
+}
+
+
+fn get_router() -> Router {
+    router!(get "/" => view_home,
+            get "/:index/_count" => search_api::view_count,
+            post "/:index/_count" => search_api::view_count,
+            get "/:index/_search" => search_api::view_search,
+            post "/:index/_search" => search_api::view_search,
+            get "/:index/:mapping/_search" => search_api::view_search_type,
+            post "/:index/:mapping/_search" => search_api::view_search_type,
+            get "/_alias/:alias" => alias_api::view_get_global_alias,
+            get "/:index/_alias" => alias_api::view_get_alias_list,
+            get "/:index/_alias/:alias" => alias_api::view_get_alias,
+            put "/:index/_alias/:alias" => alias_api::view_put_alias,
+            get "/_mget" => document_api::view_post_mget,
+            post "/_mget" => document_api::view_post_mget,
+            get "/:index/_mget" => document_api::view_post_mget,
+            post "/:index/_mget" => document_api::view_post_mget,
+            get "/:index/:mapping/:doc" => document_api::view_get_doc,
+            put "/:index/:mapping/:doc" => document_api::view_put_doc,
+            delete "/:index/:mapping/:doc" => document_api::view_delete_doc,
+            get "/:index" => index_api::view_get_index,
+            put "/:index" => index_api::view_put_index,
+            delete "/:index" => index_api::view_delete_index,
+            post "/:index/_refresh" => index_api::view_post_refresh_index,
+            get "/:index/_settings" => index_api::view_get_settings,
+            put "/:index/_settings" => index_api::view_put_settings,
+            get "/_nodes" => node_info_api::view_get_node_info,
+            get "/_nodes/_local" => node_info_api::view_get_node_info,
+            get "/_nodes/stats" => stats_api::view_get_node_stats,
+            get "/_cluster/state" => cluster_api::view_get_cluster_state,
+            get "/_cluster/settings" => cluster_api::view_get_cluster_settings,
+            put "/_cluster/settings" => cluster_api::view_put_cluster_settings,
+            get "/_tasks" => tasks_api::view_get_task_list,
+            get "/_tasks/:task" => tasks_api::view_get_task,
+            get "/:index/_stats/fielddata" => stats_api::view_get_index_fielddata_stats,
+            get "/:index/_stats/maintenance" => stats_api::view_get_index_maintenance_stats,
+            post "/:index/_disk_usage" => stats_api::view_post_disk_usage,
+            get "/:index/_terms" => terms_api::view_get_terms,
+            get "/:index/_segments/_explain_merges" => segments_api::view_get_explain_merges,
+            post "/:index/_checkpoint/:checkpoint" => checkpoint_api::view_post_checkpoint,
+            post "/:index/_attach" => attach_api::view_post_attach,
+            post "/:index/_detach" => attach_api::view_post_detach,
+            put "/:index/_mapping/:mapping" => mapping_api::view_put_mapping,
+            post "/_bulk" => bulk_api::view_post_bulk,
+            post "/:index/_bulk" => bulk_api::view_post_index_bulk,
+            post "/_reindex" => reindex_api::view_post_reindex,
+            post "/:index/_update_by_query" => update_by_query_api::view_post_update_by_query,
+            get "/_watcher/watch/:watch" => watcher_api::view_get_watch,
+            put "/_watcher/watch/:watch" => watcher_api::view_put_watch,
+            delete "/_watcher/watch/:watch" => watcher_api::view_delete_watch)
+}
+
+
+// The "Context" struct just wraps Arc<System> so we can put it into chain.link()
+// Workaround for: https://github.com/iron/persistent/issues/55
+
+struct Context {
+    system: Arc<System>,
+}
+
+
+impl Context {
+    fn new(system: Arc<System>) -> Context {
+        Context {
+            system: system,
+        }
+    }
+}
+
+
+impl Key for Context {
+    type Value = Context;
+}
+
+
+pub fn api_main(system: Arc<System>) {
+    let router = get_router();
+    let mut chain = Chain::new(router);
+    install_panic_hook();
+    chain.around(DateMathIndexNames);
+    chain.around(CatchPanic::new(system.log.clone()));
+    chain.around(RequestIds::new(system.log.clone()));
+    chain.link(persistent::Read::<Context>::both(Context::new(system.clone())));
+    info!(system.log, "listening"; "scheme" => "http", "address" => "localhost", "port" => 9200);
+
+    if let Err(error) = Iron::new(chain).http("localhost:9200") {
+        crit!(system.log, "unable to start api server"; "error" => format!("{}", error));
+    }
+}
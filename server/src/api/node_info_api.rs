@@ -0,0 +1,66 @@
+use rusticsearch::VERSION;
+use rusticsearch::build_info::{self, BUILD_HASH, RUSTC_VERSION, ROCKSDB_VERSION};
+use rusticsearch::settings::WRITE_QUEUE_SIZE;
+use rusticsearch::query_parser::registry::custom_query_names;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::utils::json_response;
+
+
+/// Describes this node: how it was built, where it keeps its data and how it's configured
+///
+/// There's only ever one node, so "_nodes" and "_nodes/_local" are the same.
+pub fn view_get_node_info(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    get_tenant_or_401!(req, system);
+
+    // Collectors, token filters, tokenizers and query types can be added to the node,
+    // they're registered by applications that embed it
+    let mut plugins = system.collectors.names().into_iter().map(|name| {
+        json!({
+            "name": name,
+            "type": "collector",
+        })
+    }).collect::<Vec<_>>();
+    for name in system.analysis.filter_names() {
+        plugins.push(json!({"name": name, "type": "token_filter"}));
+    }
+    for name in system.analysis.tokenizer_names() {
+        plugins.push(json!({"name": name, "type": "tokenizer"}));
+    }
+    for name in custom_query_names() {
+        plugins.push(json!({"name": name, "type": "query"}));
+    }
+
+    let settings_path = system.settings.path().map(|path| path.display().to_string());
+
+    Ok(json_response(status::Ok, json!({
+        "cluster_name": "rusticsearch",
+        "nodes": {
+            "local": {
+                "name": "local",
+                "version": VERSION,
+                "build_hash": BUILD_HASH,
+                "build_type": build_info::build_type(),
+                "rust_version": RUSTC_VERSION,
+                "rocksdb_version": ROCKSDB_VERSION,
+                "features": build_info::features(),
+                "paths": {
+                    "data": system.data_dir().display().to_string(),
+                    "indices": system.get_indices_dir().display().to_string(),
+                    "settings": settings_path,
+                },
+                "settings": system.settings.effective(),
+                "thread_pool": {
+                    "write": {
+                        "type": "fixed",
+                        "queue_size": system.settings.get_positive_integer(WRITE_QUEUE_SIZE),
+                    }
+                },
+                "plugins": plugins,
+            }
+        }
+    })))
+}
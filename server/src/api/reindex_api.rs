@@ -0,0 +1,168 @@
+use std::io::Read;
+use std::time::Instant;
+
+use serde_json;
+
+use rusticsearch::document::DocumentSource;
+use rusticsearch::remote::RemoteSource;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::utils::{json_response, millis_since};
+
+
+pub fn view_post_reindex(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+
+    // Load data from body
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "Request body is required"})));
+        }
+    };
+
+    // Source
+    let source = match data.get("source") {
+        Some(source) => source,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "\"source\" is required"})));
+        }
+    };
+
+    // Documents can't be read back out of rusticsearch indices so only remote sources are supported
+    if source.get("remote").is_none() {
+        return Ok(json_response(status::BadRequest, json!({"message": "Only remote sources are supported"})));
+    }
+
+    let source = match RemoteSource::parse(source) {
+        Ok(source) => source,
+        Err(e) => {
+            return Ok(json_response(status::BadRequest, json!({"message": String::from(e)})));
+        }
+    };
+
+    // Destination
+    let dest_index = match data.get("dest").and_then(|dest| dest.get("index")).and_then(|index| index.as_str()) {
+        Some(dest_index) => dest_index,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "\"dest.index\" is required"})));
+        }
+    };
+
+    // Overrides the type of every document, for when the remote types don't match the local mappings
+    let dest_type = data.get("dest").and_then(|dest| dest.get("type")).and_then(|doc_type| doc_type.as_str());
+
+    // Make sure the destination exists before reading anything
+    {
+        let cluster_metadata = system.metadata.read().unwrap();
+        let index = get_index_or_404!(cluster_metadata, dest_index);
+        check_writable_or_403!(index);
+    }
+
+    info!(log, "started reindex from remote"; "host" => source.host(), "index" => dest_index);
+
+    let start_time = Instant::now();
+    let mut total = 0;
+    let mut created = 0;
+    let mut updated = 0;
+    let mut batches = 0;
+    let mut failures = Vec::new();
+
+    let mut scroll = source.scroll();
+    loop {
+        // Fetch the next batch without holding any locks, as this could take a while
+        let hits = match scroll.next_batch() {
+            Ok(hits) => hits,
+            Err(e) => {
+                let message = String::from(e);
+                warn!(log, "reindex from remote failed"; "host" => source.host(), "index" => dest_index, "error" => &message);
+
+                return Ok(json_response(status::BadGateway, json!({
+                    "message": format!("Request to remote cluster failed: {}", message),
+                    "total": total,
+                    "created": created,
+                    "updated": updated,
+                    "batches": batches,
+                    "failures": failures,
+                })));
+            }
+        };
+
+        if hits.is_empty() {
+            break;
+        }
+
+        batches += 1;
+
+        // Index the batch
+        let cluster_metadata = system.metadata.read().unwrap();
+        let index = get_index_or_404!(cluster_metadata, dest_index);
+        let index_metadata = index.metadata.read().unwrap();
+        let store = get_store_or_500!(index.store());
+
+        for hit in hits.iter() {
+            total += 1;
+
+            let doc_type = dest_type.unwrap_or(&hit.doc_type);
+            let mapping = match index_metadata.mappings.get(doc_type) {
+                Some(mapping) => mapping,
+                None => {
+                    failures.push(json!({"id": hit.id, "type": doc_type, "cause": "Mapping not found"}));
+                    continue;
+                }
+            };
+
+            // Transform the document through the mapping
+            let document_source = DocumentSource {
+                key: &hit.id,
+                doc_type: doc_type,
+                data: &hit.source,
+            };
+
+            let doc = match document_source.prepare(mapping) {
+                Ok(doc) => doc,
+                Err(e) => {
+                    failures.push(json!({"id": hit.id, "type": doc_type, "cause": format!("{:?}", e)}));
+                    continue;
+                }
+            };
+
+            let exists = match store.reader().contains_document_key(&hit.id) {
+                Ok(exists) => exists,
+                Err(e) => {
+                    failures.push(json!({"id": hit.id, "type": doc_type, "cause": format!("{}", e)}));
+                    continue;
+                }
+            };
+            match store.insert_or_update_document(&doc) {
+                Ok(()) => {
+                    if exists {
+                        updated += 1;
+                    } else {
+                        created += 1;
+                    }
+                }
+                Err(e) => {
+                    failures.push(json!({"id": hit.id, "type": doc_type, "cause": format!("{:?}", e)}));
+                }
+            }
+        }
+    }
+
+    let took = millis_since(start_time);
+
+    info!(log, "finished reindex from remote"; "host" => source.host(), "index" => dest_index, "total" => total, "failures" => failures.len());
+
+    Ok(json_response(status::Ok, json!({
+        "took": took,
+        "timed_out": false,
+        "total": total,
+        "created": created,
+        "updated": updated,
+        "batches": batches,
+        "failures": failures,
+    })))
+}
@@ -0,0 +1,80 @@
+//! Gives each request an id and a logger that includes it
+//!
+//! Views log through the request's logger (see `get_request_log!`), so everything
+//! logged while handling a request can be matched up. The id is also returned in the
+//! "X-Request-Id" header of the response and is used as the id of the request's trace.
+
+use slog::Logger;
+use uuid::Uuid;
+
+use api::iron::prelude::*;
+use api::iron::{Handler, AroundMiddleware};
+use api::iron::typemap::Key;
+
+
+/// The logger of a request, in the request's extensions
+pub struct RequestLog;
+
+
+impl Key for RequestLog {
+    type Value = Logger;
+}
+
+
+/// The id of a request, in the request's extensions
+pub struct RequestId;
+
+
+impl Key for RequestId {
+    type Value = String;
+}
+
+
+pub struct RequestIds {
+    log: Logger,
+}
+
+
+impl RequestIds {
+    pub fn new(log: Logger) -> RequestIds {
+        RequestIds {
+            log: log,
+        }
+    }
+}
+
+
+impl AroundMiddleware for RequestIds {
+    fn around(self, handler: Box<Handler>) -> Box<Handler> {
+        Box::new(RequestIdsHandler {
+            log: self.log,
+            handler: handler,
+        })
+    }
+}
+
+
+struct RequestIdsHandler {
+    log: Logger,
+    handler: Box<Handler>,
+}
+
+
+impl Handler for RequestIdsHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let request_id = Uuid::new_v4().simple().to_string();
+        req.extensions.insert::<RequestLog>(self.log.new(o!("request_id" => request_id.clone())));
+        req.extensions.insert::<RequestId>(request_id.clone());
+
+        match self.handler.handle(req) {
+            Ok(mut response) => {
+                response.headers.set_raw("X-Request-Id", vec![request_id.into_bytes()]);
+                Ok(response)
+            }
+            Err(mut error) => {
+                error.response.headers.set_raw("X-Request-Id", vec![request_id.into_bytes()]);
+                Err(error)
+            }
+        }
+    }
+}
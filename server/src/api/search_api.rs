@@ -0,0 +1,921 @@
+use std::io::Read;
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde_json;
+use slog::Logger;
+use url::form_urlencoded;
+use uuid::Uuid;
+use rusticsearch::search::Term;
+use rusticsearch::search::document::{DocId, FieldValue};
+use rusticsearch::search::query::Query;
+use rusticsearch::search::schema::{Schema, FieldId};
+use rusticsearch::search::runtime::RuntimeField;
+use rusticsearch::search::backends::rocksdb::{RocksDBReader, SegmentFailure};
+use rusticsearch::search::collectors::top_score::TopScoreCollector;
+use rusticsearch::search::collectors::Collector;
+use rusticsearch::search::collectors::total_count::TotalCountCollector;
+use rusticsearch::search::collectors::sorted::SortedCollector;
+use rusticsearch::search::collectors::multi::MultiCollector;
+use rusticsearch::search::collectors::registry::ExtensionCollector;
+use rusticsearch::search::aggregations::AggregationsCollector;
+
+use rusticsearch::query_parser::{QueryBuildContext, QueryParseError, parse as parse_query};
+use rusticsearch::query_parser::match_all_query::parse as parse_match_all;
+use rusticsearch::cluster::terms_lookup::ClusterTermsLookup;
+use rusticsearch::query_parser::sort::parse as parse_sort;
+use rusticsearch::query_parser::rescore::parse as parse_rescore;
+use rusticsearch::query_parser::fields::parse as parse_fields;
+use rusticsearch::query_parser::paging::{parse as parse_paging, parse_param as parse_paging_param};
+use rusticsearch::query_parser::aggregations::parse as parse_aggregations;
+use rusticsearch::query_parser::aggregations::sampler::parse_sample;
+use rusticsearch::query_parser::knn_query::parse_search_section as parse_knn_section;
+use rusticsearch::query_parser::warnings::ParseWarnings;
+use rusticsearch::json_pointer;
+use rusticsearch::mapping::base64;
+use rusticsearch::mapping::date_format::format_date;
+use rusticsearch::mapping::runtime::RuntimeMappings;
+use rusticsearch::index::routing::{SearchPreference, SearchPreferenceParseError};
+use rusticsearch::index::request_cache::{RequestCacheKey, is_cacheable_search};
+use rusticsearch::index::inflight::Flight;
+use rusticsearch::settings::{SEARCH_SLOWLOG_WARN, SEARCH_SLOWLOG_INFO};
+use rusticsearch::system::System;
+use rusticsearch::trace::{Trace, Span};
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, raw_json_response, add_warning_headers, too_many_clauses_response, result_window_too_large_response, millis_since, read_strict, strict_validation_response};
+
+
+/// The keys of a search body that are supported, others are ignored with a warning
+///
+/// "indices_boost" isn't supported until several indices can be searched together, as
+/// boosting the only index that is searched would scale every score by the same amount.
+const SEARCH_BODY_KEYS: &'static [&'static str] = &[
+    "query", "knn", "from", "size", "sort", "rescore", "fields", "_source", "collectors",
+    "aggs", "aggregations", "sample", "runtime_mappings",
+];
+
+
+/// Reads the "preference" parameter from the URL
+pub fn read_preference(req: &Request) -> Result<SearchPreference, SearchPreferenceParseError> {
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key == "preference" {
+                return SearchPreference::parse(&value);
+            }
+        }
+    }
+
+    Ok(SearchPreference::default())
+}
+
+
+/// Checks if the request asked for its response to be cached
+fn read_request_cache(req: &Request) -> bool {
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key == "request_cache" {
+                return value == "true";
+            }
+        }
+    }
+
+    false
+}
+
+
+/// Builds the request cache key of a request
+///
+/// The request is normalised into its path, its URL parameters (sorted) and its body
+/// (serialised with sorted keys), so requests that only differ in the order of these
+/// share a cache entry. The version of the index metadata is included as changes to
+/// mappings and analyzers change how queries are built.
+fn build_request_cache_key(req: &Request, index_id: &Uuid, epoch: u64, metadata_version: u64, body: Option<&serde_json::Value>) -> RequestCacheKey {
+    let mut params = match req.url.query() {
+        Some(url_query) => {
+            form_urlencoded::parse(url_query.as_bytes())
+                .filter(|&(ref key, _)| key != "request_cache")
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+        }
+        None => Vec::new(),
+    };
+    params.sort();
+
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+
+    RequestCacheKey {
+        index_id: *index_id,
+        epoch: epoch,
+        request: format!("/{} {} {} {}", req.url.path().join("/"), metadata_version, params.join("&"), body),
+    }
+}
+
+
+/// Finds the pointer to the key that a parse error is about, in the first of the sections of the body that has it
+fn parse_error_pointer(body: &serde_json::Value, sections: &[&str], error: &QueryParseError) -> Option<String> {
+    sections.iter().filter_map(|section| {
+        body.get(*section).and_then(|json| error.pointer(json)).map(|pointer| json_pointer::join("", section) + &pointer)
+    }).next()
+}
+
+
+/// Builds the "_shards" section of a response
+///
+/// Each index is stored in a single shard. If any part of it couldn't be searched, the
+/// shard is reported as failed, as its results are incomplete.
+fn shards_json(failures: Vec<serde_json::Value>) -> serde_json::Value {
+    if failures.is_empty() {
+        json!({"total": 1, "successful": 1, "skipped": 0, "failed": 0})
+    } else {
+        json!({
+            "total": 1,
+            "successful": 0,
+            "skipped": 0,
+            "failed": 1,
+            "failures": failures,
+        })
+    }
+}
+
+
+/// Lists the segments that couldn't be searched, for the "failures" of the "_shards" section
+fn segment_failures_json(index_name: &str, segment_failures: &[SegmentFailure]) -> Vec<serde_json::Value> {
+    segment_failures.iter().map(|failure| {
+        json!({
+            "shard": 0,
+            "index": index_name,
+            "segment": failure.segment,
+            "reason": failure.reason,
+        })
+    }).collect()
+}
+
+
+/// The search couldn't be run on the index
+fn search_failed_response(index_name: &str, error: &str, start_time: Instant) -> Response {
+    json_response(status::InternalServerError, json!({
+        "message": "all shards failed",
+        "took": millis_since(start_time),
+        "timed_out": false,
+        "_shards": shards_json(vec![json!({"shard": 0, "index": index_name, "reason": error})]),
+    }))
+}
+
+
+/// The body of a search or count wasn't a JSON object
+fn body_not_object_response() -> Response {
+    json_response(status::BadRequest, json!({"message": "Request body must be an object"}))
+}
+
+
+/// Logs the search if it took longer than one of the slowlog thresholds
+fn log_slow_search(system: &System, log: &Logger, index_name: &str, query_json: &serde_json::Value, start_time: Instant) {
+    let took = start_time.elapsed();
+    let exceeds = |setting| system.settings.get_time_value(setting).map(|threshold| took >= threshold).unwrap_or(false);
+
+    if exceeds(SEARCH_SLOWLOG_WARN) {
+        warn!(log, "slow search"; "index" => index_name, "took" => millis_since(start_time), "source" => query_json.to_string());
+    } else if exceeds(SEARCH_SLOWLOG_INFO) {
+        info!(log, "slow search"; "index" => index_name, "took" => millis_since(start_time), "source" => query_json.to_string());
+    }
+}
+
+
+pub fn view_count(req: &mut Request) -> IronResult<Response> {
+    let start_time = Instant::now();
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Check that the API key can be used with this index
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_name));
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+
+    // An alias or pattern may have resolved to an index that the tenant can't use
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+
+    let preference = match read_preference(req) {
+        Ok(preference) => preference,
+        Err(SearchPreferenceParseError::UnrecognisedPreference(preference)) => {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Unrecognised preference: {}", preference)})));
+        }
+    };
+    let store = get_store_or_500!(index.store_for_search(&preference));
+
+    // The epoch must be read before the reader is taken, see RocksDBStore::epoch
+    let epoch = store.epoch();
+    let index_reader = store.reader();
+    let index_metadata = index.metadata.read().unwrap();
+    let query_json = json_from_request_body!(req);
+
+    // Respond from the request cache, if the request asked for it
+    let mut request_cache_key = None;
+    if read_request_cache(req) {
+        let key = build_request_cache_key(req, index.id(), epoch, index_metadata.version, query_json.as_ref());
+        if let Some(response) = system.request_cache.get(&key) {
+            return Ok(raw_json_response(status::Ok, (*response).clone()));
+        }
+
+        request_cache_key = Some(key);
+    }
+
+    let (count, segment_failures) = match query_json {
+        Some(query_json) => {
+            let query_object = match query_json.as_object() {
+                Some(query_object) => query_object,
+                None => return Ok(body_not_object_response()),
+            };
+
+            // Parse runtime fields
+            let runtime_mappings = match query_object.get("runtime_mappings") {
+                Some(runtime_mappings_json) => {
+                    match RuntimeMappings::parse(runtime_mappings_json) {
+                        Ok(runtime_mappings) => runtime_mappings,
+                        Err(message) => {
+                            return Ok(json_response(status::BadRequest, json!({"message": message})));
+                        }
+                    }
+                }
+                None => RuntimeMappings::default(),
+            };
+            let (schema, _) = runtime_mappings.extend_schema(index_reader.schema());
+
+            // Parse query, every document is counted if there isn't one
+            let query = match query_object.get("query") {
+                Some(query_json) => parse_query(query_json),
+                None => parse_match_all(&json!({})),
+            };
+            //debug!("{:#?}", query);
+
+            match query {
+                Ok(query) => {
+                    let terms_lookup = ClusterTermsLookup::new(&cluster_metadata, tenant.as_ref());
+                    let build_context = QueryBuildContext::new().set_index_metadata(&index_metadata).set_terms_lookup(&terms_lookup).set_runtime_mappings(&runtime_mappings).no_score();
+
+                    let query = query.build(&build_context, &schema);
+
+                    // Looked up terms come from other indices, which could change without changing the epoch
+                    // Queries relative to the current time (eg, "now-1d") match different documents as time goes on
+                    if terms_lookup.has_looked_up_terms() || build_context.has_used_now() {
+                        request_cache_key = None;
+                    }
+
+                    if let Err(error) = index_reader.check_query_limits(&query, &system.query_limits()) {
+                        return Ok(too_many_clauses_response(&error));
+                    }
+
+                    let mut collector = TotalCountCollector::new();
+                    match index_reader.search_with_segment_failures(&mut collector, &query) {
+                        Ok(segment_failures) => (collector.get_total_count(), segment_failures),
+                        Err(error) => return Ok(search_failed_response(index.canonical_name(), &error, start_time)),
+                    }
+                }
+                Err(_) => {
+                    // TODO: What specifically is bad about the Query?
+                    let mut response = Response::with((status::BadRequest,
+                                                       "{\"message\": \"Query error\"}"));
+                    response.headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+                    return Ok(response);
+                }
+            }
+        }
+        None => {
+            let mut collector = TotalCountCollector::new();
+            match index_reader.search_with_segment_failures(&mut collector, &Query::all()) {
+                Ok(segment_failures) => (collector.get_total_count(), segment_failures),
+                Err(error) => return Ok(search_failed_response(index.canonical_name(), &error, start_time)),
+            }
+        }
+    };
+
+    // The count is missing the documents of any segments that failed, so it isn't cached
+    if !segment_failures.is_empty() {
+        warn!(log, "count failed on some segments"; "index" => index.canonical_name(), "failed_segments" => segment_failures.len());
+        request_cache_key = None;
+    }
+
+    let response = json!({
+        "count": count,
+        "_shards": shards_json(segment_failures_json(index.canonical_name(), &segment_failures)),
+    });
+    if let Some(request_cache_key) = request_cache_key {
+        system.request_cache.insert(request_cache_key, response.to_string());
+    }
+
+    return Ok(json_response(status::Ok, response));
+}
+
+
+/// Runs a search, also passing every match to the aggregations and the collectors that were requested by name
+///
+/// Returns the segments that couldn't be searched.
+fn search_with_extension_collectors<C: Collector, F: Fn(FieldId, DocId) -> Vec<FieldValue>>(index_reader: &RocksDBReader, collector: &mut C, query: &Query, aggregations_collector: &mut AggregationsCollector<F>, extension_collectors: &mut [(String, Box<ExtensionCollector>)], span: &Span) -> Result<Vec<SegmentFailure>, String> {
+    if aggregations_collector.is_empty() && extension_collectors.is_empty() {
+        return index_reader.search_traced(collector, query, Some(span));
+    }
+
+    let mut multi_collector = MultiCollector::new();
+    multi_collector.push(collector);
+    if !aggregations_collector.is_empty() {
+        multi_collector.push(aggregations_collector);
+    }
+    for &mut (_, ref mut extension_collector) in extension_collectors.iter_mut() {
+        multi_collector.push(extension_collector);
+    }
+
+    index_reader.search_traced(&mut multi_collector, query, Some(span))
+}
+
+
+/// Restricts a query to documents of the given mapping type
+fn filter_by_type(query: Query, doc_type: &str, schema: &Schema) -> Query {
+    match schema.get_field_by_name("_type") {
+        Some(type_field) => query.filter(Query::term(type_field, Term::from_string(doc_type))),
+        None => Query::None,
+    }
+}
+
+
+/// Reads the doc values of a field, or computes them if it's a runtime field
+fn read_field_values(index_reader: &RocksDBReader, runtime_fields: &HashMap<FieldId, RuntimeField>, field_id: FieldId, doc_id: DocId) -> Result<Vec<FieldValue>, String> {
+    match runtime_fields.get(&field_id) {
+        Some(runtime_field) => index_reader.runtime_values(runtime_field, doc_id),
+        None => index_reader.doc_values(field_id, doc_id).map_err(|e| format!("{:?}", e)),
+    }
+}
+
+
+/// Reads a stored string field of a document, if the field exists
+pub fn read_string_field(index_reader: &RocksDBReader, field: Option<FieldId>, doc_id: DocId) -> Option<String> {
+    match field.map(|field| index_reader.read_stored_field(field, doc_id)) {
+        Some(Ok(Some(FieldValue::String(value)))) => Some(value),
+        _ => None,
+    }
+}
+
+
+/// Converts a value read from doc values into JSON, formatting dates with the format if given
+fn doc_value_to_json(value: FieldValue, date_format: Option<&str>) -> serde_json::Value {
+    match (value, date_format) {
+        (FieldValue::DateTime(value), Some(date_format)) => {
+            match format_date(&value, date_format) {
+                Some(formatted) => serde_json::Value::String(formatted),
+                None => serde_json::Value::String(value.to_rfc3339()),
+            }
+        }
+        (value, _) => field_value_to_json(value),
+    }
+}
+
+
+fn field_value_to_json(value: FieldValue) -> serde_json::Value {
+    match value {
+        FieldValue::String(value) => serde_json::Value::String(value),
+        FieldValue::Integer(value) => json!(value),
+        FieldValue::Boolean(value) => serde_json::Value::Bool(value),
+        FieldValue::DateTime(value) => serde_json::Value::String(value.to_rfc3339()),
+        FieldValue::Bytes(value) => serde_json::Value::String(base64::encode(&value)),
+        FieldValue::Vector(value) => json!(value),
+        FieldValue::GeoPoint(point) => json!({"lat": point.lat, "lon": point.lon}),
+        FieldValue::Float(value) => json!(value),
+    }
+}
+
+
+pub fn view_search(req: &mut Request) -> IronResult<Response> {
+    search(req, None)
+}
+
+
+pub fn view_search_type(req: &mut Request) -> IronResult<Response> {
+    let doc_type = read_path_parameter!(req, "mapping").unwrap_or("").to_string();
+    search(req, Some(doc_type))
+}
+
+
+fn search(req: &mut Request, doc_type: Option<String>) -> IronResult<Response> {
+    let start_time = Instant::now();
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let trace = Trace::new(get_request_id!(req));
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Check that the API key can be used with this index
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_name));
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+
+    // An alias or pattern may have resolved to an index that the tenant can't use
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+
+    let preference = match read_preference(req) {
+        Ok(preference) => preference,
+        Err(SearchPreferenceParseError::UnrecognisedPreference(preference)) => {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Unrecognised preference: {}", preference)})));
+        }
+    };
+    let store = get_store_or_500!(index.store_for_search(&preference));
+
+    // The epoch must be read before the reader is taken, see RocksDBStore::epoch
+    let epoch = store.epoch();
+    let index_reader = store.reader();
+    let index_metadata = index.metadata.read().unwrap();
+    let strict = read_strict(req);
+
+    match json_from_request_body!(req) {
+        Some(query_json) => {
+            let parse_span = trace.span("parse");
+
+            let query_object = match query_json.as_object() {
+                Some(query_object) => query_object,
+                None => return Ok(body_not_object_response()),
+            };
+
+            // Parts of the request that aren't supported are ignored, the client is warned about them
+            let mut warnings = ParseWarnings::new();
+            warnings.check_keys("search", "", query_object, SEARCH_BODY_KEYS);
+            if doc_type.is_some() {
+                warnings.deprecated("specifying types in search requests is deprecated");
+            }
+
+            // Parse runtime fields, their values are computed from each document when it's searched
+            // They're added to a copy of the schema so the query, sorts and aggregations can find them
+            let runtime_mappings = match query_object.get("runtime_mappings") {
+                Some(runtime_mappings_json) => {
+                    match RuntimeMappings::parse(runtime_mappings_json) {
+                        Ok(runtime_mappings) => runtime_mappings,
+                        Err(message) => {
+                            return Ok(json_response(status::BadRequest, json!({"message": message})));
+                        }
+                    }
+                }
+                None => RuntimeMappings::default(),
+            };
+            let (schema, runtime_fields) = runtime_mappings.extend_schema(index_reader.schema());
+
+            // Parse query, every document matches if there isn't one
+            // A "knn" section scores the documents that match the query (or all documents)
+            // by vector similarity
+            let (query, knn_k) = match query_object.get("knn") {
+                Some(knn_json) => {
+                    let query = match query_object.get("query") {
+                        Some(query_json) => parse_query(query_json).map(Some),
+                        None => Ok(None),
+                    };
+
+                    match query.and_then(|query| parse_knn_section(knn_json, query)) {
+                        Ok((query, k)) => (Ok(query), Some(k)),
+                        Err(error) => (Err(error), None),
+                    }
+                }
+                None => {
+                    let query = match query_object.get("query") {
+                        Some(query_json) => parse_query(query_json),
+                        None => parse_match_all(&json!({})),
+                    };
+
+                    (query, None)
+                }
+            };
+            //debug!("{:#?}", query);
+
+            // Parse sort
+            let sort = match query_object.get("sort") {
+                Some(sort_json) => {
+                    match parse_sort(sort_json).and_then(|builder| builder.build(&schema)) {
+                        Ok(sort) => Some(sort),
+                        Err(error) => {
+                            return Ok(json_response(status::BadRequest, json!({"message": format!("Sort error: {:?}", error)})));
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            // Parse rescore
+            let rescore = match query_object.get("rescore") {
+                Some(rescore_json) => {
+                    if sort.is_some() {
+                        return Ok(json_response(status::BadRequest, json!({"message": "Cannot use \"rescore\" with \"sort\""})));
+                    }
+
+                    match parse_rescore(rescore_json) {
+                        Ok(rescore) => Some(rescore),
+                        Err(error) => {
+                            return Ok(json_response(status::BadRequest, json!({"message": format!("Rescore error: {:?}", error)})));
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            // Parse fields, their values are returned with each hit
+            let requested_fields = match query_object.get("fields") {
+                Some(fields_json) => {
+                    match parse_fields(fields_json) {
+                        Ok(requested_fields) => requested_fields,
+                        Err(error) => {
+                            return Ok(json_response(status::BadRequest, json!({"message": format!("Fields error: {:?}", error)})));
+                        }
+                    }
+                }
+                None => Vec::new(),
+            };
+
+            // "_source": false leaves the source out of hits
+            let include_source = match query_object.get("_source") {
+                Some(&serde_json::Value::Bool(include_source)) => include_source,
+                _ => true,
+            };
+
+            // Create the collectors that were requested by name
+            let mut extension_collectors = match query_object.get("collectors") {
+                Some(collectors_json) => {
+                    match system.collectors.create_from_json(collectors_json) {
+                        Ok(extension_collectors) => extension_collectors,
+                        Err(error) => {
+                            return Ok(json_response(status::BadRequest, json!({"message": error.message()})));
+                        }
+                    }
+                }
+                None => Vec::new(),
+            };
+
+            // Parse aggregations, they're run over every match of the query
+            let aggregations = match query_object.get("aggs").or_else(|| query_object.get("aggregations")) {
+                Some(aggregations_json) => {
+                    match parse_aggregations(aggregations_json) {
+                        Ok(aggregations) => Some(aggregations),
+                        Err(error) => {
+                            if strict {
+                                if let Some(pointer) = parse_error_pointer(&query_json, &["aggs", "aggregations"], &error) {
+                                    return Ok(strict_validation_response(json!({"message": format!("Aggregations error: {:?}", error), "pointer": pointer})));
+                                }
+                            }
+
+                            return Ok(json_response(status::BadRequest, json!({"message": format!("Aggregations error: {:?}", error)})));
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            // A "sample" limits the aggregations to the best scoring matches of each segment
+            let sample_size = match query_object.get("sample") {
+                Some(sample_json) => {
+                    match parse_sample(sample_json) {
+                        Ok(shard_size) => Some(shard_size),
+                        Err(error) => {
+                            return Ok(json_response(status::BadRequest, json!({"message": format!("Sample error: {:?}", error)})));
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            // Parse paging, a "knn" section returns its "k" nearest documents unless a size is given
+            let mut paging = match parse_paging(&query_json, knn_k.unwrap_or(10)) {
+                Ok(paging) => paging,
+                Err(error) => {
+                    return Ok(json_response(status::BadRequest, json!({"message": format!("Paging error: {:?}", error)})));
+                }
+            };
+
+            match query {
+                Ok(query) => {
+                    let mut fields = Vec::new();
+
+                    // TODO: Rewrite this
+                    if let Some(ref url_query) = req.url.query() {
+                        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+                            match key.as_ref() {
+                                "from" | "size" => {
+                                    let count = match parse_paging_param(&value) {
+                                        Ok(count) => count,
+                                        Err(_) => {
+                                            return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid value for the [{}] parameter: [{}]", key, value)})));
+                                        }
+                                    };
+
+                                    if key == "from" {
+                                        paging.from = count;
+                                    } else {
+                                        paging.size = count;
+                                    }
+                                }
+                                "fields" => {
+                                    for field_name in value.split(",") {
+                                        let field_ref = match schema.get_field_by_name(field_name) {
+                                            Some(field_ref) => field_ref,
+                                            None => {
+                                                warn!(log, "unknown field {:?}", field_name);
+                                                warnings.add(format!("[search] field [{}] doesn't exist and was ignored", field_name));
+                                                continue;
+                                            }
+                                        };
+
+                                        fields.push((field_name.to_owned(), field_ref));
+                                    }
+                                }
+                                "preference" => {}  // Handled by read_preference
+                                "request_cache" => {}  // Handled by read_request_cache
+                                "strict" => {}  // Handled by read_strict
+                                // terminate_after
+                                // explain
+                                // version
+                                // timeout
+                                // fielddata_fields
+                                // track_scores
+                                // stats
+                                // suggest_field
+                                _ => {
+                                    warn!(log, "unrecognised GET parameter {:?}", key);
+                                    warnings.unsupported_parameter("search", &key);
+                                }
+                            }
+                        }
+                    }
+
+                    // In strict mode, parts of the request that aren't supported are errors
+                    if strict {
+                        if let Some(error) = warnings.to_strict_error() {
+                            return Ok(strict_validation_response(error));
+                        }
+                    }
+
+                    // Collectors hold every hit up to the end of the page, so deep pages are refused
+                    let max_result_window = index_metadata.max_result_window();
+                    let result_window = paging.result_window();
+                    if result_window > max_result_window {
+                        return Ok(result_window_too_large_response(result_window, max_result_window));
+                    }
+
+                    // Searches that don't return any hits can be answered from the request cache
+                    let mut request_cache_key = None;
+                    if is_cacheable_search(&paging, !extension_collectors.is_empty()) && read_request_cache(req) {
+                        let key = build_request_cache_key(req, index.id(), epoch, index_metadata.version, Some(&query_json));
+                        if let Some(response) = system.request_cache.get(&key) {
+                            return Ok(add_warning_headers(raw_json_response(status::Ok, (*response).clone()), &warnings));
+                        }
+
+                        request_cache_key = Some(key);
+                    }
+
+                    // Identical searches that are running at the same time share one response
+                    let mut flight_leader = None;
+                    if extension_collectors.is_empty() {
+                        let key = build_request_cache_key(req, index.id(), epoch, index_metadata.version, Some(&query_json));
+                        match system.inflight_searches.join(key) {
+                            Flight::Leader(leader) => flight_leader = Some(leader),
+                            Flight::Shared(response) => return Ok(add_warning_headers(raw_json_response(status::Ok, (*response).clone()), &warnings)),
+                            Flight::Unshared => {}
+                        }
+                    }
+
+                    // Build query
+                    let terms_lookup = ClusterTermsLookup::new(&cluster_metadata, tenant.as_ref());
+                    let build_context = QueryBuildContext::new().set_index_metadata(&index_metadata).set_terms_lookup(&terms_lookup).set_runtime_mappings(&runtime_mappings);
+                    let mut query = query.build(&build_context, &schema);
+                    let rescorers = rescore.as_ref().map(|rescore| rescore.build(&build_context, &schema)).unwrap_or_default();
+                    let aggregations = aggregations.as_ref().map(|aggregations| aggregations.build(&build_context, &schema)).unwrap_or_default();
+
+                    if let Some(ref doc_type) = doc_type {
+                        query = filter_by_type(query, doc_type, &index_reader.schema());
+                    }
+
+                    // Looked up terms come from other indices, which could change without changing the epoch
+                    // They also depend on which indices the tenant can access, so the response isn't shared either
+                    if terms_lookup.has_looked_up_terms() {
+                        request_cache_key = None;
+                        flight_leader = None;
+                    }
+
+                    // Queries relative to the current time (eg, "now-1d") match different documents as time goes on
+                    if build_context.has_used_now() {
+                        request_cache_key = None;
+                    }
+
+                    // Check the query (and the queries of the rescorers) won't expand into too many clauses
+                    for query in Some(&query).into_iter().chain(rescorers.iter().map(|rescorer| &rescorer.query)) {
+                        if let Err(error) = index_reader.check_query_limits(query, &system.query_limits()) {
+                            return Ok(too_many_clauses_response(&error));
+                        }
+                    }
+
+                    drop(parse_span);
+
+                    // Do the search
+                    // Each match is returned with its sort values, if the results are sorted
+                    let execute_span = trace.span("execute");
+                    let mut aggregations_collector = AggregationsCollector::new(&aggregations, |field_id, doc_id| {
+                        read_field_values(&index_reader, &runtime_fields, field_id, doc_id).unwrap_or_default()
+                    });
+                    if let Some(sample_size) = sample_size {
+                        aggregations_collector = aggregations_collector.set_sample(sample_size);
+                    }
+                    let (matches, segment_failures) = match sort {
+                        Some(sort) => {
+                            let mut collector = SortedCollector::new(sort, paging.result_window(), |field_id, doc_id| {
+                                read_field_values(&index_reader, &runtime_fields, field_id, doc_id).unwrap_or_default()
+                            });
+                            let segment_failures = match search_with_extension_collectors(&index_reader, &mut collector, &query, &mut aggregations_collector, &mut extension_collectors, &execute_span) {
+                                Ok(segment_failures) => segment_failures,
+                                Err(error) => return Ok(search_failed_response(index.canonical_name(), &error, start_time)),
+                            };
+                            let needs_score = collector.needs_score();
+
+                            let matches = collector.into_sorted_vec().into_iter().map(|doc| {
+                                let score = if needs_score { doc.doc_match.score() } else { None };
+                                (doc.doc_match.doc_id(), score, Some(doc.sort_values))
+                            }).collect::<Vec<_>>();
+
+                            (matches, segment_failures)
+                        }
+                        None => {
+                            // Enough hits are collected to fill the window of each rescorer
+                            let max_window_size = rescore.as_ref().map(|rescore| rescore.max_window_size()).unwrap_or(0);
+                            let mut collector = TopScoreCollector::new(max(paging.result_window(), max_window_size));
+                            let segment_failures = match search_with_extension_collectors(&index_reader, &mut collector, &query, &mut aggregations_collector, &mut extension_collectors, &execute_span) {
+                                Ok(segment_failures) => segment_failures,
+                                Err(error) => return Ok(search_failed_response(index.canonical_name(), &error, start_time)),
+                            };
+                            let mut doc_matches = collector.into_sorted_vec();
+
+                            // Rescore the top hits
+                            for rescorer in rescorers.iter() {
+                                let window_doc_ids = doc_matches.iter().take(rescorer.window_size).map(|doc_match| DocId::from_u64(doc_match.doc_id())).collect::<Vec<_>>();
+                                let rescore_scores = index_reader.score_documents(&rescorer.query, &window_doc_ids).unwrap();
+                                rescorer.apply(&mut doc_matches, &rescore_scores);
+                            }
+
+                            doc_matches.truncate(paging.result_window());
+                            let matches = doc_matches.into_iter().map(|doc_match| {
+                                (doc_match.doc_id(), doc_match.score(), None)
+                            }).collect::<Vec<_>>();
+
+                            (matches, segment_failures)
+                        }
+                    };
+                    drop(execute_span);
+
+                    // Convert hits into JSON
+                    let serialize_span = trace.span("serialize");
+                    let type_field = index_reader.schema().get_field_by_name("_type");
+                    let source_field = index_reader.schema().get_field_by_name("_source");
+                    let page = matches.into_iter().skip(paging.from).map(|(doc_id, score, sort_values)| {
+                        (DocId::from_u64(doc_id), score, sort_values)
+                    }).collect::<Vec<_>>();
+
+                    // Find which named queries matched each hit
+                    let page_doc_ids = page.iter().map(|&(doc_id, _, _)| doc_id).collect::<Vec<_>>();
+                    let matched_queries = index_reader.matched_queries(&query, &page_doc_ids).unwrap();
+
+                    // Fields to read from doc values, fields that aren't in the index are left out
+                    // Dates are formatted with the format given in the request or with the field's format
+                    let doc_value_fields = requested_fields.iter().filter_map(|requested_field| {
+                        schema.get_field_by_name(&requested_field.field).map(|field_ref| {
+                            let date_format = requested_field.format.clone().or_else(|| {
+                                index_metadata.get_field_mapping(&requested_field.field).and_then(|field_mapping| field_mapping.date_format.clone())
+                            });
+
+                            (requested_field.field.clone(), field_ref, date_format)
+                        })
+                    }).collect::<Vec<_>>();
+
+                    let mut hits = Vec::new();
+                    for ((doc_id, score, sort_values), matched_queries) in page.into_iter().zip(matched_queries.into_iter()) {
+                        let mut hit = json!({
+                            "_index": index.canonical_name(),
+                            "_type": read_string_field(&index_reader, type_field, doc_id),
+                            "_id": index_reader.doc_key(doc_id).unwrap_or(None),
+                            "_score": score,
+                        });
+
+                        if !matched_queries.is_empty() {
+                            hit["matched_queries"] = json!(matched_queries);
+                        }
+
+                        if let Some(sort_values) = sort_values {
+                            hit["sort"] = serde_json::Value::Array(sort_values.into_iter().map(|value| {
+                                value.map(field_value_to_json).unwrap_or(serde_json::Value::Null)
+                            }).collect());
+                        }
+
+                        if fields.is_empty() && include_source {
+                            let source = read_string_field(&index_reader, source_field, doc_id)
+                                .and_then(|source| serde_json::from_str::<serde_json::Value>(&source).ok());
+                            hit["_source"] = source.unwrap_or(serde_json::Value::Null);
+                        } else {
+                            let mut field_values = BTreeMap::new();
+
+                            for &(ref field_name, field_ref) in fields.iter() {
+                                let value = match index_reader.read_stored_field(field_ref, doc_id) {
+                                    Ok(Some(value)) => vec![field_value_to_json(value)],
+                                    Ok(None) => vec![],
+                                    Err(_) => vec![],
+                                };
+
+                                field_values.insert(field_name.clone(), value);
+                            }
+
+                            hit["fields"] = json!(field_values);
+                        }
+
+                        for &(ref field_name, field_ref, ref date_format) in doc_value_fields.iter() {
+                            let values = match read_field_values(&index_reader, &runtime_fields, field_ref, doc_id) {
+                                Ok(values) => values,
+                                Err(_) => continue,
+                            };
+
+                            if !values.is_empty() {
+                                hit["fields"][field_name] = serde_json::Value::Array(values.into_iter().map(|value| {
+                                    doc_value_to_json(value, date_format.as_ref().map(|date_format| date_format.as_str()))
+                                }).collect());
+                            }
+                        }
+
+                        hits.push(hit);
+                    }
+
+                    // The hits are missing the documents of any segments that failed, so they aren't cached
+                    if !segment_failures.is_empty() {
+                        warn!(log, "search failed on some segments"; "index" => index.canonical_name(), "failed_segments" => segment_failures.len());
+                        request_cache_key = None;
+                    }
+
+                    // Searches can't be given a timeout yet, so they never time out
+                    let mut response = json!({
+                        "took": millis_since(start_time),
+                        "timed_out": false,
+                        "_shards": shards_json(segment_failures_json(index.canonical_name(), &segment_failures)),
+                        "hits": {
+                            "total": hits.len(),
+                            "hits": hits
+                        }
+                    });
+
+                    // Add the results of the collectors that were requested by name
+                    if !extension_collectors.is_empty() {
+                        let mut collector_results = serde_json::Map::new();
+                        for (name, extension_collector) in extension_collectors {
+                            collector_results.insert(name, extension_collector.finish());
+                        }
+
+                        response["collectors"] = serde_json::Value::Object(collector_results);
+                    }
+
+                    if !aggregations_collector.is_empty() {
+                        response["aggregations"] = aggregations_collector.results();
+                    }
+
+                    if !warnings.is_empty() {
+                        response["#warnings"] = warnings.to_json();
+                    }
+
+                    let response = response.to_string();
+                    if let Some(request_cache_key) = request_cache_key {
+                        system.request_cache.insert(request_cache_key, response.clone());
+                    }
+
+                    // Give the response to any identical searches that were waiting for this one
+                    if let Some(flight_leader) = flight_leader {
+                        flight_leader.finish(Arc::new(response.clone()));
+                    }
+
+                    drop(serialize_span);
+                    log_slow_search(system, &log, index.canonical_name(), &query_json, start_time);
+                    system.export_trace(&log, &trace);
+
+                    Ok(add_warning_headers(raw_json_response(status::Ok, response), &warnings))
+                }
+                Err(error) => {
+                    if strict {
+                        if let Some(pointer) = parse_error_pointer(&query_json, &["query", "knn"], &error) {
+                            return Ok(strict_validation_response(json!({"message": format!("Query error: {:?}", error), "pointer": pointer})));
+                        }
+                    }
+
+                    // TODO: What specifically is bad about the Query?
+                    let mut response = Response::with((status::BadRequest,
+                                                       "{\"message\": \"Query error\"}"));
+                    response.headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+                    Ok(response)
+                }
+            }
+        }
+        None => Ok(json_response(status::BadRequest, json!({"message": "Missing query"}))),
+    }
+}
@@ -0,0 +1,66 @@
+use serde_json::{self, Map};
+use rusticsearch::index::merge_policy::{MergePlan, SegmentMergeExplanation};
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::json_response;
+
+
+fn segment_explanation_to_json(segment: &SegmentMergeExplanation) -> serde_json::Value {
+    json!({
+        "segment": segment.segment,
+        "num_docs": segment.total_docs - segment.deleted_docs,
+        "deleted_docs": segment.deleted_docs,
+        "tier": segment.tier,
+        "decision": segment.decision.reason(),
+    })
+}
+
+
+fn merge_plan_to_json(plan: &MergePlan) -> serde_json::Value {
+    json!({
+        "will_merge": plan.tier.is_some(),
+        "tier": plan.tier,
+        "candidates": plan.segments_to_merge(),
+        "estimated_merged_segment": {
+            "num_docs": plan.estimated_total_docs() - plan.estimated_deleted_docs(),
+            "deleted_docs": plan.estimated_deleted_docs(),
+        },
+        "segments": plan.segments.iter().map(segment_explanation_to_json).collect::<Vec<_>>(),
+    })
+}
+
+
+/// Explains what the next merge of each index would be, without running it
+pub fn view_get_explain_merges(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    let mut indices_json = Map::new();
+    for index_ref in resolve_targets_or_404!(cluster_metadata, *index_selector) {
+        let index = match cluster_metadata.indices.get(&index_ref) {
+            Some(index) => index,
+            None => continue,
+        };
+
+        // Closed indices aren't merged
+        let plan_json = match index.explain_merges() {
+            Ok(Some(plan)) => merge_plan_to_json(&plan),
+            Ok(None) => json!({"will_merge": false, "closed": true}),
+            Err(error) => {
+                return Ok(json_response(status::InternalServerError, json!({"message": format!("Failed to read segments of {}: {}", index.canonical_name(), error)})));
+            }
+        };
+
+        indices_json.insert(index.canonical_name().to_string(), plan_json);
+    }
+
+    Ok(json_response(status::Ok, json!({
+        "indices": indices_json,
+    })))
+}
@@ -0,0 +1,268 @@
+use std::cmp;
+
+use serde_json::{self, Map};
+use url::form_urlencoded;
+use rusticsearch::search::backends::rocksdb::{FieldDataCacheStats, FieldDiskUsage, corrupt_value_count};
+
+use rusticsearch::bulk_queue::BulkQueueStats;
+use rusticsearch::index::request_cache::RequestCacheStats;
+use rusticsearch::index::inflight::InflightSearchStats;
+use rusticsearch::index::maintenance::MaintenanceStats;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::json_response;
+
+
+fn fielddata_stats_to_json(stats: &FieldDataCacheStats) -> serde_json::Value {
+    json!({
+        "memory_size_in_bytes": stats.memory_size,
+        "evictions": stats.evictions,
+        "hit_count": stats.hit_count,
+        "miss_count": stats.miss_count,
+    })
+}
+
+
+fn request_cache_stats_to_json(stats: &RequestCacheStats) -> serde_json::Value {
+    json!({
+        "memory_size_in_bytes": stats.memory_size,
+        "evictions": stats.evictions,
+        "hit_count": stats.hit_count,
+        "miss_count": stats.miss_count,
+    })
+}
+
+
+fn inflight_search_stats_to_json(stats: &InflightSearchStats) -> serde_json::Value {
+    json!({
+        "leaders": stats.leaders,
+        "shared": stats.shared,
+    })
+}
+
+
+fn maintenance_stats_to_json(stats: &MaintenanceStats) -> serde_json::Value {
+    json!({
+        "runs": stats.runs,
+        "failures": stats.failures,
+        "consecutive_failures": stats.consecutive_failures,
+        "purges": stats.purges,
+        "segments_merged": stats.segments_merged,
+        "total_time_in_millis": stats.total_time_in_millis,
+        "current_interval_in_millis": stats.current_interval_in_millis,
+        "last_error": stats.last_error,
+    })
+}
+
+
+fn field_disk_usage_to_json(usage: &FieldDiskUsage) -> serde_json::Value {
+    json!({
+        "total_in_bytes": usage.total(),
+        "inverted_index": {
+            "total_in_bytes": usage.inverted_index(),
+            "postings_in_bytes": usage.postings,
+            "positions_in_bytes": usage.positions,
+        },
+        "norms_in_bytes": usage.norms,
+        "stored_fields_in_bytes": usage.stored_fields,
+        "doc_values_in_bytes": usage.doc_values,
+    })
+}
+
+
+fn bulk_queue_stats_to_json(stats: &BulkQueueStats) -> serde_json::Value {
+    json!({
+        "queue": stats.queue,
+        "queue_size_in_bytes": stats.queue_size_in_bytes,
+        "completed": stats.completed,
+        "rejected": stats.rejected,
+    })
+}
+
+
+pub fn view_get_node_stats(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+
+    Ok(json_response(status::Ok, json!({
+        "nodes": {
+            "local": {
+                "thread_pool": {
+                    "bulk": bulk_queue_stats_to_json(&system.bulk_queue.stats()),
+                },
+                "indices": {
+                    "request_cache": request_cache_stats_to_json(&system.request_cache.stats()),
+                    "inflight_searches": inflight_search_stats_to_json(&system.inflight_searches.stats()),
+                    "store": {
+                        // Values that were skipped when merging, shared by every index
+                        "corrupt_merge_values": corrupt_value_count(),
+                    },
+                }
+            }
+        }
+    })))
+}
+
+
+pub fn view_get_index_fielddata_stats(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    // Collect stats from each index
+    let mut total = FieldDataCacheStats::default();
+    let mut indices_json = Map::new();
+    for index_ref in resolve_targets_or_404!(cluster_metadata, *index_selector) {
+        let index = match cluster_metadata.indices.get(&index_ref) {
+            Some(index) => index,
+            None => continue,
+        };
+
+        // Closed indices have nothing in their field data cache
+        let stats = match index.store_if_open() {
+            Some(store) => store.field_data_cache_stats(),
+            None => FieldDataCacheStats::default(),
+        };
+        total.memory_size += stats.memory_size;
+        total.evictions += stats.evictions;
+        total.hit_count += stats.hit_count;
+        total.miss_count += stats.miss_count;
+
+        indices_json.insert(index.canonical_name().to_string(), json!({
+            "total": {
+                "fielddata": fielddata_stats_to_json(&stats),
+            }
+        }));
+    }
+
+    Ok(json_response(status::Ok, json!({
+        "_all": {
+            "total": {
+                "fielddata": fielddata_stats_to_json(&total),
+            }
+        },
+        "indices": indices_json,
+    })))
+}
+
+
+pub fn view_get_index_maintenance_stats(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    // Collect stats from each index
+    let mut total = MaintenanceStats::default();
+    let mut indices_json = Map::new();
+    for index_ref in resolve_targets_or_404!(cluster_metadata, *index_selector) {
+        let index = match cluster_metadata.indices.get(&index_ref) {
+            Some(index) => index,
+            None => continue,
+        };
+
+        let stats = index.maintenance_stats();
+        total.runs += stats.runs;
+        total.failures += stats.failures;
+        total.consecutive_failures = cmp::max(total.consecutive_failures, stats.consecutive_failures);
+        total.purges += stats.purges;
+        total.segments_merged += stats.segments_merged;
+        total.total_time_in_millis += stats.total_time_in_millis;
+
+        indices_json.insert(index.canonical_name().to_string(), json!({
+            "total": {
+                "maintenance": maintenance_stats_to_json(&stats),
+            }
+        }));
+    }
+
+    // The intervals and errors are per-index
+    let mut total_json = maintenance_stats_to_json(&total);
+    if let Some(total_json) = total_json.as_object_mut() {
+        total_json.remove("current_interval_in_millis");
+        total_json.remove("last_error");
+    }
+
+    Ok(json_response(status::Ok, json!({
+        "_all": {
+            "total": {
+                "maintenance": total_json,
+            }
+        },
+        "indices": indices_json,
+    })))
+}
+
+
+/// Analyses how much space each field of the indices takes up
+///
+/// Every key of each index is read, so this must be asked for with "run_expensive_tasks=true".
+/// Sizes are before compression. Fields that have been removed from the mapping but still
+/// have data are only counted in "all_fields".
+pub fn view_post_disk_usage(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_selector));
+
+    let mut run_expensive_tasks = false;
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key == "run_expensive_tasks" {
+                run_expensive_tasks = value == "true";
+            }
+        }
+    }
+
+    if !run_expensive_tasks {
+        return Ok(json_response(status::BadRequest, json!({
+            "message": "Analysing the disk usage of an index reads all of its data, set \"run_expensive_tasks=true\" to run it",
+        })));
+    }
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    let mut indices_json = Map::new();
+    for index_ref in resolve_targets_or_404!(cluster_metadata, *index_selector) {
+        let index = match cluster_metadata.indices.get(&index_ref) {
+            Some(index) => index,
+            None => continue,
+        };
+        check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+
+        let store = get_store_or_500!(index.store());
+        let usage = match store.disk_usage() {
+            Ok(usage) => usage,
+            Err(error) => {
+                return Ok(json_response(status::InternalServerError, json!({"message": format!("Couldn't analyse the disk usage of {}: {}", index.canonical_name(), error)})));
+            }
+        };
+
+        let mut fields_json = Map::new();
+        {
+            let index_reader = store.reader();
+            for (field_id, field_usage) in usage.fields.iter() {
+                if let Some(field_info) = index_reader.schema().get(field_id) {
+                    fields_json.insert(field_info.name().to_string(), field_disk_usage_to_json(field_usage));
+                }
+            }
+        }
+
+        indices_json.insert(index.canonical_name().to_string(), json!({
+            "store_size_in_bytes": usage.total(),
+            "all_fields": field_disk_usage_to_json(&usage.all_fields()),
+            "term_dictionary_in_bytes": usage.term_dictionary,
+            "documents_in_bytes": usage.documents,
+            "other_in_bytes": usage.other,
+            "fields": fields_json,
+        }));
+    }
+
+    Ok(json_response(status::Ok, serde_json::Value::Object(indices_json)))
+}
@@ -0,0 +1,45 @@
+use rusticsearch::tasks::TaskStatus;
+use rusticsearch::tenancy::Tenant;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::json_response;
+
+
+/// Tenants can only see the tasks of indices they can use
+fn can_see_task(tenant: Option<&Tenant>, task: &TaskStatus) -> bool {
+    match (tenant, task.index.as_ref()) {
+        (None, _) => true,
+        (Some(tenant), Some(index_name)) => tenant.can_access_index(index_name),
+        (Some(_), None) => false,
+    }
+}
+
+
+pub fn view_get_task_list(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let tenant = get_tenant_or_401!(req, system);
+
+    let tasks = system.tasks.list().iter().filter(|task| can_see_task(tenant.as_ref(), task)).map(TaskStatus::to_json).collect::<Vec<_>>();
+
+    Ok(json_response(status::Ok, json!({"tasks": tasks})))
+}
+
+
+pub fn view_get_task(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref task_id = read_path_parameter!(req, "task").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+
+    let task = match task_id.parse::<u64>().ok().and_then(|task_id| system.tasks.get(task_id)) {
+        Some(ref task) if can_see_task(tenant.as_ref(), task) => task.clone(),
+        _ => return Ok(json_response(status::NotFound, json!({"message": "Task not found"}))),
+    };
+
+    Ok(json_response(status::Ok, json!({
+        "completed": task.is_finished(),
+        "task": task.to_json(),
+    })))
+}
@@ -0,0 +1,100 @@
+use url::form_urlencoded;
+use rusticsearch::search::schema::FieldType;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::json_response;
+
+
+/// The number of terms returned if the request doesn't give a size
+const DEFAULT_SIZE: usize = 10;
+
+/// The most terms a request can ask for
+const MAX_SIZE: usize = 1000;
+
+
+pub fn view_get_terms(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Check that the API key can be used with this index
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_name));
+
+    // Read parameters
+    let mut field_name = None;
+    let mut prefix = String::new();
+    let mut size = DEFAULT_SIZE;
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            match key.as_ref() {
+                "field" => field_name = Some(value.into_owned()),
+                "prefix" => prefix = value.into_owned(),
+                "size" => {
+                    size = match value.parse::<usize>() {
+                        Ok(size) if size <= MAX_SIZE => size,
+                        _ => {
+                            return Ok(json_response(status::BadRequest, json!({"message": format!("size must be a number up to {}", MAX_SIZE)})));
+                        }
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let field_name = match field_name {
+        Some(field_name) => field_name,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "The field parameter is required"})));
+        }
+    };
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+
+    // An alias or pattern may have resolved to an index that the tenant can't use
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+    let store = get_store_or_500!(index.store());
+    let index_reader = store.reader();
+
+    // Find the field, the terms of other types of field aren't readable strings
+    let field_id = {
+        let schema = index_reader.schema();
+        let field_id = match schema.get_field_by_name(&field_name) {
+            Some(field_id) => field_id,
+            None => {
+                return Ok(json_response(status::NotFound, json!({"message": format!("Field not found: {}", field_name)})));
+            }
+        };
+
+        match schema.get(&field_id).map(|field_info| &field_info.field_type) {
+            Some(&FieldType::Text) | Some(&FieldType::PlainString) => field_id,
+            _ => {
+                return Ok(json_response(status::BadRequest, json!({"message": format!("Terms can only be listed for string fields: {}", field_name)})));
+            }
+        }
+    };
+
+    let terms = match index_reader.terms_enum(field_id, prefix.as_bytes(), size) {
+        Ok(terms) => terms,
+        Err(error) => {
+            return Ok(json_response(status::InternalServerError, json!({"message": "Unable to read terms", "error": error})));
+        }
+    };
+
+    let terms_json = terms.iter().map(|&(ref term, doc_count)| {
+        json!({
+            "term": String::from_utf8_lossy(term.as_bytes()),
+            "doc_count": doc_count,
+        })
+    }).collect::<Vec<_>>();
+
+    Ok(json_response(status::Ok, json!({
+        "field": field_name,
+        "terms": terms_json,
+    })))
+}
@@ -0,0 +1,334 @@
+use std::io::Read;
+use std::thread;
+use std::time::Instant;
+
+use serde_json;
+use slog::Logger;
+use url::form_urlencoded;
+
+use rusticsearch::document::DocumentSource;
+use rusticsearch::search::document::{DocId, FieldValue};
+use rusticsearch::search::collectors::doc_ids::DocIdsCollector;
+use rusticsearch::query_parser::{QueryBuildContext, parse as parse_query};
+use rusticsearch::cluster::terms_lookup::ClusterTermsLookup;
+use rusticsearch::cluster::metadata::IndexRef;
+use rusticsearch::system::System;
+use rusticsearch::tasks::Task;
+use rusticsearch::tenancy::Tenant;
+use rusticsearch::update::UpdateResult;
+use rusticsearch::update::by_query::{UpdateByQuery, UpdateByQueryProgress, ConflictsPolicy, DEFAULT_BATCH_SIZE};
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, too_many_clauses_response, millis_since};
+use api::search_api::read_string_field;
+
+
+/// A document that matched the query, as it was when the query ran
+struct Target {
+    key: String,
+    doc_type: String,
+    doc_id: DocId,
+}
+
+
+/// Updates the documents in batches, reporting the progress to the task after each one
+///
+/// Each batch holds the index's update lock, so it can't overwrite the changes of
+/// concurrent updates. Errors that stop the whole update (like the index being deleted)
+/// are returned, the documents that were updated before then are left updated.
+fn run_update_by_query(system: &System, log: &Logger, tenant: Option<&Tenant>, index_ref: IndexRef, update_by_query: &UpdateByQuery, targets: &[Target], batch_size: usize, mut progress: UpdateByQueryProgress, task: &Task) -> Result<UpdateByQueryProgress, String> {
+    for batch in targets.chunks(batch_size) {
+        let cluster_metadata = system.metadata.read().unwrap();
+        let index = match cluster_metadata.indices.get(&index_ref) {
+            Some(index) => index,
+            None => return Err("the index was deleted".to_string()),
+        };
+
+        if index.is_read_only() {
+            return Err(format!("index {} is read only", index.canonical_name()));
+        }
+
+        let _update_guard = index.lock_updates();
+        let store = index.store()?;
+        let index_reader = store.reader();
+        let source_field = index_reader.schema().get_field_by_name("_source");
+
+        progress.batches += 1;
+
+        for target in batch.iter() {
+            let failure = |status: u16, cause: String| {
+                json!({"id": target.key, "type": target.doc_type, "status": status, "cause": cause})
+            };
+
+            // Documents that were changed or deleted since the query ran are version conflicts
+            match index_reader.find_doc_id(&target.key) {
+                Ok(Some(doc_id)) if doc_id == target.doc_id => {}
+                Ok(_) => {
+                    progress.version_conflicts += 1;
+
+                    if update_by_query.conflicts == ConflictsPolicy::Abort {
+                        progress.failures.push(failure(409, "the document was changed after the query ran".to_string()));
+                        task.set_progress(progress.to_json());
+                        return Ok(progress);
+                    }
+
+                    continue;
+                }
+                Err(e) => return Err(format!("{}", e)),
+            }
+
+            let source = match source_field.map(|field| index_reader.read_stored_field(field, target.doc_id)) {
+                Some(Ok(Some(FieldValue::String(source)))) => serde_json::from_str(&source).ok(),
+                _ => None,
+            };
+            let source = match source {
+                Some(serde_json::Value::Object(source)) => source,
+                _ => {
+                    progress.failures.push(failure(400, "the source of the document isn't stored".to_string()));
+                    continue;
+                }
+            };
+
+            let source = match update_by_query.apply(source) {
+                Ok(UpdateResult::Updated(source)) | Ok(UpdateResult::Created(source)) => source,
+                Ok(UpdateResult::Noop) => {
+                    progress.noops += 1;
+                    continue;
+                }
+                Err(e) => {
+                    progress.failures.push(failure(400, e.message()));
+                    continue;
+                }
+            };
+
+            // Add any new fields to the mapping, this must be done before the metadata is locked below
+            match index.add_dynamic_fields(&target.doc_type, &source) {
+                Ok(ref field_names) if !field_names.is_empty() => {
+                    info!(log, "added dynamic fields"; "index" => index.canonical_name(), "mapping" => &target.doc_type, "fields" => field_names.join(", "));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    progress.failures.push(failure(400, format!("Couldn't add fields to mapping: {}", e)));
+                    continue;
+                }
+            }
+
+            let index_metadata = index.metadata.read().unwrap();
+            let mapping = match index_metadata.mappings.get(&target.doc_type) {
+                Some(mapping) => mapping,
+                None => {
+                    progress.failures.push(failure(404, "Mapping not found".to_string()));
+                    continue;
+                }
+            };
+
+            let document_source = DocumentSource {
+                key: &target.key,
+                doc_type: &target.doc_type,
+                data: &source,
+            };
+
+            let doc = match document_source.prepare(mapping) {
+                Ok(doc) => doc,
+                Err(e) => {
+                    progress.failures.push(failure(400, format!("{:?}", e)));
+                    continue;
+                }
+            };
+
+            // Updating a document doesn't add to the number of documents
+            let source_size = serde_json::to_string(&source).unwrap().len() as u64;
+            if let Err(e) = system.tenancy.check_index_documents(tenant, index.canonical_name(), 0, source_size) {
+                return Err(String::from(e));
+            }
+
+            match store.insert_or_update_document(&doc) {
+                Ok(()) => progress.updated += 1,
+                Err(e) => progress.failures.push(failure(500, format!("{:?}", e))),
+            }
+        }
+
+        task.set_progress(progress.to_json());
+    }
+
+    Ok(progress)
+}
+
+
+/// Runs a query and updates every document that it matches
+///
+/// The documents are changed by a "script" or a partial document ("doc"), or reindexed
+/// as they are if there's neither. With "wait_for_completion=false", the update runs in
+/// the background and its progress can be followed through the "_tasks" API.
+pub fn view_post_update_by_query(req: &mut Request) -> IronResult<Response> {
+    let start_time = Instant::now();
+    let system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index_name));
+
+    // Read URL parameters
+    let mut wait_for_completion = true;
+    let mut batch_size = DEFAULT_BATCH_SIZE;
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            match key.as_ref() {
+                "wait_for_completion" => {
+                    wait_for_completion = match value.as_ref() {
+                        "true" => true,
+                        "false" => false,
+                        _ => return Ok(json_response(status::BadRequest, json!({"message": "\"wait_for_completion\" must be true or false"}))),
+                    };
+                }
+                "scroll_size" => {
+                    batch_size = match value.parse() {
+                        Ok(batch_size) if batch_size > 0 => batch_size,
+                        _ => return Ok(json_response(status::BadRequest, json!({"message": "\"scroll_size\" must be a positive integer"}))),
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Load data from body, without a body every document is reindexed as it is
+    let data = json_from_request_body!(req).unwrap_or_else(|| json!({}));
+
+    let update_by_query = match UpdateByQuery::parse(&data) {
+        Ok(update_by_query) => update_by_query,
+        Err(e) => {
+            return Ok(json_response(status::BadRequest, json!({"message": e.message()})));
+        }
+    };
+
+    let query = match parse_query(data.get("query").unwrap_or(&json!({"match_all": {}}))) {
+        Ok(query) => query,
+        Err(e) => {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Query error: {:?}", e)})));
+        }
+    };
+
+    // Find the documents to update, from a snapshot of the index
+    let (index_ref, canonical_name, targets, progress) = {
+        let cluster_metadata = system.metadata.read().unwrap();
+        let index = get_index_or_404!(cluster_metadata, *index_name);
+        check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), index.canonical_name()));
+        check_writable_or_403!(index);
+
+        let index_ref = match cluster_metadata.names.find_canonical(index.canonical_name()) {
+            Some(index_ref) => index_ref,
+            None => return Ok(json_response(status::NotFound, json!({"message": "Index not found"}))),
+        };
+
+        let store = get_store_or_500!(index.store());
+        let index_reader = store.reader();
+        let index_metadata = index.metadata.read().unwrap();
+
+        let terms_lookup = ClusterTermsLookup::new(&cluster_metadata, tenant.as_ref());
+        let build_context = QueryBuildContext::new().set_index_metadata(&index_metadata).set_terms_lookup(&terms_lookup).no_score();
+        let query = query.build(&build_context, &index_reader.schema());
+
+        if let Err(error) = index_reader.check_query_limits(&query, &system.query_limits()) {
+            return Ok(too_many_clauses_response(&error));
+        }
+
+        // Every matching document must be found, so segments that can't be searched are an error
+        let mut collector = DocIdsCollector::new();
+        if let Err(error) = index_reader.search(&mut collector, &query) {
+            return Ok(json_response(status::InternalServerError, json!({"message": format!("Search failed: {}", error)})));
+        }
+
+        let mut doc_ids = collector.into_vec();
+        if let Some(max_docs) = update_by_query.max_docs {
+            doc_ids.truncate(max_docs);
+        }
+
+        let mut progress = UpdateByQueryProgress::new(doc_ids.len() as u64);
+        let type_field = index_reader.schema().get_field_by_name("_type");
+        let mut targets = Vec::with_capacity(doc_ids.len());
+        for doc_id in doc_ids.into_iter().map(DocId::from_u64) {
+            let key = index_reader.doc_key(doc_id).unwrap_or(None);
+            let doc_type = read_string_field(&index_reader, type_field, doc_id);
+
+            match (key, doc_type) {
+                (Some(key), Some(doc_type)) => {
+                    targets.push(Target {
+                        key: key,
+                        doc_type: doc_type,
+                        doc_id: doc_id,
+                    });
+                }
+                (key, _) => {
+                    progress.failures.push(json!({"id": key, "status": 500, "cause": "the key or type of the document couldn't be read"}));
+                }
+            }
+        }
+
+        (index_ref, index.canonical_name().to_string(), targets, progress)
+    };
+
+    let task = system.tasks.start("indices:data/write/update/byquery", format!("update-by-query [{}]", canonical_name), Some(canonical_name.clone()));
+    task.set_progress(progress.to_json());
+
+    info!(log, "started update by query"; "index" => &canonical_name, "total" => progress.total, "task" => task.id());
+
+    // Run in the background, the response only has the id of the task
+    if !wait_for_completion {
+        let task_id = task.id();
+        let system = system.clone();
+        let tenant = tenant.clone();
+
+        thread::spawn(move || {
+            match run_update_by_query(&system, &log, tenant.as_ref(), index_ref, &update_by_query, &targets, batch_size, progress, &task) {
+                Ok(ref progress) if update_by_query.conflicts == ConflictsPolicy::Abort && progress.version_conflicts > 0 => {
+                    info!(log, "update by query stopped by a version conflict"; "index" => &canonical_name, "updated" => progress.updated);
+                    task.fail("version conflict".to_string());
+                }
+                Ok(progress) => {
+                    info!(log, "finished update by query"; "index" => &canonical_name, "updated" => progress.updated, "failures" => progress.failures.len());
+                    task.complete();
+                }
+                Err(error) => {
+                    warn!(log, "update by query failed"; "index" => &canonical_name, "error" => &error);
+                    task.fail(error);
+                }
+            }
+        });
+
+        return Ok(json_response(status::Ok, json!({"task": task_id})));
+    }
+
+    match run_update_by_query(&system, &log, tenant.as_ref(), index_ref, &update_by_query, &targets, batch_size, progress, &task) {
+        Ok(progress) => {
+            info!(log, "finished update by query"; "index" => &canonical_name, "updated" => progress.updated, "failures" => progress.failures.len());
+
+            // Stopping at a version conflict is reported as a conflict, along with what was done before it
+            let aborted = update_by_query.conflicts == ConflictsPolicy::Abort && progress.version_conflicts > 0;
+            if aborted {
+                task.fail("version conflict".to_string());
+            } else {
+                task.complete();
+            }
+
+            let mut response = progress.to_json();
+            response["took"] = json!(millis_since(start_time));
+            response["timed_out"] = json!(false);
+
+            if aborted {
+                Ok(json_response(status::Conflict, response))
+            } else {
+                Ok(json_response(status::Ok, response))
+            }
+        }
+        Err(error) => {
+            warn!(log, "update by query failed"; "index" => &canonical_name, "error" => &error);
+            task.fail(error.clone());
+
+            Ok(json_response(status::InternalServerError, json!({"message": format!("Update by query failed: {}", error)})))
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use std::time::{Duration, Instant};
+
+use serde_json;
+use url::form_urlencoded;
+
+use api::iron::prelude::*;
+use api::iron::status;
+
+use rusticsearch::tenancy::TenancyError;
+use rusticsearch::cluster::metadata::targets::TargetError;
+use rusticsearch::search::backends::rocksdb::QueryLimitError;
+use rusticsearch::query_parser::warnings::ParseWarnings;
+
+
+macro_rules! get_system {
+    ($req: expr) => {{
+        use api::Context;
+
+        $req.get::<persistent::Read<Context>>().unwrap().system.clone()
+    }}
+}
+
+
+/// The logger of the request, which includes its id (see `request_log`)
+macro_rules! get_request_log {
+    ($req: expr) => {{
+        use api::request_log::RequestLog;
+
+        $req.extensions.get::<RequestLog>().unwrap().clone()
+    }}
+}
+
+
+/// The id of the request, 32 hex characters
+macro_rules! get_request_id {
+    ($req: expr) => {{
+        use api::request_log::RequestId;
+
+        $req.extensions.get::<RequestId>().unwrap().clone()
+    }}
+}
+
+
+macro_rules! read_path_parameter {
+    ($req: expr, $name: expr) => {{
+        $req.extensions.get::<Router>().unwrap().find($name)
+    }}
+}
+
+
+/// The number of whole milliseconds since a request started, for the "took" of responses
+pub fn millis_since(start_time: Instant) -> u64 {
+    let elapsed = start_time.elapsed();
+    elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64
+}
+
+
+pub fn json_response(status: status::Status, content: serde_json::Value) -> Response {
+    raw_json_response(status, format!("{}", content))
+}
+
+
+/// Responds with JSON that has already been serialised
+pub fn raw_json_response(status: status::Status, content: String) -> Response {
+    let mut response = Response::with((status, content));
+    response.headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+    response
+}
+
+
+/// Adds a `Warning` header to the response for each warning
+pub fn add_warning_headers(mut response: Response, warnings: &ParseWarnings) -> Response {
+    if !warnings.is_empty() {
+        response.headers.set_raw("Warning", warnings.header_values());
+    }
+
+    response
+}
+
+
+/// Reads the "strict" URL parameter
+///
+/// In strict mode, the parts of a request that aren't supported are errors instead of
+/// being ignored with a warning.
+pub fn read_strict(req: &Request) -> bool {
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key == "strict" {
+                return value == "true" || value == "";
+            }
+        }
+    }
+
+    false
+}
+
+
+/// A request in strict mode used something that isn't supported
+///
+/// The error has a "message", and a "pointer" to the offending key of the body if it's there.
+pub fn strict_validation_response(mut error: serde_json::Value) -> Response {
+    error["type"] = json!("strict_validation_exception");
+    json_response(status::BadRequest, error)
+}
+
+
+pub fn too_many_requests_response(retry_after: Duration) -> Response {
+    let mut response = json_response(status::TooManyRequests, json!({"message": "Too many requests, try again later"}));
+    response.headers.set_raw("Retry-After", vec![retry_after.as_secs().to_string().into_bytes()]);
+    response
+}
+
+
+/// The index metadata isn't at the version the request expected
+pub fn version_conflict_response(expected_version: u64, current_version: u64) -> Response {
+    json_response(status::Conflict, json!({
+        "message": "Index metadata has been changed by another request",
+        "type": "version_conflict",
+        "expected_version": expected_version,
+        "current_version": current_version,
+    }))
+}
+
+
+/// The query expands into more clauses than the node allows
+pub fn too_many_clauses_response(error: &QueryLimitError) -> Response {
+    json_response(status::BadRequest, json!({
+        "message": error.message(),
+        "type": "too_many_clauses",
+    }))
+}
+
+
+/// The search asked for hits past the end of the index's result window
+pub fn result_window_too_large_response(result_window: usize, max_result_window: usize) -> Response {
+    json_response(status::BadRequest, json!({
+        "message": format!("Result window is too large, from + size must be less than or equal to: [{}] but was [{}]. \
+                            See the scroll api for a more efficient way to request large data sets. \
+                            This limit can be set by changing the [index.max_result_window] index level setting.", max_result_window, result_window),
+        "type": "illegal_argument_exception",
+    }))
+}
+
+
+pub fn index_not_found_response() -> Response {
+    json_response(status::NotFound, json!({"message": "Index not found"}))
+}
+
+
+/// The target of the request didn't resolve into the indices that it needs
+pub fn target_error_response(error: &TargetError) -> Response {
+    match *error {
+        TargetError::IndexNotFound(_) => json_response(status::NotFound, json!({"message": "Index not found", "error": error.message()})),
+        _ => json_response(status::BadRequest, json!({"message": error.message()})),
+    }
+}
+
+
+/// The index has a "read_only" block
+pub fn index_read_only_response(index_name: &str) -> Response {
+    json_response(status::Forbidden, json!({
+        "message": format!("Index {} is read only", index_name),
+        "type": "cluster_block_exception",
+    }))
+}
+
+
+pub fn store_unavailable_response(error: &str) -> Response {
+    json_response(status::InternalServerError, json!({"message": "Unable to open index", "error": error}))
+}
+
+
+/// Reads the API key from an "Authorization: ApiKey <key>" header
+pub fn read_api_key(req: &Request) -> Option<String> {
+    let header = match req.headers.get_raw("Authorization") {
+        Some(values) if values.len() == 1 => String::from_utf8_lossy(&values[0]).into_owned(),
+        _ => return None,
+    };
+
+    if header.starts_with("ApiKey ") {
+        Some(header["ApiKey ".len()..].trim().to_string())
+    } else {
+        None
+    }
+}
+
+
+pub fn tenancy_error_response(error: TenancyError) -> Response {
+    let status = match error {
+        TenancyError::MissingApiKey | TenancyError::InvalidApiKey => status::Unauthorized,
+        TenancyError::IndexNotAllowed(_) | TenancyError::QuotaExceeded(_) => status::Forbidden,
+    };
+
+    json_response(status, json!({"message": String::from(error)}))
+}
+
+
+/// Finds the tenant that the request's API key belongs to
+///
+/// This is None if tenancy is disabled.
+macro_rules! get_tenant_or_401 {
+    ($req: expr, $system: expr) => {{
+        use api::utils::{read_api_key, tenancy_error_response};
+
+        let api_key = read_api_key($req);
+        match $system.tenancy.authenticate(api_key.as_ref().map(|api_key| api_key.as_str())) {
+            Ok(tenant) => tenant,
+            Err(error) => {
+                return Ok(tenancy_error_response(error));
+            }
+        }
+    }}
+}
+
+
+/// Returns an error response if one of the tenant's checks failed
+macro_rules! check_tenancy_or_403 {
+    ($result: expr) => {{
+        use api::utils::tenancy_error_response;
+
+        if let Err(error) = $result {
+            return Ok(tenancy_error_response(error));
+        }
+    }}
+}
+
+
+/// Finds the index that a request is for
+///
+/// The target can be anything that `ResolvedNames::resolve_targets` accepts, as long as
+/// it resolves to a single index.
+macro_rules! get_index_or_404 {
+    ($cluster_metadata: expr, $target: expr) => {{
+        use chrono::Utc;
+        use api::utils::{index_not_found_response, target_error_response};
+
+        let index_ref = match $cluster_metadata.names.resolved().resolve_single_target($target, Utc::now()) {
+            Ok(index_ref) => index_ref,
+            Err(error) => {
+                return Ok(target_error_response(&error));
+            }
+        };
+
+        match $cluster_metadata.indices.get(&index_ref) {
+            Some(index) => index,
+            None => {
+                return Ok(index_not_found_response());
+            }
+        }
+    }}
+}
+
+
+macro_rules! get_index_or_404_mut {
+    ($cluster_metadata: expr, $target: expr) => {{
+        use chrono::Utc;
+        use api::utils::{index_not_found_response, target_error_response};
+
+        let index_ref = match $cluster_metadata.names.resolved().resolve_single_target($target, Utc::now()) {
+            Ok(index_ref) => index_ref,
+            Err(error) => {
+                return Ok(target_error_response(&error));
+            }
+        };
+
+        match $cluster_metadata.indices.get_mut(&index_ref) {
+            Some(index) => index,
+            None => {
+                return Ok(index_not_found_response());
+            }
+        }
+    }}
+}
+
+
+/// Finds every index that a request's target refers to
+macro_rules! resolve_targets_or_404 {
+    ($cluster_metadata: expr, $target: expr) => {{
+        use chrono::Utc;
+        use api::utils::target_error_response;
+
+        match $cluster_metadata.names.resolved().resolve_targets($target, Utc::now()) {
+            Ok(index_refs) => index_refs,
+            Err(error) => {
+                return Ok(target_error_response(&error));
+            }
+        }
+    }}
+}
+
+
+/// Returns an error response if the index can't be written to
+macro_rules! check_writable_or_403 {
+    ($index: expr) => {{
+        use api::utils::index_read_only_response;
+
+        if $index.is_read_only() {
+            return Ok(index_read_only_response($index.canonical_name()));
+        }
+    }}
+}
+
+
+/// Unwraps the result of opening the store of an index
+macro_rules! get_store_or_500 {
+    ($result: expr) => {{
+        use api::utils::store_unavailable_response;
+
+        match $result {
+            Ok(store) => store,
+            Err(error) => {
+                return Ok(store_unavailable_response(&error));
+            }
+        }
+    }}
+}
+
+
+macro_rules! parse_json {
+    ($string: expr) => {{
+        use api::utils::json_response;
+
+        let value: serde_json::Value = match serde_json::from_str($string) {
+            Ok(data) => data,
+            Err(_) => {
+                return Ok(json_response(status::BadRequest, json!({"message": "Couldn't parse JSON"})));
+            }
+        };
+
+        value
+    }}
+}
+
+
+macro_rules! json_from_request_body {
+    ($req: expr) => {{
+        // Read request body to a string
+        let mut payload = String::new();
+        $req.body.read_to_string(&mut payload).unwrap();
+
+        if !payload.is_empty() {
+            Some(parse_json!(&payload))
+        } else {
+            None
+        }
+    }}
+}
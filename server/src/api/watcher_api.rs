@@ -0,0 +1,95 @@
+use std::io::Read;
+
+use serde_json;
+
+use rusticsearch::watcher::Watch;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::json_response;
+
+
+pub fn view_get_watch(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref watch_id = read_path_parameter!(req, "watch").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+
+    let watch = match system.watcher.get_watch(watch_id) {
+        Some(watch) => watch,
+        None => return Ok(json_response(status::NotFound, json!({"_id": watch_id, "found": false}))),
+    };
+
+    // Watches can only be seen by tenants that can use their index
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), &watch.index));
+
+    Ok(json_response(status::Ok, json!({
+        "_id": watch_id,
+        "found": true,
+        "watch": watch,
+    })))
+}
+
+
+pub fn view_put_watch(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref watch_id = read_path_parameter!(req, "watch").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+
+    let watch = match json_from_request_body!(req).map(Watch::parse) {
+        Some(Ok(watch)) => watch,
+        Some(Err(e)) => {
+            let message: String = e.into();
+            return Ok(json_response(status::BadRequest, json!({"message": message})));
+        }
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "Request body is required"})));
+        }
+    };
+
+    check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), &watch.index));
+
+    // A watch may only be replaced by a tenant that can use the index of the existing watch
+    if let Some(existing_watch) = system.watcher.get_watch(watch_id) {
+        check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), &existing_watch.index));
+    }
+
+    match system.watcher.put_watch(watch_id, watch) {
+        Ok(created) => {
+            info!(log, "saved watch"; "watch" => *watch_id, "created" => created);
+
+            let status = if created { status::Created } else { status::Ok };
+            Ok(json_response(status, json!({"_id": watch_id, "created": created})))
+        }
+        Err(e) => {
+            error!(log, "failed to save watch"; "watch" => *watch_id, "error" => &e);
+            Ok(json_response(status::InternalServerError, json!({"message": e})))
+        }
+    }
+}
+
+
+pub fn view_delete_watch(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let log = get_request_log!(req);
+    let ref watch_id = read_path_parameter!(req, "watch").unwrap_or("");
+    let tenant = get_tenant_or_401!(req, system);
+
+    if let Some(watch) = system.watcher.get_watch(watch_id) {
+        check_tenancy_or_403!(system.tenancy.check_index_access(tenant.as_ref(), &watch.index));
+    }
+
+    match system.watcher.delete_watch(watch_id) {
+        Ok(true) => {
+            info!(log, "deleted watch"; "watch" => *watch_id);
+            Ok(json_response(status::Ok, json!({"_id": watch_id, "found": true})))
+        }
+        Ok(false) => Ok(json_response(status::NotFound, json!({"_id": watch_id, "found": false}))),
+        Err(e) => {
+            error!(log, "failed to delete watch"; "watch" => *watch_id, "error" => &e);
+            Ok(json_response(status::InternalServerError, json!({"message": e})))
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Writes log records to a file as JSON lines, for shipping into log pipelines
+//!
+//! Each line has the "timestamp", "level", "module" and "message" of the record
+//! along with its key-values (like "index" and "request_id"). When the file reaches
+//! its maximum size, it's renamed to "<file>.1" (the previous "<file>.1" becomes
+//! "<file>.2", and so on) and a new one is started.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use serde_json::{self, Map, Value as Json};
+use slog::{self, Drain, Record, OwnedKVList, KV, Key};
+
+
+/// Maximum size of a log file before it's rotated
+pub const DEFAULT_MAX_FILE_SIZE: usize = 100 * 1024 * 1024;
+
+/// Number of rotated log files that are kept
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+
+/// Collects the key-values of a record into a JSON object
+struct JsonSerializer<'a> {
+    object: &'a mut Map<String, Json>,
+}
+
+
+impl<'a> JsonSerializer<'a> {
+    fn insert(&mut self, key: Key, value: Json) -> slog::Result {
+        self.object.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+
+impl<'a> slog::Serializer for JsonSerializer<'a> {
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        self.insert(key, Json::Bool(val))
+    }
+
+    fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
+        self.insert(key, json!(val))
+    }
+
+    fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
+        self.insert(key, json!(val))
+    }
+
+    fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
+        self.insert(key, json!(val))
+    }
+
+    fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
+        self.insert(key, json!(val))
+    }
+
+    fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+        self.insert(key, json!(val))
+    }
+
+    fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
+        self.insert(key, json!(val))
+    }
+
+    fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
+        self.insert(key, json!(val))
+    }
+
+    fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
+        self.insert(key, Json::String(val.to_string()))
+    }
+
+    fn emit_unit(&mut self, key: Key) -> slog::Result {
+        self.insert(key, Json::Null)
+    }
+
+    fn emit_none(&mut self, key: Key) -> slog::Result {
+        self.insert(key, Json::Null)
+    }
+
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.insert(key, Json::String(fmt::format(*val)))
+    }
+}
+
+
+/// A log file that's rotated when it gets too big
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: usize,
+    max_size: usize,
+    max_files: usize,
+}
+
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: usize, max_files: usize) -> io::Result<RotatingFile> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len() as usize;
+
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file: file,
+            size: size,
+            max_size: max_size,
+            max_files: max_files,
+        })
+    }
+
+    fn rotated_path(&self, number: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", number));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        // Shift the older files up by one, the oldest is overwritten
+        for number in (1..self.max_files).rev() {
+            let from = self.rotated_path(number);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(number + 1))?;
+            }
+        }
+
+        if self.max_files > 0 {
+            fs::rename(&self.path, self.rotated_path(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        // A line is never split, so a file can go over the limit if a single line is bigger
+        if self.size > 0 && self.size + line.len() + 1 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line)?;
+        self.file.write_all(b"\n")?;
+        self.size += line.len() + 1;
+        Ok(())
+    }
+}
+
+
+pub struct JsonFileDrain {
+    file: Mutex<RotatingFile>,
+}
+
+
+impl JsonFileDrain {
+    pub fn open(path: &Path, max_size: usize, max_files: usize) -> io::Result<JsonFileDrain> {
+        Ok(JsonFileDrain {
+            file: Mutex::new(RotatingFile::open(path, max_size, max_files)?),
+        })
+    }
+}
+
+
+impl Drain for JsonFileDrain {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        let mut object = Map::new();
+        object.insert("timestamp".to_string(), Json::String(Utc::now().to_rfc3339()));
+        object.insert("level".to_string(), Json::String(record.level().as_str().to_string()));
+        object.insert("module".to_string(), Json::String(record.module().to_string()));
+        object.insert("message".to_string(), Json::String(fmt::format(*record.msg())));
+
+        // Values of the logger (like "request_id") come first, so the record's own values take their place
+        {
+            let mut serializer = JsonSerializer { object: &mut object };
+            values.serialize(record, &mut serializer).map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to serialize log values"))?;
+            record.kv().serialize(record, &mut serializer).map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to serialize log values"))?;
+        }
+
+        let line = serde_json::to_vec(&Json::Object(object)).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        self.file.lock().unwrap().write_line(&line)
+    }
+}
+
@@ -0,0 +1,194 @@
+extern crate rusticsearch;
+extern crate chrono;
+#[macro_use]
+extern crate router;
+extern crate url;
+#[macro_use]
+extern crate slog;
+extern crate slog_term;
+extern crate slog_async;
+extern crate uuid;
+#[macro_use]
+extern crate serde_json;
+
+mod api;
+mod logging;
+
+use std::env;
+use std::process;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::panic;
+
+use slog::Drain;
+
+use logging::{JsonFileDrain, DEFAULT_MAX_FILE_SIZE, DEFAULT_MAX_FILES};
+
+use rusticsearch::VERSION;
+use rusticsearch::bench;
+use rusticsearch::system::System;
+use rusticsearch::tenancy::Tenancy;
+use rusticsearch::watcher::Watcher;
+use rusticsearch::settings::NodeSettings;
+use rusticsearch::index::store_cache::DEFAULT_MAX_OPEN_STORES;
+use rusticsearch::index::maintenance::MAINTENANCE_BUSY_INTERVAL_MS;
+use rusticsearch::search::backends::rocksdb::{QueryLimits, DEFAULT_MAX_CLAUSE_COUNT, DEFAULT_MAX_EXPANSIONS};
+
+
+/// Reads a positive integer from an environment variable, exiting if it's invalid
+fn read_positive_env_var(name: &str, default: usize) -> usize {
+    match env::var(name) {
+        Ok(value) => match value.parse::<usize>() {
+            Ok(value) if value > 0 => value,
+            _ => {
+                eprintln!("{} must be a positive integer", name);
+                process::exit(1);
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+
+fn main() {
+    // "rusticsearch bench" runs the benchmark harness instead of the server
+    let mut args = env::args().skip(1);
+    if let Some(ref command) = args.next() {
+        if command == "bench" {
+            let config = match bench::BenchConfig::from_args(args) {
+                Ok(config) => config,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                }
+            };
+
+            match bench::run(&config) {
+                Ok(results) => bench::print_results(&results),
+                Err(error) => {
+                    eprintln!("benchmark failed: {}", error);
+                    process::exit(1);
+                }
+            }
+
+            return;
+        }
+    }
+
+    // Setup logging
+    // With "RUSTICSEARCH_LOG_FORMAT=json", logs are written as JSON lines to a file that's
+    // rotated when it reaches "RUSTICSEARCH_LOG_MAX_SIZE" bytes
+    let log = match env::var("RUSTICSEARCH_LOG_FORMAT").as_ref().map(|format| format.as_str()) {
+        Ok("json") => {
+            let path = env::var("RUSTICSEARCH_LOG_FILE").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from("logs/rusticsearch.json"));
+            let max_size = read_positive_env_var("RUSTICSEARCH_LOG_MAX_SIZE", DEFAULT_MAX_FILE_SIZE);
+            let max_files = read_positive_env_var("RUSTICSEARCH_LOG_MAX_FILES", DEFAULT_MAX_FILES);
+
+            let drain = match JsonFileDrain::open(&path, max_size, max_files) {
+                Ok(drain) => drain,
+                Err(error) => {
+                    eprintln!("failed to open log file {}: {}", path.display(), error);
+                    process::exit(1);
+                }
+            };
+
+            // Records that can't be written are dropped rather than stopping the server
+            let drain = slog_async::Async::new(drain.ignore_res()).build().fuse();
+            slog::Logger::root(drain, o!())
+        }
+        Ok("text") | Err(_) => {
+            let decorator = slog_term::TermDecorator::new().build();
+            let drain = slog_term::CompactFormat::new(decorator).build().fuse();
+            let drain = slog_async::Async::new(drain).build().fuse();
+            slog::Logger::root(drain, o!())
+        }
+        Ok(format) => {
+            eprintln!("RUSTICSEARCH_LOG_FORMAT must be \"text\" or \"json\", not {:?}", format);
+            process::exit(1);
+        }
+    };
+
+    info!(log, "starting rusticsearch"; "version" => VERSION);
+
+    // Bounds the number of file handles used by indices
+    let max_open_indices = read_positive_env_var("RUSTICSEARCH_MAX_OPEN_INDICES", DEFAULT_MAX_OPEN_STORES);
+
+    // Bounds the number of term clauses that a query can expand into
+    // (like "indices.query.bool.max_clause_count" in Elasticsearch)
+    let query_limits = QueryLimits {
+        max_clause_count: read_positive_env_var("RUSTICSEARCH_MAX_CLAUSE_COUNT", DEFAULT_MAX_CLAUSE_COUNT),
+        max_expansions: read_positive_env_var("RUSTICSEARCH_MAX_EXPANSIONS", DEFAULT_MAX_EXPANSIONS),
+    };
+
+    // API keys and quotas, only enabled if "data/tenants.json" exists
+    let data_dir = Path::new("data/").to_path_buf();
+    let tenancy = match Tenancy::load(&data_dir) {
+        Ok(tenancy) => tenancy,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    info!(log, "loaded tenants"; "enabled" => tenancy.is_enabled());
+
+    let watcher = match Watcher::load(&data_dir) {
+        Ok(watcher) => watcher,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+
+    // Settings changed through "_cluster/settings", the limits above are their defaults
+    let settings = match NodeSettings::load(&data_dir, &query_limits) {
+        Ok(settings) => settings,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+
+    let system = Arc::new(System::new(log, data_dir, max_open_indices, settings, tenancy, watcher));
+
+    info!(system.log, "loading indices"; "max_open" => max_open_indices);
+    system.load_indices();
+
+    {
+        let system = system.clone();
+        thread::spawn(move || {
+            let mut last_watch_run: Option<Instant> = None;
+
+            loop {
+                {
+                    // Each index has its own schedule, this only runs the ones that are due
+                    let cluster_metadata = system.metadata.read().unwrap();
+                    for index in cluster_metadata.indices.values() {
+                        if let Some(Err(error)) = index.run_scheduled_maintenance() {
+                            error!(system.log, "index maintenance task failed"; "index" => index.canonical_name(), "error" => error, "consecutive_failures" => index.maintenance_stats().consecutive_failures);
+                        }
+                    }
+                }
+
+                // Run watches after the cluster metadata lock is released as they take it themselves
+                if last_watch_run.map(|last_watch_run| last_watch_run.elapsed() >= Duration::new(1, 0)).unwrap_or(true) {
+                    last_watch_run = Some(Instant::now());
+
+                    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                        system.watcher.run_due_watches(&system);
+                    }));
+
+                    if let Err(error) = result {
+                        error!(system.log, "watcher panicked"; "error" => format!("{:?}", error));
+                    }
+                }
+
+                thread::sleep(Duration::from_millis(MAINTENANCE_BUSY_INTERVAL_MS));
+            }
+        });
+    }
+
+    info!(system.log, "starting api server");
+    api::api_main(system);
+}
@@ -0,0 +1,241 @@
+//! Sort keys that order strings by the conventions of a language
+//!
+//! Comparing the sort keys of two strings byte by byte gives the same order as
+//! comparing the strings with the collation. Keys are made of up to four levels,
+//! separated by a character that sorts before any other:
+//!
+//! Primary: the base letters, so "a", "á" and "A" are equal
+//! Secondary: accents, so "a" < "á"
+//! Tertiary: case, lowercase first
+//! Identical: the original string, to break any remaining ties
+//!
+//! Letters are folded to their base letter by the rules of the ASCII folding filter,
+//! some languages then treat extra letters as separate letters that sort after another
+//! (eg, "å", "ä" and "ö" after "z" in Swedish).
+
+use analysis::lucene_asciifold::fold_to_ascii;
+
+
+/// Separates the levels of a sort key
+const LEVEL_SEPARATOR: char = '\u{1}';
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollationStrength {
+    Primary,
+    Secondary,
+    Tertiary,
+    Identical,
+}
+
+
+impl CollationStrength {
+    pub fn from_str(strength: &str) -> Option<CollationStrength> {
+        match strength {
+            "primary" => Some(CollationStrength::Primary),
+            "secondary" => Some(CollationStrength::Secondary),
+            "tertiary" => Some(CollationStrength::Tertiary),
+            "identical" => Some(CollationStrength::Identical),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CollationStrength::Primary => "primary",
+            CollationStrength::Secondary => "secondary",
+            CollationStrength::Tertiary => "tertiary",
+            CollationStrength::Identical => "identical",
+        }
+    }
+}
+
+
+impl Default for CollationStrength {
+    fn default() -> CollationStrength {
+        CollationStrength::Tertiary
+    }
+}
+
+
+/// The rules of a language that differ from the default order
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tailoring {
+    /// Every letter sorts as its base letter
+    Root,
+
+    /// German phone books, umlauts sort as the letter followed by "e"
+    GermanPhonebook,
+
+    /// Swedish and Finnish, "å", "ä" and "ö" sort after "z"
+    Swedish,
+
+    /// Danish and Norwegian, "æ", "ø" and "å" sort after "z"
+    Danish,
+
+    /// "ñ" sorts after "n"
+    Spanish,
+}
+
+
+impl Tailoring {
+    fn from_locale(locale: &str) -> Option<Tailoring> {
+        let locale = locale.to_lowercase();
+        let language = locale.split(|c| c == '-' || c == '_' || c == '@').next().unwrap_or("");
+
+        match language {
+            "" | "root" | "und" | "en" | "fr" | "it" | "nl" | "pt" => Some(Tailoring::Root),
+            "de" if locale.contains("phonebook") || locale.contains("phonebk") => Some(Tailoring::GermanPhonebook),
+            "de" => Some(Tailoring::Root),
+            "sv" | "fi" => Some(Tailoring::Swedish),
+            "da" | "nb" | "nn" | "no" => Some(Tailoring::Danish),
+            "es" => Some(Tailoring::Spanish),
+            _ => None,
+        }
+    }
+
+    /// The primary weight of a lowercase letter, if this language treats it differently
+    ///
+    /// Letters that sort after a base letter are given that letter followed by a character
+    /// above any ASCII character, so they sort after every word that continues with the
+    /// base letter.
+    fn primary(&self, c: char) -> Option<&'static str> {
+        match (*self, c) {
+            (Tailoring::GermanPhonebook, 'ä') => Some("ae"),
+            (Tailoring::GermanPhonebook, 'ö') => Some("oe"),
+            (Tailoring::GermanPhonebook, 'ü') => Some("ue"),
+            (Tailoring::Swedish, 'å') => Some("z\u{80}"),
+            (Tailoring::Swedish, 'ä') | (Tailoring::Swedish, 'æ') => Some("z\u{81}"),
+            (Tailoring::Swedish, 'ö') | (Tailoring::Swedish, 'ø') => Some("z\u{82}"),
+            (Tailoring::Danish, 'æ') | (Tailoring::Danish, 'ä') => Some("z\u{80}"),
+            (Tailoring::Danish, 'ø') | (Tailoring::Danish, 'ö') => Some("z\u{81}"),
+            (Tailoring::Danish, 'å') => Some("z\u{82}"),
+            (Tailoring::Spanish, 'ñ') => Some("n\u{80}"),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collation {
+    locale: String,
+    tailoring: Tailoring,
+    strength: CollationStrength,
+}
+
+
+impl Collation {
+    /// Returns None if the locale isn't supported
+    pub fn new(locale: &str, strength: CollationStrength) -> Option<Collation> {
+        Tailoring::from_locale(locale).map(|tailoring| {
+            Collation {
+                locale: locale.to_string(),
+                tailoring: tailoring,
+                strength: strength,
+            }
+        })
+    }
+
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    pub fn strength(&self) -> CollationStrength {
+        self.strength
+    }
+
+    pub fn sort_key(&self, string: &str) -> String {
+        let mut key = String::with_capacity(string.len() * 2);
+
+        // Primary level
+        for c in string.chars().flat_map(char::to_lowercase) {
+            match self.tailoring.primary(c) {
+                Some(weight) => key.push_str(weight),
+                None => {
+                    let mut buf = [0; 4];
+                    key.push_str(&fold_to_ascii(c.encode_utf8(&mut buf)).to_lowercase());
+                }
+            }
+        }
+
+        if self.strength == CollationStrength::Primary {
+            return key;
+        }
+
+        // Secondary level
+        key.push(LEVEL_SEPARATOR);
+        key.extend(string.chars().flat_map(char::to_lowercase));
+
+        if self.strength == CollationStrength::Secondary {
+            return key;
+        }
+
+        // Tertiary level
+        key.push(LEVEL_SEPARATOR);
+        key.extend(string.chars().map(|c| if c.is_uppercase() { '1' } else { '0' }));
+
+        if self.strength == CollationStrength::Tertiary {
+            return key;
+        }
+
+        key.push(LEVEL_SEPARATOR);
+        key.push_str(string);
+        key
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Collation, CollationStrength};
+
+    fn sort(locale: &str, strength: CollationStrength, words: &[&str]) -> Vec<String> {
+        let collation = Collation::new(locale, strength).unwrap();
+        let mut words = words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+        words.sort_by_key(|word| collation.sort_key(word));
+        words
+    }
+
+    #[test]
+    fn test_german() {
+        let words = ["Zucker", "Äpfel", "Apfel", "Ofen", "Öl", "Ober"];
+
+        // Umlauts sort with their base letter, rather than after "z" like they do byte by byte
+        assert_eq!(sort("de", CollationStrength::Tertiary, &words), vec!["Apfel", "Äpfel", "Ober", "Ofen", "Öl", "Zucker"]);
+
+        // In phone books, "Ä" sorts as "ae" and "Ö" sorts as "oe"
+        assert_eq!(sort("de-DE-u-co-phonebk", CollationStrength::Tertiary, &words), vec!["Äpfel", "Apfel", "Ober", "Öl", "Ofen", "Zucker"]);
+        assert_eq!(sort("de@collation=phonebook", CollationStrength::Tertiary, &["Offen", "Öl", "Oel"]), vec!["Oel", "Öl", "Offen"]);
+    }
+
+    #[test]
+    fn test_swedish() {
+        assert_eq!(sort("sv", CollationStrength::Tertiary, &["öl", "zebra", "åka", "ärm", "apa"]), vec!["apa", "zebra", "åka", "ärm", "öl"]);
+    }
+
+    #[test]
+    fn test_spanish() {
+        assert_eq!(sort("es", CollationStrength::Tertiary, &["oso", "ñu", "nube"]), vec!["nube", "ñu", "oso"]);
+    }
+
+    #[test]
+    fn test_strength() {
+        let primary = Collation::new("en", CollationStrength::Primary).unwrap();
+        assert_eq!(primary.sort_key("Resume"), primary.sort_key("résumé"));
+
+        let secondary = Collation::new("en", CollationStrength::Secondary).unwrap();
+        assert_eq!(secondary.sort_key("Resume"), secondary.sort_key("resume"));
+        assert!(secondary.sort_key("resume") < secondary.sort_key("résumé"));
+
+        // Lowercase sorts first, then shorter strings before longer ones
+        let tertiary = Collation::new("en", CollationStrength::Tertiary).unwrap();
+        assert!(tertiary.sort_key("resume") < tertiary.sort_key("Resume"));
+        assert!(tertiary.sort_key("Resume") < tertiary.sort_key("resumes"));
+    }
+
+    #[test]
+    fn test_unsupported_locale() {
+        assert_eq!(Collation::new("xx", CollationStrength::Primary), None);
+    }
+}
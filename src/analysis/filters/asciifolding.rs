@@ -3,20 +3,20 @@
 //! For example, "Ĥéllø" is converted to "Hello" but non-latin scripts such as
 //! arabic or hiragana are not changed.
 
-use std::str;
+use std::borrow::Cow;
 
-use search::{Term, Token};
+use search::AnalysisToken;
 
 use analysis::lucene_asciifold::fold_to_ascii;
 
 
 pub struct ASCIIFoldingFilter<'a> {
-    tokens: Box<Iterator<Item=Token> + 'a>,
+    tokens: Box<Iterator<Item=AnalysisToken<'a>> + 'a>,
 }
 
 
 impl<'a> ASCIIFoldingFilter<'a> {
-    pub fn new(tokens: Box<Iterator<Item=Token> +'a >) -> ASCIIFoldingFilter<'a> {
+    pub fn new(tokens: Box<Iterator<Item=AnalysisToken<'a>> +'a >) -> ASCIIFoldingFilter<'a> {
         ASCIIFoldingFilter {
             tokens: tokens,
         }
@@ -25,20 +25,17 @@ impl<'a> ASCIIFoldingFilter<'a> {
 
 
 impl<'a> Iterator for ASCIIFoldingFilter<'a> {
-    type Item = Token;
+    type Item = AnalysisToken<'a>;
 
-    fn next(&mut self) -> Option<Token> {
+    fn next(&mut self) -> Option<AnalysisToken<'a>> {
         match self.tokens.next() {
-            Some(token) => {
-                Some(Token {
-                    term: match str::from_utf8(token.term.as_bytes()) {
-                        Ok(ref string) => {
-                            Term::from_string(&fold_to_ascii(string))
-                        }
-                        _ => token.term.clone(),
-                    },
-                    position: token.position,
-                })
+            Some(mut token) => {
+                // ASCII text doesn't change, so it doesn't need to be copied
+                if !token.text.bytes().all(|byte| byte < 128) {
+                    token.text = Cow::Owned(fold_to_ascii(&token.text));
+                }
+
+                Some(token)
             }
             None => None
         }
@@ -48,37 +45,37 @@ impl<'a> Iterator for ASCIIFoldingFilter<'a> {
 
 #[cfg(test)]
 mod tests {
-    use search::{Term, Token};
+    use search::AnalysisToken;
 
     use super::ASCIIFoldingFilter;
 
     #[test]
     fn test_simple() {
-        let mut tokens: Vec<Token> = vec![
-            Token { term: Term::from_string("Ĥéllø"), position: 1 },
+        let mut tokens: Vec<AnalysisToken> = vec![
+            AnalysisToken::new("Ĥéllø", 1),
         ];
 
         let token_filter = ASCIIFoldingFilter::new(Box::new(tokens.drain(..)));
-        let tokens = token_filter.collect::<Vec<Token>>();
+        let tokens = token_filter.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("Hello"), position: 1 }
+            AnalysisToken::new("Hello", 1)
         ]);
     }
 
     #[test]
     fn test_hiragana_not_changed() {
-        let mut tokens: Vec<Token> = vec![
-            Token { term: Term::from_string("こんにちは"), position: 1 },
-            Token { term: Term::from_string("ハチ公"), position: 2 },
+        let mut tokens: Vec<AnalysisToken> = vec![
+            AnalysisToken::new("こんにちは", 1),
+            AnalysisToken::new("ハチ公", 2),
         ];
 
         let token_filter = ASCIIFoldingFilter::new(Box::new(tokens.drain(..)));
-        let tokens = token_filter.collect::<Vec<Token>>();
+        let tokens = token_filter.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("こんにちは"), position: 1 },
-            Token { term: Term::from_string("ハチ公"), position: 2 },
+            AnalysisToken::new("こんにちは", 1),
+            AnalysisToken::new("ハチ公", 2),
         ]);
     }
 }
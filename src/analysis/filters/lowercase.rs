@@ -1,17 +1,19 @@
 //! Converts each token into lowercase
+//!
+//! Tokens that are already lowercase are passed through without copying their text.
 
-use std::str;
+use std::borrow::Cow;
 
-use search::{Term, Token};
+use search::AnalysisToken;
 
 
 pub struct LowercaseFilter<'a> {
-    tokens: Box<Iterator<Item=Token> + 'a>,
+    tokens: Box<Iterator<Item=AnalysisToken<'a>> + 'a>,
 }
 
 
 impl<'a> LowercaseFilter<'a> {
-    pub fn new(tokens: Box<Iterator<Item=Token> +'a>) -> LowercaseFilter<'a> {
+    pub fn new(tokens: Box<Iterator<Item=AnalysisToken<'a>> +'a>) -> LowercaseFilter<'a> {
         LowercaseFilter {
             tokens: tokens,
         }
@@ -20,21 +22,16 @@ impl<'a> LowercaseFilter<'a> {
 
 
 impl<'a> Iterator for LowercaseFilter<'a> {
-    type Item = Token;
+    type Item = AnalysisToken<'a>;
 
-    fn next(&mut self) -> Option<Token> {
+    fn next(&mut self) -> Option<AnalysisToken<'a>> {
         match self.tokens.next() {
-            Some(token) => {
-                Some(Token {
-                    term: match str::from_utf8(token.term.as_bytes()) {
-                        Ok(string) => {
-                            // TODO: Can this be done in place?
-                            Term::from_string(&string.to_lowercase())
-                        }
-                        _ => token.term.clone(),
-                    },
-                    position: token.position,
-                })
+            Some(mut token) => {
+                if !token.text.chars().flat_map(char::to_lowercase).eq(token.text.chars()) {
+                    token.text = Cow::Owned(token.text.to_lowercase());
+                }
+
+                Some(token)
             }
             None => None
         }
@@ -44,41 +41,64 @@ impl<'a> Iterator for LowercaseFilter<'a> {
 
 #[cfg(test)]
 mod tests {
-    use search::{Term, Token};
+    use std::borrow::Cow;
+
+    use search::AnalysisToken;
 
     use super::LowercaseFilter;
 
     #[test]
     fn test_lowercase_filter() {
-        let mut tokens: Vec<Token> = vec![
-            Token { term: Term::from_string("Hulk"), position: 1 },
-            Token { term: Term::from_string("SMASH"), position: 2 }
+        let mut tokens: Vec<AnalysisToken> = vec![
+            AnalysisToken::new("Hulk", 1),
+            AnalysisToken::new("SMASH", 2)
         ];
 
         let token_filter = LowercaseFilter::new(Box::new(tokens.drain(..)));
-        let tokens = token_filter.collect::<Vec<Token>>();
+        let tokens = token_filter.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("hulk"), position: 1 },
-            Token { term: Term::from_string("smash"), position: 2 }
+            AnalysisToken::new("hulk", 1),
+            AnalysisToken::new("smash", 2)
         ]);
     }
 
     #[test]
     fn test_lowercase_filter_cjk() {
-        let mut tokens: Vec<Token> = vec![
-            Token { term: Term::from_string("こんにちは"), position: 1 },
-            Token { term: Term::from_string("ハチ公"), position: 2 },
-            Token { term: Term::from_string("Test"), position: 3 }
+        let mut tokens: Vec<AnalysisToken> = vec![
+            AnalysisToken::new("こんにちは", 1),
+            AnalysisToken::new("ハチ公", 2),
+            AnalysisToken::new("Test", 3)
         ];
 
         let token_filter = LowercaseFilter::new(Box::new(tokens.drain(..)));
-        let tokens = token_filter.collect::<Vec<Token>>();
+        let tokens = token_filter.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("こんにちは"), position: 1 },
-            Token { term: Term::from_string("ハチ公"), position: 2 },
-            Token { term: Term::from_string("test"), position: 3 }
+            AnalysisToken::new("こんにちは", 1),
+            AnalysisToken::new("ハチ公", 2),
+            AnalysisToken::new("test", 3)
         ]);
     }
+
+    #[test]
+    fn test_lowercase_filter_only_copies_changed_text() {
+        let mut tokens: Vec<AnalysisToken> = vec![
+            AnalysisToken::new("hulk", 1),
+            AnalysisToken::new("SMASH", 2)
+        ];
+
+        let token_filter = LowercaseFilter::new(Box::new(tokens.drain(..)));
+        let tokens = token_filter.collect::<Vec<AnalysisToken>>();
+
+        match tokens[0].text {
+            Cow::Borrowed("hulk") => {}
+            ref text => panic!("expected borrowed text, got {:?}", text),
+        }
+
+        match tokens[1].text {
+            Cow::Owned(ref text) if text == "smash" => {}
+            ref text => panic!("expected owned text, got {:?}", text),
+        }
+    }
 }
@@ -3,12 +3,13 @@ pub mod ngram;
 pub mod asciifolding;
 
 use serde::{Serialize, Serializer};
-use search::Token;
+use search::AnalysisToken;
 
 use analysis::ngram_generator::Edge;
 use analysis::filters::lowercase::LowercaseFilter;
 use analysis::filters::ngram::NGramFilter;
 use analysis::filters::asciifolding::ASCIIFoldingFilter;
+use analysis::registry::CustomFilterSpec;
 
 
 /// Defines a token filter
@@ -18,9 +19,9 @@ use analysis::filters::asciifolding::ASCIIFoldingFilter;
 /// # Examples
 ///
 /// ```
-/// use search::{Term, Token};
-/// use search::analysis::tokenizers::TokenizerSpec;
-/// use search::analysis::filters::FilterSpec;
+/// use rusticsearch::search::AnalysisToken;
+/// use rusticsearch::analysis::tokenizers::TokenizerSpec;
+/// use rusticsearch::analysis::filters::FilterSpec;
 ///
 /// let standard_tokenizer = TokenizerSpec::Standard;
 /// let token_stream = standard_tokenizer.initialise("Hello, WORLD!");
@@ -29,11 +30,11 @@ use analysis::filters::asciifolding::ASCIIFoldingFilter;
 /// let lowercase_filter = FilterSpec::Lowercase;
 /// let filtered_token_stream = lowercase_filter.initialise(token_stream);
 ///
-/// let tokens = filtered_token_stream.collect::<Vec<Token>>();
+/// let tokens = filtered_token_stream.collect::<Vec<AnalysisToken>>();
 ///
 /// assert_eq!(tokens, vec![
-///     Token { term: Term::from_string("hello"), position: 1 },
-///     Token { term: Term::from_string("world"), position: 2 },
+///     AnalysisToken::new("hello", 1),
+///     AnalysisToken::new("world", 2),
 /// ]);
 /// ```
 #[derive(Debug, Clone, PartialEq)]
@@ -45,11 +46,14 @@ pub enum FilterSpec {
         edge: Edge,
     },
     ASCIIFolding,
+
+    /// A filter of a type that was added through the analysis registry
+    Custom(CustomFilterSpec),
 }
 
 
 impl FilterSpec {
-    pub fn initialise<'a>(&self, input: Box<Iterator<Item=Token> + 'a>) -> Box<Iterator<Item=Token> + 'a> {
+    pub fn initialise<'a>(&self, input: Box<Iterator<Item=AnalysisToken<'a>> + 'a>) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a> {
         match *self {
             FilterSpec::Lowercase => {
                 Box::new(LowercaseFilter::new(input))
@@ -60,6 +64,24 @@ impl FilterSpec {
             FilterSpec::ASCIIFolding => {
                 Box::new(ASCIIFoldingFilter::new(input))
             }
+            FilterSpec::Custom(ref custom) => {
+                custom.initialise(input)
+            }
+        }
+    }
+}
+
+
+impl FilterSpec {
+    /// The name of the builtin filter, if this filter can be used in normalizers
+    ///
+    /// Normalizers keep the whole value as a single term, so only filters that change
+    /// each character are allowed.
+    pub fn normalizer_filter_name(&self) -> Option<&'static str> {
+        match *self {
+            FilterSpec::Lowercase => Some("lowercase"),
+            FilterSpec::ASCIIFolding => Some("asciifolding"),
+            FilterSpec::NGram{..} | FilterSpec::Custom(_) => None,
         }
     }
 }
@@ -105,6 +127,9 @@ impl Serialize for FilterSpec {
                     "type": "asciifolding",
                 })
             }
+            FilterSpec::Custom(ref custom) => {
+                custom.definition.clone()
+            }
         };
 
         json.serialize(serializer)
@@ -1,24 +1,26 @@
 //! Generates a set of "ngram" tokens for each source token
+//!
+//! The ngrams of a token that borrows its text from the input borrow from the input too.
 
+use std::borrow::Cow;
 use std::collections::VecDeque;
-use std::str;
 
-use search::{Term, Token};
+use search::AnalysisToken;
 
 use analysis::ngram_generator::{Edge, NGramGenerator};
 
 
 pub struct NGramFilter<'a> {
-    tokens: Box<Iterator<Item=Token> + 'a>,
+    tokens: Box<Iterator<Item=AnalysisToken<'a>> + 'a>,
     min_size: usize,
     max_size: usize,
     edge: Edge,
-    output_buffer: VecDeque<Token>,
+    output_buffer: VecDeque<AnalysisToken<'a>>,
 }
 
 
 impl<'a> NGramFilter<'a> {
-    pub fn new(tokens: Box<Iterator<Item=Token> +'a >, min_size: usize, max_size: usize, edge: Edge) -> NGramFilter<'a> {
+    pub fn new(tokens: Box<Iterator<Item=AnalysisToken<'a>> +'a >, min_size: usize, max_size: usize, edge: Edge) -> NGramFilter<'a> {
         NGramFilter {
             tokens: tokens,
             min_size: min_size,
@@ -31,23 +33,27 @@ impl<'a> NGramFilter<'a> {
 
 
 impl<'a> Iterator for NGramFilter<'a> {
-    type Item = Token;
+    type Item = AnalysisToken<'a>;
 
-    fn next(&mut self) -> Option<Token> {
+    fn next(&mut self) -> Option<AnalysisToken<'a>> {
         while self.output_buffer.is_empty() {
             // Generate ngrams for next token
             let token = self.tokens.next();
 
             match token {
                 Some(token) => {
-                    if let Ok(ref word) = str::from_utf8(&token.term.as_bytes()) {
-                        let ngram_generator = NGramGenerator::new(&word, self.min_size, self.max_size, self.edge);
-
-                        for gram in ngram_generator {
-                            self.output_buffer.push_back(Token {
-                                term: Term::from_string(gram),
-                                position: token.position,
-                            });
+                    let position = token.position;
+
+                    match token.text {
+                        Cow::Borrowed(word) => {
+                            for gram in NGramGenerator::new(word, self.min_size, self.max_size, self.edge) {
+                                self.output_buffer.push_back(AnalysisToken::new(gram, position));
+                            }
+                        }
+                        Cow::Owned(ref word) => {
+                            for gram in NGramGenerator::new(word, self.min_size, self.max_size, self.edge) {
+                                self.output_buffer.push_back(AnalysisToken::new(gram.to_string(), position));
+                            }
                         }
                     }
                 }
@@ -62,7 +68,7 @@ impl<'a> Iterator for NGramFilter<'a> {
 
 #[cfg(test)]
 mod tests {
-    use search::{Term, Token};
+    use search::AnalysisToken;
 
     use analysis::ngram_generator::Edge;
 
@@ -70,74 +76,74 @@ mod tests {
 
     #[test]
     fn test_ngram_filter() {
-        let mut tokens: Vec<Token> = vec![
-            Token { term: Term::from_string("hello"), position: 1 },
+        let mut tokens: Vec<AnalysisToken> = vec![
+            AnalysisToken::new("hello", 1),
         ];
 
         let token_filter = NGramFilter::new(Box::new(tokens.drain(..)), 2, 3, Edge::Neither);
-        let tokens = token_filter.collect::<Vec<Token>>();
+        let tokens = token_filter.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("he"), position: 1 },
-            Token { term: Term::from_string("hel"), position: 1 },
-            Token { term: Term::from_string("el"), position: 1 },
-            Token { term: Term::from_string("ell"), position: 1 },
-            Token { term: Term::from_string("ll"), position: 1 },
-            Token { term: Term::from_string("llo"), position: 1 },
-            Token { term: Term::from_string("lo"), position: 1 },
+            AnalysisToken::new("he", 1),
+            AnalysisToken::new("hel", 1),
+            AnalysisToken::new("el", 1),
+            AnalysisToken::new("ell", 1),
+            AnalysisToken::new("ll", 1),
+            AnalysisToken::new("llo", 1),
+            AnalysisToken::new("lo", 1),
         ]);
     }
 
     #[test]
     fn test_edgengram_filter() {
-        let mut tokens: Vec<Token> = vec![
-            Token { term: Term::from_string("hello"), position: 1 },
-            Token { term: Term::from_string("world"), position: 2 }
+        let mut tokens: Vec<AnalysisToken> = vec![
+            AnalysisToken::new("hello", 1),
+            AnalysisToken::new("world", 2)
         ];
 
         let token_filter = NGramFilter::new(Box::new(tokens.drain(..)), 2, 3, Edge::Left);
-        let tokens = token_filter.collect::<Vec<Token>>();
+        let tokens = token_filter.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("he"), position: 1 },
-            Token { term: Term::from_string("hel"), position: 1 },
-            Token { term: Term::from_string("wo"), position: 2 },
-            Token { term: Term::from_string("wor"), position: 2 },
+            AnalysisToken::new("he", 1),
+            AnalysisToken::new("hel", 1),
+            AnalysisToken::new("wo", 2),
+            AnalysisToken::new("wor", 2),
         ]);
     }
 
     #[test]
     fn test_edgengram_filter_max_size() {
-        let mut tokens: Vec<Token> = vec![
-            Token { term: Term::from_string("hello"), position: 1 },
+        let mut tokens: Vec<AnalysisToken> = vec![
+            AnalysisToken::new("hello", 1),
         ];
 
         let token_filter = NGramFilter::new(Box::new(tokens.drain(..)), 2, 1000, Edge::Left);
-        let tokens = token_filter.collect::<Vec<Token>>();
+        let tokens = token_filter.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("he"), position: 1 },
-            Token { term: Term::from_string("hel"), position: 1 },
-            Token { term: Term::from_string("hell"), position: 1 },
-            Token { term: Term::from_string("hello"), position: 1 },
+            AnalysisToken::new("he", 1),
+            AnalysisToken::new("hel", 1),
+            AnalysisToken::new("hell", 1),
+            AnalysisToken::new("hello", 1),
         ]);
     }
 
     #[test]
     fn test_edgengram_filter_right() {
-        let mut tokens: Vec<Token> = vec![
-            Token { term: Term::from_string("hello"), position: 1 },
-            Token { term: Term::from_string("world"), position: 2 }
+        let mut tokens: Vec<AnalysisToken> = vec![
+            AnalysisToken::new("hello", 1),
+            AnalysisToken::new("world", 2)
         ];
 
         let token_filter = NGramFilter::new(Box::new(tokens.drain(..)), 2, 3, Edge::Right);
-        let tokens = token_filter.collect::<Vec<Token>>();
+        let tokens = token_filter.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("lo"), position: 1 },
-            Token { term: Term::from_string("llo"), position: 1 },
-            Token { term: Term::from_string("ld"), position: 2 },
-            Token { term: Term::from_string("rld"), position: 2 },
+            AnalysisToken::new("lo", 1),
+            AnalysisToken::new("llo", 1),
+            AnalysisToken::new("ld", 2),
+            AnalysisToken::new("rld", 2),
         ]);
     }
 }
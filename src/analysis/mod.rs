@@ -11,10 +11,15 @@
 
 pub mod ngram_generator;
 pub mod lucene_asciifold;
+pub mod collation;
 pub mod tokenizers;
 pub mod filters;
+pub mod registry;
 
-use search::token::Token;
+use std::iter;
+
+use serde::{Serialize, Serializer};
+use search::token::{Token, AnalysisToken};
 
 use analysis::tokenizers::TokenizerSpec;
 use analysis::filters::FilterSpec;
@@ -27,10 +32,10 @@ use analysis::filters::FilterSpec;
 /// # Examples
 ///
 /// ```
-/// use search::{Term, Token};
-/// use search::analysis::tokenizers::TokenizerSpec;
-/// use search::analysis::filters::FilterSpec;
-/// use search::analysis::AnalyzerSpec;
+/// use rusticsearch::search::{Term, Token};
+/// use rusticsearch::analysis::tokenizers::TokenizerSpec;
+/// use rusticsearch::analysis::filters::FilterSpec;
+/// use rusticsearch::analysis::AnalyzerSpec;
 ///
 /// // Define an analyzer that splits words and converts them into lowercase
 /// let analyzer = AnalyzerSpec {
@@ -56,7 +61,9 @@ pub struct AnalyzerSpec {
 
 
 impl AnalyzerSpec {
-    pub fn initialise<'a>(&self, input: &'a str) -> Box<Iterator<Item=Token> + 'a> {
+    /// Runs the analyzer, the text of each token is borrowed from the input unless a
+    /// filter had to change it
+    pub fn tokens<'a>(&self, input: &'a str) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a> {
         let mut analyzer = self.tokenizer.initialise(input);
 
         for filter in self.filters.iter() {
@@ -65,4 +72,53 @@ impl AnalyzerSpec {
 
         analyzer
     }
+
+    /// Runs the analyzer and converts each token into a term that can be indexed or searched for
+    pub fn initialise<'a>(&self, input: &'a str) -> Box<Iterator<Item=Token> + 'a> {
+        Box::new(self.tokens(input).map(AnalysisToken::into_token))
+    }
+}
+
+
+/// Defines a normalizer
+///
+/// Normalizers are like analyzers without a tokenizer, the whole value is kept as a
+/// single term. They are used on keyword fields so exact matches can ignore case or
+/// accents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalizerSpec {
+    pub filters: Vec<FilterSpec>,
+}
+
+
+impl NormalizerSpec {
+    pub fn normalize<'a>(&self, input: &'a str) -> String {
+        let mut token_stream: Box<Iterator<Item=AnalysisToken<'a>> + 'a> = Box::new(iter::once(AnalysisToken::new(input, 1)));
+
+        for filter in self.filters.iter() {
+            token_stream = filter.initialise(token_stream);
+        }
+
+        // Normalizer filters always give back the one token
+        match token_stream.next() {
+            Some(token) => token.text.into_owned(),
+            None => input.to_string(),
+        }
+    }
+}
+
+
+impl Serialize for NormalizerSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Filters are written by their builtin names so they can be read back without
+        // the filter definitions of the index
+        let filter_names = self.filters.iter().filter_map(FilterSpec::normalizer_filter_name).collect::<Vec<_>>();
+
+        let json = json!({
+            "type": "custom",
+            "filter": filter_names,
+        });
+
+        json.serialize(serializer)
+    }
 }
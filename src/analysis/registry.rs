@@ -0,0 +1,281 @@
+//! Token filters and tokenizers that are added by embedders
+//!
+//! Embedders register a factory for each type of filter or tokenizer with the registry
+//! on the system. Index settings can then define filters and tokenizers of that type
+//! like any of the builtin ones:
+//!
+//! ```json
+//! {
+//!     "settings": {
+//!         "analysis": {
+//!             "filter": {
+//!                 "short_words": {"type": "max_length", "length": 5}
+//!             },
+//!             "analyzer": {
+//!                 "short": {"type": "custom", "tokenizer": "standard", "filter": ["short_words"]}
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! The factory is called with the whole definition (including its "type"). Analyzers
+//! can also refer to a registered type by name, in which case the factory is given an
+//! object with just the "type". Builtin types can't be replaced.
+//!
+//! The definitions are saved with the index metadata, so the types must be registered
+//! before the indices that use them are loaded.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use serde_json::Value as Json;
+
+use search::token::AnalysisToken;
+
+use analysis::filters::FilterSpec;
+use analysis::tokenizers::TokenizerSpec;
+
+
+/// A token filter that was created through the registry
+pub trait TokenFilter: Send + Sync {
+    fn initialise<'a>(&self, input: Box<Iterator<Item=AnalysisToken<'a>> + 'a>) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a>;
+}
+
+
+/// A tokenizer that was created through the registry
+pub trait Tokenizer: Send + Sync {
+    fn initialise<'a>(&self, input: &'a str) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a>;
+}
+
+
+pub type TokenFilterFactory = Box<Fn(&Json) -> Result<Box<TokenFilter>, String> + Send + Sync>;
+pub type TokenizerFactory = Box<Fn(&Json) -> Result<Box<Tokenizer>, String> + Send + Sync>;
+
+
+/// A filter of a registered type, along with the definition it was created from
+#[derive(Clone)]
+pub struct CustomFilterSpec {
+    pub definition: Json,
+    filter: Arc<TokenFilter>,
+}
+
+
+impl CustomFilterSpec {
+    pub fn initialise<'a>(&self, input: Box<Iterator<Item=AnalysisToken<'a>> + 'a>) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a> {
+        self.filter.initialise(input)
+    }
+}
+
+
+impl PartialEq for CustomFilterSpec {
+    fn eq(&self, other: &CustomFilterSpec) -> bool {
+        self.definition == other.definition
+    }
+}
+
+
+impl fmt::Debug for CustomFilterSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CustomFilterSpec").field("definition", &self.definition).finish()
+    }
+}
+
+
+/// A tokenizer of a registered type, along with the definition it was created from
+#[derive(Clone)]
+pub struct CustomTokenizerSpec {
+    pub definition: Json,
+    tokenizer: Arc<Tokenizer>,
+}
+
+
+impl CustomTokenizerSpec {
+    pub fn initialise<'a>(&self, input: &'a str) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a> {
+        self.tokenizer.initialise(input)
+    }
+}
+
+
+impl PartialEq for CustomTokenizerSpec {
+    fn eq(&self, other: &CustomTokenizerSpec) -> bool {
+        self.definition == other.definition
+    }
+}
+
+
+impl fmt::Debug for CustomTokenizerSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CustomTokenizerSpec").field("definition", &self.definition).finish()
+    }
+}
+
+
+#[derive(Default)]
+pub struct AnalysisRegistry {
+    filters: RwLock<HashMap<String, TokenFilterFactory>>,
+    tokenizers: RwLock<HashMap<String, TokenizerFactory>>,
+}
+
+
+impl AnalysisRegistry {
+    pub fn new() -> AnalysisRegistry {
+        AnalysisRegistry::default()
+    }
+
+    /// Registers a token filter type, replacing any that was registered with the same name
+    pub fn register_filter<F>(&self, name: &str, factory: F) where F: Fn(&Json) -> Result<Box<TokenFilter>, String> + Send + Sync + 'static {
+        self.filters.write().unwrap().insert(name.to_string(), Box::new(factory));
+    }
+
+    /// Registers a tokenizer type, replacing any that was registered with the same name
+    pub fn register_tokenizer<F>(&self, name: &str, factory: F) where F: Fn(&Json) -> Result<Box<Tokenizer>, String> + Send + Sync + 'static {
+        self.tokenizers.write().unwrap().insert(name.to_string(), Box::new(factory));
+    }
+
+    /// The names of the registered token filter types, in order
+    pub fn filter_names(&self) -> Vec<String> {
+        let mut names = self.filters.read().unwrap().keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// The names of the registered tokenizer types, in order
+    pub fn tokenizer_names(&self) -> Vec<String> {
+        let mut names = self.tokenizers.read().unwrap().keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Creates a filter from its definition, returns None if its type isn't registered
+    pub fn create_filter(&self, filter_type: &str, definition: &Json) -> Option<Result<FilterSpec, String>> {
+        let filters = self.filters.read().unwrap();
+        let factory = match filters.get(filter_type) {
+            Some(factory) => factory,
+            None => return None,
+        };
+
+        Some(factory(definition).map(|filter| {
+            FilterSpec::Custom(CustomFilterSpec {
+                definition: definition.clone(),
+                filter: Arc::from(filter),
+            })
+        }))
+    }
+
+    /// Creates a tokenizer from its definition, returns None if its type isn't registered
+    pub fn create_tokenizer(&self, tokenizer_type: &str, definition: &Json) -> Option<Result<TokenizerSpec, String>> {
+        let tokenizers = self.tokenizers.read().unwrap();
+        let factory = match tokenizers.get(tokenizer_type) {
+            Some(factory) => factory,
+            None => return None,
+        };
+
+        Some(factory(definition).map(|tokenizer| {
+            TokenizerSpec::Custom(CustomTokenizerSpec {
+                definition: definition.clone(),
+                tokenizer: Arc::from(tokenizer),
+            })
+        }))
+    }
+}
+
+
+impl fmt::Debug for AnalysisRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AnalysisRegistry")
+            .field("filters", &self.filter_names())
+            .field("tokenizers", &self.tokenizer_names())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::AnalysisToken;
+    use analysis::filters::FilterSpec;
+    use analysis::tokenizers::TokenizerSpec;
+
+    use super::{AnalysisRegistry, TokenFilter, Tokenizer};
+
+    /// Removes tokens that are longer than a number of bytes
+    struct MaxLengthFilter {
+        length: usize,
+    }
+
+    impl TokenFilter for MaxLengthFilter {
+        fn initialise<'a>(&self, input: Box<Iterator<Item=AnalysisToken<'a>> + 'a>) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a> {
+            let length = self.length;
+            Box::new(input.filter(move |token| token.text.len() <= length))
+        }
+    }
+
+    /// Splits on commas
+    struct CommaTokenizer;
+
+    impl Tokenizer for CommaTokenizer {
+        fn initialise<'a>(&self, input: &'a str) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a> {
+            Box::new(input.split(',').enumerate().map(|(i, word)| {
+                AnalysisToken::new(word, i as u32 + 1)
+            }))
+        }
+    }
+
+    fn make_registry() -> AnalysisRegistry {
+        let registry = AnalysisRegistry::new();
+        registry.register_filter("max_length", |definition| {
+            match definition.get("length").and_then(|length| length.as_u64()) {
+                Some(length) => Ok(Box::new(MaxLengthFilter { length: length as usize })),
+                None => Err("\"length\" must be a positive integer".to_string()),
+            }
+        });
+        registry.register_tokenizer("comma", |_| Ok(Box::new(CommaTokenizer)));
+        registry
+    }
+
+    #[test]
+    fn test_create() {
+        let registry = make_registry();
+        assert_eq!(registry.filter_names(), vec!["max_length".to_string()]);
+        assert_eq!(registry.tokenizer_names(), vec!["comma".to_string()]);
+
+        let tokenizer = registry.create_tokenizer("comma", &json!({"type": "comma"})).unwrap().unwrap();
+        let filter = registry.create_filter("max_length", &json!({"type": "max_length", "length": 3})).unwrap().unwrap();
+
+        let tokens = filter.initialise(tokenizer.initialise("foo,quux,bar")).collect::<Vec<_>>();
+        assert_eq!(tokens, vec![
+            AnalysisToken::new("foo", 1),
+            AnalysisToken::new("bar", 3),
+        ]);
+    }
+
+    #[test]
+    fn test_unregistered() {
+        let registry = make_registry();
+
+        assert!(registry.create_filter("comma", &json!({"type": "comma"})).is_none());
+        assert!(registry.create_tokenizer("max_length", &json!({"type": "max_length"})).is_none());
+    }
+
+    #[test]
+    fn test_invalid_definition() {
+        let registry = make_registry();
+
+        assert_eq!(registry.create_filter("max_length", &json!({"type": "max_length"})), Some(Err("\"length\" must be a positive integer".to_string())));
+    }
+
+    #[test]
+    fn test_specs_compare_definitions() {
+        let registry = make_registry();
+
+        let filter = registry.create_filter("max_length", &json!({"type": "max_length", "length": 3})).unwrap().unwrap();
+        assert_eq!(filter, registry.create_filter("max_length", &json!({"type": "max_length", "length": 3})).unwrap().unwrap());
+        assert!(filter != registry.create_filter("max_length", &json!({"type": "max_length", "length": 4})).unwrap().unwrap());
+        assert!(filter != FilterSpec::Lowercase);
+
+        let tokenizer = registry.create_tokenizer("comma", &json!({"type": "comma"})).unwrap().unwrap();
+        assert!(tokenizer != TokenizerSpec::Standard);
+    }
+}
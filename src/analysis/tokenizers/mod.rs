@@ -2,12 +2,13 @@ pub mod standard;
 pub mod ngram;
 
 use serde::{Serialize, Serializer};
-use search::token::Token;
+use search::token::AnalysisToken;
 
 use analysis::ngram_generator::Edge;
 use analysis::filters::lowercase::LowercaseFilter;
 use analysis::tokenizers::standard::StandardTokenizer;
 use analysis::tokenizers::ngram::NGramTokenizer;
+use analysis::registry::CustomTokenizerSpec;
 
 
 /// Defines a tokenizer
@@ -17,17 +18,17 @@ use analysis::tokenizers::ngram::NGramTokenizer;
 /// # Examples
 ///
 /// ```
-/// use search::{Term, Token};
-/// use search::analysis::tokenizers::TokenizerSpec;
+/// use rusticsearch::search::AnalysisToken;
+/// use rusticsearch::analysis::tokenizers::TokenizerSpec;
 ///
 /// let standard_tokenizer = TokenizerSpec::Standard;
 /// let token_stream = standard_tokenizer.initialise("Hello, world!");
 ///
-/// let tokens = token_stream.collect::<Vec<Token>>();
+/// let tokens = token_stream.collect::<Vec<AnalysisToken>>();
 ///
 /// assert_eq!(tokens, vec![
-///     Token { term: Term::from_string("Hello"), position: 1 },
-///     Token { term: Term::from_string("world"), position: 2 },
+///     AnalysisToken::new("Hello", 1),
+///     AnalysisToken::new("world", 2),
 /// ]);
 /// ```
 #[derive(Debug, Clone, PartialEq)]
@@ -38,12 +39,15 @@ pub enum TokenizerSpec {
         min_size: usize,
         max_size: usize,
         edge: Edge,
-    }
+    },
+
+    /// A tokenizer of a type that was added through the analysis registry
+    Custom(CustomTokenizerSpec),
 }
 
 
 impl TokenizerSpec {
-    pub fn initialise<'a>(&self, input: &'a str) -> Box<Iterator<Item=Token> + 'a> {
+    pub fn initialise<'a>(&self, input: &'a str) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a> {
         match *self {
             TokenizerSpec::Standard => {
                 Box::new(StandardTokenizer::new(input))
@@ -54,6 +58,9 @@ impl TokenizerSpec {
             TokenizerSpec::NGram{min_size, max_size, edge} => {
                 Box::new(NGramTokenizer::new(input, min_size, max_size, edge))
             }
+            TokenizerSpec::Custom(ref custom) => {
+                custom.initialise(input)
+            }
         }
     }
 }
@@ -98,6 +105,9 @@ impl Serialize for TokenizerSpec {
                     }
                 }
             }
+            TokenizerSpec::Custom(ref custom) => {
+                custom.definition.clone()
+            }
         };
 
         json.serialize(serializer)
@@ -1,6 +1,6 @@
 use unicode_segmentation::{UnicodeSegmentation, UnicodeWords};
 
-use search::{Term, Token};
+use search::AnalysisToken;
 
 use analysis::ngram_generator::{Edge, NGramGenerator};
 
@@ -30,17 +30,14 @@ impl<'a> NGramTokenizer<'a> {
 
 
 impl<'a> Iterator for NGramTokenizer<'a> {
-    type Item = Token;
+    type Item = AnalysisToken<'a>;
 
-    fn next(&mut self) -> Option<Token> {
+    fn next(&mut self) -> Option<AnalysisToken<'a>> {
         loop {
             // Get next ngram
             if let Some(ref mut ngram_generator) = self.ngram_generator {
                 if let Some(gram) = ngram_generator.next() {
-                    return Some(Token {
-                        term: Term::from_string(gram),
-                        position: self.position_counter,
-                    });
+                    return Some(AnalysisToken::new(gram, self.position_counter));
                 }
             }
 
@@ -63,7 +60,7 @@ impl<'a> Iterator for NGramTokenizer<'a> {
 
 #[cfg(test)]
 mod tests {
-    use search::{Term, Token};
+    use search::AnalysisToken;
 
     use analysis::ngram_generator::Edge;
 
@@ -73,55 +70,55 @@ mod tests {
     #[test]
     fn test_ngram_tokenizer() {
         let tokenizer = NGramTokenizer::new("hello", 2, 3, Edge::Neither);
-        let tokens = tokenizer.collect::<Vec<Token>>();
+        let tokens = tokenizer.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("he"), position: 1 },
-            Token { term: Term::from_string("hel"), position: 1 },
-            Token { term: Term::from_string("el"), position: 1 },
-            Token { term: Term::from_string("ell"), position: 1 },
-            Token { term: Term::from_string("ll"), position: 1 },
-            Token { term: Term::from_string("llo"), position: 1 },
-            Token { term: Term::from_string("lo"), position: 1 },
+            AnalysisToken::new("he", 1),
+            AnalysisToken::new("hel", 1),
+            AnalysisToken::new("el", 1),
+            AnalysisToken::new("ell", 1),
+            AnalysisToken::new("ll", 1),
+            AnalysisToken::new("llo", 1),
+            AnalysisToken::new("lo", 1),
         ]);
     }
 
     #[test]
     fn test_edgengram_tokenizer() {
         let tokenizer = NGramTokenizer::new("hello world", 2, 3, Edge::Left);
-        let tokens = tokenizer.collect::<Vec<Token>>();
+        let tokens = tokenizer.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("he"), position: 1 },
-            Token { term: Term::from_string("hel"), position: 1 },
-            Token { term: Term::from_string("wo"), position: 2 },
-            Token { term: Term::from_string("wor"), position: 2 },
+            AnalysisToken::new("he", 1),
+            AnalysisToken::new("hel", 1),
+            AnalysisToken::new("wo", 2),
+            AnalysisToken::new("wor", 2),
         ]);
     }
 
     #[test]
     fn test_edgengram_tokenizer_max_size() {
         let tokenizer = NGramTokenizer::new("hello", 2, 1000, Edge::Left);
-        let tokens = tokenizer.collect::<Vec<Token>>();
+        let tokens = tokenizer.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("he"), position: 1 },
-            Token { term: Term::from_string("hel"), position: 1 },
-            Token { term: Term::from_string("hell"), position: 1 },
-            Token { term: Term::from_string("hello"), position: 1 },
+            AnalysisToken::new("he", 1),
+            AnalysisToken::new("hel", 1),
+            AnalysisToken::new("hell", 1),
+            AnalysisToken::new("hello", 1),
         ]);
     }
 
     #[test]
     fn test_edgengram_tokenizer_right() {
         let tokenizer = NGramTokenizer::new("hello world", 2, 3, Edge::Right);
-        let tokens = tokenizer.collect::<Vec<Token>>();
+        let tokens = tokenizer.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("lo"), position: 1 },
-            Token { term: Term::from_string("llo"), position: 1 },
-            Token { term: Term::from_string("ld"), position: 2 },
-            Token { term: Term::from_string("rld"), position: 2 },
+            AnalysisToken::new("lo", 1),
+            AnalysisToken::new("llo", 1),
+            AnalysisToken::new("ld", 2),
+            AnalysisToken::new("rld", 2),
         ]);
     }
 }
@@ -2,7 +2,7 @@
 
 use unicode_segmentation::{UnicodeSegmentation, UnicodeWords};
 
-use search::{Term, Token};
+use search::AnalysisToken;
 
 
 pub struct StandardTokenizer<'a> {
@@ -22,17 +22,14 @@ impl<'a> StandardTokenizer<'a> {
 
 
 impl<'a> Iterator for StandardTokenizer<'a> {
-    type Item = Token;
+    type Item = AnalysisToken<'a>;
 
-    fn next(&mut self) -> Option<Token> {
+    fn next(&mut self) -> Option<AnalysisToken<'a>> {
         match self.unicode_words.next() {
             Some(word) => {
                 self.position_counter += 1;
 
-                Some(Token {
-                    term: Term::from_string(word),
-                    position: self.position_counter,
-                })
+                Some(AnalysisToken::new(word, self.position_counter))
             }
             None => None,
         }
@@ -43,7 +40,7 @@ impl<'a> Iterator for StandardTokenizer<'a> {
 
 #[cfg(test)]
 mod tests {
-    use search::{Term, Token};
+    use search::AnalysisToken;
 
     use super::StandardTokenizer;
 
@@ -52,40 +49,40 @@ mod tests {
     #[test]
     fn test_standard_tokenizer() {
         let tokenizer = StandardTokenizer::new(TEXT);
-        let tokens = tokenizer.collect::<Vec<Token>>();
+        let tokens = tokenizer.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("Up"), position: 1 },
-            Token { term: Term::from_string("from"), position: 2 },
-            Token { term: Term::from_string("the"), position: 3 },
-            Token { term: Term::from_string("bowels"), position: 4 },
-            Token { term: Term::from_string("of"), position: 5 },
-            Token { term: Term::from_string("hell"), position: 6 },
-            Token { term: Term::from_string("he"), position: 7 },
-            Token { term: Term::from_string("sails"), position: 8 },
-            Token { term: Term::from_string("weilding"), position: 9 },
-            Token { term: Term::from_string("a"), position: 10 },
-            Token { term: Term::from_string("tankard"), position: 11 },
-            Token { term: Term::from_string("of"), position: 12 },
-            Token { term: Term::from_string("freshly"), position: 13 },
-            Token { term: Term::from_string("brewed"), position: 14 },
-            Token { term: Term::from_string("ale"), position: 15 }
+            AnalysisToken::new("Up", 1),
+            AnalysisToken::new("from", 2),
+            AnalysisToken::new("the", 3),
+            AnalysisToken::new("bowels", 4),
+            AnalysisToken::new("of", 5),
+            AnalysisToken::new("hell", 6),
+            AnalysisToken::new("he", 7),
+            AnalysisToken::new("sails", 8),
+            AnalysisToken::new("weilding", 9),
+            AnalysisToken::new("a", 10),
+            AnalysisToken::new("tankard", 11),
+            AnalysisToken::new("of", 12),
+            AnalysisToken::new("freshly", 13),
+            AnalysisToken::new("brewed", 14),
+            AnalysisToken::new("ale", 15)
         ]);
     }
 
     #[test]
     fn test_standard_tokenizer_cjk() {
         let tokenizer = StandardTokenizer::new("こんにちは、ハチ公！");
-        let tokens = tokenizer.collect::<Vec<Token>>();
+        let tokens = tokenizer.collect::<Vec<AnalysisToken>>();
 
         assert_eq!(tokens, vec![
-            Token { term: Term::from_string("こ"), position: 1 },
-            Token { term: Term::from_string("ん"), position: 2 },
-            Token { term: Term::from_string("に"), position: 3 },
-            Token { term: Term::from_string("ち"), position: 4 },
-            Token { term: Term::from_string("は"), position: 5 },
-            Token { term: Term::from_string("ハチ"), position: 6 },
-            Token { term: Term::from_string("公"), position: 7 },
+            AnalysisToken::new("こ", 1),
+            AnalysisToken::new("ん", 2),
+            AnalysisToken::new("に", 3),
+            AnalysisToken::new("ち", 4),
+            AnalysisToken::new("は", 5),
+            AnalysisToken::new("ハチ", 6),
+            AnalysisToken::new("公", 7),
         ]);
     }
 }
@@ -0,0 +1,436 @@
+//! Built-in benchmark harness
+//!
+//! Run with `rusticsearch bench [--docs N] [--queries N] [--words N] [--vocabulary N] [--seed N]`.
+//!
+//! Generates a corpus of synthetic documents, indexes them into a fresh store and
+//! then runs a set of query workloads against it. Throughput and latency percentiles
+//! are reported for each phase so performance regressions in the backend can be
+//! spotted. The corpus is generated from a seed so runs are repeatable.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use search::{Term, Token, Document, DocId};
+use search::document::FieldValue;
+use search::schema::{FieldId, FieldType, FIELD_INDEXED, FIELD_STORED};
+use search::query::Query;
+use search::collectors::top_score::TopScoreCollector;
+use search::backends::rocksdb::RocksDBStore;
+
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    /// Where to create the benchmark store. This is deleted before and after the run
+    pub path: PathBuf,
+
+    /// Number of documents to index
+    pub num_docs: usize,
+
+    /// Number of words in the body of each document
+    pub words_per_doc: usize,
+
+    /// Number of distinct words in the corpus
+    pub vocabulary_size: usize,
+
+    /// Number of queries to run in each query workload
+    pub num_queries: usize,
+
+    /// Seed for generating the corpus and queries
+    pub seed: u64,
+}
+
+
+impl Default for BenchConfig {
+    fn default() -> BenchConfig {
+        BenchConfig {
+            path: PathBuf::from("data/bench"),
+            num_docs: 10000,
+            words_per_doc: 100,
+            vocabulary_size: 10000,
+            num_queries: 1000,
+            seed: 42,
+        }
+    }
+}
+
+
+impl BenchConfig {
+    /// Reads the config from command line arguments (not including "bench")
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<BenchConfig, String> {
+        let mut config = BenchConfig::default();
+
+        while let Some(arg) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => return Err(format!("missing value for {}", arg)),
+            };
+
+            if arg == "--path" {
+                config.path = PathBuf::from(value);
+                continue;
+            }
+
+            let value = value.parse::<u64>().map_err(|_| format!("expected a number for {}, got {:?}", arg, value))?;
+
+            match arg.as_ref() {
+                "--docs" => config.num_docs = value as usize,
+                "--words" => config.words_per_doc = value as usize,
+                "--vocabulary" => config.vocabulary_size = value as usize,
+                "--queries" => config.num_queries = value as usize,
+                "--seed" => config.seed = value,
+                _ => return Err(format!("unrecognised argument {}", arg)),
+            }
+        }
+
+        if config.vocabulary_size == 0 {
+            return Err("vocabulary must contain at least one word".to_string());
+        }
+
+        Ok(config)
+    }
+}
+
+
+/// A small, deterministic random number generator (xorshift64*)
+///
+/// The corpus must be the same on every run so results can be compared.
+struct Rng(u64);
+
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // Zero is a fixed point of xorshift
+        Rng(if seed == 0 { 0x9E3779B97F4A7C15 } else { seed })
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Picks a word from the vocabulary
+    ///
+    /// Squaring a uniform number biases the pick towards low word numbers. This gives
+    /// a rough approximation of the skewed word frequencies found in real text.
+    fn word(&mut self, vocabulary_size: usize) -> usize {
+        let uniform = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        ((uniform * uniform) * vocabulary_size as f64) as usize
+    }
+}
+
+
+fn word_term(word: usize) -> Term {
+    Term::from_string(&format!("w{}", word))
+}
+
+
+/// Latencies of each operation in a workload
+#[derive(Debug)]
+pub struct WorkloadResult {
+    pub name: String,
+    pub total_time: Duration,
+    latencies: Vec<Duration>,
+}
+
+
+impl WorkloadResult {
+    fn new(name: &str, total_time: Duration, mut latencies: Vec<Duration>) -> WorkloadResult {
+        latencies.sort();
+
+        WorkloadResult {
+            name: name.to_string(),
+            total_time: total_time,
+            latencies: latencies,
+        }
+    }
+
+    pub fn operations(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// Operations per second
+    pub fn throughput(&self) -> f64 {
+        let secs = duration_to_secs(self.total_time);
+        if secs == 0.0 {
+            return 0.0;
+        }
+
+        self.latencies.len() as f64 / secs
+    }
+
+    /// Returns the latency that the given percentage of operations completed within
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::new(0, 0);
+        }
+
+        let rank = ((percentile / 100.0) * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.max(1).min(self.latencies.len()) - 1]
+    }
+}
+
+
+fn duration_to_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+
+fn duration_to_millis(duration: Duration) -> f64 {
+    duration_to_secs(duration) * 1000.0
+}
+
+
+fn time<F: FnMut() -> Result<(), String>>(mut f: F) -> Result<Duration, String> {
+    let start = Instant::now();
+    f()?;
+    Ok(start.elapsed())
+}
+
+
+struct Corpus {
+    title_field: FieldId,
+    body_field: FieldId,
+    id_field: FieldId,
+}
+
+
+impl Corpus {
+    fn make_document(&self, config: &BenchConfig, rng: &mut Rng, i: usize) -> Document {
+        let mut body_tokens = Vec::with_capacity(config.words_per_doc);
+        for position in 0..config.words_per_doc {
+            body_tokens.push(Token {
+                term: word_term(rng.word(config.vocabulary_size)),
+                position: position as u32 + 1,
+            });
+        }
+
+        let mut title_tokens = Vec::with_capacity(5);
+        for position in 0..5 {
+            title_tokens.push(Token {
+                term: word_term(rng.word(config.vocabulary_size)),
+                position: position + 1,
+            });
+        }
+
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(self.title_field, title_tokens.into());
+        indexed_fields.insert(self.body_field, body_tokens.into());
+
+        let mut stored_fields = FnvHashMap::default();
+        stored_fields.insert(self.id_field, FieldValue::Integer(i as i64));
+
+        Document {
+            key: i.to_string(),
+            boost: 1.0f32,
+            indexed_fields: indexed_fields,
+            stored_fields: stored_fields,
+            doc_values: FnvHashMap::default(),
+        }
+    }
+}
+
+
+fn run_query_workload<F>(name: &str, store: &RocksDBStore, config: &BenchConfig, mut make_query: F) -> Result<WorkloadResult, String>
+    where F: FnMut() -> Query
+{
+    let mut latencies = Vec::with_capacity(config.num_queries);
+    let total_time = time(|| {
+        for _ in 0..config.num_queries {
+            let query = make_query();
+
+            latencies.push(time(|| {
+                let mut collector = TopScoreCollector::new(10);
+                store.reader().search(&mut collector, &query)
+            })?);
+        }
+
+        Ok(())
+    })?;
+
+    Ok(WorkloadResult::new(name, total_time, latencies))
+}
+
+
+/// Runs all workloads, returning their results
+pub fn run(config: &BenchConfig) -> Result<Vec<WorkloadResult>, String> {
+    let _ = fs::remove_dir_all(&config.path);
+    let mut store = RocksDBStore::create(&config.path)?;
+    let corpus = Corpus {
+        title_field: store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).map_err(|e| format!("{:?}", e))?,
+        body_field: store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).map_err(|e| format!("{:?}", e))?,
+        id_field: store.add_field("id".to_string(), FieldType::I64, FIELD_STORED).map_err(|e| format!("{:?}", e))?,
+    };
+
+    let mut rng = Rng::new(config.seed);
+    let mut results = Vec::new();
+
+    // Generate the corpus up front so it isn't included in the timings
+    let docs = (0..config.num_docs).map(|i| corpus.make_document(config, &mut rng, i)).collect::<Vec<_>>();
+
+    // Indexing
+    let mut latencies = Vec::with_capacity(docs.len());
+    let total_time = time(|| {
+        for doc in docs.iter() {
+            latencies.push(time(|| store.insert_or_update_document(doc).map_err(|e| format!("{:?}", e)))?);
+        }
+
+        Ok(())
+    })?;
+    results.push(WorkloadResult::new("index", total_time, latencies));
+
+    // Merging
+    // Each insert creates a segment. Merge them in the same size groups the maintenance
+    // task would so the query workloads run against a realistic number of segments
+    let segments = store.get_segment_statistics()?.into_iter().map(|(segment, _)| segment).collect::<Vec<_>>();
+    let mut latencies = Vec::new();
+    let total_time = time(|| {
+        for chunk in segments.chunks(1000) {
+            let chunk = chunk.to_vec();
+            latencies.push(time(|| {
+                store.merge_segments(&chunk).map_err(|e| format!("{:?}", e))?;
+                store.purge_segments(&chunk).map_err(|e| format!("{:?}", e))
+            })?);
+        }
+
+        Ok(())
+    })?;
+    results.push(WorkloadResult::new("merge", total_time, latencies));
+
+    // Query workloads
+    results.push(run_query_workload("term", &store, config, || {
+        Query::term(corpus.body_field, word_term(rng.word(config.vocabulary_size)))
+    })?);
+
+    results.push(run_query_workload("conjunction", &store, config, || {
+        Query::Conjunction {
+            queries: vec![
+                Query::term(corpus.body_field, word_term(rng.word(config.vocabulary_size))),
+                Query::term(corpus.body_field, word_term(rng.word(config.vocabulary_size))),
+            ]
+        }
+    })?);
+
+    results.push(run_query_workload("disjunction", &store, config, || {
+        Query::Disjunction {
+            queries: vec![
+                Query::term(corpus.title_field, word_term(rng.word(config.vocabulary_size))).boost(2.0),
+                Query::term(corpus.body_field, word_term(rng.word(config.vocabulary_size))),
+                Query::term(corpus.body_field, word_term(rng.word(config.vocabulary_size))),
+            ]
+        }
+    })?);
+
+    // Positions
+    // Decodes the positions of a term in each of the top hits, as a phrase query would
+    let mut latencies = Vec::with_capacity(config.num_queries);
+    let total_time = time(|| {
+        for _ in 0..config.num_queries {
+            let term = word_term(rng.word(config.vocabulary_size));
+
+            latencies.push(time(|| {
+                let reader = store.reader();
+                let mut collector = TopScoreCollector::new(10);
+                reader.search(&mut collector, &Query::term(corpus.body_field, term.clone()))?;
+
+                for doc in collector.into_sorted_vec() {
+                    if let Some(positions) = reader.positions(corpus.body_field, &term, DocId::from_u64(doc.doc_id()))? {
+                        positions.iter().count();
+                    }
+                }
+
+                Ok(())
+            })?);
+        }
+
+        Ok(())
+    })?;
+    results.push(WorkloadResult::new("positions", total_time, latencies));
+
+    drop(store);
+    let _ = fs::remove_dir_all(&config.path);
+
+    Ok(results)
+}
+
+
+/// Prints the results as a table
+pub fn print_results(results: &[WorkloadResult]) {
+    println!("{:<12} {:>10} {:>12} {:>10} {:>10} {:>10} {:>10}", "workload", "ops", "ops/sec", "p50 ms", "p90 ms", "p99 ms", "max ms");
+
+    for result in results {
+        println!("{:<12} {:>10} {:>12.1} {:>10.3} {:>10.3} {:>10.3} {:>10.3}",
+                 result.name,
+                 result.operations(),
+                 result.throughput(),
+                 duration_to_millis(result.percentile(50.0)),
+                 duration_to_millis(result.percentile(90.0)),
+                 duration_to_millis(result.percentile(99.0)),
+                 duration_to_millis(result.percentile(100.0)));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::{BenchConfig, Rng, WorkloadResult, run};
+
+    #[test]
+    fn test_rng_is_deterministic() {
+        let mut a = Rng::new(1);
+        let mut b = Rng::new(1);
+
+        for _ in 0..100 {
+            let word = a.word(1000);
+            assert!(word < 1000);
+            assert_eq!(word, b.word(1000));
+        }
+    }
+
+    #[test]
+    fn test_percentile() {
+        let latencies = (1..101).map(|ms| Duration::from_millis(ms)).collect::<Vec<_>>();
+        let result = WorkloadResult::new("test", Duration::from_secs(1), latencies);
+
+        assert_eq!(result.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(result.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(result.percentile(100.0), Duration::from_millis(100));
+        assert_eq!(result.throughput(), 100.0);
+    }
+
+    #[test]
+    fn test_from_args() {
+        let args = vec!["--docs", "50", "--seed", "7"].into_iter().map(|arg| arg.to_string());
+        let config = BenchConfig::from_args(args).unwrap();
+
+        assert_eq!(config.num_docs, 50);
+        assert_eq!(config.seed, 7);
+
+        assert!(BenchConfig::from_args(vec!["--docs".to_string()].into_iter()).is_err());
+        assert!(BenchConfig::from_args(vec!["--foo".to_string(), "1".to_string()].into_iter()).is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let config = BenchConfig {
+            path: PathBuf::from("test_indices/test_bench_run"),
+            num_docs: 20,
+            words_per_doc: 10,
+            vocabulary_size: 50,
+            num_queries: 5,
+            seed: 1,
+        };
+
+        let results = run(&config).unwrap();
+        let names = results.iter().map(|result| result.name.as_ref()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["index", "merge", "term", "conjunction", "disjunction", "positions"]);
+        assert_eq!(results[0].operations(), 20);
+        assert_eq!(results[2].operations(), 5);
+    }
+}
@@ -0,0 +1,27 @@
+//! How this build of rusticsearch was made, recorded by "build.rs"
+
+/// The git commit that was built
+pub const BUILD_HASH: &'static str = env!("RUSTICSEARCH_BUILD_HASH");
+
+/// The output of "rustc --version"
+pub const RUSTC_VERSION: &'static str = env!("RUSTICSEARCH_RUSTC_VERSION");
+
+/// The version of the RocksDB library that's bundled
+pub const ROCKSDB_VERSION: &'static str = env!("RUSTICSEARCH_ROCKSDB_VERSION");
+
+/// The Cargo features that were enabled, separated by commas
+const FEATURES: &'static str = env!("RUSTICSEARCH_FEATURES");
+
+
+pub fn build_type() -> &'static str {
+    if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    }
+}
+
+
+pub fn features() -> Vec<&'static str> {
+    FEATURES.split(',').filter(|feature| !feature.is_empty()).collect()
+}
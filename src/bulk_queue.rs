@@ -0,0 +1,224 @@
+//! Bulk request admission control
+//!
+//! Bulk requests are still processed on the thread that received them, but each one
+//! must reserve space in a bounded queue first. When too many requests (or too many
+//! bytes) are in flight, new requests are rejected so clients can back off rather than
+//! piling more work onto an overloaded node.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+
+/// Maximum number of bulk requests that may be processed at once
+pub const DEFAULT_MAX_REQUESTS: usize = 50;
+
+/// Maximum total size of the bodies of bulk requests being processed at once
+pub const DEFAULT_MAX_BYTES: usize = 100 * 1024 * 1024;
+
+/// Bounds for the retry hint given to rejected clients
+const MIN_RETRY_AFTER_SECS: u64 = 1;
+const MAX_RETRY_AFTER_SECS: u64 = 60;
+
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BulkQueueStats {
+    /// Number of requests currently being processed
+    pub queue: usize,
+
+    /// Total size of the requests currently being processed
+    pub queue_size_in_bytes: usize,
+
+    /// Number of requests that have been accepted
+    pub completed: u64,
+
+    /// Number of requests that have been rejected
+    pub rejected: u64,
+
+    /// Estimated throughput, used to work out how long clients should wait before retrying
+    pub bytes_per_second: f64,
+}
+
+
+/// Returned when a request couldn't be added to the queue
+#[derive(Debug, PartialEq)]
+pub struct BulkQueueFull {
+    /// How long the client should wait before retrying
+    pub retry_after: Duration,
+}
+
+
+#[derive(Debug)]
+pub struct BulkQueue {
+    /// Can be changed while requests are being processed, see `set_max_requests`
+    max_requests: AtomicUsize,
+    max_bytes: usize,
+    stats: Mutex<BulkQueueStats>,
+}
+
+
+impl BulkQueue {
+    pub fn new(max_requests: usize, max_bytes: usize) -> BulkQueue {
+        BulkQueue {
+            max_requests: AtomicUsize::new(max_requests),
+            max_bytes: max_bytes,
+            stats: Mutex::new(BulkQueueStats::default()),
+        }
+    }
+
+    /// Changes how many requests can be processed at once
+    ///
+    /// Requests that are already being processed carry on, even if there are now more
+    /// of them than allowed.
+    pub fn set_max_requests(&self, max_requests: usize) {
+        self.max_requests.store(max_requests, Ordering::Relaxed);
+    }
+
+    /// Reserves space in the queue for a request of the given size
+    ///
+    /// The space is released when the returned permit is dropped. A single request
+    /// larger than the byte limit is let through when the queue is empty, otherwise
+    /// it could never be processed.
+    pub fn try_acquire(&self, size: usize) -> Result<BulkPermit, BulkQueueFull> {
+        let mut stats = self.stats.lock().unwrap();
+
+        let is_full = stats.queue >= self.max_requests.load(Ordering::Relaxed)
+            || (stats.queue > 0 && stats.queue_size_in_bytes + size > self.max_bytes);
+
+        if is_full {
+            stats.rejected += 1;
+
+            return Err(BulkQueueFull {
+                retry_after: retry_after(stats.queue_size_in_bytes, stats.bytes_per_second),
+            });
+        }
+
+        stats.queue += 1;
+        stats.queue_size_in_bytes += size;
+
+        Ok(BulkPermit {
+            queue: self,
+            size: size,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn stats(&self) -> BulkQueueStats {
+        *self.stats.lock().unwrap()
+    }
+
+    fn release(&self, size: usize, elapsed: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.queue -= 1;
+        stats.queue_size_in_bytes -= size;
+        stats.completed += 1;
+
+        // Update throughput estimate (exponential moving average)
+        let elapsed_secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+        if elapsed_secs > 0.0 {
+            let bytes_per_second = size as f64 / elapsed_secs;
+
+            if stats.bytes_per_second == 0.0 {
+                stats.bytes_per_second = bytes_per_second;
+            } else {
+                stats.bytes_per_second = stats.bytes_per_second * 0.8 + bytes_per_second * 0.2;
+            }
+        }
+    }
+}
+
+
+impl Default for BulkQueue {
+    fn default() -> BulkQueue {
+        BulkQueue::new(DEFAULT_MAX_REQUESTS, DEFAULT_MAX_BYTES)
+    }
+}
+
+
+/// Works out how long it should take for the queue to drain
+fn retry_after(queued_bytes: usize, bytes_per_second: f64) -> Duration {
+    let secs = if bytes_per_second > 0.0 {
+        (queued_bytes as f64 / bytes_per_second).ceil() as u64
+    } else {
+        MIN_RETRY_AFTER_SECS
+    };
+
+    Duration::from_secs(secs.max(MIN_RETRY_AFTER_SECS).min(MAX_RETRY_AFTER_SECS))
+}
+
+
+/// A reservation of space in the bulk queue, released when dropped
+#[derive(Debug)]
+pub struct BulkPermit<'a> {
+    queue: &'a BulkQueue,
+    size: usize,
+    started: Instant,
+}
+
+
+impl<'a> Drop for BulkPermit<'a> {
+    fn drop(&mut self) {
+        self.queue.release(self.size, self.started.elapsed());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{BulkQueue, retry_after};
+
+    #[test]
+    fn test_max_requests() {
+        let queue = BulkQueue::new(2, 1000);
+
+        let permit_1 = queue.try_acquire(10).unwrap();
+        let _permit_2 = queue.try_acquire(10).unwrap();
+        assert!(queue.try_acquire(10).is_err());
+
+        // Releasing a permit should let the next request in
+        drop(permit_1);
+        assert!(queue.try_acquire(10).is_ok());
+
+        let stats = queue.stats();
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(stats.completed, 2);
+    }
+
+    #[test]
+    fn test_set_max_requests() {
+        let queue = BulkQueue::new(1, 1000);
+
+        let _permit = queue.try_acquire(10).unwrap();
+        assert!(queue.try_acquire(10).is_err());
+
+        queue.set_max_requests(2);
+        assert!(queue.try_acquire(10).is_ok());
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let queue = BulkQueue::new(10, 100);
+
+        let _permit = queue.try_acquire(60).unwrap();
+        assert_eq!(queue.stats().queue_size_in_bytes, 60);
+        assert!(queue.try_acquire(60).is_err());
+        assert!(queue.try_acquire(40).is_ok());
+    }
+
+    #[test]
+    fn test_oversized_request_allowed_when_empty() {
+        let queue = BulkQueue::new(10, 100);
+
+        let _permit = queue.try_acquire(1000).unwrap();
+        assert!(queue.try_acquire(1).is_err());
+    }
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(retry_after(0, 0.0), Duration::from_secs(1));
+        assert_eq!(retry_after(5000, 1000.0), Duration::from_secs(5));
+        assert_eq!(retry_after(1000000, 1.0), Duration::from_secs(60));
+    }
+}
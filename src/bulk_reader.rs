@@ -0,0 +1,254 @@
+//! Reads the actions of a bulk request one at a time
+//!
+//! The body of a bulk request is newline delimited JSON. Each action is on a line of its
+//! own, followed by a line with the document for the actions that need one:
+//!
+//! ```text
+//! {"index": {"_index": "test", "_type": "doc", "_id": "1"}}
+//! {"title": "Hello"}
+//! {"delete": {"_index": "test", "_type": "doc", "_id": "2"}}
+//! ```
+//!
+//! Bodies can be hundreds of megabytes, so rather than reading the whole body before
+//! parsing it, each action and its document are read from the body when they're needed.
+//! Only the current line is held in memory.
+
+use std::io::{self, BufRead};
+
+use serde_json::{self, Map, Value as Json};
+
+
+/// The actions that are followed by a line with a document
+const ACTIONS_WITH_SOURCE: &'static [&'static str] = &["index", "create", "update"];
+
+
+#[derive(Debug)]
+pub enum BulkReadError {
+    Io(io::Error),
+
+    /// The line isn't valid JSON, lines are numbered from 1
+    InvalidJson(usize),
+
+    /// The line isn't an object with a single key, the name of the action, whose value is an object
+    InvalidAction(usize),
+
+    /// The action on the line must be followed by a document, but isn't
+    MissingSource(usize),
+}
+
+
+impl BulkReadError {
+    pub fn message(&self) -> String {
+        match *self {
+            BulkReadError::Io(ref error) => format!("Couldn't read request body: {}", error),
+            BulkReadError::InvalidJson(line_number) => format!("Couldn't parse JSON on line {}", line_number),
+            BulkReadError::InvalidAction(line_number) => format!("Malformed action on line {}, expected an object with a single key", line_number),
+            BulkReadError::MissingSource(line_number) => format!("The action on line {} must be followed by a document", line_number),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct BulkAction {
+    /// The name of the action, eg "index" or "delete"
+    pub name: String,
+
+    /// The parameters of the action, such as "_index" and "_id"
+    pub params: Map<String, Json>,
+
+    /// The document that follows the action, None for actions that don't have one
+    pub source: Option<Json>,
+
+    /// The size of the document's line, in bytes
+    pub source_size: usize,
+}
+
+
+/// Iterates over the actions of a bulk request
+///
+/// Reading stops at the first error, the actions before it have already been returned.
+pub struct BulkReader<R> {
+    reader: R,
+
+    /// The line that was read last, the buffer is reused for every line
+    line: String,
+    line_number: usize,
+    failed: bool,
+}
+
+
+impl<R: BufRead> BulkReader<R> {
+    pub fn new(reader: R) -> BulkReader<R> {
+        BulkReader {
+            reader: reader,
+            line: String::new(),
+            line_number: 0,
+            failed: false,
+        }
+    }
+
+    /// Reads the next line, returns false at the end of the body
+    fn read_line(&mut self) -> Result<bool, BulkReadError> {
+        self.line.clear();
+        if self.reader.read_line(&mut self.line).map_err(BulkReadError::Io)? == 0 {
+            return Ok(false);
+        }
+
+        self.line_number += 1;
+        Ok(true)
+    }
+
+    fn parse_line(&self) -> Result<Json, BulkReadError> {
+        serde_json::from_str(&self.line).map_err(|_| BulkReadError::InvalidJson(self.line_number))
+    }
+
+    fn read_action(&mut self) -> Result<Option<BulkAction>, BulkReadError> {
+        // Empty lines between actions are skipped
+        loop {
+            if !self.read_line()? {
+                return Ok(None);
+            }
+
+            if !self.line.trim().is_empty() {
+                break;
+            }
+        }
+
+        // Parse action line
+        let action_line_number = self.line_number;
+        let (name, params) = match self.parse_line()? {
+            Json::Object(mut object) => {
+                let name = match object.keys().next() {
+                    Some(name) if object.len() == 1 => name.clone(),
+                    _ => return Err(BulkReadError::InvalidAction(action_line_number)),
+                };
+
+                match object.remove(&name) {
+                    Some(Json::Object(params)) => (name, params),
+                    _ => return Err(BulkReadError::InvalidAction(action_line_number)),
+                }
+            }
+            _ => return Err(BulkReadError::InvalidAction(action_line_number)),
+        };
+
+        // Parse document line
+        let mut source = None;
+        let mut source_size = 0;
+        if ACTIONS_WITH_SOURCE.contains(&name.as_str()) {
+            if !self.read_line()? || self.line.trim().is_empty() {
+                return Err(BulkReadError::MissingSource(action_line_number));
+            }
+
+            source = Some(self.parse_line()?);
+            source_size = self.line.trim_right_matches(|c| c == '\r' || c == '\n').len();
+        }
+
+        Ok(Some(BulkAction {
+            name: name,
+            params: params,
+            source: source,
+            source_size: source_size,
+        }))
+    }
+}
+
+
+impl<R: BufRead> Iterator for BulkReader<R> {
+    type Item = Result<BulkAction, BulkReadError>;
+
+    fn next(&mut self) -> Option<Result<BulkAction, BulkReadError>> {
+        if self.failed {
+            return None;
+        }
+
+        match self.read_action() {
+            Ok(Some(action)) => Some(Ok(action)),
+            Ok(None) => None,
+            Err(error) => {
+                self.failed = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{BulkReader, BulkAction, BulkReadError};
+
+    fn read(body: &str) -> Vec<Result<BulkAction, BulkReadError>> {
+        BulkReader::new(body.as_bytes()).collect()
+    }
+
+    #[test]
+    fn test_read_actions() {
+        let actions = read("{\"index\": {\"_id\": \"1\"}}\n\
+                            {\"title\": \"Hello\"}\n\
+                            {\"delete\": {\"_id\": \"2\"}}\n\
+                            {\"update\": {\"_id\": \"3\"}}\n\
+                            {\"doc\": {\"title\": \"World\"}}\n");
+
+        let actions = actions.into_iter().map(|action| action.unwrap()).collect::<Vec<_>>();
+        assert_eq!(actions.len(), 3);
+
+        assert_eq!(actions[0].name, "index");
+        assert_eq!(actions[0].params.get("_id"), Some(&json!("1")));
+        assert_eq!(actions[0].source, Some(json!({"title": "Hello"})));
+        assert_eq!(actions[0].source_size, 18);
+
+        assert_eq!(actions[1].name, "delete");
+        assert_eq!(actions[1].source, None);
+        assert_eq!(actions[1].source_size, 0);
+
+        assert_eq!(actions[2].name, "update");
+        assert_eq!(actions[2].source, Some(json!({"doc": {"title": "World"}})));
+    }
+
+    #[test]
+    fn test_empty_lines_and_missing_final_newline() {
+        let actions = read("\n{\"create\": {\"_id\": \"1\"}}\r\n{\"title\": \"Hello\"}\r\n\n{\"delete\": {\"_id\": \"1\"}}");
+
+        let names = actions.into_iter().map(|action| action.unwrap().name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["create".to_string(), "delete".to_string()]);
+    }
+
+    #[test]
+    fn test_empty_body() {
+        assert!(read("").is_empty());
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let actions = read("{\"index\": {\"_id\": \"1\"}}\n{\"title\": \n{\"delete\": {\"_id\": \"1\"}}\n");
+
+        // Reading stops at the error
+        assert_eq!(actions.len(), 1);
+        match actions[0] {
+            Err(BulkReadError::InvalidJson(2)) => {}
+            ref result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_invalid_action() {
+        for body in &["[]\n", "{\"index\": {}, \"delete\": {}}\n", "{\"index\": \"1\"}\n"] {
+            match read(body)[0] {
+                Err(BulkReadError::InvalidAction(1)) => {}
+                ref result => panic!("unexpected result for {:?}: {:?}", body, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_source() {
+        let actions = read("{\"delete\": {\"_id\": \"1\"}}\n{\"index\": {\"_id\": \"1\"}}\n");
+
+        assert!(actions[0].is_ok());
+        match actions[1] {
+            Err(BulkReadError::MissingSource(2)) => {}
+            ref result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
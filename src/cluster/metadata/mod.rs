@@ -1,4 +1,6 @@
 pub mod name_registry;
+pub mod state;
+pub mod targets;
 
 use std::collections::HashMap;
 
@@ -7,6 +9,7 @@ use uuid::Uuid;
 use index::Index;
 
 use self::name_registry::NameRegistry;
+use self::state::ClusterStateStore;
 
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
@@ -24,6 +27,9 @@ impl IndexRef {
 pub struct ClusterMetadata {
     pub indices: HashMap<IndexRef, Index>,
     pub names: NameRegistry,
+
+    /// The persisted record of the indices and aliases above, change it before them
+    pub state: ClusterStateStore,
 }
 
 
@@ -32,6 +38,7 @@ impl ClusterMetadata {
         ClusterMetadata {
             indices: HashMap::new(),
             names: NameRegistry::new(),
+            state: ClusterStateStore::in_memory(),
         }
     }
 
@@ -41,4 +48,9 @@ impl ClusterMetadata {
 
         index_ref
     }
+
+    /// Finds an index by its canonical name
+    pub fn get_index(&self, name: &str) -> Option<&Index> {
+        self.names.find_canonical(name).and_then(|index_ref| self.indices.get(&index_ref))
+    }
 }
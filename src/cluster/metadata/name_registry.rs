@@ -1,5 +1,13 @@
+//! The names of indices and their aliases
+//!
+//! Changes are made to the registry while holding the cluster metadata's write lock.
+//! After each change, an immutable copy of the registry with the aliases of each index
+//! worked out is published to a `NameCache`, so requests can resolve names without
+//! walking the registry or waiting on the metadata lock.
+
 use std::collections::HashMap;
 use std::collections::hash_map::Iter as HashMapIter;
+use std::sync::{Arc, RwLock};
 
 use super::IndexRef;
 
@@ -14,9 +22,103 @@ enum Name {
 }
 
 
+/// A copy of the name registry from a point in time
+#[derive(Debug, Default)]
+pub struct ResolvedNames {
+    /// The indices that each name refers to
+    names: HashMap<String, Vec<IndexRef>>,
+
+    canonical_names: HashMap<String, IndexRef>,
+
+    /// The aliases that refer to each index, sorted by name
+    index_aliases: HashMap<IndexRef, Vec<String>>,
+}
+
+
+impl ResolvedNames {
+    fn build(names: &HashMap<String, Name>) -> ResolvedNames {
+        let mut resolved = ResolvedNames::default();
+
+        for (name, target) in names.iter() {
+            match *target {
+                Name::Canonical(index_ref) => {
+                    resolved.names.insert(name.clone(), vec![index_ref]);
+                    resolved.canonical_names.insert(name.clone(), index_ref);
+                }
+                Name::Alias(ref indices) => {
+                    resolved.names.insert(name.clone(), indices.clone());
+
+                    for index_ref in indices.iter() {
+                        resolved.index_aliases.entry(*index_ref).or_insert_with(Vec::new).push(name.clone());
+                    }
+                }
+            }
+        }
+
+        for aliases in resolved.index_aliases.values_mut() {
+            aliases.sort();
+        }
+
+        resolved
+    }
+
+    pub fn find(&self, selector: &str) -> &[IndexRef] {
+        match self.names.get(selector) {
+            Some(indices) => indices,
+            None => &[],
+        }
+    }
+
+    pub fn find_canonical(&self, name: &str) -> Option<IndexRef> {
+        self.canonical_names.get(name).cloned()
+    }
+
+    /// Every name, canonical names and aliases
+    pub fn names<'a>(&'a self) -> Box<Iterator<Item = &'a str> + 'a> {
+        Box::new(self.names.keys().map(|name| name.as_str()))
+    }
+
+    pub fn canonical_names<'a>(&'a self) -> Box<Iterator<Item = &'a str> + 'a> {
+        Box::new(self.canonical_names.keys().map(|name| name.as_str()))
+    }
+
+    /// The aliases that refer to an index
+    pub fn index_aliases(&self, index_ref: IndexRef) -> &[String] {
+        match self.index_aliases.get(&index_ref) {
+            Some(aliases) => aliases,
+            None => &[],
+        }
+    }
+}
+
+
+/// Shares the latest copy of the name registry between threads
+///
+/// The lock is only held to clone or replace the `Arc`, never while a name is being
+/// resolved.
+#[derive(Debug, Clone, Default)]
+pub struct NameCache {
+    current: Arc<RwLock<Arc<ResolvedNames>>>,
+}
+
+
+impl NameCache {
+    pub fn load(&self) -> Arc<ResolvedNames> {
+        self.current.read().unwrap().clone()
+    }
+
+    fn store(&self, resolved: ResolvedNames) {
+        *self.current.write().unwrap() = Arc::new(resolved);
+    }
+}
+
+
 #[derive(Debug)]
 pub struct NameRegistry {
     names: HashMap<String, Name>,
+
+    /// Updated after every change to the names
+    cache: NameCache,
 }
 
 
@@ -24,15 +126,31 @@ impl NameRegistry {
     pub fn new() -> NameRegistry {
         NameRegistry {
             names: HashMap::new(),
+            cache: NameCache::default(),
         }
     }
 
+    /// A handle to the copy of the registry that's kept up to date as it changes
+    pub fn cache(&self) -> NameCache {
+        self.cache.clone()
+    }
+
+    /// The current copy of the registry
+    pub fn resolved(&self) -> Arc<ResolvedNames> {
+        self.cache.load()
+    }
+
+    fn publish(&self) {
+        self.cache.store(ResolvedNames::build(&self.names));
+    }
+
     pub fn insert_canonical(&mut self, name: String, index_ref: IndexRef) -> Result<(), ()> {
         if let Some(_) = self.names.get(&name) {
             return Err(());
         }
 
         self.names.insert(name, Name::Canonical(index_ref));
+        self.publish();
         Ok(())
     }
 
@@ -46,6 +164,7 @@ impl NameRegistry {
         }
 
         self.names.remove(name);
+        self.publish();
         Ok(())
     }
 
@@ -55,6 +174,7 @@ impl NameRegistry {
         }
 
         self.names.insert(name, Name::Alias(indices));
+        self.publish();
         Ok(())
     }
 
@@ -65,6 +185,8 @@ impl NameRegistry {
         }
 
         let old_indices = self.names.insert(name, Name::Alias(indices));
+        self.publish();
+
         match old_indices {
             Some(Name::Alias(_)) => {
                  Ok(false)
@@ -105,6 +227,7 @@ impl NameRegistry {
             self.names.remove(name);
         }
 
+        self.publish();
         Ok(remove_alias)
     }
 
@@ -114,6 +237,7 @@ impl NameRegistry {
         }
 
         let alias = self.names.remove(name);
+        self.publish();
         Ok(alias.is_some())
     }
 
@@ -175,3 +299,40 @@ impl<'a> Iterator for IndexAliasesIterator<'a> {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use cluster::metadata::IndexRef;
+
+    use super::NameRegistry;
+
+    #[test]
+    fn test_cache_follows_changes() {
+        let mut names = NameRegistry::new();
+        let cache = names.cache();
+
+        let first = IndexRef(Uuid::new_v4());
+        let second = IndexRef(Uuid::new_v4());
+        names.insert_canonical("first".to_string(), first).unwrap();
+        names.insert_canonical("second".to_string(), second).unwrap();
+        names.insert_alias("both".to_string(), vec![first, second]).unwrap();
+        names.insert_alias("latest".to_string(), vec![second]).unwrap();
+
+        let resolved = cache.load();
+        assert_eq!(resolved.find("both"), &[first, second]);
+        assert_eq!(resolved.find_canonical("first"), Some(first));
+        assert_eq!(resolved.find_canonical("both"), None);
+        assert_eq!(resolved.index_aliases(second), &["both".to_string(), "latest".to_string()]);
+
+        names.delete_alias("both", second).unwrap();
+
+        // Copies that were already loaded don't change
+        assert_eq!(resolved.find("both"), &[first, second]);
+        assert_eq!(cache.load().find("both"), &[first]);
+        assert_eq!(cache.load().index_aliases(second), &["latest".to_string()]);
+        assert!(cache.load().find("missing").is_empty());
+    }
+}
@@ -0,0 +1,418 @@
+//! The cluster state, a single versioned document that describes the cluster
+//!
+//! It records which indices exist (by their canonical names), the aliases and the
+//! index templates. Indices that were attached from a directory outside of the data
+//! directory are recorded with their path and a "read_only" block. The mappings and settings of each index stay in the index's own
+//! metadata file.
+//!
+//! The state is only changed through transactions. Each one changes a copy of the
+//! state, which is saved as a whole (by writing a new file and renaming it over the
+//! old one) before it replaces the state in memory. So a change that touches several
+//! indices, like deleting them along with their aliases, is saved completely or not
+//! at all.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{self, Value as Json};
+use atomicwrites::{self, AtomicFile, AllowOverwrite};
+use uuid::Uuid;
+
+
+#[derive(Debug)]
+pub enum ClusterStateError {
+    /// The transaction returned an error, nothing was changed
+    Rejected(String),
+
+    /// The saved state couldn't be understood
+    InvalidState(String),
+
+    JsonEncoderError(serde_json::Error),
+    JsonParserError(serde_json::Error),
+    ReadError(io::Error),
+    WriteError(atomicwrites::Error<io::Error>),
+}
+
+
+impl From<ClusterStateError> for String {
+    fn from(e: ClusterStateError) -> String {
+        match e {
+            ClusterStateError::Rejected(reason) => reason,
+            ClusterStateError::InvalidState(reason) => format!("invalid cluster state: {}", reason),
+            ClusterStateError::JsonEncoderError(e) => format!("failed to save cluster state: {}", e),
+            ClusterStateError::JsonParserError(e) => format!("failed to load cluster state: {}", e),
+            ClusterStateError::ReadError(e) => format!("failed to load cluster state: {}", e),
+            ClusterStateError::WriteError(e) => format!("failed to save cluster state: {}", e),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ClusterState {
+    /// Incremented by each transaction that changes the state
+    pub version: u64,
+
+    /// The uuid of each index, by canonical name
+    pub indices: BTreeMap<String, Uuid>,
+
+    /// The directories of the indices that were attached, by canonical name. These
+    /// indices are read only and their files don't belong to the node
+    pub attached: BTreeMap<String, PathBuf>,
+
+    /// The canonical names of the indices of each alias, aliases always have at least one
+    pub aliases: BTreeMap<String, BTreeSet<String>>,
+
+    /// Index templates, kept as they were given
+    pub templates: BTreeMap<String, Json>,
+}
+
+
+impl ClusterState {
+    /// Adds an index, replacing any alias with the same name
+    ///
+    /// Returns true if an alias was replaced.
+    pub fn insert_index(&mut self, name: String, uuid: Uuid) -> Result<bool, String> {
+        if self.indices.contains_key(&name) {
+            return Err(format!("index already exists: {}", name));
+        }
+
+        let alias_replaced = self.aliases.remove(&name).is_some();
+        self.indices.insert(name, uuid);
+        Ok(alias_replaced)
+    }
+
+    /// Adds an index whose data is read from a directory outside of the data directory
+    pub fn attach_index(&mut self, name: String, uuid: Uuid, path: PathBuf) -> Result<bool, String> {
+        let alias_replaced = self.insert_index(name.clone(), uuid)?;
+        self.attached.insert(name, path);
+        Ok(alias_replaced)
+    }
+
+    /// Removes an index and takes it out of its aliases
+    ///
+    /// Returns the aliases that were removed because this was their last index.
+    pub fn remove_index(&mut self, name: &str) -> Result<Vec<String>, String> {
+        if self.indices.remove(name).is_none() {
+            return Err(format!("index not found: {}", name));
+        }
+
+        self.attached.remove(name);
+
+        let mut removed_aliases = Vec::new();
+        for (alias_name, indices) in self.aliases.iter_mut() {
+            if indices.remove(name) && indices.is_empty() {
+                removed_aliases.push(alias_name.clone());
+            }
+        }
+
+        for alias_name in removed_aliases.iter() {
+            self.aliases.remove(alias_name);
+        }
+
+        Ok(removed_aliases)
+    }
+
+    /// Points an alias at a set of indices, replacing the indices it had before
+    ///
+    /// Returns true if the alias is new.
+    pub fn set_alias(&mut self, name: String, indices: BTreeSet<String>) -> Result<bool, String> {
+        if self.indices.contains_key(&name) {
+            return Err(format!("an index is already called {}", name));
+        }
+
+        if indices.is_empty() {
+            return Err(format!("alias {} must have at least one index", name));
+        }
+
+        if let Some(missing_index) = indices.iter().find(|index_name| !self.indices.contains_key(*index_name)) {
+            return Err(format!("index not found: {}", missing_index));
+        }
+
+        Ok(self.aliases.insert(name, indices).is_none())
+    }
+
+    /// Removes an alias, returns true if it existed
+    pub fn remove_alias(&mut self, name: &str) -> bool {
+        self.aliases.remove(name).is_some()
+    }
+
+    /// Adds or replaces an index template, returns true if it's new
+    pub fn put_template(&mut self, name: String, template: Json) -> Result<bool, String> {
+        if !template.is_object() {
+            return Err(format!("template {} must be an object", name));
+        }
+
+        Ok(self.templates.insert(name, template).is_none())
+    }
+
+    /// Removes an index template, returns true if it existed
+    pub fn remove_template(&mut self, name: &str) -> bool {
+        self.templates.remove(name).is_some()
+    }
+
+    pub fn to_json(&self) -> Json {
+        let indices = self.indices.iter().map(|(name, uuid)| {
+            let index = match self.attached.get(name) {
+                Some(path) => json!({
+                    "uuid": uuid.to_string(),
+                    "path": path.to_string_lossy(),
+                    "blocks": {"read_only": true},
+                }),
+                None => json!({"uuid": uuid.to_string()}),
+            };
+
+            (name.clone(), index)
+        }).collect::<serde_json::Map<String, Json>>();
+
+        json!({
+            "version": self.version,
+            "indices": indices,
+            "aliases": self.aliases,
+            "templates": self.templates,
+        })
+    }
+
+    pub fn from_json(json: &Json) -> Result<ClusterState, String> {
+        let object = json.as_object().ok_or("expected an object")?;
+        let mut state = ClusterState::default();
+
+        state.version = match object.get("version") {
+            Some(version) => version.as_u64().ok_or("\"version\" must be a number")?,
+            None => 0,
+        };
+
+        if let Some(indices) = object.get("indices") {
+            for (name, index) in indices.as_object().ok_or("\"indices\" must be an object")?.iter() {
+                let uuid = index.get("uuid").and_then(|uuid| uuid.as_str()).and_then(|uuid| Uuid::parse_str(uuid).ok());
+                match uuid {
+                    Some(uuid) => state.indices.insert(name.clone(), uuid),
+                    None => return Err(format!("index {} must have a valid uuid", name)),
+                };
+
+                match index.get("path") {
+                    Some(&Json::String(ref path)) => {
+                        state.attached.insert(name.clone(), PathBuf::from(path));
+                    }
+                    Some(_) => return Err(format!("path of index {} must be a string", name)),
+                    None => {}
+                }
+            }
+        }
+
+        if let Some(aliases) = object.get("aliases") {
+            for (name, indices) in aliases.as_object().ok_or("\"aliases\" must be an object")?.iter() {
+                let indices = indices.as_array().ok_or_else(|| format!("indices of alias {} must be a list", name))?;
+                let indices = indices.iter().map(|index_name| {
+                    index_name.as_str().map(|index_name| index_name.to_string()).ok_or_else(|| format!("indices of alias {} must be strings", name))
+                }).collect::<Result<BTreeSet<String>, String>>()?;
+
+                state.aliases.insert(name.clone(), indices);
+            }
+        }
+
+        if let Some(templates) = object.get("templates") {
+            for (name, template) in templates.as_object().ok_or("\"templates\" must be an object")?.iter() {
+                state.templates.insert(name.clone(), template.clone());
+            }
+        }
+
+        Ok(state)
+    }
+}
+
+
+/// Holds the cluster state and saves each change to it
+#[derive(Debug)]
+pub struct ClusterStateStore {
+    /// Where the state is saved, or None to keep it in memory only
+    path: Option<PathBuf>,
+    state: ClusterState,
+}
+
+
+impl ClusterStateStore {
+    pub fn in_memory() -> ClusterStateStore {
+        ClusterStateStore {
+            path: None,
+            state: ClusterState::default(),
+        }
+    }
+
+    /// Loads the state saved at the path, a new state is started if there isn't one yet
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ClusterStateStore, ClusterStateError> {
+        let path = path.as_ref();
+
+        let state = match File::open(path) {
+            Ok(mut file) => {
+                let mut s = String::new();
+                file.read_to_string(&mut s).map_err(ClusterStateError::ReadError)?;
+
+                let json = serde_json::from_str(&s).map_err(ClusterStateError::JsonParserError)?;
+                ClusterState::from_json(&json).map_err(ClusterStateError::InvalidState)?
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => ClusterState::default(),
+            Err(e) => return Err(ClusterStateError::ReadError(e)),
+        };
+
+        Ok(ClusterStateStore {
+            path: Some(path.to_path_buf()),
+            state: state,
+        })
+    }
+
+    pub fn state(&self) -> &ClusterState {
+        &self.state
+    }
+
+    /// Changes the state in a transaction
+    ///
+    /// The function changes a copy of the state. If it returns an error or the new
+    /// state can't be saved, the state is left as it was.
+    pub fn update<T, F>(&mut self, f: F) -> Result<T, ClusterStateError> where F: FnOnce(&mut ClusterState) -> Result<T, String> {
+        let mut new_state = self.state.clone();
+        let result = f(&mut new_state).map_err(ClusterStateError::Rejected)?;
+
+        // Nothing to save if nothing changed
+        if new_state == self.state {
+            return Ok(result);
+        }
+
+        new_state.version = self.state.version + 1;
+        if let Some(ref path) = self.path {
+            ClusterStateStore::save(path, &new_state)?;
+        }
+
+        self.state = new_state;
+        Ok(result)
+    }
+
+    fn save(path: &Path, state: &ClusterState) -> Result<(), ClusterStateError> {
+        let s = serde_json::to_string(&state.to_json()).map_err(ClusterStateError::JsonEncoderError)?;
+
+        let file = AtomicFile::new(path, AllowOverwrite);
+        file.write(|f| f.write_all(s.as_bytes())).map_err(ClusterStateError::WriteError)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::fs::{create_dir_all, remove_file};
+    use std::path::PathBuf;
+
+    use uuid::Uuid;
+
+    use super::{ClusterStateStore, ClusterStateError};
+
+    fn index_names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_update_is_saved() {
+        let path = "test_indices/test_cluster_state_update_is_saved.json";
+        create_dir_all("test_indices").unwrap();
+        let _ = remove_file(path);
+
+        let uuid = Uuid::new_v4();
+        let mut store = ClusterStateStore::open(path).unwrap();
+        store.update(|state| {
+            state.insert_index("foo".to_string(), uuid)?;
+            state.set_alias("bar".to_string(), index_names(&["foo"]))?;
+            state.put_template("logs".to_string(), json!({"index_patterns": ["logs-*"]}))
+        }).unwrap();
+        assert_eq!(store.state().version, 1);
+
+        let loaded_store = ClusterStateStore::open(path).unwrap();
+        assert_eq!(loaded_store.state(), store.state());
+        assert_eq!(loaded_store.state().indices.get("foo"), Some(&uuid));
+        assert_eq!(loaded_store.state().aliases.get("bar"), Some(&index_names(&["foo"])));
+    }
+
+    #[test]
+    fn test_rejected_update_changes_nothing() {
+        let mut store = ClusterStateStore::in_memory();
+        store.update(|state| state.insert_index("foo".to_string(), Uuid::new_v4())).unwrap();
+        let state_before = store.state().clone();
+
+        // The index is added to the copy before the alias is rejected
+        let result = store.update(|state| {
+            state.insert_index("bar".to_string(), Uuid::new_v4())?;
+            state.set_alias("baz".to_string(), index_names(&["bar", "missing"]))
+        });
+
+        match result {
+            Err(ClusterStateError::Rejected(reason)) => assert_eq!(reason, "index not found: missing"),
+            result => panic!("expected the update to be rejected, got {:?}", result),
+        }
+        assert_eq!(*store.state(), state_before);
+    }
+
+    #[test]
+    fn test_update_without_changes_keeps_version() {
+        let mut store = ClusterStateStore::in_memory();
+        store.update(|state| state.insert_index("foo".to_string(), Uuid::new_v4())).unwrap();
+        store.update(|state| Ok(state.remove_alias("missing"))).unwrap();
+
+        assert_eq!(store.state().version, 1);
+    }
+
+    #[test]
+    fn test_remove_index_removes_it_from_aliases() {
+        let mut store = ClusterStateStore::in_memory();
+        store.update(|state| {
+            state.insert_index("foo".to_string(), Uuid::new_v4())?;
+            state.insert_index("bar".to_string(), Uuid::new_v4())?;
+            state.set_alias("both".to_string(), index_names(&["foo", "bar"]))?;
+            state.set_alias("just_foo".to_string(), index_names(&["foo"]))
+        }).unwrap();
+
+        let removed_aliases = store.update(|state| state.remove_index("foo")).unwrap();
+
+        assert_eq!(removed_aliases, vec!["just_foo".to_string()]);
+        assert_eq!(store.state().aliases.get("both"), Some(&index_names(&["bar"])));
+        assert_eq!(store.state().aliases.get("just_foo"), None);
+    }
+
+    #[test]
+    fn test_index_replaces_alias() {
+        let mut store = ClusterStateStore::in_memory();
+        store.update(|state| {
+            state.insert_index("foo".to_string(), Uuid::new_v4())?;
+            state.set_alias("bar".to_string(), index_names(&["foo"]))
+        }).unwrap();
+
+        assert_eq!(store.update(|state| state.insert_index("bar".to_string(), Uuid::new_v4())).unwrap(), true);
+        assert!(store.state().aliases.is_empty());
+
+        // But an alias can't replace an index
+        assert!(store.update(|state| state.set_alias("foo".to_string(), index_names(&["bar"]))).is_err());
+    }
+
+    #[test]
+    fn test_attached_index() {
+        let path = "test_indices/test_cluster_state_attached_index.json";
+        create_dir_all("test_indices").unwrap();
+        let _ = remove_file(path);
+
+        let mut store = ClusterStateStore::open(path).unwrap();
+        store.update(|state| {
+            state.insert_index("foo".to_string(), Uuid::new_v4())?;
+            state.attach_index("restored".to_string(), Uuid::new_v4(), PathBuf::from("/backups/restored"))
+        }).unwrap();
+
+        let loaded_store = ClusterStateStore::open(path).unwrap();
+        assert_eq!(loaded_store.state(), store.state());
+        assert_eq!(loaded_store.state().attached.get("restored"), Some(&PathBuf::from("/backups/restored")));
+        assert_eq!(loaded_store.state().attached.get("foo"), None);
+        assert_eq!(loaded_store.state().to_json()["indices"]["restored"]["blocks"], json!({"read_only": true}));
+
+        // Detaching is removing the index
+        store.update(|state| state.remove_index("restored")).unwrap();
+        assert!(store.state().attached.is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+//! Resolves the targets of requests into indices
+//!
+//! A target is a comma separated list of expressions, each of which may be:
+//!
+//! - The canonical name of an index
+//! - An alias, this resolves to every index that it refers to
+//! - A wildcard pattern (eg, "logs-*"), matched against index names and aliases
+//! - "_all" or "*", every index
+//! - A date math name (eg, "<logs-{now/d}>"), resolved into a name first
+//! - A wildcard pattern prefixed with "-", this removes the indices that it matches
+//!   from the ones matched by the expressions before it
+//!
+//! Names that don't exist are errors, wildcard patterns that don't match anything aren't.
+
+use chrono::{DateTime, Utc};
+
+use mapping::date_math::resolve_index_name;
+use query_parser::utils::matches_wildcard;
+
+use super::IndexRef;
+use super::name_registry::ResolvedNames;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetError {
+    /// There isn't an index or alias with this name
+    IndexNotFound(String),
+
+    /// The date math name couldn't be evaluated
+    InvalidDateMath(String, String),
+
+    /// The request can only use a single index, but the target resolved to more
+    MultipleIndices(String),
+}
+
+
+impl TargetError {
+    pub fn message(&self) -> String {
+        match *self {
+            TargetError::IndexNotFound(ref name) => format!("no such index [{}]", name),
+            TargetError::InvalidDateMath(ref name, ref error) => format!("invalid date math name [{}]: {}", name, error),
+            TargetError::MultipleIndices(ref target) => format!("[{}] resolves to more than one index, only one is allowed", target),
+        }
+    }
+}
+
+
+impl ResolvedNames {
+    /// Finds the indices that a target refers to, in the order that they're first named
+    pub fn resolve_targets(&self, target: &str, now: DateTime<Utc>) -> Result<Vec<IndexRef>, TargetError> {
+        let mut indices: Vec<IndexRef> = Vec::new();
+
+        for expression in target.split(',').map(|expression| expression.trim()).filter(|expression| !expression.is_empty()) {
+            // Exclusions
+            if expression.starts_with('-') && !indices.is_empty() {
+                let excluded = self.resolve_expression(&expression[1..], now)?;
+                indices.retain(|index_ref| !excluded.contains(index_ref));
+                continue;
+            }
+
+            for index_ref in self.resolve_expression(expression, now)? {
+                if !indices.contains(&index_ref) {
+                    indices.push(index_ref);
+                }
+            }
+        }
+
+        Ok(indices)
+    }
+
+    /// Finds the index that a target refers to, for requests that can only use one
+    ///
+    /// Aliases and patterns can be used as long as they resolve to a single index.
+    pub fn resolve_single_target(&self, target: &str, now: DateTime<Utc>) -> Result<IndexRef, TargetError> {
+        let indices = self.resolve_targets(target, now)?;
+
+        match indices.len() {
+            0 => Err(TargetError::IndexNotFound(target.to_string())),
+            1 => Ok(indices[0]),
+            _ => Err(TargetError::MultipleIndices(target.to_string())),
+        }
+    }
+
+    fn resolve_expression(&self, expression: &str, now: DateTime<Utc>) -> Result<Vec<IndexRef>, TargetError> {
+        let name = resolve_index_name(expression, now).map_err(|error| {
+            TargetError::InvalidDateMath(expression.to_string(), error.message())
+        })?;
+
+        // Every index
+        if name == "_all" || name == "*" {
+            let mut names = self.canonical_names().collect::<Vec<_>>();
+            names.sort();
+            return Ok(names.iter().filter_map(|name| self.find_canonical(name)).collect());
+        }
+
+        // Patterns, sorted by name so the order doesn't depend on the hash map
+        if name.contains('*') {
+            let mut names = self.names().filter(|candidate| matches_wildcard(&name, candidate)).collect::<Vec<_>>();
+            names.sort();
+
+            let mut indices: Vec<IndexRef> = Vec::new();
+            for name in names {
+                for index_ref in self.find(name) {
+                    if !indices.contains(index_ref) {
+                        indices.push(*index_ref);
+                    }
+                }
+            }
+
+            return Ok(indices);
+        }
+
+        let indices = self.find(&name);
+        if indices.is_empty() {
+            return Err(TargetError::IndexNotFound(name));
+        }
+
+        Ok(indices.to_vec())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use cluster::metadata::IndexRef;
+    use cluster::metadata::name_registry::NameRegistry;
+
+    use super::TargetError;
+
+    fn build_registry() -> (NameRegistry, IndexRef, IndexRef, IndexRef) {
+        let mut names = NameRegistry::new();
+
+        let logs_1 = IndexRef(Uuid::new_v4());
+        let logs_2 = IndexRef(Uuid::new_v4());
+        let users = IndexRef(Uuid::new_v4());
+        names.insert_canonical("logs-2017.01.01".to_string(), logs_1).unwrap();
+        names.insert_canonical("logs-2017.01.02".to_string(), logs_2).unwrap();
+        names.insert_canonical("users".to_string(), users).unwrap();
+        names.insert_alias("logs".to_string(), vec![logs_1, logs_2]).unwrap();
+        names.insert_alias("people".to_string(), vec![users]).unwrap();
+
+        (names, logs_1, logs_2, users)
+    }
+
+    #[test]
+    fn test_names_and_aliases() {
+        let (names, logs_1, logs_2, users) = build_registry();
+        let resolved = names.resolved();
+        let now = Utc::now();
+
+        assert_eq!(resolved.resolve_targets("users", now), Ok(vec![users]));
+        assert_eq!(resolved.resolve_targets("people", now), Ok(vec![users]));
+        assert_eq!(resolved.resolve_targets("logs", now), Ok(vec![logs_1, logs_2]));
+
+        // Indices named more than once are only given once
+        assert_eq!(resolved.resolve_targets("people,logs,users", now), Ok(vec![users, logs_1, logs_2]));
+
+        assert_eq!(resolved.resolve_targets("users,missing", now), Err(TargetError::IndexNotFound("missing".to_string())));
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let (names, logs_1, logs_2, users) = build_registry();
+        let resolved = names.resolved();
+        let now = Utc::now();
+
+        assert_eq!(resolved.resolve_targets("logs-*", now), Ok(vec![logs_1, logs_2]));
+        assert_eq!(resolved.resolve_targets("peo*", now), Ok(vec![users]));
+        assert_eq!(resolved.resolve_targets("_all", now), Ok(vec![logs_1, logs_2, users]));
+        assert_eq!(resolved.resolve_targets("*,-logs-*", now), Ok(vec![users]));
+        assert_eq!(resolved.resolve_targets("logs-*,-*.02", now), Ok(vec![logs_1]));
+
+        // Patterns that don't match anything aren't an error
+        assert_eq!(resolved.resolve_targets("missing-*", now), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_date_math() {
+        let (names, logs_1, _, _) = build_registry();
+        let resolved = names.resolved();
+        let now = Utc.ymd(2017, 1, 1).and_hms(12, 0, 0);
+
+        assert_eq!(resolved.resolve_targets("<logs-{now/d}>", now), Ok(vec![logs_1]));
+        assert!(resolved.resolve_targets("<logs-{now/d>", now).is_err());
+    }
+
+    #[test]
+    fn test_single_target() {
+        let (names, _, _, users) = build_registry();
+        let resolved = names.resolved();
+        let now = Utc::now();
+
+        assert_eq!(resolved.resolve_single_target("people", now), Ok(users));
+        assert_eq!(resolved.resolve_single_target("logs", now), Err(TargetError::MultipleIndices("logs".to_string())));
+        assert_eq!(resolved.resolve_single_target("missing-*", now), Err(TargetError::IndexNotFound("missing-*".to_string())));
+    }
+}
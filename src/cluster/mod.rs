@@ -1 +1,2 @@
 pub mod metadata;
+pub mod terms_lookup;
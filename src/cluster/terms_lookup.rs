@@ -0,0 +1,98 @@
+//! Looks up the terms of "terms" queries from documents in other indices
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use search::Term;
+use search::document::FieldValue;
+
+use query_parser::TermsLookup;
+use cluster::metadata::ClusterMetadata;
+use tenancy::Tenant;
+
+
+fn field_value_to_term(value: &FieldValue) -> Option<Term> {
+    match *value {
+        FieldValue::String(ref value) => Some(Term::from_string(value)),
+        FieldValue::Integer(value) => Some(Term::from_integer(value)),
+        FieldValue::Boolean(value) => Some(Term::from_boolean(value)),
+        FieldValue::DateTime(ref value) => Some(Term::from_datetime(value)),
+        FieldValue::Bytes(_) | FieldValue::Vector(_) | FieldValue::GeoPoint(_) | FieldValue::Float(_) => None,
+    }
+}
+
+
+/// Reads the terms from the stored values of documents
+///
+/// Each lookup is cached for the lifetime of this object, which is usually a single
+/// request, so a query that uses the same lookup more than once only reads the
+/// document once.
+#[derive(Debug)]
+pub struct ClusterTermsLookup<'a> {
+    cluster_metadata: &'a ClusterMetadata,
+
+    /// Documents can only be looked up in indices that this tenant can use
+    tenant: Option<&'a Tenant>,
+
+    cache: RefCell<HashMap<(String, String, String), Vec<Term>>>,
+}
+
+
+impl<'a> ClusterTermsLookup<'a> {
+    pub fn new(cluster_metadata: &'a ClusterMetadata, tenant: Option<&'a Tenant>) -> ClusterTermsLookup<'a> {
+        ClusterTermsLookup {
+            cluster_metadata: cluster_metadata,
+            tenant: tenant,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Checks if any terms have been looked up, results that depend on them can't be
+    /// cached against the epoch of the searched index alone
+    pub fn has_looked_up_terms(&self) -> bool {
+        !self.cache.borrow().is_empty()
+    }
+
+    fn read_terms(&self, index_name: &str, id: &str, path: &str) -> Result<Vec<Term>, String> {
+        if let Some(tenant) = self.tenant {
+            if !tenant.can_access_index(index_name) {
+                return Err(format!("index not allowed: {}", index_name));
+            }
+        }
+
+        let index = match self.cluster_metadata.names.find_canonical(index_name).and_then(|index_ref| self.cluster_metadata.indices.get(&index_ref)) {
+            Some(index) => index,
+            None => return Err(format!("index not found: {}", index_name)),
+        };
+
+        let store = index.store()?;
+        let index_reader = store.reader();
+
+        let doc_id = match index_reader.find_doc_id(id).map_err(|e| format!("failed to find document: {}", e))? {
+            Some(doc_id) => doc_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let field_id = match index_reader.schema().get_field_by_name(path) {
+            Some(field_id) => field_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let values = index_reader.doc_values(field_id, doc_id).map_err(|_| format!("failed to read field: {}", path))?;
+        Ok(values.iter().filter_map(field_value_to_term).collect())
+    }
+}
+
+
+impl<'a> TermsLookup for ClusterTermsLookup<'a> {
+    fn lookup_terms(&self, index: &str, id: &str, path: &str) -> Result<Vec<Term>, String> {
+        let key = (index.to_string(), id.to_string(), path.to_string());
+        if let Some(terms) = self.cache.borrow().get(&key) {
+            return Ok(terms.clone());
+        }
+
+        let terms = self.read_terms(index, id, path)?;
+        self.cache.borrow_mut().insert(key, terms.clone());
+        Ok(terms)
+    }
+}
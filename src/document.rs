@@ -2,12 +2,13 @@ use serde_json;
 use search::Document;
 use fnv::FnvHashMap;
 
-use mapping::{Mapping, MappingProperty, FieldValueError};
+use mapping::{Mapping, MappingProperty, FieldValueError, IndexPrefixes};
 
 
 #[derive(Debug)]
 pub struct DocumentSource<'a> {
     pub key: &'a str,
+    pub doc_type: &'a str,
     pub data: &'a serde_json::Map<String, serde_json::Value>,
 }
 
@@ -29,8 +30,23 @@ impl<'a> DocumentSource<'a> {
     pub fn prepare(&self, mapping: &Mapping) -> Result<Document, PrepareDocumentError> {
         let mut indexed_fields = FnvHashMap::default();
         let mut stored_fields = FnvHashMap::default();
+        let mut doc_values = FnvHashMap::default();
         let mut all_field_strings: Vec<String> = Vec::new();
 
+        // Document boost
+        let boost = match mapping.get_document_boost(self.data) {
+            Ok(boost) => boost,
+            Err(error) => {
+                let boost_field_name = mapping.boost_field.as_ref().map(|boost_field| boost_field.name.clone()).unwrap_or_default();
+
+                return Err(PrepareDocumentError::FieldValueError {
+                    value: self.data.get(&boost_field_name).cloned().unwrap_or(serde_json::Value::Null),
+                    field_name: boost_field_name,
+                    error: error,
+                });
+            }
+        };
+
         for (field_name, field_value) in self.data {
             if *field_value == serde_json::Value::Null {
                 // Treat null like a missing field
@@ -39,47 +55,97 @@ impl<'a> DocumentSource<'a> {
 
             match mapping.properties.get(field_name) {
                 Some(&MappingProperty::Field(ref field_mapping)) => {
-                    if field_mapping.is_indexed {
-                        let value = field_mapping.process_value_for_index(field_value);
-
-                        match value {
-                            Ok(Some(value)) => {
-                                // Copy the field's value into the _all field
-                                if field_mapping.is_in_all {
-                                    if let serde_json::Value::String(ref string) = *field_value {
-                                        all_field_strings.push(string.clone());
+                    // Multi-fields are given the same value as the field, to index it in other ways
+                    let mut field_mappings = vec![(field_name.clone(), field_mapping)];
+                    for multi_field_name in field_mapping.multi_fields.iter() {
+                        if let Some(&MappingProperty::Field(ref multi_field_mapping)) = mapping.properties.get(multi_field_name) {
+                            field_mappings.push((multi_field_name.clone(), multi_field_mapping));
+                        }
+                    }
+
+                    // As is the hidden sub field that the prefixes of its terms are indexed into
+                    if field_mapping.index_prefixes.is_some() {
+                        let prefix_field_name = IndexPrefixes::sub_field_name(field_name);
+                        if let Some(&MappingProperty::Field(ref prefix_field_mapping)) = mapping.properties.get(&prefix_field_name) {
+                            field_mappings.push((prefix_field_name, prefix_field_mapping));
+                        }
+                    }
+
+                    for (field_name, field_mapping) in field_mappings {
+                        if field_mapping.is_indexed {
+                            let value = field_mapping.process_value_for_index(field_value);
+
+                            match value {
+                                Ok(Some(value)) => {
+                                    // Copy the field's value into the _all field
+                                    if field_mapping.is_in_all {
+                                        if let serde_json::Value::String(ref string) = *field_value {
+                                            all_field_strings.push(string.clone());
+                                        }
                                     }
-                                }
 
-                                // Insert the field
-                                indexed_fields.insert(field_mapping.index_ref.unwrap(), value);
-                            }
-                            Ok(None) => {}
-                            Err(error) => {
-                                return Err(PrepareDocumentError::FieldValueError {
-                                    field_name: field_name.clone(),
-                                    value: field_value.clone(),
-                                    error: error,
-                                });
+                                    // Insert the field
+                                    indexed_fields.insert(field_mapping.index_ref.unwrap(), value);
+                                }
+                                Ok(None) => {}
+                                Err(error) => {
+                                    return Err(PrepareDocumentError::FieldValueError {
+                                        field_name: field_name.clone(),
+                                        value: field_value.clone(),
+                                        error: error,
+                                    });
+                                }
                             }
                         }
-                    }
 
-                    if field_mapping.is_stored {
-                        let value = field_mapping.process_value_for_store(field_value);
+                        if field_mapping.is_stored {
+                            let value = field_mapping.process_value_for_store(field_value);
 
-                        match value {
-                            Ok(Some(value)) => {
-                                // Insert the field
-                                stored_fields.insert(field_mapping.index_ref.unwrap(), value);
+                            match value {
+                                Ok(Some(value)) => {
+                                    // Insert the field
+                                    stored_fields.insert(field_mapping.index_ref.unwrap(), value);
+                                }
+                                Ok(None) => {}
+                                Err(error) => {
+                                    return Err(PrepareDocumentError::FieldValueError {
+                                        field_name: field_name.clone(),
+                                        value: field_value.clone(),
+                                        error: error,
+                                    });
+                                }
                             }
-                            Ok(None) => {}
-                            Err(error) => {
-                                return Err(PrepareDocumentError::FieldValueError {
-                                    field_name: field_name.clone(),
-                                    value: field_value.clone(),
-                                    error: error,
-                                });
+
+                            // Keep every value of arrays for sorting and aggregations. Collated
+                            // fields are sorted by their sort keys, so these are always kept
+                            if field_value.is_array() || field_mapping.collation.is_some() {
+                                match field_mapping.process_value_for_doc_values(field_value) {
+                                    Ok(values) => {
+                                        doc_values.insert(field_mapping.index_ref.unwrap(), values);
+                                    }
+                                    Err(error) => {
+                                        return Err(PrepareDocumentError::FieldValueError {
+                                            field_name: field_name.clone(),
+                                            value: field_value.clone(),
+                                            error: error,
+                                        });
+                                    }
+                                }
+                            }
+                        } else if field_mapping.data_type.is_numeric() || field_mapping.collation.is_some() {
+                            // Range filters read numeric fields from their doc values, so
+                            // these are kept even if the field isn't stored (as are sort keys)
+                            match field_mapping.process_value_for_doc_values(field_value) {
+                                Ok(values) => {
+                                    doc_values.insert(field_mapping.index_ref.unwrap(), values);
+                                }
+                                Err(error) => {
+                                    return Err(PrepareDocumentError::FieldValueError {
+                                        field_name: field_name.clone(),
+                                        value: field_value.clone(),
+                                        error: error,
+                                    });
+                                }
                             }
                         }
                     }
@@ -88,6 +154,11 @@ impl<'a> DocumentSource<'a> {
                     // TODO
                 }
                 None => {
+                    // The "_boost" field doesn't need to be mapped
+                    if mapping.boost_field.as_ref().map(|boost_field| boost_field.name == *field_name).unwrap_or(false) {
+                        continue;
+                    }
+
                     // No mapping found
                     return Err(PrepareDocumentError::FieldDoesntExist {
                         field_name: field_name.clone(),
@@ -118,10 +189,42 @@ impl<'a> DocumentSource<'a> {
             }
         }
 
+        // Insert _type and _id fields
+        for &(field_name, value) in &[("_type", self.doc_type), ("_id", self.key)] {
+            if let Some(&MappingProperty::Field(ref field_mapping)) = mapping.properties.get(field_name) {
+                let value_json = serde_json::Value::String(value.to_string());
+
+                if field_mapping.is_indexed {
+                    if let Ok(Some(value)) = field_mapping.process_value_for_index(&value_json) {
+                        indexed_fields.insert(field_mapping.index_ref.unwrap(), value);
+                    }
+                }
+
+                if field_mapping.is_stored {
+                    if let Ok(Some(value)) = field_mapping.process_value_for_store(&value_json) {
+                        stored_fields.insert(field_mapping.index_ref.unwrap(), value);
+                    }
+                }
+            }
+        }
+
+        // Insert _source field
+        if let Some(&MappingProperty::Field(ref field_mapping)) = mapping.properties.get("_source") {
+            if field_mapping.is_stored {
+                let source_json = serde_json::Value::String(serde_json::to_string(self.data).unwrap());
+
+                if let Ok(Some(value)) = field_mapping.process_value_for_store(&source_json) {
+                    stored_fields.insert(field_mapping.index_ref.unwrap(), value);
+                }
+            }
+        }
+
         Ok(Document {
             key: self.key.to_string(),
+            boost: boost as f32,
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            doc_values: doc_values,
         })
     }
 }
@@ -0,0 +1,198 @@
+//! Fetching documents by their id
+//!
+//! There are two ways to find a document. A realtime get looks its id up in the
+//! document index, which always points at the latest version of every document. A
+//! non-realtime get finds the document with a search on its "_id" field instead, so
+//! it's routed like a search (following the "preference") and sees exactly what a
+//! search on that copy of the index would see.
+//!
+//! Writes are searchable as soon as they're made, so both agree while an index only
+//! has its primary copy.
+
+use serde_json::{self, Value as Json};
+
+use search::Term;
+use search::document::{DocId, FieldValue};
+use search::query::Query;
+use search::schema::FieldId;
+use search::collectors::doc_ids::DocIdsCollector;
+use search::backends::rocksdb::RocksDBReader;
+
+use index::Index;
+use index::routing::SearchPreference;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GetMode {
+    /// Read the latest version of the document from the primary copy ("realtime=true")
+    Realtime,
+
+    /// Read the document as a search would see it ("realtime=false")
+    Search(SearchPreference),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetResult {
+    pub doc_type: Option<String>,
+    pub source: Option<Json>,
+}
+
+
+/// Finds a document by looking its key up in the document index
+pub fn find_doc_id_realtime(reader: &RocksDBReader, doc_key: &str) -> Result<Option<DocId>, String> {
+    reader.find_doc_id(doc_key).map_err(|e| format!("{}", e))
+}
+
+
+/// Finds a document with a search on its "_id" field
+pub fn find_doc_id_by_search(reader: &RocksDBReader, doc_key: &str) -> Result<Option<DocId>, String> {
+    let id_field = match reader.schema().get_field_by_name("_id") {
+        Some(id_field) => id_field,
+        None => return Ok(None),
+    };
+
+    let mut collector = DocIdsCollector::new();
+    reader.search(&mut collector, &Query::term(id_field, Term::from_string(doc_key)))?;
+
+    // Replaced versions of the document are deleted, so there's only ever one match
+    Ok(collector.into_vec().into_iter().next().map(DocId::from_u64))
+}
+
+
+fn read_string_field(reader: &RocksDBReader, field: Option<FieldId>, doc_id: DocId) -> Option<String> {
+    match field.map(|field| reader.read_stored_field(field, doc_id)) {
+        Some(Ok(Some(FieldValue::String(value)))) => Some(value),
+        _ => None,
+    }
+}
+
+
+/// Reads the type and source of a document that was found with one of the functions above
+pub fn read_document(reader: &RocksDBReader, doc_id: DocId) -> GetResult {
+    let type_field = reader.schema().get_field_by_name("_type");
+    let source_field = reader.schema().get_field_by_name("_source");
+
+    GetResult {
+        doc_type: read_string_field(reader, type_field, doc_id),
+        source: read_string_field(reader, source_field, doc_id).and_then(|source| serde_json::from_str(&source).ok()),
+    }
+}
+
+
+impl Index {
+    /// Fetches a document by its key, returns None if there isn't one
+    pub fn get_document(&self, doc_key: &str, mode: &GetMode) -> Result<Option<GetResult>, String> {
+        let store = match *mode {
+            GetMode::Realtime => self.store()?,
+            GetMode::Search(ref preference) => self.store_for_search(preference)?,
+        };
+        let reader = store.reader();
+
+        let doc_id = match *mode {
+            GetMode::Realtime => find_doc_id_realtime(&reader, doc_key)?,
+            GetMode::Search(_) => find_doc_id_by_search(&reader, doc_key)?,
+        };
+
+        Ok(doc_id.map(|doc_id| read_document(&reader, doc_id)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use fnv::FnvHashMap;
+
+    use search::{Term, Token, Document};
+    use search::document::FieldValue;
+    use search::schema::{FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::backends::rocksdb::RocksDBStore;
+
+    use super::{GetResult, find_doc_id_realtime, find_doc_id_by_search, read_document};
+
+    fn make_test_store(path: &str) -> RocksDBStore {
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        store.add_field("_id".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        store.add_field("_type".to_string(), FieldType::Text, FIELD_STORED).unwrap();
+        store.add_field("_source".to_string(), FieldType::Text, FIELD_STORED).unwrap();
+        store
+    }
+
+    fn insert_doc(store: &RocksDBStore, key: &str, source: &str) {
+        let id_field = store.reader().schema().get_field_by_name("_id").unwrap();
+        let type_field = store.reader().schema().get_field_by_name("_type").unwrap();
+        let source_field = store.reader().schema().get_field_by_name("_source").unwrap();
+
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(id_field, vec![Token { term: Term::from_string(key), position: 1 }].into());
+
+        let mut stored_fields = FnvHashMap::default();
+        stored_fields.insert(type_field, FieldValue::String("post".to_string()));
+        stored_fields.insert(source_field, FieldValue::String(source.to_string()));
+
+        store.insert_or_update_document(&Document {
+            key: key.to_string(),
+            boost: 1.0f32,
+            indexed_fields: indexed_fields,
+            stored_fields: stored_fields,
+            doc_values: FnvHashMap::default(),
+        }).unwrap();
+    }
+
+    #[test]
+    fn test_get() {
+        let store = make_test_store("test_indices/test_get");
+        insert_doc(&store, "1", "{\"title\": \"hello\"}");
+
+        let reader = store.reader();
+        let expected = GetResult {
+            doc_type: Some("post".to_string()),
+            source: Some(json!({"title": "hello"})),
+        };
+
+        let doc_id = find_doc_id_realtime(&reader, "1").unwrap().expect("realtime get didn't find the document");
+        assert_eq!(read_document(&reader, doc_id), expected);
+
+        let doc_id = find_doc_id_by_search(&reader, "1").unwrap().expect("search didn't find the document");
+        assert_eq!(read_document(&reader, doc_id), expected);
+
+        assert_eq!(find_doc_id_realtime(&reader, "2"), Ok(None));
+        assert_eq!(find_doc_id_by_search(&reader, "2"), Ok(None));
+    }
+
+    #[test]
+    fn test_get_after_update() {
+        let store = make_test_store("test_indices/test_get_after_update");
+        insert_doc(&store, "1", "{\"title\": \"hello\"}");
+
+        // A reader only sees the writes made before it was created
+        let old_reader = store.reader();
+        insert_doc(&store, "1", "{\"title\": \"updated\"}");
+        insert_doc(&store, "2", "{\"title\": \"new\"}");
+
+        let doc_id = find_doc_id_by_search(&old_reader, "1").unwrap().unwrap();
+        assert_eq!(read_document(&old_reader, doc_id).source, Some(json!({"title": "hello"})));
+        assert_eq!(find_doc_id_by_search(&old_reader, "2"), Ok(None));
+
+        // Both ways of finding the document see the latest version in a new reader
+        let reader = store.reader();
+        for doc_id in vec![find_doc_id_realtime(&reader, "1").unwrap().unwrap(), find_doc_id_by_search(&reader, "1").unwrap().unwrap()] {
+            assert_eq!(read_document(&reader, doc_id).source, Some(json!({"title": "updated"})));
+        }
+    }
+
+    #[test]
+    fn test_get_deleted() {
+        let store = make_test_store("test_indices/test_get_deleted");
+        insert_doc(&store, "1", "{\"title\": \"hello\"}");
+        store.remove_document_by_key("1").unwrap();
+
+        let reader = store.reader();
+        assert_eq!(find_doc_id_realtime(&reader, "1"), Ok(None));
+        assert_eq!(find_doc_id_by_search(&reader, "1"), Ok(None));
+    }
+}
@@ -0,0 +1,277 @@
+//! Shares the response of a search with identical searches running at the same time
+//!
+//! Dashboards often send the same query from many clients at once. The first of these
+//! requests runs the search (the "leader") and the others wait for it to finish and
+//! respond with the same serialised response.
+//!
+//! Searches are keyed the same way as the request cache, so they're only shared if
+//! they're for the same epoch of the index's store. If the leader can't share its
+//! response (eg, the search failed), the waiting requests run the search themselves.
+
+use std::sync::{Arc, Mutex, Condvar};
+
+use fnv::FnvHashMap;
+
+use index::request_cache::RequestCacheKey;
+
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InflightSearchStats {
+    /// The number of searches that were run and shared
+    pub leaders: u64,
+
+    /// The number of searches that were answered with another request's response
+    pub shared: u64,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum CallState {
+    Running,
+    Finished(Arc<String>),
+    Abandoned,
+}
+
+
+#[derive(Debug)]
+struct Call {
+    state: Mutex<CallState>,
+    finished: Condvar,
+}
+
+
+#[derive(Debug, Default)]
+struct InflightState {
+    calls: FnvHashMap<RequestCacheKey, Arc<Call>>,
+    stats: InflightSearchStats,
+}
+
+
+/// The outcome of joining a search
+#[derive(Debug)]
+pub enum Flight<'a> {
+    /// No identical search is running, this request must run it and share the response
+    Leader(FlightLeader<'a>),
+
+    /// An identical search finished while this request was waiting
+    Shared(Arc<String>),
+
+    /// An identical search was running but couldn't share its response
+    Unshared,
+}
+
+
+/// Held by the request that runs a search
+///
+/// Dropping this without calling `finish` lets any waiting requests run the search
+/// themselves.
+#[derive(Debug)]
+pub struct FlightLeader<'a> {
+    searches: &'a InflightSearches,
+    key: RequestCacheKey,
+    call: Arc<Call>,
+}
+
+
+impl<'a> FlightLeader<'a> {
+    /// Gives the serialised response to the requests waiting for it
+    pub fn finish(self, response: Arc<String>) {
+        self.complete(CallState::Finished(response));
+    }
+
+    fn complete(&self, state: CallState) {
+        // Stop new requests from waiting for this call before waking the ones that are
+        {
+            let mut inflight = self.searches.state.lock().unwrap();
+            let is_this_call = inflight.calls.get(&self.key).map(|call| Arc::ptr_eq(call, &self.call)).unwrap_or(false);
+            if is_this_call {
+                inflight.calls.remove(&self.key);
+            }
+        }
+
+        let mut call_state = self.call.state.lock().unwrap();
+        if *call_state == CallState::Running {
+            *call_state = state;
+        }
+        self.call.finished.notify_all();
+    }
+}
+
+
+impl<'a> Drop for FlightLeader<'a> {
+    fn drop(&mut self) {
+        self.complete(CallState::Abandoned);
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub struct InflightSearches {
+    state: Mutex<InflightState>,
+}
+
+
+impl InflightSearches {
+    pub fn new() -> InflightSearches {
+        InflightSearches::default()
+    }
+
+    /// Joins an identical search that's already running, or starts a new one
+    ///
+    /// If another request is running the search, this blocks until it has finished.
+    pub fn join(&self, key: RequestCacheKey) -> Flight {
+        let call = {
+            let mut inflight = self.state.lock().unwrap();
+            let existing_call = inflight.calls.get(&key).cloned();
+
+            match existing_call {
+                Some(call) => call,
+                None => {
+                    let call = Arc::new(Call {
+                        state: Mutex::new(CallState::Running),
+                        finished: Condvar::new(),
+                    });
+
+                    inflight.calls.insert(key.clone(), call.clone());
+                    inflight.stats.leaders += 1;
+
+                    return Flight::Leader(FlightLeader {
+                        searches: self,
+                        key: key,
+                        call: call,
+                    });
+                }
+            }
+        };
+
+        // Wait for the leader to finish
+        let mut call_state = call.state.lock().unwrap();
+        while *call_state == CallState::Running {
+            call_state = call.finished.wait(call_state).unwrap();
+        }
+
+        match *call_state {
+            CallState::Finished(ref response) => {
+                self.state.lock().unwrap().stats.shared += 1;
+                Flight::Shared(response.clone())
+            }
+            _ => Flight::Unshared,
+        }
+    }
+
+    pub fn stats(&self) -> InflightSearchStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use index::request_cache::RequestCacheKey;
+
+    use super::{InflightSearches, Flight};
+
+    fn make_key(index_id: Uuid, epoch: u64) -> RequestCacheKey {
+        RequestCacheKey {
+            index_id: index_id,
+            epoch: epoch,
+            request: "/test/_search {}".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_waiting_request_gets_shared_response() {
+        let searches = Arc::new(InflightSearches::new());
+        let index_id = Uuid::new_v4();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let leader = match searches.join(make_key(index_id, 1)) {
+            Flight::Leader(leader) => leader,
+            _ => panic!("expected to lead the search"),
+        };
+
+        let follower = {
+            let searches = searches.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                match searches.join(make_key(index_id, 1)) {
+                    Flight::Shared(response) => (*response).clone(),
+                    _ => panic!("expected a shared response"),
+                }
+            })
+        };
+
+        // Give the follower time to start waiting
+        barrier.wait();
+        thread::sleep(Duration::from_millis(100));
+        leader.finish(Arc::new("{\"hits\":{}}".to_string()));
+
+        assert_eq!(follower.join().unwrap(), "{\"hits\":{}}");
+        assert_eq!(searches.stats().shared, 1);
+    }
+
+    #[test]
+    fn test_finished_searches_are_not_shared() {
+        let searches = InflightSearches::new();
+        let index_id = Uuid::new_v4();
+
+        match searches.join(make_key(index_id, 1)) {
+            Flight::Leader(leader) => leader.finish(Arc::new("{}".to_string())),
+            _ => panic!("expected to lead the search"),
+        }
+
+        // The search has finished so the next request runs it again
+        assert!(match searches.join(make_key(index_id, 1)) {
+            Flight::Leader(_) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_different_epochs_are_not_shared() {
+        let searches = InflightSearches::new();
+        let index_id = Uuid::new_v4();
+
+        let _leader = searches.join(make_key(index_id, 1));
+
+        assert!(match searches.join(make_key(index_id, 2)) {
+            Flight::Leader(_) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_abandoned_search() {
+        let searches = Arc::new(InflightSearches::new());
+        let index_id = Uuid::new_v4();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let leader = searches.join(make_key(index_id, 1));
+
+        let follower = {
+            let searches = searches.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                match searches.join(make_key(index_id, 1)) {
+                    Flight::Unshared => true,
+                    _ => false,
+                }
+            })
+        };
+
+        // The leader is dropped without a response, so the follower runs the search itself
+        barrier.wait();
+        thread::sleep(Duration::from_millis(100));
+        drop(leader);
+
+        assert!(follower.join().unwrap());
+    }
+}
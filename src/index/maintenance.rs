@@ -1,83 +1,293 @@
+//! Background maintenance of indices (purging and merging segments)
+//!
+//! Each index has its own schedule. An index that still has work to do after a run is
+//! visited again soon, and one that failed backs off exponentially. The intervals are
+//! jittered so the indices of a node don't all run at the same time.
+
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::any::Any;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
 use index::Index;
+use index::merge_policy::plan_merge;
+
+
+/// How often an index with nothing to do is maintained, in milliseconds
+pub const MAINTENANCE_INTERVAL_MS: u64 = 1000;
+
+/// How soon an index that has work remaining is maintained again, in milliseconds
+pub const MAINTENANCE_BUSY_INTERVAL_MS: u64 = 100;
+
+/// The longest an index is left after failing, in milliseconds
+pub const MAINTENANCE_MAX_BACKOFF_MS: u64 = 60000;
+
+/// Intervals are moved by up to this fraction either way
+const MAINTENANCE_JITTER: f64 = 0.2;
+
+
+/// What a maintenance run did
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MaintenanceReport {
+    /// The number of deferred purges that were run
+    pub purges: u64,
+
+    pub segments_merged: u64,
+
+    /// There are purges waiting for readers to finish, or more segments that could be merged
+    pub work_remaining: bool,
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceStats {
+    pub runs: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    pub purges: u64,
+    pub segments_merged: u64,
+    pub total_time_in_millis: u64,
+
+    /// The delay that was chosen after the last run
+    pub current_interval_in_millis: u64,
+
+    pub last_error: Option<String>,
+}
+
+
+#[derive(Debug, Default)]
+struct MaintenanceState {
+    /// None until the first run, which happens straight away
+    next_run: Option<Instant>,
+
+    is_running: bool,
+    stats: MaintenanceStats,
+}
+
+
+/// Decides when an index is next maintained and records how the runs went
+#[derive(Debug, Default)]
+pub struct MaintenanceScheduler {
+    state: Mutex<MaintenanceState>,
+}
+
+
+impl MaintenanceScheduler {
+    pub fn new() -> MaintenanceScheduler {
+        MaintenanceScheduler::default()
+    }
+
+    pub fn stats(&self) -> MaintenanceStats {
+        self.state.lock().unwrap().stats.clone()
+    }
+
+    /// Marks the task as running if it's due, returns false if it isn't
+    fn start(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.is_running || state.next_run.map(|next_run| now < next_run).unwrap_or(false) {
+            return false;
+        }
+
+        state.is_running = true;
+        true
+    }
+
+    /// Records the result of a run and schedules the next one
+    fn finish(&self, started: Instant, result: &Result<MaintenanceReport, String>) {
+        let mut state = self.state.lock().unwrap();
+        state.is_running = false;
+        state.stats.runs += 1;
+
+        let elapsed = started.elapsed();
+        state.stats.total_time_in_millis += elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1000000;
+
+        let work_remaining = match *result {
+            Ok(ref report) => {
+                state.stats.consecutive_failures = 0;
+                state.stats.purges += report.purges;
+                state.stats.segments_merged += report.segments_merged;
+                report.work_remaining
+            }
+            Err(ref error) => {
+                state.stats.failures += 1;
+                state.stats.consecutive_failures += 1;
+                state.stats.last_error = Some(error.clone());
+                false
+            }
+        };
+
+        let interval = jitter(next_interval(state.stats.consecutive_failures, work_remaining));
+        state.stats.current_interval_in_millis = interval;
+        state.next_run = Some(Instant::now() + Duration::from_millis(interval));
+    }
+}
+
+
+/// The delay before the next run in milliseconds, before jitter is added
+fn next_interval(consecutive_failures: u32, work_remaining: bool) -> u64 {
+    if consecutive_failures > 0 {
+        // Double the interval for each failure in a row
+        let shift = cmp::min(consecutive_failures - 1, 16);
+        return cmp::min(MAINTENANCE_INTERVAL_MS << shift, MAINTENANCE_MAX_BACKOFF_MS);
+    }
+
+    if work_remaining {
+        MAINTENANCE_BUSY_INTERVAL_MS
+    } else {
+        MAINTENANCE_INTERVAL_MS
+    }
+}
+
+
+/// Moves an interval by a small amount either way
+fn jitter(interval: u64) -> u64 {
+    let spread = (interval as f64 * MAINTENANCE_JITTER) as u64;
+    if spread == 0 {
+        return interval;
+    }
+
+    // This only needs to spread the indices out, so the clock is random enough
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.subsec_nanos()).unwrap_or(0);
+    interval - spread + nanos as u64 % (spread * 2 + 1)
+}
+
+
+fn panic_message(error: &Box<Any + Send>) -> String {
+    if let Some(message) = error.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+
+    if let Some(message) = error.downcast_ref::<String>() {
+        return message.clone();
+    }
+
+    "unknown error".to_string()
+}
 
 
 impl Index {
+    /// Runs the maintenance task if the index's schedule says it's due
+    ///
+    /// Returns None if it wasn't due. This must be called periodically by a background
+    /// thread, a panic in the task is caught and returned as an error.
+    pub fn run_scheduled_maintenance(&self) -> Option<Result<MaintenanceReport, String>> {
+        let now = Instant::now();
+        if !self.maintenance.start(now) {
+            return None;
+        }
+
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| self.run_maintenance_task())) {
+            Ok(result) => result,
+            Err(error) => Err(format!("maintenance task panicked: {}", panic_message(&error))),
+        };
+
+        self.maintenance.finish(now, &result);
+        Some(result)
+    }
+
+    pub fn maintenance_stats(&self) -> MaintenanceStats {
+        self.maintenance.stats()
+    }
+
     /// Run a maintenance task on the index
-    /// This must be run periodically by a background thread. It is not currently thread-safe
-    pub fn run_maintenance_task(&self) -> Result<(), String> {
-        let segment_stats = self.store.get_segment_statistics()?;
+    /// This is not thread-safe, use `run_scheduled_maintenance` which makes sure only one runs at a time
+    pub fn run_maintenance_task(&self) -> Result<MaintenanceReport, String> {
+        let mut report = MaintenanceReport::default();
+
+        // Read-only indices can't be changed, even by merges
+        if self.is_read_only() {
+            return Ok(report);
+        }
+
+        // Closed indices haven't changed since they were last open, so they're left closed
+        let store = match self.store_if_open() {
+            Some(store) => store,
+            None => return Ok(report),
+        };
+
+        // Purge the segments of earlier merges that were still being read at the time
+        report.purges = store.purge_deferred_segments()? as u64;
+
+        let segment_stats = store.get_segment_statistics()?;
 
         // TODO: Deactivate segments with 100% deletions
         // TODO: Vacuum segments with many deletions
 
+        let segment_ids = plan_merge(&segment_stats).segments_to_merge();
+        if segment_ids.is_empty() {
+            // No point in merging these
+            report.work_remaining = store.deferred_purge_count() > 0;
+            return Ok(report);
+        }
+
         // Merge segments
+        store.merge_segments(&segment_ids)?;
+        store.purge_segments(&segment_ids)?;
+        report.segments_merged = segment_ids.len() as u64;
 
-        // Firstly we classify each active segment into one of 5 groups (based on the number of
-        // total documents they have):
-        // Group 1: 1 - 9 docs
-        // Group 2: 10 - 99 docs
-        // Group 3: 100 - 999 docs
-        // Group 4: 1000 - 9999 docs
-        // Group 5: 10000 - 65536 docs
-
-        // The group with the most active segments can perform a merge. A merge can be done on
-        // between 5 - 1000 segments at a time. The smallest segments get merged first.
-
-        let mut segments_g1 = Vec::new();
-        let mut segments_g2 = Vec::new();
-        let mut segments_g3 = Vec::new();
-        let mut segments_g4 = Vec::new();
-        let mut segments_g5 = Vec::new();
-
-        for (segment, stats) in segment_stats {
-            match stats.total_docs() {
-                1 ... 9 => segments_g1.push((segment, stats)),
-                10 ... 99 => segments_g2.push((segment, stats)),
-                100 ... 999 => segments_g3.push((segment, stats)),
-                1000 ... 9999 => segments_g4.push((segment, stats)),
-                10000 ... 65536 => segments_g5.push((segment, stats)),
-                _ => {},
-            }
-        }
+        // Another group may be ready to merge now, and the purge may have been deferred
+        report.work_remaining = true;
+
+        Ok(report)
+    }
+}
 
-        // Now sort the groups by length in ascending order
-        let mut segments_grouped = vec![segments_g1, segments_g2, segments_g3, segments_g4, segments_g5];
-        segments_grouped.sort_by_key(|group| group.len());
 
-        // The group with the most segments is our merge candidate. Check that it has above the
-        // minimum number of documents to start a merge and truncate it to be less than the maximum.
-        let mut group_to_merge = segments_grouped.pop().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::{next_interval, jitter, MaintenanceScheduler, MaintenanceReport};
+    use super::{MAINTENANCE_INTERVAL_MS, MAINTENANCE_BUSY_INTERVAL_MS, MAINTENANCE_MAX_BACKOFF_MS};
 
-        if group_to_merge.len() < 3 {
-            // No point in merging these
-            return Ok(());
+    use std::time::Instant;
+
+    #[test]
+    fn test_next_interval() {
+        assert_eq!(next_interval(0, false), MAINTENANCE_INTERVAL_MS);
+        assert_eq!(next_interval(0, true), MAINTENANCE_BUSY_INTERVAL_MS);
+
+        // Failures back off exponentially, up to a limit
+        assert_eq!(next_interval(1, true), MAINTENANCE_INTERVAL_MS);
+        assert_eq!(next_interval(3, false), MAINTENANCE_INTERVAL_MS * 4);
+        assert_eq!(next_interval(100, false), MAINTENANCE_MAX_BACKOFF_MS);
+    }
+
+    #[test]
+    fn test_jitter() {
+        for _ in 0..100 {
+            let interval = jitter(1000);
+            assert!(interval >= 800 && interval <= 1200, "{} is out of range", interval);
         }
+    }
 
-        // Now we've found a group of segments to merge, we must check that all the docs will fit in a
-        // single segment. If not, we choose the largest sub-group of segments to merge that fills the
-        // quota as much as possible
+    #[test]
+    fn test_scheduler() {
+        let scheduler = MaintenanceScheduler::new();
 
-        let mut current_doc_count: u32 = 0;
-        let mut segment_ids = Vec::new();
+        // The first run happens straight away, but only one can run at a time
+        let now = Instant::now();
+        assert!(scheduler.start(now));
+        assert!(!scheduler.start(now));
 
-        // Sort segments total_docs in descending order
-        // TODO: Check that this is descending order
-        group_to_merge.sort_by_key(|&(_, ref stats)| -stats.total_docs());
+        scheduler.finish(now, &Err("failed".to_string()));
+        scheduler.finish(now, &Err("failed again".to_string()));
 
-        for (segment, stats) in group_to_merge {
-            if current_doc_count + stats.total_docs() as u32 > 65536 {
-                // No space for this segment
-                continue;
-            }
+        let stats = scheduler.stats();
+        assert_eq!(stats.runs, 2);
+        assert_eq!(stats.failures, 2);
+        assert_eq!(stats.consecutive_failures, 2);
+        assert_eq!(stats.last_error, Some("failed again".to_string()));
+        assert!(stats.current_interval_in_millis >= MAINTENANCE_INTERVAL_MS * 2 * 8 / 10);
 
-            segment_ids.push(segment);
-            current_doc_count += stats.total_docs() as u32;
-        }
+        // The index is backing off, so it's not due yet
+        assert!(!scheduler.start(Instant::now()));
 
-        // Merge segments
-        self.store.merge_segments(&segment_ids)?;
-        self.store.purge_segments(&segment_ids)?;
+        // A successful run resets the backoff
+        scheduler.finish(now, &Ok(MaintenanceReport { purges: 1, segments_merged: 5, work_remaining: true }));
 
-        Ok(())
+        let stats = scheduler.stats();
+        assert_eq!(stats.consecutive_failures, 0);
+        assert_eq!(stats.segments_merged, 5);
+        assert!(stats.current_interval_in_millis <= MAINTENANCE_BUSY_INTERVAL_MS * 12 / 10);
     }
 }
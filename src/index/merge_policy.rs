@@ -0,0 +1,243 @@
+//! Chooses which segments the maintenance task merges
+//!
+//! Each active segment is put into a tier by its total number of documents:
+//!
+//! Tier 1: 1 - 9 docs
+//! Tier 2: 10 - 99 docs
+//! Tier 3: 100 - 999 docs
+//! Tier 4: 1000 - 9999 docs
+//! Tier 5: 10000 - 65536 docs
+//!
+//! The tier with the most segments is merged, as long as it has at least three of them.
+//! The largest segments of the tier are taken first until the merged segment would
+//! overflow.
+//!
+//! The policy only reads segment statistics, so it can also be used to explain what the
+//! next merge would be without running it.
+
+use search::backends::rocksdb::SegmentStatistics;
+
+use index::Index;
+
+
+/// A tier must have at least this many segments to be merged
+pub const MIN_SEGMENTS_TO_MERGE: usize = 3;
+
+/// The most documents that a segment can have, including deleted ones
+pub const MAX_SEGMENT_DOCS: i64 = 65536;
+
+/// The smallest and largest number of documents of the segments in each tier
+const TIERS: [(i64, i64); 5] = [(1, 9), (10, 99), (100, 999), (1000, 9999), (10000, MAX_SEGMENT_DOCS)];
+
+
+/// Why a segment is, or isn't, in the next merge
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeDecision {
+    /// The segment will be merged
+    Merge,
+
+    /// The segment is empty or too large to be put into a tier
+    NoTier,
+
+    /// Another tier has more segments
+    OtherTierChosen,
+
+    /// The tier doesn't have enough segments to be worth merging
+    TooFewSegments,
+
+    /// The merged segment would have too many documents if this was added
+    WouldOverflow,
+}
+
+
+impl MergeDecision {
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            MergeDecision::Merge => "merge",
+            MergeDecision::NoTier => "no_tier",
+            MergeDecision::OtherTierChosen => "other_tier_chosen",
+            MergeDecision::TooFewSegments => "too_few_segments",
+            MergeDecision::WouldOverflow => "would_overflow",
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentMergeExplanation {
+    pub segment: u32,
+    pub total_docs: i64,
+    pub deleted_docs: i64,
+
+    /// The tier of the segment, numbered from 1
+    pub tier: Option<usize>,
+
+    pub decision: MergeDecision,
+}
+
+
+/// What the next merge would be
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergePlan {
+    /// The tier that was chosen, numbered from 1. None if no merge would be done
+    pub tier: Option<usize>,
+
+    /// Every active segment, in the order they were considered
+    pub segments: Vec<SegmentMergeExplanation>,
+}
+
+
+impl MergePlan {
+    /// The segments that will be merged, this is empty if no merge would be done
+    pub fn segments_to_merge(&self) -> Vec<u32> {
+        self.segments.iter()
+            .filter(|segment| segment.decision == MergeDecision::Merge)
+            .map(|segment| segment.segment)
+            .collect()
+    }
+
+    /// The number of documents that the merged segment would have
+    ///
+    /// Deleted documents are carried over by the merge, so these are counted too.
+    pub fn estimated_total_docs(&self) -> i64 {
+        self.segments.iter()
+            .filter(|segment| segment.decision == MergeDecision::Merge)
+            .map(|segment| segment.total_docs)
+            .sum()
+    }
+
+    pub fn estimated_deleted_docs(&self) -> i64 {
+        self.segments.iter()
+            .filter(|segment| segment.decision == MergeDecision::Merge)
+            .map(|segment| segment.deleted_docs)
+            .sum()
+    }
+}
+
+
+fn find_tier(total_docs: i64) -> Option<usize> {
+    TIERS.iter().position(|&(min, max)| total_docs >= min && total_docs <= max)
+}
+
+
+/// Decides which segments the next merge would combine
+pub fn plan_merge(segment_stats: &[(u32, SegmentStatistics)]) -> MergePlan {
+    let mut segments = segment_stats.iter().map(|&(segment, ref stats)| {
+        let tier = find_tier(stats.total_docs());
+
+        SegmentMergeExplanation {
+            segment: segment,
+            total_docs: stats.total_docs(),
+            deleted_docs: stats.deleted_docs(),
+            tier: tier.map(|tier| tier + 1),
+            decision: if tier.is_some() { MergeDecision::OtherTierChosen } else { MergeDecision::NoTier },
+        }
+    }).collect::<Vec<_>>();
+
+    // The tier with the most segments is merged, ties go to the larger tier
+    let mut tier_sizes = vec![0; TIERS.len()];
+    for segment in segments.iter() {
+        if let Some(tier) = segment.tier {
+            tier_sizes[tier - 1] += 1;
+        }
+    }
+
+    let chosen_tier = (0..TIERS.len()).max_by_key(|tier| (tier_sizes[*tier], *tier)).unwrap() + 1;
+
+    if tier_sizes[chosen_tier - 1] < MIN_SEGMENTS_TO_MERGE {
+        for segment in segments.iter_mut() {
+            if segment.tier == Some(chosen_tier) {
+                segment.decision = MergeDecision::TooFewSegments;
+            }
+        }
+
+        return MergePlan {
+            tier: None,
+            segments: segments,
+        };
+    }
+
+    // Take the largest segments first, skipping any that would make the merged segment too big
+    {
+        let mut candidates = segments.iter_mut().filter(|segment| segment.tier == Some(chosen_tier)).collect::<Vec<_>>();
+        candidates.sort_by_key(|segment| -segment.total_docs);
+
+        let mut current_doc_count = 0;
+        for segment in candidates {
+            if current_doc_count + segment.total_docs > MAX_SEGMENT_DOCS {
+                segment.decision = MergeDecision::WouldOverflow;
+                continue;
+            }
+
+            segment.decision = MergeDecision::Merge;
+            current_doc_count += segment.total_docs;
+        }
+    }
+
+    MergePlan {
+        tier: Some(chosen_tier),
+        segments: segments,
+    }
+}
+
+
+impl Index {
+    /// Explains what the next merge of the index would be, without running it
+    ///
+    /// Returns None if the index is closed, closed indices aren't maintained.
+    pub fn explain_merges(&self) -> Result<Option<MergePlan>, String> {
+        let store = match self.store_if_open() {
+            Some(store) => store,
+            None => return Ok(None),
+        };
+
+        let segment_stats = store.get_segment_statistics()?;
+        Ok(Some(plan_merge(&segment_stats)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::backends::rocksdb::SegmentStatistics;
+
+    use super::{plan_merge, MergeDecision};
+
+    fn segments(total_docs: &[i64]) -> Vec<(u32, SegmentStatistics)> {
+        total_docs.iter().enumerate().map(|(i, total_docs)| (i as u32 + 1, SegmentStatistics::new(*total_docs, 0))).collect()
+    }
+
+    #[test]
+    fn test_merges_tier_with_most_segments() {
+        let plan = plan_merge(&segments(&[5, 50, 3, 60, 70, 80, 0]));
+
+        assert_eq!(plan.tier, Some(2));
+        assert_eq!(plan.segments_to_merge(), vec![2, 4, 5, 6]);
+        assert_eq!(plan.estimated_total_docs(), 260);
+
+        let decisions = plan.segments.iter().map(|segment| segment.decision).collect::<Vec<_>>();
+        assert_eq!(decisions[0], MergeDecision::OtherTierChosen);
+        assert_eq!(decisions[6], MergeDecision::NoTier);
+    }
+
+    #[test]
+    fn test_too_few_segments() {
+        let plan = plan_merge(&segments(&[5, 6, 50]));
+
+        assert_eq!(plan.tier, None);
+        assert!(plan.segments_to_merge().is_empty());
+        assert_eq!(plan.segments[0].decision, MergeDecision::TooFewSegments);
+        assert_eq!(plan.segments[2].decision, MergeDecision::OtherTierChosen);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let plan = plan_merge(&segments(&[40000, 30000, 20000, 10000]));
+
+        // The largest segments are taken first, ones that don't fit are skipped
+        assert_eq!(plan.tier, Some(5));
+        assert_eq!(plan.segments_to_merge(), vec![1, 3]);
+        assert_eq!(plan.segments[1].decision, MergeDecision::WouldOverflow);
+        assert_eq!(plan.segments[3].decision, MergeDecision::WouldOverflow);
+    }
+}
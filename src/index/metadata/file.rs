@@ -1,10 +1,12 @@
 use std::path::Path;
 use std::io::{self, Read, Write};
 use std::fs::File;
+use std::sync::Arc;
 
 use serde_json;
 use atomicwrites::{self, AtomicFile, AllowOverwrite};
 
+use analysis::registry::AnalysisRegistry;
 use index::metadata::IndexMetadata;
 use index::metadata::parse::{parse, IndexMetadataParseError};
 
@@ -13,6 +15,13 @@ use index::metadata::parse::{parse, IndexMetadataParseError};
 pub enum SaveIndexMetadataError {
     JsonEncoderError(serde_json::Error),
     IoError(atomicwrites::Error<io::Error>),
+    ReadVersionError(LoadIndexMetadataError),
+
+    /// The saved metadata has been changed since this copy was loaded
+    VersionConflict {
+        expected: u64,
+        actual: u64,
+    },
 }
 
 
@@ -21,6 +30,8 @@ impl From<SaveIndexMetadataError> for String {
         match e {
             SaveIndexMetadataError::JsonEncoderError(e) => format!("failed to save index metadata: {}", e).to_string(),
             SaveIndexMetadataError::IoError(e) => format!("failed to save index metadata: {}", e).to_string(),
+            SaveIndexMetadataError::ReadVersionError(e) => format!("failed to save index metadata: {}", String::from(e)).to_string(),
+            SaveIndexMetadataError::VersionConflict{expected, actual} => format!("failed to save index metadata: expected version {} but found version {}", expected, actual).to_string(),
         }
     }
 }
@@ -79,28 +90,134 @@ impl From<io::Error> for LoadIndexMetadataError {
 }
 
 
+fn read_version(data: &serde_json::Value) -> u64 {
+    data.get("version").and_then(|version| version.as_u64()).unwrap_or(0)
+}
+
+
+/// Reads the version of the saved metadata, this is 0 if it hasn't been saved yet
+fn read_saved_version(path: &Path) -> Result<u64, LoadIndexMetadataError> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut s = String::new();
+    file.read_to_string(&mut s)?;
+
+    Ok(read_version(&serde_json::from_str(&s)?))
+}
+
+
 impl IndexMetadata {
-    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SaveIndexMetadataError> {
+    /// Saves the metadata and increments its version
+    ///
+    /// This is a compare-and-swap, if the saved metadata isn't the version this
+    /// copy was loaded from (or last saved as), a VersionConflict error is returned
+    /// and nothing is written.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<(), SaveIndexMetadataError> {
+        let path = path.as_ref();
+
+        // Check that nothing else has saved the metadata in the meantime
+        let saved_version = read_saved_version(path).map_err(SaveIndexMetadataError::ReadVersionError)?;
+        if saved_version != self.version {
+            return Err(SaveIndexMetadataError::VersionConflict {
+                expected: self.version,
+                actual: saved_version,
+            });
+        }
+
         // Encode to JSON
-        let s = format!("{}", serde_json::to_value(self)?);
+        self.version += 1;
+        let s = match serde_json::to_value(&*self) {
+            Ok(json) => format!("{}", json),
+            Err(e) => {
+                self.version -= 1;
+                return Err(e.into());
+            }
+        };
 
         // Write to file
         let file = AtomicFile::new(path, AllowOverwrite);
-        file.write(|f| {
+        let result = file.write(|f| {
             f.write_all(s.as_bytes())
-        })?;
+        });
+
+        if let Err(e) = result {
+            self.version -= 1;
+            return Err(e.into());
+        }
 
         Ok(())
     }
 
     pub fn load<P: AsRef<Path>>(path: P) -> Result<IndexMetadata, LoadIndexMetadataError> {
+        IndexMetadata::load_with_analysis_registry(path, Arc::new(AnalysisRegistry::new()))
+    }
+
+    /// Loads the metadata of an index that may use filters and tokenizers from the registry
+    pub fn load_with_analysis_registry<P: AsRef<Path>>(path: P, analysis_registry: Arc<AnalysisRegistry>) -> Result<IndexMetadata, LoadIndexMetadataError> {
         let mut file = File::open(path)?;
         let mut s = String::new();
         file.read_to_string(&mut s)?;
 
-        let mut metadata = IndexMetadata::default();
-        parse(&mut metadata, serde_json::from_str(&s)?)?;
+        let data = serde_json::from_str(&s)?;
+        let mut metadata = IndexMetadata::with_analysis_registry(analysis_registry);
+        metadata.version = read_version(&data);
+        parse(&mut metadata, data)?;
 
         Ok(metadata)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, remove_file};
+
+    use index::metadata::IndexMetadata;
+
+    use super::SaveIndexMetadataError;
+
+    #[test]
+    fn test_save_increments_version() {
+        let path = "test_indices/test_metadata_save_increments_version.json";
+        create_dir_all("test_indices").unwrap();
+        let _ = remove_file(path);
+
+        let mut metadata = IndexMetadata::default();
+        metadata.save(path).unwrap();
+        metadata.save(path).unwrap();
+        assert_eq!(metadata.version, 2);
+
+        let loaded_metadata = IndexMetadata::load(path).unwrap();
+        assert_eq!(loaded_metadata.version, 2);
+    }
+
+    #[test]
+    fn test_save_conflict() {
+        let path = "test_indices/test_metadata_save_conflict.json";
+        create_dir_all("test_indices").unwrap();
+        let _ = remove_file(path);
+
+        IndexMetadata::default().save(path).unwrap();
+
+        // Two copies are loaded, the first one to be saved wins
+        let mut metadata_a = IndexMetadata::load(path).unwrap();
+        let mut metadata_b = IndexMetadata::load(path).unwrap();
+        metadata_a.save(path).unwrap();
+
+        match metadata_b.save(path) {
+            Err(SaveIndexMetadataError::VersionConflict{expected, actual}) => {
+                assert_eq!(expected, 1);
+                assert_eq!(actual, 2);
+            }
+            result => panic!("expected a version conflict, got {:?}", result),
+        }
+
+        // Nothing was written and the version wasn't changed
+        assert_eq!(metadata_b.version, 1);
+        assert_eq!(IndexMetadata::load(path).unwrap().version, 2);
+    }
+}
@@ -2,14 +2,23 @@ pub mod parse;
 pub mod file;
 
 use std::collections::{HashMap, BTreeMap};
+use std::sync::Arc;
 
 use serde::{Serialize, Serializer};
 use serde_json;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
 
-use analysis::AnalyzerSpec;
+use analysis::{AnalyzerSpec, NormalizerSpec};
 use analysis::tokenizers::TokenizerSpec;
 use analysis::filters::FilterSpec;
-use mapping::{Mapping, MappingProperty, FieldMapping};
+use analysis::registry::AnalysisRegistry;
+use mapping::{Mapping, MappingProperty, FieldMapping, FieldType};
+use query_parser::utils::Operator;
+
+
+/// The furthest into the results that a search can page, unless the index says otherwise
+pub const DEFAULT_MAX_RESULT_WINDOW: usize = 10000;
 
 
 #[derive(Debug)]
@@ -17,17 +26,58 @@ pub struct IndexMetadata {
     analyzers: HashMap<String, AnalyzerSpec>,
     tokenizers: HashMap<String, TokenizerSpec>,
     filters: HashMap<String, FilterSpec>,
+    normalizers: HashMap<String, NormalizerSpec>,
+
+    /// The filter and tokenizer types that were added by the embedder
+    analysis_registry: Arc<AnalysisRegistry>,
+
     pub mappings: HashMap<String, Mapping>,
+
+    /// When the index was created, in milliseconds since the epoch
+    pub creation_date: Option<i64>,
+    pub uuid: Option<Uuid>,
+
+    /// The version of rusticsearch that created the index
+    pub version_created: Option<String>,
+
+    /// Incremented each time the metadata is saved
+    pub version: u64,
+
+    /// The field searched by queries that don't specify any ("index.query.default_field")
+    pub default_field: Option<String>,
+
+    /// The operator used by queries that don't specify one ("index.query.default_operator")
+    pub default_operator: Option<Operator>,
+
+    /// The most that "from" + "size" can be in a search ("index.max_result_window")
+    pub max_result_window: Option<usize>,
 }
 
 
 impl Default for IndexMetadata {
     fn default() -> IndexMetadata {
+        IndexMetadata::with_analysis_registry(Arc::new(AnalysisRegistry::new()))
+    }
+}
+
+
+impl IndexMetadata {
+    /// Creates metadata that can use the filter and tokenizer types in the registry
+    pub fn with_analysis_registry(analysis_registry: Arc<AnalysisRegistry>) -> IndexMetadata {
         let mut metadata = IndexMetadata {
             analyzers: HashMap::new(),
             tokenizers: HashMap::new(),
             filters: HashMap::new(),
+            normalizers: HashMap::new(),
+            analysis_registry: analysis_registry,
             mappings: HashMap::new(),
+            creation_date: None,
+            uuid: None,
+            version_created: None,
+            version: 0,
+            default_field: None,
+            default_operator: None,
+            max_result_window: None,
         };
 
         // Builtin tokenizers
@@ -47,12 +97,38 @@ impl Default for IndexMetadata {
             ]
         });
 
+        // Builtin normalizers
+        metadata.insert_normalizer("lowercase".to_string(), NormalizerSpec {
+            filters: vec![
+                FilterSpec::Lowercase,
+            ]
+        });
+
         metadata
     }
 }
 
 
 impl IndexMetadata {
+    /// Records the identity of a new index, this should be called once when the index is created
+    pub fn set_created(&mut self, uuid: Uuid, creation_date: DateTime<Utc>) {
+        self.uuid = Some(uuid);
+        self.creation_date = Some(creation_date.timestamp() * 1000 + creation_date.timestamp_subsec_millis() as i64);
+        self.version_created = Some(env!("CARGO_PKG_VERSION").to_string());
+    }
+
+    /// The most that "from" + "size" can be in a search of the index
+    ///
+    /// Collectors hold every hit up to the end of the page, so this stops deep pages from
+    /// using too much memory.
+    pub fn max_result_window(&self) -> usize {
+        self.max_result_window.unwrap_or(DEFAULT_MAX_RESULT_WINDOW)
+    }
+
+    pub fn analysis_registry(&self) -> &Arc<AnalysisRegistry> {
+        &self.analysis_registry
+    }
+
     // Tokenizer helpers
 
     pub fn insert_tokenizer(&mut self, name: String, tokenizer: TokenizerSpec) -> Option<TokenizerSpec> {
@@ -73,6 +149,16 @@ impl IndexMetadata {
         &self.filters
     }
 
+    // Normalizer helpers
+
+    pub fn insert_normalizer(&mut self, name: String, normalizer: NormalizerSpec) -> Option<NormalizerSpec> {
+        self.normalizers.insert(name, normalizer)
+    }
+
+    pub fn normalizers(&self) -> &HashMap<String, NormalizerSpec> {
+        &self.normalizers
+    }
+
     // Analyzer helpers
 
     pub fn insert_analyzer(&mut self, name: String, analyzer: AnalyzerSpec) -> Option<AnalyzerSpec> {
@@ -120,6 +206,21 @@ impl IndexMetadata {
 
         None
     }
+
+    /// Finds the join field, there can only be one per index
+    pub fn get_join_field(&self) -> Option<(&str, &FieldMapping)> {
+        for mapping in self.mappings.values() {
+            for (name, property) in mapping.properties.iter() {
+                if let MappingProperty::Field(ref field_mapping) = *property {
+                    if field_mapping.data_type == FieldType::Join {
+                        return Some((name, field_mapping));
+                    }
+                }
+            }
+        }
+
+        None
+    }
 }
 
 
@@ -137,21 +238,59 @@ impl Serialize for IndexMetadata {
             filters_json.insert(name.to_string(), serde_json::to_value(&filter).unwrap());
         }
 
+        // Normalizers
+        let mut normalizers_json = BTreeMap::new();
+        for (name, normalizer) in self.normalizers.iter() {
+            normalizers_json.insert(name.to_string(), serde_json::to_value(&normalizer).unwrap());
+        }
+
         // Mappings
         let mut mappings_json = BTreeMap::new();
         for (name, mapping) in self.mappings.iter() {
             mappings_json.insert(name.to_string(), serde_json::to_value(&mapping).unwrap());
         }
 
+        // Index identity, indices created before these were recorded don't have them
+        let mut index_json = BTreeMap::new();
+        if let Some(creation_date) = self.creation_date {
+            index_json.insert("creation_date".to_string(), json!(creation_date.to_string()));
+        }
+        if let Some(uuid) = self.uuid {
+            index_json.insert("uuid".to_string(), json!(uuid.to_string()));
+        }
+        if let Some(ref version_created) = self.version_created {
+            index_json.insert("version".to_string(), json!({"created": version_created}));
+        }
+        if let Some(max_result_window) = self.max_result_window {
+            index_json.insert("max_result_window".to_string(), json!(max_result_window.to_string()));
+        }
+
+        let mut query_json = BTreeMap::new();
+        if let Some(ref default_field) = self.default_field {
+            query_json.insert("default_field".to_string(), json!(default_field));
+        }
+        if let Some(default_operator) = self.default_operator {
+            query_json.insert("default_operator".to_string(), json!(default_operator.name()));
+        }
+        if !query_json.is_empty() {
+            index_json.insert("query".to_string(), json!(query_json));
+        }
+
         let json = json!({
             "settings": {
+                "index": index_json,
                 "analysis": {
                     "tokenizers": tokenizers_json,
                     "filters": filters_json,
                     "analyzers": {},  // TODO
+
+                    // Mappings refer to normalizers by name, so these are written in the
+                    // format they are parsed in to be available when the index is loaded
+                    "normalizer": normalizers_json,
                 },
             },
             "mappings": mappings_json,
+            "version": self.version,
         });
 
         json.serialize(serializer)
@@ -13,6 +13,11 @@ pub enum AnalyzerParseError {
     UnrecognisedAnalyzerType(String),
     UnrecognisedTokenizer(String),
     UnrecognisedFilter(String),
+
+    /// A registered tokenizer or filter type that was used by name rejected being
+    /// created without any settings
+    InvalidTokenizer(String, String),
+    InvalidFilter(String, String),
 }
 
 
@@ -36,14 +41,22 @@ pub fn parse(json: &serde_json::Value, index_metadata: &IndexMetadata) -> Result
                 None => return Err(AnalyzerParseError::ExpectedKey("tokenizer".to_string())),
             };
 
+            // Tokenizers that aren't defined in the index can be registered types used
+            // with their default settings
             let tokenizer_spec = match index_metadata.tokenizers().get(tokenizer_name) {
-                Some(tokenizer_spec) => tokenizer_spec,
-                None => return Err(AnalyzerParseError::UnrecognisedTokenizer(tokenizer_name.to_string())),
+                Some(tokenizer_spec) => tokenizer_spec.clone(),
+                None => {
+                    match index_metadata.analysis_registry().create_tokenizer(tokenizer_name, &json!({"type": tokenizer_name})) {
+                        Some(Ok(tokenizer_spec)) => tokenizer_spec,
+                        Some(Err(message)) => return Err(AnalyzerParseError::InvalidTokenizer(tokenizer_name.to_string(), message)),
+                        None => return Err(AnalyzerParseError::UnrecognisedTokenizer(tokenizer_name.to_string())),
+                    }
+                }
             };
 
             // Build analyzer
             let mut analyzer_spec = AnalyzerSpec {
-                tokenizer: tokenizer_spec.clone(),
+                tokenizer: tokenizer_spec,
                 filters: Vec::new(),
             };
 
@@ -56,11 +69,17 @@ pub fn parse(json: &serde_json::Value, index_metadata: &IndexMetadata) -> Result
                             match filter_name_json.as_str() {
                                 Some(filter_name) => {
                                     let filter_spec = match index_metadata.filters().get(filter_name) {
-                                        Some(filter_spec) => filter_spec,
-                                        None => return Err(AnalyzerParseError::UnrecognisedFilter(filter_name.to_string())),
+                                        Some(filter_spec) => filter_spec.clone(),
+                                        None => {
+                                            match index_metadata.analysis_registry().create_filter(filter_name, &json!({"type": filter_name})) {
+                                                Some(Ok(filter_spec)) => filter_spec,
+                                                Some(Err(message)) => return Err(AnalyzerParseError::InvalidFilter(filter_name.to_string(), message)),
+                                                None => return Err(AnalyzerParseError::UnrecognisedFilter(filter_name.to_string())),
+                                            }
+                                        }
                                     };
 
-                                    analyzer_spec.filters.push(filter_spec.clone());
+                                    analyzer_spec.filters.push(filter_spec);
                                 }
                                 None => return Err(AnalyzerParseError::ExpectedString),
                             }
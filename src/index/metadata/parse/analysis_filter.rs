@@ -2,6 +2,7 @@ use serde_json;
 
 use analysis::ngram_generator::Edge;
 use analysis::filters::FilterSpec;
+use analysis::registry::AnalysisRegistry;
 
 
 #[derive(Debug, PartialEq)]
@@ -12,10 +13,13 @@ pub enum FilterParseError {
     ExpectedKey(String),
     UnrecognisedType(String),
     InvalidSideValue,
+
+    /// A registered filter type rejected its settings
+    InvalidSettings(String),
 }
 
 
-pub fn parse(json: &serde_json::Value) -> Result<FilterSpec, FilterParseError> {
+pub fn parse(json: &serde_json::Value, registry: &AnalysisRegistry) -> Result<FilterSpec, FilterParseError> {
     let data = json.as_object().ok_or(FilterParseError::ExpectedObject)?;
 
     // Get type
@@ -154,6 +158,13 @@ pub fn parse(json: &serde_json::Value) -> Result<FilterSpec, FilterParseError> {
         // classic
         // decimal_digit
         // fingerprint
-        _ => Err(FilterParseError::UnrecognisedType(filter_type.to_string())),
+        _ => {
+            // Types that were added by the embedder
+            match registry.create_filter(filter_type, json) {
+                Some(Ok(filter)) => Ok(filter),
+                Some(Err(message)) => Err(FilterParseError::InvalidSettings(message)),
+                None => Err(FilterParseError::UnrecognisedType(filter_type.to_string())),
+            }
+        }
     }
 }
@@ -0,0 +1,57 @@
+use serde_json;
+
+use analysis::NormalizerSpec;
+use index::metadata::IndexMetadata;
+
+
+#[derive(Debug, PartialEq)]
+pub enum NormalizerParseError {
+    ExpectedObject,
+    ExpectedString,
+    ExpectedArray,
+    UnrecognisedNormalizerType(String),
+    UnrecognisedFilter(String),
+
+    /// The filter changes more than single characters so it would split the value
+    FilterNotAllowedInNormalizer(String),
+}
+
+
+pub fn parse(json: &serde_json::Value, index_metadata: &IndexMetadata) -> Result<NormalizerSpec, NormalizerParseError> {
+    let data = json.as_object().ok_or(NormalizerParseError::ExpectedObject)?;
+
+    // Normalizers are always custom, but the type can be given like it is for analyzers
+    if let Some(normalizer_type_json) = data.get("type") {
+        let normalizer_type = normalizer_type_json.as_str().ok_or(NormalizerParseError::ExpectedString)?;
+
+        if normalizer_type != "custom" {
+            return Err(NormalizerParseError::UnrecognisedNormalizerType(normalizer_type.to_string()));
+        }
+    }
+
+    let mut normalizer_spec = NormalizerSpec {
+        filters: Vec::new(),
+    };
+
+    // Add filters
+    if let Some(filter_json) = data.get("filter") {
+        let filter_names = filter_json.as_array().ok_or(NormalizerParseError::ExpectedArray)?;
+
+        for filter_name_json in filter_names.iter() {
+            let filter_name = filter_name_json.as_str().ok_or(NormalizerParseError::ExpectedString)?;
+
+            let filter_spec = match index_metadata.filters().get(filter_name) {
+                Some(filter_spec) => filter_spec,
+                None => return Err(NormalizerParseError::UnrecognisedFilter(filter_name.to_string())),
+            };
+
+            if filter_spec.normalizer_filter_name().is_none() {
+                return Err(NormalizerParseError::FilterNotAllowedInNormalizer(filter_name.to_string()));
+            }
+
+            normalizer_spec.filters.push(filter_spec.clone());
+        }
+    }
+
+    Ok(normalizer_spec)
+}
@@ -2,6 +2,7 @@ use serde_json;
 
 use analysis::ngram_generator::Edge;
 use analysis::tokenizers::TokenizerSpec;
+use analysis::registry::AnalysisRegistry;
 
 
 #[derive(Debug, PartialEq)]
@@ -12,10 +13,13 @@ pub enum TokenizerParseError {
     ExpectedKey(String),
     UnrecognisedType(String),
     InvalidSideValue,
+
+    /// A registered tokenizer type rejected its settings
+    InvalidSettings(String),
 }
 
 
-pub fn parse(json: &serde_json::Value) -> Result<TokenizerSpec, TokenizerParseError> {
+pub fn parse(json: &serde_json::Value, registry: &AnalysisRegistry) -> Result<TokenizerSpec, TokenizerParseError> {
     let data = json.as_object().ok_or(TokenizerParseError::ExpectedObject)?;
 
     // Get type
@@ -113,6 +117,13 @@ pub fn parse(json: &serde_json::Value) -> Result<TokenizerSpec, TokenizerParseEr
         // pattern
         // classic
         // thai
-        _ => Err(TokenizerParseError::UnrecognisedType(tokenizer_type.to_owned())),
+        _ => {
+            // Types that were added by the embedder
+            match registry.create_tokenizer(tokenizer_type, json) {
+                Some(Ok(tokenizer)) => Ok(tokenizer),
+                Some(Err(message)) => Err(TokenizerParseError::InvalidSettings(message)),
+                None => Err(TokenizerParseError::UnrecognisedType(tokenizer_type.to_owned())),
+            }
+        }
     }
 }
@@ -1,15 +1,21 @@
 pub mod analysis_tokenizer;
 pub mod analysis_filter;
 pub mod analysis_analyzer;
+pub mod analysis_normalizer;
 
 use serde_json;
+use uuid::Uuid;
 
 use index::metadata::IndexMetadata;
 use mapping::parse::{MappingParseError, parse as parse_mapping};
+use query_parser::utils::{Operator, parse_operator};
+use query_parser::warnings::ParseWarnings;
+use json_pointer;
 
 use self::analysis_tokenizer::{TokenizerParseError, parse as parse_tokenizer};
 use self::analysis_filter::{FilterParseError, parse as parse_filter};
 use self::analysis_analyzer::{AnalyzerParseError, parse as parse_analyzer};
+use self::analysis_normalizer::{NormalizerParseError, parse as parse_normalizer};
 
 
 #[derive(Debug, PartialEq)]
@@ -18,7 +24,148 @@ pub enum IndexMetadataParseError {
     TokenizerParseError(String, TokenizerParseError),
     FilterParseError(String, FilterParseError),
     AnalyzerParseError(String, AnalyzerParseError),
+    NormalizerParseError(String, NormalizerParseError),
     MappingParseError(String, MappingParseError),
+    InvalidIndexSetting(String),
+
+    /// The setting can't be changed after the index is created
+    NonDynamicIndexSetting(String),
+}
+
+
+impl IndexMetadataParseError {
+    /// Finds the pointer to the key that the error is about in the JSON that was parsed
+    ///
+    /// Only errors about unrecognised keys in mappings point to a key, this returns None for others.
+    pub fn pointer(&self, json: &serde_json::Value) -> Option<String> {
+        match *self {
+            IndexMetadataParseError::MappingParseError(ref name, ref error) => {
+                let path = json_pointer::join("/mappings", name);
+                json.pointer(&path).and_then(|json| error.pointer(json)).map(|pointer| path + &pointer)
+            }
+            _ => None,
+        }
+    }
+}
+
+
+/// The settings that are read when an index is created, by their full name
+///
+/// The "analysis" settings aren't included, they're checked when they're parsed.
+const SUPPORTED_SETTINGS: &'static [&'static str] = &[
+    "index.creation_date",
+    "index.uuid",
+    "index.version.created",
+    "index.max_result_window",
+    "index.query.default_field",
+    "index.query.default_operator",
+];
+
+
+fn parse_default_field(default_field: &serde_json::Value) -> Result<Option<String>, IndexMetadataParseError> {
+    match *default_field {
+        serde_json::Value::String(ref default_field) => Ok(Some(default_field.clone())),
+        serde_json::Value::Null => Ok(None),
+        _ => Err(IndexMetadataParseError::InvalidIndexSetting("query.default_field".to_string())),
+    }
+}
+
+
+fn parse_default_operator(default_operator: &serde_json::Value) -> Result<Option<Operator>, IndexMetadataParseError> {
+    // Elasticsearch accepts "AND" and "OR" in any case
+    match *default_operator {
+        serde_json::Value::String(ref default_operator) => {
+            match parse_operator(&serde_json::Value::String(default_operator.to_lowercase())) {
+                Ok(default_operator) => Ok(Some(default_operator)),
+                Err(_) => Err(IndexMetadataParseError::InvalidIndexSetting("query.default_operator".to_string())),
+            }
+        }
+        serde_json::Value::Null => Ok(None),
+        _ => Err(IndexMetadataParseError::InvalidIndexSetting("query.default_operator".to_string())),
+    }
+}
+
+
+fn parse_max_result_window(max_result_window: &serde_json::Value) -> Result<Option<usize>, IndexMetadataParseError> {
+    // Elasticsearch renders numeric settings as strings
+    let max_result_window = match *max_result_window {
+        serde_json::Value::String(ref string) => string.parse::<u64>().ok(),
+        serde_json::Value::Null => return Ok(None),
+        ref value => value.as_u64(),
+    };
+
+    match max_result_window {
+        Some(max_result_window) if max_result_window > 0 => Ok(Some(max_result_window as usize)),
+        _ => Err(IndexMetadataParseError::InvalidIndexSetting("max_result_window".to_string())),
+    }
+}
+
+
+/// Parses the "query" block of the index settings
+fn parse_query_settings(metadata: &mut IndexMetadata, query: &serde_json::Value) -> Result<(), IndexMetadataParseError> {
+    let query = match query.as_object() {
+        Some(object) => object,
+        None => return Err(IndexMetadataParseError::ExpectedObject),
+    };
+
+    if let Some(default_field) = query.get("default_field") {
+        metadata.default_field = parse_default_field(default_field)?;
+    }
+
+    if let Some(default_operator) = query.get("default_operator") {
+        metadata.default_operator = parse_default_operator(default_operator)?;
+    }
+
+    Ok(())
+}
+
+
+/// Parses the "index" block of the settings
+///
+/// Most of these are written by rusticsearch when the index is created so they are
+/// read back when the index is loaded. Other index settings are ignored.
+fn parse_index_settings(metadata: &mut IndexMetadata, index: &serde_json::Value) -> Result<(), IndexMetadataParseError> {
+    let index = match index.as_object() {
+        Some(object) => object,
+        None => return Err(IndexMetadataParseError::ExpectedObject),
+    };
+
+    if let Some(creation_date) = index.get("creation_date") {
+        // Elasticsearch renders the creation date as a string
+        let creation_date = match *creation_date {
+            serde_json::Value::String(ref string) => string.parse::<i64>().ok(),
+            ref value => value.as_i64(),
+        };
+
+        match creation_date {
+            Some(creation_date) => metadata.creation_date = Some(creation_date),
+            None => return Err(IndexMetadataParseError::InvalidIndexSetting("creation_date".to_string())),
+        }
+    }
+
+    if let Some(uuid) = index.get("uuid") {
+        match uuid.as_str().and_then(|uuid| Uuid::parse_str(uuid).ok()) {
+            Some(uuid) => metadata.uuid = Some(uuid),
+            None => return Err(IndexMetadataParseError::InvalidIndexSetting("uuid".to_string())),
+        }
+    }
+
+    if let Some(version) = index.get("version") {
+        match version.get("created").and_then(|created| created.as_str()) {
+            Some(created) => metadata.version_created = Some(created.to_string()),
+            None => return Err(IndexMetadataParseError::InvalidIndexSetting("version".to_string())),
+        }
+    }
+
+    if let Some(max_result_window) = index.get("max_result_window") {
+        metadata.max_result_window = parse_max_result_window(max_result_window)?;
+    }
+
+    if let Some(query) = index.get("query") {
+        parse_query_settings(metadata, query)?;
+    }
+
+    Ok(())
 }
 
 
@@ -36,11 +183,27 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
             None => return Err(IndexMetadataParseError::ExpectedObject),
         };
 
+        if let Some(index) = settings.get("index") {
+            parse_index_settings(metadata, index)?;
+        }
+
+        // Settings can also be given with their full name, eg {"index.max_result_window": 50000}
+        if let Some(max_result_window) = settings.get("index.max_result_window") {
+            metadata.max_result_window = parse_max_result_window(max_result_window)?;
+        }
+        if let Some(default_field) = settings.get("index.query.default_field") {
+            metadata.default_field = parse_default_field(default_field)?;
+        }
+        if let Some(default_operator) = settings.get("index.query.default_operator") {
+            metadata.default_operator = parse_default_operator(default_operator)?;
+        }
+
         if let Some(analysis) = settings.get("analysis") {
             let analysis = match analysis.as_object() {
                 Some(object) => object,
                 None => return Err(IndexMetadataParseError::ExpectedObject),
             };
+            let analysis_registry = metadata.analysis_registry().clone();
 
             // Tokenisers
             if let Some(tokenizer_data) = analysis.get("tokenizer") {
@@ -50,7 +213,7 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
                 };
 
                 for (name, data) in tokenizer_data {
-                    let tokenizer = match parse_tokenizer(data) {
+                    let tokenizer = match parse_tokenizer(data, &analysis_registry) {
                         Ok(tokenizer) => tokenizer,
                         Err(e) => return Err(IndexMetadataParseError::TokenizerParseError(name.to_string(), e)),
                     };
@@ -67,7 +230,7 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
                 };
 
                 for (name, data) in filter_data {
-                    let filter = match parse_filter(data) {
+                    let filter = match parse_filter(data, &analysis_registry) {
                         Ok(filter) => filter,
                         Err(e) => return Err(IndexMetadataParseError::FilterParseError(name.to_string(), e)),
                     };
@@ -92,6 +255,23 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
                     metadata.insert_analyzer(name.clone(), analyzer);
                 }
             }
+
+            // Normalizers
+            if let Some(normalizer_data) = analysis.get("normalizer") {
+                let normalizer_data = match normalizer_data.as_object() {
+                    Some(object) => object,
+                    None => return Err(IndexMetadataParseError::ExpectedObject),
+                };
+
+                for (name, data) in normalizer_data {
+                    let normalizer = match parse_normalizer(data, &metadata) {
+                        Ok(normalizer) => normalizer,
+                        Err(e) => return Err(IndexMetadataParseError::NormalizerParseError(name.to_string(), e)),
+                    };
+
+                    metadata.insert_normalizer(name.clone(), normalizer);
+                }
+            }
         }
     }
 
@@ -115,20 +295,111 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
 }
 
 
+/// Records the settings that aren't in SUPPORTED_SETTINGS, with the pointer to where they're set
+fn check_settings(name: &str, pointer: &str, json: &serde_json::Value, warnings: &mut ParseWarnings) {
+    match *json {
+        serde_json::Value::Object(ref object) => {
+            for (key, value) in object.iter() {
+                let name = if name.is_empty() { key.clone() } else { format!("{}.{}", name, key) };
+                if name != "analysis" {
+                    check_settings(&name, &json_pointer::join(pointer, key), value, warnings);
+                }
+            }
+        }
+        _ => {
+            if !SUPPORTED_SETTINGS.contains(&name) {
+                warnings.unsupported_key("settings", name, pointer.to_string());
+            }
+        }
+    }
+}
+
+
+/// Records the parts of the body of a create index request that would be ignored by `parse`
+pub fn check_unsupported_settings(data: &serde_json::Value, warnings: &mut ParseWarnings) {
+    if let Some(object) = data.as_object() {
+        warnings.check_keys("index", "", object, &["settings", "mappings"]);
+    }
+
+    if let Some(settings) = data.get("settings") {
+        check_settings("", "/settings", settings, warnings);
+    }
+}
+
+
+/// Collects the settings in an object by their full name, eg {"index": {"max_result_window": 50000}} gives "index.max_result_window"
+fn flatten_settings<'a>(prefix: &str, json: &'a serde_json::Value, settings: &mut Vec<(String, &'a serde_json::Value)>) {
+    match *json {
+        serde_json::Value::Object(ref object) => {
+            for (key, value) in object.iter() {
+                let name = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten_settings(&name, value, settings);
+            }
+        }
+        _ => settings.push((prefix.to_string(), json)),
+    }
+}
+
+
+/// Changes the settings of an existing index
+///
+/// Only settings that can be changed after the index is created are allowed. They can be
+/// nested or given with their full name, and may be wrapped in a "settings" object. Nothing
+/// is changed if any of the settings are invalid.
+pub fn parse_settings_update(metadata: &mut IndexMetadata, data: &serde_json::Value) -> Result<(), IndexMetadataParseError> {
+    let data = data.get("settings").unwrap_or(data);
+    if !data.is_object() {
+        return Err(IndexMetadataParseError::ExpectedObject);
+    }
+
+    let mut settings = Vec::new();
+    flatten_settings("", data, &mut settings);
+
+    let mut default_field = None;
+    let mut default_operator = None;
+    let mut max_result_window = None;
+    for (name, value) in settings {
+        match name.as_ref() {
+            "index.query.default_field" => default_field = Some(parse_default_field(value)?),
+            "index.query.default_operator" => default_operator = Some(parse_default_operator(value)?),
+            "index.max_result_window" => max_result_window = Some(parse_max_result_window(value)?),
+            _ => return Err(IndexMetadataParseError::NonDynamicIndexSetting(name.clone())),
+        }
+    }
+
+    if let Some(default_field) = default_field {
+        metadata.default_field = default_field;
+    }
+    if let Some(default_operator) = default_operator {
+        metadata.default_operator = default_operator;
+    }
+    if let Some(max_result_window) = max_result_window {
+        metadata.max_result_window = max_result_window;
+    }
+
+    Ok(())
+}
+
+
 #[cfg(test)]
 mod tests {
     use serde_json;
+    use chrono::{Utc, TimeZone};
+    use uuid::Uuid;
 
     use analysis::ngram_generator::Edge;
     use analysis::tokenizers::TokenizerSpec;
     use analysis::filters::FilterSpec;
-    use analysis::AnalyzerSpec;
+    use analysis::{AnalyzerSpec, NormalizerSpec};
     use mapping::parse::MappingParseError;
     use index::metadata::IndexMetadata;
+    use query_parser::utils::Operator;
+    use query_parser::warnings::ParseWarnings;
 
-    use super::{parse, IndexMetadataParseError};
+    use super::{parse, parse_settings_update, check_unsupported_settings, IndexMetadataParseError};
     use super::analysis_tokenizer::TokenizerParseError;
     use super::analysis_filter::FilterParseError;
+    use super::analysis_normalizer::NormalizerParseError;
 
     #[test]
     fn test_default() {
@@ -329,6 +600,60 @@ mod tests {
         assert_eq!(error, IndexMetadataParseError::FilterParseError("bad_filter".to_string(), FilterParseError::UnrecognisedType("foo".to_string())));
     }
 
+    #[test]
+    fn test_normalizer() {
+        let mut metadata = IndexMetadata::default();
+        parse(&mut metadata, json!({
+            "settings": {
+                "analysis": {
+                    "normalizer": {
+                        "folding": {
+                            "type": "custom",
+                            "filter": ["lowercase", "asciifolding"]
+                        }
+                    }
+                }
+            }
+        })).expect("parse() returned an error");
+
+        let folding_normalizer = metadata.normalizers().get("folding").expect("'folding' normalizer wasn't created");
+        assert_eq!(*folding_normalizer, NormalizerSpec {
+            filters: vec![
+                FilterSpec::Lowercase,
+                FilterSpec::ASCIIFolding,
+            ]
+        });
+        assert_eq!(folding_normalizer.normalize("Crème Brûlée"), "creme brulee");
+
+        // Normalizers are saved with the index so mappings can refer to them when it's loaded
+        let mut loaded_metadata = IndexMetadata::default();
+        parse(&mut loaded_metadata, serde_json::to_value(&metadata).unwrap()).expect("parse() returned an error");
+        assert_eq!(loaded_metadata.normalizers().get("folding"), Some(folding_normalizer));
+    }
+
+    #[test]
+    fn test_normalizer_bad_filter() {
+        let mut metadata = IndexMetadata::default();
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "analysis": {
+                    "filter": {
+                        "grams": {
+                            "type": "ngram"
+                        }
+                    },
+                    "normalizer": {
+                        "bad_normalizer": {
+                            "filter": ["lowercase", "grams"]
+                        }
+                    }
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::NormalizerParseError("bad_normalizer".to_string(), NormalizerParseError::FilterNotAllowedInNormalizer("grams".to_string())));
+    }
+
     #[test]
     fn test_mapping() {
         let mut metadata = IndexMetadata::default();
@@ -360,4 +685,285 @@ mod tests {
 
         assert_eq!(error, IndexMetadataParseError::MappingParseError("test_mapping".to_string(), MappingParseError::UnrecognisedKeys(vec!["foo".to_string()])));
     }
+
+    #[test]
+    fn test_mapping_error_pointer() {
+        let json = json!({
+            "mappings": {
+                "test_mapping": {
+                    "properties": {
+                        "title": {
+                            "type": "string",
+                            "fielddata": true
+                        }
+                    }
+                }
+            }
+        });
+        let error = parse(&mut IndexMetadata::default(), json.clone()).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error.pointer(&json), Some("/mappings/test_mapping/properties/title/fielddata".to_string()));
+    }
+
+    #[test]
+    fn test_unsupported_settings() {
+        let mut warnings = ParseWarnings::new();
+        check_unsupported_settings(&json!({
+            "settings": {
+                "index": {
+                    "max_result_window": 50000,
+                    "number_of_shards": 3,
+                    "query": {
+                        "default_field": "title"
+                    }
+                },
+                "index.refresh_interval": "1s",
+                "analysis": {
+                    "analyzer": {
+                        "my_analyzer": {"tokenizer": "standard"}
+                    }
+                }
+            },
+            "aliases": {}
+        }), &mut warnings);
+
+        assert_eq!(warnings.messages(), &[
+            "[index] unsupported parameter [aliases] was ignored".to_string(),
+            "[settings] unsupported parameter [index.number_of_shards] was ignored".to_string(),
+            "[settings] unsupported parameter [index.refresh_interval] was ignored".to_string(),
+        ]);
+        assert_eq!(warnings.to_strict_error(), Some(json!({
+            "message": "[index] unsupported parameter [aliases] was ignored",
+            "pointer": "/aliases"
+        })));
+    }
+
+    #[test]
+    fn test_unsupported_setting_pointer() {
+        let mut warnings = ParseWarnings::new();
+        check_unsupported_settings(&json!({
+            "settings": {
+                "index": {
+                    "number_of_replicas": 0
+                }
+            }
+        }), &mut warnings);
+
+        assert_eq!(warnings.to_strict_error(), Some(json!({
+            "message": "[settings] unsupported parameter [index.number_of_replicas] was ignored",
+            "pointer": "/settings/index/number_of_replicas"
+        })));
+    }
+
+    #[test]
+    fn test_index_settings_round_trip() {
+        let mut metadata = IndexMetadata::default();
+        metadata.set_created(Uuid::new_v4(), Utc.timestamp(1500000000, 123000000));
+
+        let mut loaded_metadata = IndexMetadata::default();
+        parse(&mut loaded_metadata, serde_json::to_value(&metadata).unwrap()).expect("parse() returned an error");
+
+        assert_eq!(loaded_metadata.creation_date, Some(1500000000123));
+        assert_eq!(loaded_metadata.uuid, metadata.uuid);
+        assert_eq!(loaded_metadata.version_created, metadata.version_created);
+    }
+
+    #[test]
+    fn test_invalid_uuid() {
+        let mut metadata = IndexMetadata::default();
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "index": {
+                    "uuid": "foo"
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::InvalidIndexSetting("uuid".to_string()));
+    }
+
+    #[test]
+    fn test_query_settings() {
+        let mut metadata = IndexMetadata::default();
+        parse(&mut metadata, json!({
+            "settings": {
+                "index": {
+                    "query": {
+                        "default_field": "title",
+                        "default_operator": "AND"
+                    }
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.default_field, Some("title".to_string()));
+        assert_eq!(metadata.default_operator, Some(Operator::And));
+
+        // Check they're saved
+        let mut loaded_metadata = IndexMetadata::default();
+        parse(&mut loaded_metadata, serde_json::to_value(&metadata).unwrap()).expect("parse() returned an error");
+        assert_eq!(loaded_metadata.default_field, Some("title".to_string()));
+        assert_eq!(loaded_metadata.default_operator, Some(Operator::And));
+    }
+
+    #[test]
+    fn test_invalid_default_operator() {
+        let mut metadata = IndexMetadata::default();
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "index.query.default_operator": "xor"
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::InvalidIndexSetting("query.default_operator".to_string()));
+    }
+
+    #[test]
+    fn test_settings_update() {
+        let mut metadata = IndexMetadata::default();
+        parse_settings_update(&mut metadata, &json!({
+            "index": {
+                "query": {
+                    "default_field": "title"
+                }
+            },
+            "index.query.default_operator": "and"
+        })).expect("parse_settings_update() returned an error");
+
+        assert_eq!(metadata.default_field, Some("title".to_string()));
+        assert_eq!(metadata.default_operator, Some(Operator::And));
+
+        // Null resets a setting to its default
+        parse_settings_update(&mut metadata, &json!({
+            "settings": {
+                "index.query.default_field": null
+            }
+        })).expect("parse_settings_update() returned an error");
+
+        assert_eq!(metadata.default_field, None);
+        assert_eq!(metadata.default_operator, Some(Operator::And));
+    }
+
+    #[test]
+    fn test_settings_update_non_dynamic() {
+        let mut metadata = IndexMetadata::default();
+        let error = parse_settings_update(&mut metadata, &json!({
+            "index": {
+                "query": {
+                    "default_field": "title"
+                },
+                "number_of_shards": 3
+            }
+        })).err().expect("parse_settings_update() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::NonDynamicIndexSetting("index.number_of_shards".to_string()));
+
+        // Nothing is changed
+        assert_eq!(metadata.default_field, None);
+    }
+    #[test]
+    fn test_max_result_window() {
+        let mut metadata = IndexMetadata::default();
+        assert_eq!(metadata.max_result_window(), 10000);
+
+        parse(&mut metadata, json!({
+            "settings": {
+                "index": {
+                    "max_result_window": "50000"
+                }
+            }
+        })).expect("parse() returned an error");
+        assert_eq!(metadata.max_result_window(), 50000);
+
+        // Check it's saved
+        let mut loaded_metadata = IndexMetadata::default();
+        parse(&mut loaded_metadata, serde_json::to_value(&metadata).unwrap()).expect("parse() returned an error");
+        assert_eq!(loaded_metadata.max_result_window, Some(50000));
+
+        // It can be changed after the index is created
+        parse_settings_update(&mut metadata, &json!({"index.max_result_window": 100})).expect("parse_settings_update() returned an error");
+        assert_eq!(metadata.max_result_window(), 100);
+
+        parse_settings_update(&mut metadata, &json!({"index": {"max_result_window": null}})).expect("parse_settings_update() returned an error");
+        assert_eq!(metadata.max_result_window(), 10000);
+    }
+
+    #[test]
+    fn test_invalid_max_result_window() {
+        let mut metadata = IndexMetadata::default();
+        for value in vec![json!(0), json!(-1), json!("lots"), json!(true)] {
+            let error = parse_settings_update(&mut metadata, &json!({"index.max_result_window": value}))
+                .err().expect("parse_settings_update() was supposed to return an error, but didn't");
+
+            assert_eq!(error, IndexMetadataParseError::InvalidIndexSetting("max_result_window".to_string()));
+        }
+    }
+
+    #[test]
+    fn test_registered_filter_and_tokenizer() {
+        use std::sync::Arc;
+        use search::{Term, Token, AnalysisToken};
+        use analysis::registry::{AnalysisRegistry, TokenFilter, Tokenizer};
+
+        struct MaxLengthFilter(usize);
+
+        impl TokenFilter for MaxLengthFilter {
+            fn initialise<'a>(&self, input: Box<Iterator<Item=AnalysisToken<'a>> + 'a>) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a> {
+                let length = self.0;
+                Box::new(input.filter(move |token| token.text.len() <= length))
+            }
+        }
+
+        struct CommaTokenizer;
+
+        impl Tokenizer for CommaTokenizer {
+            fn initialise<'a>(&self, input: &'a str) -> Box<Iterator<Item=AnalysisToken<'a>> + 'a> {
+                Box::new(input.split(',').enumerate().map(|(i, word)| AnalysisToken::new(word, i as u32 + 1)))
+            }
+        }
+
+        let registry = Arc::new(AnalysisRegistry::new());
+        registry.register_filter("max_length", |definition| {
+            Ok(Box::new(MaxLengthFilter(definition.get("length").and_then(|length| length.as_u64()).unwrap_or(10) as usize)))
+        });
+        registry.register_tokenizer("comma", |_| Ok(Box::new(CommaTokenizer)));
+
+        // The filter is defined in the settings, the tokenizer is used by name
+        let settings = json!({
+            "settings": {
+                "analysis": {
+                    "filter": {
+                        "short_words": {
+                            "type": "max_length",
+                            "length": 3
+                        }
+                    },
+                    "analyzer": {
+                        "short": {
+                            "type": "custom",
+                            "tokenizer": "comma",
+                            "filter": ["short_words", "lowercase"]
+                        }
+                    }
+                }
+            }
+        });
+
+        let mut metadata = IndexMetadata::with_analysis_registry(registry.clone());
+        parse(&mut metadata, settings.clone()).expect("parse() returned an error");
+
+        let analyzer = metadata.analyzers().get("short").expect("'short' analyzer wasn't created");
+        assert_eq!(analyzer.initialise("Foo,quux,BAR").collect::<Vec<_>>(), vec![
+            Token { term: Term::from_string("foo"), position: 1 },
+            Token { term: Term::from_string("bar"), position: 3 },
+        ]);
+
+        // The filter is saved with its original definition
+        let saved = serde_json::to_value(&metadata).unwrap();
+        assert_eq!(saved["settings"]["analysis"]["filters"]["short_words"], json!({"type": "max_length", "length": 3}));
+
+        // The types can't be used without the registry
+        let error = parse(&mut IndexMetadata::default(), settings).err().expect("parse() was supposed to return an error, but didn't");
+        assert_eq!(error, IndexMetadataParseError::FilterParseError("short_words".to_string(), FilterParseError::UnrecognisedType("max_length".to_string())));
+    }
 }
@@ -1,13 +1,51 @@
+pub mod get;
+pub mod inflight;
 pub mod maintenance;
+pub mod merge_policy;
 pub mod metadata;
+pub mod request_cache;
+pub mod routing;
+pub mod store_cache;
 
-use std::sync::RwLock;
-use std::path::PathBuf;
+use std::sync::{Arc, RwLock, Mutex, MutexGuard};
+use std::path::{Path, PathBuf};
+use std::fs;
 
-use search::backends::rocksdb::RocksDBStore;
+use serde_json;
 use uuid::Uuid;
 
+use mapping::MappingProperty;
+use mapping::build::FieldMappingBuilder;
 use index::metadata::IndexMetadata;
+use index::store_cache::{IndexStore, StoreRef, StoreRefMut};
+use index::maintenance::MaintenanceScheduler;
+
+
+/// Finds the fields of a document that need to be added to a dynamic mapping
+fn find_dynamic_fields(metadata: &IndexMetadata, mapping_name: &str, data: &serde_json::Map<String, serde_json::Value>) -> Vec<(String, FieldMappingBuilder)> {
+    let mapping = match metadata.mappings.get(mapping_name) {
+        Some(mapping) if mapping.dynamic => mapping,
+        _ => return Vec::new(),
+    };
+
+    let mut new_fields = Vec::new();
+    for (field_name, field_value) in data.iter() {
+        if mapping.properties.contains_key(field_name) {
+            continue;
+        }
+
+        // The "_boost" field doesn't need to be mapped
+        if mapping.boost_field.as_ref().map(|boost_field| boost_field.name == *field_name).unwrap_or(false) {
+            continue;
+        }
+
+        if let Some((field_type, date_format)) = mapping.detect_field_type(field_value) {
+            new_fields.push((field_name.clone(), FieldMappingBuilder::dynamic(field_type, date_format)));
+        }
+    }
+
+    new_fields
+}
 
 
 #[derive(Debug)]
@@ -15,17 +53,29 @@ pub struct Index {
     id: Uuid,
     canonical_name: String,
     pub metadata: RwLock<IndexMetadata>,
-    pub store: RocksDBStore,
+    store: Arc<IndexStore>,
+
+    /// Held while a document is read, changed and reindexed
+    update_lock: Mutex<()>,
+
+    maintenance: MaintenanceScheduler,
+
+    /// The "read_only" block, nothing may write to the index. This is set on indices that
+    /// were attached from a directory outside of the node's data directory
+    read_only: bool,
 }
 
 
 impl Index {
-    pub fn new(id: Uuid, canonical_name: String, metadata: IndexMetadata, store: RocksDBStore) -> Index {
+    pub fn new(id: Uuid, canonical_name: String, metadata: IndexMetadata, store: Arc<IndexStore>) -> Index {
         Index {
             id: id,
             canonical_name: canonical_name,
             metadata: RwLock::new(metadata),
             store: store,
+            update_lock: Mutex::new(()),
+            maintenance: MaintenanceScheduler::new(),
+            read_only: false,
         }
     }
 
@@ -37,9 +87,139 @@ impl Index {
         &self.canonical_name
     }
 
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Blocks (or unblocks) writes to the index, this must be set before the index is shared
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Gets the store of the index, opening it if it has been closed
+    pub fn store(&self) -> Result<StoreRef, String> {
+        IndexStore::get(&self.store)
+    }
+
+    pub fn store_mut(&self) -> Result<StoreRefMut, String> {
+        IndexStore::get_mut(&self.store)
+    }
+
+    /// Gets the store of the index if it's currently open
+    pub fn store_if_open(&self) -> Option<StoreRef> {
+        self.store.get_if_open()
+    }
+
+    /// Stops other updates to documents in the index until the guard is dropped
+    ///
+    /// Updates read the current source of a document before reindexing it, so this
+    /// stops two of them from starting with the same source and losing a change.
+    pub fn lock_updates(&self) -> MutexGuard<()> {
+        self.update_lock.lock().unwrap()
+    }
+
+    /// Closes the store of the index, it will be opened again when it's next used
+    pub fn close_store(&self) {
+        self.store.close();
+    }
+
+    /// Adds any fields of a document that aren't in its mapping yet
+    ///
+    /// This only changes mappings with "dynamic" turned on, the type of each field is
+    /// detected from its value. Must be called before the metadata or store are locked
+    /// for indexing the document. Returns the names of the fields that were added.
+    pub fn add_dynamic_fields(&self, mapping_name: &str, data: &serde_json::Map<String, serde_json::Value>) -> Result<Vec<String>, String> {
+        // Most documents don't add any fields, so check without blocking other requests first
+        if find_dynamic_fields(&self.metadata.read().unwrap(), mapping_name, data).is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Check again, another request may have added them in the meantime
+        let mut metadata = self.metadata.write().unwrap();
+        let new_fields = find_dynamic_fields(&metadata, mapping_name, data);
+        if new_fields.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut field_mappings = new_fields.iter().map(|&(ref field_name, ref builder)| {
+            (field_name.clone(), builder.build(&metadata))
+        }).collect::<Vec<_>>();
+
+        // Add the fields to the store, they could already be there from another mapping
+        let mut store = self.store_mut()?;
+        for &mut (ref field_name, ref mut field_mapping) in field_mappings.iter_mut() {
+            let (field_type, field_flags) = field_mapping.schema_field();
+            let existing_field = {
+                let index_reader = store.reader();
+                let schema = index_reader.schema();
+
+                match schema.get_field_by_name(field_name) {
+                    Some(field_ref) => {
+                        let field_info = schema.get(&field_ref).expect("get_field_by_name returned an invalid FieldId");
+                        if field_info.field_type != field_type || field_info.field_flags != field_flags {
+                            return Err(format!("field \"{}\" is already mapped with a different type", field_name));
+                        }
+
+                        Some(field_ref)
+                    }
+                    None => None,
+                }
+            };
+
+            let field_ref = match existing_field {
+                Some(field_ref) => field_ref,
+                None => store.add_field(field_name.clone(), field_type, field_flags).map_err(|e| format!("{:?}", e))?,
+            };
+
+            field_mapping.index_ref = Some(field_ref);
+        }
+
+        let field_names = field_mappings.iter().map(|&(ref field_name, _)| field_name.clone()).collect::<Vec<_>>();
+        {
+            let mapping = metadata.mappings.get_mut(mapping_name).expect("find_dynamic_fields returned fields for a missing mapping");
+            for (field_name, field_mapping) in field_mappings {
+                mapping.properties.insert(field_name, MappingProperty::Field(field_mapping));
+            }
+        }
+
+        if let Err(error) = metadata.save(self.metadata_path()) {
+            // Take the fields out again so memory matches what was saved
+            let mapping = metadata.mappings.get_mut(mapping_name).expect("find_dynamic_fields returned fields for a missing mapping");
+            for field_name in field_names.iter() {
+                mapping.properties.remove(field_name);
+            }
+
+            return Err(String::from(error));
+        }
+
+        Ok(field_names)
+    }
+
+    /// The directory that holds the index's store and metadata
+    pub fn path(&self) -> &Path {
+        self.store.path()
+    }
+
     pub fn metadata_path(&self) -> PathBuf {
         let mut path = self.store.path().to_path_buf();
         path.push("metadata.json");
         path
     }
+
+    /// Writes a consistent copy of the index's store and metadata into a new directory
+    ///
+    /// The metadata is locked while the copy is made, so fields can't be added to the
+    /// store that the copied metadata doesn't know about. Returns the number of keys
+    /// copied from the store.
+    pub fn checkpoint(&self, path: &Path) -> Result<u64, String> {
+        let _metadata = self.metadata.read().unwrap();
+        let store = self.store()?;
+        let keys_copied = store.checkpoint(path)?;
+
+        let mut metadata_path = path.to_path_buf();
+        metadata_path.push("metadata.json");
+        fs::copy(self.metadata_path(), metadata_path).map_err(|e| format!("unable to copy metadata: {}", e))?;
+
+        Ok(keys_copied)
+    }
 }
@@ -0,0 +1,293 @@
+//! Caches the responses of requests that don't return any hits
+//!
+//! Dashboards tend to run the same counts over and over. When a request asks for it
+//! with "request_cache=true", the responses of count requests and searches with
+//! "size=0" are kept in memory. They're keyed by the index, the epoch of its store
+//! (which changes whenever the contents of the store do) and the request normalised
+//! into a string.
+//!
+//! Cached responses are never stale: once an index has been written to, requests look
+//! for responses with the new epoch. Responses for older epochs are removed the next
+//! time a response is cached for the index, or when they're the least recently used.
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use fnv::FnvHashMap;
+use uuid::Uuid;
+
+use query_parser::paging::Paging;
+
+
+/// The default amount of memory the node may use for cached responses
+pub const DEFAULT_REQUEST_CACHE_SIZE: usize = 16 * 1024 * 1024;
+
+
+/// Checks if the response of a search can be cached
+///
+/// Only searches that don't return any hits are cached. The page must be the one the
+/// search uses after reading both its body and its URL parameters. Searches that
+/// requested collectors by name are never cached, as these may do more than build
+/// their results.
+pub fn is_cacheable_search(paging: &Paging, has_named_collectors: bool) -> bool {
+    paging.size == 0 && !has_named_collectors
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestCacheKey {
+    pub index_id: Uuid,
+
+    /// The epoch of the index's store when the response was built
+    pub epoch: u64,
+
+    /// The request, including its body and any parameters that change the response
+    pub request: String,
+}
+
+
+impl RequestCacheKey {
+    fn memory_size(&self) -> usize {
+        mem::size_of::<RequestCacheKey>() + self.request.capacity()
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestCacheStats {
+    pub memory_size: usize,
+    pub evictions: u64,
+    pub hit_count: u64,
+    pub miss_count: u64,
+}
+
+
+#[derive(Debug)]
+struct CacheEntry {
+    response: Arc<String>,
+    memory_size: usize,
+    last_used: u64,
+}
+
+
+#[derive(Debug)]
+struct CacheState {
+    entries: FnvHashMap<RequestCacheKey, CacheEntry>,
+    clock: u64,
+    stats: RequestCacheStats,
+}
+
+
+impl CacheState {
+    fn remove(&mut self, key: &RequestCacheKey) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.stats.memory_size -= entry.memory_size;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+
+/// Caches serialised responses, evicting the least recently used ones when full
+#[derive(Debug)]
+pub struct RequestCache {
+    limit: usize,
+    state: Mutex<CacheState>,
+}
+
+
+impl RequestCache {
+    pub fn new(limit: usize) -> RequestCache {
+        RequestCache {
+            limit: limit,
+            state: Mutex::new(CacheState {
+                entries: FnvHashMap::default(),
+                clock: 0,
+                stats: RequestCacheStats::default(),
+            }),
+        }
+    }
+
+    /// Retrieves a cached response
+    pub fn get(&self, key: &RequestCacheKey) -> Option<Arc<String>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+
+        let response = state.entries.get_mut(key).map(|entry| {
+            entry.last_used = clock;
+            entry.response.clone()
+        });
+
+        if response.is_some() {
+            state.stats.hit_count += 1;
+        } else {
+            state.stats.miss_count += 1;
+        }
+
+        response
+    }
+
+    /// Caches a response, removing any responses cached for older epochs of the index
+    pub fn insert(&self, key: RequestCacheKey, response: String) {
+        let memory_size = key.memory_size() + response.capacity();
+
+        // Don't cache anything that would take up the whole cache by itself
+        if memory_size > self.limit {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        // The index has changed since these were cached, so they'll never be used again
+        let stale_keys = state.entries.keys().filter(|other_key| other_key.index_id == key.index_id && other_key.epoch < key.epoch).cloned().collect::<Vec<_>>();
+        for stale_key in stale_keys {
+            state.remove(&stale_key);
+        }
+
+        // Evict least recently used entries until there's enough space
+        state.remove(&key);
+        while state.stats.memory_size + memory_size > self.limit {
+            let lru_key = match state.entries.iter().min_by_key(|&(_, entry)| entry.last_used) {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+
+            if state.remove(&lru_key) {
+                state.stats.evictions += 1;
+            }
+        }
+
+        state.clock += 1;
+        let clock = state.clock;
+        state.stats.memory_size += memory_size;
+        state.entries.insert(key, CacheEntry {
+            response: Arc::new(response),
+            memory_size: memory_size,
+            last_used: clock,
+        });
+    }
+
+    /// Removes every response cached for an index
+    pub fn invalidate_index(&self, index_id: &Uuid) {
+        let mut state = self.state.lock().unwrap();
+        let keys = state.entries.keys().filter(|key| key.index_id == *index_id).cloned().collect::<Vec<_>>();
+
+        for key in keys {
+            state.remove(&key);
+        }
+    }
+
+    pub fn stats(&self) -> RequestCacheStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use query_parser::paging::parse as parse_paging;
+
+    use super::{RequestCache, RequestCacheKey, is_cacheable_search};
+
+    fn make_key(index_id: Uuid, epoch: u64, request: &str) -> RequestCacheKey {
+        RequestCacheKey {
+            index_id: index_id,
+            epoch: epoch,
+            request: request.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_get_and_insert() {
+        let cache = RequestCache::new(1024 * 1024);
+        let index_id = Uuid::new_v4();
+
+        assert_eq!(cache.get(&make_key(index_id, 1, "count")), None);
+
+        cache.insert(make_key(index_id, 1, "count"), "{\"count\":3}".to_string());
+        assert_eq!(cache.get(&make_key(index_id, 1, "count")).map(|response| (*response).clone()), Some("{\"count\":3}".to_string()));
+
+        // Different request
+        assert_eq!(cache.get(&make_key(index_id, 1, "search")), None);
+
+        let stats = cache.stats();
+        assert_eq!(stats.hit_count, 1);
+        assert_eq!(stats.miss_count, 2);
+    }
+
+    #[test]
+    fn test_new_epoch_misses() {
+        let cache = RequestCache::new(1024 * 1024);
+        let index_id = Uuid::new_v4();
+
+        cache.insert(make_key(index_id, 1, "count"), "{\"count\":3}".to_string());
+        assert_eq!(cache.get(&make_key(index_id, 2, "count")), None);
+
+        // Caching a response for the new epoch removes the old one
+        cache.insert(make_key(index_id, 2, "count"), "{\"count\":4}".to_string());
+        assert_eq!(cache.get(&make_key(index_id, 1, "count")), None);
+        assert_eq!(cache.stats().memory_size, make_key(index_id, 2, "count").memory_size() + "{\"count\":4}".to_string().capacity());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let index_id = Uuid::new_v4();
+        let entry_size = make_key(index_id, 1, "a").memory_size() + "response".to_string().capacity();
+        let cache = RequestCache::new(entry_size * 2);
+
+        cache.insert(make_key(index_id, 1, "a"), "response".to_string());
+        cache.insert(make_key(index_id, 1, "b"), "response".to_string());
+
+        // Use "a" so "b" becomes the least recently used
+        cache.get(&make_key(index_id, 1, "a"));
+        cache.insert(make_key(index_id, 1, "c"), "response".to_string());
+
+        assert_eq!(cache.stats().evictions, 1);
+        assert!(cache.get(&make_key(index_id, 1, "a")).is_some());
+        assert!(cache.get(&make_key(index_id, 1, "b")).is_none());
+    }
+
+    #[test]
+    fn test_invalidate_index() {
+        let cache = RequestCache::new(1024 * 1024);
+        let index_id = Uuid::new_v4();
+        let other_index_id = Uuid::new_v4();
+
+        cache.insert(make_key(index_id, 1, "count"), "{\"count\":3}".to_string());
+        cache.insert(make_key(other_index_id, 1, "count"), "{\"count\":5}".to_string());
+        cache.invalidate_index(&index_id);
+
+        assert!(cache.get(&make_key(index_id, 1, "count")).is_none());
+        assert!(cache.get(&make_key(other_index_id, 1, "count")).is_some());
+    }
+    #[test]
+    fn test_size_in_body_is_cached() {
+        let cache = RequestCache::new(1024 * 1024);
+        let index_id = Uuid::new_v4();
+        let body = json!({"size": 0, "aggs": {"tags": {"terms": {"field": "tag"}}}});
+
+        // The size of the body is used, a search would return 10 hits without it
+        let paging = parse_paging(&body, 10).unwrap();
+        assert!(is_cacheable_search(&paging, false));
+
+        let key = make_key(index_id, 1, &body.to_string());
+        cache.insert(key.clone(), "{\"aggregations\":{}}".to_string());
+        assert_eq!(cache.get(&key).map(|response| (*response).clone()), Some("{\"aggregations\":{}}".to_string()));
+        assert_eq!(cache.stats().hit_count, 1);
+    }
+
+    #[test]
+    fn test_searches_returning_hits_arent_cached() {
+        let paging = parse_paging(&json!({"aggs": {"tags": {"terms": {"field": "tag"}}}}), 10).unwrap();
+        assert!(!is_cacheable_search(&paging, false));
+
+        let paging = parse_paging(&json!({"size": 0}), 10).unwrap();
+        assert!(!is_cacheable_search(&paging, true));
+    }
+}
@@ -0,0 +1,129 @@
+//! Search routing
+//!
+//! Decides which copy of an index a search request is sent to. Indices currently
+//! only have a single copy, but requests already carry a preference so that, once
+//! replicas exist, repeated searches from the same user session hit the same copy
+//! and see consistent results.
+
+use std::hash::{Hash, Hasher};
+
+use fnv::FnvHasher;
+use index::Index;
+use index::store_cache::StoreRef;
+
+
+/// Value of the "preference" parameter of a search request
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchPreference {
+    /// Any copy may be used (the default)
+    Any,
+
+    /// Prefer copies held by the node that received the request
+    Local,
+
+    /// Only use the primary copy
+    Primary,
+
+    /// Any string not starting with an underscore. Requests with the same string are
+    /// always routed to the same copy
+    Custom(String),
+}
+
+
+impl Default for SearchPreference {
+    fn default() -> SearchPreference {
+        SearchPreference::Any
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum SearchPreferenceParseError {
+    UnrecognisedPreference(String),
+}
+
+
+impl SearchPreference {
+    pub fn parse(preference: &str) -> Result<SearchPreference, SearchPreferenceParseError> {
+        match preference {
+            "" => Ok(SearchPreference::Any),
+            "_local" => Ok(SearchPreference::Local),
+            "_primary" => Ok(SearchPreference::Primary),
+            _ => {
+                if preference.starts_with('_') {
+                    return Err(SearchPreferenceParseError::UnrecognisedPreference(preference.to_string()));
+                }
+
+                Ok(SearchPreference::Custom(preference.to_string()))
+            }
+        }
+    }
+
+    /// Picks one of num_copies copies of an index, where copy 0 is the primary
+    pub fn select_copy(&self, num_copies: usize) -> usize {
+        if num_copies == 0 {
+            return 0;
+        }
+
+        match *self {
+            SearchPreference::Any | SearchPreference::Local | SearchPreference::Primary => 0,
+            SearchPreference::Custom(ref value) => {
+                // FNV is used as its output doesn't change between releases, so sessions
+                // keep hitting the same copy across restarts
+                let mut hasher = FnvHasher::default();
+                value.hash(&mut hasher);
+                (hasher.finish() % num_copies as u64) as usize
+            }
+        }
+    }
+}
+
+
+impl Index {
+    /// Returns the number of copies of this index that can serve searches
+    pub fn num_copies(&self) -> usize {
+        1
+    }
+
+    /// Returns the store that should serve a search with the given preference
+    pub fn store_for_search(&self, preference: &SearchPreference) -> Result<StoreRef, String> {
+        // Only the primary copy exists for now
+        debug_assert_eq!(preference.select_copy(self.num_copies()), 0);
+        self.store()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{SearchPreference, SearchPreferenceParseError};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(SearchPreference::parse(""), Ok(SearchPreference::Any));
+        assert_eq!(SearchPreference::parse("_local"), Ok(SearchPreference::Local));
+        assert_eq!(SearchPreference::parse("_primary"), Ok(SearchPreference::Primary));
+        assert_eq!(SearchPreference::parse("user-1234"), Ok(SearchPreference::Custom("user-1234".to_string())));
+    }
+
+    #[test]
+    fn test_parse_unrecognised() {
+        assert_eq!(SearchPreference::parse("_foo"), Err(SearchPreferenceParseError::UnrecognisedPreference("_foo".to_string())));
+    }
+
+    #[test]
+    fn test_select_copy() {
+        assert_eq!(SearchPreference::Primary.select_copy(3), 0);
+
+        // Custom preferences should always pick the same copy
+        let preference = SearchPreference::Custom("user-1234".to_string());
+        let copy = preference.select_copy(3);
+        assert!(copy < 3);
+        for _ in 0..10 {
+            assert_eq!(preference.select_copy(3), copy);
+        }
+
+        assert_eq!(preference.select_copy(1), 0);
+        assert_eq!(preference.select_copy(0), 0);
+    }
+}
@@ -0,0 +1,320 @@
+//! Lazily opened index stores
+//!
+//! Each open RocksDB store holds file handles and caches, so opening every index
+//! at startup doesn't scale to nodes with hundreds of them. Instead, stores are
+//! opened the first time they're used and the store cache closes the least
+//! recently used ones once too many are open.
+//!
+//! A store is only closed while nothing is using it. If every open store is in use,
+//! the limit is exceeded until some of them are released.
+
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use search::backends::rocksdb::RocksDBStore;
+
+
+/// The number of stores that may be open at the same time, unless configured otherwise
+pub const DEFAULT_MAX_OPEN_STORES: usize = 100;
+
+
+#[derive(Debug)]
+pub struct StoreCache {
+    max_open: usize,
+
+    /// The open stores, least recently used first
+    open_stores: Mutex<VecDeque<Weak<IndexStore>>>,
+}
+
+
+impl StoreCache {
+    pub fn new(max_open: usize) -> StoreCache {
+        StoreCache {
+            max_open: max_open,
+            open_stores: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn max_open(&self) -> usize {
+        self.max_open
+    }
+
+    /// Returns the number of stores that are currently open
+    pub fn num_open(&self) -> usize {
+        self.open_stores.lock().unwrap().len()
+    }
+
+    /// Moves a store to the back of the queue
+    fn touch(&self, store: &IndexStore) {
+        let mut open_stores = self.open_stores.lock().unwrap();
+
+        if let Some(position) = open_stores.iter().position(|open_store| is_same_store(open_store, store)) {
+            if let Some(open_store) = open_stores.remove(position) {
+                open_stores.push_back(open_store);
+            }
+        }
+    }
+
+    /// Adds a newly opened store, closing the least recently used stores if there are too many
+    fn insert(&self, store: &Arc<IndexStore>) {
+        let mut open_stores = self.open_stores.lock().unwrap();
+        open_stores.retain(|open_store| !is_same_store(open_store, store));
+        open_stores.push_back(Arc::downgrade(store));
+
+        let mut position = 0;
+        while open_stores.len() > self.max_open && position < open_stores.len() - 1 {
+            let closed = match open_stores[position].upgrade() {
+                Some(open_store) => open_store.try_close(),
+
+                // The index has been deleted
+                None => true,
+            };
+
+            if closed {
+                open_stores.remove(position);
+            } else {
+                position += 1;
+            }
+        }
+    }
+
+    /// Forgets a store that has been closed
+    fn remove(&self, store: &IndexStore) {
+        let mut open_stores = self.open_stores.lock().unwrap();
+        open_stores.retain(|open_store| !is_same_store(open_store, store));
+    }
+}
+
+
+fn is_same_store(open_store: &Weak<IndexStore>, store: &IndexStore) -> bool {
+    match open_store.upgrade() {
+        Some(open_store) => &*open_store as *const IndexStore == store as *const IndexStore,
+        None => false,
+    }
+}
+
+
+/// The store of an index, which may or may not be open
+#[derive(Debug)]
+pub struct IndexStore {
+    path: PathBuf,
+    store: RwLock<Option<RocksDBStore>>,
+    cache: Arc<StoreCache>,
+}
+
+
+impl IndexStore {
+    /// Creates an index store that is opened when it's first used
+    pub fn new(path: PathBuf, cache: Arc<StoreCache>) -> Arc<IndexStore> {
+        Arc::new(IndexStore {
+            path: path,
+            store: RwLock::new(None),
+            cache: cache,
+        })
+    }
+
+    /// Creates an index store from a store that's already open
+    pub fn from_open_store(store: RocksDBStore, cache: Arc<StoreCache>) -> Arc<IndexStore> {
+        let index_store = Arc::new(IndexStore {
+            path: store.path().to_path_buf(),
+            store: RwLock::new(Some(store)),
+            cache: cache.clone(),
+        });
+
+        cache.insert(&index_store);
+        index_store
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.store.read().unwrap().is_some()
+    }
+
+    /// Opens the store if it isn't open already
+    fn open(this: &Arc<IndexStore>) -> Result<(), String> {
+        {
+            let mut store = this.store.write().unwrap();
+            if store.is_some() {
+                return Ok(());
+            }
+
+            *store = Some(RocksDBStore::open(&this.path)?);
+        }
+
+        this.cache.insert(this);
+        Ok(())
+    }
+
+    /// Closes the store if nothing is using it, returns true if the store is now closed
+    fn try_close(&self) -> bool {
+        match self.store.try_write() {
+            Ok(mut store) => {
+                *store = None;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Gets the store, opening it if necessary
+    ///
+    /// The store can't be closed while the returned reference is alive.
+    pub fn get(this: &Arc<IndexStore>) -> Result<StoreRef, String> {
+        loop {
+            {
+                let store = this.store.read().unwrap();
+                if store.is_some() {
+                    this.cache.touch(this);
+                    return Ok(StoreRef(store));
+                }
+            }
+
+            // The store could be closed again before the read lock is taken, in
+            // which case it's just opened again
+            IndexStore::open(this)?;
+        }
+    }
+
+    /// Gets the store for modification, opening it if necessary
+    pub fn get_mut(this: &Arc<IndexStore>) -> Result<StoreRefMut, String> {
+        loop {
+            {
+                let store = this.store.write().unwrap();
+                if store.is_some() {
+                    this.cache.touch(this);
+                    return Ok(StoreRefMut(store));
+                }
+            }
+
+            IndexStore::open(this)?;
+        }
+    }
+
+    /// Gets the store if it's open, without marking it as used
+    ///
+    /// This is for background tasks that shouldn't keep unused indices open.
+    pub fn get_if_open(&self) -> Option<StoreRef> {
+        let store = self.store.read().unwrap();
+        if store.is_some() {
+            Some(StoreRef(store))
+        } else {
+            None
+        }
+    }
+
+    /// Closes the store, waiting for any requests that are using it to finish
+    pub fn close(&self) {
+        *self.store.write().unwrap() = None;
+        self.cache.remove(self);
+    }
+}
+
+
+/// A reference to an open store
+pub struct StoreRef<'a>(RwLockReadGuard<'a, Option<RocksDBStore>>);
+
+
+impl<'a> Deref for StoreRef<'a> {
+    type Target = RocksDBStore;
+
+    fn deref(&self) -> &RocksDBStore {
+        self.0.as_ref().unwrap()
+    }
+}
+
+
+/// A mutable reference to an open store
+pub struct StoreRefMut<'a>(RwLockWriteGuard<'a, Option<RocksDBStore>>);
+
+
+impl<'a> Deref for StoreRefMut<'a> {
+    type Target = RocksDBStore;
+
+    fn deref(&self) -> &RocksDBStore {
+        self.0.as_ref().unwrap()
+    }
+}
+
+
+impl<'a> DerefMut for StoreRefMut<'a> {
+    fn deref_mut(&mut self) -> &mut RocksDBStore {
+        self.0.as_mut().unwrap()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use search::backends::rocksdb::RocksDBStore;
+
+    use super::{StoreCache, IndexStore};
+
+    fn make_index_store(path: &str, cache: &Arc<StoreCache>) -> Arc<IndexStore> {
+        let _ = remove_dir_all(path);
+        RocksDBStore::create(path).unwrap();
+        IndexStore::new(PathBuf::from(path), cache.clone())
+    }
+
+    #[test]
+    fn test_lazy_open() {
+        let cache = Arc::new(StoreCache::new(2));
+        let store = make_index_store("test_indices/test_store_cache_lazy_open", &cache);
+
+        assert!(!store.is_open());
+        assert_eq!(cache.num_open(), 0);
+
+        IndexStore::get(&store).unwrap();
+
+        assert!(store.is_open());
+        assert_eq!(cache.num_open(), 1);
+    }
+
+    #[test]
+    fn test_closes_least_recently_used() {
+        let cache = Arc::new(StoreCache::new(2));
+        let store_a = make_index_store("test_indices/test_store_cache_lru_a", &cache);
+        let store_b = make_index_store("test_indices/test_store_cache_lru_b", &cache);
+        let store_c = make_index_store("test_indices/test_store_cache_lru_c", &cache);
+
+        IndexStore::get(&store_a).unwrap();
+        IndexStore::get(&store_b).unwrap();
+
+        // Use "a" again so "b" becomes the least recently used
+        IndexStore::get(&store_a).unwrap();
+        IndexStore::get(&store_c).unwrap();
+
+        assert!(store_a.is_open());
+        assert!(!store_b.is_open());
+        assert!(store_c.is_open());
+        assert_eq!(cache.num_open(), 2);
+
+        // Closed stores are opened again when they're next used
+        IndexStore::get(&store_b).unwrap();
+        assert!(store_b.is_open());
+        assert!(!store_a.is_open());
+    }
+
+    #[test]
+    fn test_store_in_use_is_not_closed() {
+        let cache = Arc::new(StoreCache::new(1));
+        let store_a = make_index_store("test_indices/test_store_cache_in_use_a", &cache);
+        let store_b = make_index_store("test_indices/test_store_cache_in_use_b", &cache);
+
+        let _store_a_ref = IndexStore::get(&store_a).unwrap();
+        IndexStore::get(&store_b).unwrap();
+
+        // "a" is still being used so the limit is exceeded for now
+        assert!(store_a.is_open());
+        assert!(store_b.is_open());
+        assert_eq!(cache.num_open(), 2);
+    }
+}
@@ -0,0 +1,96 @@
+//! JSON pointers (RFC 6901) to parts of a request body
+//!
+//! These are returned in the errors of requests made in strict mode, so the client can
+//! see exactly which key wasn't recognised, eg "/query/match/title/fuzziness".
+
+use serde_json::Value as Json;
+
+
+/// Escapes a key so it can be used as a token of a pointer, "~" and "/" have special meanings
+pub fn escape_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+
+/// Adds a key to the end of a pointer
+pub fn join(pointer: &str, key: &str) -> String {
+    format!("{}/{}", pointer, escape_token(key))
+}
+
+
+/// Finds the pointer to the first occurrence of a key
+///
+/// The keys of an object are checked before the objects inside it, so a key isn't found
+/// deep inside one value when the object itself has it.
+pub fn find_key(json: &Json, key: &str) -> Option<String> {
+    match *json {
+        Json::Object(ref object) => {
+            if object.contains_key(key) {
+                return Some(join("", key));
+            }
+
+            for (child_key, child) in object.iter() {
+                if let Some(pointer) = find_key(child, key) {
+                    return Some(join("", child_key) + &pointer);
+                }
+            }
+
+            None
+        }
+        Json::Array(ref array) => {
+            for (index, child) in array.iter().enumerate() {
+                if let Some(pointer) = find_key(child, key) {
+                    return Some(format!("/{}{}", index, pointer));
+                }
+            }
+
+            None
+        }
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_token, join, find_key};
+
+    #[test]
+    fn test_escape_token() {
+        assert_eq!(escape_token("title"), "title");
+        assert_eq!(escape_token("a/b~c"), "a~1b~0c");
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(join("", "query"), "/query");
+        assert_eq!(join("/mappings", "my/type"), "/mappings/my~1type");
+    }
+
+    #[test]
+    fn test_find_key() {
+        let json = json!({
+            "query": {
+                "bool": {
+                    "must": [
+                        {"match": {"title": "hello"}},
+                        {"match": {"body": {"query": "world", "fuzzyness": 2}}}
+                    ]
+                }
+            }
+        });
+
+        assert_eq!(find_key(&json, "fuzzyness"), Some("/query/bool/must/1/match/body/fuzzyness".to_string()));
+        assert_eq!(find_key(&json, "cutoff_frequency"), None);
+    }
+
+    #[test]
+    fn test_find_key_prefers_outer_keys() {
+        let json = json!({
+            "a": {"boost": 1},
+            "boost": 2
+        });
+
+        assert_eq!(find_key(&json, "boost"), Some("/boost".to_string()));
+    }
+}
@@ -0,0 +1,96 @@
+//! The rusticsearch search engine, without the HTTP server
+//!
+//! This crate can be embedded in other applications to index and search documents
+//! in-process. The HTTP server (in the "rusticsearch-server" crate) is a thin layer
+//! on top of it.
+//!
+//! A [`System`](system/struct.System.html) holds all the indices stored in a data
+//! directory. The indices and their aliases are in its cluster metadata:
+//!
+//! ```no_run
+//! #[macro_use]
+//! extern crate slog;
+//! #[macro_use]
+//! extern crate serde_json;
+//! extern crate rusticsearch;
+//!
+//! use std::collections::BTreeMap;
+//! use std::path::PathBuf;
+//!
+//! use rusticsearch::system::System;
+//! use rusticsearch::tenancy::Tenancy;
+//! use rusticsearch::settings::NodeSettings;
+//! use rusticsearch::watcher::Watcher;
+//! use rusticsearch::index::store_cache::DEFAULT_MAX_OPEN_STORES;
+//! use rusticsearch::query_parser::{QueryBuildContext, parse as parse_query};
+//! use rusticsearch::search::collectors::total_count::TotalCountCollector;
+//!
+//! fn main() {
+//!     let log = slog::Logger::root(slog::Discard, o!());
+//!     let system = System::new(log, PathBuf::from("data/"), DEFAULT_MAX_OPEN_STORES, NodeSettings::default(), Tenancy::disabled(), Watcher::new(BTreeMap::new(), None));
+//!     system.load_indices();
+//!
+//!     // Count the documents in the "blog" index that match a query
+//!     let cluster_metadata = system.metadata.read().unwrap();
+//!     let index = cluster_metadata.get_index("blog").expect("index not found");
+//!     let store = index.store().unwrap();
+//!     let reader = store.reader();
+//!     let index_metadata = index.metadata.read().unwrap();
+//!
+//!     let query = parse_query(&json!({"match": {"title": "hello"}})).unwrap();
+//!     let query = query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata).no_score(), &reader.schema());
+//!
+//!     let mut collector = TotalCountCollector::new();
+//!     reader.search(&mut collector, &query).unwrap();
+//!     println!("{} matching documents", collector.get_total_count());
+//! }
+//! ```
+//!
+//! Documents are indexed through the index's store, see
+//! [`RocksDBStore`](search/backends/rocksdb/struct.RocksDBStore.html).
+
+extern crate chrono;
+extern crate url;
+#[macro_use]
+extern crate slog;
+#[macro_use]
+extern crate maplit;
+extern crate unicode_segmentation;
+extern crate uuid;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate atomicwrites;
+extern crate fnv;
+#[macro_use]
+extern crate bitflags;
+extern crate roaring;
+extern crate byteorder;
+extern crate rocksdb;
+
+pub mod search;
+pub mod analysis;
+pub mod query_parser;
+pub mod json_pointer;
+pub mod mapping;
+pub mod document;
+pub mod index;
+pub mod cluster;
+pub mod system;
+pub mod bulk_queue;
+pub mod bulk_reader;
+pub mod tenancy;
+pub mod settings;
+pub mod trace;
+pub mod watcher;
+pub mod tasks;
+pub mod remote;
+pub mod update;
+pub mod bench;
+pub mod build_info;
+
+
+/// The version of rusticsearch
+pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
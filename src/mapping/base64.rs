@@ -0,0 +1,112 @@
+//! Base64 encoding for the values of binary fields (standard alphabet, with padding)
+
+const ALPHABET: &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+
+#[derive(Debug, PartialEq)]
+pub struct Base64DecodeError;
+
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+
+    for chunk in bytes.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = chunk.get(1).cloned().unwrap_or(0) as u32;
+        let b2 = chunk.get(2).cloned().unwrap_or(0) as u32;
+        let group = (b0 << 16) | (b1 << 8) | b2;
+
+        encoded.push(ALPHABET[(group >> 18) as usize & 0x3f] as char);
+        encoded.push(ALPHABET[(group >> 12) as usize & 0x3f] as char);
+
+        if chunk.len() > 1 {
+            encoded.push(ALPHABET[(group >> 6) as usize & 0x3f] as char);
+        } else {
+            encoded.push('=');
+        }
+
+        if chunk.len() > 2 {
+            encoded.push(ALPHABET[group as usize & 0x3f] as char);
+        } else {
+            encoded.push('=');
+        }
+    }
+
+    encoded
+}
+
+
+fn decode_char(c: u8) -> Result<u32, Base64DecodeError> {
+    match ALPHABET.iter().position(|a| *a == c) {
+        Some(position) => Ok(position as u32),
+        None => Err(Base64DecodeError),
+    }
+}
+
+
+/// Decodes a base64 string, the padding at the end may be left out
+pub fn decode(string: &str) -> Result<Vec<u8>, Base64DecodeError> {
+    let input = string.trim_right_matches('=').as_bytes();
+
+    // A single character left over can't encode a whole byte
+    if input.len() % 4 == 1 {
+        return Err(Base64DecodeError);
+    }
+
+    let mut decoded = Vec::with_capacity(input.len() * 3 / 4);
+
+    for chunk in input.chunks(4) {
+        let mut group = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            group |= decode_char(*c)? << (18 - i * 6);
+        }
+
+        decoded.push((group >> 16) as u8);
+
+        if chunk.len() > 2 {
+            decoded.push((group >> 8) as u8);
+        }
+
+        if chunk.len() > 3 {
+            decoded.push(group as u8);
+        }
+    }
+
+    Ok(decoded)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, decode, Base64DecodeError};
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(b"f"), "Zg==");
+        assert_eq!(encode(b"fo"), "Zm8=");
+        assert_eq!(encode(b"foo"), "Zm9v");
+        assert_eq!(encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(encode(&[0xff, 0xfe]), "//4=");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(""), Ok(b"".to_vec()));
+        assert_eq!(decode("Zg=="), Ok(b"f".to_vec()));
+        assert_eq!(decode("Zm8="), Ok(b"fo".to_vec()));
+        assert_eq!(decode("Zm9vYmFy"), Ok(b"foobar".to_vec()));
+        assert_eq!(decode("//4="), Ok(vec![0xff, 0xfe]));
+    }
+
+    #[test]
+    fn test_decode_without_padding() {
+        assert_eq!(decode("Zg"), Ok(b"f".to_vec()));
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(decode("Zm9v!"), Err(Base64DecodeError));
+        assert_eq!(decode("Zm9vY"), Err(Base64DecodeError));
+    }
+}
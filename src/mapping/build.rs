@@ -1,6 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
 
-use mapping::{Mapping, MappingProperty, FieldMapping, NestedMapping, FieldType, get_standard_analyzer};
+use search::similarity::VectorSimilarity;
+
+use analysis::collation::Collation;
+
+use mapping::{Mapping, MappingProperty, FieldMapping, NestedMapping, FieldType, BoostFieldMapping, IndexPrefixes, get_standard_analyzer, default_dynamic_date_formats};
 use index::metadata::IndexMetadata;
 
 
@@ -14,7 +18,31 @@ pub struct FieldMappingBuilder {
     pub boost: f64,
     pub base_analyzer: Option<String>,
     pub index_analyzer: Option<String>,
-    pub search_analyzer: Option<String>
+    pub search_analyzer: Option<String>,
+
+    /// Name of the normalizer applied to the whole value (keyword fields only)
+    pub normalizer: Option<String>,
+
+    /// Parent relation name to child relation names (join fields only)
+    pub relations: BTreeMap<String, Vec<String>>,
+
+    /// Number of values in each vector (dense vector fields only)
+    pub dims: Option<usize>,
+
+    /// How vectors are compared by knn queries (dense vector fields only)
+    pub vector_similarity: VectorSimilarity,
+
+    /// Other ways to index the value of this field, by sub field name
+    pub fields: BTreeMap<String, FieldMappingBuilder>,
+
+    /// Formats that dates can be given in, besides RFC 3339 (date fields only)
+    pub date_format: Option<String>,
+
+    /// Orders values by the conventions of a language when sorting (keyword fields only)
+    pub collation: Option<Collation>,
+
+    /// Lengths of the prefixes to index into a hidden sub field (analyzed fields only)
+    pub index_prefixes: Option<IndexPrefixes>,
 }
 
 
@@ -30,12 +58,30 @@ impl Default for FieldMappingBuilder {
             base_analyzer: None,
             index_analyzer: None,
             search_analyzer: None,
+            normalizer: None,
+            relations: BTreeMap::new(),
+            dims: None,
+            vector_similarity: VectorSimilarity::default(),
+            fields: BTreeMap::new(),
+            date_format: None,
+            collation: None,
+            index_prefixes: None,
         }
     }
 }
 
 
 impl FieldMappingBuilder {
+    /// Builder for a field that is added to a mapping when a document is indexed
+    pub fn dynamic(field_type: FieldType, date_format: Option<String>) -> FieldMappingBuilder {
+        FieldMappingBuilder {
+            field_type: field_type,
+            is_analyzed: field_type.uses_analyzer(),
+            date_format: date_format,
+            .. FieldMappingBuilder::default()
+        }
+    }
+
     pub fn build(&self, index_metadata: &IndexMetadata) -> FieldMapping {
         let base_analyzer = match self.base_analyzer {
             Some(ref base_analyzer) => {
@@ -81,6 +127,16 @@ impl FieldMappingBuilder {
             None
         };
 
+        let normalizer = match self.normalizer {
+            Some(ref normalizer_name) => {
+                match index_metadata.normalizers().get(normalizer_name) {
+                    Some(normalizer) => Some((normalizer_name.clone(), normalizer.clone())),
+                    None => None,  // TODO: error
+                }
+            }
+            None => None,
+        };
+
         FieldMapping {
             data_type: self.field_type,
             index_ref: None,
@@ -90,6 +146,14 @@ impl FieldMappingBuilder {
             boost: self.boost,
             index_analyzer: index_analyzer,
             search_analyzer: search_analyzer,
+            normalizer: normalizer,
+            relations: self.relations.clone(),
+            dims: self.dims,
+            vector_similarity: self.vector_similarity,
+            multi_fields: Vec::new(),
+            date_format: self.date_format.clone(),
+            collation: self.collation.clone(),
+            index_prefixes: self.index_prefixes,
         }
     }
 }
@@ -142,6 +206,27 @@ pub enum MappingPropertyBuilder {
 #[derive(Debug, PartialEq)]
 pub struct MappingBuilder {
     pub properties: HashMap<String, MappingPropertyBuilder>,
+    pub boost: f64,
+    pub boost_field: Option<BoostFieldMapping>,
+    pub dynamic: bool,
+    pub date_detection: bool,
+    pub numeric_detection: bool,
+    pub dynamic_date_formats: Vec<String>,
+}
+
+
+impl Default for MappingBuilder {
+    fn default() -> MappingBuilder {
+        MappingBuilder {
+            properties: HashMap::new(),
+            boost: 1.0f64,
+            boost_field: None,
+            dynamic: false,
+            date_detection: true,
+            numeric_detection: false,
+            dynamic_date_formats: default_dynamic_date_formats(),
+        }
+    }
 }
 
 
@@ -152,7 +237,22 @@ impl MappingBuilder {
         for (field_name, builder) in self.properties.iter() {
             match *builder {
                 MappingPropertyBuilder::Field(ref field_builder) => {
-                     properties.insert(field_name.to_string(), MappingProperty::Field(field_builder.build(index_metadata)));
+                    let mut field_mapping = field_builder.build(index_metadata);
+
+                    // Multi-fields are added alongside the field, it keeps a list of their names
+                    // so they can be given the same value
+                    for (sub_field_name, sub_field_builder) in field_builder.fields.iter() {
+                        let multi_field_name = format!("{}.{}", field_name, sub_field_name);
+                        properties.insert(multi_field_name.clone(), MappingProperty::Field(sub_field_builder.build(index_metadata)));
+                        field_mapping.multi_fields.push(multi_field_name);
+                    }
+
+                    // Prefixes are indexed into a sub field that isn't listed in the mapping
+                    if let Some(prefix_field_mapping) = field_mapping.index_prefixes_field_mapping() {
+                        properties.insert(IndexPrefixes::sub_field_name(field_name), MappingProperty::Field(prefix_field_mapping));
+                    }
+
+                    properties.insert(field_name.to_string(), MappingProperty::Field(field_mapping));
                 }
                 MappingPropertyBuilder::NestedMapping(ref nested_mapping_builder) => {
                     properties.insert(field_name.to_string(), MappingProperty::NestedMapping(Box::new(nested_mapping_builder.build(index_metadata))));
@@ -175,8 +275,53 @@ impl MappingBuilder {
             ));
         }
 
+        // Insert _type and _id fields
+        // These are filled in from the document's metadata rather than its source. They
+        // are indexed without analysis so they can be searched with the "type" and "ids"
+        // queries
+        if !properties.contains_key("_type") {
+            properties.insert("_type".to_string(), MappingProperty::Field(
+                FieldMapping {
+                    data_type: FieldType::String,
+                    is_stored: true,
+                    is_in_all: false,
+                    .. FieldMapping::default()
+                }
+            ));
+        }
+
+        if !properties.contains_key("_id") {
+            properties.insert("_id".to_string(), MappingProperty::Field(
+                FieldMapping {
+                    data_type: FieldType::String,
+                    is_in_all: false,
+                    .. FieldMapping::default()
+                }
+            ));
+        }
+
+        // Insert _source field
+        // This keeps the original JSON of the document so it can be returned in search hits
+        if !properties.contains_key("_source") {
+            properties.insert("_source".to_string(), MappingProperty::Field(
+                FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    .. FieldMapping::default()
+                }
+            ));
+        }
+
         Mapping {
             properties: properties,
+            boost: self.boost,
+            boost_field: self.boost_field.clone(),
+            dynamic: self.dynamic,
+            date_detection: self.date_detection,
+            numeric_detection: self.numeric_detection,
+            dynamic_date_formats: self.dynamic_date_formats.clone(),
         }
     }
 }
@@ -187,7 +332,10 @@ mod tests {
     use analysis::AnalyzerSpec;
     use analysis::tokenizers::TokenizerSpec;
     use analysis::filters::FilterSpec;
-    use mapping::{Mapping, MappingProperty, FieldMapping, FieldType, get_standard_analyzer};
+    use chrono::{TimeZone, Utc};
+    use search::{Term, Token};
+    use search::document::FieldValue;
+    use mapping::{Mapping, MappingProperty, FieldMapping, FieldType, IndexPrefixes, get_standard_analyzer};
     use index::metadata::IndexMetadata;
 
     use super::{MappingBuilder, MappingPropertyBuilder, FieldMappingBuilder};
@@ -206,6 +354,7 @@ mod tests {
                     }
                 )
             },
+            ..MappingBuilder::default()
         };
 
         let mapping = builder.build(&index_metadata);
@@ -226,16 +375,125 @@ mod tests {
                     index_analyzer: Some(get_standard_analyzer()),
                     search_analyzer: Some(get_standard_analyzer()),
                     ..FieldMapping::default()
+                }),
+                "_type".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_id".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_source".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
                 })
-            }
+            },
+            ..Mapping::default()
         });
     }
 
+    #[test]
+    fn test_build_multi_fields() {
+        let index_metadata = IndexMetadata::default();
+        let builder = MappingBuilder {
+            properties: hashmap! {
+                "title".to_string() => MappingPropertyBuilder::Field(
+                    FieldMappingBuilder {
+                        field_type: FieldType::String,
+                        fields: btreemap! {
+                            "length".to_string() => FieldMappingBuilder {
+                                field_type: FieldType::TokenCount,
+                                is_in_all: false,
+                                ..FieldMappingBuilder::default()
+                            }
+                        },
+                        ..FieldMappingBuilder::default()
+                    }
+                )
+            },
+            ..MappingBuilder::default()
+        };
+
+        let mapping = builder.build(&index_metadata);
+
+        // The multi-field is added alongside the field
+        match mapping.properties.get("title") {
+            Some(&MappingProperty::Field(ref field_mapping)) => {
+                assert_eq!(field_mapping.multi_fields, vec!["title.length".to_string()]);
+            }
+            _ => panic!("title field is missing"),
+        }
+
+        match mapping.properties.get("title.length") {
+            Some(&MappingProperty::Field(ref field_mapping)) => {
+                assert_eq!(field_mapping.data_type, FieldType::TokenCount);
+                assert_eq!(field_mapping.process_value_for_store(&json!("Hello, big world!")).unwrap(), Some(FieldValue::Integer(3)));
+            }
+            _ => panic!("title.length field is missing"),
+        }
+
+        // Multi-fields are serialised under the field they belong to
+        let mapping_json = json!(mapping);
+        assert_eq!(mapping_json["properties"]["title"]["fields"]["length"]["type"], json!("token_count"));
+        assert_eq!(mapping_json["properties"].get("title.length"), None);
+    }
+
+    #[test]
+    fn test_build_index_prefixes() {
+        let index_metadata = IndexMetadata::default();
+        let builder = MappingBuilder {
+            properties: hashmap! {
+                "title".to_string() => MappingPropertyBuilder::Field(
+                    FieldMappingBuilder {
+                        field_type: FieldType::String,
+                        index_prefixes: Some(IndexPrefixes {
+                            min_chars: 2,
+                            max_chars: 3,
+                        }),
+                        ..FieldMappingBuilder::default()
+                    }
+                )
+            },
+            ..MappingBuilder::default()
+        };
+
+        let mapping = builder.build(&index_metadata);
+
+        // The prefixes are made with the field's analyzer
+        match mapping.properties.get("title._index_prefix") {
+            Some(&MappingProperty::Field(ref field_mapping)) => {
+                assert_eq!(field_mapping.is_in_all, false);
+
+                let terms = field_mapping.index_analyzer().unwrap().initialise("Hello, World!").map(|token| token.term).collect::<Vec<_>>();
+                assert_eq!(terms, vec![
+                    Term::from_string("he"),
+                    Term::from_string("hel"),
+                    Term::from_string("wo"),
+                    Term::from_string("wor"),
+                ]);
+            }
+            _ => panic!("title._index_prefix field is missing"),
+        }
+
+        // The sub field is hidden, it's added back from the setting when the mapping is loaded
+        let mapping_json = json!(mapping);
+        assert_eq!(mapping_json["properties"]["title"]["index_prefixes"], json!({"min_chars": 2, "max_chars": 3}));
+        assert_eq!(mapping_json["properties"].get("title._index_prefix"), None);
+    }
+
     #[test]
     fn test_build_no_fields() {
         let index_metadata = IndexMetadata::default();
         let builder = MappingBuilder {
             properties: hashmap! {},
+            ..MappingBuilder::default()
         };
 
         let mapping = builder.build(&index_metadata);
@@ -248,8 +506,27 @@ mod tests {
                     index_analyzer: Some(get_standard_analyzer()),
                     search_analyzer: Some(get_standard_analyzer()),
                     ..FieldMapping::default()
+                }),
+                "_type".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_id".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_source".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
                 })
-            }
+            },
+            ..Mapping::default()
         });
     }
 
@@ -266,6 +543,7 @@ mod tests {
                     }
                 )
             },
+            ..MappingBuilder::default()
         };
 
         let mapping = builder.build(&index_metadata);
@@ -278,8 +556,27 @@ mod tests {
                     index_analyzer: Some(get_standard_analyzer()),
                     search_analyzer: Some(get_standard_analyzer()),
                     ..FieldMapping::default()
+                }),
+                "_type".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_id".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_in_all: false,
+                    ..FieldMapping::default()
+                }),
+                "_source".to_string() => MappingProperty::Field(FieldMapping {
+                    data_type: FieldType::String,
+                    is_indexed: false,
+                    is_stored: true,
+                    is_in_all: false,
+                    ..FieldMapping::default()
                 })
-            }
+            },
+            ..Mapping::default()
         });
     }
 
@@ -469,4 +766,69 @@ mod tests {
             ..FieldMapping::default()
         });
     }
+
+    #[test]
+    fn test_build_field_normalizer() {
+        let index_metadata = IndexMetadata::default();
+
+        let builder = FieldMappingBuilder {
+            field_type: FieldType::String,
+            is_analyzed: false,
+            normalizer: Some("lowercase".to_string()),
+            ..FieldMappingBuilder::default()
+        };
+
+        let mapping = builder.build(&index_metadata);
+        let lowercase_normalizer = index_metadata.normalizers().get("lowercase").cloned().unwrap();
+
+        assert_eq!(mapping, FieldMapping {
+            data_type: FieldType::String,
+            normalizer: Some(("lowercase".to_string(), lowercase_normalizer)),
+            ..FieldMapping::default()
+        });
+
+        // The whole value is normalized into a single term
+        let tokens: Vec<Token> = mapping.process_value_for_index(&json!("Hello World")).unwrap().unwrap().into();
+        assert_eq!(tokens, vec![Token { term: Term::from_string("hello world"), position: 1 }]);
+    }
+
+    #[test]
+    fn test_detect_field_type() {
+        let mapping = MappingBuilder::default().build(&IndexMetadata::default());
+
+        assert_eq!(mapping.detect_field_type(&json!("hello")), Some((FieldType::String, None)));
+        assert_eq!(mapping.detect_field_type(&json!(123)), Some((FieldType::Integer, None)));
+        assert_eq!(mapping.detect_field_type(&json!(1.5)), Some((FieldType::String, None)));
+        assert_eq!(mapping.detect_field_type(&json!(true)), Some((FieldType::Boolean, None)));
+        assert_eq!(mapping.detect_field_type(&json!([null, 1, 2])), Some((FieldType::Integer, None)));
+        assert_eq!(mapping.detect_field_type(&json!({"foo": "bar"})), None);
+
+        // Dates are detected with the default formats
+        assert_eq!(mapping.detect_field_type(&json!("2015-09-02")), Some((FieldType::Date, Some("strict_date_optional_time".to_string()))));
+        assert_eq!(mapping.detect_field_type(&json!("2015/09/02 +0000")), Some((FieldType::Date, Some("yyyy/MM/dd HH:mm:ss Z||yyyy/MM/dd Z".to_string()))));
+
+        // Numbers in strings are only detected if numeric detection is on
+        assert_eq!(mapping.detect_field_type(&json!("123")), Some((FieldType::String, None)));
+    }
+
+    #[test]
+    fn test_detect_field_type_toggles() {
+        let mapping = MappingBuilder {
+            date_detection: false,
+            numeric_detection: true,
+            ..MappingBuilder::default()
+        }.build(&IndexMetadata::default());
+
+        assert_eq!(mapping.detect_field_type(&json!("2015-09-02")), Some((FieldType::String, None)));
+        assert_eq!(mapping.detect_field_type(&json!("123")), Some((FieldType::Integer, None)));
+    }
+
+    #[test]
+    fn test_build_dynamic_date_field() {
+        let field_mapping = FieldMappingBuilder::dynamic(FieldType::Date, Some("yyyy/MM/dd".to_string())).build(&IndexMetadata::default());
+
+        assert_eq!(field_mapping.process_value_for_store(&json!("2015/09/02")).unwrap(), Some(FieldValue::DateTime(Utc.ymd(2015, 9, 2).and_hms(0, 0, 0))));
+        assert_eq!(field_mapping.process_value_for_store(&json!("2015-09-02T00:00:00Z")).unwrap(), Some(FieldValue::DateTime(Utc.ymd(2015, 9, 2).and_hms(0, 0, 0))));
+        assert!(field_mapping.process_value_for_store(&json!("02/09/2015")).is_err());
+    }
 }
@@ -0,0 +1,216 @@
+//! Date formats, used by the "format" setting of date fields, by date detection and
+//! by date math in index names
+//!
+//! A format is either the name of a builtin format or a pattern in the syntax that
+//! Elasticsearch uses (eg, "yyyy/MM/dd HH:mm:ss Z"). Several formats can be given
+//! separated by "||", the first one that matches is used.
+//!
+//! Dates without a time are at midnight and dates without a timezone are in UTC.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+
+
+/// Converts a pattern into a chrono format string
+///
+/// Returns None if the pattern contains a letter that isn't supported.
+fn pattern_to_strftime(pattern: &str) -> Option<String> {
+    let mut strftime = String::new();
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        // Text in quotes is copied as it is
+        if c == '\'' {
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    break;
+                }
+
+                strftime.push(c);
+            }
+
+            continue;
+        }
+
+        if !c.is_alphabetic() {
+            if c == '%' {
+                strftime.push_str("%%");
+            } else {
+                strftime.push(c);
+            }
+
+            continue;
+        }
+
+        // Letters are repeated to set the width of the value
+        let mut width = 1;
+        while chars.peek() == Some(&c) {
+            chars.next();
+            width += 1;
+        }
+
+        match (c, width) {
+            ('y', 2) => strftime.push_str("%y"),
+            ('y', _) => strftime.push_str("%Y"),
+            ('M', 3) => strftime.push_str("%b"),
+            ('M', w) if w < 3 => strftime.push_str("%m"),
+            ('d', _) => strftime.push_str("%d"),
+            ('H', _) => strftime.push_str("%H"),
+            ('m', _) => strftime.push_str("%M"),
+            ('s', _) => strftime.push_str("%S"),
+            ('S', _) => {
+                // Fractions of a second always follow a "."
+                if !strftime.ends_with('.') {
+                    return None;
+                }
+
+                strftime.pop();
+                strftime.push_str("%.f");
+            }
+            ('Z', _) => strftime.push_str("%z"),
+            _ => return None,
+        }
+    }
+
+    Some(strftime)
+}
+
+
+fn parse_strftime(string: &str, strftime: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_str(string, strftime) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    if let Ok(date) = NaiveDateTime::parse_from_str(string, strftime) {
+        return Some(DateTime::from_utc(date, Utc));
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(string, strftime) {
+        return Some(DateTime::from_utc(date.and_hms(0, 0, 0), Utc));
+    }
+
+    None
+}
+
+
+/// Parses ISO 8601 dates, the time is optional
+fn parse_date_optional_time(string: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = string.parse::<DateTime<Utc>>() {
+        return Some(date);
+    }
+
+    for strftime in &["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d"] {
+        if let Some(date) = parse_strftime(string, strftime) {
+            return Some(date);
+        }
+    }
+
+    None
+}
+
+
+fn parse_single_format(string: &str, format: &str) -> Option<DateTime<Utc>> {
+    match format {
+        "strict_date_optional_time" | "date_optional_time" => parse_date_optional_time(string),
+        "date" | "strict_date" => parse_strftime(string, "%Y-%m-%d"),
+        _ => {
+            match pattern_to_strftime(format) {
+                Some(strftime) => parse_strftime(string, &strftime),
+                None => None,
+            }
+        }
+    }
+}
+
+
+/// Checks that every format separated by "||" is either builtin or a supported pattern
+pub fn is_valid_format(format: &str) -> bool {
+    format.split("||").all(|format| {
+        match format {
+            "strict_date_optional_time" | "date_optional_time" | "date" | "strict_date" => true,
+            _ => pattern_to_strftime(format).is_some(),
+        }
+    })
+}
+
+
+/// Formats a date with a builtin format or a pattern
+///
+/// If several formats are separated by "||", the first one is used. Returns None if
+/// the format isn't supported.
+pub fn format_date(date: &DateTime<Utc>, format: &str) -> Option<String> {
+    match format.split("||").next().unwrap_or(format) {
+        "strict_date_optional_time" | "date_optional_time" => Some(date.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()),
+        "date" | "strict_date" => Some(date.format("%Y-%m-%d").to_string()),
+        "epoch_millis" => Some((date.timestamp() * 1000 + date.timestamp_subsec_millis() as i64).to_string()),
+        "epoch_second" => Some(date.timestamp().to_string()),
+        pattern => pattern_to_strftime(pattern).map(|strftime| date.format(&strftime).to_string()),
+    }
+}
+
+
+/// Parses a date in any of the formats, which may be separated by "||"
+pub fn parse_date(string: &str, format: &str) -> Option<DateTime<Utc>> {
+    for format in format.split("||") {
+        if let Some(date) = parse_single_format(string, format) {
+            return Some(date);
+        }
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{parse_date, format_date, is_valid_format, pattern_to_strftime};
+
+    #[test]
+    fn test_pattern_to_strftime() {
+        assert_eq!(pattern_to_strftime("yyyy/MM/dd HH:mm:ss Z"), Some("%Y/%m/%d %H:%M:%S %z".to_string()));
+        assert_eq!(pattern_to_strftime("yyyy-MM-dd'T'HH:mm:ss.SSS"), Some("%Y-%m-%dT%H:%M:%S%.f".to_string()));
+        assert_eq!(pattern_to_strftime("dd MMM yy"), Some("%d %b %y".to_string()));
+        assert_eq!(pattern_to_strftime("yyyy-ww"), None);
+    }
+
+    #[test]
+    fn test_parse_date_optional_time() {
+        assert_eq!(parse_date("2015-09-02", "strict_date_optional_time"), Some(Utc.ymd(2015, 9, 2).and_hms(0, 0, 0)));
+        assert_eq!(parse_date("2015-09-02T10:20:30", "strict_date_optional_time"), Some(Utc.ymd(2015, 9, 2).and_hms(10, 20, 30)));
+        assert_eq!(parse_date("2015-09-02T10:20:30+01:00", "strict_date_optional_time"), Some(Utc.ymd(2015, 9, 2).and_hms(9, 20, 30)));
+        assert_eq!(parse_date("2015/09/02", "strict_date_optional_time"), None);
+        assert_eq!(parse_date("hello", "strict_date_optional_time"), None);
+    }
+
+    #[test]
+    fn test_parse_date_pattern() {
+        let format = "yyyy/MM/dd HH:mm:ss Z||yyyy/MM/dd";
+
+        assert_eq!(parse_date("2015/09/02 10:20:30 +0100", format), Some(Utc.ymd(2015, 9, 2).and_hms(9, 20, 30)));
+        assert_eq!(parse_date("2015/09/02", format), Some(Utc.ymd(2015, 9, 2).and_hms(0, 0, 0)));
+        assert_eq!(parse_date("2015-09-02", format), None);
+    }
+
+    #[test]
+    fn test_format_date() {
+        let date = Utc.ymd(2015, 9, 2).and_hms(10, 20, 30);
+
+        assert_eq!(format_date(&date, "yyyy.MM.dd"), Some("2015.09.02".to_string()));
+        assert_eq!(format_date(&date, "dd MMM yy HH:mm"), Some("02 Sep 15 10:20".to_string()));
+        assert_eq!(format_date(&date, "yyyy-ww"), None);
+
+        // Builtin formats
+        assert_eq!(format_date(&date, "strict_date_optional_time"), Some("2015-09-02T10:20:30.000Z".to_string()));
+        assert_eq!(format_date(&date, "date||yyyy/MM/dd"), Some("2015-09-02".to_string()));
+        assert_eq!(format_date(&date, "epoch_millis"), Some("1441189230000".to_string()));
+        assert_eq!(format_date(&date, "epoch_second"), Some("1441189230".to_string()));
+    }
+
+    #[test]
+    fn test_is_valid_format() {
+        assert!(is_valid_format("strict_date_optional_time"));
+        assert!(is_valid_format("yyyy/MM/dd HH:mm:ss Z||yyyy/MM/dd Z"));
+        assert!(!is_valid_format("strict_date_optional_time||yyyy-ww"));
+    }
+}
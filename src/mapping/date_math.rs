@@ -0,0 +1,374 @@
+//! Date math expressions, used by range queries and index names
+//!
+//! An expression starts with an anchor, which is either "now" or a date followed by
+//! "||", then any number of operations:
+//!
+//! - "+1d" and "-1d" add or subtract a number of units (the number defaults to 1)
+//! - "/d" rounds to the unit
+//!
+//! The units are "y" (years), "M" (months), "w" (weeks), "d" (days), "h" or "H"
+//! (hours), "m" (minutes) and "s" (seconds). For example, "now-7d/d" is midnight
+//! seven days ago and "2017-01-31||+1M" is 2017-02-28.
+//!
+//! Index names can contain expressions too. They're wrapped in "<" and ">" and each
+//! expression is in braces, optionally with the format of the date in another pair
+//! of braces: "<logs-{now/d}>" is "logs-2017.03.05" and "<logs-{now/M{yyyy.MM}}>" is
+//! "logs-2017.03".
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+
+use mapping::date_format;
+
+
+/// The format of dates in index names, unless the expression gives one
+pub const DEFAULT_INDEX_NAME_DATE_FORMAT: &'static str = "yyyy.MM.dd";
+
+
+/// Which end of the unit a date is rounded to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    /// The first microsecond of the unit
+    Down,
+
+    /// The last microsecond of the unit, so an inclusive upper bound covers all of it
+    Up,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DateMathError {
+    InvalidAnchor(String),
+    InvalidNumber(String),
+    MissingUnit,
+    UnrecognisedUnit(char),
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    InvalidFormat(String),
+    TimeZonesNotSupported,
+
+    /// The date is outside of the range that can be represented
+    OutOfRange,
+}
+
+
+impl DateMathError {
+    pub fn message(&self) -> String {
+        match *self {
+            DateMathError::InvalidAnchor(ref anchor) => format!("invalid date: {:?}", anchor),
+            DateMathError::InvalidNumber(ref number) => format!("invalid number: {:?}", number),
+            DateMathError::MissingUnit => "missing unit".to_string(),
+            DateMathError::UnrecognisedUnit(unit) => format!("unrecognised unit: {:?}", unit),
+            DateMathError::UnexpectedCharacter(c) => format!("unexpected character: {:?}", c),
+            DateMathError::UnexpectedEnd => "unexpected end of expression".to_string(),
+            DateMathError::InvalidFormat(ref format) => format!("invalid date format: {:?}", format),
+            DateMathError::TimeZonesNotSupported => "time zones are not supported".to_string(),
+            DateMathError::OutOfRange => "date out of range".to_string(),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    Year,
+    Month,
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+
+fn parse_unit(c: Option<char>) -> Result<Unit, DateMathError> {
+    match c {
+        Some('y') => Ok(Unit::Year),
+        Some('M') => Ok(Unit::Month),
+        Some('w') => Ok(Unit::Week),
+        Some('d') => Ok(Unit::Day),
+        Some('h') | Some('H') => Ok(Unit::Hour),
+        Some('m') => Ok(Unit::Minute),
+        Some('s') => Ok(Unit::Second),
+        Some(c) => Err(DateMathError::UnrecognisedUnit(c)),
+        None => Err(DateMathError::MissingUnit),
+    }
+}
+
+
+/// Adds months, the day is clamped to the length of the month (Jan 31st + 1 month is Feb 28th)
+fn add_months(date: DateTime<Utc>, months: i64) -> Option<DateTime<Utc>> {
+    let naive = date.naive_utc();
+    let total_months = naive.year() as i64 * 12 + naive.month0() as i64 + months;
+    let year = if total_months >= 0 { total_months / 12 } else { (total_months - 11) / 12 };
+    let month = (total_months - year * 12) as u32 + 1;
+
+    if year < i32::min_value() as i64 || year > i32::max_value() as i64 {
+        return None;
+    }
+
+    let mut day = naive.day();
+    loop {
+        if let Some(new_date) = NaiveDate::from_ymd_opt(year as i32, month, day) {
+            return Some(DateTime::from_utc(new_date.and_time(naive.time()), Utc));
+        }
+
+        if day <= 28 {
+            return None;
+        }
+
+        day -= 1;
+    }
+}
+
+
+fn add_units(date: DateTime<Utc>, amount: i64, unit: Unit) -> Option<DateTime<Utc>> {
+    let duration = match unit {
+        Unit::Year => return add_months(date, amount * 12),
+        Unit::Month => return add_months(date, amount),
+        Unit::Week => Duration::weeks(amount),
+        Unit::Day => Duration::days(amount),
+        Unit::Hour => Duration::hours(amount),
+        Unit::Minute => Duration::minutes(amount),
+        Unit::Second => Duration::seconds(amount),
+    };
+
+    date.checked_add_signed(duration)
+}
+
+
+fn round_down(date: DateTime<Utc>, unit: Unit) -> Option<DateTime<Utc>> {
+    let naive = date.naive_utc();
+    let day = naive.date();
+
+    let rounded = match unit {
+        Unit::Year => NaiveDate::from_ymd_opt(day.year(), 1, 1)?.and_hms(0, 0, 0),
+        Unit::Month => NaiveDate::from_ymd_opt(day.year(), day.month(), 1)?.and_hms(0, 0, 0),
+        Unit::Week => {
+            // Weeks start on Monday
+            let days_since_monday = day.weekday().num_days_from_monday() as i64;
+            day.checked_add_signed(Duration::days(-days_since_monday))?.and_hms(0, 0, 0)
+        }
+        Unit::Day => day.and_hms(0, 0, 0),
+        Unit::Hour => day.and_hms(naive.hour(), 0, 0),
+        Unit::Minute => day.and_hms(naive.hour(), naive.minute(), 0),
+        Unit::Second => day.and_hms(naive.hour(), naive.minute(), naive.second()),
+    };
+
+    Some(DateTime::from_utc(rounded, Utc))
+}
+
+
+fn round_up(date: DateTime<Utc>, unit: Unit) -> Option<DateTime<Utc>> {
+    add_units(round_down(date, unit)?, 1, unit)?.checked_add_signed(Duration::microseconds(-1))
+}
+
+
+fn parse_anchor(anchor: &str) -> Result<DateTime<Utc>, DateMathError> {
+    date_format::parse_date(anchor, "strict_date_optional_time").ok_or_else(|| DateMathError::InvalidAnchor(anchor.to_string()))
+}
+
+
+/// Checks if a string is a date math expression rather than a plain date
+pub fn is_date_math(string: &str) -> bool {
+    string.starts_with("now") || string.contains("||")
+}
+
+
+/// Checks if a date math expression is relative to the current time
+pub fn uses_now(string: &str) -> bool {
+    string.starts_with("now")
+}
+
+
+/// Evaluates a date math expression
+///
+/// Rounding only applies to the "/" operations, so "now/d" rounded up is the end of
+/// today but "now" is always the current time.
+pub fn evaluate(expression: &str, now: DateTime<Utc>, rounding: Rounding) -> Result<DateTime<Utc>, DateMathError> {
+    let (mut date, operations) = if expression.starts_with("now") {
+        (now, &expression[3..])
+    } else {
+        match expression.find("||") {
+            Some(position) => (parse_anchor(&expression[..position])?, &expression[position + 2..]),
+            None => (parse_anchor(expression)?, ""),
+        }
+    };
+
+    let mut chars = operations.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '+' | '-' => {
+                let mut digits = String::new();
+                while let Some(&digit) = chars.peek() {
+                    if !digit.is_ascii_digit() {
+                        break;
+                    }
+
+                    digits.push(digit);
+                    chars.next();
+                }
+
+                // Numbers are limited to 32 bits so multiplying them by a unit can't overflow
+                let amount = if digits.is_empty() {
+                    1
+                } else {
+                    digits.parse::<u32>().map_err(|_| DateMathError::InvalidNumber(digits.clone()))? as i64
+                };
+                let amount = if c == '-' { -amount } else { amount };
+
+                let unit = parse_unit(chars.next())?;
+                date = add_units(date, amount, unit).ok_or(DateMathError::OutOfRange)?;
+            }
+            '/' => {
+                let unit = parse_unit(chars.next())?;
+                let rounded = match rounding {
+                    Rounding::Down => round_down(date, unit),
+                    Rounding::Up => round_up(date, unit),
+                };
+
+                date = rounded.ok_or(DateMathError::OutOfRange)?;
+            }
+            c => return Err(DateMathError::UnexpectedCharacter(c)),
+        }
+    }
+
+    Ok(date)
+}
+
+
+/// Resolves the expressions in an index name
+///
+/// Names that aren't wrapped in "<" and ">" are returned as they are. Braces can be
+/// escaped with a backslash.
+pub fn resolve_index_name(name: &str, now: DateTime<Utc>) -> Result<String, DateMathError> {
+    if !(name.len() >= 2 && name.starts_with('<') && name.ends_with('>')) {
+        return Ok(name.to_string());
+    }
+
+    let mut resolved = String::new();
+    let mut chars = name[1..name.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => resolved.push(chars.next().ok_or(DateMathError::UnexpectedEnd)?),
+            '{' => {
+                // Read up to the closing brace, the format may be given in another pair of braces
+                let mut expression = String::new();
+                let mut format = None;
+                loop {
+                    match chars.next().ok_or(DateMathError::UnexpectedEnd)? {
+                        '}' => break,
+                        '{' => {
+                            let mut inner_format = String::new();
+                            loop {
+                                match chars.next().ok_or(DateMathError::UnexpectedEnd)? {
+                                    '}' => break,
+                                    c => inner_format.push(c),
+                                }
+                            }
+
+                            format = Some(inner_format);
+                        }
+                        c => expression.push(c),
+                    }
+                }
+
+                let format = format.unwrap_or_else(|| DEFAULT_INDEX_NAME_DATE_FORMAT.to_string());
+                if format.contains('|') {
+                    return Err(DateMathError::TimeZonesNotSupported);
+                }
+
+                let date = evaluate(&expression, now, Rounding::Down)?;
+                match date_format::format_date(&date, &format) {
+                    Some(formatted) => resolved.push_str(&formatted),
+                    None => return Err(DateMathError::InvalidFormat(format)),
+                }
+            }
+            '}' => return Err(DateMathError::UnexpectedCharacter('}')),
+            c => resolved.push(c),
+        }
+    }
+
+    Ok(resolved)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::{evaluate, resolve_index_name, is_date_math, uses_now, Rounding, DateMathError};
+
+    fn now() -> DateTime<Utc> {
+        // A Wednesday
+        Utc.ymd(2017, 3, 15).and_hms(10, 20, 30)
+    }
+
+    #[test]
+    fn test_now() {
+        assert_eq!(evaluate("now", now(), Rounding::Down), Ok(now()));
+        assert_eq!(evaluate("now", now(), Rounding::Up), Ok(now()));
+    }
+
+    #[test]
+    fn test_add_and_subtract() {
+        assert_eq!(evaluate("now+1h", now(), Rounding::Down), Ok(Utc.ymd(2017, 3, 15).and_hms(11, 20, 30)));
+        assert_eq!(evaluate("now-7d", now(), Rounding::Down), Ok(Utc.ymd(2017, 3, 8).and_hms(10, 20, 30)));
+        assert_eq!(evaluate("now-1y+2M", now(), Rounding::Down), Ok(Utc.ymd(2016, 5, 15).and_hms(10, 20, 30)));
+        assert_eq!(evaluate("now+d", now(), Rounding::Down), Ok(Utc.ymd(2017, 3, 16).and_hms(10, 20, 30)));
+    }
+
+    #[test]
+    fn test_round() {
+        assert_eq!(evaluate("now/d", now(), Rounding::Down), Ok(Utc.ymd(2017, 3, 15).and_hms(0, 0, 0)));
+        assert_eq!(evaluate("now/d", now(), Rounding::Up), Ok(Utc.ymd(2017, 3, 15).and_hms_micro(23, 59, 59, 999999)));
+        assert_eq!(evaluate("now/w", now(), Rounding::Down), Ok(Utc.ymd(2017, 3, 13).and_hms(0, 0, 0)));
+        assert_eq!(evaluate("now/M", now(), Rounding::Up), Ok(Utc.ymd(2017, 3, 31).and_hms_micro(23, 59, 59, 999999)));
+        assert_eq!(evaluate("now-7d/d", now(), Rounding::Down), Ok(Utc.ymd(2017, 3, 8).and_hms(0, 0, 0)));
+    }
+
+    #[test]
+    fn test_anchor_date() {
+        assert_eq!(evaluate("2017-01-31||+1M", now(), Rounding::Down), Ok(Utc.ymd(2017, 2, 28).and_hms(0, 0, 0)));
+        assert_eq!(evaluate("2016-01-31T12:00:00Z||+1M/d", now(), Rounding::Down), Ok(Utc.ymd(2016, 2, 29).and_hms(0, 0, 0)));
+        assert_eq!(evaluate("2017-01-01", now(), Rounding::Down), Ok(Utc.ymd(2017, 1, 1).and_hms(0, 0, 0)));
+        assert_eq!(evaluate("yesterday||+1d", now(), Rounding::Down), Err(DateMathError::InvalidAnchor("yesterday".to_string())));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert_eq!(evaluate("now+1", now(), Rounding::Down), Err(DateMathError::MissingUnit));
+        assert_eq!(evaluate("now+1x", now(), Rounding::Down), Err(DateMathError::UnrecognisedUnit('x')));
+        assert_eq!(evaluate("now*2d", now(), Rounding::Down), Err(DateMathError::UnexpectedCharacter('*')));
+        assert_eq!(evaluate("now+99999999999d", now(), Rounding::Down), Err(DateMathError::InvalidNumber("99999999999".to_string())));
+        assert_eq!(evaluate("now+4000000000y", now(), Rounding::Down), Err(DateMathError::OutOfRange));
+    }
+
+    #[test]
+    fn test_is_date_math() {
+        assert!(is_date_math("now-1d"));
+        assert!(is_date_math("2017-01-01||/M"));
+        assert!(!is_date_math("2017-01-01"));
+    }
+
+    #[test]
+    fn test_uses_now() {
+        assert!(uses_now("now-1d"));
+        assert!(!uses_now("2017-01-01||/M"));
+    }
+
+    #[test]
+    fn test_resolve_index_name() {
+        assert_eq!(resolve_index_name("logs", now()), Ok("logs".to_string()));
+        assert_eq!(resolve_index_name("<logs-{now/d}>", now()), Ok("logs-2017.03.15".to_string()));
+        assert_eq!(resolve_index_name("<logs-{now/M-1M{yyyy.MM}}>", now()), Ok("logs-2017.02".to_string()));
+        assert_eq!(resolve_index_name("<logs-\\{{now/y{yyyy}}\\}>", now()), Ok("logs-{2017}".to_string()));
+    }
+
+    #[test]
+    fn test_resolve_invalid_index_name() {
+        assert_eq!(resolve_index_name("<logs-{now/d>", now()), Err(DateMathError::UnexpectedEnd));
+        assert_eq!(resolve_index_name("<logs-}>", now()), Err(DateMathError::UnexpectedCharacter('}')));
+        assert_eq!(resolve_index_name("<logs-{now/d{yyyy.MM.dd|+01:00}}>", now()), Err(DateMathError::TimeZonesNotSupported));
+        assert_eq!(resolve_index_name("<logs-{now/d{yyyy-ww}}>", now()), Err(DateMathError::InvalidFormat("yyyy-ww".to_string())));
+    }
+}
@@ -1,21 +1,32 @@
 pub mod build;
 pub mod parse;
+pub mod base64;
+pub mod date_format;
+pub mod date_math;
+pub mod runtime;
 
 use std::collections::{HashMap, BTreeMap};
+use std::net::IpAddr;
+use std::str;
 
 use serde::{Serialize, Serializer};
 use serde_json;
+use unicode_segmentation::UnicodeSegmentation;
 //use serde_json::value::ToJson;
 use chrono::{DateTime, Utc};
-use search::{Term, Token};
+use search::{Term, Token, RangeBound};
+use search::term::datetime_to_micros;
 use search::term_vector::TermVector;
 use search::document::FieldValue;
-use search::similarity::SimilarityModel;
-use search::schema::FieldId;
+use search::similarity::{SimilarityModel, VectorSimilarity};
+use search::geo::{self, GeoShape, GeoPoint};
+use search::schema::{self, FieldId, FieldFlags, FIELD_INDEXED, FIELD_STORED};
 
-use analysis::AnalyzerSpec;
+use analysis::{AnalyzerSpec, NormalizerSpec};
+use analysis::collation::Collation;
 use analysis::tokenizers::TokenizerSpec;
 use analysis::filters::FilterSpec;
+use analysis::ngram_generator::Edge;
 
 
 // TEMPORARY
@@ -36,6 +47,63 @@ pub enum FieldType {
     Integer,
     Boolean,
     Date,
+    IntegerRange,
+    DateRange,
+    Ip,
+    Binary,
+    Join,
+    DenseVector,
+
+    /// A GeoJSON shape, indexed as the geohash cells that cover it
+    GeoShape,
+
+    /// A latitude and longitude, used by geo aggregations
+    GeoPoint,
+
+    /// The number of tokens the analyzer produces from a string, indexed as an integer
+    TokenCount,
+
+    /// A positive number that rank_feature queries score documents by (eg, popularity)
+    RankFeature,
+}
+
+
+impl FieldType {
+    /// Whether values of this type are passed through an analyzer
+    pub fn uses_analyzer(&self) -> bool {
+        match *self {
+            FieldType::String | FieldType::TokenCount => true,
+            _ => false,
+        }
+    }
+
+    /// Range fields hold a lower and upper bound for each document, rather than a single value
+    pub fn is_range(&self) -> bool {
+        match *self {
+            FieldType::IntegerRange | FieldType::DateRange => true,
+            _ => false,
+        }
+    }
+
+    /// Numeric fields can be filtered by range using their doc values
+    pub fn is_numeric(&self) -> bool {
+        match *self {
+            FieldType::Integer | FieldType::Date => true,
+            _ => false,
+        }
+    }
+
+    /// Whether each item of an array is treated as a separate value of the field
+    ///
+    /// Strings are joined together instead. Range and join fields only accept a single
+    /// value, as the parts of different values would get mixed up. Dense vectors are
+    /// arrays themselves, and so are the coordinates of geo shapes.
+    pub fn splits_arrays(&self) -> bool {
+        match *self {
+            FieldType::String | FieldType::Join | FieldType::DenseVector | FieldType::GeoShape => false,
+            _ => !self.is_range(),
+        }
+    }
 }
 
 
@@ -53,6 +121,16 @@ impl ToString for FieldType {
             FieldType::Integer => "integer".to_string(),
             FieldType::Boolean => "boolean".to_string(),
             FieldType::Date => "date".to_string(),
+            FieldType::IntegerRange => "integer_range".to_string(),
+            FieldType::DateRange => "date_range".to_string(),
+            FieldType::Ip => "ip".to_string(),
+            FieldType::Binary => "binary".to_string(),
+            FieldType::Join => "join".to_string(),
+            FieldType::DenseVector => "dense_vector".to_string(),
+            FieldType::GeoShape => "geo_shape".to_string(),
+            FieldType::GeoPoint => "geo_point".to_string(),
+            FieldType::TokenCount => "token_count".to_string(),
+            FieldType::RankFeature => "rank_feature".to_string(),
         }
     }
 }
@@ -61,6 +139,9 @@ impl ToString for FieldType {
 #[derive(Debug, Clone, PartialEq)]
 pub struct FieldSearchOptions {
     pub analyzer: Option<AnalyzerSpec>,
+
+    /// Applied to the whole query string if there is no analyzer
+    pub normalizer: Option<NormalizerSpec>,
     pub similarity_model: SimilarityModel,
 }
 
@@ -69,6 +150,7 @@ impl Default for FieldSearchOptions {
     fn default() -> FieldSearchOptions {
         FieldSearchOptions {
             analyzer: Some(get_standard_analyzer()),
+            normalizer: None,
             similarity_model: SimilarityModel::Bm25 {
                 k1: 1.2,
                 b: 0.75,
@@ -82,6 +164,42 @@ impl Default for FieldSearchOptions {
 pub struct FieldValueError;
 
 
+/// Lengths of the prefixes that are indexed into a hidden sub field ("index_prefixes")
+///
+/// Prefix queries can look these up as a single term instead of scanning the term
+/// dictionary for every term that starts with the prefix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexPrefixes {
+    pub min_chars: usize,
+    pub max_chars: usize,
+}
+
+
+impl Default for IndexPrefixes {
+    fn default() -> IndexPrefixes {
+        IndexPrefixes {
+            min_chars: 2,
+            max_chars: 5,
+        }
+    }
+}
+
+
+impl IndexPrefixes {
+    /// The name of the sub field that the prefixes of a field are indexed into
+    pub fn sub_field_name(field_name: &str) -> String {
+        format!("{}._index_prefix", field_name)
+    }
+
+    /// Whether prefixes of this length are indexed, counted in the same way as the
+    /// edge ngrams they're indexed as
+    pub fn covers(&self, prefix: &str) -> bool {
+        let length = prefix.graphemes(true).count();
+        length >= self.min_chars && length <= self.max_chars
+    }
+}
+
+
 #[derive(Debug, PartialEq)]
 pub struct FieldMapping {
     pub data_type: FieldType,
@@ -92,6 +210,32 @@ pub struct FieldMapping {
     boost: f64,
     index_analyzer: Option<AnalyzerSpec>,
     search_analyzer: Option<AnalyzerSpec>,
+
+    /// The name and definition of the normalizer applied to the whole value (keyword fields only)
+    normalizer: Option<(String, NormalizerSpec)>,
+
+    /// Parent relation name to child relation names (join fields only)
+    pub relations: BTreeMap<String, Vec<String>>,
+
+    /// Number of values in each vector (dense vector fields only)
+    pub dims: Option<usize>,
+
+    /// How vectors are compared by knn queries (dense vector fields only)
+    pub vector_similarity: VectorSimilarity,
+
+    /// Names of the fields that index the value of this field in other ways
+    /// These are added to the mapping as "field.subfield"
+    pub multi_fields: Vec<String>,
+
+    /// Formats that dates can be given in, besides RFC 3339 (date fields only)
+    pub date_format: Option<String>,
+
+    /// Orders values by the conventions of a language when sorting (keyword fields only)
+    /// Sort keys replace the values in doc values, so aggregations see the keys too
+    pub collation: Option<Collation>,
+
+    /// Indexes the prefixes of terms into a hidden sub field (analyzed fields only)
+    pub index_prefixes: Option<IndexPrefixes>,
 }
 
 
@@ -106,6 +250,14 @@ impl Default for FieldMapping {
             boost: 1.0f64,
             index_analyzer: None,
             search_analyzer: None,
+            normalizer: None,
+            relations: BTreeMap::new(),
+            dims: None,
+            vector_similarity: VectorSimilarity::default(),
+            multi_fields: Vec::new(),
+            date_format: None,
+            collation: None,
+            index_prefixes: None,
         }
     }
 }
@@ -117,7 +269,7 @@ impl Serialize for FieldMapping {
             (false, &None) => "no",
             (true, &None) => "not_analyzed",
             _ => {
-                if self.data_type == FieldType::String {
+                if self.data_type.uses_analyzer() {
                     "analyzed"
                 } else {
                     "not_analyzed"
@@ -125,7 +277,7 @@ impl Serialize for FieldMapping {
             }
         };
 
-        let json = json!({
+        let mut json = json!({
             "type": self.data_type.to_string(),
             "index": index,
             "store": self.is_stored,
@@ -136,6 +288,40 @@ impl Serialize for FieldMapping {
             "include_in_all": self.is_in_all
         });
 
+        if let Some((ref normalizer_name, _)) = self.normalizer {
+            json["normalizer"] = json!(normalizer_name);
+        }
+
+        if self.data_type == FieldType::Join {
+            json["relations"] = json!(self.relations);
+        }
+
+        if let Some(ref date_format) = self.date_format {
+            json["format"] = json!(date_format);
+        }
+
+        if let Some(ref collation) = self.collation {
+            json["collation"] = json!({
+                "locale": collation.locale(),
+                "strength": collation.strength().as_str(),
+            });
+        }
+
+        if let Some(index_prefixes) = self.index_prefixes {
+            json["index_prefixes"] = json!({
+                "min_chars": index_prefixes.min_chars,
+                "max_chars": index_prefixes.max_chars,
+            });
+        }
+
+        if self.data_type == FieldType::DenseVector {
+            json["dims"] = json!(self.dims);
+            json["similarity"] = json!(match self.vector_similarity {
+                VectorSimilarity::Cosine => "cosine",
+                VectorSimilarity::DotProduct => "dot_product",
+            });
+        }
+
         json.serialize(serializer)
     }
 }
@@ -158,9 +344,124 @@ impl FieldMapping {
         }
     }
 
+    pub fn normalizer(&self) -> Option<&NormalizerSpec> {
+        self.normalizer.as_ref().map(|&(_, ref normalizer)| normalizer)
+    }
+
+    /// The mapping of the hidden sub field that the prefixes of this field are indexed
+    /// into, if it has "index_prefixes"
+    ///
+    /// The prefixes are made by adding an edge ngram filter to the end of the field's
+    /// index analyzer, so they're the prefixes of the terms that are in the field.
+    pub fn index_prefixes_field_mapping(&self) -> Option<FieldMapping> {
+        let (index_prefixes, index_analyzer) = match (self.index_prefixes, &self.index_analyzer) {
+            (Some(index_prefixes), &Some(ref index_analyzer)) => (index_prefixes, index_analyzer),
+            _ => return None,
+        };
+
+        let mut prefix_analyzer = index_analyzer.clone();
+        prefix_analyzer.filters.push(FilterSpec::NGram {
+            min_size: index_prefixes.min_chars,
+            max_size: index_prefixes.max_chars,
+            edge: Edge::Left,
+        });
+
+        Some(FieldMapping {
+            data_type: self.data_type,
+            is_stored: false,
+            is_in_all: false,
+            index_analyzer: Some(prefix_analyzer),
+            search_analyzer: self.search_analyzer.clone(),
+            .. FieldMapping::default()
+        })
+    }
+
+    /// Normalizes a string, so it can be compared with the terms of the field
+    pub fn normalize(&self, value: &str) -> String {
+        match self.normalizer() {
+            Some(normalizer) => normalizer.normalize(value),
+            None => value.to_string(),
+        }
+    }
+
+    /// Normalizes a string term, so it can be compared with the terms of the field
+    ///
+    /// Terms are returned unchanged if the field doesn't have a normalizer.
+    pub fn normalize_term(&self, term: &Term) -> Term {
+        match (self.normalizer(), str::from_utf8(term.as_bytes())) {
+            (Some(normalizer), Ok(value)) => Term::from_string(&normalizer.normalize(value)),
+            _ => term.clone(),
+        }
+    }
+
+    /// Finds the parent relation of a child relation in a join field
+    pub fn parent_relation(&self, child_relation: &str) -> Option<&str> {
+        for (parent_relation, child_relations) in self.relations.iter() {
+            if child_relations.iter().any(|relation| relation == child_relation) {
+                return Some(parent_relation);
+            }
+        }
+
+        None
+    }
+
+    /// The type and flags of the field in the store's schema
+    pub fn schema_field(&self) -> (schema::FieldType, FieldFlags) {
+        let field_type = match self.data_type {
+            FieldType::String => schema::FieldType::Text,
+            FieldType::Integer | FieldType::TokenCount => schema::FieldType::I64,
+            FieldType::Boolean => schema::FieldType::Boolean,
+            FieldType::Date => schema::FieldType::DateTime,
+            FieldType::IntegerRange | FieldType::DateRange => schema::FieldType::PlainString,
+            FieldType::Ip => schema::FieldType::PlainString,
+            FieldType::Binary => schema::FieldType::Binary,
+            FieldType::DenseVector => schema::FieldType::DenseVector,
+            FieldType::Join => schema::FieldType::PlainString,
+            FieldType::GeoShape => schema::FieldType::PlainString,
+            FieldType::GeoPoint => schema::FieldType::GeoPoint,
+            FieldType::RankFeature => schema::FieldType::F64,
+        };
+
+        let mut field_flags = FieldFlags::empty();
+
+        if self.is_indexed {
+            field_flags |= FIELD_INDEXED;
+        }
+
+        if self.is_stored {
+            field_flags |= FIELD_STORED;
+        }
+
+        (field_type, field_flags)
+    }
+
+    /// Parses the value of a date field, in RFC 3339 or the field's format
+    fn parse_date(&self, string: &str) -> Option<DateTime<Utc>> {
+        if let Ok(date_parsed) = string.parse::<DateTime<Utc>>() {
+            return Some(date_parsed);
+        }
+
+        match self.date_format {
+            Some(ref date_format) => date_format::parse_date(string, date_format),
+            None => None,
+        }
+    }
+
+    /// Reads the value of an integer field
+    ///
+    /// Numeric strings are accepted too, these are mapped as integers by numeric detection.
+    fn parse_integer(&self, value: &serde_json::Value) -> Result<i64, FieldValueError> {
+        match *value {
+            serde_json::Value::Number(ref num) => num.as_i64().ok_or(FieldValueError),
+            serde_json::Value::String(ref string) => string.parse::<i64>().map_err(|_| FieldValueError),
+            _ => Err(FieldValueError),
+        }
+    }
+
     pub fn get_search_options(&self) -> FieldSearchOptions {
         FieldSearchOptions {
             analyzer: self.search_analyzer().cloned(),
+            normalizer: self.normalizer().cloned(),
             .. FieldSearchOptions::default()
         }
     }
@@ -170,6 +471,28 @@ impl FieldMapping {
             return Ok(None);
         }
 
+        // Arrays of non-string values are indexed as if each item was a separate value
+        // (string arrays are handled below, as the analyzer must see them)
+        if let serde_json::Value::Array(ref array) = *value {
+            if self.data_type.splits_arrays() && !self.is_single_value_array(array) {
+                let mut tokens: Vec<Token> = Vec::new();
+
+                for item in array {
+                    if let Some(item_tokens) = self.process_value_for_index(item)? {
+                        let item_tokens: Vec<Token> = item_tokens.into();
+                        let position_offset = tokens.len() as u32;
+
+                        for mut token in item_tokens {
+                            token.position += position_offset;
+                            tokens.push(token);
+                        }
+                    }
+                }
+
+                return Ok(Some(tokens.into()));
+            }
+        }
+
         match self.data_type {
             FieldType::String => {
                 match *value {
@@ -181,8 +504,13 @@ impl FieldMapping {
                                 token_stream.collect::<Vec<Token>>().into()
                             }
                             None => {
+                                let term = match self.normalizer() {
+                                    Some(normalizer) => Term::from_string(&normalizer.normalize(string)),
+                                    None => Term::from_string(string),
+                                };
+
                                 vec![
-                                    Token {term: Term::from_string(string), position: 1}
+                                    Token {term: term, position: 1}
                                 ].into()
                             }
                         };
@@ -230,27 +558,14 @@ impl FieldMapping {
                 }
             }
             FieldType::Integer => {
-                match *value {
-                    serde_json::Value::Number(ref num) => {
-                        match num.as_i64() {
-                            Some(num) => Ok(Some(vec![Token{term: Term::from_integer(num), position: 1}].into())),
-                            None => Err(FieldValueError),
-                        }
-                    }
-                    _ => Err(FieldValueError),
-                }
+                let num = self.parse_integer(value)?;
+                Ok(Some(vec![Token{term: Term::from_integer(num), position: 1}].into()))
             }
             FieldType::Boolean => Ok(Some(vec![Token{term: Term::from_boolean(parse_boolean(&value)), position: 1}].into())),
             FieldType::Date => {
                 match *value {
                     serde_json::Value::String(ref string) => {
-                        let date_parsed = match string.parse::<DateTime<Utc>>() {
-                            Ok(date_parsed) => date_parsed,
-                            Err(_) => {
-                                // TODO: Handle this properly
-                                return Err(FieldValueError);
-                            }
-                        };
+                        let date_parsed = self.parse_date(string).ok_or(FieldValueError)?;
 
                         Ok(Some(vec![Token{term: Term::from_datetime(&date_parsed), position: 1}].into()))
                     }
@@ -262,6 +577,54 @@ impl FieldMapping {
                     _ => Err(FieldValueError),
                 }
             }
+            FieldType::Ip => {
+                match *value {
+                    serde_json::Value::String(ref string) => {
+                        match string.parse::<IpAddr>() {
+                            Ok(ip) => Ok(Some(vec![Token{term: Term::from_ip(&ip), position: 1}].into())),
+                            Err(_) => Err(FieldValueError),
+                        }
+                    }
+                    _ => Err(FieldValueError),
+                }
+            }
+            FieldType::Binary | FieldType::DenseVector | FieldType::GeoPoint | FieldType::RankFeature => {
+                // Binary fields are opaque, vectors and rank features are only used for
+                // scoring and points are only used by aggregations, they can only be stored
+                Ok(None)
+            }
+            FieldType::Join => {
+                // The relation name is indexed so documents can be filtered by relation,
+                // children also index a term linking them to their parent
+                let (name, parent) = self.process_join_value(value)?;
+                let mut tokens = vec![Token{term: Term::from_string(name), position: 1}];
+
+                if let Some((parent_relation, parent_key)) = parent {
+                    tokens.push(Token{term: Term::from_join_parent(parent_relation, &parent_key), position: 2});
+                }
+
+                Ok(Some(tokens.into()))
+            }
+            FieldType::GeoShape => {
+                let shape = GeoShape::from_geojson(value).map_err(|_| FieldValueError)?;
+                let tokens = geo::index_terms(&shape).into_iter().enumerate().map(|(i, term)| {
+                    Token{term: term, position: i as u32 + 1}
+                }).collect::<Vec<Token>>();
+
+                Ok(Some(tokens.into()))
+            }
+            FieldType::TokenCount => {
+                let token_count = self.count_tokens(value)?;
+                Ok(Some(vec![Token{term: Term::from_integer(token_count), position: 1}].into()))
+            }
+            FieldType::IntegerRange | FieldType::DateRange => {
+                let (lower, upper) = self.process_range_value(value)?;
+
+                Ok(Some(vec![
+                    Token{term: Term::from_range_bound(RangeBound::Lower, lower), position: 1},
+                    Token{term: Term::from_range_bound(RangeBound::Upper, upper), position: 2},
+                ].into()))
+            }
         }
     }
 
@@ -270,6 +633,14 @@ impl FieldMapping {
             return Ok(None);
         }
 
+        // Only the first item of arrays of non-string values is stored. Use
+        // process_value_for_doc_values to get all of them
+        if let serde_json::Value::Array(ref array) = *value {
+            if self.data_type.splits_arrays() && !self.is_single_value_array(array) {
+                return Ok(self.process_value_for_doc_values(value)?.into_iter().next());
+            }
+        }
+
         match self.data_type {
             FieldType::String => {
                 match *value {
@@ -296,28 +667,12 @@ impl FieldMapping {
                     _ => Err(FieldValueError),
                 }
             }
-            FieldType::Integer => {
-                match *value {
-                    serde_json::Value::Number(ref num) => {
-                        match num.as_i64() {
-                            Some(num) => Ok(Some(FieldValue::Integer(num))),
-                            None => Err(FieldValueError)
-                        }
-                    }
-                    _ => Err(FieldValueError),
-                }
-            }
+            FieldType::Integer => Ok(Some(FieldValue::Integer(self.parse_integer(value)?))),
             FieldType::Boolean => Ok(Some(FieldValue::Boolean(parse_boolean(&value)))),
             FieldType::Date => {
                 match *value {
                     serde_json::Value::String(ref string) => {
-                        let date_parsed = match string.parse::<DateTime<Utc>>() {
-                            Ok(date_parsed) => date_parsed,
-                            Err(_) => {
-                                // TODO: Handle this properly
-                                return Err(FieldValueError);
-                            }
-                        };
+                        let date_parsed = self.parse_date(string).ok_or(FieldValueError)?;
 
                         Ok(Some(FieldValue::DateTime(date_parsed)))
                     }
@@ -329,6 +684,280 @@ impl FieldMapping {
                     _ => Err(FieldValueError)
                 }
             }
+            FieldType::Ip => {
+                match *value {
+                    serde_json::Value::String(ref string) => {
+                        // Store the address in its canonical form
+                        match string.parse::<IpAddr>() {
+                            Ok(ip) => Ok(Some(FieldValue::String(ip.to_string()))),
+                            Err(_) => Err(FieldValueError),
+                        }
+                    }
+                    _ => Err(FieldValueError),
+                }
+            }
+            FieldType::Binary => {
+                match *value {
+                    serde_json::Value::String(ref string) => {
+                        match base64::decode(string) {
+                            Ok(bytes) => Ok(Some(FieldValue::Bytes(bytes))),
+                            Err(_) => Err(FieldValueError),
+                        }
+                    }
+                    _ => Err(FieldValueError),
+                }
+            }
+            FieldType::Join => {
+                // The key of the parent is stored for children so parents can be looked up
+                // from the children that match a has_child query
+                match self.process_join_value(value)? {
+                    (_, Some((_, parent_key))) => Ok(Some(FieldValue::String(parent_key))),
+                    (_, None) => Ok(None),
+                }
+            }
+            FieldType::DenseVector => {
+                match *value {
+                    serde_json::Value::Array(ref array) => {
+                        let mut vector = Vec::with_capacity(array.len());
+
+                        for item in array {
+                            match item.as_f64() {
+                                Some(item) => vector.push(item as f32),
+                                None => return Err(FieldValueError),
+                            }
+                        }
+
+                        // All vectors in the field must be the same length
+                        if let Some(dims) = self.dims {
+                            if vector.len() != dims {
+                                return Err(FieldValueError);
+                            }
+                        }
+
+                        Ok(Some(FieldValue::Vector(vector)))
+                    }
+                    _ => Err(FieldValueError),
+                }
+            }
+            FieldType::GeoShape => {
+                // The shape is stored so queries can check it exactly
+                GeoShape::from_geojson(value).map_err(|_| FieldValueError)?;
+                Ok(Some(FieldValue::String(value.to_string())))
+            }
+            FieldType::GeoPoint => {
+                let point = GeoPoint::from_json(value).map_err(|_| FieldValueError)?;
+                Ok(Some(FieldValue::GeoPoint(point)))
+            }
+            FieldType::TokenCount => Ok(Some(FieldValue::Integer(self.count_tokens(value)?))),
+            FieldType::RankFeature => {
+                // Scoring functions expect features to be positive
+                match value.as_f64() {
+                    Some(feature) if feature > 0.0 && feature.is_finite() => Ok(Some(FieldValue::Float(feature))),
+                    _ => Err(FieldValueError),
+                }
+            }
+            FieldType::IntegerRange | FieldType::DateRange => {
+                // Check the range is valid, but store it as it was given
+                self.process_range_value(value)?;
+                Ok(Some(FieldValue::String(value.to_string())))
+            }
+        }
+    }
+
+    /// Converts a value into the list of values used for sorting and aggregations
+    ///
+    /// Unlike process_value_for_store, each item of an array is kept as a separate value.
+    pub fn process_value_for_doc_values(&self, value: &serde_json::Value) -> Result<Vec<FieldValue>, FieldValueError> {
+        let values = match *value {
+            serde_json::Value::Array(ref array) if self.is_single_value_array(array) => {
+                Ok(self.process_value_for_store(value)?.into_iter().collect())
+            }
+            serde_json::Value::Array(ref array) => {
+                let mut values = Vec::with_capacity(array.len());
+
+                for item in array {
+                    if let serde_json::Value::Array(ref item_array) = *item {
+                        // Nested arrays aren't supported, unless each item is a single value
+                        if !self.is_single_value_array(item_array) {
+                            return Err(FieldValueError);
+                        }
+                    }
+
+                    if let Some(item_value) = self.process_value_for_store(item)? {
+                        values.push(item_value);
+                    }
+                }
+
+                Ok(values)
+            }
+            _ => Ok(self.process_value_for_store(value)?.into_iter().collect()),
+        }?;
+
+        Ok(self.apply_collation(values))
+    }
+
+    /// Replaces strings with their sort keys, if the field has a collation
+    fn apply_collation(&self, values: Vec<FieldValue>) -> Vec<FieldValue> {
+        let collation = match self.collation {
+            Some(ref collation) => collation,
+            None => return values,
+        };
+
+        values.into_iter().map(|value| {
+            match value {
+                FieldValue::String(string) => FieldValue::String(collation.sort_key(&string)),
+                value => value,
+            }
+        }).collect()
+    }
+
+    /// Whether an array is a single value of the field, rather than a list of values
+    ///
+    /// Dense vectors are always arrays, and geo points can be given as [lon, lat].
+    fn is_single_value_array(&self, array: &[serde_json::Value]) -> bool {
+        match self.data_type {
+            FieldType::DenseVector => true,
+            FieldType::GeoPoint => array.first().map(|item| item.is_number()).unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    /// Counts the tokens the index analyzer produces from a value (token count fields only)
+    fn count_tokens(&self, value: &serde_json::Value) -> Result<i64, FieldValueError> {
+        let string = match *value {
+            serde_json::Value::String(ref string) => string.clone(),
+            serde_json::Value::Number(ref num) => num.to_string(),
+            _ => return Err(FieldValueError),
+        };
+
+        match self.index_analyzer() {
+            Some(index_analyzer) => Ok(index_analyzer.tokens(&string).count() as i64),
+
+            // Without an analyzer, the whole value is a single token
+            None => Ok(1),
+        }
+    }
+
+    /// Reads the relation name of a value for a join field, and the parent relation
+    /// and key for children
+    ///
+    /// Values are either the relation name ("question") or an object containing the
+    /// name and the key of the parent ({"name": "answer", "parent": "1"}).
+    fn process_join_value<'a>(&'a self, value: &'a serde_json::Value) -> Result<(&'a str, Option<(&'a str, String)>), FieldValueError> {
+        let (name, parent) = match *value {
+            serde_json::Value::String(ref name) => (name.as_str(), None),
+            serde_json::Value::Object(ref object) => {
+                let name = match object.get("name") {
+                    Some(&serde_json::Value::String(ref name)) => name.as_str(),
+                    _ => return Err(FieldValueError),
+                };
+
+                let parent = match object.get("parent") {
+                    Some(&serde_json::Value::String(ref parent)) => Some(parent.clone()),
+                    Some(&serde_json::Value::Number(ref parent)) => Some(parent.to_string()),
+                    None | Some(&serde_json::Value::Null) => None,
+                    _ => return Err(FieldValueError),
+                };
+
+                (name, parent)
+            }
+            _ => return Err(FieldValueError),
+        };
+
+        match (self.parent_relation(name), parent) {
+            // Child with a parent
+            (Some(parent_relation), Some(parent)) => Ok((name, Some((parent_relation, parent)))),
+
+            // Children must have a parent
+            (Some(_), None) => Err(FieldValueError),
+
+            // Parent, it may not have a parent itself
+            (None, None) if self.relations.contains_key(name) => Ok((name, None)),
+
+            // Unknown relation
+            _ => Err(FieldValueError),
+        }
+    }
+
+    /// Reads the lower and upper bounds of a value for a range field
+    ///
+    /// The bounds are converted into inclusive integers (dates are converted into
+    /// microseconds since the epoch). Missing bounds are unbounded.
+    fn process_range_value(&self, value: &serde_json::Value) -> Result<(i64, i64), FieldValueError> {
+        let object = value.as_object().ok_or(FieldValueError)?;
+        let mut lower = i64::min_value();
+        let mut upper = i64::max_value();
+
+        for (key, bound) in object.iter() {
+            if *bound == serde_json::Value::Null {
+                continue;
+            }
+
+            let bound = self.process_range_bound(bound)?;
+
+            match key.as_ref() {
+                "gte" => lower = bound,
+                "gt" => lower = bound.checked_add(1).ok_or(FieldValueError)?,
+                "lte" => upper = bound,
+                "lt" => upper = bound.checked_sub(1).ok_or(FieldValueError)?,
+                _ => return Err(FieldValueError),
+            }
+        }
+
+        if lower > upper {
+            return Err(FieldValueError);
+        }
+
+        Ok((lower, upper))
+    }
+
+    fn process_range_bound(&self, value: &serde_json::Value) -> Result<i64, FieldValueError> {
+        match (self.data_type, value) {
+            (FieldType::IntegerRange, &serde_json::Value::Number(ref num)) => num.as_i64().ok_or(FieldValueError),
+            (FieldType::DateRange, &serde_json::Value::String(ref string)) => {
+                match self.parse_date(string) {
+                    Some(date_parsed) => Ok(datetime_to_micros(&date_parsed)),
+                    None => Err(FieldValueError),
+                }
+            }
+            _ => Err(FieldValueError),
+        }
+    }
+}
+
+
+/// Removes the hidden sub fields from the JSON of a mapping's properties, these are
+/// added back from the settings of the field they belong to when it's parsed
+fn remove_hidden_sub_fields(properties: &HashMap<String, MappingProperty>, properties_json: &mut BTreeMap<String, serde_json::Value>) {
+    for (name, prop) in properties.iter() {
+        if let MappingProperty::Field(ref field_mapping) = *prop {
+            if field_mapping.index_prefixes.is_some() {
+                properties_json.remove(&IndexPrefixes::sub_field_name(name));
+            }
+        }
+    }
+}
+
+
+/// Moves the JSON of multi-fields back under the field they belong to, as they're
+/// given in the "fields" setting of that field rather than as properties of their own
+fn move_multi_fields_under_parents(properties: &HashMap<String, MappingProperty>, properties_json: &mut BTreeMap<String, serde_json::Value>) {
+    for (name, prop) in properties.iter() {
+        if let MappingProperty::Field(ref field_mapping) = *prop {
+            if field_mapping.multi_fields.is_empty() {
+                continue;
+            }
+
+            let mut fields_json = BTreeMap::new();
+            for multi_field_name in field_mapping.multi_fields.iter() {
+                if let Some(multi_field_json) = properties_json.remove(multi_field_name) {
+                    fields_json.insert(multi_field_name[name.len() + 1..].to_string(), multi_field_json);
+                }
+            }
+
+            if let Some(field_json) = properties_json.get_mut(name) {
+                field_json["fields"] = json!(fields_json);
+            }
         }
     }
 }
@@ -348,6 +977,8 @@ impl Serialize for NestedMapping {
         for (name, prop) in self.properties.iter() {
             properties_json.insert(name.to_string(), serde_json::to_value(&prop).unwrap());
         }
+        remove_hidden_sub_fields(&self.properties, &mut properties_json);
+        move_multi_fields_under_parents(&self.properties, &mut properties_json);
 
         let json = json!({
             "type": "nested",
@@ -376,9 +1007,62 @@ impl Serialize for MappingProperty {
 }
 
 
+/// Configuration of the "_boost" meta field
+///
+/// This names a field in the document source which contains a boost factor for
+/// the whole document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoostFieldMapping {
+    pub name: String,
+    pub null_value: f64,
+}
+
+
 #[derive(Debug, PartialEq)]
 pub struct Mapping {
     pub properties: HashMap<String, MappingProperty>,
+
+    /// Boost applied to every document of this type
+    pub boost: f64,
+
+    pub boost_field: Option<BoostFieldMapping>,
+
+    /// Whether fields that aren't in the mapping are added when a document is indexed
+    /// Documents with unmapped fields are rejected if this is off
+    pub dynamic: bool,
+
+    /// Whether new string fields that look like dates are mapped as dates
+    pub date_detection: bool,
+
+    /// Whether new string fields that look like integers are mapped as integers
+    pub numeric_detection: bool,
+
+    /// The formats tried by date detection, in order
+    pub dynamic_date_formats: Vec<String>,
+}
+
+
+/// The formats tried by date detection if none are given in the mapping
+pub fn default_dynamic_date_formats() -> Vec<String> {
+    vec![
+        "strict_date_optional_time".to_string(),
+        "yyyy/MM/dd HH:mm:ss Z||yyyy/MM/dd Z".to_string(),
+    ]
+}
+
+
+impl Default for Mapping {
+    fn default() -> Mapping {
+        Mapping {
+            properties: HashMap::new(),
+            boost: 1.0f64,
+            boost_field: None,
+            dynamic: false,
+            date_detection: true,
+            numeric_detection: false,
+            dynamic_date_formats: default_dynamic_date_formats(),
+        }
+    }
 }
 
 
@@ -390,16 +1074,104 @@ impl Serialize for Mapping {
         for (name, prop) in self.properties.iter() {
             properties_json.insert(name.to_string(), serde_json::to_value(&prop).unwrap());
         }
+        remove_hidden_sub_fields(&self.properties, &mut properties_json);
+        move_multi_fields_under_parents(&self.properties, &mut properties_json);
 
-        let json = json!({
+        let mut json = json!({
             "properties": properties_json,
         });
 
+        if self.boost != 1.0f64 {
+            json["boost"] = json!(self.boost);
+        }
+
+        if let Some(ref boost_field) = self.boost_field {
+            json["_boost"] = json!({
+                "name": boost_field.name,
+                "null_value": boost_field.null_value,
+            });
+        }
+
+        if self.dynamic {
+            json["dynamic"] = json!(true);
+        }
+
+        if !self.date_detection {
+            json["date_detection"] = json!(false);
+        }
+
+        if self.numeric_detection {
+            json["numeric_detection"] = json!(true);
+        }
+
+        if self.dynamic_date_formats != default_dynamic_date_formats() {
+            json["dynamic_date_formats"] = json!(self.dynamic_date_formats);
+        }
+
         json.serialize(serializer)
     }
 }
 
 
+impl Mapping {
+    /// Works out the boost of a document from its source
+    pub fn get_document_boost(&self, data: &serde_json::Map<String, serde_json::Value>) -> Result<f64, FieldValueError> {
+        let mut boost = self.boost;
+
+        if let Some(ref boost_field) = self.boost_field {
+            boost *= match data.get(&boost_field.name) {
+                Some(&serde_json::Value::Number(ref num)) => num.as_f64().ok_or(FieldValueError)?,
+                Some(&serde_json::Value::Null) | None => boost_field.null_value,
+                Some(_) => return Err(FieldValueError),
+            };
+        }
+
+        Ok(boost)
+    }
+
+    /// Works out the type of a field that isn't in the mapping from its value
+    ///
+    /// For dates, the format that matched the value is returned too. Returns None
+    /// for values that can't be mapped automatically (nulls and objects).
+    pub fn detect_field_type(&self, value: &serde_json::Value) -> Option<(FieldType, Option<String>)> {
+        match *value {
+            serde_json::Value::Bool(_) => Some((FieldType::Boolean, None)),
+            serde_json::Value::Number(ref num) => {
+                if num.is_i64() {
+                    Some((FieldType::Integer, None))
+                } else {
+                    // There's no floating point field type, string fields accept numbers
+                    Some((FieldType::String, None))
+                }
+            }
+            serde_json::Value::String(ref string) => {
+                if self.date_detection {
+                    for date_format in self.dynamic_date_formats.iter() {
+                        if date_format::parse_date(string, date_format).is_some() {
+                            return Some((FieldType::Date, Some(date_format.clone())));
+                        }
+                    }
+                }
+
+                if self.numeric_detection && string.parse::<i64>().is_ok() {
+                    return Some((FieldType::Integer, None));
+                }
+
+                Some((FieldType::String, None))
+            }
+            serde_json::Value::Array(ref array) => {
+                // The type of the first value is used, the rest must match it
+                match array.iter().find(|item| **item != serde_json::Value::Null) {
+                    Some(item) => self.detect_field_type(item),
+                    None => None,
+                }
+            }
+            serde_json::Value::Null | serde_json::Value::Object(_) => None,
+        }
+    }
+}
+
+
 fn parse_boolean(json: &serde_json::Value) -> bool {
     match *json {
         serde_json::Value::Bool(val) => val,
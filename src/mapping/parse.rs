@@ -2,8 +2,14 @@ use std::collections::{HashMap, BTreeSet};
 
 use serde_json;
 
-use mapping::FieldType;
+use search::similarity::VectorSimilarity;
+
+use analysis::collation::{Collation, CollationStrength};
+
+use mapping::{FieldType, BoostFieldMapping, IndexPrefixes};
+use mapping::date_format::is_valid_format;
 use mapping::build::{MappingBuilder, MappingPropertyBuilder, FieldMappingBuilder, NestedMappingBuilder};
+use json_pointer;
 
 
 #[derive(Debug, PartialEq)]
@@ -26,9 +32,40 @@ pub enum FieldMappingParseError {
     AnalyzersOnlyAllowedOnStringType,
     AnalyzersOnlyAllowedOnAnalyzedFields,
 
+    // "index" setting on binary fields
+    BinaryFieldsCannotBeIndexed,
+
+    // "relations" setting
+    RelationsOnlyAllowedOnJoinType,
+
+    // "dims" and "similarity" settings
+    VectorSettingsOnlyAllowedOnDenseVectorType,
+    UnrecognisedVectorSimilarity(String),
+
+    // "fields" setting
+    MultiFieldParseError(String, Box<FieldMappingParseError>),
+    MultiFieldsCannotBeNested,
+
     // "boost" setting
     BoostOnlyAllowedOnIndexedFields,
     BoostMustBePositive,
+
+    // "format" setting
+    FormatOnlyAllowedOnDateTypes,
+    InvalidDateFormat(String),
+
+    // "collation" setting
+    CollationOnlyAllowedOnKeywordFields,
+    UnrecognisedCollationLocale(String),
+    UnrecognisedCollationStrength(String),
+
+    // "normalizer" setting
+    NormalizerOnlyAllowedOnKeywordFields,
+
+    // "index_prefixes" setting
+    IndexPrefixesOnlyAllowedOnAnalyzedFields,
+    IndexPrefixesNotAllowedOnMultiFields,
+    InvalidIndexPrefixes,
 }
 
 
@@ -42,6 +79,57 @@ pub enum MappingParseError {
     UnrecognisedKeys(Vec<String>),
     FieldMappingParseError(String, FieldMappingParseError),
     NestedMappingParseError(String, Box<MappingParseError>),
+    BoostMustBePositive,
+    InvalidDateFormat(String),
+}
+
+
+/// Finds the pointer to the first of the unrecognised keys
+fn unrecognised_key_pointer(json: &serde_json::Value, keys: &[String]) -> Option<String> {
+    keys.first().and_then(|key| json_pointer::find_key(json, key))
+}
+
+
+/// Adds "path" to the start of the pointer to an error in the object that it points to
+fn nested_pointer<F>(json: &serde_json::Value, path: String, find: F) -> Option<String>
+    where F: FnOnce(&serde_json::Value) -> Option<String>
+{
+    json.pointer(&path).and_then(find).map(|pointer| path + &pointer)
+}
+
+
+impl FieldMappingParseError {
+    /// Finds the pointer to the key that the error is about in the JSON of the field
+    ///
+    /// Only errors about unrecognised keys point to a key, this returns None for others.
+    pub fn pointer(&self, json: &serde_json::Value) -> Option<String> {
+        match *self {
+            FieldMappingParseError::UnrecognisedKeys(ref keys) => unrecognised_key_pointer(json, keys),
+            FieldMappingParseError::MultiFieldParseError(ref name, ref error) => {
+                nested_pointer(json, json_pointer::join("/fields", name), |json| error.pointer(json))
+            }
+            _ => None,
+        }
+    }
+}
+
+
+impl MappingParseError {
+    /// Finds the pointer to the key that the error is about in the JSON of the mapping
+    ///
+    /// Only errors about unrecognised keys point to a key, this returns None for others.
+    pub fn pointer(&self, json: &serde_json::Value) -> Option<String> {
+        match *self {
+            MappingParseError::UnrecognisedKeys(ref keys) => unrecognised_key_pointer(json, keys),
+            MappingParseError::FieldMappingParseError(ref name, ref error) => {
+                nested_pointer(json, json_pointer::join("/properties", name), |json| error.pointer(json))
+            }
+            MappingParseError::NestedMappingParseError(ref name, ref error) => {
+                nested_pointer(json, json_pointer::join("/properties", name), |json| error.pointer(json))
+            }
+            _ => None,
+        }
+    }
 }
 
 
@@ -79,11 +167,70 @@ fn parse_field_type(field_type_str: &str) -> Result<FieldType, FieldMappingParse
         "integer" => Ok(FieldType::Integer),
         "boolean" => Ok(FieldType::Boolean),
         "date" => Ok(FieldType::Date),
+        "integer_range" => Ok(FieldType::IntegerRange),
+        "date_range" => Ok(FieldType::DateRange),
+        "ip" => Ok(FieldType::Ip),
+        "binary" => Ok(FieldType::Binary),
+        "join" => Ok(FieldType::Join),
+        "dense_vector" => Ok(FieldType::DenseVector),
+        "geo_shape" => Ok(FieldType::GeoShape),
+        "geo_point" => Ok(FieldType::GeoPoint),
+        "token_count" => Ok(FieldType::TokenCount),
+        "rank_feature" => Ok(FieldType::RankFeature),
         _ => Err(FieldMappingParseError::UnrecognisedFieldType(field_type_str.to_string())),
     }
 }
 
 
+fn parse_collation(json: &serde_json::Value) -> Result<Collation, FieldMappingParseError> {
+    let collation_object = json.as_object().ok_or(FieldMappingParseError::ExpectedObject)?;
+
+    // Check for unrecognised keys
+    for key in collation_object.keys() {
+        if key != "locale" && key != "strength" {
+            return Err(FieldMappingParseError::UnrecognisedKeys(vec![key.clone()]));
+        }
+    }
+
+    let locale_json = collation_object.get("locale").ok_or(FieldMappingParseError::ExpectedKey("locale".to_string()))?;
+    let locale_str = locale_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
+
+    let strength = match collation_object.get("strength") {
+        Some(strength_json) => {
+            let strength_str = strength_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
+            CollationStrength::from_str(strength_str).ok_or_else(|| FieldMappingParseError::UnrecognisedCollationStrength(strength_str.to_string()))?
+        }
+        None => CollationStrength::default(),
+    };
+
+    Collation::new(locale_str, strength).ok_or_else(|| FieldMappingParseError::UnrecognisedCollationLocale(locale_str.to_string()))
+}
+
+
+/// Parses "index_prefixes", eg {"min_chars": 1, "max_chars": 10}
+fn parse_index_prefixes(json: &serde_json::Value) -> Result<IndexPrefixes, FieldMappingParseError> {
+    let object = json.as_object().ok_or(FieldMappingParseError::ExpectedObject)?;
+    let mut index_prefixes = IndexPrefixes::default();
+
+    for (key, value) in object.iter() {
+        let value = value.as_u64().ok_or(FieldMappingParseError::ExpectedNumber)? as usize;
+
+        match key.as_ref() {
+            "min_chars" => index_prefixes.min_chars = value,
+            "max_chars" => index_prefixes.max_chars = value,
+            _ => return Err(FieldMappingParseError::UnrecognisedKeys(vec![key.clone()])),
+        }
+    }
+
+    // Elasticsearch has the same limits
+    if index_prefixes.min_chars < 1 || index_prefixes.max_chars > 20 || index_prefixes.min_chars > index_prefixes.max_chars {
+        return Err(FieldMappingParseError::InvalidIndexPrefixes);
+    }
+
+    Ok(index_prefixes)
+}
+
+
 fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMappingParseError> {
     let field_object = json.as_object().ok_or(FieldMappingParseError::ExpectedObject)?;
     let mut mapping_builder = FieldMappingBuilder::default();
@@ -99,6 +246,14 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         "search_analyzer".to_string(),
         "boost".to_string(),
         "include_in_all".to_string(),
+        "relations".to_string(),
+        "dims".to_string(),
+        "similarity".to_string(),
+        "fields".to_string(),
+        "format".to_string(),
+        "collation".to_string(),
+        "normalizer".to_string(),
+        "index_prefixes".to_string(),
     ];
     let unrecognised_keys = provided_keys.difference(&allowed_keys).cloned().collect::<Vec<String>>();
 
@@ -111,8 +266,8 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
     let field_type_str = field_type_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
     mapping_builder.field_type = parse_field_type(field_type_str)?;
 
-    // Non-string fields cannot be analyzed
-    if mapping_builder.field_type != FieldType::String {
+    // Only string and token count fields can be analyzed
+    if !mapping_builder.field_type.uses_analyzer() {
         mapping_builder.is_analyzed = false;
     }
 
@@ -134,7 +289,7 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
                 mapping_builder.is_analyzed = true;
 
                 // Not valid for non-string fields
-                if mapping_builder.field_type != FieldType::String {
+                if !mapping_builder.field_type.uses_analyzer() {
                     return Err(FieldMappingParseError::IndexAnalyzedOnlyAllowedOnStringType);
                 }
             }
@@ -144,6 +299,16 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         }
     }
 
+    // Binary fields are only stored, they aren't searchable
+    if mapping_builder.field_type == FieldType::Binary {
+        if field_object.contains_key("index") && mapping_builder.is_indexed {
+            return Err(FieldMappingParseError::BinaryFieldsCannotBeIndexed);
+        }
+
+        mapping_builder.is_indexed = false;
+        mapping_builder.is_in_all = false;
+    }
+
     // "store" setting
     if let Some(store_json) = field_object.get("store") {
         mapping_builder.is_stored = parse_boolean(store_json)?;
@@ -154,7 +319,7 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         let analyzer_str = analyzer_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
         mapping_builder.base_analyzer = Some(analyzer_str.to_string());
 
-        if mapping_builder.field_type != FieldType::String {
+        if !mapping_builder.field_type.uses_analyzer() {
             return Err(FieldMappingParseError::AnalyzersOnlyAllowedOnStringType);
         }
 
@@ -167,7 +332,7 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         let index_analyzer_str = index_analyzer_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
         mapping_builder.index_analyzer = Some(index_analyzer_str.to_string());
 
-        if mapping_builder.field_type != FieldType::String {
+        if !mapping_builder.field_type.uses_analyzer() {
             return Err(FieldMappingParseError::AnalyzersOnlyAllowedOnStringType);
         }
 
@@ -180,7 +345,7 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         let search_analyzer_str = search_analyzer_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
         mapping_builder.search_analyzer = Some(search_analyzer_str.to_string());
 
-        if mapping_builder.field_type != FieldType::String {
+        if !mapping_builder.field_type.uses_analyzer() {
             return Err(FieldMappingParseError::AnalyzersOnlyAllowedOnStringType);
         }
 
@@ -209,6 +374,165 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         mapping_builder.is_in_all = include_in_all;
     }
 
+    // "relations" setting
+    if let Some(relations_json) = field_object.get("relations") {
+        if mapping_builder.field_type != FieldType::Join {
+            return Err(FieldMappingParseError::RelationsOnlyAllowedOnJoinType);
+        }
+
+        let relations_object = relations_json.as_object().ok_or(FieldMappingParseError::ExpectedObject)?;
+
+        for (parent_relation, child_relations_json) in relations_object.iter() {
+            // Either a single child relation or a list of them
+            let child_relations = match *child_relations_json {
+                serde_json::Value::String(ref child_relation) => vec![child_relation.clone()],
+                serde_json::Value::Array(ref child_relations) => {
+                    let mut relations = Vec::with_capacity(child_relations.len());
+
+                    for child_relation in child_relations {
+                        let child_relation = child_relation.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
+                        relations.push(child_relation.to_string());
+                    }
+
+                    relations
+                }
+                _ => return Err(FieldMappingParseError::ExpectedString),
+            };
+
+            mapping_builder.relations.insert(parent_relation.clone(), child_relations);
+        }
+    }
+
+    // Join fields must be indexed and stored so has_child and has_parent queries can
+    // find related documents
+    if mapping_builder.field_type == FieldType::Join {
+        if mapping_builder.relations.is_empty() {
+            return Err(FieldMappingParseError::ExpectedKey("relations".to_string()));
+        }
+
+        mapping_builder.is_indexed = true;
+        mapping_builder.is_stored = true;
+        mapping_builder.is_in_all = false;
+    }
+
+    // Geo points are read from the doc values of each document by aggregations, and
+    // rank features from the stored value of each document when scoring
+    if mapping_builder.field_type == FieldType::GeoPoint || mapping_builder.field_type == FieldType::RankFeature {
+        mapping_builder.is_indexed = false;
+        mapping_builder.is_stored = true;
+        mapping_builder.is_in_all = false;
+    }
+
+    // "dims" setting
+    if let Some(dims_json) = field_object.get("dims") {
+        if mapping_builder.field_type != FieldType::DenseVector {
+            return Err(FieldMappingParseError::VectorSettingsOnlyAllowedOnDenseVectorType);
+        }
+
+        let dims = dims_json.as_u64().ok_or(FieldMappingParseError::ExpectedNumber)?;
+        mapping_builder.dims = Some(dims as usize);
+    }
+
+    // "similarity" setting
+    if let Some(similarity_json) = field_object.get("similarity") {
+        if mapping_builder.field_type != FieldType::DenseVector {
+            return Err(FieldMappingParseError::VectorSettingsOnlyAllowedOnDenseVectorType);
+        }
+
+        let similarity_str = similarity_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
+        mapping_builder.vector_similarity = match similarity_str {
+            "cosine" => VectorSimilarity::Cosine,
+            "dot_product" => VectorSimilarity::DotProduct,
+            _ => return Err(FieldMappingParseError::UnrecognisedVectorSimilarity(similarity_str.to_string())),
+        };
+    }
+
+    // Dense vectors are read from the stored value of each document when scoring
+    if mapping_builder.field_type == FieldType::DenseVector {
+        if mapping_builder.dims.is_none() {
+            return Err(FieldMappingParseError::ExpectedKey("dims".to_string()));
+        }
+
+        mapping_builder.is_indexed = false;
+        mapping_builder.is_stored = true;
+        mapping_builder.is_in_all = false;
+    }
+
+    // Geo shapes are read from the stored value of each candidate document when querying
+    if mapping_builder.field_type == FieldType::GeoShape {
+        mapping_builder.is_indexed = true;
+        mapping_builder.is_stored = true;
+        mapping_builder.is_in_all = false;
+    }
+
+    // "format" setting
+    if let Some(format_json) = field_object.get("format") {
+        match mapping_builder.field_type {
+            FieldType::Date | FieldType::DateRange => {}
+            _ => return Err(FieldMappingParseError::FormatOnlyAllowedOnDateTypes),
+        }
+
+        let format_str = format_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
+        if !is_valid_format(format_str) {
+            return Err(FieldMappingParseError::InvalidDateFormat(format_str.to_string()));
+        }
+
+        mapping_builder.date_format = Some(format_str.to_string());
+    }
+
+    // "normalizer" setting
+    if let Some(normalizer_json) = field_object.get("normalizer") {
+        if mapping_builder.field_type != FieldType::String || mapping_builder.is_analyzed {
+            return Err(FieldMappingParseError::NormalizerOnlyAllowedOnKeywordFields);
+        }
+
+        let normalizer_str = normalizer_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
+        mapping_builder.normalizer = Some(normalizer_str.to_string());
+    }
+
+    // "collation" setting
+    if let Some(collation_json) = field_object.get("collation") {
+        if mapping_builder.field_type != FieldType::String || mapping_builder.is_analyzed {
+            return Err(FieldMappingParseError::CollationOnlyAllowedOnKeywordFields);
+        }
+
+        mapping_builder.collation = Some(parse_collation(collation_json)?);
+    }
+
+    // "index_prefixes" setting
+    if let Some(index_prefixes_json) = field_object.get("index_prefixes") {
+        if mapping_builder.field_type != FieldType::String || !mapping_builder.is_analyzed || !mapping_builder.is_indexed {
+            return Err(FieldMappingParseError::IndexPrefixesOnlyAllowedOnAnalyzedFields);
+        }
+
+        mapping_builder.index_prefixes = Some(parse_index_prefixes(index_prefixes_json)?);
+    }
+
+    // "fields" setting
+    if let Some(fields_json) = field_object.get("fields") {
+        let fields_object = fields_json.as_object().ok_or(FieldMappingParseError::ExpectedObject)?;
+
+        for (sub_field_name, sub_field_json) in fields_object.iter() {
+            let mut sub_field = match parse_field(sub_field_json) {
+                Ok(sub_field) => sub_field,
+                Err(e) => return Err(FieldMappingParseError::MultiFieldParseError(sub_field_name.clone(), Box::new(e))),
+            };
+
+            if !sub_field.fields.is_empty() {
+                return Err(FieldMappingParseError::MultiFieldsCannotBeNested);
+            }
+
+            if sub_field.index_prefixes.is_some() {
+                return Err(FieldMappingParseError::IndexPrefixesNotAllowedOnMultiFields);
+            }
+
+            // The value is already copied into _all by the main field
+            sub_field.is_in_all = false;
+
+            mapping_builder.fields.insert(sub_field_name.clone(), sub_field);
+        }
+    }
+
     Ok(mapping_builder)
 }
 
@@ -265,6 +589,47 @@ fn parse_nested_mapping(json: &serde_json::Value) -> Result<NestedMappingBuilder
 }
 
 
+fn parse_mapping_boost(json: &serde_json::Value) -> Result<f64, MappingParseError> {
+    let boost = json.as_f64().ok_or(MappingParseError::ExpectedNumber)?;
+
+    if boost < 0.0f64 {
+        return Err(MappingParseError::BoostMustBePositive);
+    }
+
+    Ok(boost)
+}
+
+
+fn parse_boost_field(json: &serde_json::Value) -> Result<BoostFieldMapping, MappingParseError> {
+    let boost_field_object = json.as_object().ok_or(MappingParseError::ExpectedObject)?;
+
+    // Check for unrecognised keys
+    let provided_keys = boost_field_object.keys().cloned().collect::<BTreeSet<String>>();
+    let allowed_keys = btreeset![
+        "name".to_string(),
+        "null_value".to_string(),
+    ];
+    let unrecognised_keys = provided_keys.difference(&allowed_keys).cloned().collect::<Vec<String>>();
+
+    if !unrecognised_keys.is_empty() {
+        return Err(MappingParseError::UnrecognisedKeys(unrecognised_keys));
+    }
+
+    let name_json = boost_field_object.get("name").ok_or(MappingParseError::ExpectedKey("name".to_string()))?;
+    let name = name_json.as_str().ok_or(MappingParseError::ExpectedString)?;
+
+    let null_value = match boost_field_object.get("null_value") {
+        Some(null_value_json) => parse_mapping_boost(null_value_json)?,
+        None => 1.0f64,
+    };
+
+    Ok(BoostFieldMapping {
+        name: name.to_string(),
+        null_value: null_value,
+    })
+}
+
+
 pub fn parse(json: &serde_json::Value) -> Result<MappingBuilder, MappingParseError> {
     let mapping_object = json.as_object().ok_or(MappingParseError::ExpectedObject)?;
 
@@ -272,6 +637,12 @@ pub fn parse(json: &serde_json::Value) -> Result<MappingBuilder, MappingParseErr
     let provided_keys = mapping_object.keys().cloned().collect::<BTreeSet<String>>();
     let allowed_keys = btreeset![
         "properties".to_string(),
+        "boost".to_string(),
+        "_boost".to_string(),
+        "dynamic".to_string(),
+        "date_detection".to_string(),
+        "numeric_detection".to_string(),
+        "dynamic_date_formats".to_string(),
     ];
     let unrecognised_keys = provided_keys.difference(&allowed_keys).cloned().collect::<Vec<String>>();
 
@@ -310,15 +681,64 @@ pub fn parse(json: &serde_json::Value) -> Result<MappingBuilder, MappingParseErr
         }
     }
 
-    Ok(MappingBuilder {
+    // Type-level boost
+    let boost = match mapping_object.get("boost") {
+        Some(boost_json) => parse_mapping_boost(boost_json)?,
+        None => 1.0f64,
+    };
+
+    // "_boost" field
+    let boost_field = match mapping_object.get("_boost") {
+        Some(boost_field_json) => Some(parse_boost_field(boost_field_json)?),
+        None => None,
+    };
+
+    let mut mapping_builder = MappingBuilder {
         properties: properties,
-    })
+        boost: boost,
+        boost_field: boost_field,
+        .. MappingBuilder::default()
+    };
+
+    // Dynamic mapping settings
+    if let Some(dynamic_json) = mapping_object.get("dynamic") {
+        mapping_builder.dynamic = dynamic_json.as_bool().ok_or(MappingParseError::ExpectedBoolean)?;
+    }
+
+    if let Some(date_detection_json) = mapping_object.get("date_detection") {
+        mapping_builder.date_detection = date_detection_json.as_bool().ok_or(MappingParseError::ExpectedBoolean)?;
+    }
+
+    if let Some(numeric_detection_json) = mapping_object.get("numeric_detection") {
+        mapping_builder.numeric_detection = numeric_detection_json.as_bool().ok_or(MappingParseError::ExpectedBoolean)?;
+    }
+
+    if let Some(formats_json) = mapping_object.get("dynamic_date_formats") {
+        let formats_array = formats_json.as_array().ok_or(MappingParseError::ExpectedString)?;
+        let mut formats = Vec::with_capacity(formats_array.len());
+
+        for format_json in formats_array {
+            let format_str = format_json.as_str().ok_or(MappingParseError::ExpectedString)?;
+            if !is_valid_format(format_str) {
+                return Err(MappingParseError::InvalidDateFormat(format_str.to_string()));
+            }
+
+            formats.push(format_str.to_string());
+        }
+
+        mapping_builder.dynamic_date_formats = formats;
+    }
+
+    Ok(mapping_builder)
 }
 
 
 #[cfg(test)]
 mod tests {
-    use mapping::FieldType;
+    use search::similarity::VectorSimilarity;
+
+    use analysis::collation::{Collation, CollationStrength};
+    use mapping::{FieldType, BoostFieldMapping, IndexPrefixes};
     use mapping::build::{FieldMappingBuilder, NestedMappingBuilder, MappingPropertyBuilder, MappingBuilder};
 
     use super::{MappingParseError, FieldMappingParseError, parse, parse_field};
@@ -343,7 +763,8 @@ mod tests {
                         ..FieldMappingBuilder::default()
                     }
                 )
-            }
+            },
+            ..MappingBuilder::default()
         }));
     }
 
@@ -378,7 +799,8 @@ mod tests {
                         }
                     }
                 ))
-            }
+            },
+            ..MappingBuilder::default()
         }));
     }
 
@@ -424,7 +846,8 @@ mod tests {
                         }
                     }
                 ))
-            }
+            },
+            ..MappingBuilder::default()
         }));
     }
 
@@ -476,9 +899,67 @@ mod tests {
 
         assert_eq!(mapping, Ok(MappingBuilder {
             properties: hashmap! {},
+            ..MappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_boost() {
+        let mapping = parse(&json!(
+            {
+                "properties": {},
+                "boost": 2.0,
+                "_boost": {
+                    "name": "popularity",
+                    "null_value": 0.5
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Ok(MappingBuilder {
+            properties: hashmap! {},
+            boost: 2.0f64,
+            boost_field: Some(BoostFieldMapping {
+                name: "popularity".to_string(),
+                null_value: 0.5f64,
+            }),
+            ..MappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_boost_field_default_null_value() {
+        let mapping = parse(&json!(
+            {
+                "properties": {},
+                "_boost": {
+                    "name": "popularity"
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Ok(MappingBuilder {
+            properties: hashmap! {},
+            boost_field: Some(BoostFieldMapping {
+                name: "popularity".to_string(),
+                null_value: 1.0f64,
+            }),
+            ..MappingBuilder::default()
         }));
     }
 
+    #[test]
+    fn test_parse_negative_boost() {
+        let mapping = parse(&json!(
+            {
+                "properties": {},
+                "boost": -1.0
+            }
+        ));
+
+        assert_eq!(mapping, Err(MappingParseError::BoostMustBePositive));
+    }
+
     #[test]
     fn test_parse_unrecognised_key() {
         let mapping = parse(&json!(
@@ -492,6 +973,51 @@ mod tests {
         assert_eq!(mapping, Err(MappingParseError::UnrecognisedKeys(vec!["baz".to_string(), "foo".to_string()])));
     }
 
+    #[test]
+    fn test_unrecognised_key_pointer() {
+        let json = json!(
+            {
+                "properties": {
+                    "title": {
+                        "type": "string",
+                        "fields": {
+                            "raw": {
+                                "type": "string",
+                                "index": "not_analyzed",
+                                "ignore_above": 256
+                            }
+                        }
+                    }
+                }
+            }
+        );
+        let error = parse(&json).err().unwrap();
+
+        assert_eq!(error.pointer(&json), Some("/properties/title/fields/raw/ignore_above".to_string()));
+    }
+
+    #[test]
+    fn test_unrecognised_key_pointer_in_nested_mapping() {
+        let json = json!(
+            {
+                "properties": {
+                    "comments": {
+                        "type": "nested",
+                        "properties": {
+                            "author": {
+                                "type": "string",
+                                "copy_to": "authors"
+                            }
+                        }
+                    }
+                }
+            }
+        );
+        let error = parse(&json).err().unwrap();
+
+        assert_eq!(error.pointer(&json), Some("/properties/comments/properties/author/copy_to".to_string()));
+    }
+
     #[test]
     fn test_parse_bad_type_properties() {
         // Array
@@ -575,48 +1101,370 @@ mod tests {
             is_analyzed: false,
             ..FieldMappingBuilder::default()
         }));
-    }
 
-    #[test]
-    fn test_parse_field_no_type() {
-        let mapping = parse_field(&json!({}));
+        // Integer range
+        let mapping = parse_field(&json!(
+            {
+                "type": "integer_range"
+            }
+        ));
 
-        assert_eq!(mapping, Err(FieldMappingParseError::ExpectedKey("type".to_string())));
-    }
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::IntegerRange,
+            is_analyzed: false,
+            ..FieldMappingBuilder::default()
+        }));
 
-    #[test]
-    fn test_parse_field_unrecognised_type() {
+        // Date range
         let mapping = parse_field(&json!(
             {
-                "type": "foo"
+                "type": "date_range"
             }
         ));
 
-        assert_eq!(mapping, Err(FieldMappingParseError::UnrecognisedFieldType("foo".to_string())));
-    }
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::DateRange,
+            is_analyzed: false,
+            ..FieldMappingBuilder::default()
+        }));
 
-    #[test]
-    fn test_parse_field_type_not_string() {
+        // IP
         let mapping = parse_field(&json!(
             {
-                "type": 123
+                "type": "ip"
             }
         ));
 
-        assert_eq!(mapping, Err(FieldMappingParseError::ExpectedString));
-    }
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::Ip,
+            is_analyzed: false,
+            ..FieldMappingBuilder::default()
+        }));
 
-    #[test]
-    fn test_parse_field_unrecognised_key() {
+        // Binary
         let mapping = parse_field(&json!(
             {
-                "type": "string",
-                "foo": "bar",
-                "baz": "quux"
+                "type": "binary"
             }
         ));
 
-        assert_eq!(mapping, Err(FieldMappingParseError::UnrecognisedKeys(vec!["baz".to_string(), "foo".to_string()])));
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::Binary,
+            is_indexed: false,
+            is_analyzed: false,
+            is_in_all: false,
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_binary_field_indexed() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "binary",
+                "index": "not_analyzed"
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::BinaryFieldsCannotBeIndexed));
+    }
+
+    #[test]
+    fn test_parse_join_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "join",
+                "relations": {
+                    "question": ["answer", "comment"],
+                    "answer": "vote"
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::Join,
+            is_analyzed: false,
+            is_stored: true,
+            is_in_all: false,
+            relations: btreemap! {
+                "question".to_string() => vec!["answer".to_string(), "comment".to_string()],
+                "answer".to_string() => vec!["vote".to_string()],
+            },
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_join_field_without_relations() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "join"
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::ExpectedKey("relations".to_string())));
+    }
+
+    #[test]
+    fn test_parse_relations_on_non_join_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "relations": {
+                    "question": "answer"
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::RelationsOnlyAllowedOnJoinType));
+    }
+
+    #[test]
+    fn test_parse_multi_fields() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "fields": {
+                    "length": {
+                        "type": "token_count",
+                        "analyzer": "standard"
+                    }
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::String,
+            fields: btreemap! {
+                "length".to_string() => FieldMappingBuilder {
+                    field_type: FieldType::TokenCount,
+                    is_in_all: false,
+                    base_analyzer: Some("standard".to_string()),
+                    ..FieldMappingBuilder::default()
+                }
+            },
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_nested_multi_fields() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "fields": {
+                    "raw": {
+                        "type": "string",
+                        "fields": {
+                            "length": {
+                                "type": "token_count"
+                            }
+                        }
+                    }
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::MultiFieldsCannotBeNested));
+    }
+
+    #[test]
+    fn test_parse_invalid_multi_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "fields": {
+                    "length": {
+                        "type": "foo"
+                    }
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::MultiFieldParseError("length".to_string(), Box::new(FieldMappingParseError::UnrecognisedFieldType("foo".to_string())))));
+    }
+
+    #[test]
+    fn test_parse_index_prefixes() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "index_prefixes": {
+                    "min_chars": 1,
+                    "max_chars": 10
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::String,
+            index_prefixes: Some(IndexPrefixes {
+                min_chars: 1,
+                max_chars: 10,
+            }),
+            ..FieldMappingBuilder::default()
+        }));
+
+        // The lengths default to 2 to 5 characters
+        let mapping = parse_field(&json!({"type": "string", "index_prefixes": {}}));
+        assert_eq!(mapping.map(|mapping| mapping.index_prefixes), Ok(Some(IndexPrefixes { min_chars: 2, max_chars: 5 })));
+    }
+
+    #[test]
+    fn test_parse_invalid_index_prefixes() {
+        for index_prefixes in vec![json!({"min_chars": 0}), json!({"max_chars": 21}), json!({"min_chars": 4, "max_chars": 3})] {
+            let mapping = parse_field(&json!({"type": "string", "index_prefixes": index_prefixes}));
+            assert_eq!(mapping, Err(FieldMappingParseError::InvalidIndexPrefixes));
+        }
+
+        let mapping = parse_field(&json!({"type": "string", "index": "not_analyzed", "index_prefixes": {}}));
+        assert_eq!(mapping, Err(FieldMappingParseError::IndexPrefixesOnlyAllowedOnAnalyzedFields));
+
+        let mapping = parse_field(&json!({"type": "string", "fields": {"prefixed": {"type": "string", "index_prefixes": {}}}}));
+        assert_eq!(mapping, Err(FieldMappingParseError::IndexPrefixesNotAllowedOnMultiFields));
+    }
+
+    #[test]
+    fn test_parse_dense_vector_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "dense_vector",
+                "dims": 3,
+                "similarity": "dot_product"
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::DenseVector,
+            is_indexed: false,
+            is_analyzed: false,
+            is_stored: true,
+            is_in_all: false,
+            dims: Some(3),
+            vector_similarity: VectorSimilarity::DotProduct,
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_geo_shape_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "geo_shape",
+                "store": false
+            }
+        ));
+
+        // Geo shapes are always stored, queries check the shape of each candidate
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::GeoShape,
+            is_indexed: true,
+            is_analyzed: false,
+            is_stored: true,
+            is_in_all: false,
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_geo_point_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "geo_point"
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::GeoPoint,
+            is_indexed: false,
+            is_analyzed: false,
+            is_stored: true,
+            is_in_all: false,
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_rank_feature_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "rank_feature"
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::RankFeature,
+            is_indexed: false,
+            is_analyzed: false,
+            is_stored: true,
+            is_in_all: false,
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_dense_vector_field_without_dims() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "dense_vector"
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::ExpectedKey("dims".to_string())));
+    }
+
+    #[test]
+    fn test_parse_dims_on_non_vector_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "integer",
+                "dims": 3
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::VectorSettingsOnlyAllowedOnDenseVectorType));
+    }
+
+    #[test]
+    fn test_parse_field_no_type() {
+        let mapping = parse_field(&json!({}));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::ExpectedKey("type".to_string())));
+    }
+
+    #[test]
+    fn test_parse_field_unrecognised_type() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "foo"
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::UnrecognisedFieldType("foo".to_string())));
+    }
+
+    #[test]
+    fn test_parse_field_type_not_string() {
+        let mapping = parse_field(&json!(
+            {
+                "type": 123
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::ExpectedString));
+    }
+
+    #[test]
+    fn test_parse_field_unrecognised_key() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "foo": "bar",
+                "baz": "quux"
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::UnrecognisedKeys(vec!["baz".to_string(), "foo".to_string()])));
     }
 
     #[test]
@@ -1000,4 +1848,146 @@ mod tests {
             ..FieldMappingBuilder::default()
         }));
     }
+
+    #[test]
+    fn test_parse_dynamic_settings() {
+        let mapping = parse(&json!(
+            {
+                "properties": {},
+                "dynamic": true,
+                "date_detection": false,
+                "numeric_detection": true,
+                "dynamic_date_formats": ["yyyy/MM/dd"]
+            }
+        ));
+
+        assert_eq!(mapping, Ok(MappingBuilder {
+            dynamic: true,
+            date_detection: false,
+            numeric_detection: true,
+            dynamic_date_formats: vec!["yyyy/MM/dd".to_string()],
+            ..MappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_invalid_dynamic_date_format() {
+        let mapping = parse(&json!(
+            {
+                "properties": {},
+                "dynamic_date_formats": ["yyyy-ww"]
+            }
+        ));
+
+        assert_eq!(mapping, Err(MappingParseError::InvalidDateFormat("yyyy-ww".to_string())));
+    }
+
+    #[test]
+    fn test_parse_date_format() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "date",
+                "format": "yyyy/MM/dd||strict_date_optional_time"
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::Date,
+            is_analyzed: false,
+            date_format: Some("yyyy/MM/dd||strict_date_optional_time".to_string()),
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_format_on_string_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "format": "yyyy/MM/dd"
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::FormatOnlyAllowedOnDateTypes));
+    }
+
+    #[test]
+    fn test_parse_collation() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "index": "not_analyzed",
+                "collation": {
+                    "locale": "sv",
+                    "strength": "primary"
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::String,
+            is_analyzed: false,
+            collation: Collation::new("sv", CollationStrength::Primary),
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_collation_on_analyzed_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "collation": {
+                    "locale": "de"
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::CollationOnlyAllowedOnKeywordFields));
+    }
+
+    #[test]
+    fn test_parse_collation_unrecognised_locale() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "index": "not_analyzed",
+                "collation": {
+                    "locale": "tlh"
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::UnrecognisedCollationLocale("tlh".to_string())));
+    }
+
+    #[test]
+    fn test_parse_normalizer() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "index": "not_analyzed",
+                "normalizer": "lowercase"
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::String,
+            is_analyzed: false,
+            normalizer: Some("lowercase".to_string()),
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_normalizer_on_analyzed_field() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "normalizer": "lowercase"
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::NormalizerOnlyAllowedOnKeywordFields));
+    }
 }
@@ -0,0 +1,261 @@
+//! Runtime fields defined in the "runtime_mappings" of a search request
+//!
+//! ```json
+//! "runtime_mappings": {
+//!     "price_with_tax": {
+//!         "type": "double",
+//!         "script": {"source": "emit(params._source.price * (1 + doc['tax_rate'].value))"}
+//!     }
+//! }
+//! ```
+//!
+//! A runtime field without a script takes the values of the field with the same name
+//! in the document's source.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_json::{Map, Number, Value as Json};
+use search::document::FieldValue;
+use search::runtime::{RuntimeField, RuntimeValues, RuntimeCondition};
+use search::schema::{self, Schema, FieldId, FieldFlags};
+use search::Query;
+
+use update::script::RuntimeScript;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuntimeFieldType {
+    Keyword,
+    Long,
+    Double,
+    Boolean,
+}
+
+
+impl RuntimeFieldType {
+    pub fn from_str(name: &str) -> Option<RuntimeFieldType> {
+        match name {
+            "keyword" => Some(RuntimeFieldType::Keyword),
+            "long" => Some(RuntimeFieldType::Long),
+            "double" => Some(RuntimeFieldType::Double),
+            "boolean" => Some(RuntimeFieldType::Boolean),
+            _ => None,
+        }
+    }
+
+    /// The type of the field in the schema that sorts and aggregations see
+    pub fn schema_type(&self) -> schema::FieldType {
+        match *self {
+            RuntimeFieldType::Keyword => schema::FieldType::PlainString,
+            RuntimeFieldType::Long => schema::FieldType::I64,
+            RuntimeFieldType::Double => schema::FieldType::F64,
+            RuntimeFieldType::Boolean => schema::FieldType::Boolean,
+        }
+    }
+
+    /// Converts a value into this type, returns None if it can't be
+    pub fn to_field_value(&self, value: &Json) -> Option<FieldValue> {
+        match (*self, value) {
+            (RuntimeFieldType::Keyword, &Json::String(ref string)) => Some(FieldValue::String(string.clone())),
+            (RuntimeFieldType::Keyword, &Json::Number(ref number)) => Some(FieldValue::String(number.to_string())),
+            (RuntimeFieldType::Keyword, &Json::Bool(boolean)) => Some(FieldValue::String(boolean.to_string())),
+            (RuntimeFieldType::Long, &Json::Number(ref number)) => {
+                // Fractions are truncated, like casting a double to a long
+                number.as_i64().or_else(|| number.as_f64().map(|number| number as i64)).map(FieldValue::Integer)
+            }
+            (RuntimeFieldType::Long, &Json::String(ref string)) => string.parse().ok().map(FieldValue::Integer),
+            (RuntimeFieldType::Double, &Json::Number(ref number)) => number.as_f64().map(FieldValue::Float),
+            (RuntimeFieldType::Double, &Json::String(ref string)) => string.parse().ok().map(FieldValue::Float),
+            (RuntimeFieldType::Boolean, &Json::Bool(boolean)) => Some(FieldValue::Boolean(boolean)),
+            (RuntimeFieldType::Boolean, &Json::String(ref string)) => string.parse().ok().map(FieldValue::Boolean),
+            _ => None,
+        }
+    }
+}
+
+
+/// Converts a doc value into the JSON that scripts see
+fn field_value_to_json(value: &FieldValue) -> Json {
+    match *value {
+        FieldValue::String(ref string) => Json::String(string.clone()),
+        FieldValue::Integer(value) => Json::Number(value.into()),
+        FieldValue::Boolean(value) => Json::Bool(value),
+        FieldValue::DateTime(ref value) => Json::String(value.to_rfc3339()),
+        FieldValue::Float(value) => Number::from_f64(value).map(Json::Number).unwrap_or(Json::Null),
+        FieldValue::GeoPoint(ref point) => json!({"lat": point.lat, "lon": point.lon}),
+        FieldValue::Vector(ref vector) => Json::Array(vector.iter().map(|value| json!(value)).collect()),
+        FieldValue::Bytes(_) => Json::Null,
+    }
+}
+
+
+/// Computes values with a script, or reads them from the source if there isn't one
+#[derive(Debug)]
+struct ScriptedValues {
+    name: String,
+    field_type: RuntimeFieldType,
+    script: Option<RuntimeScript>,
+}
+
+
+impl RuntimeValues for ScriptedValues {
+    fn doc_value_fields(&self) -> Vec<String> {
+        self.script.as_ref().map(|script| script.doc_fields()).unwrap_or_default()
+    }
+
+    fn compute(&self, source: &Map<String, Json>, doc_values: &HashMap<String, Vec<FieldValue>>) -> Result<Vec<FieldValue>, String> {
+        let values = match self.script {
+            Some(ref script) => {
+                let mut doc = Map::new();
+                for (name, values) in doc_values.iter() {
+                    doc.insert(name.clone(), Json::Array(values.iter().map(field_value_to_json).collect()));
+                }
+
+                script.execute(source, &doc).map_err(|e| format!("runtime field [{}] failed: {}", self.name, e.message()))?
+            }
+            None => {
+                match source.get(&self.name) {
+                    Some(&Json::Array(ref values)) => values.iter().filter(|value| !value.is_null()).cloned().collect(),
+                    Some(&Json::Null) | None => Vec::new(),
+                    Some(value) => vec![value.clone()],
+                }
+            }
+        };
+
+        values.iter().map(|value| {
+            self.field_type.to_field_value(value).ok_or_else(|| {
+                format!("runtime field [{}] can't hold the value {}", self.name, value)
+            })
+        }).collect()
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct RuntimeMapping {
+    pub field_type: RuntimeFieldType,
+    pub field: RuntimeField,
+}
+
+
+impl RuntimeMapping {
+    /// Builds a query that matches documents where the field has any of the values
+    ///
+    /// Values that the field can't hold are left out.
+    pub fn build_terms_query(&self, values: &[Json], boost: f32) -> Query {
+        let terms = values.iter().filter_map(|value| self.field_type.to_field_value(value)).collect();
+
+        Query::Runtime {
+            field: self.field.clone(),
+            condition: RuntimeCondition::Terms(terms),
+            score: boost,
+        }
+    }
+
+    /// Builds a query that matches documents where the field has a value within the bounds
+    ///
+    /// Bounds that the field can't hold don't match anything.
+    pub fn build_range_query(&self, gte: Option<&Json>, gt: Option<&Json>, lte: Option<&Json>, lt: Option<&Json>, boost: f32) -> Query {
+        let field_type = self.field_type;
+        let convert = |bound: Option<&Json>| {
+            match bound {
+                Some(bound) => field_type.to_field_value(bound).map(Some).ok_or(()),
+                None => Ok(None),
+            }
+        };
+
+        let condition = match (convert(gte), convert(gt), convert(lte), convert(lt)) {
+            (Ok(gte), Ok(gt), Ok(lte), Ok(lt)) => RuntimeCondition::Range { gte: gte, gt: gt, lte: lte, lt: lt },
+            _ => return Query::None,
+        };
+
+        Query::Runtime {
+            field: self.field.clone(),
+            condition: condition,
+            score: boost,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeMappings {
+    fields: HashMap<String, RuntimeMapping>,
+}
+
+
+impl RuntimeMappings {
+    /// Parses the "runtime_mappings" of a search request
+    pub fn parse(json: &Json) -> Result<RuntimeMappings, String> {
+        let object = json.as_object().ok_or_else(|| "runtime_mappings must be an object".to_string())?;
+
+        let mut fields = HashMap::new();
+        for (name, field_json) in object.iter() {
+            let field_object = field_json.as_object().ok_or_else(|| format!("runtime field [{}] must be an object", name))?;
+
+            let mut field_type = None;
+            let mut script = None;
+            for (key, value) in field_object.iter() {
+                match key.as_ref() {
+                    "type" => {
+                        let type_name = value.as_str().ok_or_else(|| format!("type of runtime field [{}] must be a string", name))?;
+                        field_type = Some(RuntimeFieldType::from_str(type_name).ok_or_else(|| {
+                            format!("runtime field [{}] has an unsupported type [{}]", name, type_name)
+                        })?);
+                    }
+                    "script" => {
+                        script = Some(RuntimeScript::parse(value).map_err(|e| {
+                            format!("failed to parse script of runtime field [{}]: {}", name, e.message())
+                        })?);
+                    }
+                    _ => return Err(format!("runtime field [{}] has an unrecognised key [{}]", name, key)),
+                }
+            }
+
+            let field_type = field_type.ok_or_else(|| format!("runtime field [{}] must have a type", name))?;
+            let values = ScriptedValues {
+                name: name.clone(),
+                field_type: field_type,
+                script: script,
+            };
+
+            fields.insert(name.clone(), RuntimeMapping {
+                field_type: field_type,
+                field: RuntimeField::new(name.clone(), Arc::new(values)),
+            });
+        }
+
+        Ok(RuntimeMappings {
+            fields: fields,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RuntimeMapping> {
+        self.fields.get(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Adds the runtime fields to a copy of the schema, so sorts and aggregations can find them
+    ///
+    /// Returns the schema and the runtime field of each FieldId. A runtime field with the
+    /// same name as a field in the index takes the place of its values.
+    pub fn extend_schema(&self, schema: &Schema) -> (Schema, HashMap<FieldId, RuntimeField>) {
+        let mut schema = schema.clone();
+        let mut runtime_fields = HashMap::new();
+
+        for (name, mapping) in self.fields.iter() {
+            let field_id = match schema.get_field_by_name(name) {
+                Some(field_id) => field_id,
+                None => schema.add_field(name.clone(), mapping.field_type.schema_type(), FieldFlags::empty()).unwrap(),
+            };
+
+            runtime_fields.insert(field_id, mapping.field.clone());
+        }
+
+        (schema, runtime_fields)
+    }
+}
@@ -0,0 +1,109 @@
+//! Parses "bucket_sort" pipeline aggregations
+//!
+//! ```json
+//! {"bucket_sort": {"sort": [{"sales": {"order": "desc"}}, "_key"], "from": 0, "size": 3}}
+//! ```
+//!
+//! Each item of "sort" is a buckets path, on its own to sort in ascending order or with
+//! an order.
+
+use serde_json::Value as Json;
+use search::collectors::sorted::SortOrder;
+use search::aggregations::pipeline::GapPolicy;
+use search::aggregations::bucket_sort::BucketSortAggregation;
+
+use query_parser::QueryParseError;
+use query_parser::aggregations::{PipelineAggregationBuilder, parse_gap_policy, parse_order};
+
+
+// "sales", {"sales": "desc"} or {"sales": {"order": "desc"}}
+fn parse_sort_item(json: &Json) -> Result<(String, SortOrder), QueryParseError> {
+    if let Some(path) = json.as_str() {
+        return Ok((path.to_string(), SortOrder::Asc));
+    }
+
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObjectOrString)?;
+    let path = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let value = object.get(path).unwrap();
+    let order = match *value {
+        Json::String(_) => parse_order(value)?,
+        Json::Object(ref inner_object) => {
+            let mut order = SortOrder::Asc;
+
+            for (key, value) in inner_object.iter() {
+                match key.as_ref() {
+                    "order" => order = parse_order(value)?,
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+
+            order
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString),
+    };
+
+    Ok((path.clone(), order))
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<PipelineAggregationBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut sort = Vec::new();
+    let mut from = 0;
+    let mut size = None;
+    let mut gap_policy = GapPolicy::Skip;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "sort" => {
+                // A single sort item doesn't need to be in a list
+                sort = match *value {
+                    Json::Array(ref items) => items.iter().map(parse_sort_item).collect::<Result<Vec<_>, _>>()?,
+                    _ => vec![parse_sort_item(value)?],
+                };
+            }
+            "from" => from = value.as_u64().ok_or(QueryParseError::InvalidValue)? as usize,
+            "size" => size = Some(value.as_u64().ok_or(QueryParseError::InvalidValue)? as usize),
+            "gap_policy" => gap_policy = parse_gap_policy(value)?,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(BucketSortAggregation {
+        sort: sort,
+        from: from,
+        size: size,
+        gap_policy: gap_policy,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::collectors::sorted::SortOrder;
+
+    use query_parser::QueryParseError;
+
+    use super::{parse, parse_sort_item};
+
+    #[test]
+    fn test_parse_sort_item() {
+        assert_eq!(parse_sort_item(&json!("_key")), Ok(("_key".to_string(), SortOrder::Asc)));
+        assert_eq!(parse_sort_item(&json!({"sales": "desc"})), Ok(("sales".to_string(), SortOrder::Desc)));
+        assert_eq!(parse_sort_item(&json!({"sales": {"order": "desc"}})), Ok(("sales".to_string(), SortOrder::Desc)));
+        assert_eq!(parse_sort_item(&json!({"sales": {"order": "up"}})), Err(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        let builder = parse(&json!({"size": -1}));
+
+        assert_eq!(builder.err(), Some(QueryParseError::InvalidValue));
+    }
+}
@@ -0,0 +1,290 @@
+//! Parses "composite" aggregations
+//!
+//! ```json
+//! {
+//!     "composite": {
+//!         "size": 100,
+//!         "sources": [
+//!             {"product": {"terms": {"field": "product"}}},
+//!             {"day": {"date_histogram": {"field": "timestamp", "interval": "1d", "order": "desc"}}},
+//!             {"price": {"histogram": {"field": "price", "interval": 10}}}
+//!         ],
+//!         "after": {"product": "apple", "day": 1510704000000, "price": 20.0}
+//!     }
+//! }
+//! ```
+
+use serde_json::Value as Json;
+use search::schema::Schema;
+use search::collectors::sorted::SortOrder;
+use search::aggregations::Aggregation;
+use search::aggregations::composite::{CompositeAggregation, CompositeSource, CompositeSourceType};
+
+use mapping::FieldType;
+
+use query_parser::{QueryBuildContext, QueryParseError};
+use query_parser::utils::parse_string;
+use query_parser::aggregations::{AggregationBuilder, AggregationsBuilder, find_field, parse_date_interval, parse_order};
+
+
+const DEFAULT_SIZE: usize = 10;
+
+
+#[derive(Debug)]
+struct CompositeSourceBuilder {
+    name: String,
+    field: String,
+    source_type: CompositeSourceType,
+    order: SortOrder,
+}
+
+
+#[derive(Debug)]
+struct CompositeAggregationBuilder {
+    sources: Vec<CompositeSourceBuilder>,
+    size: usize,
+    after: Option<Vec<Json>>,
+    sub_aggregations: AggregationsBuilder,
+}
+
+
+impl AggregationBuilder for CompositeAggregationBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Box<Aggregation> {
+        let sources = self.sources.iter().map(|source| {
+            // Histograms only work on numbers and dates, terms can be any type
+            let field = match source.source_type {
+                CompositeSourceType::Terms => schema.get_field_by_name(&source.field),
+                CompositeSourceType::Histogram(_) => find_field(context, schema, &source.field, FieldType::Integer),
+                CompositeSourceType::DateHistogram(_) => find_field(context, schema, &source.field, FieldType::Date),
+            };
+
+            CompositeSource {
+                name: source.name.clone(),
+                field: field,
+                source_type: source.source_type.clone(),
+                order: source.order,
+            }
+        }).collect();
+
+        Box::new(CompositeAggregation {
+            sources: sources,
+            size: self.size,
+            after: self.after.clone(),
+            sub_aggregations: self.sub_aggregations.build(context, schema),
+        })
+    }
+}
+
+
+// {"price": {"histogram": {"field": "price", "interval": 10, "order": "desc"}}}
+fn parse_source(json: &Json) -> Result<CompositeSourceBuilder, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let source_object = object.get(name).unwrap().as_object().ok_or(QueryParseError::ExpectedObject)?;
+    let source_type_name = if source_object.len() == 1 {
+        source_object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let inner_object = source_object.get(source_type_name).unwrap().as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut interval = None;
+    let mut order = SortOrder::Asc;
+
+    for (key, value) in inner_object.iter() {
+        match key.as_ref() {
+            "field" => field = Some(parse_string(value)?),
+            "interval" | "calendar_interval" | "fixed_interval" => interval = Some(value),
+            "order" => order = parse_order(value)?,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    let source_type = match (source_type_name.as_ref(), interval) {
+        ("terms", None) => CompositeSourceType::Terms,
+        ("histogram", Some(interval)) => {
+            match interval.as_f64() {
+                Some(interval) if interval > 0.0 => CompositeSourceType::Histogram(interval),
+                _ => return Err(QueryParseError::InvalidValue),
+            }
+        }
+        ("date_histogram", Some(interval)) => CompositeSourceType::DateHistogram(parse_date_interval(interval)?),
+        ("terms", Some(_)) => return Err(QueryParseError::UnrecognisedKey("interval".to_string())),
+        ("histogram", None) | ("date_histogram", None) => return Err(QueryParseError::ExpectedKey("interval")),
+        _ => return Err(QueryParseError::UnrecognisedKey(source_type_name.clone())),
+    };
+
+    Ok(CompositeSourceBuilder {
+        name: name.clone(),
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        source_type: source_type,
+        order: order,
+    })
+}
+
+
+pub fn parse(json: &Json, sub_aggregations: AggregationsBuilder) -> Result<Box<AggregationBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut sources = None;
+    let mut size = DEFAULT_SIZE;
+    let mut after_json = None;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "sources" => {
+                let array = value.as_array().ok_or(QueryParseError::ExpectedArray)?;
+                sources = Some(array.iter().map(parse_source).collect::<Result<Vec<_>, _>>()?);
+            }
+            "size" => size = value.as_u64().ok_or(QueryParseError::InvalidValue)? as usize,
+            "after" => after_json = Some(value.as_object().ok_or(QueryParseError::ExpectedObject)?),
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    let sources = sources.ok_or(QueryParseError::ExpectedKey("sources"))?;
+    if sources.is_empty() {
+        return Err(QueryParseError::InvalidValue);
+    }
+
+    // The after key must have a value for every source
+    let after = match after_json {
+        Some(after_json) => {
+            let values = sources.iter().map(|source| after_json.get(&source.name).cloned()).collect::<Option<Vec<_>>>();
+            Some(values.ok_or(QueryParseError::InvalidValue)?)
+        }
+        None => None,
+    };
+
+    Ok(Box::new(CompositeAggregationBuilder {
+        sources: sources,
+        size: size,
+        after: after,
+        sub_aggregations: sub_aggregations,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value as Json;
+    use search::document::{DocId, FieldValue};
+    use search::schema::{Schema, FieldType, FIELD_STORED};
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::AggregationsCollector;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::aggregations::parse as parse_aggregations;
+
+    fn run(json: Json) -> Json {
+        let mut schema = Schema::new();
+        let product_field = schema.add_field("product".to_string(), FieldType::PlainString, FIELD_STORED).unwrap();
+        schema.add_field("timestamp".to_string(), FieldType::DateTime, FIELD_STORED).unwrap();
+
+        let products = vec!["apple", "pear", "apple"];
+        let days = vec![15, 15, 16];
+
+        let aggregations = parse_aggregations(&json).unwrap().build(&QueryBuildContext::new(), &schema);
+        let mut collector = AggregationsCollector::new(&aggregations, |field, doc_id: DocId| {
+            if field == product_field {
+                vec![FieldValue::String(products[doc_id.1 as usize].to_string())]
+            } else {
+                vec![FieldValue::DateTime(Utc.ymd(2017, 11, days[doc_id.1 as usize]).and_hms(13, 0, 0))]
+            }
+        });
+        for doc_id in 0..products.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()
+    }
+
+    #[test]
+    fn test_composite_aggregation() {
+        let result = run(json!({
+            "rollup": {
+                "composite": {
+                    "size": 2,
+                    "sources": [
+                        {"day": {"date_histogram": {"field": "timestamp", "interval": "1d", "order": "desc"}}},
+                        {"product": {"terms": {"field": "product"}}}
+                    ]
+                }
+            }
+        }));
+
+        assert_eq!(result, json!({
+            "rollup": {
+                "after_key": {"day": 1510704000000i64, "product": "apple"},
+                "buckets": [
+                    {"key": {"day": 1510790400000i64, "product": "apple"}, "doc_count": 1},
+                    {"key": {"day": 1510704000000i64, "product": "apple"}, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_composite_after() {
+        let result = run(json!({
+            "rollup": {
+                "composite": {
+                    "sources": [
+                        {"product": {"terms": {"field": "product"}}}
+                    ],
+                    "after": {"product": "apple"}
+                }
+            }
+        }));
+
+        assert_eq!(result, json!({
+            "rollup": {
+                "after_key": {"product": "pear"},
+                "buckets": [
+                    {"key": {"product": "pear"}, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_after_without_every_source() {
+        let builder = parse_aggregations(&json!({
+            "rollup": {
+                "composite": {
+                    "sources": [
+                        {"product": {"terms": {"field": "product"}}},
+                        {"day": {"date_histogram": {"field": "timestamp", "interval": "1d"}}}
+                    ],
+                    "after": {"product": "apple"}
+                }
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_histogram_without_interval() {
+        let builder = parse_aggregations(&json!({
+            "rollup": {
+                "composite": {
+                    "sources": [
+                        {"price": {"histogram": {"field": "price"}}}
+                    ]
+                }
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedKey("interval")));
+    }
+}
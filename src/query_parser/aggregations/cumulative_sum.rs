@@ -0,0 +1,45 @@
+//! Parses "cumulative_sum" pipeline aggregations
+//!
+//! ```json
+//! {"cumulative_sum": {"buckets_path": "sales"}}
+//! ```
+
+use serde_json::Value as Json;
+use search::aggregations::cumulative_sum::CumulativeSumAggregation;
+
+use query_parser::QueryParseError;
+use query_parser::utils::parse_string;
+use query_parser::aggregations::PipelineAggregationBuilder;
+
+
+pub fn parse(json: &Json) -> Result<Box<PipelineAggregationBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut buckets_path = None;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "buckets_path" => buckets_path = Some(parse_string(value)?),
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(CumulativeSumAggregation {
+        buckets_path: buckets_path.ok_or(QueryParseError::ExpectedKey("buckets_path"))?,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use query_parser::QueryParseError;
+
+    use super::parse;
+
+    #[test]
+    fn test_missing_buckets_path() {
+        let builder = parse(&json!({}));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedKey("buckets_path")));
+    }
+}
@@ -0,0 +1,161 @@
+//! Parses "date_histogram" aggregations
+//!
+//! ```json
+//! {"date_histogram": {"field": "timestamp", "interval": "month", "min_doc_count": 1}}
+//! ```
+
+use serde_json::Value as Json;
+use search::schema::Schema;
+use search::aggregations::Aggregation;
+use search::aggregations::date_interval::DateInterval;
+use search::aggregations::date_histogram::DateHistogramAggregation;
+
+use mapping::FieldType;
+
+use query_parser::{QueryBuildContext, QueryParseError};
+use query_parser::utils::parse_string;
+use query_parser::aggregations::{AggregationBuilder, AggregationsBuilder, find_field, parse_date_interval};
+
+
+#[derive(Debug)]
+struct DateHistogramAggregationBuilder {
+    field: String,
+    interval: DateInterval,
+    min_doc_count: u64,
+    sub_aggregations: AggregationsBuilder,
+}
+
+
+impl AggregationBuilder for DateHistogramAggregationBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Box<Aggregation> {
+        Box::new(DateHistogramAggregation {
+            field: find_field(context, schema, &self.field, FieldType::Date),
+            interval: self.interval,
+            min_doc_count: self.min_doc_count,
+            sub_aggregations: self.sub_aggregations.build(context, schema),
+        })
+    }
+}
+
+
+pub fn parse(json: &Json, sub_aggregations: AggregationsBuilder) -> Result<Box<AggregationBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut interval = None;
+    let mut min_doc_count = 0;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => field = Some(parse_string(value)?),
+            "interval" | "calendar_interval" | "fixed_interval" => interval = Some(parse_date_interval(value)?),
+            "min_doc_count" => min_doc_count = value.as_u64().ok_or(QueryParseError::InvalidValue)?,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(DateHistogramAggregationBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        interval: interval.ok_or(QueryParseError::ExpectedKey("interval"))?,
+        min_doc_count: min_doc_count,
+        sub_aggregations: sub_aggregations,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value as Json;
+    use search::document::{DocId, FieldValue};
+    use search::schema::{Schema, FieldType, FIELD_STORED};
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::AggregationsCollector;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::aggregations::parse as parse_aggregations;
+
+    fn run(json: Json) -> Json {
+        let mut schema = Schema::new();
+        schema.add_field("timestamp".to_string(), FieldType::DateTime, FIELD_STORED).unwrap();
+
+        // Two documents on the first day, none on the second and one on the third
+        let docs = vec![
+            Utc.ymd(2017, 11, 15).and_hms(9, 0, 0),
+            Utc.ymd(2017, 11, 15).and_hms(17, 0, 0),
+            Utc.ymd(2017, 11, 17).and_hms(12, 0, 0),
+        ];
+
+        let aggregations = parse_aggregations(&json).unwrap().build(&QueryBuildContext::new(), &schema);
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| vec![FieldValue::DateTime(docs[doc_id.1 as usize])]);
+        for doc_id in 0..docs.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()
+    }
+
+    #[test]
+    fn test_date_histogram_aggregation() {
+        let result = run(json!({
+            "per_day": {
+                "date_histogram": {"field": "timestamp", "interval": "day", "min_doc_count": 1}
+            }
+        }));
+
+        assert_eq!(result, json!({
+            "per_day": {
+                "buckets": [
+                    {"key": 1510704000000i64, "key_as_string": "2017-11-15T00:00:00.000Z", "doc_count": 2},
+                    {"key": 1510876800000i64, "key_as_string": "2017-11-17T00:00:00.000Z", "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_pipeline_aggregations() {
+        let result = run(json!({
+            "per_day": {
+                "date_histogram": {"field": "timestamp", "fixed_interval": "1d"},
+                "aggs": {
+                    "change": {"derivative": {"buckets_path": "_count"}},
+                    "total": {"cumulative_sum": {"buckets_path": "_count"}},
+                    "busiest": {"bucket_sort": {"sort": [{"_count": {"order": "desc"}}], "size": 2}}
+                }
+            }
+        }));
+
+        // The buckets are sorted after the derivatives and totals are added
+        assert_eq!(result, json!({
+            "per_day": {
+                "buckets": [
+                    {
+                        "key": 1510704000000i64,
+                        "key_as_string": "2017-11-15T00:00:00.000Z",
+                        "doc_count": 2,
+                        "total": {"value": 2.0},
+                    },
+                    {
+                        "key": 1510876800000i64,
+                        "key_as_string": "2017-11-17T00:00:00.000Z",
+                        "doc_count": 1,
+                        "change": {"value": 1.0},
+                        "total": {"value": 3.0},
+                    },
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_missing_interval() {
+        let builder = parse_aggregations(&json!({
+            "per_day": {
+                "date_histogram": {"field": "timestamp"}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedKey("interval")));
+    }
+}
@@ -0,0 +1,56 @@
+//! Parses "derivative" pipeline aggregations
+//!
+//! ```json
+//! {"derivative": {"buckets_path": "sales", "gap_policy": "insert_zeros"}}
+//! ```
+
+use serde_json::Value as Json;
+use search::aggregations::pipeline::GapPolicy;
+use search::aggregations::derivative::DerivativeAggregation;
+
+use query_parser::QueryParseError;
+use query_parser::utils::parse_string;
+use query_parser::aggregations::{PipelineAggregationBuilder, parse_gap_policy};
+
+
+pub fn parse(json: &Json) -> Result<Box<PipelineAggregationBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut buckets_path = None;
+    let mut gap_policy = GapPolicy::Skip;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "buckets_path" => buckets_path = Some(parse_string(value)?),
+            "gap_policy" => gap_policy = parse_gap_policy(value)?,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(DerivativeAggregation {
+        buckets_path: buckets_path.ok_or(QueryParseError::ExpectedKey("buckets_path"))?,
+        gap_policy: gap_policy,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use query_parser::QueryParseError;
+
+    use super::parse;
+
+    #[test]
+    fn test_missing_buckets_path() {
+        let builder = parse(&json!({"gap_policy": "skip"}));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedKey("buckets_path")));
+    }
+
+    #[test]
+    fn test_invalid_gap_policy() {
+        let builder = parse(&json!({"buckets_path": "_count", "gap_policy": "interpolate"}));
+
+        assert_eq!(builder.err(), Some(QueryParseError::InvalidValue));
+    }
+}
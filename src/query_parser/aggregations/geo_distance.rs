@@ -0,0 +1,215 @@
+//! Parses "geo_distance" aggregations
+//!
+//! ```json
+//! {"geo_distance": {"field": "location", "origin": "52.37,4.89", "unit": "km", "ranges": [{"to": 100}, {"from": 100, "to": 300}, {"from": 300}]}}
+//! ```
+//!
+//! Distances are in meters unless another unit is given.
+
+use serde_json::Value as Json;
+use search::schema::Schema;
+use search::geo::{self, GeoPoint};
+use search::aggregations::Aggregation;
+use search::aggregations::geo_distance::{GeoDistanceAggregation, DistanceRange, DistanceType};
+
+use mapping::FieldType;
+
+use query_parser::{QueryBuildContext, QueryParseError};
+use query_parser::utils::parse_string;
+use query_parser::aggregations::{AggregationBuilder, AggregationsBuilder, find_field};
+
+
+#[derive(Debug)]
+struct GeoDistanceAggregationBuilder {
+    field: String,
+    origin: GeoPoint,
+    unit: f64,
+    distance_type: DistanceType,
+    ranges: Vec<DistanceRange>,
+    sub_aggregations: AggregationsBuilder,
+}
+
+
+impl AggregationBuilder for GeoDistanceAggregationBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Box<Aggregation> {
+        Box::new(GeoDistanceAggregation {
+            field: find_field(context, schema, &self.field, FieldType::GeoPoint),
+            origin: self.origin,
+            unit: self.unit,
+            distance_type: self.distance_type,
+            ranges: self.ranges.clone(),
+            sub_aggregations: self.sub_aggregations.build(context, schema),
+        })
+    }
+}
+
+
+fn parse_unit(json: &Json) -> Result<f64, QueryParseError> {
+    geo::distance_unit_in_meters(&parse_string(json)?).ok_or(QueryParseError::InvalidValue)
+}
+
+
+fn parse_distance_type(json: &Json) -> Result<DistanceType, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "arc" => Ok(DistanceType::Arc),
+        "plane" => Ok(DistanceType::Plane),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+// {"key": "near", "from": 100, "to": 300}
+fn parse_range(json: &Json) -> Result<DistanceRange, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut range = DistanceRange {
+        key: None,
+        from: None,
+        to: None,
+    };
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "key" => range.key = Some(parse_string(value)?),
+            "from" => range.from = Some(value.as_f64().ok_or(QueryParseError::ExpectedFloat)?),
+            "to" => range.to = Some(value.as_f64().ok_or(QueryParseError::ExpectedFloat)?),
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(range)
+}
+
+
+pub fn parse(json: &Json, sub_aggregations: AggregationsBuilder) -> Result<Box<AggregationBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut origin = None;
+    let mut unit = 1.0;
+    let mut distance_type = DistanceType::Arc;
+    let mut ranges = None;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => field = Some(parse_string(value)?),
+            "origin" => origin = Some(GeoPoint::from_json(value).map_err(|_| QueryParseError::InvalidValue)?),
+            "unit" => unit = parse_unit(value)?,
+            "distance_type" => distance_type = parse_distance_type(value)?,
+            "ranges" => {
+                let array = value.as_array().ok_or(QueryParseError::ExpectedArray)?;
+                ranges = Some(array.iter().map(parse_range).collect::<Result<Vec<_>, _>>()?);
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(GeoDistanceAggregationBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        origin: origin.ok_or(QueryParseError::ExpectedKey("origin"))?,
+        unit: unit,
+        distance_type: distance_type,
+        ranges: ranges.ok_or(QueryParseError::ExpectedKey("ranges"))?,
+        sub_aggregations: sub_aggregations,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+    use search::document::{DocId, FieldValue};
+    use search::schema::{Schema, FieldType, FIELD_STORED};
+    use search::geo::GeoPoint;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::AggregationsCollector;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::aggregations::parse as parse_aggregations;
+
+    fn run(json: Json, docs: Vec<Vec<FieldValue>>) -> Json {
+        let mut schema = Schema::new();
+        schema.add_field("location".to_string(), FieldType::GeoPoint, FIELD_STORED).unwrap();
+
+        let aggregations = parse_aggregations(&json).unwrap().build(&QueryBuildContext::new(), &schema);
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| docs[doc_id.1 as usize].clone());
+        for doc_id in 0..docs.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()
+    }
+
+    #[test]
+    fn test_geo_distance_aggregation() {
+        let result = run(json!({
+            "rings": {
+                "geo_distance": {
+                    "field": "location",
+                    "origin": {"lat": 52.3702, "lon": 4.8952},
+                    "unit": "km",
+                    "ranges": [{"to": 100}, {"key": "far", "from": 100}]
+                }
+            }
+        }), vec![
+            vec![FieldValue::GeoPoint(GeoPoint::new(5.1214, 52.0907))],
+            vec![FieldValue::GeoPoint(GeoPoint::new(13.4050, 52.5200))],
+            vec![],
+        ]);
+
+        assert_eq!(result, json!({
+            "rings": {
+                "buckets": [
+                    {"key": "*-100.0", "to": 100.0, "doc_count": 1},
+                    {"key": "far", "from": 100.0, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_default_unit_is_meters() {
+        let result = run(json!({
+            "rings": {
+                "geo_distance": {
+                    "field": "location",
+                    "origin": "52.3702,4.8952",
+                    "distance_type": "plane",
+                    "ranges": [{"to": 100000}]
+                }
+            }
+        }), vec![
+            vec![FieldValue::GeoPoint(GeoPoint::new(5.1214, 52.0907))],
+        ]);
+
+        assert_eq!(result, json!({
+            "rings": {
+                "buckets": [
+                    {"key": "*-100000.0", "to": 100000.0, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_invalid_unit() {
+        let builder = parse_aggregations(&json!({
+            "rings": {
+                "geo_distance": {"field": "location", "origin": "52.37,4.89", "unit": "parsecs", "ranges": [{"to": 100}]}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_missing_origin() {
+        let builder = parse_aggregations(&json!({
+            "rings": {
+                "geo_distance": {"field": "location", "ranges": [{"to": 100}]}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedKey("origin")));
+    }
+}
@@ -0,0 +1,156 @@
+//! Parses "geohash_grid" aggregations
+//!
+//! ```json
+//! {"geohash_grid": {"field": "location", "precision": 5, "size": 100}}
+//! ```
+
+use serde_json::Value as Json;
+use search::schema::Schema;
+use search::aggregations::Aggregation;
+use search::aggregations::geohash_grid::{GeohashGridAggregation, MAX_PRECISION};
+
+use mapping::FieldType;
+
+use query_parser::{QueryBuildContext, QueryParseError};
+use query_parser::utils::parse_string;
+use query_parser::aggregations::{AggregationBuilder, AggregationsBuilder, find_field};
+
+
+const DEFAULT_PRECISION: usize = 5;
+const DEFAULT_SIZE: usize = 10000;
+
+
+#[derive(Debug)]
+struct GeohashGridAggregationBuilder {
+    field: String,
+    precision: usize,
+    size: usize,
+    sub_aggregations: AggregationsBuilder,
+}
+
+
+impl AggregationBuilder for GeohashGridAggregationBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Box<Aggregation> {
+        Box::new(GeohashGridAggregation {
+            field: find_field(context, schema, &self.field, FieldType::GeoPoint),
+            precision: self.precision,
+            size: self.size,
+            sub_aggregations: self.sub_aggregations.build(context, schema),
+        })
+    }
+}
+
+
+fn parse_precision(json: &Json) -> Result<usize, QueryParseError> {
+    match json.as_u64() {
+        Some(precision) if precision >= 1 && precision <= MAX_PRECISION as u64 => Ok(precision as usize),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+pub fn parse(json: &Json, sub_aggregations: AggregationsBuilder) -> Result<Box<AggregationBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut precision = DEFAULT_PRECISION;
+    let mut size = DEFAULT_SIZE;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => field = Some(parse_string(value)?),
+            "precision" => precision = parse_precision(value)?,
+            "size" => size = value.as_u64().ok_or(QueryParseError::InvalidValue)? as usize,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(GeohashGridAggregationBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        precision: precision,
+        size: size,
+        sub_aggregations: sub_aggregations,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+    use search::document::{DocId, FieldValue};
+    use search::schema::{Schema, FieldType, FIELD_STORED};
+    use search::geo::GeoPoint;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::AggregationsCollector;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::aggregations::parse as parse_aggregations;
+
+    fn run(json: Json, docs: Vec<Vec<FieldValue>>) -> Json {
+        let mut schema = Schema::new();
+        schema.add_field("location".to_string(), FieldType::GeoPoint, FIELD_STORED).unwrap();
+
+        let aggregations = parse_aggregations(&json).unwrap().build(&QueryBuildContext::new(), &schema);
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| docs[doc_id.1 as usize].clone());
+        for doc_id in 0..docs.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()
+    }
+
+    #[test]
+    fn test_geohash_grid_aggregation() {
+        let result = run(json!({
+            "cells": {
+                "geohash_grid": {"field": "location", "precision": 3}
+            }
+        }), vec![
+            vec![FieldValue::GeoPoint(GeoPoint::new(4.8952, 52.3702))],
+            vec![FieldValue::GeoPoint(GeoPoint::new(5.1214, 52.0907))],
+        ]);
+
+        assert_eq!(result, json!({
+            "cells": {
+                "buckets": [
+                    {"key": "u17", "doc_count": 2},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        let result = run(json!({
+            "cells": {
+                "geohash_grid": {"field": "foo"}
+            }
+        }), vec![
+            vec![FieldValue::GeoPoint(GeoPoint::new(4.8952, 52.3702))],
+        ]);
+
+        assert_eq!(result, json!({"cells": {"buckets": []}}));
+    }
+
+    #[test]
+    fn test_invalid_precision() {
+        let builder = parse_aggregations(&json!({
+            "cells": {
+                "geohash_grid": {"field": "location", "precision": 13}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let builder = parse_aggregations(&json!({
+            "cells": {
+                "geohash_grid": {"precision": 3}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedKey("field")));
+    }
+}
@@ -0,0 +1,130 @@
+//! Parses "histogram" aggregations
+//!
+//! ```json
+//! {"histogram": {"field": "price", "interval": 50, "min_doc_count": 1}}
+//! ```
+
+use serde_json::Value as Json;
+use search::schema::Schema;
+use search::aggregations::Aggregation;
+use search::aggregations::histogram::HistogramAggregation;
+
+use query_parser::{QueryBuildContext, QueryParseError};
+use query_parser::utils::parse_string;
+use query_parser::aggregations::{AggregationBuilder, AggregationsBuilder};
+
+
+#[derive(Debug)]
+struct HistogramAggregationBuilder {
+    field: String,
+    interval: f64,
+    min_doc_count: u64,
+    sub_aggregations: AggregationsBuilder,
+}
+
+
+impl AggregationBuilder for HistogramAggregationBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Box<Aggregation> {
+        // Values that aren't numbers are skipped
+        Box::new(HistogramAggregation {
+            field: schema.get_field_by_name(&self.field),
+            interval: self.interval,
+            min_doc_count: self.min_doc_count,
+            sub_aggregations: self.sub_aggregations.build(context, schema),
+        })
+    }
+}
+
+
+pub fn parse(json: &Json, sub_aggregations: AggregationsBuilder) -> Result<Box<AggregationBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut interval = None;
+    let mut min_doc_count = 0;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => field = Some(parse_string(value)?),
+            "interval" => {
+                interval = match value.as_f64() {
+                    Some(interval) if interval > 0.0 => Some(interval),
+                    _ => return Err(QueryParseError::InvalidValue),
+                };
+            }
+            "min_doc_count" => min_doc_count = value.as_u64().ok_or(QueryParseError::InvalidValue)?,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(HistogramAggregationBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        interval: interval.ok_or(QueryParseError::ExpectedKey("interval"))?,
+        min_doc_count: min_doc_count,
+        sub_aggregations: sub_aggregations,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::document::{DocId, FieldValue};
+    use search::schema::{Schema, FieldType, FIELD_STORED};
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::AggregationsCollector;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::aggregations::parse as parse_aggregations;
+
+    #[test]
+    fn test_histogram_aggregation() {
+        let mut schema = Schema::new();
+        schema.add_field("price".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        let prices = vec![5, 12, 38];
+        let aggregations = parse_aggregations(&json!({
+            "prices": {
+                "histogram": {"field": "price", "interval": 10, "min_doc_count": 1}
+            }
+        })).unwrap().build(&QueryBuildContext::new(), &schema);
+
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| vec![FieldValue::Integer(prices[doc_id.1 as usize])]);
+        for doc_id in 0..prices.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        assert_eq!(collector.results(), json!({
+            "prices": {
+                "buckets": [
+                    {"key": 0.0, "doc_count": 1},
+                    {"key": 10.0, "doc_count": 1},
+                    {"key": 30.0, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_invalid_interval() {
+        for interval in vec![json!(0), json!(-5), json!("10")] {
+            let builder = parse_aggregations(&json!({
+                "prices": {
+                    "histogram": {"field": "price", "interval": interval}
+                }
+            }));
+
+            assert_eq!(builder.err(), Some(QueryParseError::InvalidValue));
+        }
+    }
+
+    #[test]
+    fn test_missing_interval() {
+        let builder = parse_aggregations(&json!({
+            "prices": {
+                "histogram": {"field": "price"}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedKey("interval")));
+    }
+}
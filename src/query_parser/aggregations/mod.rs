@@ -0,0 +1,369 @@
+//! Parses the "aggs" section of a search request
+//!
+//! ```json
+//! {
+//!     "aggs": {
+//!         "cells": {
+//!             "geohash_grid": {"field": "location", "precision": 3},
+//!             "aggs": {
+//!                 "rings": {"geo_distance": {"field": "location", "origin": "52.37,4.89", "ranges": [{"to": 1000}]}}
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! The section can also be called "aggregations".
+//!
+//! Pipeline aggregations (eg, "derivative") are given in the "aggs" of the bucket
+//! aggregation whose buckets they work on:
+//!
+//! ```json
+//! {
+//!     "aggs": {
+//!         "per_day": {
+//!             "date_histogram": {"field": "timestamp", "interval": "day"},
+//!             "aggs": {
+//!                 "change": {"derivative": {"buckets_path": "_count"}}
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+
+pub mod geohash_grid;
+pub mod geo_distance;
+pub mod composite;
+pub mod date_histogram;
+pub mod derivative;
+pub mod cumulative_sum;
+pub mod bucket_sort;
+pub mod sampler;
+pub mod terms;
+pub mod range;
+pub mod histogram;
+
+use std::fmt::Debug;
+
+use serde_json::Value as Json;
+use search::schema::{Schema, FieldId};
+use search::collectors::sorted::SortOrder;
+use search::aggregations::{Aggregation, Aggregations};
+use search::aggregations::date_interval::DateInterval;
+use search::aggregations::pipeline::{PipelineAggregation, GapPolicy};
+
+use mapping::FieldType;
+
+use query_parser::{QueryBuildContext, QueryParseError};
+use query_parser::utils::parse_string;
+
+
+pub trait AggregationBuilder: Debug {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Box<Aggregation>;
+}
+
+
+/// Pipeline aggregations don't depend on the index, so they're built by copying them
+pub trait PipelineAggregationBuilder: Debug {
+    fn build(&self) -> Box<PipelineAggregation>;
+}
+
+
+impl<P: PipelineAggregation + Clone + 'static> PipelineAggregationBuilder for P {
+    fn build(&self) -> Box<PipelineAggregation> {
+        Box::new(self.clone())
+    }
+}
+
+
+/// The builders of a list of named aggregations
+#[derive(Debug, Default)]
+pub struct AggregationsBuilder {
+    aggregations: Vec<(String, Box<AggregationBuilder>)>,
+    pipelines: Vec<(String, Box<PipelineAggregationBuilder>)>,
+}
+
+
+impl AggregationsBuilder {
+    pub fn is_empty(&self) -> bool {
+        self.aggregations.is_empty()
+    }
+
+    pub fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Aggregations {
+        let mut aggregations = Aggregations::new();
+
+        for &(ref name, ref builder) in self.aggregations.iter() {
+            aggregations.push(name.clone(), builder.build(context, schema));
+        }
+
+        for &(ref name, ref builder) in self.pipelines.iter() {
+            aggregations.push_pipeline(name.clone(), builder.build());
+        }
+
+        aggregations
+    }
+}
+
+
+/// Finds the field an aggregation reads values from
+///
+/// Returns None if the field isn't in the index or is mapped as a different type, the
+/// aggregation then has no buckets.
+fn find_field(context: &QueryBuildContext, schema: &Schema, name: &str, field_type: FieldType) -> Option<FieldId> {
+    if let Some(field_mapping) = context.get_field_mapping(name) {
+        if field_mapping.data_type != field_type {
+            return None;
+        }
+    }
+
+    schema.get_field_by_name(name)
+}
+
+
+/// Parses the interval of a date histogram
+///
+/// This is either a calendar unit ("month", "1M") or a fixed length of time ("12h").
+fn parse_date_interval(json: &Json) -> Result<DateInterval, QueryParseError> {
+    let string = parse_string(json)?;
+
+    match string.as_ref() {
+        "week" | "1w" => return Ok(DateInterval::Week),
+        "month" | "1M" => return Ok(DateInterval::Month),
+        "quarter" | "1q" => return Ok(DateInterval::Quarter),
+        "year" | "1y" => return Ok(DateInterval::Year),
+        "day" => return Ok(DateInterval::Fixed(86400000)),
+        "hour" => return Ok(DateInterval::Fixed(3600000)),
+        "minute" => return Ok(DateInterval::Fixed(60000)),
+        "second" => return Ok(DateInterval::Fixed(1000)),
+        _ => {}
+    }
+
+    let unit_start = string.find(|c: char| !c.is_digit(10)).unwrap_or(string.len());
+    let (amount, unit) = string.split_at(unit_start);
+    let amount = amount.parse::<i64>().map_err(|_| QueryParseError::InvalidValue)?;
+    let unit_millis = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60000,
+        "h" => 3600000,
+        "d" => 86400000,
+        _ => return Err(QueryParseError::InvalidValue),
+    };
+
+    if amount <= 0 {
+        return Err(QueryParseError::InvalidValue);
+    }
+
+    Ok(DateInterval::Fixed(amount * unit_millis))
+}
+
+
+/// Parses the order of a sort ("asc" or "desc")
+fn parse_order(json: &Json) -> Result<SortOrder, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "asc" => Ok(SortOrder::Asc),
+        "desc" => Ok(SortOrder::Desc),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+/// Parses the "gap_policy" of a pipeline aggregation
+fn parse_gap_policy(json: &Json) -> Result<GapPolicy, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "skip" => Ok(GapPolicy::Skip),
+        "insert_zeros" => Ok(GapPolicy::InsertZeros),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn get_aggregation_parser(aggregation_type: &str) -> Option<fn(&Json, AggregationsBuilder) -> Result<Box<AggregationBuilder>, QueryParseError>> {
+    match aggregation_type {
+        "geohash_grid" => Some(geohash_grid::parse),
+        "geo_distance" => Some(geo_distance::parse),
+        "composite" => Some(composite::parse),
+        "date_histogram" => Some(date_histogram::parse),
+        "sampler" => Some(sampler::parse),
+        "terms" => Some(terms::parse),
+        "range" => Some(range::parse),
+        "histogram" => Some(histogram::parse),
+        _ => None
+    }
+}
+
+
+fn get_pipeline_aggregation_parser(aggregation_type: &str) -> Option<fn(&Json) -> Result<Box<PipelineAggregationBuilder>, QueryParseError>> {
+    match aggregation_type {
+        "derivative" => Some(derivative::parse),
+        "cumulative_sum" => Some(cumulative_sum::parse),
+        "bucket_sort" => Some(bucket_sort::parse),
+        _ => None
+    }
+}
+
+
+/// Parses an aggregation, which is an object with its type and any sub-aggregations
+fn parse_aggregation(name: &str, json: &Json, builder: &mut AggregationsBuilder) -> Result<(), QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut aggregation = None;
+    let mut sub_aggregations = None;
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "aggs" | "aggregations" => sub_aggregations = Some((key, parse_aggregations(value)?)),
+            _ => {
+                if aggregation.is_some() {
+                    return Err(QueryParseError::ExpectedSingleKey);
+                }
+
+                aggregation = Some((key, value));
+            }
+        }
+    }
+
+    let (aggregation_type, aggregation_json) = aggregation.ok_or(QueryParseError::ExpectedSingleKey)?;
+
+    // Pipeline aggregations work on the buckets of their parent, so they can't have sub-aggregations
+    if let Some(parse) = get_pipeline_aggregation_parser(aggregation_type) {
+        if let Some((key, _)) = sub_aggregations {
+            return Err(QueryParseError::UnrecognisedKey(key.clone()));
+        }
+
+        builder.pipelines.push((name.to_string(), parse(aggregation_json)?));
+        return Ok(());
+    }
+
+    match get_aggregation_parser(aggregation_type) {
+        Some(parse) => {
+            let aggregation = parse(aggregation_json, sub_aggregations.map(|(_, sub_aggregations)| sub_aggregations).unwrap_or_default())?;
+            builder.aggregations.push((name.to_string(), aggregation));
+            Ok(())
+        }
+        None => Err(QueryParseError::UnrecognisedAggregationType(aggregation_type.clone())),
+    }
+}
+
+
+fn parse_aggregations(json: &Json) -> Result<AggregationsBuilder, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut builder = AggregationsBuilder::default();
+    for (name, aggregation_json) in object.iter() {
+        parse_aggregation(name, aggregation_json, &mut builder)?;
+    }
+
+    Ok(builder)
+}
+
+
+/// Parses the top-level aggregations of a search
+pub fn parse(json: &Json) -> Result<AggregationsBuilder, QueryParseError> {
+    let builder = parse_aggregations(json)?;
+
+    // There are no buckets at the top level for pipeline aggregations to work on
+    if let Some(&(ref name, _)) = builder.pipelines.first() {
+        return Err(QueryParseError::ExpectedParentAggregation(name.clone()));
+    }
+
+    Ok(builder)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::aggregations::date_interval::DateInterval;
+
+    use query_parser::QueryParseError;
+
+    use super::{parse, parse_date_interval};
+
+    #[test]
+    fn test_sub_aggregations() {
+        let builder = parse(&json!({
+            "cells": {
+                "geohash_grid": {"field": "location"},
+                "aggs": {
+                    "rings": {"geo_distance": {"field": "location", "origin": "52.37,4.89", "ranges": [{"to": 1000}]}}
+                }
+            }
+        })).unwrap();
+
+        assert!(!builder.is_empty());
+    }
+
+    #[test]
+    fn test_parse_date_interval() {
+        assert_eq!(parse_date_interval(&json!("1M")), Ok(DateInterval::Month));
+        assert_eq!(parse_date_interval(&json!("week")), Ok(DateInterval::Week));
+        assert_eq!(parse_date_interval(&json!("day")), Ok(DateInterval::Fixed(86400000)));
+        assert_eq!(parse_date_interval(&json!("12h")), Ok(DateInterval::Fixed(12 * 3600000)));
+        assert_eq!(parse_date_interval(&json!("90s")), Ok(DateInterval::Fixed(90000)));
+
+        assert_eq!(parse_date_interval(&json!("0d")), Err(QueryParseError::InvalidValue));
+        assert_eq!(parse_date_interval(&json!("2M")), Err(QueryParseError::InvalidValue));
+        assert_eq!(parse_date_interval(&json!("h")), Err(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_unrecognised_aggregation_type() {
+        let builder = parse(&json!({
+            "foo": {
+                "bar": {"field": "location"}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::UnrecognisedAggregationType("bar".to_string())));
+    }
+
+    #[test]
+    fn test_aggregation_with_two_types() {
+        let builder = parse(&json!({
+            "foo": {
+                "geohash_grid": {"field": "location"},
+                "geo_distance": {"field": "location", "origin": "52.37,4.89", "ranges": [{"to": 1000}]}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedSingleKey));
+    }
+
+    #[test]
+    fn test_aggregation_without_type() {
+        let builder = parse(&json!({
+            "foo": {
+                "aggs": {}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedSingleKey));
+    }
+
+    #[test]
+    fn test_pipeline_aggregation_without_parent() {
+        let builder = parse(&json!({
+            "change": {
+                "derivative": {"buckets_path": "_count"}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedParentAggregation("change".to_string())));
+    }
+
+    #[test]
+    fn test_pipeline_aggregation_with_sub_aggregations() {
+        let builder = parse(&json!({
+            "per_day": {
+                "date_histogram": {"field": "timestamp", "interval": "day"},
+                "aggs": {
+                    "change": {
+                        "derivative": {"buckets_path": "_count"},
+                        "aggs": {}
+                    }
+                }
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::UnrecognisedKey("aggs".to_string())));
+    }
+}
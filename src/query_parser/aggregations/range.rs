@@ -0,0 +1,143 @@
+//! Parses "range" aggregations
+//!
+//! ```json
+//! {"range": {"field": "price", "ranges": [{"to": 10}, {"from": 10, "to": 20}, {"key": "expensive", "from": 20}]}}
+//! ```
+
+use serde_json::Value as Json;
+use search::schema::Schema;
+use search::aggregations::Aggregation;
+use search::aggregations::range::{RangeAggregation, NumericRange};
+
+use query_parser::{QueryBuildContext, QueryParseError};
+use query_parser::utils::parse_string;
+use query_parser::aggregations::{AggregationBuilder, AggregationsBuilder};
+
+
+#[derive(Debug)]
+struct RangeAggregationBuilder {
+    field: String,
+    ranges: Vec<NumericRange>,
+    sub_aggregations: AggregationsBuilder,
+}
+
+
+impl AggregationBuilder for RangeAggregationBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Box<Aggregation> {
+        // Values that aren't numbers are skipped
+        Box::new(RangeAggregation {
+            field: schema.get_field_by_name(&self.field),
+            ranges: self.ranges.clone(),
+            sub_aggregations: self.sub_aggregations.build(context, schema),
+        })
+    }
+}
+
+
+// {"key": "cheap", "from": 10, "to": 20}
+fn parse_range(json: &Json) -> Result<NumericRange, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut range = NumericRange {
+        key: None,
+        from: None,
+        to: None,
+    };
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "key" => range.key = Some(parse_string(value)?),
+            "from" => range.from = Some(value.as_f64().ok_or(QueryParseError::ExpectedFloat)?),
+            "to" => range.to = Some(value.as_f64().ok_or(QueryParseError::ExpectedFloat)?),
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(range)
+}
+
+
+pub fn parse(json: &Json, sub_aggregations: AggregationsBuilder) -> Result<Box<AggregationBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut ranges = None;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => field = Some(parse_string(value)?),
+            "ranges" => {
+                let array = value.as_array().ok_or(QueryParseError::ExpectedArray)?;
+                ranges = Some(array.iter().map(parse_range).collect::<Result<Vec<_>, _>>()?);
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(RangeAggregationBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        ranges: ranges.ok_or(QueryParseError::ExpectedKey("ranges"))?,
+        sub_aggregations: sub_aggregations,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::document::{DocId, FieldValue};
+    use search::schema::{Schema, FieldType, FIELD_STORED};
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::AggregationsCollector;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::aggregations::parse as parse_aggregations;
+
+    #[test]
+    fn test_range_aggregation() {
+        let mut schema = Schema::new();
+        schema.add_field("price".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        let prices = vec![5, 15, 25];
+        let aggregations = parse_aggregations(&json!({
+            "prices": {
+                "range": {"field": "price", "ranges": [{"to": 10}, {"key": "expensive", "from": 10}]}
+            }
+        })).unwrap().build(&QueryBuildContext::new(), &schema);
+
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| vec![FieldValue::Integer(prices[doc_id.1 as usize])]);
+        for doc_id in 0..prices.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        assert_eq!(collector.results(), json!({
+            "prices": {
+                "buckets": [
+                    {"key": "*-10.0", "to": 10.0, "doc_count": 1},
+                    {"key": "expensive", "from": 10.0, "doc_count": 2},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_invalid_bound() {
+        let builder = parse_aggregations(&json!({
+            "prices": {
+                "range": {"field": "price", "ranges": [{"to": "ten"}]}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedFloat));
+    }
+
+    #[test]
+    fn test_missing_ranges() {
+        let builder = parse_aggregations(&json!({
+            "prices": {
+                "range": {"field": "price"}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedKey("ranges")));
+    }
+}
@@ -0,0 +1,117 @@
+//! Parses "sampler" aggregations
+//!
+//! ```json
+//! {"sampler": {"shard_size": 200}}
+//! ```
+//!
+//! The sub-aggregations only see the `shard_size` best scoring matches of each segment.
+
+use serde_json::Value as Json;
+use search::schema::Schema;
+use search::aggregations::Aggregation;
+use search::aggregations::sampler::SamplerAggregation;
+
+use query_parser::{QueryBuildContext, QueryParseError};
+use query_parser::aggregations::{AggregationBuilder, AggregationsBuilder};
+
+
+pub const DEFAULT_SHARD_SIZE: usize = 100;
+
+
+#[derive(Debug)]
+struct SamplerAggregationBuilder {
+    shard_size: usize,
+    sub_aggregations: AggregationsBuilder,
+}
+
+
+impl AggregationBuilder for SamplerAggregationBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Box<Aggregation> {
+        Box::new(SamplerAggregation {
+            shard_size: self.shard_size,
+            sub_aggregations: self.sub_aggregations.build(context, schema),
+        })
+    }
+}
+
+
+/// Parses the settings of a sample, these are shared with the "sample" option of a search
+///
+/// Returns the number of matches to keep from each segment.
+pub fn parse_sample(json: &Json) -> Result<usize, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut shard_size = DEFAULT_SHARD_SIZE;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "shard_size" => shard_size = value.as_u64().ok_or(QueryParseError::InvalidValue)? as usize,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    if shard_size == 0 {
+        return Err(QueryParseError::InvalidValue);
+    }
+
+    Ok(shard_size)
+}
+
+
+pub fn parse(json: &Json, sub_aggregations: AggregationsBuilder) -> Result<Box<AggregationBuilder>, QueryParseError> {
+    Ok(Box::new(SamplerAggregationBuilder {
+        shard_size: parse_sample(json)?,
+        sub_aggregations: sub_aggregations,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+    use search::document::{DocId, FieldValue};
+    use search::schema::{Schema, FieldType, FIELD_STORED};
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::AggregationsCollector;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::aggregations::parse as parse_aggregations;
+
+    use super::parse_sample;
+
+    #[test]
+    fn test_sampler_aggregation() {
+        let mut schema = Schema::new();
+        schema.add_field("tag".to_string(), FieldType::PlainString, FIELD_STORED).unwrap();
+
+        let aggregations = parse_aggregations(&json!({
+            "sample": {
+                "sampler": {"shard_size": 2},
+                "aggs": {
+                    "tags": {"composite": {"sources": [{"tag": {"terms": {"field": "tag"}}}]}}
+                }
+            }
+        })).unwrap().build(&QueryBuildContext::new(), &schema);
+
+        let tags = vec!["a", "b", "b"];
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| vec![FieldValue::String(tags[doc_id.1 as usize].to_string())]);
+        collector.collect(DocumentMatch::new_scored(0, 3.0));
+        collector.collect(DocumentMatch::new_scored(1, 1.0));
+        collector.collect(DocumentMatch::new_scored(2, 2.0));
+
+        let result: Json = collector.results();
+        assert_eq!(result["sample"]["doc_count"], json!(2));
+        assert_eq!(result["sample"]["tags"]["buckets"], json!([
+            {"key": {"tag": "a"}, "doc_count": 1},
+            {"key": {"tag": "b"}, "doc_count": 1},
+        ]));
+    }
+
+    #[test]
+    fn test_parse_sample() {
+        assert_eq!(parse_sample(&json!({})), Ok(100));
+        assert_eq!(parse_sample(&json!({"shard_size": 10})), Ok(10));
+        assert_eq!(parse_sample(&json!({"shard_size": 0})), Err(QueryParseError::InvalidValue));
+        assert_eq!(parse_sample(&json!({"size": 10})), Err(QueryParseError::UnrecognisedKey("size".to_string())));
+    }
+}
@@ -0,0 +1,156 @@
+//! Parses "terms" aggregations
+//!
+//! ```json
+//! {"terms": {"field": "tags", "size": 5, "min_doc_count": 2}}
+//! ```
+
+use serde_json::Value as Json;
+use search::schema::Schema;
+use search::aggregations::Aggregation;
+use search::aggregations::terms::TermsAggregation;
+
+use query_parser::{QueryBuildContext, QueryParseError};
+use query_parser::utils::parse_string;
+use query_parser::aggregations::{AggregationBuilder, AggregationsBuilder};
+
+
+const DEFAULT_SIZE: usize = 10;
+
+
+#[derive(Debug)]
+struct TermsAggregationBuilder {
+    field: String,
+    size: usize,
+    min_doc_count: u64,
+    sub_aggregations: AggregationsBuilder,
+}
+
+
+impl AggregationBuilder for TermsAggregationBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Box<Aggregation> {
+        // Fields of any type can be aggregated, values that can't be keys are skipped
+        Box::new(TermsAggregation {
+            field: schema.get_field_by_name(&self.field),
+            size: self.size,
+            min_doc_count: self.min_doc_count,
+            sub_aggregations: self.sub_aggregations.build(context, schema),
+        })
+    }
+}
+
+
+pub fn parse(json: &Json, sub_aggregations: AggregationsBuilder) -> Result<Box<AggregationBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut size = DEFAULT_SIZE;
+    let mut min_doc_count = 1;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => field = Some(parse_string(value)?),
+            "size" => size = value.as_u64().ok_or(QueryParseError::InvalidValue)? as usize,
+            "min_doc_count" => min_doc_count = value.as_u64().ok_or(QueryParseError::InvalidValue)?,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(TermsAggregationBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        size: size,
+        min_doc_count: min_doc_count,
+        sub_aggregations: sub_aggregations,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+    use search::document::{DocId, FieldValue};
+    use search::schema::{Schema, FieldType, FIELD_STORED};
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::AggregationsCollector;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::aggregations::parse as parse_aggregations;
+
+    fn run(json: Json) -> Json {
+        let mut schema = Schema::new();
+        schema.add_field("tags".to_string(), FieldType::Text, FIELD_STORED).unwrap();
+        schema.add_field("price".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        let tags = vec![vec!["rust", "search"], vec!["rust"], vec!["python"]];
+        let prices = vec![5, 15, 25];
+
+        let aggregations = parse_aggregations(&json).unwrap().build(&QueryBuildContext::new(), &schema);
+        let mut collector = AggregationsCollector::new(&aggregations, |field_id, doc_id: DocId| {
+            if field_id == schema.get_field_by_name("tags").unwrap() {
+                tags[doc_id.1 as usize].iter().map(|tag| FieldValue::String(tag.to_string())).collect()
+            } else {
+                vec![FieldValue::Integer(prices[doc_id.1 as usize])]
+            }
+        });
+        for doc_id in 0..tags.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()
+    }
+
+    #[test]
+    fn test_terms_aggregation() {
+        let result = run(json!({
+            "tags": {
+                "terms": {"field": "tags", "size": 2}
+            }
+        }));
+
+        assert_eq!(result, json!({
+            "tags": {
+                "doc_count_error_upper_bound": 0,
+                "sum_other_doc_count": 1,
+                "buckets": [
+                    {"key": "rust", "doc_count": 2},
+                    {"key": "python", "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_sub_aggregations() {
+        let result = run(json!({
+            "tags": {
+                "terms": {"field": "tags", "min_doc_count": 2},
+                "aggs": {
+                    "prices": {"range": {"field": "price", "ranges": [{"to": 10}, {"from": 10}]}}
+                }
+            }
+        }));
+
+        assert_eq!(result["tags"]["buckets"], json!([
+            {
+                "key": "rust",
+                "doc_count": 2,
+                "prices": {
+                    "buckets": [
+                        {"key": "*-10.0", "to": 10.0, "doc_count": 1},
+                        {"key": "10.0-*", "from": 10.0, "doc_count": 1},
+                    ]
+                }
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let builder = parse_aggregations(&json!({
+            "tags": {
+                "terms": {"size": 2}
+            }
+        }));
+
+        assert_eq!(builder.err(), Some(QueryParseError::ExpectedKey("field")));
+    }
+}
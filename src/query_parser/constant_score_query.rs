@@ -5,7 +5,7 @@ use search::Query;
 use search::schema::Schema;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
-use query_parser::utils::parse_float;
+use query_parser::utils::parse_boost;
 
 #[derive(Debug)]
 struct ConstantScoreQueryBuilder {
@@ -31,7 +31,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     };
 
     let boost = match object.get("boost") {
-        Some(inner) => parse_float(inner)?,
+        Some(inner) => parse_boost(inner)?,
         None => return Err(QueryParseError::ExpectedKey("boost")),
     };
 
@@ -0,0 +1,305 @@
+//! Parses "distance_feature" queries
+//!
+//! ```json
+//! {"distance_feature": {"field": "published", "origin": "now", "pivot": "7d"}}
+//! {"distance_feature": {"field": "location", "origin": {"lat": 52.37, "lon": 4.89}, "pivot": "1km"}}
+//! ```
+//!
+//! Every document with a value in the field matches, scored by how near its value is to
+//! the origin. Documents at the pivot distance score half of the boost.
+//!
+//! The origin can be a date (or date math expression), an integer or a geo point. The
+//! pivot is a duration ("12h", "7d") for dates and a distance ("500m", "2km") for geo
+//! points. A numeric pivot is in milliseconds for dates and meters for geo points.
+
+use serde_json::Value as Json;
+use chrono::{DateTime, Utc};
+use search::Query;
+use search::schema::Schema;
+use search::term::datetime_to_micros;
+use search::geo::{self, GeoPoint};
+use search::feature::{FeatureFunction, FeatureOrigin};
+
+use mapping::FieldType;
+use mapping::date_math::{self, Rounding};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_boost, parse_string};
+
+
+/// The kind of value given as the origin
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OriginKind {
+    Integer,
+    Date,
+    Geo,
+}
+
+
+#[derive(Debug)]
+struct DistanceFeatureQueryBuilder {
+    field: String,
+    origin: FeatureOrigin,
+    origin_kind: OriginKind,
+    pivot: f64,
+    boost: f32,
+
+    /// Set if the origin is relative to the current time
+    uses_now: bool,
+}
+
+
+impl QueryBuilder for DistanceFeatureQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        if self.uses_now {
+            context.mark_uses_now();
+        }
+
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // The origin must be the same kind of value as the field
+        if let Some(field_mapping) = context.get_field_mapping(&self.field) {
+            let is_compatible = match (&field_mapping.data_type, self.origin_kind) {
+                (&FieldType::Date, OriginKind::Date) => true,
+                (&FieldType::Date, OriginKind::Integer) => true,
+                (&FieldType::Integer, OriginKind::Integer) => true,
+                (&FieldType::GeoPoint, OriginKind::Geo) => true,
+                _ => false,
+            };
+
+            if !is_compatible {
+                return Query::None;
+            }
+        }
+
+        Query::FeatureScore {
+            field: field,
+            function: FeatureFunction::Distance {
+                origin: self.origin,
+                pivot: self.pivot,
+            },
+            boost: self.boost,
+        }
+    }
+}
+
+
+fn parse_origin(json: &Json, now: DateTime<Utc>) -> Result<(FeatureOrigin, OriginKind), QueryParseError> {
+    match *json {
+        Json::Number(ref number) => {
+            let number = number.as_i64().ok_or(QueryParseError::InvalidValue)?;
+            Ok((FeatureOrigin::Number(number), OriginKind::Integer))
+        }
+        Json::String(ref string) => {
+            if date_math::is_date_math(string) {
+                let date = date_math::evaluate(string, now, Rounding::Down).map_err(|_| QueryParseError::InvalidValue)?;
+                return Ok((FeatureOrigin::Number(datetime_to_micros(&date)), OriginKind::Date));
+            }
+
+            if let Ok(date) = string.parse::<DateTime<Utc>>() {
+                return Ok((FeatureOrigin::Number(datetime_to_micros(&date)), OriginKind::Date));
+            }
+
+            // A "lat,lon" string
+            let point = GeoPoint::from_json(json).map_err(|_| QueryParseError::InvalidValue)?;
+            Ok((FeatureOrigin::Geo(point), OriginKind::Geo))
+        }
+        _ => {
+            let point = GeoPoint::from_json(json).map_err(|_| QueryParseError::InvalidValue)?;
+            Ok((FeatureOrigin::Geo(point), OriginKind::Geo))
+        }
+    }
+}
+
+
+/// The number of microseconds in a unit of time
+fn time_unit_in_micros(unit: &str) -> Option<f64> {
+    match unit {
+        "ms" => Some(1000.0),
+        "s" => Some(1000000.0),
+        "m" => Some(60000000.0),
+        "h" => Some(3600000000.0),
+        "d" => Some(86400000000.0),
+        _ => None,
+    }
+}
+
+
+/// Parses the pivot, converting it into the units that the distance from the origin is measured in
+fn parse_pivot(json: &Json, origin_kind: OriginKind) -> Result<f64, QueryParseError> {
+    let pivot = match *json {
+        Json::Number(ref number) => {
+            let amount = number.as_f64().ok_or(QueryParseError::ExpectedFloat)?;
+
+            match origin_kind {
+                OriginKind::Date => amount * 1000.0,
+                OriginKind::Integer | OriginKind::Geo => amount,
+            }
+        }
+        Json::String(ref string) => {
+            let unit_start = string.find(|c: char| !c.is_digit(10) && c != '.').unwrap_or(string.len());
+            let (amount, unit) = string.split_at(unit_start);
+            let amount = amount.parse::<f64>().map_err(|_| QueryParseError::InvalidValue)?;
+
+            let unit = match origin_kind {
+                OriginKind::Date => time_unit_in_micros(unit),
+                OriginKind::Geo => geo::distance_unit_in_meters(unit),
+                OriginKind::Integer => None,
+            };
+
+            amount * unit.ok_or(QueryParseError::InvalidValue)?
+        }
+        _ => return Err(QueryParseError::InvalidValue),
+    };
+
+    if pivot > 0.0 && pivot.is_finite() {
+        Ok(pivot)
+    } else {
+        Err(QueryParseError::InvalidValue)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut field = None;
+    let mut boost = 1.0f32;
+    let now = Utc::now();
+
+    for (key, val) in object.iter() {
+        match key.as_ref() {
+            "field" => field = Some(parse_string(val)?),
+            "boost" => boost = parse_boost(val)?,
+            "origin" | "pivot" => {},
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    // The units of the pivot depend on the origin, so it's parsed afterwards
+    let origin_json = object.get("origin").ok_or(QueryParseError::ExpectedKey("origin"))?;
+    let (origin, origin_kind) = parse_origin(origin_json, now)?;
+    let pivot = parse_pivot(object.get("pivot").ok_or(QueryParseError::ExpectedKey("pivot"))?, origin_kind)?;
+
+    Ok(Box::new(DistanceFeatureQueryBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        origin: origin,
+        origin_kind: origin_kind,
+        pivot: pivot,
+        boost: boost,
+        uses_now: origin_json.as_str().map(date_math::uses_now).unwrap_or(false),
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use search::Query;
+    use search::schema::{Schema, FieldType, FIELD_STORED};
+    use search::term::datetime_to_micros;
+    use search::geo::GeoPoint;
+    use search::feature::{FeatureFunction, FeatureOrigin};
+
+    use index::metadata::IndexMetadata;
+    use mapping::parse::parse as parse_mapping;
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_date_distance_feature() {
+        let mut schema = Schema::new();
+        let published_field = schema.add_field("published".to_string(), FieldType::DateTime, FIELD_STORED).unwrap();
+
+        let query = parse(&json!({
+            "field": "published",
+            "origin": "2017-01-10T00:00:00Z",
+            "pivot": "7d",
+            "boost": 2.0
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::FeatureScore {
+            field: published_field,
+            function: FeatureFunction::Distance {
+                origin: FeatureOrigin::Number(datetime_to_micros(&Utc.ymd(2017, 1, 10).and_hms(0, 0, 0))),
+                pivot: 7.0 * 86400000000.0,
+            },
+            boost: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_geo_distance_feature() {
+        let mut schema = Schema::new();
+        let location_field = schema.add_field("location".to_string(), FieldType::GeoPoint, FIELD_STORED).unwrap();
+
+        let query = parse(&json!({
+            "field": "location",
+            "origin": "52.37,4.89",
+            "pivot": "1.5km"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::FeatureScore {
+            field: location_field,
+            function: FeatureFunction::Distance {
+                origin: FeatureOrigin::Geo(GeoPoint::new(4.89, 52.37)),
+                pivot: 1500.0,
+            },
+            boost: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_invalid_pivot() {
+        // Distances can't be used with dates
+        let query = parse(&json!({
+            "field": "published",
+            "origin": "now",
+            "pivot": "1km"
+        }));
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&json!({
+            "field": "published",
+            "origin": "now",
+            "pivot": 0
+        }));
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&json!({
+            "field": "published",
+            "origin": "now"
+        }));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("pivot")));
+    }
+
+    #[test]
+    fn test_incompatible_field() {
+        let mut schema = Schema::new();
+        schema.add_field("published".to_string(), FieldType::DateTime, FIELD_STORED).unwrap();
+
+        let mut index_metadata = IndexMetadata::default();
+        let mapping = parse_mapping(&json!({
+            "properties": {
+                "published": {
+                    "type": "date"
+                }
+            }
+        })).unwrap().build(&index_metadata);
+        index_metadata.mappings.insert("test".to_string(), mapping);
+
+        let query = parse(&json!({
+            "field": "published",
+            "origin": {"lat": 52.37, "lon": 4.89},
+            "pivot": "1km"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+}
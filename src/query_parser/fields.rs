@@ -0,0 +1,106 @@
+//! Parses the "fields" section of a search request
+//!
+//! This lists the fields to return with each hit. Their values are read from doc
+//! values rather than the source, so large documents don't need to be loaded:
+//!
+//! ```json
+//! "fields": ["title", {"field": "published", "format": "yyyy-MM-dd"}]
+//! ```
+
+use chrono::{TimeZone, Utc};
+use serde_json::Value as Json;
+
+use mapping::date_format::format_date;
+use query_parser::QueryParseError;
+use query_parser::utils::parse_string;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldRequest {
+    pub field: String,
+
+    /// How to format dates, the date format of the field's mapping is used if this isn't set
+    pub format: Option<String>,
+}
+
+
+fn parse_field_request(json: &Json) -> Result<FieldRequest, QueryParseError> {
+    if json.is_string() {
+        return Ok(FieldRequest {
+            field: parse_string(json)?,
+            format: None,
+        });
+    }
+
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObjectOrString)?;
+
+    let mut field = None;
+    let mut format = None;
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => field = Some(parse_string(value)?),
+            "format" => {
+                let value = parse_string(value)?;
+
+                // Check that the format can be used
+                if format_date(&Utc.timestamp(0, 0), &value).is_none() {
+                    return Err(QueryParseError::InvalidValue);
+                }
+
+                format = Some(value);
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone())),
+        }
+    }
+
+    Ok(FieldRequest {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        format: format,
+    })
+}
+
+
+pub fn parse(json: &Json) -> Result<Vec<FieldRequest>, QueryParseError> {
+    let array = json.as_array().ok_or(QueryParseError::ExpectedArray)?;
+
+    array.iter().map(parse_field_request).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use query_parser::QueryParseError;
+
+    use super::{parse, FieldRequest};
+
+    #[test]
+    fn test_fields() {
+        let fields = parse(&json!(["title", {"field": "published", "format": "yyyy-MM-dd"}]));
+
+        assert_eq!(fields, Ok(vec![
+            FieldRequest {
+                field: "title".to_string(),
+                format: None,
+            },
+            FieldRequest {
+                field: "published".to_string(),
+                format: Some("yyyy-MM-dd".to_string()),
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_invalid_format() {
+        assert_eq!(parse(&json!([{"field": "published", "format": "yyyy-ww"}])), Err(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_missing_field() {
+        assert_eq!(parse(&json!([{"format": "epoch_millis"}])), Err(QueryParseError::ExpectedKey("field")));
+    }
+
+    #[test]
+    fn test_expected_array() {
+        assert_eq!(parse(&json!("title")), Err(QueryParseError::ExpectedArray));
+    }
+}
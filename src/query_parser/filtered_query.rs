@@ -5,12 +5,14 @@ use search::Query;
 use search::schema::Schema;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::utils::parse_boost;
 
 
 #[derive(Debug)]
 struct FilteredQueryBuilder {
     query: Option<Box<QueryBuilder>>,
     filter: Box<QueryBuilder>,
+    boost: f32,
 }
 
 
@@ -21,10 +23,13 @@ impl QueryBuilder for FilteredQueryBuilder {
             None => Query::all(),
         };
 
-        Query::Filter {
+        let query = Query::Filter {
             query: Box::new(query),
             filter: Box::new(self.filter.build(&context.clone().no_score(), schema)),
-        }
+        };
+
+        // Add boost
+        query.boost(self.boost)
     }
 }
 
@@ -37,6 +42,8 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let mut filter = None;
     let mut has_filter_key = false;
 
+    let mut boost = 1.0f32;
+
     for (key, value) in object.iter() {
         match key.as_ref() {
             "query" => {
@@ -46,6 +53,9 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                 has_filter_key = true;
                 filter = Some(parse_query(value)?);
             }
+            "boost" => {
+                boost = parse_boost(value)?;
+            }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
     }
@@ -57,6 +67,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     Ok(Box::new(FilteredQueryBuilder {
         query: query,
         filter: filter.unwrap(),
+        boost: boost,
     }))
 }
 
@@ -231,4 +242,38 @@ mod tests {
 
         assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("foo".to_string())));
     }
+
+    #[test]
+    fn test_filtered_query_with_boost() {
+        let mut schema = Schema::new();
+        let the_field = schema.add_field("the".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": {
+                "term": {
+                    "the": "query"
+                }
+            },
+            "filter": {
+                "term": {
+                    "the": "filter"
+                }
+            },
+            "boost": 2.0
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // Only the query is boosted, the filter doesn't contribute to the score
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::Term {
+                field: the_field,
+                term: Term::from_string("query"),
+                scorer: TermScorer::default_with_boost(2.0f32),
+            }),
+            filter: Box::new(Query::Term {
+                field: the_field,
+                term: Term::from_string("filter"),
+                scorer: TermScorer::default(),
+            }),
+        }))
+    }
 }
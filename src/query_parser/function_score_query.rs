@@ -0,0 +1,221 @@
+//! Parses "function_score" queries
+//!
+//! Only the "random_score" function is supported. The inner query selects the documents
+//! and each is given a reproducible random score:
+//!
+//! ```json
+//! {"function_score": {"query": {"match_all": {}}, "random_score": {"seed": 10, "field": "user_id"}}}
+//! ```
+//!
+//! Without a field, the score is taken from the document's key.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::utils::{parse_boost, parse_string};
+
+
+#[derive(Debug)]
+struct RandomScoreQueryBuilder {
+    query: Option<Box<QueryBuilder>>,
+    seed: u64,
+    field: Option<String>,
+    boost: f32,
+}
+
+
+impl QueryBuilder for RandomScoreQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match self.field {
+            Some(ref field_name) => {
+                match schema.get_field_by_name(field_name) {
+                    Some(field) => Some(field),
+                    None => return Query::None,
+                }
+            }
+            None => None,
+        };
+
+        // The inner query only selects the documents to score
+        let query = match self.query {
+            Some(ref query) => query.build(&context.clone().no_score(), schema),
+            None => Query::all(),
+        };
+
+        Query::RandomScore {
+            seed: self.seed,
+            field: field,
+            query: Box::new(query),
+            boost: self.boost,
+        }
+    }
+}
+
+
+fn parse_seed(json: &Json) -> Result<u64, QueryParseError> {
+    match *json {
+        Json::Number(ref number) => {
+            number.as_u64().or_else(|| number.as_i64().map(|seed| seed as u64)).ok_or(QueryParseError::InvalidValue)
+        }
+        Json::String(ref string) => string.parse::<u64>().map_err(|_| QueryParseError::InvalidValue),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_random_score(json: &Json) -> Result<(Option<u64>, Option<String>), QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut seed = None;
+    let mut field = None;
+
+    for (key, val) in object.iter() {
+        match key.as_ref() {
+            "seed" => seed = Some(parse_seed(val)?),
+            "field" => field = Some(parse_string(val)?),
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok((seed, field))
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut query = None;
+    let mut random_score = None;
+    let mut boost = 1.0f32;
+
+    for (key, val) in object.iter() {
+        match key.as_ref() {
+            "query" => query = Some(parse_query(val)?),
+            "random_score" => random_score = Some(parse_random_score(val)?),
+            "boost" => boost = parse_boost(val)?,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    let (seed, field) = random_score.ok_or(QueryParseError::ExpectedKey("random_score"))?;
+
+    // Without a seed, each search gets a different order
+    let seed = match seed {
+        Some(seed) => seed,
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs() ^ duration.subsec_nanos() as u64).unwrap_or(0),
+    };
+
+    Ok(Box::new(RandomScoreQueryBuilder {
+        query: query,
+        seed: seed,
+        field: field,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_random_score() {
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let user_field = schema.add_field("user_id".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&json!({
+            "query": {
+                "term": {
+                    "title": "foo"
+                }
+            },
+            "random_score": {
+                "seed": 10,
+                "field": "user_id"
+            },
+            "boost": 2.0
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::RandomScore {
+            seed: 10,
+            field: Some(user_field),
+            query: Box::new(Query::Term {
+                field: title_field,
+                term: Term::from_string("foo"),
+                scorer: TermScorer::default(),
+            }),
+            boost: 2.0,
+        }));
+    }
+
+    #[test]
+    fn test_defaults() {
+        let query = parse(&json!({
+            "random_score": {
+                "seed": "42"
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &Schema::new())));
+
+        // Without a field, the document's key is used
+        assert_eq!(query, Ok(Query::RandomScore {
+            seed: 42,
+            field: None,
+            query: Box::new(Query::all()),
+            boost: 1.0,
+        }));
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let query = parse(&json!({
+            "random_score": {
+                "seed": 1,
+                "field": "user_id"
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &Schema::new())));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_missing_random_score() {
+        let query = parse(&json!({
+            "query": {
+                "match_all": {}
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("random_score")));
+    }
+
+    #[test]
+    fn test_unsupported_function() {
+        let query = parse(&json!({
+            "random_score": {},
+            "script_score": {}
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("script_score".to_string())));
+    }
+
+    #[test]
+    fn test_invalid_seed() {
+        let query = parse(&json!({
+            "random_score": {
+                "seed": "abc"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+}
@@ -0,0 +1,190 @@
+//! Parses "geo_shape" queries
+//!
+//! ```json
+//! {"geo_shape": {"location": {"shape": {"type": "envelope", "coordinates": [[13.0, 53.0], [14.0, 52.0]]}, "relation": "within"}}}
+//! ```
+//!
+//! The shape is covered with geohash cells to find the documents that might match,
+//! then the stored shape of each of these is checked exactly.
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+use search::geo::{self, GeoShape, GeoShapeRelation};
+
+use mapping::FieldType;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_boost, parse_string};
+
+
+#[derive(Debug)]
+struct GeoShapeQueryBuilder {
+    field: String,
+    shape: GeoShape,
+    relation: GeoShapeRelation,
+    boost: f32,
+}
+
+
+impl QueryBuilder for GeoShapeQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // Only geo shape fields are indexed with cells
+        if let Some(field_mapping) = context.get_field_mapping(&self.field) {
+            if field_mapping.data_type != FieldType::GeoShape {
+                return Query::None;
+            }
+        }
+
+        Query::GeoShape {
+            field: field,
+            terms: geo::query_terms(&self.shape),
+            shape: self.shape.clone(),
+            relation: self.relation,
+            score: self.boost,
+        }
+    }
+}
+
+
+fn parse_relation(json: &Json) -> Result<GeoShapeRelation, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "intersects" => Ok(GeoShapeRelation::Intersects),
+        "within" => Ok(GeoShapeRelation::Within),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let inner_object = object.get(field_name).unwrap().as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut shape = None;
+    let mut relation = GeoShapeRelation::Intersects;
+    let mut boost = 1.0f32;
+
+    for (key, val) in inner_object.iter() {
+        match key.as_ref() {
+            "shape" => shape = Some(GeoShape::from_geojson(val).map_err(|_| QueryParseError::InvalidValue)?),
+            "relation" => relation = parse_relation(val)?,
+            "boost" => boost = parse_boost(val)?,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(GeoShapeQueryBuilder {
+        field: field_name.clone(),
+        shape: shape.ok_or(QueryParseError::ExpectedKey("shape"))?,
+        relation: relation,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::Query;
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::geo::{self, GeoShape, GeoPoint, GeoShapeRelation};
+
+    use index::metadata::IndexMetadata;
+    use mapping::parse::parse as parse_mapping;
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_geo_shape_query() {
+        let mut schema = Schema::new();
+        let location_field = schema.add_field("location".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&json!({
+            "location": {
+                "shape": {"type": "point", "coordinates": [1.0, 2.0]},
+                "relation": "within",
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        let shape = GeoShape::Point(GeoPoint::new(1.0, 2.0));
+        assert_eq!(query, Ok(Query::GeoShape {
+            field: location_field,
+            terms: geo::query_terms(&shape),
+            shape: shape,
+            relation: GeoShapeRelation::Within,
+            score: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_non_geo_shape_field() {
+        let mut schema = Schema::new();
+        schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let mut index_metadata = IndexMetadata::default();
+        let mapping = parse_mapping(&json!({
+            "properties": {
+                "title": {
+                    "type": "string"
+                }
+            }
+        })).unwrap().build(&index_metadata);
+        index_metadata.mappings.insert("test".to_string(), mapping);
+
+        let query = parse(&json!({
+            "title": {
+                "shape": {"type": "point", "coordinates": [1.0, 2.0]}
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_invalid_shape() {
+        let query = parse(&json!({
+            "location": {
+                "shape": {"type": "point", "coordinates": [1.0]}
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_unsupported_relation() {
+        let query = parse(&json!({
+            "location": {
+                "shape": {"type": "point", "coordinates": [1.0, 2.0]},
+                "relation": "contains"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_missing_shape() {
+        let query = parse(&json!({
+            "location": {
+                "relation": "within"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("shape")));
+    }
+}
@@ -0,0 +1,200 @@
+//! Parses "has_parent" queries
+
+use serde_json::Value as Json;
+use search::{Query, JoinType};
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::utils::{parse_string, parse_boost};
+
+
+#[derive(Debug)]
+struct HasParentQueryBuilder {
+    parent_relation: String,
+    query: Box<QueryBuilder>,
+    boost: f32,
+}
+
+
+impl QueryBuilder for HasParentQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field_name = match context.index_metadata.and_then(|index_metadata| index_metadata.get_join_field()) {
+            Some((field_name, field_mapping)) => {
+                if !field_mapping.relations.contains_key(&self.parent_relation) {
+                    return Query::None;
+                }
+
+                field_name
+            }
+            None => return Query::None,
+        };
+
+        let field = match schema.get_field_by_name(field_name) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        let key_field = match schema.get_field_by_name("_id") {
+            Some(key_field) => key_field,
+            None => return Query::None,
+        };
+
+        Query::Join {
+            field: field,
+            key_field: key_field,
+            join_type: JoinType::HasParent {
+                parent_relation: self.parent_relation.clone(),
+            },
+            query: Box::new(self.query.build(&context.clone().no_score(), schema)),
+            score: self.boost,
+        }
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let parent_relation = match object.get("parent_type") {
+        Some(inner) => parse_string(inner)?,
+        None => return Err(QueryParseError::ExpectedKey("parent_type")),
+    };
+
+    let query = match object.get("query") {
+        Some(inner) => parse_query(inner)?,
+        None => return Err(QueryParseError::ExpectedKey("query")),
+    };
+
+    let boost = match object.get("boost") {
+        Some(inner) => parse_boost(inner)?,
+        None => 1.0f32,
+    };
+
+    // Check for any keys that we don't recognise
+    for key in object.keys() {
+        match key.as_ref() {
+            "parent_type" | "query" | "boost" => {},
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(HasParentQueryBuilder {
+        parent_relation: parent_relation,
+        query: query,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Query, JoinType};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+
+    use index::metadata::IndexMetadata;
+    use mapping::parse::parse as parse_mapping;
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    fn make_join_index_metadata() -> IndexMetadata {
+        let mut index_metadata = IndexMetadata::default();
+        let mapping = parse_mapping(&json!({
+            "properties": {
+                "join": {
+                    "type": "join",
+                    "relations": {
+                        "question": "answer"
+                    }
+                }
+            }
+        })).unwrap().build(&index_metadata);
+        index_metadata.mappings.insert("test".to_string(), mapping);
+        index_metadata
+    }
+
+    #[test]
+    fn test_has_parent_query() {
+        let mut schema = Schema::new();
+        let id_field = schema.add_field("_id".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+        let join_field = schema.add_field("join".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+        let index_metadata = make_join_index_metadata();
+
+        let query = parse(&json!({
+            "parent_type": "question",
+            "query": {
+                "match_all": {}
+            },
+            "boost": 2.0
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::Join {
+            field: join_field,
+            key_field: id_field,
+            join_type: JoinType::HasParent {
+                parent_relation: "question".to_string(),
+            },
+            query: Box::new(Query::all()),
+            score: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_has_parent_query_unknown_relation() {
+        let mut schema = Schema::new();
+        schema.add_field("_id".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+        schema.add_field("join".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+        let index_metadata = make_join_index_metadata();
+
+        // "answer" is a child relation, it has no children of its own
+        let query = parse(&json!({
+            "parent_type": "answer",
+            "query": {
+                "term": {
+                    "join": "foo"
+                }
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_has_parent_query_without_join_field() {
+        let mut schema = Schema::new();
+        schema.add_field("_id".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "parent_type": "question",
+            "query": {
+                "match_all": {}
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_missing_parent_type() {
+        let query = parse(&json!({
+            "query": {
+                "match_all": {}
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("parent_type")));
+    }
+
+    #[test]
+    fn test_extra_key() {
+        let query = parse(&json!({
+            "parent_type": "question",
+            "query": {
+                "match_all": {}
+            },
+            "foo": "bar"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("foo".to_string())));
+    }
+}
@@ -0,0 +1,242 @@
+//! Parses "ids" queries
+
+use serde_json::Value as Json;
+use search::{Term, Query};
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_boost};
+
+
+#[derive(Debug)]
+struct IdsQueryBuilder {
+    doc_types: Vec<String>,
+    ids: Vec<String>,
+    boost: f32,
+}
+
+
+impl QueryBuilder for IdsQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let id_field = match schema.get_field_by_name("_id") {
+            Some(id_field) => id_field,
+            None => return Query::None,
+        };
+
+        let mut filter = Query::Disjunction {
+            queries: self.ids.iter().map(|id| Query::term(id_field, Term::from_string(id))).collect(),
+        };
+
+        // Restrict to types
+        if !self.doc_types.is_empty() {
+            let type_field = match schema.get_field_by_name("_type") {
+                Some(type_field) => type_field,
+                None => return Query::None,
+            };
+
+            filter = Query::Conjunction {
+                queries: vec![
+                    filter,
+                    Query::Disjunction {
+                        queries: self.doc_types.iter().map(|doc_type| Query::term(type_field, Term::from_string(doc_type))).collect(),
+                    },
+                ],
+            };
+        }
+
+        let query = Query::Filter {
+            query: Box::new(Query::all()),
+            filter: Box::new(filter),
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+fn parse_string_or_array(json: &Json) -> Result<Vec<String>, QueryParseError> {
+    match *json {
+        Json::String(ref string) => Ok(vec![string.clone()]),
+        Json::Array(ref array) => {
+            let mut strings = Vec::with_capacity(array.len());
+
+            for item in array.iter() {
+                strings.push(parse_string(item)?);
+            }
+
+            Ok(strings)
+        }
+        _ => Err(QueryParseError::ExpectedString),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut doc_types = Vec::new();
+    let mut ids: Option<Vec<String>> = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match &key[..] {
+            "type" => {
+                doc_types = parse_string_or_array(value)?;
+            }
+            "values" => {
+                match *value {
+                    Json::Array(ref array) => {
+                        let mut values = Vec::with_capacity(array.len());
+
+                        for item in array.iter() {
+                            match *item {
+                                Json::String(ref string) => values.push(string.clone()),
+                                Json::Number(ref number) => values.push(number.to_string()),
+                                _ => return Err(QueryParseError::ExpectedString),
+                            }
+                        }
+
+                        ids = Some(values);
+                    }
+                    _ => return Err(QueryParseError::ExpectedArray),
+                }
+            }
+            "boost" => {
+                boost = parse_boost(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    match ids {
+        Some(ids) => {
+            Ok(Box::new(IdsQueryBuilder {
+                doc_types: doc_types,
+                ids: ids,
+                boost: boost,
+            }))
+        }
+        None => Err(QueryParseError::ExpectedKey("values"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Term, Query};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_ids_query() {
+        let mut schema = Schema::new();
+        let id_field = schema.add_field("_id".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"values\": [\"1\", 2]
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::all()),
+            filter: Box::new(Query::Disjunction {
+                queries: vec![
+                    Query::term(id_field, Term::from_string("1")),
+                    Query::term(id_field, Term::from_string("2")),
+                ],
+            }),
+        }));
+    }
+
+    #[test]
+    fn test_ids_query_with_type() {
+        let mut schema = Schema::new();
+        let id_field = schema.add_field("_id".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let type_field = schema.add_field("_type".to_string(), FieldType::Text, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"type\": \"foo\",
+            \"values\": [\"1\"]
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::all()),
+            filter: Box::new(Query::Conjunction {
+                queries: vec![
+                    Query::Disjunction {
+                        queries: vec![
+                            Query::term(id_field, Term::from_string("1")),
+                        ],
+                    },
+                    Query::Disjunction {
+                        queries: vec![
+                            Query::term(type_field, Term::from_string("foo")),
+                        ],
+                    },
+                ],
+            }),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_values() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"type\": \"foo\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("values")));
+    }
+
+    #[test]
+    fn test_gives_error_for_incorrect_values_type() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"values\": \"1\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+    }
+
+    #[test]
+    fn test_ids_query_with_boost() {
+        let mut schema = Schema::new();
+        let id_field = schema.add_field("_id".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "values": ["1"],
+            "boost": 2.0
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::All { score: 2.0f32 }),
+            filter: Box::new(Query::Disjunction {
+                queries: vec![
+                    Query::term(id_field, Term::from_string("1")),
+                ],
+            }),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_boost() {
+        let query = parse(&json!({
+            "values": ["1"],
+            "boost": -1.0
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidBoost));
+    }
+}
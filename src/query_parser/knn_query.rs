@@ -0,0 +1,335 @@
+//! Parses "knn" queries and the "knn" section of search requests
+//!
+//! Every candidate document is scored by comparing its vector with the query vector,
+//! this is fine for small indices but an approximate index will be needed for larger ones.
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+use search::similarity::VectorSimilarity;
+
+use mapping::FieldType;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::utils::{parse_string, parse_float, parse_boost};
+
+
+/// The number of nearest neighbours returned by the "knn" section if "k" isn't set
+const DEFAULT_K: usize = 10;
+
+
+#[derive(Debug)]
+struct KnnQueryBuilder {
+    field: String,
+    query_vector: Vec<f32>,
+    similarity: Option<VectorSimilarity>,
+
+    /// Only documents that match all of these are scored
+    filters: Vec<Box<QueryBuilder>>,
+    boost: f32,
+}
+
+
+impl QueryBuilder for KnnQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // Check the query vector can be compared with the vectors in the field
+        let field_mapping = context.get_field_mapping(&self.field);
+        if let Some(field_mapping) = field_mapping {
+            if field_mapping.data_type != FieldType::DenseVector {
+                return Query::None;
+            }
+
+            if let Some(dims) = field_mapping.dims {
+                if dims != self.query_vector.len() {
+                    return Query::None;
+                }
+            }
+        }
+
+        let similarity = match self.similarity {
+            Some(similarity) => similarity,
+            None => field_mapping.map(|field_mapping| field_mapping.vector_similarity).unwrap_or_default(),
+        };
+
+        let filter_context = context.clone().no_score();
+        let query = match self.filters.len() {
+            0 => Query::all(),
+            1 => self.filters[0].build(&filter_context, schema),
+            _ => {
+                Query::Conjunction {
+                    queries: self.filters.iter().map(|filter| filter.build(&filter_context, schema)).collect(),
+                }
+            }
+        };
+
+        Query::VectorScore {
+            field: field,
+            vector: self.query_vector.clone(),
+            similarity: similarity,
+            query: Box::new(query),
+            boost: self.boost,
+        }
+    }
+}
+
+
+fn parse_query_vector(json: &Json) -> Result<Vec<f32>, QueryParseError> {
+    let array = json.as_array().ok_or(QueryParseError::ExpectedArray)?;
+    let mut vector = Vec::with_capacity(array.len());
+
+    for item in array.iter() {
+        vector.push(parse_float(item)?);
+    }
+
+    if vector.is_empty() {
+        return Err(QueryParseError::InvalidValue);
+    }
+
+    Ok(vector)
+}
+
+
+fn parse_similarity(json: &Json) -> Result<VectorSimilarity, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "cosine" => Ok(VectorSimilarity::Cosine),
+        "dot_product" => Ok(VectorSimilarity::DotProduct),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+/// Parses a single filter query or an array of them
+fn parse_filters(json: &Json) -> Result<Vec<Box<QueryBuilder>>, QueryParseError> {
+    match *json {
+        Json::Array(ref array) => {
+            let mut filters = Vec::with_capacity(array.len());
+
+            for item in array.iter() {
+                filters.push(parse_query(item)?);
+            }
+
+            Ok(filters)
+        }
+        _ => Ok(vec![parse_query(json)?]),
+    }
+}
+
+
+/// Parses the keys shared by the query and the search section, "k" is only allowed in the search section
+fn parse_knn(json: &Json, allow_k: bool) -> Result<(KnnQueryBuilder, usize), QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field = match object.get("field") {
+        Some(inner) => parse_string(inner)?,
+        None => return Err(QueryParseError::ExpectedKey("field")),
+    };
+
+    let query_vector = match object.get("query_vector") {
+        Some(inner) => parse_query_vector(inner)?,
+        None => return Err(QueryParseError::ExpectedKey("query_vector")),
+    };
+
+    let mut similarity = None;
+    let mut filters = Vec::new();
+    let mut boost = 1.0f32;
+    let mut k = DEFAULT_K;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" | "query_vector" => {},
+            "similarity" => similarity = Some(parse_similarity(value)?),
+            "filter" => filters = parse_filters(value)?,
+            "boost" => boost = parse_boost(value)?,
+            "k" if allow_k => {
+                k = match value.as_u64() {
+                    Some(k) if k > 0 => k as usize,
+                    _ => return Err(QueryParseError::InvalidValue),
+                };
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok((KnnQueryBuilder {
+        field: field,
+        query_vector: query_vector,
+        similarity: similarity,
+        filters: filters,
+        boost: boost,
+    }, k))
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let (builder, _) = parse_knn(json, false)?;
+    Ok(Box::new(builder))
+}
+
+
+/// Parses the "knn" section of a search request, returning the query and the number of results
+///
+/// The query of the search request (if there is one) selects which documents are scored.
+pub fn parse_search_section(json: &Json, query: Option<Box<QueryBuilder>>) -> Result<(Box<QueryBuilder>, usize), QueryParseError> {
+    let (mut builder, k) = parse_knn(json, true)?;
+
+    if let Some(query) = query {
+        builder.filters.push(query);
+    }
+
+    Ok((Box::new(builder), k))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::similarity::VectorSimilarity;
+
+    use index::metadata::IndexMetadata;
+    use mapping::parse::parse as parse_mapping;
+    use query_parser::{QueryBuildContext, QueryParseError, parse as parse_query};
+
+    use super::{parse, parse_search_section};
+
+    fn make_vector_index_metadata() -> IndexMetadata {
+        let mut index_metadata = IndexMetadata::default();
+        let mapping = parse_mapping(&json!({
+            "properties": {
+                "embedding": {
+                    "type": "dense_vector",
+                    "dims": 2,
+                    "similarity": "dot_product"
+                }
+            }
+        })).unwrap().build(&index_metadata);
+        index_metadata.mappings.insert("test".to_string(), mapping);
+        index_metadata
+    }
+
+    #[test]
+    fn test_knn_query() {
+        let mut schema = Schema::new();
+        let embedding_field = schema.add_field("embedding".to_string(), FieldType::DenseVector, FIELD_STORED).unwrap();
+        let index_metadata = make_vector_index_metadata();
+
+        let query = parse(&json!({
+            "field": "embedding",
+            "query_vector": [0.5, 1],
+            "boost": 2.0
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        // The similarity comes from the mapping
+        assert_eq!(query, Ok(Query::VectorScore {
+            field: embedding_field,
+            vector: vec![0.5, 1.0],
+            similarity: VectorSimilarity::DotProduct,
+            query: Box::new(Query::all()),
+            boost: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_knn_query_with_filter() {
+        let mut schema = Schema::new();
+        let embedding_field = schema.add_field("embedding".to_string(), FieldType::DenseVector, FIELD_STORED).unwrap();
+        let tag_field = schema.add_field("tag".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "field": "embedding",
+            "query_vector": [0.5, 1],
+            "similarity": "cosine",
+            "filter": {
+                "term": {
+                    "tag": "foo"
+                }
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::VectorScore {
+            field: embedding_field,
+            vector: vec![0.5, 1.0],
+            similarity: VectorSimilarity::Cosine,
+            query: Box::new(Query::term(tag_field, Term::from_string("foo"))),
+            boost: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_knn_query_wrong_dims() {
+        let mut schema = Schema::new();
+        schema.add_field("embedding".to_string(), FieldType::DenseVector, FIELD_STORED).unwrap();
+        let index_metadata = make_vector_index_metadata();
+
+        let query = parse(&json!({
+            "field": "embedding",
+            "query_vector": [0.5, 1, 2]
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_knn_search_section() {
+        let mut schema = Schema::new();
+        let embedding_field = schema.add_field("embedding".to_string(), FieldType::DenseVector, FIELD_STORED).unwrap();
+        let tag_field = schema.add_field("tag".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+        let index_metadata = make_vector_index_metadata();
+
+        let search_query = parse_query(&json!({
+            "term": {
+                "tag": "foo"
+            }
+        })).unwrap();
+
+        let (builder, k) = parse_search_section(&json!({
+            "field": "embedding",
+            "query_vector": [0.5, 1],
+            "k": 5
+        }), Some(search_query)).unwrap();
+
+        assert_eq!(k, 5);
+        assert_eq!(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema), Query::VectorScore {
+            field: embedding_field,
+            vector: vec![0.5, 1.0],
+            similarity: VectorSimilarity::DotProduct,
+            query: Box::new(Query::term(tag_field, Term::from_string("foo"))),
+            boost: 1.0f32,
+        });
+    }
+
+    #[test]
+    fn test_k_not_allowed_in_query() {
+        let query = parse(&json!({
+            "field": "embedding",
+            "query_vector": [0.5, 1],
+            "k": 5
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("k".to_string())));
+    }
+
+    #[test]
+    fn test_missing_query_vector() {
+        let query = parse(&json!({
+            "field": "embedding"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("query_vector")));
+    }
+
+    #[test]
+    fn test_invalid_query_vector() {
+        let query = parse(&json!({
+            "field": "embedding",
+            "query_vector": [0.5, "foo"]
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+    }
+}
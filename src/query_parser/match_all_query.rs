@@ -5,7 +5,7 @@ use search::Query;
 use search::schema::Schema;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::parse_float;
+use query_parser::utils::parse_boost;
 
 
 #[derive(Debug)]
@@ -30,7 +30,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     for (key, value) in object.iter() {
         match &key[..] {
             "boost" => {
-                boost = parse_float(value)?;
+                boost = parse_boost(value)?;
             }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
@@ -159,4 +159,13 @@ mod tests {
 
         assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
     }
+
+    #[test]
+    fn test_gives_error_for_negative_boost() {
+        let query = parse(&json!({
+            "boost": -2.0
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidBoost));
+    }
 }
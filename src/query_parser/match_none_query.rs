@@ -5,6 +5,7 @@ use search::Query;
 use search::schema::Schema;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::parse_boost;
 
 
 #[derive(Debug)]
@@ -22,8 +23,12 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
 
     // Get configuration
-    for (key, _value) in object.iter() {
+    // Nothing is matched so there are no scores to boost, but the boost is still validated
+    for (key, value) in object.iter() {
         match &key[..] {
+            "boost" => {
+                parse_boost(value)?;
+            }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
     }
@@ -91,4 +96,24 @@ mod tests {
 
         assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
     }
+
+    #[test]
+    fn test_match_none_query_with_boost() {
+        let schema = Schema::new();
+
+        let query = parse(&json!({
+            "boost": 2.0
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_gives_error_for_incorrect_boost_type() {
+        let query = parse(&json!({
+            "boost": "2"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+    }
 }
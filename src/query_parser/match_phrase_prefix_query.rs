@@ -0,0 +1,246 @@
+//! Parses "match_phrase_prefix" queries
+
+use serde_json::Value as Json;
+use search::{Term, Token, Query, TermScorer};
+use search::schema::Schema;
+
+use mapping::FieldSearchOptions;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_boost};
+
+
+/// The most terms that the final term is expanded into, unless the query says otherwise
+const DEFAULT_MAX_EXPANSIONS: usize = 50;
+
+
+#[derive(Debug)]
+struct MatchPhrasePrefixQueryBuilder {
+    field: String,
+    query: String,
+    max_expansions: usize,
+    boost: f32,
+}
+
+
+impl QueryBuilder for MatchPhrasePrefixQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // Get search options for field
+        let field_search_options = match context.get_field_mapping(&self.field) {
+            Some(field_mapping) => field_mapping.get_search_options(),
+            None => FieldSearchOptions::default(),
+        };
+
+        // Tokenise query string
+        let mut tokens = match field_search_options.analyzer {
+            Some(ref analyzer) => {
+                let token_stream = analyzer.initialise(&self.query);
+                token_stream.collect::<Vec<Token>>()
+            }
+            None => {
+                let term = match field_search_options.normalizer {
+                    Some(ref normalizer) => Term::from_string(&normalizer.normalize(&self.query)),
+                    None => Term::from_string(&self.query),
+                };
+
+                vec![Token {term: term, position: 1}]
+            }
+        };
+
+        // The final token is still being typed, so it's matched as a prefix
+        let prefix = match tokens.pop() {
+            Some(token) => String::from_utf8_lossy(token.term.as_bytes()).into_owned(),
+            None => return Query::None,
+        };
+
+        let query = Query::Phrase {
+            field: field,
+            terms: tokens.into_iter().map(|token| token.term).collect(),
+            prefix: Some(prefix),
+            max_expansions: self.max_expansions,
+            scorer: TermScorer::default(),
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    // Get configuration
+    let mut query = String::new();
+    let mut max_expansions = DEFAULT_MAX_EXPANSIONS;
+    let mut boost = 1.0f32;
+
+    match object.get(field_name).unwrap() {
+        s @ &Json::String(_) => query = parse_string(s)?,
+        &Json::Object(ref inner_object) => {
+            let mut has_query_key = false;
+
+            for (key, value) in inner_object.iter() {
+                match key.as_ref() {
+                    "query" => {
+                        has_query_key = true;
+                        query = parse_string(value)?;
+                    }
+                    "max_expansions" => {
+                        max_expansions = match value.as_u64() {
+                            Some(max_expansions) if max_expansions > 0 => max_expansions as usize,
+                            _ => return Err(QueryParseError::InvalidValue),
+                        };
+                    }
+                    "boost" => {
+                        boost = parse_boost(value)?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+
+            if !has_query_key {
+                return Err(QueryParseError::ExpectedKey("query"))
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString),
+    }
+
+    Ok(Box::new(MatchPhrasePrefixQueryBuilder {
+        field: field_name.clone(),
+        query: query,
+        max_expansions: max_expansions,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_match_phrase_prefix_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "foo": {
+                "query": "Quick brown f",
+                "max_expansions": 10
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Phrase {
+            field: foo_field,
+            terms: vec![Term::from_string("quick"), Term::from_string("brown")],
+            prefix: Some("f".to_string()),
+            max_expansions: 10,
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_simple_match_phrase_prefix_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "foo": "hel"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Phrase {
+            field: foo_field,
+            terms: vec![],
+            prefix: Some("hel".to_string()),
+            max_expansions: 50,
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_with_boost() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "foo": {
+                "query": "hello wor",
+                "boost": 2
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Phrase {
+            field: foo_field,
+            terms: vec![Term::from_string("hello")],
+            prefix: Some("wor".to_string()),
+            max_expansions: 50,
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_empty_query() {
+        let mut schema = Schema::new();
+        schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "foo": "  "
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_max_expansions() {
+        for max_expansions in vec![json!(0), json!(-1), json!("10")] {
+            let query = parse(&json!({
+                "foo": {
+                    "query": "hello",
+                    "max_expansions": max_expansions
+                }
+            }));
+
+            assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+        }
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_query() {
+        let query = parse(&json!({
+            "foo": {
+                "max_expansions": 10
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("query")));
+    }
+
+    #[test]
+    fn test_gives_error_for_extra_inner_key() {
+        let query = parse(&json!({
+            "foo": {
+                "query": "hello",
+                "slop": 1
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("slop".to_string())));
+    }
+}
@@ -7,14 +7,16 @@ use search::schema::Schema;
 use mapping::FieldSearchOptions;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::{parse_string, parse_float, Operator, parse_operator};
+use query_parser::utils::{parse_string, parse_boost, Operator, parse_operator};
 
 
 #[derive(Debug)]
 struct MatchQueryBuilder {
     field: String,
     query: String,
-    operator: Operator,
+
+    /// The index's default operator is used if this isn't set
+    operator: Option<Operator>,
     boost: f32,
 }
 
@@ -39,7 +41,12 @@ impl QueryBuilder for MatchQueryBuilder {
                 token_stream.collect::<Vec<Token>>()
             }
             None => {
-                vec![Token {term: Term::from_string(&self.query), position: 1}]
+                let term = match field_search_options.normalizer {
+                    Some(ref normalizer) => Term::from_string(&normalizer.normalize(&self.query)),
+                    None => Term::from_string(&self.query),
+                };
+
+                vec![Token {term: term, position: 1}]
             }
         };
 
@@ -58,7 +65,7 @@ impl QueryBuilder for MatchQueryBuilder {
             0 => Query::None,
             1 => sub_queries.pop().unwrap(),
             _ => {
-                match self.operator {
+                match self.operator.unwrap_or_else(|| context.default_operator()) {
                     Operator::Or => {
                         Query::Disjunction { queries: sub_queries }
                     }
@@ -87,7 +94,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     // Get configuration
     let mut query = String::new();
     let mut boost = 1.0f32;
-    let mut operator = Operator::Or;
+    let mut operator = None;
 
     match object.get(field_name).unwrap() {
         s @ &Json::String(_) => query = parse_string(s)?,
@@ -101,10 +108,10 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                         query = parse_string(value)?;
                     }
                     "boost" => {
-                        boost = parse_float(value)?;
+                        boost = parse_boost(value)?;
                     }
                     "operator" => {
-                        operator = parse_operator(value)?;
+                        operator = Some(parse_operator(value)?);
                     }
                     _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
                 }
@@ -134,6 +141,8 @@ mod tests {
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
 
     use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::utils::Operator;
+    use index::metadata::IndexMetadata;
 
     use super::parse;
 
@@ -285,6 +294,54 @@ mod tests {
         }))
     }
 
+    #[test]
+    fn test_uses_index_default_operator() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.default_operator = Some(Operator::And);
+
+        let builder = parse(&json!({
+            "foo": "bar baz"
+        })).unwrap();
+
+        assert_eq!(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema), Query::Conjunction {
+            queries: vec![
+                Query::Term {
+                    field: foo_field,
+                    term: Term::from_string("bar"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: foo_field,
+                    term: Term::from_string("baz"),
+                    scorer: TermScorer::default(),
+                }
+            ],
+        });
+
+        // The operator in the query takes precedence
+        let builder = parse(&json!({
+            "foo": {"query": "bar baz", "operator": "or"}
+        })).unwrap();
+
+        assert_eq!(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema), Query::Disjunction {
+            queries: vec![
+                Query::Term {
+                    field: foo_field,
+                    term: Term::from_string("bar"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: foo_field,
+                    term: Term::from_string("baz"),
+                    scorer: TermScorer::default(),
+                }
+            ],
+        });
+    }
+
     #[test]
     fn test_gives_error_for_incorrect_type() {
         // Array
@@ -2,6 +2,7 @@
 
 pub mod utils;
 pub mod match_query;
+pub mod match_phrase_prefix_query;
 pub mod multi_match_query;
 pub mod match_all_query;
 pub mod match_none_query;
@@ -9,24 +10,63 @@ pub mod filtered_query;
 pub mod terms_query;
 pub mod term_query;
 pub mod prefix_query;
+pub mod wildcard_query;
 pub mod and_query;
 pub mod or_query;
 pub mod not_query;
 pub mod constant_score_query;
+pub mod type_query;
+pub mod ids_query;
+pub mod range_query;
+pub mod has_child_query;
+pub mod has_parent_query;
+pub mod knn_query;
+pub mod geo_shape_query;
+pub mod function_score_query;
+pub mod rank_feature_query;
+pub mod distance_feature_query;
+pub mod sort;
+pub mod rescore;
+pub mod fields;
+pub mod paging;
+pub mod aggregations;
+pub mod registry;
+pub mod warnings;
 
 use std::fmt::Debug;
+use std::rc::Rc;
+use std::cell::Cell;
 
 use serde_json::Value as Json;
-use search::Query;
+use search::{Term, Query};
 use search::schema::Schema;
 
 use index::metadata::IndexMetadata;
+use mapping::FieldMapping;
+use json_pointer;
+use mapping::runtime::{RuntimeMappings, RuntimeMapping};
+
+use self::utils::Operator;
+use self::registry::get_query_parser;
+
+
+/// Fetches the values of a field of a document, used by "terms" queries that look up their terms
+pub trait TermsLookup: Debug {
+    /// Returns the values of the field at "path" in the document, or an empty list
+    /// if the document or field doesn't exist
+    fn lookup_terms(&self, index: &str, id: &str, path: &str) -> Result<Vec<Term>, String>;
+}
 
 
 #[derive(Debug, Clone)]
 pub struct QueryBuildContext<'a> {
     pub index_metadata: Option<&'a IndexMetadata>,
+    pub terms_lookup: Option<&'a TermsLookup>,
+    pub runtime_mappings: Option<&'a RuntimeMappings>,
     score_required: bool,
+
+    /// Set when a query is built relative to the current time, shared with clones of the context
+    uses_now: Rc<Cell<bool>>,
 }
 
 
@@ -34,7 +74,10 @@ impl<'a> QueryBuildContext<'a> {
     pub fn new() -> QueryBuildContext<'a> {
         QueryBuildContext {
             index_metadata: None,
-            score_required: true
+            terms_lookup: None,
+            runtime_mappings: None,
+            score_required: true,
+            uses_now: Rc::new(Cell::new(false)),
         }
     }
 
@@ -44,6 +87,50 @@ impl<'a> QueryBuildContext<'a> {
         self
     }
 
+    #[inline]
+    pub fn set_terms_lookup(mut self, terms_lookup: &'a TermsLookup) -> QueryBuildContext<'a> {
+        self.terms_lookup = Some(terms_lookup);
+        self
+    }
+
+    #[inline]
+    pub fn set_runtime_mappings(mut self, runtime_mappings: &'a RuntimeMappings) -> QueryBuildContext<'a> {
+        self.runtime_mappings = Some(runtime_mappings);
+        self
+    }
+
+    /// The field searched by queries that don't specify any ("index.query.default_field")
+    pub fn default_field(&self) -> &'a str {
+        self.index_metadata.and_then(|index_metadata| index_metadata.default_field.as_ref()).map(|field| field.as_str()).unwrap_or("_all")
+    }
+
+    /// The operator used by queries that don't specify one ("index.query.default_operator")
+    pub fn default_operator(&self) -> Operator {
+        self.index_metadata.and_then(|index_metadata| index_metadata.default_operator).unwrap_or(Operator::Or)
+    }
+
+    /// Finds the mapping of a field, if the index metadata is available
+    pub fn get_field_mapping(&self, name: &str) -> Option<&'a FieldMapping> {
+        self.index_metadata.and_then(|index_metadata| index_metadata.get_field_mapping(name))
+    }
+
+    /// Finds the runtime field with the name, if the search request defined one
+    pub fn get_runtime_mapping(&self, name: &str) -> Option<&'a RuntimeMapping> {
+        self.runtime_mappings.and_then(|runtime_mappings| runtime_mappings.get(name))
+    }
+
+    /// Records that a query was built relative to the current time (eg, "now-1d")
+    pub fn mark_uses_now(&self) {
+        self.uses_now.set(true);
+    }
+
+    /// Checks if any query was built relative to the current time
+    ///
+    /// The results of these queries change as time goes on, so they mustn't be cached.
+    pub fn has_used_now(&self) -> bool {
+        self.uses_now.get()
+    }
+
     #[inline]
     pub fn no_score(mut self) -> QueryBuildContext<'a> {
         self.score_required = false;
@@ -55,6 +142,8 @@ impl<'a> QueryBuildContext<'a> {
 #[derive(Debug, PartialEq)]
 pub enum QueryParseError {
     UnrecognisedQueryType(String),
+    UnrecognisedAggregationType(String),
+    ExpectedParentAggregation(String),
     FieldDoesntExist(String),
     UnrecognisedKey(String),
     ExpectedKey(&'static str),
@@ -62,10 +151,27 @@ pub enum QueryParseError {
     ExpectedArray,
     ExpectedString,
     ExpectedFloat,
+    ExpectedBoolean,
     ExpectedObjectOrString,
     InvalidValue,
     ExpectedSingleKey,
     InvalidOperator,
+    InvalidBoost,
+}
+
+
+impl QueryParseError {
+    /// Finds the pointer to the key that the error is about in the JSON that was parsed
+    ///
+    /// Only errors about unrecognised keys and types point to a key, this returns None for others.
+    pub fn pointer(&self, json: &Json) -> Option<String> {
+        match *self {
+            QueryParseError::UnrecognisedQueryType(ref key) |
+            QueryParseError::UnrecognisedAggregationType(ref key) |
+            QueryParseError::UnrecognisedKey(ref key) => json_pointer::find_key(json, key),
+            _ => None,
+        }
+    }
 }
 
 
@@ -74,22 +180,51 @@ pub trait QueryBuilder: Debug {
 }
 
 
-fn get_query_parser(query_name: &str) -> Option<fn(&Json) -> Result<Box<QueryBuilder>, QueryParseError>> {
-    match query_name {
-        "match" => Some(match_query::parse),
-        "multi_match" => Some(multi_match_query::parse),
-        "match_all" => Some(match_all_query::parse),
-        "match_none" => Some(match_none_query::parse),
-        "filtered" => Some(filtered_query::parse),
-        "terms" => Some(terms_query::parse),
-        "in" => Some(terms_query::parse),
-        "term" => Some(term_query::parse),
-        "prefix" => Some(prefix_query::parse),
-        "and" => Some(and_query::parse),
-        "or" => Some(or_query::parse),
-        "not" => Some(not_query::parse),
-        "constant_score" => Some(constant_score_query::parse),
-        _ => None
+/// Wraps a query that was given a "_name"
+#[derive(Debug)]
+struct NamedQueryBuilder {
+    name: String,
+    query: Box<QueryBuilder>,
+}
+
+
+impl QueryBuilder for NamedQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        self.query.build(context, schema).named(self.name.clone())
+    }
+}
+
+
+/// Queries that take their parameters in an object under the field name, eg {"term": {"title": {"value": "foo"}}}
+const FIELD_LEVEL_QUERIES: &'static [&'static str] = &["match", "match_phrase_prefix", "term", "prefix", "wildcard", "range"];
+
+
+/// Removes "_name" from the parameters of a query
+///
+/// This is usually at the top level of the parameters but field level queries
+/// have it alongside the other parameters of the field.
+fn take_query_name(query_type: &str, json: &Json) -> Result<(Option<String>, Json), QueryParseError> {
+    let mut json = json.clone();
+
+    let name = {
+        let object = match json.as_object_mut() {
+            Some(object) => object,
+            None => return Ok((None, json)),
+        };
+
+        match object.remove("_name") {
+            Some(name) => Some(name),
+            None if FIELD_LEVEL_QUERIES.contains(&query_type) && object.len() == 1 => {
+                object.iter_mut().next().and_then(|(_, field_json)| field_json.as_object_mut()).and_then(|field_object| field_object.remove("_name"))
+            }
+            None => None,
+        }
+    };
+
+    match name {
+        Some(Json::String(name)) => Ok((Some(name), json)),
+        Some(_) => Err(QueryParseError::ExpectedString),
+        None => Ok((None, json)),
     }
 }
 
@@ -103,8 +238,128 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
         return Err(QueryParseError::ExpectedSingleKey)
     };
 
-    match get_query_parser(&query_type) {
-        Some(parse) => parse(object.get(query_type).unwrap()),
-        None => Err(QueryParseError::UnrecognisedQueryType(query_type.clone())),
+    let parse = match get_query_parser(&query_type) {
+        Some(parse) => parse,
+        None => return Err(QueryParseError::UnrecognisedQueryType(query_type.clone())),
+    };
+
+    // Any query can be given a name
+    let (name, query_json) = take_query_name(query_type, object.get(query_type).unwrap())?;
+    let query = parse(&query_json)?;
+
+    match name {
+        Some(name) => {
+            Ok(Box::new(NamedQueryBuilder {
+                name: name,
+                query: query,
+            }))
+        }
+        None => Ok(query),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use super::{QueryBuildContext, QueryParseError, parse};
+
+    #[test]
+    fn test_records_queries_relative_to_now() {
+        let mut schema = Schema::new();
+        schema.add_field("published".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        // The context is cloned for filters, they must still be recorded
+        let context = QueryBuildContext::new();
+        parse(&json!({
+            "constant_score": {
+                "filter": {
+                    "range": {
+                        "published": {"gte": "now-1d/d"}
+                    }
+                },
+                "boost": 1.0
+            }
+        })).unwrap().build(&context, &schema);
+        assert!(context.has_used_now());
+
+        // Plain dates and date math anchored to them don't change over time
+        let context = QueryBuildContext::new();
+        parse(&json!({
+            "range": {
+                "published": {"gte": "2017-01-01T00:00:00Z", "lte": "2017-01-01T00:00:00Z||+1d"}
+            }
+        })).unwrap().build(&context, &schema);
+        assert!(!context.has_used_now());
+    }
+
+    #[test]
+    fn test_named_query() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "terms": {
+                "test": ["foo"],
+                "_name": "my_query"
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Disjunction {
+            queries: vec![
+                Query::term(test_field, Term::from_string("foo")),
+            ],
+        }.named("my_query".to_string())));
+    }
+
+    #[test]
+    fn test_named_field_level_query() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "term": {
+                "test": {
+                    "value": "foo",
+                    "_name": "my_query"
+                }
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::term(test_field, Term::from_string("foo")).named("my_query".to_string())));
+    }
+
+    #[test]
+    fn test_name_must_be_string() {
+        let query = parse(&json!({
+            "match_all": {
+                "_name": 123
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedString));
+    }
+
+    #[test]
+    fn test_error_pointer() {
+        let json = json!({
+            "constant_score": {
+                "filter": {
+                    "match_phrase_prefix": {
+                        "title": {
+                            "query": "hello wor",
+                            "slop": 1
+                        }
+                    }
+                }
+            }
+        });
+        let error = parse(&json).err().unwrap();
+
+        assert_eq!(error, QueryParseError::UnrecognisedKey("slop".to_string()));
+        assert_eq!(error.pointer(&json), Some("/constant_score/filter/match_phrase_prefix/title/slop".to_string()));
+        assert_eq!(QueryParseError::ExpectedObject.pointer(&json), None);
     }
 }
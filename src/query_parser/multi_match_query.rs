@@ -1,88 +1,197 @@
 //! Parses "multi_match" queries
 
+use std::collections::BTreeMap;
+
 use serde_json::Value as Json;
 use search::{Term, Token, Query, TermScorer};
-use search::schema::Schema;
+use search::schema::{Schema, FieldId};
 
 use mapping::FieldSearchOptions;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::{parse_string, parse_float, Operator, parse_operator, parse_field_and_boost};
+use query_parser::utils::{parse_string, parse_float, parse_boost, Operator, parse_operator, parse_field_and_boost, expand_field_patterns};
+
+
+/// How the scores of each field are combined
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MultiMatchType {
+    /// Each field is searched separately, the score of the best matching field is used
+    BestFields,
+
+    /// Each field is searched separately, the scores of every matching field are added together
+    MostFields,
+
+    /// The fields are searched as if they were one big field, each term can match in any of them
+    CrossFields,
+}
 
 
 #[derive(Debug)]
 struct MultiMatchQueryBuilder {
-    fields: Vec<(String, f32)>,
+    /// The index's default field is searched if this isn't set
+    fields: Option<Vec<(String, f32)>>,
     query: String,
-    operator: Operator,
+
+    /// The index's default operator is used if this isn't set
+    operator: Option<Operator>,
+    match_type: MultiMatchType,
+
+    /// Multiplies the scores of fields that aren't the best match, before adding them to the score
+    /// Used by "best_fields" and "cross_fields"
+    tie_breaker: f32,
     boost: f32,
 }
 
 
-impl QueryBuilder for MultiMatchQueryBuilder {
-    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
-        // Convert query string into term query objects
-        let mut field_queries = Vec::new();
-        for &(ref field_name, field_boost) in self.fields.iter() {
-            // Get search options for field
-            let field_search_options = match context.index_metadata {
-                Some(index_metadata) => {
-                    match index_metadata.get_field_mapping(&field_name) {
-                        Some(field_mapping) => field_mapping.get_search_options(),
-                        None => FieldSearchOptions::default(),  // TODO: error?
-                    }
+/// Combines queries with an operator
+fn combine_queries(mut queries: Vec<Query>, operator: Operator) -> Query {
+    match queries.len() {
+        0 => Query::None,
+        1 => queries.pop().unwrap(),
+        _ => {
+            match operator {
+                Operator::Or => {
+                    Query::Disjunction { queries: queries }
                 }
-                None => FieldSearchOptions::default(),  // TODO: error?
-            };
-
-            // Tokenise query string
-            let tokens = match field_search_options.analyzer {
-                Some(ref analyzer) => {
-                    let token_stream = analyzer.initialise(&self.query);
-                    token_stream.collect::<Vec<Token>>()
+                Operator::And => {
+                    Query::Conjunction { queries: queries }
                 }
-                None => {
-                    vec![Token {term: Term::from_string(&self.query), position: 1}]
+            }
+        }
+    }
+}
+
+
+impl MultiMatchQueryBuilder {
+    /// Tokenises the query string with the analyzer of the field
+    fn analyze(&self, context: &QueryBuildContext, field_name: &str) -> Vec<Token> {
+        // Get search options for field
+        let field_search_options = match context.index_metadata {
+            Some(index_metadata) => {
+                match index_metadata.get_field_mapping(field_name) {
+                    Some(field_mapping) => field_mapping.get_search_options(),
+                    None => FieldSearchOptions::default(),  // TODO: error?
                 }
+            }
+            None => FieldSearchOptions::default(),  // TODO: error?
+        };
+
+        // Tokenise query string
+        match field_search_options.analyzer {
+            Some(ref analyzer) => {
+                let token_stream = analyzer.initialise(&self.query);
+                token_stream.collect::<Vec<Token>>()
+            }
+            None => {
+                let term = match field_search_options.normalizer {
+                    Some(ref normalizer) => Term::from_string(&normalizer.normalize(&self.query)),
+                    None => Term::from_string(&self.query),
+                };
+
+                vec![Token {term: term, position: 1}]
+            }
+        }
+    }
+
+    /// Builds a query for every field, combining them by their best score or the sum of their scores
+    fn build_field_centric(&self, context: &QueryBuildContext, schema: &Schema, fields: &[(String, f32)], operator: Operator) -> Query {
+        // Convert query string into term query objects
+        let mut field_queries = Vec::new();
+        for &(ref field_name, field_boost) in fields.iter() {
+            // Fields that are mapped but haven't had anything indexed into them yet
+            // aren't in the schema, they can't match anything
+            let field = match schema.get_field_by_name(field_name) {
+                Some(field) => field,
+                None => continue,
             };
 
-            let mut term_queries = Vec::new();
-            for token in tokens {
-                term_queries.push(Query::Term {
-                    field: schema.get_field_by_name(field_name).unwrap(),
+            let term_queries = self.analyze(context, field_name).into_iter().map(|token| {
+                Query::Term {
+                    field: field,
                     term: token.term,
                     scorer: TermScorer::default(),
-                });
-            }
-
-            // Combine the term queries
-            let field_query = match term_queries.len() {
-                0 => Query::None,
-                1 => term_queries.pop().unwrap(),
-                _ => {
-                    match self.operator {
-                        Operator::Or => {
-                            Query::Disjunction { queries: term_queries }
-                        }
-                        Operator::And => {
-                            Query::Conjunction { queries: term_queries }
-                        }
-                    }
                 }
-            };
+            }).collect();
 
-            // Add boost
-            let field_query = field_query.boost(field_boost);
+            // Combine the term queries and add boost
+            let field_query = combine_queries(term_queries, operator).boost(field_boost);
 
             field_queries.push(field_query);
         }
 
-        let query = match field_queries.len() {
+        match field_queries.len() {
             0 => Query::None,
             1 => field_queries.pop().unwrap(),
-            _ => {
-                Query::DisjunctionMax { queries: field_queries }
+            num_fields => {
+                if self.match_type == MultiMatchType::MostFields {
+                    // Disjunctions average the scores of their queries, boosting them by the
+                    // number of fields turns this into the sum
+                    Query::Disjunction { queries: field_queries }.boost(num_fields as f32)
+                } else {
+                    Query::DisjunctionMax { queries: field_queries, tie_breaker: self.tie_breaker }
+                }
             }
+        }
+    }
+
+    /// Builds a query for every term, that searches for it in all of the fields
+    fn build_term_centric(&self, context: &QueryBuildContext, schema: &Schema, fields: &[(String, f32)], operator: Operator) -> Query {
+        // Group the terms of every field by their position in the query string
+        let mut positions: BTreeMap<u32, Vec<(Term, Vec<(FieldId, TermScorer)>)>> = BTreeMap::new();
+        for &(ref field_name, field_boost) in fields.iter() {
+            let field = match schema.get_field_by_name(field_name) {
+                Some(field) => field,
+                None => continue,
+            };
+
+            for token in self.analyze(context, field_name) {
+                let terms = positions.entry(token.position).or_insert_with(Vec::new);
+                let scorer = TermScorer::default_with_boost(field_boost);
+
+                match terms.iter().position(|&(ref term, _)| *term == token.term) {
+                    Some(index) => terms[index].1.push((field, scorer)),
+                    None => terms.push((token.term, vec![(field, scorer)])),
+                }
+            }
+        }
+
+        let term_queries = positions.into_iter().map(|(_, terms)| {
+            let mut blended_queries = terms.into_iter().map(|(term, fields)| {
+                Query::BlendedTerm {
+                    fields: fields,
+                    term: term,
+                    tie_breaker: self.tie_breaker,
+                }
+            }).collect::<Vec<Query>>();
+
+            // Fields with different analyzers may have different terms at the same position
+            match blended_queries.len() {
+                1 => blended_queries.pop().unwrap(),
+                _ => Query::DisjunctionMax { queries: blended_queries, tie_breaker: self.tie_breaker },
+            }
+        }).collect();
+
+        combine_queries(term_queries, operator)
+    }
+}
+
+
+impl QueryBuilder for MultiMatchQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let fields = match self.fields {
+            // Patterns like "title*" are expanded into the fields they match
+            Some(ref fields) => expand_field_patterns(fields, schema),
+            None => {
+                // The default field may not exist if it wasn't set and the index has no "_all" field
+                let default_field = context.default_field();
+                if schema.get_field_by_name(default_field).is_some() { vec![(default_field.to_string(), 1.0f32)] } else { Vec::new() }
+            }
+        };
+        let operator = self.operator.unwrap_or_else(|| context.default_operator());
+
+        let query = match self.match_type {
+            MultiMatchType::BestFields | MultiMatchType::MostFields => self.build_field_centric(context, schema, &fields, operator),
+            MultiMatchType::CrossFields => self.build_term_centric(context, schema, &fields, operator),
         };
 
         // Add boost
@@ -91,6 +200,16 @@ impl QueryBuilder for MultiMatchQueryBuilder {
 }
 
 
+fn parse_match_type(json: &Json) -> Result<MultiMatchType, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "best_fields" => Ok(MultiMatchType::BestFields),
+        "most_fields" => Ok(MultiMatchType::MostFields),
+        "cross_fields" => Ok(MultiMatchType::CrossFields),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
 pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
 
@@ -98,7 +217,9 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let mut fields_with_boosts = Vec::new();
     let mut query = String::new();
     let mut boost = 1.0f32;
-    let mut operator = Operator::Or;
+    let mut operator = None;
+    let mut match_type = MultiMatchType::BestFields;
+    let mut tie_breaker = 0.0f32;
 
     let mut has_fields_key = false;
     let mut has_query_key = false;
@@ -122,27 +243,35 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                 query = parse_string(val)?;
             }
             "boost" => {
-                boost = parse_float(val)?;
+                boost = parse_boost(val)?;
             }
             "operator" => {
-                operator = parse_operator(val)?;
+                operator = Some(parse_operator(val)?);
+            }
+            "type" => {
+                match_type = parse_match_type(val)?;
+            }
+            "tie_breaker" => {
+                tie_breaker = parse_float(val)?;
+
+                if !(tie_breaker >= 0.0 && tie_breaker <= 1.0) {
+                    return Err(QueryParseError::InvalidValue);
+                }
             }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
     }
 
-    if !has_fields_key {
-        return Err(QueryParseError::ExpectedKey("fields"))
-    }
-
     if !has_query_key {
         return Err(QueryParseError::ExpectedKey("query"))
     }
 
     Ok(Box::new(MultiMatchQueryBuilder {
-        fields: fields_with_boosts,
+        fields: if has_fields_key { Some(fields_with_boosts) } else { None },
         query: query,
         operator: operator,
+        match_type: match_type,
+        tie_breaker: tie_breaker,
         boost: boost,
     }))
 }
@@ -156,6 +285,8 @@ mod tests {
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
 
     use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::utils::{Operator, matches_wildcard};
+    use index::metadata::IndexMetadata;
 
     use super::parse;
 
@@ -185,6 +316,7 @@ mod tests {
                     scorer: TermScorer::default(),
                 }
             ],
+            tie_breaker: 0.0,
         }));
     }
 
@@ -232,6 +364,7 @@ mod tests {
                     ],
                 }
             ],
+            tie_breaker: 0.0,
         }));
     }
 
@@ -262,6 +395,7 @@ mod tests {
                     scorer: TermScorer::default_with_boost(2.0f32),
                 }
             ],
+            tie_breaker: 0.0,
         }));
     }
 
@@ -292,6 +426,7 @@ mod tests {
                     scorer: TermScorer::default_with_boost(2.0f32),
                 }
             ],
+            tie_breaker: 0.0,
         }));
     }
 
@@ -321,9 +456,131 @@ mod tests {
                     scorer: TermScorer::default(),
                 }
             ],
+            tie_breaker: 0.0,
+        }));
+    }
+
+    #[test]
+    fn test_field_wildcards() {
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let title_ngram_field = schema.add_field("title_ngram".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let body_field = schema.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        schema.add_field("title_length".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["title*^3", "body"]
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // Numeric fields aren't matched by wildcards
+        assert_eq!(query, Ok(Query::DisjunctionMax {
+            queries: vec![
+                Query::Term {
+                    field: title_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default_with_boost(3.0f32),
+                },
+                Query::Term {
+                    field: title_ngram_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default_with_boost(3.0f32),
+                },
+                Query::Term {
+                    field: body_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                }
+            ],
+            tie_breaker: 0.0,
+        }));
+    }
+
+    #[test]
+    fn test_field_wildcards_with_fields_not_in_schema() {
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["title*", "body^2"],
+            "type": "cross_fields"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::BlendedTerm {
+            fields: vec![
+                (title_field, TermScorer::default()),
+            ],
+            term: Term::from_string("foo"),
+            tie_breaker: 0.0,
+        }));
+
+        // Patterns that don't match any fields
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["body*", "summary"]
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_field_matched_twice_multiplies_boosts() {
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let title_ngram_field = schema.add_field("title_ngram".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["title*^2", "title^3"]
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::DisjunctionMax {
+            queries: vec![
+                Query::Term {
+                    field: title_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default_with_boost(6.0f32),
+                },
+                Query::Term {
+                    field: title_ngram_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default_with_boost(2.0f32),
+                }
+            ],
+            tie_breaker: 0.0,
+        }));
+    }
+
+    #[test]
+    fn test_wildcard_skips_underscore_fields() {
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        schema.add_field("_all".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["*"]
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: title_field,
+            term: Term::from_string("foo"),
+            scorer: TermScorer::default(),
         }));
     }
 
+    #[test]
+    fn test_matches_wildcard() {
+        assert!(matches_wildcard("title*", "title"));
+        assert!(matches_wildcard("title*", "title_ngram"));
+        assert!(matches_wildcard("*_ngram", "title_ngram"));
+        assert!(matches_wildcard("t*_*m", "title_ngram"));
+        assert!(!matches_wildcard("title*", "subtitle"));
+        assert!(!matches_wildcard("*_ngram", "title_ngram_edge"));
+        assert!(!matches_wildcard("title", "title_ngram"));
+    }
+
     #[test]
     fn test_with_field_and_query_boost() {
         let mut schema = Schema::new();
@@ -351,6 +608,7 @@ mod tests {
                     scorer: TermScorer::default_with_boost(2.0f32),
                 }
             ],
+            tie_breaker: 0.0,
         }));
     }
 
@@ -399,7 +657,206 @@ mod tests {
                     ],
                 }
             ],
+            tie_breaker: 0.0,
+        }));
+    }
+
+    #[test]
+    fn test_best_fields_with_tie_breaker() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let baz_field = schema.add_field("baz".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["bar", "baz"],
+            "type": "best_fields",
+            "tie_breaker": 0.5
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::DisjunctionMax {
+            queries: vec![
+                Query::Term {
+                    field: bar_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: baz_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                }
+            ],
+            tie_breaker: 0.5,
+        }));
+    }
+
+    #[test]
+    fn test_most_fields() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let baz_field = schema.add_field("baz".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["bar^3", "baz"],
+            "type": "most_fields"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // The disjunction averages the scores, so it's boosted by the number of fields to sum them
+        assert_eq!(query, Ok(Query::Disjunction {
+            queries: vec![
+                Query::Term {
+                    field: bar_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default_with_boost(6.0f32),
+                },
+                Query::Term {
+                    field: baz_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default_with_boost(2.0f32),
+                }
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_cross_fields() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let baz_field = schema.add_field("baz".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "hello world",
+            "fields": ["bar^2", "baz"],
+            "type": "cross_fields",
+            "operator": "and",
+            "tie_breaker": 0.1
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::BlendedTerm {
+                    fields: vec![
+                        (bar_field, TermScorer::default_with_boost(2.0f32)),
+                        (baz_field, TermScorer::default()),
+                    ],
+                    term: Term::from_string("hello"),
+                    tie_breaker: 0.1,
+                },
+                Query::BlendedTerm {
+                    fields: vec![
+                        (bar_field, TermScorer::default_with_boost(2.0f32)),
+                        (baz_field, TermScorer::default()),
+                    ],
+                    term: Term::from_string("world"),
+                    tie_breaker: 0.1,
+                }
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_cross_fields_with_boost() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let baz_field = schema.add_field("baz".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["bar", "baz"],
+            "type": "cross_fields",
+            "boost": 2.0
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::BlendedTerm {
+            fields: vec![
+                (bar_field, TermScorer::default_with_boost(2.0f32)),
+                (baz_field, TermScorer::default_with_boost(2.0f32)),
+            ],
+            term: Term::from_string("foo"),
+            tie_breaker: 0.0,
+        }));
+    }
+
+    #[test]
+    fn test_skips_fields_not_in_schema() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["bar", "baz"]
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: bar_field,
+            term: Term::from_string("foo"),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_cross_fields_skips_fields_not_in_schema() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["bar", "baz"],
+            "type": "cross_fields"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::BlendedTerm {
+            fields: vec![
+                (bar_field, TermScorer::default()),
+            ],
+            term: Term::from_string("foo"),
+            tie_breaker: 0.0,
         }));
+
+        // No fields in the schema at all
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["baz"],
+            "type": "cross_fields"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_type() {
+        let query = parse(&json!({
+            "query": "foo",
+            "type": "phrase_prefix"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&json!({
+            "query": "foo",
+            "type": 1
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedString));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_tie_breaker() {
+        let query = parse(&json!({
+            "query": "foo",
+            "tie_breaker": 1.5
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&json!({
+            "query": "foo",
+            "tie_breaker": "high"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
     }
 
     #[test]
@@ -574,14 +1031,47 @@ mod tests {
     }
 
     #[test]
-    fn test_gives_error_for_missing_fields() {
+    fn test_uses_index_default_field_and_operator() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.default_field = Some("bar".to_string());
+        index_metadata.default_operator = Some(Operator::And);
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"query\": \"hello world\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::Term {
+                    field: bar_field,
+                    term: Term::from_string("hello"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: bar_field,
+                    term: Term::from_string("world"),
+                    scorer: TermScorer::default(),
+                }
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_missing_default_field_matches_nothing() {
+        let schema = Schema::new();
+
         let query = parse(&serde_json::from_str("
         {
             \"query\": \"foo\"
         }
-        ").unwrap());
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("fields")));
+        assert_eq!(query, Ok(Query::None));
     }
 
     #[test]
@@ -596,4 +1086,21 @@ mod tests {
 
         assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
     }
+
+    #[test]
+    fn test_gives_error_for_invalid_field_boost() {
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["bar^high"]
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidBoost));
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["bar^-1"]
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidBoost));
+    }
 }
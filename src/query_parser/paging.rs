@@ -0,0 +1,113 @@
+//! Parses which page of hits a search returns
+//!
+//! The page can be given in the body of the search, or in the "from" and "size" URL
+//! parameters which take precedence over the body:
+//!
+//! ```json
+//! {"from": 20, "size": 10}
+//! ```
+
+use serde_json::Value as Json;
+
+use query_parser::QueryParseError;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Paging {
+    /// The number of hits to skip
+    pub from: usize,
+
+    /// The number of hits to return
+    pub size: usize,
+}
+
+
+impl Paging {
+    /// The number of hits that must be collected to fill the page
+    pub fn result_window(&self) -> usize {
+        self.from.saturating_add(self.size)
+    }
+}
+
+
+fn parse_count(json: &Json) -> Result<usize, QueryParseError> {
+    json.as_u64().map(|value| value as usize).ok_or(QueryParseError::InvalidValue)
+}
+
+
+/// Reads the page from the "from" and "size" keys of a search body
+///
+/// The first `default_size` hits are returned if the body doesn't give a size.
+pub fn parse(json: &Json, default_size: usize) -> Result<Paging, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let from = match object.get("from") {
+        Some(from) => parse_count(from)?,
+        None => 0,
+    };
+
+    let size = match object.get("size") {
+        Some(size) => parse_count(size)?,
+        None => default_size,
+    };
+
+    Ok(Paging {
+        from: from,
+        size: size,
+    })
+}
+
+
+/// Parses the value of a "from" or "size" URL parameter
+pub fn parse_param(value: &str) -> Result<usize, QueryParseError> {
+    value.parse().map_err(|_| QueryParseError::InvalidValue)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use query_parser::QueryParseError;
+
+    use super::{parse, parse_param, Paging};
+
+    #[test]
+    fn test_paging() {
+        let paging = parse(&json!({"query": {"match_all": {}}, "from": 20, "size": 5}), 10);
+
+        assert_eq!(paging, Ok(Paging { from: 20, size: 5 }));
+    }
+
+    #[test]
+    fn test_defaults() {
+        let paging = parse(&json!({"query": {"match_all": {}}}), 10);
+
+        assert_eq!(paging, Ok(Paging { from: 0, size: 10 }));
+    }
+
+    #[test]
+    fn test_result_window() {
+        assert_eq!(Paging { from: 20, size: 5 }.result_window(), 25);
+        assert_eq!(Paging { from: usize::max_value(), size: 5 }.result_window(), usize::max_value());
+    }
+
+    #[test]
+    fn test_gives_error_for_negative_size() {
+        let paging = parse(&json!({"size": -1}), 10);
+
+        assert_eq!(paging, Err(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_string_from() {
+        let paging = parse(&json!({"from": "10"}), 10);
+
+        assert_eq!(paging, Err(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_param() {
+        assert_eq!(parse_param("10"), Ok(10));
+        assert_eq!(parse_param("ten"), Err(QueryParseError::InvalidValue));
+        assert_eq!(parse_param("-1"), Err(QueryParseError::InvalidValue));
+    }
+}
@@ -1,11 +1,13 @@
 //! Parses "prefix" queries
 
 use serde_json::Value as Json;
-use search::{Query, MultiTermSelector, TermScorer};
+use search::{Term, Query, MultiTermSelector, TermScorer};
 use search::schema::Schema;
 
+use mapping::IndexPrefixes;
+
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::parse_float;
+use query_parser::utils::{parse_boost, parse_boolean};
 
 
 #[derive(Debug)]
@@ -13,14 +15,38 @@ struct PrefixQueryBuilder {
     field: String,
     prefix: String,
     boost: f32,
+    case_insensitive: bool,
 }
 
 
 impl QueryBuilder for PrefixQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        // Keyword fields may have normalized their values when they were indexed
+        let prefix = match context.get_field_mapping(&self.field) {
+            Some(field_mapping) => field_mapping.normalize(&self.prefix),
+            None => self.prefix.clone(),
+        };
+
+        // Fields with "index_prefixes" have their short prefixes indexed as terms of a
+        // hidden sub field, these can be looked up without scanning the term dictionary
+        if !self.case_insensitive {
+            let index_prefixes = context.get_field_mapping(&self.field).and_then(|field_mapping| field_mapping.index_prefixes);
+            if let Some(index_prefixes) = index_prefixes {
+                if index_prefixes.covers(&prefix) {
+                    if let Some(prefix_field) = schema.get_field_by_name(&IndexPrefixes::sub_field_name(&self.field)) {
+                        return Query::term(prefix_field, Term::from_string(&prefix)).boost(self.boost);
+                    }
+                }
+            }
+        }
+
         let query = Query::MultiTerm {
             field: schema.get_field_by_name(&self.field).unwrap(),
-            term_selector: MultiTermSelector::Prefix(self.prefix.clone()),
+            term_selector: if self.case_insensitive {
+                MultiTermSelector::case_insensitive_prefix(&prefix)
+            } else {
+                MultiTermSelector::Prefix(prefix)
+            },
             scorer: TermScorer::default(),
         };
 
@@ -44,6 +70,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     // Get configuration
     let mut value: Option<&Json> = None;
     let mut boost = 1.0f32;
+    let mut case_insensitive = false;
 
     match *object {
         Json::String(_) => value = Some(object),
@@ -57,7 +84,10 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                         value = Some(val);
                     }
                     "boost" => {
-                        boost = parse_float(val)?;
+                        boost = parse_boost(val)?;
+                    }
+                    "case_insensitive" => {
+                        case_insensitive = parse_boolean(val)?;
                     }
                     _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
                 }
@@ -73,6 +103,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                     field: field_name.clone(),
                     prefix: string.clone(),
                     boost: boost,
+                    case_insensitive: case_insensitive,
                 }))
             } else {
                 Err(QueryParseError::ExpectedString)
@@ -87,9 +118,11 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 mod tests {
     use serde_json;
 
-    use search::{Query, MultiTermSelector, TermScorer};
+    use search::{Term, Query, MultiTermSelector, TermScorer};
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
 
+    use index::metadata::IndexMetadata;
+    use mapping::parse::parse as parse_mapping;
     use query_parser::{QueryBuildContext, QueryParseError};
 
     use super::parse;
@@ -194,6 +227,27 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn test_case_insensitive() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"BAR\",
+                \"case_insensitive\": true
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::CaseInsensitivePrefix("bar".to_string()),
+            scorer: TermScorer::default(),
+        }));
+    }
+
     #[test]
     fn test_gives_error_for_incorrect_type() {
         // Array
@@ -300,4 +354,37 @@ mod tests {
 
         assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
     }
+
+    #[test]
+    fn test_index_prefixes() {
+        let mut index_metadata = IndexMetadata::default();
+        let mapping = parse_mapping(&json!({
+            "properties": {
+                "title": {
+                    "type": "string",
+                    "index_prefixes": {"min_chars": 2, "max_chars": 4}
+                }
+            }
+        })).unwrap().build(&index_metadata);
+        index_metadata.mappings.insert("test".to_string(), mapping);
+
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let prefix_field = schema.add_field("title._index_prefix".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let context = QueryBuildContext::new().set_index_metadata(&index_metadata);
+
+        // Prefixes that were indexed are looked up as a term
+        let query = parse(&json!({"title": "hel"})).and_then(|builder| Ok(builder.build(&context, &schema)));
+        assert_eq!(query, Ok(Query::term(prefix_field, Term::from_string("hel"))));
+
+        // Others still scan the term dictionary of the field
+        for prefix in vec!["h", "hello"] {
+            let query = parse(&json!({"title": prefix})).and_then(|builder| Ok(builder.build(&context, &schema)));
+            assert_eq!(query, Ok(Query::MultiTerm {
+                field: title_field,
+                term_selector: MultiTermSelector::Prefix(prefix.to_string()),
+                scorer: TermScorer::default(),
+            }));
+        }
+    }
 }
@@ -0,0 +1,657 @@
+//! Parses "range" queries on range, IP, integer and date fields
+//!
+//! Integer and date fields are filtered by scanning their doc values, rather than
+//! expanding the range into the terms it covers.
+//!
+//! Date bounds can be date math expressions (eg, "now-7d/d"). Rounding extends "gt"
+//! and "lte" bounds to the end of the unit, and "gte" and "lt" bounds to its start,
+//! so "lte": "now/d" includes the whole of today.
+//!
+//! Runtime fields compare their values with the bounds as they were given, so they can
+//! be ranged over with fractions and strings too.
+
+use std::net::IpAddr;
+
+use serde_json::Value as Json;
+use chrono::{DateTime, Utc};
+use mapping::date_math::{self, Rounding};
+use search::{Term, Query, MultiTermSelector, TermScorer, RangeBound};
+use search::term::{datetime_to_micros, ip_to_bytes};
+use search::schema::{Schema, FieldId};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_boost, parse_string};
+
+
+/// How the range in the query must relate to the range in the document (range fields only)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RangeRelation {
+    Intersects,
+    Contains,
+    Within,
+}
+
+
+/// A bound given in the query
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BoundValue {
+    /// Integers and dates (converted into microseconds since the epoch)
+    Integer(i64),
+
+    /// IP addresses, in the encoding used by IP terms
+    Ip([u8; 16]),
+}
+
+
+impl BoundValue {
+    /// Returns the next value up, for converting "gt" into "gte"
+    fn next(&self) -> Option<BoundValue> {
+        match *self {
+            BoundValue::Integer(value) => value.checked_add(1).map(BoundValue::Integer),
+            BoundValue::Ip(mut bytes) => {
+                for i in (0..16).rev() {
+                    if bytes[i] == 0xff {
+                        bytes[i] = 0;
+                    } else {
+                        bytes[i] += 1;
+                        return Some(BoundValue::Ip(bytes));
+                    }
+                }
+
+                None
+            }
+        }
+    }
+
+    /// Returns the previous value, for converting "lt" into "lte"
+    fn previous(&self) -> Option<BoundValue> {
+        match *self {
+            BoundValue::Integer(value) => value.checked_sub(1).map(BoundValue::Integer),
+            BoundValue::Ip(mut bytes) => {
+                for i in (0..16).rev() {
+                    if bytes[i] == 0 {
+                        bytes[i] = 0xff;
+                    } else {
+                        bytes[i] -= 1;
+                        return Some(BoundValue::Ip(bytes));
+                    }
+                }
+
+                None
+            }
+        }
+    }
+}
+
+
+/// The inclusive bounds of the query, missing bounds are filled in with the lowest/highest value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bounds {
+    Integer(i64, i64),
+    Ip([u8; 16], [u8; 16]),
+}
+
+
+/// The bounds as they were given in the query, for runtime fields
+#[derive(Debug, Default)]
+struct RuntimeBounds {
+    gte: Option<Json>,
+    gt: Option<Json>,
+    lte: Option<Json>,
+    lt: Option<Json>,
+}
+
+
+#[derive(Debug)]
+struct RangeQueryBuilder {
+    field: String,
+    bounds: Bounds,
+    runtime_bounds: RuntimeBounds,
+    relation: RangeRelation,
+    boost: f32,
+
+    /// Set if a bound is relative to the current time
+    uses_now: bool,
+}
+
+
+impl RangeQueryBuilder {
+    /// Finds values of a range field that intersect/contain/are within the range
+    fn build_range_field_filter(&self, field: FieldId, gte: i64, lte: i64) -> Query {
+        let bound_query = |bound, gte, lte| {
+            Query::MultiTerm {
+                field: field,
+                term_selector: MultiTermSelector::RangeBound {
+                    bound: bound,
+                    gte: gte,
+                    lte: lte,
+                },
+                scorer: TermScorer::default(),
+            }
+        };
+
+        let min = i64::min_value();
+        let max = i64::max_value();
+
+        let queries = match self.relation {
+            // The document range overlaps the query range
+            RangeRelation::Intersects => vec![
+                bound_query(RangeBound::Lower, min, lte),
+                bound_query(RangeBound::Upper, gte, max),
+            ],
+
+            // The document range covers the whole of the query range
+            RangeRelation::Contains => vec![
+                bound_query(RangeBound::Lower, min, gte),
+                bound_query(RangeBound::Upper, lte, max),
+            ],
+
+            // The document range is inside the query range
+            RangeRelation::Within => vec![
+                bound_query(RangeBound::Lower, gte, max),
+                bound_query(RangeBound::Upper, min, lte),
+            ],
+        };
+
+        Query::Conjunction { queries: queries }
+    }
+}
+
+
+impl QueryBuilder for RangeQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        if self.uses_now {
+            context.mark_uses_now();
+        }
+
+        // Runtime fields aren't indexed, their values are computed and compared for each document
+        if let Some(runtime_mapping) = context.get_runtime_mapping(&self.field) {
+            let bounds = &self.runtime_bounds;
+            return runtime_mapping.build_range_query(bounds.gte.as_ref(), bounds.gt.as_ref(), bounds.lte.as_ref(), bounds.lt.as_ref(), self.boost);
+        }
+
+        // Nothing has been indexed into the field yet, so nothing can be in the range
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // Integer and date fields aren't indexed as ranges, their doc values are scanned instead
+        if let Bounds::Integer(gte, lte) = self.bounds {
+            let is_numeric = context.get_field_mapping(&self.field).map(|field_mapping| field_mapping.data_type.is_numeric()).unwrap_or(false);
+
+            if is_numeric {
+                return Query::NumericRange {
+                    field: field,
+                    gte: gte,
+                    lte: lte,
+                    score: self.boost,
+                };
+            }
+        }
+
+        let filter = match self.bounds {
+            Bounds::Integer(gte, lte) => self.build_range_field_filter(field, gte, lte),
+            Bounds::Ip(gte, lte) => {
+                Query::MultiTerm {
+                    field: field,
+                    term_selector: MultiTermSelector::IpRange {
+                        gte: Term::from_bytes(&gte),
+                        lte: Term::from_bytes(&lte),
+                    },
+                    scorer: TermScorer::default(),
+                }
+            }
+        };
+
+        // Every match gets the same score
+        Query::Filter {
+            query: Box::new(Query::All{ score: self.boost }),
+            filter: Box::new(filter),
+        }
+    }
+}
+
+
+fn parse_bound(json: &Json, now: DateTime<Utc>, rounding: Rounding) -> Result<BoundValue, QueryParseError> {
+    match *json {
+        Json::Number(ref number) => {
+            if let Some(number) = number.as_i64() {
+                return Ok(BoundValue::Integer(number));
+            }
+
+            // Fractions are rounded up for "gte" and "lt" and down for "gt" and "lte", so
+            // the bound still includes the same integers once it's made inclusive
+            let number = number.as_f64().ok_or(QueryParseError::InvalidValue)?;
+            match rounding {
+                Rounding::Down => Ok(BoundValue::Integer(number.ceil() as i64)),
+                Rounding::Up => Ok(BoundValue::Integer(number.floor() as i64)),
+            }
+        }
+        Json::String(ref string) => {
+            if date_math::is_date_math(string) {
+                let date = date_math::evaluate(string, now, rounding).map_err(|_| QueryParseError::InvalidValue)?;
+                return Ok(BoundValue::Integer(datetime_to_micros(&date)));
+            }
+
+            if let Ok(date_parsed) = string.parse::<DateTime<Utc>>() {
+                return Ok(BoundValue::Integer(datetime_to_micros(&date_parsed)));
+            }
+
+            match string.parse::<IpAddr>() {
+                Ok(ip) => Ok(BoundValue::Ip(ip_to_bytes(&ip))),
+                Err(_) => Err(QueryParseError::InvalidValue),
+            }
+        }
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_relation(json: &Json) -> Result<RangeRelation, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "intersects" => Ok(RangeRelation::Intersects),
+        "contains" => Ok(RangeRelation::Contains),
+        "within" => Ok(RangeRelation::Within),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let inner_object = object.get(field_name).unwrap().as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut gte = None;
+    let mut lte = None;
+    let mut runtime_bounds = RuntimeBounds::default();
+    let mut relation = RangeRelation::Intersects;
+    let mut boost = 1.0f32;
+    let now = Utc::now();
+    let mut uses_now = false;
+
+    for (key, val) in inner_object.iter() {
+        if val.as_str().map(date_math::uses_now).unwrap_or(false) {
+            uses_now = true;
+        }
+
+        match key.as_ref() {
+            "gte" => {
+                gte = Some(parse_bound(val, now, Rounding::Down)?);
+                runtime_bounds.gte = Some(val.clone());
+            }
+            "gt" => {
+                gte = Some(parse_bound(val, now, Rounding::Up)?.next().ok_or(QueryParseError::InvalidValue)?);
+                runtime_bounds.gt = Some(val.clone());
+            }
+            "lte" => {
+                lte = Some(parse_bound(val, now, Rounding::Up)?);
+                runtime_bounds.lte = Some(val.clone());
+            }
+            "lt" => {
+                lte = Some(parse_bound(val, now, Rounding::Down)?.previous().ok_or(QueryParseError::InvalidValue)?);
+                runtime_bounds.lt = Some(val.clone());
+            }
+            "relation" => relation = parse_relation(val)?,
+            "boost" => boost = parse_boost(val)?,
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    // Both bounds must be the same kind of value
+    let bounds = match (gte, lte) {
+        (Some(BoundValue::Ip(gte)), Some(BoundValue::Ip(lte))) => Bounds::Ip(gte, lte),
+        (Some(BoundValue::Ip(gte)), None) => Bounds::Ip(gte, [0xff; 16]),
+        (None, Some(BoundValue::Ip(lte))) => Bounds::Ip([0; 16], lte),
+        (Some(BoundValue::Ip(_)), _) | (_, Some(BoundValue::Ip(_))) => return Err(QueryParseError::InvalidValue),
+        (gte, lte) => {
+            let gte = match gte {
+                Some(BoundValue::Integer(gte)) => gte,
+                _ => i64::min_value(),
+            };
+            let lte = match lte {
+                Some(BoundValue::Integer(lte)) => lte,
+                _ => i64::max_value(),
+            };
+
+            Bounds::Integer(gte, lte)
+        }
+    };
+
+    Ok(Box::new(RangeQueryBuilder {
+        field: field_name.clone(),
+        bounds: bounds,
+        runtime_bounds: runtime_bounds,
+        relation: relation,
+        boost: boost,
+        uses_now: uses_now,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use serde_json;
+
+    use search::{Term, Query, MultiTermSelector, TermScorer, RangeBound};
+    use search::schema::{Schema, FieldType, FieldId, FIELD_INDEXED};
+
+    use search::document::FieldValue;
+    use search::runtime::RuntimeCondition;
+
+    use index::metadata::IndexMetadata;
+    use mapping::parse::parse as parse_mapping;
+    use mapping::runtime::RuntimeMappings;
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    fn bound_query(field: FieldId, bound: RangeBound, gte: i64, lte: i64) -> Query {
+        Query::MultiTerm {
+            field: field,
+            term_selector: MultiTermSelector::RangeBound {
+                bound: bound,
+                gte: gte,
+                lte: lte,
+            },
+            scorer: TermScorer::default(),
+        }
+    }
+
+    fn range_query(score: f32, queries: Vec<Query>) -> Query {
+        Query::Filter {
+            query: Box::new(Query::All{ score: score }),
+            filter: Box::new(Query::Conjunction { queries: queries }),
+        }
+    }
+
+    #[test]
+    fn test_range_query() {
+        let mut schema = Schema::new();
+        let price_field = schema.add_field("price".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"price\": {
+                \"gte\": 10,
+                \"lt\": 20
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(range_query(1.0f32, vec![
+            bound_query(price_field, RangeBound::Lower, i64::min_value(), 19),
+            bound_query(price_field, RangeBound::Upper, 10, i64::max_value()),
+        ])));
+    }
+
+    #[test]
+    fn test_range_query_contains() {
+        let mut schema = Schema::new();
+        let price_field = schema.add_field("price".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"price\": {
+                \"gt\": 10,
+                \"lte\": 20,
+                \"relation\": \"contains\",
+                \"boost\": 2
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(range_query(2.0f32, vec![
+            bound_query(price_field, RangeBound::Lower, i64::min_value(), 11),
+            bound_query(price_field, RangeBound::Upper, 20, i64::max_value()),
+        ])));
+    }
+
+    #[test]
+    fn test_range_query_within_dates() {
+        let mut schema = Schema::new();
+        let available_field = schema.add_field("available".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"available\": {
+                \"gte\": \"2017-01-01T00:00:00Z\",
+                \"relation\": \"within\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(range_query(1.0f32, vec![
+            bound_query(available_field, RangeBound::Lower, 1483228800000000, i64::max_value()),
+            bound_query(available_field, RangeBound::Upper, i64::min_value(), i64::max_value()),
+        ])));
+    }
+
+    #[test]
+    fn test_range_query_date_math() {
+        let mut schema = Schema::new();
+        let published_field = schema.add_field("published".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        // "gte" is rounded down to the start of the month, "lte" up to the end of it
+        let query = parse(&serde_json::from_str("
+        {
+            \"published\": {
+                \"gte\": \"2017-01-15||/M\",
+                \"lte\": \"2017-01-15||+1M/M\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(range_query(1.0f32, vec![
+            bound_query(published_field, RangeBound::Lower, i64::min_value(), 1488326399999999),
+            bound_query(published_field, RangeBound::Upper, 1483228800000000, i64::max_value()),
+        ])));
+    }
+
+    #[test]
+    fn test_range_query_date_math_exclusive() {
+        let mut schema = Schema::new();
+        let published_field = schema.add_field("published".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        // "gt" skips the whole day, "lt" stops before it starts
+        let query = parse(&serde_json::from_str("
+        {
+            \"published\": {
+                \"gt\": \"2017-01-01||/d\",
+                \"lt\": \"2017-01-03T12:00:00Z||/d\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(range_query(1.0f32, vec![
+            bound_query(published_field, RangeBound::Lower, i64::min_value(), 1483401599999999),
+            bound_query(published_field, RangeBound::Upper, 1483315200000000, i64::max_value()),
+        ])));
+    }
+
+    #[test]
+    fn test_range_query_numeric_field() {
+        let mut schema = Schema::new();
+        let published_field = schema.add_field("published".to_string(), FieldType::DateTime, FIELD_INDEXED).unwrap();
+
+        let mut index_metadata = IndexMetadata::default();
+        let mapping = parse_mapping(&json!({
+            "properties": {
+                "published": {
+                    "type": "date"
+                }
+            }
+        })).unwrap().build(&index_metadata);
+        index_metadata.mappings.insert("test".to_string(), mapping);
+
+        let query = parse(&json!({
+            "published": {
+                "gte": "2017-01-01T00:00:00Z",
+                "lt": "2017-02-01T00:00:00Z",
+                "boost": 2
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::NumericRange {
+            field: published_field,
+            gte: 1483228800000000,
+            lte: 1485907199999999,
+            score: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_range_query_fractional_bounds() {
+        let mut schema = Schema::new();
+        let price_field = schema.add_field("price".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        // Only the integers within the bounds are included
+        let query = parse(&json!({
+            "price": {
+                "gt": 9.5,
+                "lt": 19.5
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(range_query(1.0f32, vec![
+            bound_query(price_field, RangeBound::Lower, i64::min_value(), 19),
+            bound_query(price_field, RangeBound::Upper, 10, i64::max_value()),
+        ])));
+    }
+
+    #[test]
+    fn test_range_query_runtime_field() {
+        let schema = Schema::new();
+        let runtime_mappings = RuntimeMappings::parse(&json!({
+            "price_with_tax": {
+                "type": "double",
+                "script": "emit(params._source.price * 1.2)"
+            }
+        })).unwrap();
+        let context = QueryBuildContext::new().set_runtime_mappings(&runtime_mappings);
+
+        // Runtime fields keep the bounds as they were given
+        let query = parse(&json!({
+            "price_with_tax": {
+                "gte": 10,
+                "lt": 19.5
+            }
+        })).and_then(|builder| Ok(builder.build(&context, &schema)));
+
+        assert_eq!(query, Ok(Query::Runtime {
+            field: runtime_mappings.get("price_with_tax").unwrap().field.clone(),
+            condition: RuntimeCondition::Range {
+                gte: Some(FieldValue::Float(10.0)),
+                gt: None,
+                lte: None,
+                lt: Some(FieldValue::Float(19.5)),
+            },
+            score: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_range_query_unknown_field() {
+        let schema = Schema::new();
+
+        let query = parse(&json!({
+            "price": {
+                "gte": 10,
+                "lt": 20
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_date_math() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"published\": {
+                \"gte\": \"now-1x\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_ip_range_query() {
+        let mut schema = Schema::new();
+        let addr_field = schema.add_field("addr".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"addr\": {
+                \"gte\": \"10.0.0.0\",
+                \"lt\": \"10.0.1.0\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::All{ score: 1.0f32 }),
+            filter: Box::new(Query::MultiTerm {
+                field: addr_field,
+                term_selector: MultiTermSelector::IpRange {
+                    gte: Term::from_ip(&"10.0.0.0".parse::<IpAddr>().unwrap()),
+                    lte: Term::from_ip(&"10.0.0.255".parse::<IpAddr>().unwrap()),
+                },
+                scorer: TermScorer::default(),
+            }),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_mixed_bounds() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"addr\": {
+                \"gte\": \"10.0.0.0\",
+                \"lte\": 100
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_relation() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"price\": {
+                \"gte\": 10,
+                \"relation\": \"overlaps\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_bound() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"price\": {
+                \"gte\": true
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+}
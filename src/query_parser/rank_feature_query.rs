@@ -0,0 +1,240 @@
+//! Parses "rank_feature" queries
+//!
+//! ```json
+//! {"rank_feature": {"field": "popularity", "saturation": {"pivot": 10}, "boost": 2.0}}
+//! ```
+//!
+//! Every document with a value in the field matches, scored by one of the "saturation",
+//! "log", "sigmoid" or "linear" functions of the value. This is usually put in the
+//! "should" clause of a bool query so the feature only adjusts the ranking.
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+use search::feature::FeatureFunction;
+
+use mapping::FieldType;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_boost, parse_string};
+
+
+/// The pivot of the saturation function if one isn't given
+const DEFAULT_PIVOT: f64 = 1.0;
+
+
+#[derive(Debug)]
+struct RankFeatureQueryBuilder {
+    field: String,
+    function: FeatureFunction,
+    boost: f32,
+}
+
+
+impl QueryBuilder for RankFeatureQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // Only rank feature fields store their value as a float
+        if let Some(field_mapping) = context.get_field_mapping(&self.field) {
+            if field_mapping.data_type != FieldType::RankFeature {
+                return Query::None;
+            }
+        }
+
+        Query::FeatureScore {
+            field: field,
+            function: self.function,
+            boost: self.boost,
+        }
+    }
+}
+
+
+/// Parses a parameter of a function, these must be positive
+fn parse_parameter(object: &Json, key: &'static str) -> Result<f64, QueryParseError> {
+    let object = object.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    match object.get(key) {
+        Some(value) => {
+            let value = value.as_f64().ok_or(QueryParseError::ExpectedFloat)?;
+
+            if value > 0.0 {
+                Ok(value)
+            } else {
+                Err(QueryParseError::InvalidValue)
+            }
+        }
+        None => Err(QueryParseError::ExpectedKey(key)),
+    }
+}
+
+
+/// Checks that a function only has the parameters it understands
+fn check_keys(object: &Json, keys: &[&str]) -> Result<(), QueryParseError> {
+    let object = object.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    for key in object.keys() {
+        if !keys.contains(&key.as_ref()) {
+            return Err(QueryParseError::UnrecognisedKey(key.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut field = None;
+    let mut function = None;
+    let mut boost = 1.0f32;
+
+    for (key, val) in object.iter() {
+        let parsed_function = match key.as_ref() {
+            "field" => {
+                field = Some(parse_string(val)?);
+                continue;
+            }
+            "boost" => {
+                boost = parse_boost(val)?;
+                continue;
+            }
+            "saturation" => {
+                check_keys(val, &["pivot"])?;
+
+                let pivot = match val.as_object().and_then(|object| object.get("pivot")) {
+                    Some(_) => parse_parameter(val, "pivot")?,
+                    None => DEFAULT_PIVOT,
+                };
+
+                FeatureFunction::Saturation{pivot: pivot}
+            }
+            "log" => {
+                check_keys(val, &["scaling_factor"])?;
+                FeatureFunction::Log{scaling_factor: parse_parameter(val, "scaling_factor")?}
+            }
+            "sigmoid" => {
+                check_keys(val, &["pivot", "exponent"])?;
+                FeatureFunction::Sigmoid{pivot: parse_parameter(val, "pivot")?, exponent: parse_parameter(val, "exponent")?}
+            }
+            "linear" => {
+                check_keys(val, &[])?;
+                FeatureFunction::Linear
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        };
+
+        // Only one function can be given
+        if function.is_some() {
+            return Err(QueryParseError::InvalidValue);
+        }
+
+        function = Some(parsed_function);
+    }
+
+    Ok(Box::new(RankFeatureQueryBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        function: function.unwrap_or(FeatureFunction::Saturation{pivot: DEFAULT_PIVOT}),
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+    use search::Query;
+    use search::schema::{Schema, FieldType, FieldId, FIELD_STORED};
+    use search::feature::FeatureFunction;
+
+    use index::metadata::IndexMetadata;
+    use mapping::parse::parse as parse_mapping;
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    fn build(json: Json) -> (FieldId, Result<Query, QueryParseError>) {
+        let mut schema = Schema::new();
+        let popularity_field = schema.add_field("popularity".to_string(), FieldType::F64, FIELD_STORED).unwrap();
+
+        (popularity_field, parse(&json).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema))))
+    }
+
+    #[test]
+    fn test_rank_feature_query() {
+        let (popularity_field, query) = build(json!({
+            "field": "popularity",
+            "saturation": {"pivot": 10},
+            "boost": 2.0
+        }));
+
+        assert_eq!(query, Ok(Query::FeatureScore {
+            field: popularity_field,
+            function: FeatureFunction::Saturation{pivot: 10.0},
+            boost: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_default_function() {
+        let (popularity_field, query) = build(json!({
+            "field": "popularity"
+        }));
+
+        assert_eq!(query, Ok(Query::FeatureScore {
+            field: popularity_field,
+            function: FeatureFunction::Saturation{pivot: 1.0},
+            boost: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_other_functions() {
+        let function = |json| {
+            match build(json).1 {
+                Ok(Query::FeatureScore{function, ..}) => Some(function),
+                _ => None,
+            }
+        };
+
+        assert_eq!(function(json!({"field": "popularity", "log": {"scaling_factor": 4}})), Some(FeatureFunction::Log{scaling_factor: 4.0}));
+        assert_eq!(function(json!({"field": "popularity", "sigmoid": {"pivot": 7, "exponent": 0.6}})), Some(FeatureFunction::Sigmoid{pivot: 7.0, exponent: 0.6}));
+        assert_eq!(function(json!({"field": "popularity", "linear": {}})), Some(FeatureFunction::Linear));
+    }
+
+    #[test]
+    fn test_invalid_functions() {
+        assert_eq!(parse(&json!({"field": "popularity", "linear": {}, "log": {"scaling_factor": 4}})).err(), Some(QueryParseError::InvalidValue));
+        assert_eq!(parse(&json!({"field": "popularity", "saturation": {"pivot": -1}})).err(), Some(QueryParseError::InvalidValue));
+        assert_eq!(parse(&json!({"field": "popularity", "sigmoid": {"pivot": 7}})).err(), Some(QueryParseError::ExpectedKey("exponent")));
+        assert_eq!(parse(&json!({"saturation": {}})).err(), Some(QueryParseError::ExpectedKey("field")));
+    }
+
+    #[test]
+    fn test_non_rank_feature_field() {
+        let mut schema = Schema::new();
+        schema.add_field("views".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        let mut index_metadata = IndexMetadata::default();
+        let mapping = parse_mapping(&json!({
+            "properties": {
+                "views": {
+                    "type": "integer"
+                }
+            }
+        })).unwrap().build(&index_metadata);
+        index_metadata.mappings.insert("test".to_string(), mapping);
+
+        let query = parse(&json!({
+            "field": "views"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+}
@@ -0,0 +1,174 @@
+//! Finds the parser for each type of query
+//!
+//! Embedders can add their own query types by registering a parser for them, these
+//! can then be used anywhere in the Query DSL, including inside compound queries:
+//!
+//! ```json
+//! {
+//!     "query": {
+//!         "filtered": {
+//!             "query": {"match": {"title": "hello"}},
+//!             "filter": {"in_region": {"region": "eu-west"}}
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Compound queries parse the queries inside them with `query_parser::parse`, so the
+//! parsers are registered for the whole process rather than passed around. Builtin
+//! query types can't be replaced.
+
+use std::collections::BTreeMap;
+use std::sync::{Once, RwLock};
+
+use serde_json::Value as Json;
+
+use query_parser::{QueryBuilder, QueryParseError};
+use query_parser::{match_query, multi_match_query, match_all_query, match_none_query, filtered_query,
+                   terms_query, term_query, prefix_query, wildcard_query, and_query, or_query, not_query,
+                   constant_score_query, type_query, ids_query, range_query, has_child_query,
+                   has_parent_query, knn_query, geo_shape_query, function_score_query,
+                   rank_feature_query, distance_feature_query, match_phrase_prefix_query};
+
+
+/// Parses the parameters of a query (the value under its type)
+pub type QueryParserFn = fn(&Json) -> Result<Box<QueryBuilder>, QueryParseError>;
+
+
+/// Query types that were registered by the embedder, created on first use by `custom_query_parsers`
+static mut CUSTOM_QUERY_PARSERS: *const RwLock<BTreeMap<String, QueryParserFn>> = 0 as *const _;
+static CUSTOM_QUERY_PARSERS_INIT: Once = Once::new();
+
+
+fn custom_query_parsers() -> &'static RwLock<BTreeMap<String, QueryParserFn>> {
+    unsafe {
+        CUSTOM_QUERY_PARSERS_INIT.call_once(|| {
+            CUSTOM_QUERY_PARSERS = Box::into_raw(Box::new(RwLock::new(BTreeMap::new())));
+        });
+
+        &*CUSTOM_QUERY_PARSERS
+    }
+}
+
+
+fn get_builtin_query_parser(query_name: &str) -> Option<QueryParserFn> {
+    match query_name {
+        "match" => Some(match_query::parse),
+        "match_phrase_prefix" => Some(match_phrase_prefix_query::parse),
+        "multi_match" => Some(multi_match_query::parse),
+        "match_all" => Some(match_all_query::parse),
+        "match_none" => Some(match_none_query::parse),
+        "filtered" => Some(filtered_query::parse),
+        "terms" => Some(terms_query::parse),
+        "in" => Some(terms_query::parse),
+        "term" => Some(term_query::parse),
+        "prefix" => Some(prefix_query::parse),
+        "wildcard" => Some(wildcard_query::parse),
+        "and" => Some(and_query::parse),
+        "or" => Some(or_query::parse),
+        "not" => Some(not_query::parse),
+        "constant_score" => Some(constant_score_query::parse),
+        "type" => Some(type_query::parse),
+        "ids" => Some(ids_query::parse),
+        "range" => Some(range_query::parse),
+        "has_child" => Some(has_child_query::parse),
+        "has_parent" => Some(has_parent_query::parse),
+        "knn" => Some(knn_query::parse),
+        "geo_shape" => Some(geo_shape_query::parse),
+        "function_score" => Some(function_score_query::parse),
+        "rank_feature" => Some(rank_feature_query::parse),
+        "distance_feature" => Some(distance_feature_query::parse),
+        _ => None
+    }
+}
+
+
+/// Registers the parser of a query type, replacing any that was registered with the same name
+///
+/// Returns an error if the name is taken by a builtin query type.
+pub fn register_query_parser(query_name: &str, parser: QueryParserFn) -> Result<(), String> {
+    if get_builtin_query_parser(query_name).is_some() {
+        return Err(format!("\"{}\" is a builtin query type and can't be replaced", query_name));
+    }
+
+    custom_query_parsers().write().unwrap().insert(query_name.to_string(), parser);
+    Ok(())
+}
+
+
+/// The names of the query types that were registered, in order
+pub fn custom_query_names() -> Vec<String> {
+    custom_query_parsers().read().unwrap().keys().cloned().collect()
+}
+
+
+pub fn get_query_parser(query_name: &str) -> Option<QueryParserFn> {
+    get_builtin_query_parser(query_name).or_else(|| {
+        // The lock is released before the parser is called, so parsers can parse the
+        // queries inside them
+        custom_query_parsers().read().unwrap().get(query_name).cloned()
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+
+    use search::{Term, Query};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuilder, QueryBuildContext, QueryParseError, parse};
+    use query_parser::utils::parse_string;
+
+    use super::{register_query_parser, custom_query_names, get_query_parser};
+
+    /// Matches documents with the exact title, eg {"title_is": "hello"}
+    #[derive(Debug)]
+    struct TitleIsQueryBuilder {
+        title: String,
+    }
+
+    impl QueryBuilder for TitleIsQueryBuilder {
+        fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+            match schema.get_field_by_name("title") {
+                Some(field) => Query::term(field, Term::from_string(&self.title)),
+                None => Query::None,
+            }
+        }
+    }
+
+    fn parse_title_is(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+        Ok(Box::new(TitleIsQueryBuilder {
+            title: parse_string(json)?,
+        }))
+    }
+
+    #[test]
+    fn test_custom_query() {
+        register_query_parser("title_is", parse_title_is).unwrap();
+        assert!(custom_query_names().contains(&"title_is".to_string()));
+
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({"title_is": "hello"})).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        assert_eq!(query, Ok(Query::term(title_field, Term::from_string("hello"))));
+
+        // It can be used inside builtin queries
+        let query = parse(&json!({"not": {"title_is": "hello"}})).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+        assert_eq!(query, Ok(Query::all().exclude(Query::term(title_field, Term::from_string("hello")))));
+    }
+
+    #[test]
+    fn test_cant_replace_builtin_query() {
+        assert!(register_query_parser("match", parse_title_is).is_err());
+        assert!(!custom_query_names().contains(&"match".to_string()));
+    }
+
+    #[test]
+    fn test_unregistered_query() {
+        assert!(get_query_parser("not_registered").is_none());
+        assert_eq!(parse(&json!({"not_registered": {}})).err(), Some(QueryParseError::UnrecognisedQueryType("not_registered".to_string())));
+    }
+}
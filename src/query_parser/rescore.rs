@@ -0,0 +1,207 @@
+//! Parses the "rescore" section of a search request
+
+use serde_json::Value as Json;
+use search::schema::Schema;
+use search::rescore::{Rescorer, RescoreMode};
+
+use query_parser::{QueryBuilder, QueryBuildContext, QueryParseError, parse as parse_query};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct RescorerBuilder {
+    window_size: Option<usize>,
+    query: Box<QueryBuilder>,
+    query_weight: Option<f32>,
+    rescore_query_weight: Option<f32>,
+    score_mode: Option<RescoreMode>,
+}
+
+
+#[derive(Debug)]
+pub struct RescoreBuilder {
+    rescorers: Vec<RescorerBuilder>,
+}
+
+
+impl RescoreBuilder {
+    pub fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Vec<Rescorer> {
+        self.rescorers.iter().map(|builder| {
+            let mut rescorer = Rescorer::new(builder.query.build(context, schema));
+
+            if let Some(window_size) = builder.window_size {
+                rescorer.window_size = window_size;
+            }
+            if let Some(query_weight) = builder.query_weight {
+                rescorer.query_weight = query_weight;
+            }
+            if let Some(rescore_query_weight) = builder.rescore_query_weight {
+                rescorer.rescore_query_weight = rescore_query_weight;
+            }
+            if let Some(score_mode) = builder.score_mode {
+                rescorer.score_mode = score_mode;
+            }
+
+            rescorer
+        }).collect()
+    }
+
+    /// The number of hits that need to be collected for the rescorers to see all of their window
+    pub fn max_window_size(&self) -> usize {
+        self.rescorers.iter().map(|builder| builder.window_size.unwrap_or(10)).max().unwrap_or(0)
+    }
+}
+
+
+fn parse_score_mode(json: &Json) -> Result<RescoreMode, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "total" => Ok(RescoreMode::Total),
+        "multiply" => Ok(RescoreMode::Multiply),
+        "avg" => Ok(RescoreMode::Avg),
+        "max" => Ok(RescoreMode::Max),
+        "min" => Ok(RescoreMode::Min),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+// {"window_size": 50, "query": {"rescore_query": {...}, "query_weight": 0.7, "rescore_query_weight": 1.2}}
+fn parse_rescorer(json: &Json) -> Result<RescorerBuilder, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut window_size = None;
+    let mut query_object = None;
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "window_size" => {
+                window_size = match value.as_u64() {
+                    Some(window_size) => Some(window_size as usize),
+                    None => return Err(QueryParseError::InvalidValue),
+                };
+            }
+            "query" => query_object = Some(value.as_object().ok_or(QueryParseError::ExpectedObject)?),
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone())),
+        }
+    }
+
+    let query_object = query_object.ok_or(QueryParseError::ExpectedKey("query"))?;
+
+    let mut query = None;
+    let mut query_weight = None;
+    let mut rescore_query_weight = None;
+    let mut score_mode = None;
+    for (key, value) in query_object.iter() {
+        match key.as_ref() {
+            "rescore_query" => query = Some(parse_query(value)?),
+            "query_weight" => query_weight = Some(parse_float(value)?),
+            "rescore_query_weight" => rescore_query_weight = Some(parse_float(value)?),
+            "score_mode" => score_mode = Some(parse_score_mode(value)?),
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone())),
+        }
+    }
+
+    Ok(RescorerBuilder {
+        window_size: window_size,
+        query: query.ok_or(QueryParseError::ExpectedKey("rescore_query"))?,
+        query_weight: query_weight,
+        rescore_query_weight: rescore_query_weight,
+        score_mode: score_mode,
+    })
+}
+
+
+/// Parses the "rescore" section, this can be a single rescorer or a list that are applied in order
+pub fn parse(json: &Json) -> Result<RescoreBuilder, QueryParseError> {
+    let rescorers = match *json {
+        Json::Array(ref array) => array.iter().map(parse_rescorer).collect::<Result<Vec<_>, _>>()?,
+        _ => vec![parse_rescorer(json)?],
+    };
+
+    Ok(RescoreBuilder {
+        rescorers: rescorers,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::rescore::{Rescorer, RescoreMode};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_rescore() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let rescore = parse(&json!({
+            "window_size": 50,
+            "query": {
+                "rescore_query": {
+                    "term": {"test": "foo"}
+                },
+                "query_weight": 0.7,
+                "rescore_query_weight": 1.5,
+                "score_mode": "max"
+            }
+        })).unwrap();
+
+        assert_eq!(rescore.max_window_size(), 50);
+        assert_eq!(rescore.build(&QueryBuildContext::new(), &schema), vec![
+            Rescorer {
+                window_size: 50,
+                query: Query::term(test_field, Term::from_string("foo")),
+                query_weight: 0.7f32,
+                rescore_query_weight: 1.5f32,
+                score_mode: RescoreMode::Max,
+            }
+        ]);
+    }
+
+    #[test]
+    fn test_rescore_defaults() {
+        let schema = Schema::new();
+
+        let rescore = parse(&json!([
+            {"query": {"rescore_query": {"match_all": {}}}},
+            {"window_size": 5, "query": {"rescore_query": {"match_none": {}}}}
+        ])).unwrap();
+
+        assert_eq!(rescore.max_window_size(), 10);
+        assert_eq!(rescore.build(&QueryBuildContext::new(), &schema), vec![
+            Rescorer::new(Query::all()),
+            Rescorer {
+                window_size: 5,
+                .. Rescorer::new(Query::None)
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_rescore_without_query() {
+        let rescore = parse(&json!({
+            "window_size": 50,
+            "query": {
+                "query_weight": 0.7
+            }
+        }));
+
+        assert_eq!(rescore.err(), Some(QueryParseError::ExpectedKey("rescore_query")));
+    }
+
+    #[test]
+    fn test_invalid_score_mode() {
+        let rescore = parse(&json!({
+            "query": {
+                "rescore_query": {"match_all": {}},
+                "score_mode": "foo"
+            }
+        }));
+
+        assert_eq!(rescore.err(), Some(QueryParseError::InvalidValue));
+    }
+}
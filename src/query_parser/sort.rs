@@ -0,0 +1,288 @@
+//! Parses the "sort" section of a search request
+
+use serde_json::Value as Json;
+use chrono::{DateTime, Utc};
+use search::document::FieldValue;
+use search::schema::{Schema, FieldType};
+use search::collectors::sorted::{SortField, SortOrder, SortMissing, SortMode};
+
+use query_parser::QueryParseError;
+use query_parser::utils::parse_string;
+
+
+#[derive(Debug)]
+struct SortFieldBuilder {
+    field: String,
+    order: Option<SortOrder>,
+    missing: Option<Json>,
+    mode: Option<SortMode>,
+}
+
+
+#[derive(Debug)]
+pub struct SortBuilder {
+    fields: Vec<SortFieldBuilder>,
+}
+
+
+/// Converts a substitute value for missing values into the type of the field
+fn missing_value_to_field_value(json: &Json, field_type: &FieldType) -> Option<FieldValue> {
+    match (field_type, json) {
+        (&FieldType::I64, &Json::Number(ref number)) => number.as_i64().map(FieldValue::Integer),
+        (&FieldType::F64, &Json::Number(ref number)) => number.as_f64().map(FieldValue::Float),
+        (&FieldType::Boolean, &Json::Bool(value)) => Some(FieldValue::Boolean(value)),
+        (&FieldType::DateTime, &Json::String(ref string)) => string.parse::<DateTime<Utc>>().ok().map(FieldValue::DateTime),
+        (&FieldType::Text, &Json::String(ref string)) | (&FieldType::PlainString, &Json::String(ref string)) => Some(FieldValue::String(string.clone())),
+        _ => None,
+    }
+}
+
+
+impl SortBuilder {
+    pub fn build(&self, schema: &Schema) -> Result<Vec<SortField>, QueryParseError> {
+        let mut sort = Vec::with_capacity(self.fields.len());
+
+        for field_builder in self.fields.iter() {
+            if field_builder.field == "_score" {
+                sort.push(SortField {
+                    order: field_builder.order.unwrap_or(SortOrder::Desc),
+                    .. SortField::score()
+                });
+                continue;
+            }
+
+            let field_id = schema.get_field_by_name(&field_builder.field).ok_or_else(|| QueryParseError::FieldDoesntExist(field_builder.field.clone()))?;
+            let field_info = schema.get(&field_id).expect("get_field_by_name returned an invalid FieldId");
+
+            let missing = match field_builder.missing {
+                Some(Json::String(ref string)) if string == "_last" => SortMissing::Last,
+                Some(Json::String(ref string)) if string == "_first" => SortMissing::First,
+                Some(ref value) => {
+                    let value = missing_value_to_field_value(value, &field_info.field_type).ok_or(QueryParseError::InvalidValue)?;
+                    SortMissing::Value(value)
+                }
+                None => SortMissing::Last,
+            };
+
+            sort.push(SortField {
+                missing: missing,
+                mode: field_builder.mode,
+                .. SortField::field(field_id, field_builder.order.unwrap_or(SortOrder::Asc))
+            });
+        }
+
+        Ok(sort)
+    }
+}
+
+
+fn parse_order(json: &Json) -> Result<SortOrder, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "asc" => Ok(SortOrder::Asc),
+        "desc" => Ok(SortOrder::Desc),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_mode(json: &Json) -> Result<SortMode, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "min" => Ok(SortMode::Min),
+        "max" => Ok(SortMode::Max),
+        "sum" => Ok(SortMode::Sum),
+        "avg" => Ok(SortMode::Avg),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_sort_field(json: &Json) -> Result<SortFieldBuilder, QueryParseError> {
+    match *json {
+        // "field"
+        Json::String(ref field) => {
+            Ok(SortFieldBuilder {
+                field: field.clone(),
+                order: None,
+                missing: None,
+                mode: None,
+            })
+        }
+
+        // {"field": "desc"} or {"field": {"order": "desc", "missing": "_first"}}
+        Json::Object(ref object) => {
+            let field = if object.len() == 1 {
+                object.keys().collect::<Vec<_>>()[0]
+            } else {
+                return Err(QueryParseError::ExpectedSingleKey)
+            };
+
+            let mut builder = SortFieldBuilder {
+                field: field.clone(),
+                order: None,
+                missing: None,
+                mode: None,
+            };
+
+            match *object.get(field).unwrap() {
+                Json::Object(ref inner_object) => {
+                    for (key, value) in inner_object.iter() {
+                        match key.as_ref() {
+                            "order" => builder.order = Some(parse_order(value)?),
+                            "missing" => builder.missing = Some(value.clone()),
+                            "mode" => builder.mode = Some(parse_mode(value)?),
+                            _ => return Err(QueryParseError::UnrecognisedKey(key.clone())),
+                        }
+                    }
+                }
+                ref value => builder.order = Some(parse_order(value)?),
+            }
+
+            Ok(builder)
+        }
+        _ => Err(QueryParseError::ExpectedObjectOrString),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<SortBuilder, QueryParseError> {
+    let fields = match *json {
+        Json::Array(ref array) => array.iter().map(parse_sort_field).collect::<Result<Vec<_>, _>>()?,
+        _ => vec![parse_sort_field(json)?],
+    };
+
+    Ok(SortBuilder {
+        fields: fields,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::document::FieldValue;
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::collectors::sorted::{SortField, SortOrder, SortMissing, SortMode};
+
+    use query_parser::QueryParseError;
+
+    use super::parse;
+
+    fn make_schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED | FIELD_STORED).unwrap();
+        schema.add_field("rating".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+        schema
+    }
+
+    #[test]
+    fn test_sort_field_names() {
+        let schema = make_schema();
+        let title_field = schema.get_field_by_name("title").unwrap();
+
+        let sort = parse(&serde_json::from_str("[\"title\", \"_score\"]").unwrap()).and_then(|builder| builder.build(&schema));
+
+        assert_eq!(sort, Ok(vec![
+            SortField::field(title_field, SortOrder::Asc),
+            SortField::score(),
+        ]));
+    }
+
+    #[test]
+    fn test_sort_order() {
+        let schema = make_schema();
+        let rating_field = schema.get_field_by_name("rating").unwrap();
+
+        let sort = parse(&serde_json::from_str("{\"rating\": \"desc\"}").unwrap()).and_then(|builder| builder.build(&schema));
+
+        assert_eq!(sort, Ok(vec![
+            SortField::field(rating_field, SortOrder::Desc),
+        ]));
+    }
+
+    #[test]
+    fn test_sort_missing_first() {
+        let schema = make_schema();
+        let rating_field = schema.get_field_by_name("rating").unwrap();
+
+        let sort = parse(&serde_json::from_str("
+        {
+            \"rating\": {
+                \"order\": \"desc\",
+                \"missing\": \"_first\"
+            }
+        }
+        ").unwrap()).and_then(|builder| builder.build(&schema));
+
+        assert_eq!(sort, Ok(vec![
+            SortField {
+                by: SortField::field(rating_field, SortOrder::Desc).by,
+                order: SortOrder::Desc,
+                missing: SortMissing::First,
+                mode: None,
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_sort_missing_value() {
+        let schema = make_schema();
+        let rating_field = schema.get_field_by_name("rating").unwrap();
+
+        let sort = parse(&serde_json::from_str("{\"rating\": {\"missing\": 3}}").unwrap()).and_then(|builder| builder.build(&schema));
+
+        assert_eq!(sort, Ok(vec![
+            SortField {
+                missing: SortMissing::Value(FieldValue::Integer(3)),
+                .. SortField::field(rating_field, SortOrder::Asc)
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_sort_missing_value_wrong_type() {
+        let schema = make_schema();
+
+        let sort = parse(&serde_json::from_str("{\"rating\": {\"missing\": \"foo\"}}").unwrap()).and_then(|builder| builder.build(&schema));
+
+        assert_eq!(sort, Err(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_sort_mode() {
+        let schema = make_schema();
+        let rating_field = schema.get_field_by_name("rating").unwrap();
+
+        let sort = parse(&serde_json::from_str("{\"rating\": {\"order\": \"desc\", \"mode\": \"avg\"}}").unwrap()).and_then(|builder| builder.build(&schema));
+
+        assert_eq!(sort, Ok(vec![
+            SortField {
+                mode: Some(SortMode::Avg),
+                .. SortField::field(rating_field, SortOrder::Desc)
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_invalid_sort_mode() {
+        let sort = parse(&serde_json::from_str("{\"rating\": {\"mode\": \"median\"}}").unwrap());
+
+        assert_eq!(sort.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_sort_unknown_field() {
+        let schema = make_schema();
+
+        let sort = parse(&serde_json::from_str("\"foo\"").unwrap()).and_then(|builder| builder.build(&schema));
+
+        assert_eq!(sort, Err(QueryParseError::FieldDoesntExist("foo".to_string())));
+    }
+
+    #[test]
+    fn test_invalid_order() {
+        let sort = parse(&serde_json::from_str("{\"rating\": \"up\"}").unwrap());
+
+        assert_eq!(sort.err(), Some(QueryParseError::InvalidValue));
+    }
+}
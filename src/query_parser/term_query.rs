@@ -1,11 +1,15 @@
 //! Parses "term" queries
 
+use std::str;
+
 use serde_json::Value as Json;
-use search::{Term, Query, TermScorer};
+use search::{Term, Query, MultiTermSelector, TermScorer};
 use search::schema::Schema;
 
+use mapping::FieldType;
+
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::{parse_float, json_value_to_term};
+use query_parser::utils::{parse_boost, parse_boolean, json_value_to_term, build_ip_query};
 
 
 #[derive(Debug)]
@@ -13,15 +17,59 @@ struct TermQueryBuilder {
     field: String,
     term: Term,
     boost: f32,
+
+    /// The value as it was given, runtime fields convert it into the type of their values
+    value: Json,
+
+    /// Set if the value was given as a string, only strings are normalized
+    is_string: bool,
+
+    /// Set if the value is a string that should be matched regardless of case
+    case_insensitive: bool,
 }
 
 
 impl QueryBuilder for TermQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
-        let query = Query::Term {
-            field: schema.get_field_by_name(&self.field).unwrap(),
-            term: self.term.clone(),
-            scorer: TermScorer::default(),
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        // Runtime fields aren't indexed, their values are computed and compared for each document
+        if let Some(runtime_mapping) = context.get_runtime_mapping(&self.field) {
+            return runtime_mapping.build_terms_query(&[self.value.clone()], self.boost);
+        }
+
+        let field = schema.get_field_by_name(&self.field).unwrap();
+        let field_mapping = context.get_field_mapping(&self.field);
+        let is_ip_field = field_mapping.map(|field_mapping| field_mapping.data_type == FieldType::Ip).unwrap_or(false);
+
+        // Keyword fields may have normalized their values when they were indexed
+        let term = match field_mapping {
+            Some(field_mapping) if self.is_string => field_mapping.normalize_term(&self.term),
+            _ => self.term.clone(),
+        };
+
+        let query = if is_ip_field {
+            // Convert the address (or CIDR block) into the encoding used by IP terms
+            match str::from_utf8(term.as_bytes()) {
+                Ok(value) => build_ip_query(field, value),
+                Err(_) => Query::None,
+            }
+        } else if self.case_insensitive {
+            // Normalize both the value and the terms of the field when looking it up
+            match str::from_utf8(term.as_bytes()) {
+                Ok(value) => {
+                    Query::MultiTerm {
+                        field: field,
+                        term_selector: MultiTermSelector::case_insensitive(value),
+                        scorer: TermScorer::default(),
+                    }
+                }
+                Err(_) => Query::None,
+            }
+        } else {
+            Query::Term {
+                field: field,
+                term: term,
+                scorer: TermScorer::default(),
+            }
         };
 
         // Add boost
@@ -43,7 +91,10 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 
     // Get configuration
     let mut term: Option<Term> = None;
+    let mut value = object.clone();
     let mut boost = 1.0f32;
+    let mut case_insensitive = false;
+    let mut is_string = object.is_string();
 
     match *object {
         Json::Object(ref inner_object) => {
@@ -51,13 +102,18 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                 match key.as_ref() {
                     "value" => {
                         term = json_value_to_term(val);
+                        value = val.clone();
+                        is_string = val.is_string();
 
                         if term == None {
                             return Err(QueryParseError::InvalidValue);
                         }
                     }
                     "boost" => {
-                        boost = parse_float(val)?;
+                        boost = parse_boost(val)?;
+                    }
+                    "case_insensitive" => {
+                        case_insensitive = parse_boolean(val)?;
                     }
                     _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
                 }
@@ -72,6 +128,11 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                 field: field_name.clone(),
                 term: term,
                 boost: boost,
+                value: value,
+                is_string: is_string,
+
+                // Only strings have a case
+                case_insensitive: case_insensitive && is_string,
             }))
         }
         None => Err(QueryParseError::ExpectedKey("value"))
@@ -83,13 +144,33 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 mod tests {
     use serde_json;
 
-    use search::{Term, Query, TermScorer};
+    use std::net::IpAddr;
+
+    use search::{Term, Query, MultiTermSelector, TermScorer};
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::document::FieldValue;
+    use search::runtime::RuntimeCondition;
+    use index::metadata::IndexMetadata;
+    use mapping::runtime::RuntimeMappings;
+    use mapping::parse::parse as parse_mapping;
 
     use query_parser::{QueryBuildContext, QueryParseError};
 
     use super::parse;
 
+    fn make_ip_index_metadata() -> IndexMetadata {
+        let mut index_metadata = IndexMetadata::default();
+        let mapping = parse_mapping(&json!({
+            "properties": {
+                "addr": {
+                    "type": "ip"
+                }
+            }
+        })).unwrap().build(&index_metadata);
+        index_metadata.mappings.insert("test".to_string(), mapping);
+        index_metadata
+    }
+
     #[test]
     fn test_term_query() {
         let mut schema = Schema::new();
@@ -190,6 +271,162 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn test_case_insensitive() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"Bar\",
+                \"case_insensitive\": true
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::CaseInsensitive("bar".to_string()),
+            scorer: TermScorer::default(),
+        }));
+
+        // Numbers don't have a case
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": 123,
+                \"case_insensitive\": true
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: foo_field,
+            term: Term::from_integer(123),
+            scorer: TermScorer::default(),
+        }));
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"Bar\",
+                \"case_insensitive\": \"yes\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedBoolean));
+    }
+
+    #[test]
+    fn test_normalized_field() {
+        let mut schema = Schema::new();
+        let tag_field = schema.add_field("tag".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let mut index_metadata = IndexMetadata::default();
+        let mapping = parse_mapping(&json!({
+            "properties": {
+                "tag": {
+                    "type": "string",
+                    "index": "not_analyzed",
+                    "normalizer": "lowercase"
+                }
+            }
+        })).unwrap().build(&index_metadata);
+        index_metadata.mappings.insert("test".to_string(), mapping);
+
+        // The value is normalized the same way as the values of the field were
+        let query = parse(&json!({
+            "tag": "Rust"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: tag_field,
+            term: Term::from_string("rust"),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_runtime_field() {
+        let schema = Schema::new();
+        let runtime_mappings = RuntimeMappings::parse(&json!({
+            "rating": {"type": "long"}
+        })).unwrap();
+        let context = QueryBuildContext::new().set_runtime_mappings(&runtime_mappings);
+
+        // The value is converted into the type of the field, rather than a term
+        let query = parse(&json!({
+            "rating": {
+                "value": "5",
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&context, &schema)));
+
+        assert_eq!(query, Ok(Query::Runtime {
+            field: runtime_mappings.get("rating").unwrap().field.clone(),
+            condition: RuntimeCondition::Terms(vec![FieldValue::Integer(5)]),
+            score: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_ip_field() {
+        let mut schema = Schema::new();
+        let addr_field = schema.add_field("addr".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+        let index_metadata = make_ip_index_metadata();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"addr\": \"192.168.0.1\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: addr_field,
+            term: Term::from_ip(&"192.168.0.1".parse::<IpAddr>().unwrap()),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_ip_field_cidr() {
+        let mut schema = Schema::new();
+        let addr_field = schema.add_field("addr".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+        let index_metadata = make_ip_index_metadata();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"addr\": \"10.1.2.3/8\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: addr_field,
+            term_selector: MultiTermSelector::IpRange {
+                gte: Term::from_ip(&"10.0.0.0".parse::<IpAddr>().unwrap()),
+                lte: Term::from_ip(&"10.255.255.255".parse::<IpAddr>().unwrap()),
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_ip_field_invalid_address() {
+        let mut schema = Schema::new();
+        schema.add_field("addr".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+        let index_metadata = make_ip_index_metadata();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"addr\": \"10.0.0.0/33\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
     #[test]
     fn test_gives_error_for_incorrect_type() {
         // Array
@@ -1,55 +1,152 @@
-//! Parses "match" queries
+//! Parses "terms" queries
+
+use std::str;
 
 use serde_json::Value as Json;
 use search::{Term, Query, TermScorer};
 use search::schema::Schema;
 
+use mapping::FieldType;
+
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::json_value_to_term;
+use query_parser::utils::{parse_string, parse_boost, json_value_to_term, build_ip_query};
+
+
+/// Where the terms of the query come from
+#[derive(Debug)]
+enum TermsSource {
+    /// The terms, and the values as they were given for runtime fields to convert
+    Terms(Vec<Term>, Vec<Json>),
+
+    /// The values of a field in another document, eg {"index": "users", "id": "2", "path": "friends"}
+    Lookup {
+        index: String,
+        id: String,
+        path: String,
+    },
+}
+
 
 #[derive(Debug)]
 struct TermsQueryBuilder {
     field: String,
-    terms: Vec<Term>,
+    source: TermsSource,
+    boost: f32,
 }
 
 
 impl QueryBuilder for TermsQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        // Fetch the terms of lookups, if the lookup fails nothing is matched
+        let looked_up_terms;
+        let terms = match self.source {
+            TermsSource::Terms(ref terms, _) => terms,
+            TermsSource::Lookup { ref index, ref id, ref path } => {
+                looked_up_terms = match context.terms_lookup.map(|terms_lookup| terms_lookup.lookup_terms(index, id, path)) {
+                    Some(Ok(terms)) => terms,
+                    _ => return Query::None,
+                };
+
+                &looked_up_terms
+            }
+        };
+
+        // Runtime fields aren't indexed, their values are computed and compared for each document
+        if let Some(runtime_mapping) = context.get_runtime_mapping(&self.field) {
+            let values = match self.source {
+                TermsSource::Terms(_, ref values) => values.clone(),
+                TermsSource::Lookup { .. } => {
+                    terms.iter().filter_map(|term| str::from_utf8(term.as_bytes()).ok()).map(|value| Json::String(value.to_string())).collect()
+                }
+            };
+
+            return runtime_mapping.build_terms_query(&values, self.boost);
+        }
+
+        let field = schema.get_field_by_name(&self.field).unwrap();
+        let field_mapping = context.get_field_mapping(&self.field);
+        let is_ip_field = field_mapping.map(|field_mapping| field_mapping.data_type == FieldType::Ip).unwrap_or(false);
+
         // Create a term query for each token
         let mut queries = Vec::new();
-        for term in self.terms.iter() {
+        for term in terms.iter() {
+            if is_ip_field {
+                // Convert the address (or CIDR block) into the encoding used by IP terms
+                if let Ok(value) = str::from_utf8(term.as_bytes()) {
+                    queries.push(build_ip_query(field, value));
+                }
+                continue;
+            }
+
+            // Keyword fields may have normalized their values when they were indexed
             queries.push(Query::Term {
-                field: schema.get_field_by_name(&self.field).unwrap(),
-                term: term.clone(),
+                field: field,
+                term: field_mapping.map(|field_mapping| field_mapping.normalize_term(term)).unwrap_or_else(|| term.clone()),
                 scorer: TermScorer::default(),
             });
         }
 
-        Query::Disjunction { queries: queries }
+        let query = Query::Disjunction { queries: queries };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+fn parse_lookup(json: &Json) -> Result<TermsSource, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut index = None;
+    let mut id = None;
+    let mut path = None;
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "index" => index = Some(parse_string(value)?),
+            "id" => id = Some(parse_string(value)?),
+            "path" => path = Some(parse_string(value)?),
+
+            // Documents are only looked up by id so the type isn't needed
+            "type" => {}
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone())),
+        }
     }
+
+    Ok(TermsSource::Lookup {
+        index: index.ok_or(QueryParseError::ExpectedKey("index"))?,
+        id: id.ok_or(QueryParseError::ExpectedKey("id"))?,
+        path: path.ok_or(QueryParseError::ExpectedKey("path"))?,
+    })
 }
 
 
 pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
 
-    let field_name = if object.len() == 1 {
-        object.keys().collect::<Vec<_>>()[0]
+    // The field is the only key other than "boost"
+    let field_names = object.keys().filter(|key| key.as_str() != "boost").collect::<Vec<_>>();
+    let field_name = if field_names.len() == 1 {
+        field_names[0]
     } else {
         return Err(QueryParseError::ExpectedSingleKey);
     };
 
+    let boost = match object.get("boost") {
+        Some(inner) => parse_boost(inner)?,
+        None => 1.0f32,
+    };
+
     // Get configuration
-    let terms: Vec<Term> = if let &Json::Array(ref arr) = object.get(field_name).unwrap() {
-        arr.iter().filter_map(|term| json_value_to_term(&term)).collect()
-    } else {
-        return Err(QueryParseError::ExpectedArray);
+    let source = match *object.get(field_name).unwrap() {
+        Json::Array(ref arr) => TermsSource::Terms(arr.iter().filter_map(|term| json_value_to_term(&term)).collect(), arr.clone()),
+        Json::Object(_) => parse_lookup(object.get(field_name).unwrap())?,
+        _ => return Err(QueryParseError::ExpectedArray),
     };
 
     Ok(Box::new(TermsQueryBuilder {
         field: field_name.clone(),
-        terms: terms,
+        source: source,
+        boost: boost,
     }))
 }
 
@@ -60,11 +157,24 @@ mod tests {
 
     use search::{Term, Query, TermScorer};
 
-    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::{QueryBuildContext, QueryParseError, TermsLookup};
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
 
     use super::parse;
 
+    #[derive(Debug)]
+    struct TestTermsLookup;
+
+    impl TermsLookup for TestTermsLookup {
+        fn lookup_terms(&self, index: &str, id: &str, path: &str) -> Result<Vec<Term>, String> {
+            match (index, id, path) {
+                ("users", "2", "friends") => Ok(vec![Term::from_string("bar"), Term::from_string("baz")]),
+                ("users", _, _) => Ok(Vec::new()),
+                _ => Err(format!("index not found: {}", index)),
+            }
+        }
+    }
+
     #[test]
     fn test_terms_query() {
         let mut schema = Schema::new();
@@ -92,6 +202,57 @@ mod tests {
         }))
     }
 
+    #[test]
+    fn test_terms_lookup() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let terms_lookup = TestTermsLookup;
+
+        let query = parse(&json!({
+            "foo": {"index": "users", "id": "2", "path": "friends"}
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_terms_lookup(&terms_lookup), &schema)));
+
+        assert_eq!(query, Ok(Query::Disjunction {
+            queries: vec![
+                Query::Term {
+                    field: foo_field,
+                    term: Term::from_string("bar"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: foo_field,
+                    term: Term::from_string("baz"),
+                    scorer: TermScorer::default(),
+                }
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_terms_lookup_failure_matches_nothing() {
+        let mut schema = Schema::new();
+        schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let terms_lookup = TestTermsLookup;
+
+        let builder = parse(&json!({
+            "foo": {"index": "missing", "id": "2", "path": "friends"}
+        })).unwrap();
+
+        assert_eq!(builder.build(&QueryBuildContext::new().set_terms_lookup(&terms_lookup), &schema), Query::None);
+
+        // Lookups can't be done without a terms lookup in the context
+        assert_eq!(builder.build(&QueryBuildContext::new(), &schema), Query::None);
+    }
+
+    #[test]
+    fn test_terms_lookup_gives_error_for_missing_key() {
+        let query = parse(&json!({
+            "foo": {"index": "users", "id": "2"}
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("path")));
+    }
+
     #[test]
     fn test_gives_error_for_incorrect_type() {
         // Array
@@ -129,7 +290,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("query".to_string())));
 
         // String
         let query = parse(&serde_json::from_str("
@@ -162,4 +323,42 @@ mod tests {
 
         assert_eq!(query.err(), Some(QueryParseError::ExpectedSingleKey));
     }
+
+    #[test]
+    fn test_terms_query_with_boost() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "foo": ["bar"],
+            "boost": 2.0
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Disjunction {
+            queries: vec![
+                Query::Term {
+                    field: foo_field,
+                    term: Term::from_string("bar"),
+                    scorer: TermScorer::default_with_boost(2.0f32),
+                },
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_boost() {
+        let query = parse(&json!({
+            "foo": ["bar"],
+            "boost": "high"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+
+        let query = parse(&json!({
+            "foo": ["bar"],
+            "boost": -2
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidBoost));
+    }
 }
@@ -0,0 +1,153 @@
+//! Parses "type" queries
+
+use serde_json::Value as Json;
+use search::{Term, Query};
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_boost};
+
+
+#[derive(Debug)]
+struct TypeQueryBuilder {
+    doc_type: String,
+    boost: f32,
+}
+
+
+impl QueryBuilder for TypeQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let query = match schema.get_field_by_name("_type") {
+            Some(field) => Query::Filter {
+                query: Box::new(Query::all()),
+                filter: Box::new(Query::term(field, Term::from_string(&self.doc_type))),
+            },
+            None => return Query::None,
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut doc_type: Option<String> = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match &key[..] {
+            "value" => {
+                doc_type = Some(parse_string(value)?);
+            }
+            "boost" => {
+                boost = parse_boost(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    match doc_type {
+        Some(doc_type) => {
+            Ok(Box::new(TypeQueryBuilder {
+                doc_type: doc_type,
+                boost: boost,
+            }))
+        }
+        None => Err(QueryParseError::ExpectedKey("value"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Term, Query};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_type_query() {
+        let mut schema = Schema::new();
+        let type_field = schema.add_field("_type".to_string(), FieldType::Text, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"value\": \"foo\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::all()),
+            filter: Box::new(Query::term(type_field, Term::from_string("foo"))),
+        }));
+    }
+
+    #[test]
+    fn test_type_query_no_type_field() {
+        let schema = Schema::new();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"value\": \"foo\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_value() {
+        let query = parse(&serde_json::from_str("
+        {
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("value")));
+    }
+
+    #[test]
+    fn test_gives_error_for_unrecognised_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"value\": \"foo\",
+            \"hello\": \"world\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+    }
+
+    #[test]
+    fn test_type_query_with_boost() {
+        let mut schema = Schema::new();
+        let type_field = schema.add_field("_type".to_string(), FieldType::Text, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&json!({
+            "value": "foo",
+            "boost": 2
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::All { score: 2.0f32 }),
+            filter: Box::new(Query::term(type_field, Term::from_string("foo"))),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_incorrect_boost_type() {
+        let query = parse(&json!({
+            "value": "foo",
+            "boost": "2"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+    }
+}
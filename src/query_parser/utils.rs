@@ -1,5 +1,9 @@
+use std::net::IpAddr;
+
 use serde_json::Value as Json;
-use search::term::Term;
+use search::{Query, MultiTermSelector, TermScorer};
+use search::term::{Term, ip_to_bytes};
+use search::schema::{Schema, FieldId, FieldType, FIELD_INDEXED};
 
 use query_parser::QueryParseError;
 
@@ -12,6 +16,14 @@ pub fn parse_string(json: &Json) -> Result<String, QueryParseError> {
 }
 
 
+pub fn parse_boolean(json: &Json) -> Result<bool, QueryParseError> {
+    match *json {
+        Json::Bool(value) => Ok(value),
+        _ => Err(QueryParseError::ExpectedBoolean),
+    }
+}
+
+
 pub fn parse_float(json: &Json) -> Result<f32, QueryParseError> {
     match json {
         &Json::Number(ref number) => {
@@ -25,13 +37,37 @@ pub fn parse_float(json: &Json) -> Result<f32, QueryParseError> {
 }
 
 
-#[derive(Debug)]
+/// Parses the "boost" of a query
+///
+/// Boosts multiply scores so they must be finite and can't be negative.
+pub fn parse_boost(json: &Json) -> Result<f32, QueryParseError> {
+    let boost = parse_float(json)?;
+
+    if !boost.is_finite() || boost < 0.0 {
+        return Err(QueryParseError::InvalidBoost);
+    }
+
+    Ok(boost)
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Operator {
     Or,
     And,
 }
 
 
+impl Operator {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Operator::Or => "or",
+            Operator::And => "and",
+        }
+    }
+}
+
+
 pub fn parse_operator(json: &Json) -> Result<Operator, QueryParseError> {
     match *json {
         Json::String(ref value) => {
@@ -54,12 +90,86 @@ pub fn parse_field_and_boost(json: &Json) -> Result<(String, f32), QueryParseErr
         return Ok((string.clone(), 1.0f32));
     } else {
         let field_name = split[0].to_owned();
-        let boost: f32 = split[1].parse().unwrap_or(1.0f32);
+        let boost = match split[1].parse::<f32>() {
+            Ok(boost) if split.len() == 2 && boost.is_finite() && boost >= 0.0 => boost,
+            _ => return Err(QueryParseError::InvalidBoost),
+        };
         return Ok((field_name, boost));
     }
 }
 
 
+/// Checks if a field name matches a pattern, where "*" matches any number of characters
+pub fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !name.starts_with(first) {
+        return false;
+    }
+
+    let mut rest = &name[first.len()..];
+    let mut parts = parts.collect::<Vec<_>>();
+    let last = match parts.pop() {
+        Some(last) => last,
+        None => return rest.is_empty(),  // No wildcards
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+
+/// Adds a field to a list of fields and boosts, multiplying the boosts if it's already there
+fn add_field_boost(fields: &mut Vec<(String, f32)>, field_name: &str, boost: f32) {
+    match fields.iter_mut().find(|field| field.0 == field_name) {
+        Some(field) => field.1 *= boost,
+        None => fields.push((field_name.to_string(), boost)),
+    }
+}
+
+
+/// Expands the wildcards in a list of fields and boosts (eg, "title*^3")
+///
+/// Patterns match the indexed text fields in the schema. Fields starting with an
+/// underscore (like "_all") are only matched by patterns that start with one too.
+/// A field that is listed more than once is searched once, with the boosts multiplied.
+/// Fields that aren't in the schema (nothing has been indexed into them yet) are left out.
+pub fn expand_field_patterns(fields: &[(String, f32)], schema: &Schema) -> Vec<(String, f32)> {
+    let mut expanded_fields = Vec::new();
+
+    for &(ref field_pattern, boost) in fields.iter() {
+        if !field_pattern.contains('*') {
+            if schema.get_field_by_name(field_pattern).is_some() {
+                add_field_boost(&mut expanded_fields, field_pattern, boost);
+            }
+            continue;
+        }
+
+        let mut field_names = schema.values().filter(|field_info| {
+            let is_text = field_info.field_type == FieldType::Text || field_info.field_type == FieldType::PlainString;
+            is_text && field_info.field_flags.contains(FIELD_INDEXED)
+        }).map(|field_info| field_info.name()).filter(|field_name| {
+            (!field_name.starts_with('_') || field_pattern.starts_with('_')) && matches_wildcard(field_pattern, field_name)
+        }).collect::<Vec<_>>();
+
+        // Sort for a predictable order, the schema is a hash map
+        field_names.sort();
+
+        for field_name in field_names {
+            add_field_boost(&mut expanded_fields, field_name, boost);
+        }
+    }
+
+    expanded_fields
+}
+
+
 pub fn json_value_to_term(json: &Json) -> Option<Term> {
     match json {
         &Json::String(ref string) => Some(Term::from_string(string)),
@@ -75,3 +185,70 @@ pub fn json_value_to_term(json: &Json) -> Option<Term> {
         &Json::Object(_) => None,
     }
 }
+
+
+/// Parses an IP address or a CIDR block (eg "10.0.0.0/8") into the first and last
+/// addresses it covers, in the encoding used by IP terms
+pub fn parse_ip_range(string: &str) -> Option<([u8; 16], [u8; 16])> {
+    let mut parts = string.splitn(2, '/');
+
+    let ip = match parts.next().map(|ip| ip.parse::<IpAddr>()) {
+        Some(Ok(ip)) => ip,
+        _ => return None,
+    };
+
+    // Number of leading bits that are fixed. IPv4 addresses are mapped into the last 32 bits
+    let prefix_len = match parts.next() {
+        Some(prefix_len) => {
+            let prefix_len = match prefix_len.parse::<u32>() {
+                Ok(prefix_len) => prefix_len,
+                Err(_) => return None,
+            };
+
+            let (max_prefix_len, offset) = if ip.is_ipv4() { (32, 96) } else { (128, 0) };
+            if prefix_len > max_prefix_len {
+                return None;
+            }
+
+            prefix_len + offset
+        }
+        None => 128,
+    };
+
+    let mut first = ip_to_bytes(&ip);
+    let mut last = first;
+
+    for i in 0..16 {
+        let fixed_bits = prefix_len.saturating_sub(i as u32 * 8).min(8);
+        let mask = if fixed_bits == 0 { 0u8 } else { 0xffu8 << (8 - fixed_bits) };
+
+        first[i] &= mask;
+        last[i] |= !mask;
+    }
+
+    Some((first, last))
+}
+
+
+/// Builds a query for an IP field from an address or a CIDR block
+///
+/// Values that aren't valid addresses can't match anything.
+pub fn build_ip_query(field: FieldId, value: &str) -> Query {
+    match parse_ip_range(value) {
+        Some((first, last)) => {
+            if first == last {
+                Query::term(field, Term::from_bytes(&first))
+            } else {
+                Query::MultiTerm {
+                    field: field,
+                    term_selector: MultiTermSelector::IpRange {
+                        gte: Term::from_bytes(&first),
+                        lte: Term::from_bytes(&last),
+                    },
+                    scorer: TermScorer::default(),
+                }
+            }
+        }
+        None => Query::None,
+    }
+}
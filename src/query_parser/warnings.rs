@@ -0,0 +1,182 @@
+//! Collects warnings about the parts of a request that were ignored
+//!
+//! Elasticsearch supports many parameters that rusticsearch doesn't. Rather than
+//! ignoring these silently, they're recorded while the request is parsed and returned
+//! to the client, both as `Warning` headers and in a "#warnings" section of the response.
+//!
+//! In strict mode, the first unsupported parameter is an error instead.
+
+use serde_json::{Map, Value as Json};
+
+use json_pointer;
+
+
+/// The warn-code of warnings about the request ("Miscellaneous persistent warning")
+const WARNING_CODE: u16 = 299;
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseWarnings {
+    messages: Vec<String>,
+
+    /// The warnings about unsupported parameters, with the pointer to the key in the body if
+    /// the parameter was given there. These are errors in strict mode
+    unsupported: Vec<(String, Option<String>)>,
+}
+
+
+impl ParseWarnings {
+    pub fn new() -> ParseWarnings {
+        ParseWarnings::default()
+    }
+
+    /// Records a warning, warnings that have already been recorded are only kept once
+    pub fn add(&mut self, message: String) {
+        if !self.messages.contains(&message) {
+            self.messages.push(message);
+        }
+    }
+
+    /// Records that a parameter isn't supported and was ignored
+    pub fn unsupported_parameter(&mut self, section: &str, name: &str) {
+        self.add_unsupported(format!("[{}] unsupported parameter [{}] was ignored", section, name), None);
+    }
+
+    /// Records that a key of the body isn't supported and was ignored
+    pub fn unsupported_key(&mut self, section: &str, name: &str, pointer: String) {
+        self.add_unsupported(format!("[{}] unsupported parameter [{}] was ignored", section, name), Some(pointer));
+    }
+
+    fn add_unsupported(&mut self, message: String, pointer: Option<String>) {
+        if !self.messages.contains(&message) {
+            self.unsupported.push((message.clone(), pointer));
+            self.messages.push(message);
+        }
+    }
+
+    /// Records that a feature is deprecated, it still works but may be removed
+    pub fn deprecated(&mut self, message: &str) {
+        self.add(format!("[deprecation] {}", message));
+    }
+
+    /// Records each key of the object that isn't in the list of supported keys
+    ///
+    /// "pointer" points to the object in the body, it's empty for the body itself.
+    pub fn check_keys(&mut self, section: &str, pointer: &str, object: &Map<String, Json>, supported_keys: &[&str]) {
+        for key in object.keys() {
+            if !supported_keys.contains(&key.as_str()) {
+                self.unsupported_key(section, key, json_pointer::join(pointer, key));
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn messages(&self) -> &[String] {
+        &self.messages
+    }
+
+    /// The value of a `Warning` header for each warning (see RFC 7234, section 5.5)
+    ///
+    /// For example: `299 rusticsearch-0.1.0 "[search] unsupported parameter [timeout] was ignored"`
+    pub fn header_values(&self) -> Vec<Vec<u8>> {
+        self.messages.iter().map(|message| {
+            let quoted = message.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("{} rusticsearch-{} \"{}\"", WARNING_CODE, env!("CARGO_PKG_VERSION"), quoted).into_bytes()
+        }).collect()
+    }
+
+    /// The "#warnings" section of a response
+    pub fn to_json(&self) -> Json {
+        json!(self.messages)
+    }
+
+    /// The error of a request made in strict mode, None if all of its parameters are supported
+    ///
+    /// Warnings that aren't about unsupported parameters, such as deprecations, aren't errors.
+    pub fn to_strict_error(&self) -> Option<Json> {
+        self.unsupported.first().map(|&(ref message, ref pointer)| {
+            match *pointer {
+                Some(ref pointer) => json!({"message": message, "pointer": pointer}),
+                None => json!({"message": message}),
+            }
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::ParseWarnings;
+
+    #[test]
+    fn test_check_keys() {
+        let mut warnings = ParseWarnings::new();
+        let body = json!({"query": {}, "timeout": "1s", "track_total_hits": true});
+        warnings.check_keys("search", "", body.as_object().unwrap(), &["query"]);
+
+        assert_eq!(warnings.messages(), &[
+            "[search] unsupported parameter [timeout] was ignored".to_string(),
+            "[search] unsupported parameter [track_total_hits] was ignored".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_no_warnings() {
+        let mut warnings = ParseWarnings::new();
+        let body = json!({"query": {}});
+        warnings.check_keys("search", "", body.as_object().unwrap(), &["query", "sort"]);
+
+        assert!(warnings.is_empty());
+        assert!(warnings.header_values().is_empty());
+        assert_eq!(warnings.to_strict_error(), None);
+    }
+
+    #[test]
+    fn test_strict_error() {
+        let mut warnings = ParseWarnings::new();
+        warnings.deprecated("specifying types in search requests is deprecated");
+        assert_eq!(warnings.to_strict_error(), None);
+
+        let body = json!({"query": {}, "suggest": {}});
+        warnings.check_keys("search", "", body.as_object().unwrap(), &["query"]);
+        warnings.unsupported_parameter("search", "timeout");
+
+        assert_eq!(warnings.to_strict_error(), Some(json!({
+            "message": "[search] unsupported parameter [suggest] was ignored",
+            "pointer": "/suggest"
+        })));
+    }
+
+    #[test]
+    fn test_strict_error_without_pointer() {
+        let mut warnings = ParseWarnings::new();
+        warnings.unsupported_parameter("search", "timeout");
+
+        assert_eq!(warnings.to_strict_error(), Some(json!({
+            "message": "[search] unsupported parameter [timeout] was ignored"
+        })));
+    }
+
+    #[test]
+    fn test_duplicate_warnings() {
+        let mut warnings = ParseWarnings::new();
+        warnings.unsupported_parameter("search", "timeout");
+        warnings.unsupported_parameter("search", "timeout");
+
+        assert_eq!(warnings.messages().len(), 1);
+    }
+
+    #[test]
+    fn test_header_values() {
+        let mut warnings = ParseWarnings::new();
+        warnings.deprecated("the \"filtered\" query is deprecated");
+
+        assert_eq!(warnings.header_values(), vec![
+            format!("299 rusticsearch-{} \"[deprecation] the \\\"filtered\\\" query is deprecated\"", env!("CARGO_PKG_VERSION")).into_bytes(),
+        ]);
+        assert_eq!(warnings.to_json(), json!(["[deprecation] the \"filtered\" query is deprecated"]));
+    }
+}
@@ -0,0 +1,164 @@
+//! Parses "wildcard" queries
+//!
+//! ```json
+//! {"wildcard": {"user": {"value": "ki*y", "case_insensitive": true}}}
+//! ```
+//!
+//! "*" matches any number of characters and "?" matches a single character. Every term
+//! of the field is checked, so patterns should be used on keyword fields.
+
+use serde_json::Value as Json;
+use search::{Query, MultiTermSelector, TermScorer};
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_boost, parse_boolean};
+
+
+#[derive(Debug)]
+struct WildcardQueryBuilder {
+    field: String,
+    pattern: String,
+    boost: f32,
+    case_insensitive: bool,
+}
+
+
+impl QueryBuilder for WildcardQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // Keyword fields may have normalized their values when they were indexed. Normalizers
+        // only change single characters, so the wildcards are kept
+        let pattern = match context.get_field_mapping(&self.field) {
+            Some(field_mapping) => field_mapping.normalize(&self.pattern),
+            None => self.pattern.clone(),
+        };
+
+        let query = Query::MultiTerm {
+            field: field,
+            term_selector: MultiTermSelector::wildcard(&pattern, self.case_insensitive),
+            scorer: TermScorer::default(),
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let object = object.get(field_name).unwrap();
+
+    // Get configuration
+    let mut value: Option<&Json> = None;
+    let mut boost = 1.0f32;
+    let mut case_insensitive = false;
+
+    match *object {
+        Json::String(_) => value = Some(object),
+        Json::Object(ref inner_object) => {
+            for (key, val) in inner_object.iter() {
+                match key.as_ref() {
+                    "value" | "wildcard" => {
+                        value = Some(val);
+                    }
+                    "boost" => {
+                        boost = parse_boost(val)?;
+                    }
+                    "case_insensitive" => {
+                        case_insensitive = parse_boolean(val)?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString),
+    }
+
+    match value {
+        Some(&Json::String(ref pattern)) => {
+            Ok(Box::new(WildcardQueryBuilder {
+                field: field_name.clone(),
+                pattern: pattern.clone(),
+                boost: boost,
+                case_insensitive: case_insensitive,
+            }))
+        }
+        Some(_) => Err(QueryParseError::ExpectedString),
+        None => Err(QueryParseError::ExpectedKey("value"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Query, MultiTermSelector, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_wildcard_query() {
+        let mut schema = Schema::new();
+        let user_field = schema.add_field("user".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "user": {
+                "value": "ki*y",
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: user_field,
+            term_selector: MultiTermSelector::Wildcard {
+                pattern: "ki*y".to_string(),
+                case_insensitive: false,
+            },
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let mut schema = Schema::new();
+        let user_field = schema.add_field("user".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "user": {
+                "wildcard": "Ki?Y",
+                "case_insensitive": true
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: user_field,
+            term_selector: MultiTermSelector::Wildcard {
+                pattern: "ki?y".to_string(),
+                case_insensitive: true,
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_value() {
+        assert_eq!(parse(&json!({"user": {"value": 123}})).err(), Some(QueryParseError::ExpectedString));
+        assert_eq!(parse(&json!({"user": {"boost": 2.0}})).err(), Some(QueryParseError::ExpectedKey("value")));
+        assert_eq!(parse(&json!({"user": {"value": "ki*y", "hello": "world"}})).err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+    }
+}
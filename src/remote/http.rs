@@ -0,0 +1,289 @@
+//! A minimal HTTP/1.1 client, just enough to talk to Elasticsearch
+//!
+//! Only plain HTTP is supported and each request is made on a new connection.
+
+use std::io::{self, Read, Write, BufRead, BufReader};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use serde_json;
+use url::Url;
+
+use mapping::base64;
+
+
+/// Largest response body that will be read, anything bigger is an error
+const MAX_RESPONSE_SIZE: usize = 100 * 1024 * 1024;
+
+
+#[derive(Debug)]
+pub enum HttpError {
+    UnsupportedScheme(String),
+    InvalidUrl(String),
+    IoError(io::Error),
+    InvalidResponse(String),
+    JsonError(serde_json::Error),
+
+    /// The body of the response is bigger than the limit, which is included
+    ResponseTooLarge(usize),
+
+    /// The server responded with a status other than 2xx, the body is included
+    ErrorStatus(u16, String),
+}
+
+
+impl From<io::Error> for HttpError {
+    fn from(e: io::Error) -> HttpError {
+        HttpError::IoError(e)
+    }
+}
+
+
+impl From<serde_json::Error> for HttpError {
+    fn from(e: serde_json::Error) -> HttpError {
+        HttpError::JsonError(e)
+    }
+}
+
+
+impl From<HttpError> for String {
+    fn from(e: HttpError) -> String {
+        match e {
+            HttpError::UnsupportedScheme(scheme) => format!("unsupported scheme: {}", scheme),
+            HttpError::InvalidUrl(url) => format!("invalid url: {}", url),
+            HttpError::IoError(e) => format!("io error: {}", e),
+            HttpError::InvalidResponse(message) => format!("invalid response: {}", message),
+            HttpError::JsonError(e) => format!("invalid json in response: {}", e),
+            HttpError::ResponseTooLarge(limit) => format!("response is larger than {} bytes", limit),
+            HttpError::ErrorStatus(status, body) => format!("server responded with status {}: {}", status, body),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+
+/// Makes a request with an optional JSON body and returns the body of the response
+pub fn request(method: &str, url: &Url, credentials: Option<&Credentials>, body: Option<&serde_json::Value>, timeout: Duration) -> Result<Vec<u8>, HttpError> {
+    if url.scheme() != "http" {
+        return Err(HttpError::UnsupportedScheme(url.scheme().to_string()));
+    }
+
+    let host = match url.host_str() {
+        Some(host) => host,
+        None => return Err(HttpError::InvalidUrl(url.to_string())),
+    };
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let mut stream = connect(host, port, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // Build request
+    let body = body.map(|body| format!("{}", body)).unwrap_or_default();
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: {}:{}\r\nAccept: application/json\r\nConnection: close\r\nContent-Length: {}\r\n", method, path, host, port, body.len());
+    if !body.is_empty() {
+        request.push_str("Content-Type: application/json\r\n");
+    }
+    if let Some(credentials) = credentials {
+        let user_pass = format!("{}:{}", credentials.username, credentials.password);
+        request.push_str(&format!("Authorization: Basic {}\r\n", base64::encode(user_pass.as_bytes())));
+    }
+    request.push_str("\r\n");
+    request.push_str(&body);
+
+    stream.write_all(request.as_bytes())?;
+
+    // Read response
+    let (status, body) = read_response(BufReader::new(stream), MAX_RESPONSE_SIZE)?;
+    if status < 200 || status >= 300 {
+        return Err(HttpError::ErrorStatus(status, String::from_utf8_lossy(&body).into_owned()));
+    }
+
+    Ok(body)
+}
+
+
+/// Makes a request with an optional JSON body and parses the JSON response
+pub fn request_json(method: &str, url: &Url, credentials: Option<&Credentials>, body: Option<&serde_json::Value>, timeout: Duration) -> Result<serde_json::Value, HttpError> {
+    let body = request(method, url, credentials, body, timeout)?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+
+/// Connects to the first address of the host that accepts the connection within the timeout
+fn connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, HttpError> {
+    let mut last_error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    match last_error {
+        Some(e) => Err(HttpError::IoError(e)),
+        None => Err(HttpError::InvalidUrl(format!("{}:{}", host, port))),
+    }
+}
+
+
+/// Reads a line without its line ending
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, HttpError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(line.trim_right_matches(|c| c == '\r' || c == '\n').to_string())
+}
+
+
+/// Reads the status code and body of a response, the body can be up to max_size bytes
+fn read_response<R: BufRead>(mut reader: R, max_size: usize) -> Result<(u16, Vec<u8>), HttpError> {
+    // Status line, eg "HTTP/1.1 200 OK"
+    let status_line = read_line(&mut reader)?;
+    let status = match status_line.split(' ').nth(1).and_then(|status| status.parse().ok()) {
+        Some(status) => status,
+        None => return Err(HttpError::InvalidResponse(format!("invalid status line: {:?}", status_line))),
+    };
+
+    // Headers
+    let mut content_length = None;
+    let mut is_chunked = false;
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let value = parts.next().unwrap_or("").trim();
+
+        match name.as_ref() {
+            "content-length" => {
+                content_length = match value.parse::<usize>() {
+                    Ok(content_length) => Some(content_length),
+                    Err(_) => return Err(HttpError::InvalidResponse(format!("invalid content length: {:?}", value))),
+                };
+            }
+            "transfer-encoding" => is_chunked = value.to_lowercase().contains("chunked"),
+            _ => {}
+        }
+    }
+
+    // Body
+    let mut body = Vec::new();
+    if is_chunked {
+        loop {
+            // Each chunk starts with its size in hex, extensions come after a semicolon
+            let size_line = read_line(&mut reader)?;
+            let size = match usize::from_str_radix(size_line.split(';').next().unwrap_or("").trim(), 16) {
+                Ok(size) => size,
+                Err(_) => return Err(HttpError::InvalidResponse(format!("invalid chunk size: {:?}", size_line))),
+            };
+
+            if size == 0 {
+                break;
+            }
+
+            let start = body.len();
+            if size > max_size - start {
+                return Err(HttpError::ResponseTooLarge(max_size));
+            }
+
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+
+            // Chunks end with a line break
+            read_line(&mut reader)?;
+        }
+    } else if let Some(content_length) = content_length {
+        if content_length > max_size {
+            return Err(HttpError::ResponseTooLarge(max_size));
+        }
+
+        body.resize(content_length, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        // The body ends when the connection is closed
+        reader.by_ref().take(max_size as u64 + 1).read_to_end(&mut body)?;
+        if body.len() > max_size {
+            return Err(HttpError::ResponseTooLarge(max_size));
+        }
+    }
+
+    Ok((status, body))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{read_response, HttpError};
+
+    #[test]
+    fn test_read_response_with_content_length() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 7\r\n\r\n{\"a\":1}";
+        let (status, body) = read_response(Cursor::new(&response[..]), 1024).unwrap();
+
+        assert_eq!(status, 200);
+        assert_eq!(body, b"{\"a\":1}".to_vec());
+    }
+
+    #[test]
+    fn test_read_response_chunked() {
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n{\"a\r\n4;foo=bar\r\n\":1}\r\n0\r\n\r\n";
+        let (status, body) = read_response(Cursor::new(&response[..]), 1024).unwrap();
+
+        assert_eq!(status, 200);
+        assert_eq!(body, b"{\"a\":1}".to_vec());
+    }
+
+    #[test]
+    fn test_read_response_until_closed() {
+        let response = b"HTTP/1.0 404 Not Found\r\n\r\nnot found";
+        let (status, body) = read_response(Cursor::new(&response[..]), 1024).unwrap();
+
+        assert_eq!(status, 404);
+        assert_eq!(body, b"not found".to_vec());
+    }
+
+    #[test]
+    fn test_read_response_invalid_status_line() {
+        let response = b"foo\r\n\r\n";
+        assert!(read_response(Cursor::new(&response[..]), 1024).is_err());
+    }
+
+    #[test]
+    fn test_read_response_too_large() {
+        // The content length is checked before anything is allocated
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 99999999999\r\n\r\n{}";
+        match read_response(Cursor::new(&response[..]), 1024) {
+            Err(HttpError::ResponseTooLarge(1024)) => {}
+            result => panic!("expected ResponseTooLarge, got {:?}", result),
+        }
+
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n{\"a\r\nffffffffff\r\n\":1}\r\n0\r\n\r\n";
+        match read_response(Cursor::new(&response[..]), 1024) {
+            Err(HttpError::ResponseTooLarge(1024)) => {}
+            result => panic!("expected ResponseTooLarge, got {:?}", result),
+        }
+
+        let response = b"HTTP/1.0 200 OK\r\n\r\n0123456789";
+        match read_response(Cursor::new(&response[..]), 5) {
+            Err(HttpError::ResponseTooLarge(5)) => {}
+            result => panic!("expected ResponseTooLarge, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Reads documents from a remote Elasticsearch cluster
+//!
+//! This is used by the "_reindex" API to migrate existing data into rusticsearch.
+//! Documents are read in batches using the scroll API.
+
+pub mod http;
+
+use std::time::Duration;
+
+use serde_json::{self, Value as Json};
+use url::Url;
+
+use self::http::{Credentials, HttpError, request_json};
+
+
+/// How long the remote cluster should keep the scroll open between batches
+const SCROLL_KEEP_ALIVE: &'static str = "5m";
+
+/// The number of documents fetched in each batch, unless "size" is set
+const DEFAULT_BATCH_SIZE: u64 = 1000;
+
+/// How long to wait for the remote cluster before giving up
+const DEFAULT_SOCKET_TIMEOUT_SECS: u64 = 30;
+
+
+#[derive(Debug, PartialEq)]
+pub enum RemoteSourceParseError {
+    ExpectedObject,
+    ExpectedKey(&'static str),
+    ExpectedString(&'static str),
+    InvalidHost(String),
+    InvalidBatchSize,
+    InvalidSocketTimeout,
+}
+
+
+impl From<RemoteSourceParseError> for String {
+    fn from(e: RemoteSourceParseError) -> String {
+        match e {
+            RemoteSourceParseError::ExpectedObject => "source must be an object".to_string(),
+            RemoteSourceParseError::ExpectedKey(key) => format!("source is missing \"{}\"", key),
+            RemoteSourceParseError::ExpectedString(key) => format!("\"{}\" must be a string", key),
+            RemoteSourceParseError::InvalidHost(host) => format!("invalid remote host: {}", host),
+            RemoteSourceParseError::InvalidBatchSize => "\"size\" must be a positive integer".to_string(),
+            RemoteSourceParseError::InvalidSocketTimeout => "\"socket_timeout\" must be a number of seconds".to_string(),
+        }
+    }
+}
+
+
+/// A document read from the remote cluster
+#[derive(Debug, PartialEq)]
+pub struct RemoteHit {
+    pub id: String,
+    pub doc_type: String,
+    pub source: serde_json::Map<String, Json>,
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct RemoteSource {
+    host: String,
+    credentials: Option<Credentials>,
+    index: String,
+    query: Json,
+    batch_size: u64,
+    timeout: Duration,
+}
+
+
+fn get_string(object: &serde_json::Map<String, Json>, key: &'static str) -> Result<Option<String>, RemoteSourceParseError> {
+    match object.get(key) {
+        Some(&Json::String(ref value)) => Ok(Some(value.clone())),
+        Some(_) => Err(RemoteSourceParseError::ExpectedString(key)),
+        None => Ok(None),
+    }
+}
+
+
+impl RemoteSource {
+    /// Parses the "source" section of a reindex request
+    pub fn parse(json: &Json) -> Result<RemoteSource, RemoteSourceParseError> {
+        let source = json.as_object().ok_or(RemoteSourceParseError::ExpectedObject)?;
+        let remote = match source.get("remote") {
+            Some(remote) => remote.as_object().ok_or(RemoteSourceParseError::ExpectedObject)?,
+            None => return Err(RemoteSourceParseError::ExpectedKey("remote")),
+        };
+
+        let index = get_string(source, "index")?.ok_or(RemoteSourceParseError::ExpectedKey("index"))?;
+
+        // Host, eg "http://otherhost:9200"
+        let host = get_string(remote, "host")?.ok_or(RemoteSourceParseError::ExpectedKey("host"))?;
+        if Url::parse(&host).is_err() {
+            return Err(RemoteSourceParseError::InvalidHost(host));
+        }
+
+        let credentials = match get_string(remote, "username")? {
+            Some(username) => {
+                Some(Credentials {
+                    username: username,
+                    password: get_string(remote, "password")?.unwrap_or_default(),
+                })
+            }
+            None => None,
+        };
+
+        let timeout = match remote.get("socket_timeout") {
+            Some(timeout) => timeout.as_u64().ok_or(RemoteSourceParseError::InvalidSocketTimeout)?,
+            None => DEFAULT_SOCKET_TIMEOUT_SECS,
+        };
+
+        let batch_size = match source.get("size") {
+            Some(size) => {
+                match size.as_u64() {
+                    Some(size) if size > 0 => size,
+                    _ => return Err(RemoteSourceParseError::InvalidBatchSize),
+                }
+            }
+            None => DEFAULT_BATCH_SIZE,
+        };
+
+        // The query is run by the remote cluster so it's passed through as is
+        let query = source.get("query").cloned().unwrap_or_else(|| json!({"match_all": {}}));
+
+        Ok(RemoteSource {
+            host: host.trim_right_matches('/').to_string(),
+            credentials: credentials,
+            index: index,
+            query: query,
+            batch_size: batch_size,
+            timeout: Duration::from_secs(timeout),
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn url(&self, path: &str) -> Result<Url, HttpError> {
+        let url = format!("{}/{}", self.host, path);
+        Url::parse(&url).map_err(|_| HttpError::InvalidUrl(url))
+    }
+
+    fn request(&self, method: &str, path: &str, body: &Json) -> Result<Json, HttpError> {
+        let url = self.url(path)?;
+        request_json(method, &url, self.credentials.as_ref(), Some(body), self.timeout)
+    }
+
+    /// Starts reading the documents, they are fetched in batches as the scroll is advanced
+    pub fn scroll(&self) -> RemoteScroll {
+        RemoteScroll {
+            source: self,
+            scroll_id: None,
+            finished: false,
+        }
+    }
+}
+
+
+fn parse_hits(response: &Json) -> Result<Vec<RemoteHit>, HttpError> {
+    let hits = match response.get("hits").and_then(|hits| hits.get("hits")).and_then(|hits| hits.as_array()) {
+        Some(hits) => hits,
+        None => return Err(HttpError::InvalidResponse("response has no hits".to_string())),
+    };
+
+    let mut remote_hits = Vec::with_capacity(hits.len());
+    for hit in hits.iter() {
+        let id = hit.get("_id").and_then(|id| id.as_str());
+        let source = hit.get("_source").and_then(|source| source.as_object());
+
+        match (id, source) {
+            (Some(id), Some(source)) => {
+                remote_hits.push(RemoteHit {
+                    id: id.to_string(),
+
+                    // Types were removed in Elasticsearch 7
+                    doc_type: hit.get("_type").and_then(|doc_type| doc_type.as_str()).unwrap_or("_doc").to_string(),
+                    source: source.clone(),
+                });
+            }
+            _ => return Err(HttpError::InvalidResponse("hit is missing \"_id\" or \"_source\"".to_string())),
+        }
+    }
+
+    Ok(remote_hits)
+}
+
+
+/// Reads documents from the remote cluster a batch at a time
+pub struct RemoteScroll<'a> {
+    source: &'a RemoteSource,
+    scroll_id: Option<String>,
+    finished: bool,
+}
+
+
+impl<'a> RemoteScroll<'a> {
+    /// Fetches the next batch of documents, this is empty once all documents have been read
+    pub fn next_batch(&mut self) -> Result<Vec<RemoteHit>, HttpError> {
+        if self.finished {
+            return Ok(Vec::new());
+        }
+
+        let response = match self.scroll_id {
+            Some(ref scroll_id) => {
+                self.source.request("POST", "_search/scroll", &json!({
+                    "scroll": SCROLL_KEEP_ALIVE,
+                    "scroll_id": scroll_id,
+                }))?
+            }
+            None => {
+                let path = format!("{}/_search?scroll={}", self.source.index, SCROLL_KEEP_ALIVE);
+                self.source.request("POST", &path, &json!({
+                    "size": self.source.batch_size,
+                    "query": self.source.query,
+                }))?
+            }
+        };
+
+        if let Some(scroll_id) = response.get("_scroll_id").and_then(|scroll_id| scroll_id.as_str()) {
+            self.scroll_id = Some(scroll_id.to_string());
+        }
+
+        let hits = parse_hits(&response)?;
+        if hits.is_empty() {
+            self.finished = true;
+        }
+
+        Ok(hits)
+    }
+}
+
+
+impl<'a> Drop for RemoteScroll<'a> {
+    fn drop(&mut self) {
+        // Free the resources held by the scroll on the remote cluster, it would
+        // expire by itself eventually so errors are ignored
+        if let Some(ref scroll_id) = self.scroll_id {
+            let _ = self.source.request("DELETE", "_search/scroll", &json!({
+                "scroll_id": [scroll_id],
+            }));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{RemoteSource, RemoteSourceParseError, RemoteHit, parse_hits};
+    use super::http::Credentials;
+
+    #[test]
+    fn test_parse_remote_source() {
+        let source = RemoteSource::parse(&json!({
+            "index": "products",
+            "size": 100,
+            "query": {
+                "term": {"type": "book"}
+            },
+            "remote": {
+                "host": "http://otherhost:9200/",
+                "username": "user",
+                "password": "pass"
+            }
+        }));
+
+        assert_eq!(source, Ok(RemoteSource {
+            host: "http://otherhost:9200".to_string(),
+            credentials: Some(Credentials {
+                username: "user".to_string(),
+                password: "pass".to_string(),
+            }),
+            index: "products".to_string(),
+            query: json!({"term": {"type": "book"}}),
+            batch_size: 100,
+            timeout: Duration::from_secs(30),
+        }));
+    }
+
+    #[test]
+    fn test_parse_remote_source_defaults() {
+        let source = RemoteSource::parse(&json!({
+            "index": "products",
+            "remote": {
+                "host": "http://otherhost:9200"
+            }
+        })).unwrap();
+
+        assert_eq!(source.credentials, None);
+        assert_eq!(source.query, json!({"match_all": {}}));
+        assert_eq!(source.batch_size, 1000);
+    }
+
+    #[test]
+    fn test_parse_without_remote() {
+        let source = RemoteSource::parse(&json!({
+            "index": "products"
+        }));
+
+        assert_eq!(source, Err(RemoteSourceParseError::ExpectedKey("remote")));
+    }
+
+    #[test]
+    fn test_parse_invalid_host() {
+        let source = RemoteSource::parse(&json!({
+            "index": "products",
+            "remote": {
+                "host": "otherhost"
+            }
+        }));
+
+        assert_eq!(source, Err(RemoteSourceParseError::InvalidHost("otherhost".to_string())));
+    }
+
+    #[test]
+    fn test_parse_hits() {
+        let hits = parse_hits(&json!({
+            "_scroll_id": "abc",
+            "hits": {
+                "total": 2,
+                "hits": [
+                    {"_index": "products", "_type": "product", "_id": "1", "_source": {"title": "foo"}},
+                    {"_index": "products", "_id": "2", "_source": {"title": "bar"}}
+                ]
+            }
+        })).unwrap();
+
+        assert_eq!(hits, vec![
+            RemoteHit {
+                id: "1".to_string(),
+                doc_type: "product".to_string(),
+                source: json!({"title": "foo"}).as_object().unwrap().clone(),
+            },
+            RemoteHit {
+                id: "2".to_string(),
+                doc_type: "_doc".to_string(),
+                source: json!({"title": "bar"}).as_object().unwrap().clone(),
+            },
+        ]);
+    }
+}
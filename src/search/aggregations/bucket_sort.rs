@@ -0,0 +1,148 @@
+//! Sorts the buckets of its parent by values read from them, and keeps a page of them
+
+use std::cmp::Ordering;
+
+use serde_json::{Map, Value as Json};
+
+use search::collectors::sorted::SortOrder;
+use search::aggregations::pipeline::{PipelineAggregation, GapPolicy, read_bucket_path};
+
+#[derive(Debug, Clone)]
+pub struct BucketSortAggregation {
+    /// The buckets paths to sort by, the buckets aren't reordered if this is empty
+    pub sort: Vec<(String, SortOrder)>,
+
+    /// The number of buckets to skip after sorting
+    pub from: usize,
+
+    /// The number of buckets to keep, all of them are kept if not set
+    pub size: Option<usize>,
+
+    /// With "skip", buckets without all of the values to sort by are removed
+    pub gap_policy: GapPolicy,
+}
+
+impl PipelineAggregation for BucketSortAggregation {
+    fn buckets_paths(&self) -> Vec<&str> {
+        self.sort.iter().map(|&(ref path, _)| path.as_str()).collect()
+    }
+
+    fn reorders_buckets(&self) -> bool {
+        true
+    }
+
+    fn apply(&self, _name: &str, buckets: &mut Vec<Map<String, Json>>) {
+        if !self.sort.is_empty() {
+            // Read the values to sort each bucket by
+            let mut sortable_buckets = buckets.drain(..).filter_map(|bucket| {
+                let values = self.sort.iter().map(|&(ref path, _)| {
+                    match (read_bucket_path(&bucket, path), self.gap_policy) {
+                        (Some(value), _) => Some(value),
+                        (None, GapPolicy::InsertZeros) => Some(0.0),
+                        (None, GapPolicy::Skip) => None,
+                    }
+                }).collect::<Option<Vec<f64>>>();
+
+                values.map(|values| (values, bucket))
+            }).collect::<Vec<_>>();
+
+            // Buckets with the same values stay in the order their parent gave them
+            sortable_buckets.sort_by(|a, b| {
+                for (i, &(_, order)) in self.sort.iter().enumerate() {
+                    let ordering = a.0[i].partial_cmp(&b.0[i]).unwrap_or(Ordering::Equal);
+                    let ordering = match order {
+                        SortOrder::Asc => ordering,
+                        SortOrder::Desc => ordering.reverse(),
+                    };
+
+                    if ordering != Ordering::Equal {
+                        return ordering;
+                    }
+                }
+
+                Ordering::Equal
+            });
+
+            buckets.extend(sortable_buckets.into_iter().map(|(_, bucket)| bucket));
+        }
+
+        let from = self.from.min(buckets.len());
+        buckets.drain(..from);
+
+        if let Some(size) = self.size {
+            buckets.truncate(size);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Map, Value as Json};
+
+    use search::collectors::sorted::SortOrder;
+    use search::aggregations::pipeline::{PipelineAggregation, GapPolicy};
+
+    use super::BucketSortAggregation;
+
+    fn make_buckets() -> Vec<Map<String, Json>> {
+        vec![
+            json!({"key": 1, "doc_count": 2, "sales": {"value": 10.0}}),
+            json!({"key": 2, "doc_count": 5}),
+            json!({"key": 3, "doc_count": 3, "sales": {"value": 4.0}}),
+            json!({"key": 4, "doc_count": 3, "sales": {"value": 12.0}}),
+        ].into_iter().map(|bucket| bucket.as_object().unwrap().clone()).collect()
+    }
+
+    fn keys(buckets: Vec<Map<String, Json>>) -> Vec<Json> {
+        buckets.iter().map(|bucket| bucket["key"].clone()).collect()
+    }
+
+    #[test]
+    fn test_bucket_sort() {
+        let mut buckets = make_buckets();
+        BucketSortAggregation {
+            sort: vec![("_count".to_string(), SortOrder::Desc), ("_key".to_string(), SortOrder::Desc)],
+            from: 1,
+            size: Some(2),
+            gap_policy: GapPolicy::Skip,
+        }.apply("sort", &mut buckets);
+
+        assert_eq!(keys(buckets), vec![json!(4), json!(3)]);
+    }
+
+    #[test]
+    fn test_gap_policy() {
+        let mut buckets = make_buckets();
+        BucketSortAggregation {
+            sort: vec![("sales".to_string(), SortOrder::Asc)],
+            from: 0,
+            size: None,
+            gap_policy: GapPolicy::Skip,
+        }.apply("sort", &mut buckets);
+
+        assert_eq!(keys(buckets), vec![json!(3), json!(1), json!(4)]);
+
+        let mut buckets = make_buckets();
+        BucketSortAggregation {
+            sort: vec![("sales".to_string(), SortOrder::Asc)],
+            from: 0,
+            size: None,
+            gap_policy: GapPolicy::InsertZeros,
+        }.apply("sort", &mut buckets);
+
+        assert_eq!(keys(buckets), vec![json!(2), json!(3), json!(1), json!(4)]);
+    }
+
+    #[test]
+    fn test_truncate_without_sorting() {
+        let mut buckets = make_buckets();
+        BucketSortAggregation {
+            sort: vec![],
+            from: 0,
+            size: Some(3),
+            gap_policy: GapPolicy::Skip,
+        }.apply("sort", &mut buckets);
+
+        assert_eq!(keys(buckets), vec![json!(1), json!(2), json!(3)]);
+    }
+}
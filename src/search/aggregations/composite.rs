@@ -0,0 +1,354 @@
+//! Pages through the combinations of the values of several sources
+//!
+//! Each bucket has a key with a value from each source. Buckets are returned in the
+//! order of their keys, a page at a time, along with the key of the last bucket as
+//! "after_key". Passing this back as "after" returns the next page.
+//!
+//! Only the buckets on the current page are kept while collecting, so this can go
+//! through all the buckets of fields with many values without using much memory.
+
+use std::cmp::Ordering;
+
+use serde_json::{Map, Value as Json};
+
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
+use search::collectors::DocumentMatch;
+use search::collectors::sorted::SortOrder;
+use search::aggregations::{Aggregation, Aggregator, Aggregations, Bucket, DocValues};
+use search::aggregations::date_interval::{DateInterval, datetime_to_millis};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompositeSourceType {
+    /// Each value of the field
+    Terms,
+
+    /// Integers, rounded down to a multiple of the interval
+    Histogram(f64),
+
+    /// Dates, rounded down to the start of their interval
+    DateHistogram(DateInterval),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompositeSource {
+    pub name: String,
+
+    /// None if the field isn't in the index, no documents have a value for the source
+    pub field: Option<FieldId>,
+
+    pub source_type: CompositeSourceType,
+    pub order: SortOrder,
+}
+
+impl CompositeSource {
+    /// The values of the source for a document, sorted with duplicates removed
+    fn keys(&self, values: &[FieldValue]) -> Vec<Json> {
+        let mut keys = values.iter().filter_map(|value| {
+            match (&self.source_type, value) {
+                (&CompositeSourceType::Terms, &FieldValue::String(ref value)) => Some(Json::String(value.clone())),
+                (&CompositeSourceType::Terms, &FieldValue::Integer(value)) => Some(Json::from(value)),
+                (&CompositeSourceType::Terms, &FieldValue::Boolean(value)) => Some(Json::Bool(value)),
+                (&CompositeSourceType::Terms, &FieldValue::DateTime(ref value)) => Some(Json::from(datetime_to_millis(value))),
+                (&CompositeSourceType::Histogram(interval), &FieldValue::Integer(value)) => {
+                    Some(Json::from((value as f64 / interval).floor() * interval))
+                }
+                (&CompositeSourceType::DateHistogram(interval), &FieldValue::DateTime(ref value)) => {
+                    Some(Json::from(interval.round_down(value)))
+                }
+                _ => None,
+            }
+        }).collect::<Vec<_>>();
+
+        keys.sort_by(compare_values);
+        keys.dedup();
+        keys
+    }
+}
+
+/// Orders two values of a key, values of different types are ordered by type
+fn compare_values(a: &Json, b: &Json) -> Ordering {
+    fn type_rank(value: &Json) -> u8 {
+        match *value {
+            Json::Null => 0,
+            Json::Bool(_) => 1,
+            Json::Number(_) => 2,
+            Json::String(_) => 3,
+            Json::Array(_) => 4,
+            Json::Object(_) => 5,
+        }
+    }
+
+    match (a, b) {
+        (&Json::Bool(a), &Json::Bool(b)) => a.cmp(&b),
+        (&Json::Number(ref a), &Json::Number(ref b)) => {
+            match (a.as_i64(), b.as_i64()) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                _ => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+            }
+        }
+        (&Json::String(ref a), &Json::String(ref b)) => a.cmp(b),
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+#[derive(Debug)]
+pub struct CompositeAggregation {
+    pub sources: Vec<CompositeSource>,
+
+    /// The number of buckets on each page
+    pub size: usize,
+
+    /// Only buckets with keys after this are returned, it has a value for each source
+    pub after: Option<Vec<Json>>,
+
+    pub sub_aggregations: Aggregations,
+}
+
+impl CompositeAggregation {
+    fn compare_keys(&self, a: &[Json], b: &[Json]) -> Ordering {
+        for ((source, a), b) in self.sources.iter().zip(a.iter()).zip(b.iter()) {
+            let ordering = match source.order {
+                SortOrder::Asc => compare_values(a, b),
+                SortOrder::Desc => compare_values(a, b).reverse(),
+            };
+
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        Ordering::Equal
+    }
+
+    fn key_to_json(&self, key: &[Json]) -> Json {
+        Json::Object(self.sources.iter().zip(key.iter()).map(|(source, value)| {
+            (source.name.clone(), value.clone())
+        }).collect::<Map<String, Json>>())
+    }
+}
+
+impl Aggregation for CompositeAggregation {
+    fn create_aggregator<'a>(&'a self) -> Box<Aggregator + 'a> {
+        Box::new(CompositeAggregator {
+            aggregation: self,
+            buckets: Vec::new(),
+        })
+    }
+}
+
+struct CompositeAggregator<'a> {
+    aggregation: &'a CompositeAggregation,
+
+    /// The buckets on the page, sorted by key
+    buckets: Vec<(Vec<Json>, Bucket<'a>)>,
+}
+
+impl<'a> Aggregator for CompositeAggregator<'a> {
+    fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues) {
+        let aggregation = self.aggregation;
+        let doc_id = DocId::from_u64(doc.doc_id());
+
+        // Documents are in a bucket for every combination of the values of the sources
+        // Documents that don't have a value for every source aren't in any buckets
+        let mut keys = vec![Vec::new()];
+        for source in aggregation.sources.iter() {
+            let source_keys = match source.field {
+                Some(field) => source.keys(&doc_values.doc_values(field, doc_id)),
+                None => return,
+            };
+
+            let mut combinations = Vec::with_capacity(keys.len() * source_keys.len());
+            for key in keys.iter() {
+                for source_key in source_keys.iter() {
+                    let mut combination: Vec<Json> = key.clone();
+                    combination.push(source_key.clone());
+                    combinations.push(combination);
+                }
+            }
+
+            keys = combinations;
+        }
+
+        for key in keys {
+            if let Some(ref after) = aggregation.after {
+                if aggregation.compare_keys(&key, after) != Ordering::Greater {
+                    continue;
+                }
+            }
+
+            match self.buckets.binary_search_by(|&(ref bucket_key, _)| aggregation.compare_keys(bucket_key, &key)) {
+                Ok(index) => self.buckets[index].1.collect(doc, doc_values),
+                Err(index) => {
+                    // Buckets after the end of a full page aren't on it
+                    if index >= aggregation.size {
+                        continue;
+                    }
+
+                    let mut bucket = Bucket::new(&aggregation.sub_aggregations);
+                    bucket.collect(doc, doc_values);
+                    self.buckets.insert(index, (key, bucket));
+                    self.buckets.truncate(aggregation.size);
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, doc_values: &DocValues) {
+        for &mut (_, ref mut bucket) in self.buckets.iter_mut() {
+            bucket.finish(doc_values);
+        }
+    }
+
+    fn result(&self) -> Json {
+        let mut buckets_json = self.buckets.iter().map(|&(ref key, ref bucket)| {
+            let mut json = bucket.to_json();
+            json.insert("key".to_string(), self.aggregation.key_to_json(key));
+            json
+        }).collect::<Vec<_>>();
+
+        self.aggregation.sub_aggregations.run_pipelines(&mut buckets_json);
+        let mut json = json!({"buckets": buckets_json});
+
+        // The next page starts after the last bucket of this one
+        if let Some(&(ref key, _)) = self.buckets.last() {
+            json["after_key"] = self.aggregation.key_to_json(key);
+        }
+
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+
+    use search::document::{DocId, FieldValue};
+    use search::schema::FieldId;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::collectors::sorted::SortOrder;
+    use search::aggregations::{Aggregations, AggregationsCollector};
+
+    use super::{CompositeAggregation, CompositeSource, CompositeSourceType};
+
+    fn sources(product_order: SortOrder) -> Vec<CompositeSource> {
+        vec![
+            CompositeSource {
+                name: "product".to_string(),
+                field: Some(FieldId(1)),
+                source_type: CompositeSourceType::Terms,
+                order: product_order,
+            },
+            CompositeSource {
+                name: "price".to_string(),
+                field: Some(FieldId(2)),
+                source_type: CompositeSourceType::Histogram(5.0),
+                order: SortOrder::Asc,
+            },
+        ]
+    }
+
+    fn run(aggregation: CompositeAggregation) -> Json {
+        let products = vec![
+            vec![FieldValue::String("b".to_string())],
+            vec![FieldValue::String("a".to_string())],
+            vec![FieldValue::String("a".to_string())],
+            vec![FieldValue::String("a".to_string()), FieldValue::String("c".to_string())],
+            vec![],
+        ];
+        let prices = vec![12, 3, 7, 14, 1];
+
+        let mut aggregations = Aggregations::new();
+        aggregations.push("rollup".to_string(), Box::new(aggregation));
+
+        let mut collector = AggregationsCollector::new(&aggregations, |field: FieldId, doc_id: DocId| {
+            match field {
+                FieldId(1) => products[doc_id.1 as usize].clone(),
+                _ => vec![FieldValue::Integer(prices[doc_id.1 as usize])],
+            }
+        });
+        for doc_id in 0..products.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()["rollup"].clone()
+    }
+
+    #[test]
+    fn test_composite() {
+        let result = run(CompositeAggregation {
+            sources: sources(SortOrder::Asc),
+            size: 10,
+            after: None,
+            sub_aggregations: Aggregations::new(),
+        });
+
+        assert_eq!(result, json!({
+            "after_key": {"product": "c", "price": 10.0},
+            "buckets": [
+                {"key": {"product": "a", "price": 0.0}, "doc_count": 1},
+                {"key": {"product": "a", "price": 5.0}, "doc_count": 1},
+                {"key": {"product": "a", "price": 10.0}, "doc_count": 1},
+                {"key": {"product": "b", "price": 10.0}, "doc_count": 1},
+                {"key": {"product": "c", "price": 10.0}, "doc_count": 1},
+            ]
+        }));
+    }
+
+    #[test]
+    fn test_composite_pages() {
+        let first_page = run(CompositeAggregation {
+            sources: sources(SortOrder::Asc),
+            size: 2,
+            after: None,
+            sub_aggregations: Aggregations::new(),
+        });
+
+        assert_eq!(first_page["after_key"], json!({"product": "a", "price": 5.0}));
+        assert_eq!(first_page["buckets"].as_array().unwrap().len(), 2);
+
+        let second_page = run(CompositeAggregation {
+            sources: sources(SortOrder::Asc),
+            size: 2,
+            after: Some(vec![json!("a"), json!(5)]),
+            sub_aggregations: Aggregations::new(),
+        });
+
+        assert_eq!(second_page, json!({
+            "after_key": {"product": "b", "price": 10.0},
+            "buckets": [
+                {"key": {"product": "a", "price": 10.0}, "doc_count": 1},
+                {"key": {"product": "b", "price": 10.0}, "doc_count": 1},
+            ]
+        }));
+
+        // There are no more buckets after the last page
+        let last_page = run(CompositeAggregation {
+            sources: sources(SortOrder::Asc),
+            size: 2,
+            after: Some(vec![json!("c"), json!(10.0)]),
+            sub_aggregations: Aggregations::new(),
+        });
+
+        assert_eq!(last_page, json!({"buckets": []}));
+    }
+
+    #[test]
+    fn test_composite_desc() {
+        let result = run(CompositeAggregation {
+            sources: sources(SortOrder::Desc),
+            size: 3,
+            after: None,
+            sub_aggregations: Aggregations::new(),
+        });
+
+        assert_eq!(result, json!({
+            "after_key": {"product": "a", "price": 0.0},
+            "buckets": [
+                {"key": {"product": "c", "price": 10.0}, "doc_count": 1},
+                {"key": {"product": "b", "price": 10.0}, "doc_count": 1},
+                {"key": {"product": "a", "price": 0.0}, "doc_count": 1},
+            ]
+        }));
+    }
+}
@@ -0,0 +1,52 @@
+//! Adds the running total of a value over the buckets to each bucket
+//!
+//! Buckets without the value add nothing to the total.
+
+use serde_json::{Map, Value as Json};
+
+use search::aggregations::pipeline::{PipelineAggregation, read_bucket_path};
+
+#[derive(Debug, Clone)]
+pub struct CumulativeSumAggregation {
+    pub buckets_path: String,
+}
+
+impl PipelineAggregation for CumulativeSumAggregation {
+    fn buckets_paths(&self) -> Vec<&str> {
+        vec![self.buckets_path.as_str()]
+    }
+
+    fn apply(&self, name: &str, buckets: &mut Vec<Map<String, Json>>) {
+        let mut sum = 0.0;
+
+        for bucket in buckets.iter_mut() {
+            sum += read_bucket_path(bucket, &self.buckets_path).unwrap_or(0.0);
+            bucket.insert(name.to_string(), json!({"value": sum}));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+
+    use search::aggregations::pipeline::PipelineAggregation;
+
+    use super::CumulativeSumAggregation;
+
+    #[test]
+    fn test_cumulative_sum() {
+        let mut buckets = vec![
+            json!({"key": 1, "doc_count": 2, "sales": {"value": 10.0}}),
+            json!({"key": 2, "doc_count": 5}),
+            json!({"key": 3, "doc_count": 3, "sales": {"value": 4.5}}),
+        ].into_iter().map(|bucket| bucket.as_object().unwrap().clone()).collect::<Vec<_>>();
+
+        CumulativeSumAggregation {
+            buckets_path: "sales".to_string(),
+        }.apply("total_sales", &mut buckets);
+
+        let totals = buckets.iter().map(|bucket| bucket["total_sales"].clone()).collect::<Vec<Json>>();
+        assert_eq!(totals, vec![json!({"value": 10.0}), json!({"value": 10.0}), json!({"value": 14.5})]);
+    }
+}
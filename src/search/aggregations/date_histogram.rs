@@ -0,0 +1,226 @@
+//! Groups documents by the interval that the dates in a date field are in
+//!
+//! Buckets are returned in date order. By default, empty buckets are added between
+//! the first and last buckets so there aren't any gaps in the histogram (pipeline
+//! aggregations such as derivative rely on this).
+
+use std::collections::BTreeMap;
+
+use chrono::{TimeZone, Utc};
+use serde_json::{Map, Value as Json};
+
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
+use search::collectors::DocumentMatch;
+use search::aggregations::{Aggregation, Aggregator, Aggregations, Bucket, DocValues};
+use search::aggregations::date_interval::DateInterval;
+
+/// The most empty buckets that are added to fill gaps, in case the interval is tiny
+pub const MAX_EMPTY_BUCKETS: usize = 10000;
+
+#[derive(Debug)]
+pub struct DateHistogramAggregation {
+    /// The date field, None if it isn't in the index
+    pub field: Option<FieldId>,
+
+    pub interval: DateInterval,
+
+    /// Buckets with fewer documents are left out, gaps are only filled if this is 0
+    pub min_doc_count: u64,
+
+    pub sub_aggregations: Aggregations,
+}
+
+impl Aggregation for DateHistogramAggregation {
+    fn create_aggregator<'a>(&'a self) -> Box<Aggregator + 'a> {
+        Box::new(DateHistogramAggregator {
+            aggregation: self,
+            buckets: BTreeMap::new(),
+        })
+    }
+}
+
+/// Formats a key (in milliseconds since the epoch) as an ISO 8601 date
+pub fn format_key(key: i64) -> String {
+    // Round the seconds down for dates before the epoch
+    let seconds = if key < 0 && key % 1000 != 0 { key / 1000 - 1 } else { key / 1000 };
+    let millis = key - seconds * 1000;
+
+    Utc.timestamp(seconds, millis as u32 * 1000000).format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+struct DateHistogramAggregator<'a> {
+    aggregation: &'a DateHistogramAggregation,
+
+    /// The buckets, keyed by the start of their interval
+    buckets: BTreeMap<i64, Bucket<'a>>,
+}
+
+impl<'a> DateHistogramAggregator<'a> {
+    fn bucket_json(&self, key: i64, bucket: &Bucket) -> Map<String, Json> {
+        let mut json = bucket.to_json();
+        json.insert("key".to_string(), Json::from(key));
+        json.insert("key_as_string".to_string(), Json::String(format_key(key)));
+        json
+    }
+}
+
+impl<'a> Aggregator for DateHistogramAggregator<'a> {
+    fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues) {
+        let aggregation = self.aggregation;
+        let field = match aggregation.field {
+            Some(field) => field,
+            None => return,
+        };
+
+        // A document with many dates in the same interval is only counted once
+        let mut keys = doc_values.doc_values(field, DocId::from_u64(doc.doc_id())).iter().filter_map(|value| {
+            match *value {
+                FieldValue::DateTime(ref date) => Some(aggregation.interval.round_down(date)),
+                _ => None,
+            }
+        }).collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+
+        for key in keys {
+            self.buckets.entry(key).or_insert_with(|| Bucket::new(&aggregation.sub_aggregations)).collect(doc, doc_values);
+        }
+    }
+
+    fn finish(&mut self, doc_values: &DocValues) {
+        for bucket in self.buckets.values_mut() {
+            bucket.finish(doc_values);
+        }
+    }
+
+    fn result(&self) -> Json {
+        let aggregation = self.aggregation;
+        let empty_bucket = Bucket::new(&aggregation.sub_aggregations);
+        let mut empty_buckets = 0;
+        let mut next_key = None;
+
+        let mut buckets_json = Vec::new();
+        for (&key, bucket) in self.buckets.iter() {
+            if aggregation.min_doc_count == 0 {
+                // Fill the gap since the previous bucket
+                if let Some(mut gap_key) = next_key {
+                    while gap_key < key && empty_buckets < MAX_EMPTY_BUCKETS {
+                        buckets_json.push(self.bucket_json(gap_key, &empty_bucket));
+                        gap_key = aggregation.interval.next(gap_key);
+                        empty_buckets += 1;
+                    }
+                }
+
+                next_key = Some(aggregation.interval.next(key));
+            }
+
+            if bucket.doc_count() >= aggregation.min_doc_count {
+                buckets_json.push(self.bucket_json(key, bucket));
+            }
+        }
+
+        aggregation.sub_aggregations.run_pipelines(&mut buckets_json);
+        json!({"buckets": buckets_json})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value as Json;
+
+    use search::document::{DocId, FieldValue};
+    use search::schema::FieldId;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::{Aggregations, AggregationsCollector};
+    use search::aggregations::date_interval::DateInterval;
+    use search::aggregations::pipeline::GapPolicy;
+    use search::aggregations::derivative::DerivativeAggregation;
+    use search::aggregations::cumulative_sum::CumulativeSumAggregation;
+
+    use super::{DateHistogramAggregation, format_key};
+
+    fn run(aggregation: DateHistogramAggregation) -> Json {
+        let dates = vec![
+            vec![Utc.ymd(2017, 1, 3).and_hms(10, 0, 0)],
+            vec![Utc.ymd(2017, 1, 20).and_hms(10, 0, 0), Utc.ymd(2017, 1, 21).and_hms(10, 0, 0)],
+            vec![Utc.ymd(2017, 3, 31).and_hms(23, 59, 59)],
+            vec![],
+        ];
+
+        let mut aggregations = Aggregations::new();
+        aggregations.push("per_month".to_string(), Box::new(aggregation));
+
+        let mut collector = AggregationsCollector::new(&aggregations, |_field: FieldId, doc_id: DocId| {
+            dates[doc_id.1 as usize].iter().cloned().map(FieldValue::DateTime).collect()
+        });
+        for doc_id in 0..dates.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()["per_month"].clone()
+    }
+
+    #[test]
+    fn test_date_histogram() {
+        let result = run(DateHistogramAggregation {
+            field: Some(FieldId(1)),
+            interval: DateInterval::Month,
+            min_doc_count: 0,
+            sub_aggregations: Aggregations::new(),
+        });
+
+        assert_eq!(result, json!({
+            "buckets": [
+                {"key": 1483228800000i64, "key_as_string": "2017-01-01T00:00:00.000Z", "doc_count": 2},
+                {"key": 1485907200000i64, "key_as_string": "2017-02-01T00:00:00.000Z", "doc_count": 0},
+                {"key": 1488326400000i64, "key_as_string": "2017-03-01T00:00:00.000Z", "doc_count": 1},
+            ]
+        }));
+    }
+
+    #[test]
+    fn test_min_doc_count() {
+        let result = run(DateHistogramAggregation {
+            field: Some(FieldId(1)),
+            interval: DateInterval::Month,
+            min_doc_count: 1,
+            sub_aggregations: Aggregations::new(),
+        });
+
+        let keys = result["buckets"].as_array().unwrap().iter().map(|bucket| bucket["key"].clone()).collect::<Vec<_>>();
+        assert_eq!(keys, vec![json!(1483228800000i64), json!(1488326400000i64)]);
+    }
+
+    #[test]
+    fn test_pipelines() {
+        let mut sub_aggregations = Aggregations::new();
+        sub_aggregations.push_pipeline("change".to_string(), Box::new(DerivativeAggregation {
+            buckets_path: "_count".to_string(),
+            gap_policy: GapPolicy::Skip,
+        }));
+        sub_aggregations.push_pipeline("running_total".to_string(), Box::new(CumulativeSumAggregation {
+            buckets_path: "_count".to_string(),
+        }));
+
+        let result = run(DateHistogramAggregation {
+            field: Some(FieldId(1)),
+            interval: DateInterval::Month,
+            min_doc_count: 0,
+            sub_aggregations: sub_aggregations,
+        });
+
+        let buckets = result["buckets"].as_array().unwrap();
+        assert_eq!(buckets[0].get("change"), None);
+        assert_eq!(buckets[1]["change"], json!({"value": -2.0}));
+        assert_eq!(buckets[2]["change"], json!({"value": 1.0}));
+        assert_eq!(buckets[2]["running_total"], json!({"value": 3.0}));
+    }
+
+    #[test]
+    fn test_format_key() {
+        assert_eq!(format_key(1483228800123), "2017-01-01T00:00:00.123Z");
+        assert_eq!(format_key(-1), "1969-12-31T23:59:59.999Z");
+    }
+}
@@ -0,0 +1,106 @@
+//! The intervals that dates are grouped into by date histograms
+//!
+//! Dates are in UTC, keys are the start of the interval in milliseconds since the epoch.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateInterval {
+    /// A fixed number of milliseconds, such as an hour or a day
+    Fixed(i64),
+
+    /// Weeks start on Monday
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+/// The number of milliseconds since the epoch
+pub fn datetime_to_millis(date: &DateTime<Utc>) -> i64 {
+    date.timestamp() * 1000 + date.timestamp_subsec_millis() as i64
+}
+
+impl DateInterval {
+    /// The start of the interval a date is in, in milliseconds since the epoch
+    pub fn round_down(&self, date: &DateTime<Utc>) -> i64 {
+        let day = date.naive_utc().date();
+
+        let start = match *self {
+            DateInterval::Fixed(interval) => {
+                // Round towards the start of time, even for dates before the epoch
+                let millis = datetime_to_millis(date);
+                let remainder = millis % interval;
+                return if remainder < 0 { millis - remainder - interval } else { millis - remainder };
+            }
+            DateInterval::Week => day - Duration::days(day.weekday().num_days_from_monday() as i64),
+            DateInterval::Month => NaiveDate::from_ymd(day.year(), day.month(), 1),
+            DateInterval::Quarter => NaiveDate::from_ymd(day.year(), day.month0() / 3 * 3 + 1, 1),
+            DateInterval::Year => NaiveDate::from_ymd(day.year(), 1, 1),
+        };
+
+        start.and_hms(0, 0, 0).timestamp() * 1000
+    }
+
+    /// The start of the interval after the one starting at the given key
+    pub fn next(&self, key: i64) -> i64 {
+        let months = match *self {
+            DateInterval::Fixed(interval) => return key + interval,
+            DateInterval::Week => return key + 7 * 86400000,
+            DateInterval::Month => 1,
+            DateInterval::Quarter => 3,
+            DateInterval::Year => 12,
+        };
+
+        // Calendar intervals always start at midnight on the first day of a month
+        let day = NaiveDateTime::from_timestamp(key / 1000, 0).date();
+        let total_months = day.year() * 12 + day.month0() as i32 + months;
+        NaiveDate::from_ymd(total_months / 12, total_months as u32 % 12 + 1, 1).and_hms(0, 0, 0).timestamp() * 1000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::DateInterval;
+
+    #[test]
+    fn test_fixed_interval() {
+        let date = Utc.ymd(2017, 11, 15).and_hms(13, 45, 10);
+
+        assert_eq!(DateInterval::Fixed(86400000).round_down(&date), 1510704000000);
+        assert_eq!(DateInterval::Fixed(12 * 3600000).round_down(&date), 1510747200000);
+    }
+
+    #[test]
+    fn test_fixed_interval_before_epoch() {
+        let date = Utc.ymd(1969, 12, 31).and_hms(23, 30, 0);
+
+        assert_eq!(DateInterval::Fixed(3600000).round_down(&date), -3600000);
+        assert_eq!(DateInterval::Fixed(86400000).round_down(&date), -86400000);
+    }
+
+    #[test]
+    fn test_calendar_intervals() {
+        // A Wednesday
+        let date = Utc.ymd(2017, 11, 15).and_hms(13, 45, 10);
+
+        assert_eq!(DateInterval::Week.round_down(&date), 1510531200000);
+        assert_eq!(DateInterval::Month.round_down(&date), 1509494400000);
+        assert_eq!(DateInterval::Quarter.round_down(&date), 1506816000000);
+        assert_eq!(DateInterval::Year.round_down(&date), 1483228800000);
+    }
+
+    #[test]
+    fn test_next() {
+        let date = Utc.ymd(2017, 11, 15).and_hms(13, 45, 10);
+        let next = |interval: DateInterval| interval.next(interval.round_down(&date));
+
+        assert_eq!(next(DateInterval::Fixed(86400000)), 1510790400000);
+        assert_eq!(next(DateInterval::Week), 1511136000000);
+        assert_eq!(next(DateInterval::Month), 1512086400000);
+        assert_eq!(next(DateInterval::Quarter), 1514764800000);
+        assert_eq!(next(DateInterval::Year), 1514764800000);
+    }
+}
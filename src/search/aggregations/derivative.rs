@@ -0,0 +1,90 @@
+//! Adds the change in a value since the previous bucket to each bucket
+//!
+//! The first bucket doesn't have a derivative as there's nothing to compare it to.
+
+use serde_json::{Map, Value as Json};
+
+use search::aggregations::pipeline::{PipelineAggregation, GapPolicy, read_bucket_path};
+
+#[derive(Debug, Clone)]
+pub struct DerivativeAggregation {
+    pub buckets_path: String,
+
+    /// With "skip", buckets without a value are compared with the last bucket that had one
+    pub gap_policy: GapPolicy,
+}
+
+impl PipelineAggregation for DerivativeAggregation {
+    fn buckets_paths(&self) -> Vec<&str> {
+        vec![self.buckets_path.as_str()]
+    }
+
+    fn apply(&self, name: &str, buckets: &mut Vec<Map<String, Json>>) {
+        let mut previous_value = None;
+
+        for bucket in buckets.iter_mut() {
+            let value = match (read_bucket_path(bucket, &self.buckets_path), self.gap_policy) {
+                (Some(value), _) => value,
+                (None, GapPolicy::InsertZeros) => 0.0,
+                (None, GapPolicy::Skip) => continue,
+            };
+
+            if let Some(previous_value) = previous_value {
+                bucket.insert(name.to_string(), json!({"value": value - previous_value}));
+            }
+
+            previous_value = Some(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Map, Value as Json};
+
+    use search::aggregations::pipeline::{PipelineAggregation, GapPolicy};
+
+    use super::DerivativeAggregation;
+
+    fn make_buckets() -> Vec<Map<String, Json>> {
+        vec![
+            json!({"key": 1, "doc_count": 2, "sales": {"value": 10.0}}),
+            json!({"key": 2, "doc_count": 5, "sales": {"value": null}}),
+            json!({"key": 3, "doc_count": 3, "sales": {"value": 4.0}}),
+        ].into_iter().map(|bucket| bucket.as_object().unwrap().clone()).collect()
+    }
+
+    fn derivatives(buckets: Vec<Map<String, Json>>) -> Vec<Json> {
+        buckets.iter().map(|bucket| bucket.get("change").cloned().unwrap_or(Json::Null)).collect()
+    }
+
+    #[test]
+    fn test_derivative_of_count() {
+        let mut buckets = make_buckets();
+        DerivativeAggregation {
+            buckets_path: "_count".to_string(),
+            gap_policy: GapPolicy::Skip,
+        }.apply("change", &mut buckets);
+
+        assert_eq!(derivatives(buckets), vec![Json::Null, json!({"value": 3.0}), json!({"value": -2.0})]);
+    }
+
+    #[test]
+    fn test_gap_policy() {
+        let mut buckets = make_buckets();
+        DerivativeAggregation {
+            buckets_path: "sales".to_string(),
+            gap_policy: GapPolicy::Skip,
+        }.apply("change", &mut buckets);
+
+        assert_eq!(derivatives(buckets), vec![Json::Null, Json::Null, json!({"value": -6.0})]);
+
+        let mut buckets = make_buckets();
+        DerivativeAggregation {
+            buckets_path: "sales".to_string(),
+            gap_policy: GapPolicy::InsertZeros,
+        }.apply("change", &mut buckets);
+
+        assert_eq!(derivatives(buckets), vec![Json::Null, json!({"value": -10.0}), json!({"value": 4.0})]);
+    }
+}
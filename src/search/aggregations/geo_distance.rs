@@ -0,0 +1,284 @@
+//! Groups documents by the distance of the points in a geo point field from an origin
+//!
+//! Each range is a ring around the origin. A document is in the bucket of a range if
+//! any of its points are in the range.
+
+use serde_json::Value as Json;
+
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
+use search::geo::GeoPoint;
+use search::collectors::DocumentMatch;
+use search::aggregations::{Aggregation, Aggregator, Aggregations, Bucket, DocValues};
+
+/// How distances between points are measured
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceType {
+    /// The great-circle distance
+    Arc,
+
+    /// Quicker, but only accurate over short distances
+    Plane,
+}
+
+impl DistanceType {
+    /// The distance between two points in meters
+    pub fn distance(&self, a: &GeoPoint, b: &GeoPoint) -> f64 {
+        match *self {
+            DistanceType::Arc => a.arc_distance(b),
+            DistanceType::Plane => a.plane_distance(b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceRange {
+    /// The key of the bucket, generated from the bounds if not set
+    pub key: Option<String>,
+
+    /// The distance the range starts at (inclusive)
+    pub from: Option<f64>,
+
+    /// The distance the range ends at (exclusive)
+    pub to: Option<f64>,
+}
+
+impl DistanceRange {
+    fn contains(&self, distance: f64) -> bool {
+        self.from.map(|from| distance >= from).unwrap_or(true) && self.to.map(|to| distance < to).unwrap_or(true)
+    }
+
+    /// The key of the bucket, for example "100.0-300.0" or "300.0-*"
+    fn key(&self) -> String {
+        fn format_bound(bound: Option<f64>) -> String {
+            match bound {
+                Some(bound) => format!("{:?}", bound),
+                None => "*".to_string(),
+            }
+        }
+
+        match self.key {
+            Some(ref key) => key.clone(),
+            None => format!("{}-{}", format_bound(self.from), format_bound(self.to)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GeoDistanceAggregation {
+    /// The geo point field, None if it isn't in the index
+    pub field: Option<FieldId>,
+
+    pub origin: GeoPoint,
+
+    /// The number of meters in the unit of the ranges
+    pub unit: f64,
+
+    pub distance_type: DistanceType,
+    pub ranges: Vec<DistanceRange>,
+    pub sub_aggregations: Aggregations,
+}
+
+impl Aggregation for GeoDistanceAggregation {
+    fn create_aggregator<'a>(&'a self) -> Box<Aggregator + 'a> {
+        Box::new(GeoDistanceAggregator {
+            aggregation: self,
+            buckets: self.ranges.iter().map(|_| Bucket::new(&self.sub_aggregations)).collect(),
+        })
+    }
+}
+
+struct GeoDistanceAggregator<'a> {
+    aggregation: &'a GeoDistanceAggregation,
+
+    /// A bucket for each range, in the same order
+    buckets: Vec<Bucket<'a>>,
+}
+
+impl<'a> Aggregator for GeoDistanceAggregator<'a> {
+    fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues) {
+        let aggregation = self.aggregation;
+        let field = match aggregation.field {
+            Some(field) => field,
+            None => return,
+        };
+
+        let distances = doc_values.doc_values(field, DocId::from_u64(doc.doc_id())).iter().filter_map(|value| {
+            match *value {
+                FieldValue::GeoPoint(ref point) => Some(aggregation.distance_type.distance(&aggregation.origin, point) / aggregation.unit),
+                _ => None,
+            }
+        }).collect::<Vec<_>>();
+
+        for (range, bucket) in aggregation.ranges.iter().zip(self.buckets.iter_mut()) {
+            if distances.iter().any(|distance| range.contains(*distance)) {
+                bucket.collect(doc, doc_values);
+            }
+        }
+    }
+
+    fn finish(&mut self, doc_values: &DocValues) {
+        for bucket in self.buckets.iter_mut() {
+            bucket.finish(doc_values);
+        }
+    }
+
+    fn result(&self) -> Json {
+        let mut buckets_json = self.aggregation.ranges.iter().zip(self.buckets.iter()).map(|(range, bucket)| {
+            let mut json = bucket.to_json();
+            json.insert("key".to_string(), Json::String(range.key()));
+
+            if let Some(from) = range.from {
+                json.insert("from".to_string(), Json::from(from));
+            }
+
+            if let Some(to) = range.to {
+                json.insert("to".to_string(), Json::from(to));
+            }
+
+            json
+        }).collect::<Vec<_>>();
+
+        self.aggregation.sub_aggregations.run_pipelines(&mut buckets_json);
+        json!({"buckets": buckets_json})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::document::{DocId, FieldValue};
+    use search::schema::FieldId;
+    use search::geo::GeoPoint;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::{Aggregations, AggregationsCollector};
+    use search::aggregations::geohash_grid::GeohashGridAggregation;
+
+    use super::{GeoDistanceAggregation, DistanceRange, DistanceType};
+
+    fn point(lon: f64, lat: f64) -> FieldValue {
+        FieldValue::GeoPoint(GeoPoint::new(lon, lat))
+    }
+
+    fn range(from: Option<f64>, to: Option<f64>) -> DistanceRange {
+        DistanceRange {
+            key: None,
+            from: from,
+            to: to,
+        }
+    }
+
+    fn run(aggregation: GeoDistanceAggregation, docs: Vec<Vec<FieldValue>>) -> ::serde_json::Value {
+        let mut aggregations = Aggregations::new();
+        aggregations.push("rings".to_string(), Box::new(aggregation));
+
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| docs[doc_id.1 as usize].clone());
+        for doc_id in 0..docs.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()
+    }
+
+    fn cities() -> Vec<Vec<FieldValue>> {
+        vec![
+            vec![point(4.8952, 52.3702)],  // Amsterdam
+            vec![point(5.1214, 52.0907)],  // Utrecht, 35km away
+            vec![point(2.3522, 48.8566)],  // Paris, 430km away
+            vec![point(13.4050, 52.5200)],  // Berlin, 577km away
+        ]
+    }
+
+    #[test]
+    fn test_geo_distance() {
+        let result = run(GeoDistanceAggregation {
+            field: Some(FieldId(1)),
+            origin: GeoPoint::new(4.8952, 52.3702),
+            unit: 1000.0,
+            distance_type: DistanceType::Arc,
+            ranges: vec![
+                range(None, Some(100.0)),
+                range(Some(100.0), Some(500.0)),
+                range(Some(500.0), None),
+            ],
+            sub_aggregations: Aggregations::new(),
+        }, cities());
+
+        assert_eq!(result, json!({
+            "rings": {
+                "buckets": [
+                    {"key": "*-100.0", "to": 100.0, "doc_count": 2},
+                    {"key": "100.0-500.0", "from": 100.0, "to": 500.0, "doc_count": 1},
+                    {"key": "500.0-*", "from": 500.0, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_geo_distance_counts_documents_once_per_range() {
+        let result = run(GeoDistanceAggregation {
+            field: Some(FieldId(1)),
+            origin: GeoPoint::new(4.8952, 52.3702),
+            unit: 1000.0,
+            distance_type: DistanceType::Plane,
+            ranges: vec![
+                DistanceRange {
+                    key: Some("near".to_string()),
+                    from: None,
+                    to: Some(100.0),
+                },
+            ],
+            sub_aggregations: Aggregations::new(),
+        }, vec![
+            vec![point(4.8952, 52.3702), point(5.1214, 52.0907)],
+        ]);
+
+        assert_eq!(result, json!({
+            "rings": {
+                "buckets": [
+                    {"key": "near", "to": 100.0, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_geo_distance_with_sub_aggregations() {
+        let mut sub_aggregations = Aggregations::new();
+        sub_aggregations.push("cells".to_string(), Box::new(GeohashGridAggregation {
+            field: Some(FieldId(1)),
+            precision: 3,
+            size: 10,
+            sub_aggregations: Aggregations::new(),
+        }));
+
+        let result = run(GeoDistanceAggregation {
+            field: Some(FieldId(1)),
+            origin: GeoPoint::new(4.8952, 52.3702),
+            unit: 1000.0,
+            distance_type: DistanceType::Arc,
+            ranges: vec![
+                range(Some(100.0), None),
+            ],
+            sub_aggregations: sub_aggregations,
+        }, cities());
+
+        assert_eq!(result, json!({
+            "rings": {
+                "buckets": [
+                    {
+                        "key": "100.0-*",
+                        "from": 100.0,
+                        "doc_count": 2,
+                        "cells": {
+                            "buckets": [
+                                {"key": "u09", "doc_count": 1},
+                                {"key": "u33", "doc_count": 1},
+                            ]
+                        }
+                    },
+                ]
+            }
+        }));
+    }
+}
@@ -0,0 +1,206 @@
+//! Groups documents by the geohash cells of the points in a geo point field
+//!
+//! Documents with several points are put in the bucket of each cell they have a point
+//! in. The buckets with the most documents are returned first.
+
+use std::collections::HashMap;
+
+use serde_json::Value as Json;
+
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
+use search::collectors::DocumentMatch;
+use search::aggregations::{Aggregation, Aggregator, Aggregations, Bucket, DocValues};
+
+/// The longest geohash that documents can be grouped by (cells of a few centimeters)
+pub const MAX_PRECISION: usize = 12;
+
+#[derive(Debug)]
+pub struct GeohashGridAggregation {
+    /// The geo point field, None if it isn't in the index
+    pub field: Option<FieldId>,
+
+    /// The length of the geohash of each cell
+    pub precision: usize,
+
+    /// The maximum number of buckets to return
+    pub size: usize,
+
+    pub sub_aggregations: Aggregations,
+}
+
+impl Aggregation for GeohashGridAggregation {
+    fn create_aggregator<'a>(&'a self) -> Box<Aggregator + 'a> {
+        Box::new(GeohashGridAggregator {
+            aggregation: self,
+            buckets: HashMap::new(),
+        })
+    }
+}
+
+struct GeohashGridAggregator<'a> {
+    aggregation: &'a GeohashGridAggregation,
+    buckets: HashMap<String, Bucket<'a>>,
+}
+
+impl<'a> Aggregator for GeohashGridAggregator<'a> {
+    fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues) {
+        let aggregation = self.aggregation;
+        let field = match aggregation.field {
+            Some(field) => field,
+            None => return,
+        };
+
+        // Documents with several points in the same cell are only counted once
+        let mut cells = doc_values.doc_values(field, DocId::from_u64(doc.doc_id())).iter().filter_map(|value| {
+            match *value {
+                FieldValue::GeoPoint(ref point) => Some(point.geohash(aggregation.precision)),
+                _ => None,
+            }
+        }).collect::<Vec<_>>();
+        cells.sort();
+        cells.dedup();
+
+        for cell in cells {
+            self.buckets.entry(cell).or_insert_with(|| Bucket::new(&aggregation.sub_aggregations)).collect(doc, doc_values);
+        }
+    }
+
+    fn finish(&mut self, doc_values: &DocValues) {
+        for bucket in self.buckets.values_mut() {
+            bucket.finish(doc_values);
+        }
+    }
+
+    fn result(&self) -> Json {
+        // The buckets with the most documents come first
+        let mut buckets = self.buckets.iter().collect::<Vec<_>>();
+        buckets.sort_by(|a, b| b.1.doc_count().cmp(&a.1.doc_count()).then_with(|| a.0.cmp(b.0)));
+        buckets.truncate(self.aggregation.size);
+
+        let mut buckets_json = buckets.into_iter().map(|(cell, bucket)| {
+            let mut json = bucket.to_json();
+            json.insert("key".to_string(), Json::String(cell.clone()));
+            json
+        }).collect::<Vec<_>>();
+
+        self.aggregation.sub_aggregations.run_pipelines(&mut buckets_json);
+        json!({"buckets": buckets_json})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::document::{DocId, FieldValue};
+    use search::schema::FieldId;
+    use search::geo::GeoPoint;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::{Aggregations, AggregationsCollector};
+
+    use super::GeohashGridAggregation;
+
+    fn amsterdam() -> FieldValue {
+        FieldValue::GeoPoint(GeoPoint::new(4.8952, 52.3702))
+    }
+
+    fn utrecht() -> FieldValue {
+        FieldValue::GeoPoint(GeoPoint::new(5.1214, 52.0907))
+    }
+
+    fn berlin() -> FieldValue {
+        FieldValue::GeoPoint(GeoPoint::new(13.4050, 52.5200))
+    }
+
+    fn run(aggregation: GeohashGridAggregation, docs: Vec<Vec<FieldValue>>) -> ::serde_json::Value {
+        let mut aggregations = Aggregations::new();
+        aggregations.push("cells".to_string(), Box::new(aggregation));
+
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| docs[doc_id.1 as usize].clone());
+        for doc_id in 0..docs.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()
+    }
+
+    #[test]
+    fn test_geohash_grid() {
+        let result = run(GeohashGridAggregation {
+            field: Some(FieldId(1)),
+            precision: 3,
+            size: 10,
+            sub_aggregations: Aggregations::new(),
+        }, vec![
+            vec![amsterdam()],
+            vec![utrecht()],
+            vec![berlin()],
+            vec![],
+        ]);
+
+        assert_eq!(result, json!({
+            "cells": {
+                "buckets": [
+                    {"key": "u17", "doc_count": 2},
+                    {"key": "u33", "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_geohash_grid_counts_documents_once_per_cell() {
+        let result = run(GeohashGridAggregation {
+            field: Some(FieldId(1)),
+            precision: 3,
+            size: 10,
+            sub_aggregations: Aggregations::new(),
+        }, vec![
+            vec![amsterdam(), utrecht(), berlin()],
+        ]);
+
+        assert_eq!(result, json!({
+            "cells": {
+                "buckets": [
+                    {"key": "u17", "doc_count": 1},
+                    {"key": "u33", "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_geohash_grid_size() {
+        let result = run(GeohashGridAggregation {
+            field: Some(FieldId(1)),
+            precision: 5,
+            size: 1,
+            sub_aggregations: Aggregations::new(),
+        }, vec![
+            vec![amsterdam()],
+            vec![utrecht()],
+            vec![utrecht()],
+        ]);
+
+        assert_eq!(result, json!({
+            "cells": {
+                "buckets": [
+                    {"key": "u178k", "doc_count": 2},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_geohash_grid_missing_field() {
+        let result = run(GeohashGridAggregation {
+            field: None,
+            precision: 3,
+            size: 10,
+            sub_aggregations: Aggregations::new(),
+        }, vec![
+            vec![amsterdam()],
+        ]);
+
+        assert_eq!(result, json!({"cells": {"buckets": []}}));
+    }
+}
@@ -0,0 +1,185 @@
+//! Groups documents by fixed size intervals of the values of a numeric field
+//!
+//! Each value is rounded down to a multiple of the interval. Buckets are returned in
+//! order and, by default, empty buckets are added between the first and last buckets
+//! so there aren't any gaps in the histogram.
+
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value as Json};
+
+use search::document::DocId;
+use search::schema::FieldId;
+use search::collectors::DocumentMatch;
+use search::aggregations::{Aggregation, Aggregator, Aggregations, Bucket, DocValues};
+use search::aggregations::range::numeric_value;
+use search::aggregations::date_histogram::MAX_EMPTY_BUCKETS;
+
+#[derive(Debug)]
+pub struct HistogramAggregation {
+    /// The integer or float field, None if it isn't in the index
+    pub field: Option<FieldId>,
+
+    /// The size of each bucket, this must be positive
+    pub interval: f64,
+
+    /// Buckets with fewer documents are left out, gaps are only filled if this is 0
+    pub min_doc_count: u64,
+
+    pub sub_aggregations: Aggregations,
+}
+
+impl Aggregation for HistogramAggregation {
+    fn create_aggregator<'a>(&'a self) -> Box<Aggregator + 'a> {
+        Box::new(HistogramAggregator {
+            aggregation: self,
+            buckets: BTreeMap::new(),
+        })
+    }
+}
+
+struct HistogramAggregator<'a> {
+    aggregation: &'a HistogramAggregation,
+
+    /// The buckets, keyed by the number of intervals their start is from zero
+    buckets: BTreeMap<i64, Bucket<'a>>,
+}
+
+impl<'a> HistogramAggregator<'a> {
+    fn bucket_json(&self, index: i64, bucket: &Bucket) -> Map<String, Json> {
+        let mut json = bucket.to_json();
+        json.insert("key".to_string(), Json::from(index as f64 * self.aggregation.interval));
+        json
+    }
+}
+
+impl<'a> Aggregator for HistogramAggregator<'a> {
+    fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues) {
+        let aggregation = self.aggregation;
+        let field = match aggregation.field {
+            Some(field) => field,
+            None => return,
+        };
+
+        // A document with many values in the same interval is only counted once
+        let mut indexes = doc_values.doc_values(field, DocId::from_u64(doc.doc_id())).iter()
+            .filter_map(numeric_value)
+            .map(|value| (value / aggregation.interval).floor() as i64)
+            .collect::<Vec<_>>();
+        indexes.sort();
+        indexes.dedup();
+
+        for index in indexes {
+            self.buckets.entry(index).or_insert_with(|| Bucket::new(&aggregation.sub_aggregations)).collect(doc, doc_values);
+        }
+    }
+
+    fn finish(&mut self, doc_values: &DocValues) {
+        for bucket in self.buckets.values_mut() {
+            bucket.finish(doc_values);
+        }
+    }
+
+    fn result(&self) -> Json {
+        let aggregation = self.aggregation;
+        let empty_bucket = Bucket::new(&aggregation.sub_aggregations);
+        let mut empty_buckets = 0;
+        let mut next_index = None;
+
+        let mut buckets_json = Vec::new();
+        for (&index, bucket) in self.buckets.iter() {
+            if aggregation.min_doc_count == 0 {
+                // Fill the gap since the previous bucket
+                if let Some(mut gap_index) = next_index {
+                    while gap_index < index && empty_buckets < MAX_EMPTY_BUCKETS {
+                        buckets_json.push(self.bucket_json(gap_index, &empty_bucket));
+                        gap_index += 1;
+                        empty_buckets += 1;
+                    }
+                }
+
+                next_index = Some(index + 1);
+            }
+
+            if bucket.doc_count() >= aggregation.min_doc_count {
+                buckets_json.push(self.bucket_json(index, bucket));
+            }
+        }
+
+        aggregation.sub_aggregations.run_pipelines(&mut buckets_json);
+        json!({"buckets": buckets_json})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::document::{DocId, FieldValue};
+    use search::schema::FieldId;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::{Aggregations, AggregationsCollector};
+
+    use super::HistogramAggregation;
+
+    fn run(aggregation: HistogramAggregation) -> ::serde_json::Value {
+        let docs = vec![
+            vec![FieldValue::Integer(3)],
+            vec![FieldValue::Integer(7), FieldValue::Integer(8)],
+            vec![FieldValue::Float(31.5)],
+            vec![FieldValue::Integer(-2)],
+            vec![],
+        ];
+
+        let mut aggregations = Aggregations::new();
+        aggregations.push("prices".to_string(), Box::new(aggregation));
+
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| docs[doc_id.1 as usize].clone());
+        for doc_id in 0..docs.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()
+    }
+
+    #[test]
+    fn test_histogram() {
+        let result = run(HistogramAggregation {
+            field: Some(FieldId(1)),
+            interval: 10.0,
+            min_doc_count: 0,
+            sub_aggregations: Aggregations::new(),
+        });
+
+        assert_eq!(result, json!({
+            "prices": {
+                "buckets": [
+                    {"key": -10.0, "doc_count": 1},
+                    {"key": 0.0, "doc_count": 2},
+                    {"key": 10.0, "doc_count": 0},
+                    {"key": 20.0, "doc_count": 0},
+                    {"key": 30.0, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_min_doc_count() {
+        let result = run(HistogramAggregation {
+            field: Some(FieldId(1)),
+            interval: 5.0,
+            min_doc_count: 1,
+            sub_aggregations: Aggregations::new(),
+        });
+
+        assert_eq!(result, json!({
+            "prices": {
+                "buckets": [
+                    {"key": -5.0, "doc_count": 1},
+                    {"key": 0.0, "doc_count": 1},
+                    {"key": 5.0, "doc_count": 1},
+                    {"key": 30.0, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+}
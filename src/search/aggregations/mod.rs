@@ -0,0 +1,257 @@
+//! Aggregations summarise the documents that match a search
+//!
+//! Each aggregation creates an aggregator, which is given every match of the search
+//! along with a way to read doc values and builds the result from them. Bucket
+//! aggregations (such as geohash_grid) sort the matches into buckets, each bucket has
+//! its own aggregators for the sub-aggregations.
+//!
+//! Pipeline aggregations (such as derivative) are sub-aggregations of a bucket
+//! aggregation that run after it has built its buckets, see the `pipeline` module.
+//!
+//! Aggregators are finished once the search has collected every match, this lets
+//! aggregations that hold on to documents (such as sampler) pass on the last of them.
+//!
+//! AggregationsCollector runs a list of aggregations as part of a search.
+
+pub mod geohash_grid;
+pub mod geo_distance;
+pub mod composite;
+pub mod date_interval;
+pub mod date_histogram;
+pub mod pipeline;
+pub mod derivative;
+pub mod cumulative_sum;
+pub mod bucket_sort;
+pub mod sampler;
+pub mod terms;
+pub mod range;
+pub mod histogram;
+
+use std::fmt::Debug;
+
+use serde_json::{Map, Value as Json};
+
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
+use search::collectors::{Collector, DocumentMatch};
+use search::aggregations::pipeline::{PipelineAggregation, run_pipelines};
+use search::aggregations::sampler::SegmentSample;
+
+/// Reads all the values of a field of a document, usually from the reader's doc values
+pub trait DocValues {
+    fn doc_values(&self, field_id: FieldId, doc_id: DocId) -> Vec<FieldValue>;
+}
+
+impl<F: Fn(FieldId, DocId) -> Vec<FieldValue>> DocValues for F {
+    fn doc_values(&self, field_id: FieldId, doc_id: DocId) -> Vec<FieldValue> {
+        self(field_id, doc_id)
+    }
+}
+
+/// The settings of an aggregation
+pub trait Aggregation: Debug {
+    /// Creates an aggregator to run the aggregation over a set of documents
+    ///
+    /// Bucket aggregations call this on their sub-aggregations for each new bucket.
+    fn create_aggregator<'a>(&'a self) -> Box<Aggregator + 'a>;
+
+    /// If true, the aggregator needs the score of each match
+    fn needs_score(&self) -> bool {
+        false
+    }
+}
+
+/// Builds the result of an aggregation from the documents it's given
+pub trait Aggregator {
+    fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues);
+
+    /// Called once every document has been collected
+    ///
+    /// Bucket aggregations must pass this on to the aggregators of their buckets.
+    fn finish(&mut self, _doc_values: &DocValues) {}
+
+    fn result(&self) -> Json;
+}
+
+/// A list of named aggregations, for example the "aggs" of a search
+#[derive(Debug, Default)]
+pub struct Aggregations {
+    aggregations: Vec<(String, Box<Aggregation>)>,
+    pipelines: Vec<(String, Box<PipelineAggregation>)>,
+}
+
+impl Aggregations {
+    pub fn new() -> Aggregations {
+        Aggregations::default()
+    }
+
+    pub fn push(&mut self, name: String, aggregation: Box<Aggregation>) {
+        self.aggregations.push((name, aggregation));
+    }
+
+    pub fn push_pipeline(&mut self, name: String, pipeline: Box<PipelineAggregation>) {
+        self.pipelines.push((name, pipeline));
+    }
+
+    /// True if there are no aggregations to collect documents for
+    pub fn is_empty(&self) -> bool {
+        self.aggregations.is_empty()
+    }
+
+    /// True if any of the aggregations need the score of each match
+    pub fn needs_score(&self) -> bool {
+        self.aggregations.iter().any(|&(_, ref aggregation)| aggregation.needs_score())
+    }
+
+    /// Runs the pipeline aggregations over the buckets of the parent aggregation
+    ///
+    /// Bucket aggregations call this on their sub-aggregations before returning their result.
+    pub fn run_pipelines(&self, buckets: &mut Vec<Map<String, Json>>) {
+        run_pipelines(&self.pipelines, buckets);
+    }
+
+    pub fn create_aggregators(&self) -> AggregatorSet {
+        AggregatorSet {
+            aggregators: self.aggregations.iter().map(|&(ref name, ref aggregation)| {
+                (name.as_str(), aggregation.create_aggregator())
+            }).collect(),
+        }
+    }
+}
+
+/// The aggregators of a list of aggregations
+pub struct AggregatorSet<'a> {
+    aggregators: Vec<(&'a str, Box<Aggregator + 'a>)>,
+}
+
+impl<'a> AggregatorSet<'a> {
+    pub fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues) {
+        for &mut (_, ref mut aggregator) in self.aggregators.iter_mut() {
+            aggregator.collect(doc, doc_values);
+        }
+    }
+
+    pub fn finish(&mut self, doc_values: &DocValues) {
+        for &mut (_, ref mut aggregator) in self.aggregators.iter_mut() {
+            aggregator.finish(doc_values);
+        }
+    }
+
+    /// The result of each aggregation, keyed by its name
+    pub fn results(&self) -> Map<String, Json> {
+        let mut results = Map::new();
+
+        for &(name, ref aggregator) in self.aggregators.iter() {
+            results.insert(name.to_string(), aggregator.result());
+        }
+
+        results
+    }
+}
+
+/// A bucket of a bucket aggregation
+///
+/// Counts the documents in the bucket and passes them to the sub-aggregations.
+pub struct Bucket<'a> {
+    doc_count: u64,
+    sub_aggregators: AggregatorSet<'a>,
+}
+
+impl<'a> Bucket<'a> {
+    pub fn new(sub_aggregations: &'a Aggregations) -> Bucket<'a> {
+        Bucket {
+            doc_count: 0,
+            sub_aggregators: sub_aggregations.create_aggregators(),
+        }
+    }
+
+    pub fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues) {
+        self.doc_count += 1;
+        self.sub_aggregators.collect(doc, doc_values);
+    }
+
+    pub fn finish(&mut self, doc_values: &DocValues) {
+        self.sub_aggregators.finish(doc_values);
+    }
+
+    pub fn doc_count(&self) -> u64 {
+        self.doc_count
+    }
+
+    /// The document count and the results of the sub-aggregations, the aggregation adds the key
+    pub fn to_json(&self) -> Map<String, Json> {
+        let mut json = self.sub_aggregators.results();
+        json.insert("doc_count".to_string(), Json::from(self.doc_count));
+        json
+    }
+}
+
+/// Runs a list of aggregations over the matches of a search
+pub struct AggregationsCollector<'a, F: Fn(FieldId, DocId) -> Vec<FieldValue>> {
+    aggregators: AggregatorSet<'a>,
+    needs_score: bool,
+    load_values: F,
+
+    /// If set, only the best scoring matches of each segment are aggregated
+    sample: Option<SegmentSample>,
+
+    finished: bool,
+}
+
+impl<'a, F: Fn(FieldId, DocId) -> Vec<FieldValue>> AggregationsCollector<'a, F> {
+    pub fn new(aggregations: &'a Aggregations, load_values: F) -> AggregationsCollector<'a, F> {
+        AggregationsCollector {
+            aggregators: aggregations.create_aggregators(),
+            needs_score: aggregations.needs_score(),
+            load_values: load_values,
+            sample: None,
+            finished: false,
+        }
+    }
+
+    /// Only aggregates the best scoring `shard_size` matches of each segment (see the sampler module)
+    pub fn set_sample(mut self, shard_size: usize) -> AggregationsCollector<'a, F> {
+        self.sample = Some(SegmentSample::new(shard_size));
+        self
+    }
+
+    /// True if there are no aggregations to run
+    pub fn is_empty(&self) -> bool {
+        self.aggregators.aggregators.is_empty()
+    }
+
+    /// The result of each aggregation, keyed by its name
+    ///
+    /// The aggregators are finished first, so this must be called after the search.
+    pub fn results(&mut self) -> Json {
+        if !self.finished {
+            if let Some(ref mut sample) = self.sample {
+                for doc in sample.take() {
+                    self.aggregators.collect(doc, &self.load_values);
+                }
+            }
+
+            self.aggregators.finish(&self.load_values);
+            self.finished = true;
+        }
+
+        Json::Object(self.aggregators.results())
+    }
+}
+
+impl<'a, F: Fn(FieldId, DocId) -> Vec<FieldValue>> Collector for AggregationsCollector<'a, F> {
+    fn needs_score(&self) -> bool {
+        self.needs_score || self.sample.is_some()
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        match self.sample {
+            Some(ref mut sample) => {
+                for sampled_doc in sample.push(doc) {
+                    self.aggregators.collect(sampled_doc, &self.load_values);
+                }
+            }
+            None => self.aggregators.collect(doc, &self.load_values),
+        }
+    }
+}
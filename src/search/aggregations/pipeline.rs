@@ -0,0 +1,163 @@
+//! Pipeline aggregations work on the results of the buckets of their parent aggregation
+//!
+//! They're run once the parent has built its buckets, for example to add the change
+//! in a value between consecutive buckets of a date histogram, or to sort the buckets.
+//! They read values from each bucket through a "buckets path".
+
+use std::fmt::Debug;
+
+use serde_json::{Map, Value as Json};
+
+/// The settings of a pipeline aggregation
+pub trait PipelineAggregation: Debug {
+    /// The paths of the values this reads from each bucket
+    fn buckets_paths(&self) -> Vec<&str>;
+
+    /// True if this changes which buckets there are or their order, rather than adding values
+    fn reorders_buckets(&self) -> bool {
+        false
+    }
+
+    /// Changes the buckets of the parent, eg adding a value to each under the given name
+    fn apply(&self, name: &str, buckets: &mut Vec<Map<String, Json>>);
+}
+
+/// What to do with buckets that don't have the value a pipeline reads
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GapPolicy {
+    Skip,
+    InsertZeros,
+}
+
+/// Reads a number from the results of a bucket
+///
+/// "_count" is the number of documents in the bucket and "_key" is its key. Otherwise
+/// the path names an aggregation in the bucket (with ">" between the names of nested
+/// aggregations), optionally followed by "." and the name of one of its values (eg,
+/// "stats.avg"). The "value" of the aggregation is read if no name is given.
+pub fn read_bucket_path(bucket: &Map<String, Json>, path: &str) -> Option<f64> {
+    match path {
+        "_count" => return bucket.get("doc_count").and_then(Json::as_f64),
+        "_key" => return bucket.get("key").and_then(Json::as_f64),
+        _ => {}
+    }
+
+    let mut names = path.split('>').collect::<Vec<_>>();
+    let last_name = names.pop().unwrap_or(path);
+    let (last_name, value_name) = match last_name.find('.') {
+        Some(dot) => (&last_name[..dot], &last_name[dot + 1..]),
+        None => (last_name, "value"),
+    };
+
+    let mut object = bucket;
+    for name in names {
+        object = match object.get(name).and_then(Json::as_object) {
+            Some(object) => object,
+            None => return None,
+        };
+    }
+
+    object.get(last_name).and_then(|aggregation| aggregation.get(value_name)).and_then(Json::as_f64)
+}
+
+/// The name of the aggregation a buckets path reads from
+fn path_aggregation_name(path: &str) -> &str {
+    match path.find(|c| c == '>' || c == '.') {
+        Some(end) => &path[..end],
+        None => path,
+    }
+}
+
+/// Runs pipeline aggregations over the buckets of their parent
+///
+/// A pipeline that reads the result of another runs after it, and pipelines that sort
+/// or remove buckets run after the ones that add values to them. Otherwise, they run in
+/// the order they were given.
+pub fn run_pipelines(pipelines: &[(String, Box<PipelineAggregation>)], buckets: &mut Vec<Map<String, Json>>) {
+    let mut remaining = pipelines.iter().collect::<Vec<_>>();
+
+    while !remaining.is_empty() {
+        // If every pipeline that's left reads another one (they refer to each other), run
+        // the first anyway. It won't find the values it reads.
+        let next = remaining.iter().position(|&&(_, ref pipeline)| {
+            if pipeline.reorders_buckets() && remaining.iter().any(|&&(_, ref other)| !other.reorders_buckets()) {
+                return false;
+            }
+
+            pipeline.buckets_paths().iter().all(|path| {
+                let name = path_aggregation_name(path);
+                !remaining.iter().any(|&&(ref other_name, _)| other_name == name)
+            })
+        }).unwrap_or(0);
+
+        let &(ref name, ref pipeline) = remaining.remove(next);
+        pipeline.apply(name, buckets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Map, Value as Json};
+
+    use super::{PipelineAggregation, read_bucket_path, run_pipelines};
+
+    fn bucket(json: Json) -> Map<String, Json> {
+        json.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_read_bucket_path() {
+        let bucket = bucket(json!({
+            "key": 1483228800000i64,
+            "doc_count": 3,
+            "total": {"value": 12.5},
+            "stats": {"avg": 4.0},
+            "sample": {"doc_count": 2, "total": {"value": 7}},
+        }));
+
+        assert_eq!(read_bucket_path(&bucket, "_count"), Some(3.0));
+        assert_eq!(read_bucket_path(&bucket, "_key"), Some(1483228800000.0));
+        assert_eq!(read_bucket_path(&bucket, "total"), Some(12.5));
+        assert_eq!(read_bucket_path(&bucket, "total.value"), Some(12.5));
+        assert_eq!(read_bucket_path(&bucket, "stats.avg"), Some(4.0));
+        assert_eq!(read_bucket_path(&bucket, "sample>total"), Some(7.0));
+        assert_eq!(read_bucket_path(&bucket, "stats"), None);
+        assert_eq!(read_bucket_path(&bucket, "missing>total"), None);
+    }
+
+    /// Adds one to the value it reads
+    #[derive(Debug)]
+    struct Increment(&'static str);
+
+    impl PipelineAggregation for Increment {
+        fn buckets_paths(&self) -> Vec<&str> {
+            vec![self.0]
+        }
+
+        fn apply(&self, name: &str, buckets: &mut Vec<Map<String, Json>>) {
+            for bucket in buckets.iter_mut() {
+                let value = read_bucket_path(bucket, self.0).map(|value| value + 1.0);
+                bucket.insert(name.to_string(), json!({"value": value}));
+            }
+        }
+    }
+
+    #[test]
+    fn test_pipelines_run_after_the_pipelines_they_read() {
+        let mut buckets = vec![bucket(json!({"key": 1, "doc_count": 3}))];
+
+        run_pipelines(&[
+            ("a".to_string(), Box::new(Increment("b")) as Box<PipelineAggregation>),
+            ("b".to_string(), Box::new(Increment("c.value")) as Box<PipelineAggregation>),
+            ("c".to_string(), Box::new(Increment("_count")) as Box<PipelineAggregation>),
+        ], &mut buckets);
+
+        assert_eq!(Json::Object(buckets[0].clone()), json!({
+            "key": 1,
+            "doc_count": 3,
+            "a": {"value": 6.0},
+            "b": {"value": 5.0},
+            "c": {"value": 4.0},
+        }));
+    }
+}
@@ -0,0 +1,208 @@
+//! Groups documents by ranges of the values of a numeric field
+//!
+//! A document is in the bucket of a range if any of its values are in the range, so
+//! the buckets can overlap. Buckets are returned in the order the ranges were given.
+
+use serde_json::Value as Json;
+
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
+use search::collectors::DocumentMatch;
+use search::aggregations::{Aggregation, Aggregator, Aggregations, Bucket, DocValues};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumericRange {
+    /// The key of the bucket, generated from the bounds if not set
+    pub key: Option<String>,
+
+    /// The value the range starts at (inclusive)
+    pub from: Option<f64>,
+
+    /// The value the range ends at (exclusive)
+    pub to: Option<f64>,
+}
+
+impl NumericRange {
+    fn contains(&self, value: f64) -> bool {
+        self.from.map(|from| value >= from).unwrap_or(true) && self.to.map(|to| value < to).unwrap_or(true)
+    }
+
+    /// The key of the bucket, for example "10.0-20.0" or "*-10.0"
+    fn key(&self) -> String {
+        fn format_bound(bound: Option<f64>) -> String {
+            match bound {
+                Some(bound) => format!("{:?}", bound),
+                None => "*".to_string(),
+            }
+        }
+
+        match self.key {
+            Some(ref key) => key.clone(),
+            None => format!("{}-{}", format_bound(self.from), format_bound(self.to)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RangeAggregation {
+    /// The integer or float field, None if it isn't in the index
+    pub field: Option<FieldId>,
+
+    pub ranges: Vec<NumericRange>,
+    pub sub_aggregations: Aggregations,
+}
+
+impl Aggregation for RangeAggregation {
+    fn create_aggregator<'a>(&'a self) -> Box<Aggregator + 'a> {
+        Box::new(RangeAggregator {
+            aggregation: self,
+            buckets: self.ranges.iter().map(|_| Bucket::new(&self.sub_aggregations)).collect(),
+        })
+    }
+}
+
+/// The value of a numeric field as a float, None for other types of value
+pub fn numeric_value(value: &FieldValue) -> Option<f64> {
+    match *value {
+        FieldValue::Integer(value) => Some(value as f64),
+        FieldValue::Float(value) => Some(value),
+        _ => None,
+    }
+}
+
+struct RangeAggregator<'a> {
+    aggregation: &'a RangeAggregation,
+
+    /// A bucket for each range, in the same order
+    buckets: Vec<Bucket<'a>>,
+}
+
+impl<'a> Aggregator for RangeAggregator<'a> {
+    fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues) {
+        let aggregation = self.aggregation;
+        let field = match aggregation.field {
+            Some(field) => field,
+            None => return,
+        };
+
+        let values = doc_values.doc_values(field, DocId::from_u64(doc.doc_id())).iter().filter_map(numeric_value).collect::<Vec<_>>();
+
+        for (range, bucket) in aggregation.ranges.iter().zip(self.buckets.iter_mut()) {
+            if values.iter().any(|value| range.contains(*value)) {
+                bucket.collect(doc, doc_values);
+            }
+        }
+    }
+
+    fn finish(&mut self, doc_values: &DocValues) {
+        for bucket in self.buckets.iter_mut() {
+            bucket.finish(doc_values);
+        }
+    }
+
+    fn result(&self) -> Json {
+        let mut buckets_json = self.aggregation.ranges.iter().zip(self.buckets.iter()).map(|(range, bucket)| {
+            let mut json = bucket.to_json();
+            json.insert("key".to_string(), Json::String(range.key()));
+
+            if let Some(from) = range.from {
+                json.insert("from".to_string(), Json::from(from));
+            }
+
+            if let Some(to) = range.to {
+                json.insert("to".to_string(), Json::from(to));
+            }
+
+            json
+        }).collect::<Vec<_>>();
+
+        self.aggregation.sub_aggregations.run_pipelines(&mut buckets_json);
+        json!({"buckets": buckets_json})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::document::{DocId, FieldValue};
+    use search::schema::FieldId;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::{Aggregations, AggregationsCollector};
+
+    use super::{RangeAggregation, NumericRange};
+
+    fn range(from: Option<f64>, to: Option<f64>) -> NumericRange {
+        NumericRange {
+            key: None,
+            from: from,
+            to: to,
+        }
+    }
+
+    fn run(aggregation: RangeAggregation, docs: Vec<Vec<FieldValue>>) -> ::serde_json::Value {
+        let mut aggregations = Aggregations::new();
+        aggregations.push("prices".to_string(), Box::new(aggregation));
+
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| docs[doc_id.1 as usize].clone());
+        for doc_id in 0..docs.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()
+    }
+
+    #[test]
+    fn test_range() {
+        let result = run(RangeAggregation {
+            field: Some(FieldId(1)),
+            ranges: vec![
+                range(None, Some(10.0)),
+                range(Some(10.0), Some(20.0)),
+                NumericRange {
+                    key: Some("expensive".to_string()),
+                    from: Some(20.0),
+                    to: None,
+                },
+            ],
+            sub_aggregations: Aggregations::new(),
+        }, vec![
+            vec![FieldValue::Integer(5)],
+            vec![FieldValue::Integer(10)],
+            vec![FieldValue::Float(19.99)],
+            vec![FieldValue::Integer(100)],
+            vec![],
+        ]);
+
+        assert_eq!(result, json!({
+            "prices": {
+                "buckets": [
+                    {"key": "*-10.0", "to": 10.0, "doc_count": 1},
+                    {"key": "10.0-20.0", "from": 10.0, "to": 20.0, "doc_count": 2},
+                    {"key": "expensive", "from": 20.0, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_range_counts_documents_once_per_range() {
+        let result = run(RangeAggregation {
+            field: Some(FieldId(1)),
+            ranges: vec![
+                range(None, Some(10.0)),
+                range(Some(5.0), None),
+            ],
+            sub_aggregations: Aggregations::new(),
+        }, vec![
+            vec![FieldValue::Integer(1), FieldValue::Integer(2), FieldValue::Integer(7)],
+        ]);
+
+        assert_eq!(result, json!({
+            "prices": {
+                "buckets": [
+                    {"key": "*-10.0", "to": 10.0, "doc_count": 1},
+                    {"key": "5.0-*", "from": 5.0, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+}
@@ -0,0 +1,219 @@
+//! Limits the documents that sub-aggregations see to the best scoring ones
+//!
+//! The top `shard_size` matches of each segment are kept and passed on once the search
+//! has moved on to the next segment. This bounds the cost of expensive sub-aggregations
+//! on searches that match a huge number of documents, while keeping the documents that
+//! are most relevant to the query.
+//!
+//! Segments are searched one after another, so only one segment's sample is held at a
+//! time. The last segment's sample is passed on when the aggregator is finished.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use serde_json::Value as Json;
+
+use search::document::DocId;
+use search::collectors::DocumentMatch;
+use search::aggregations::{Aggregation, Aggregator, Aggregations, Bucket, DocValues};
+
+/// A match in a sample, the worst match is at the top of the heap
+struct SampledDoc(DocumentMatch);
+
+impl SampledDoc {
+    fn score(&self) -> f32 {
+        self.0.score().unwrap_or(0.0)
+    }
+}
+
+impl PartialEq for SampledDoc {
+    fn eq(&self, other: &SampledDoc) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SampledDoc {}
+
+impl PartialOrd for SampledDoc {
+    fn partial_cmp(&self, other: &SampledDoc) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SampledDoc {
+    fn cmp(&self, other: &SampledDoc) -> Ordering {
+        // Lower scores are "greater" so they're popped first, ties keep the earliest document
+        other.score().partial_cmp(&self.score()).unwrap_or(Ordering::Equal)
+            .then_with(|| self.0.doc_id().cmp(&other.0.doc_id()))
+    }
+}
+
+/// Keeps the best scoring matches of the segment that's being searched
+pub struct SegmentSample {
+    shard_size: usize,
+    segment: Option<u32>,
+    docs: BinaryHeap<SampledDoc>,
+}
+
+impl SegmentSample {
+    pub fn new(shard_size: usize) -> SegmentSample {
+        SegmentSample {
+            shard_size: shard_size,
+            segment: None,
+            docs: BinaryHeap::with_capacity(shard_size + 1),
+        }
+    }
+
+    /// Adds a match to the sample
+    ///
+    /// If the match is from a different segment, the sample of the previous segment is
+    /// returned first so it can be passed on.
+    pub fn push(&mut self, doc: DocumentMatch) -> Vec<DocumentMatch> {
+        let segment = (DocId::from_u64(doc.doc_id()).0).0;
+
+        let previous_sample = if self.segment != Some(segment) {
+            self.segment = Some(segment);
+            self.take()
+        } else {
+            Vec::new()
+        };
+
+        if self.shard_size > 0 {
+            self.docs.push(SampledDoc(doc));
+
+            if self.docs.len() > self.shard_size {
+                self.docs.pop();
+            }
+        }
+
+        previous_sample
+    }
+
+    /// Removes the sample of the current segment, in the order the documents were matched
+    pub fn take(&mut self) -> Vec<DocumentMatch> {
+        let mut docs = self.docs.drain().map(|doc| doc.0).collect::<Vec<_>>();
+        docs.sort_by_key(|doc| doc.doc_id());
+        docs
+    }
+}
+
+#[derive(Debug)]
+pub struct SamplerAggregation {
+    /// The number of best scoring documents to keep from each segment
+    pub shard_size: usize,
+
+    pub sub_aggregations: Aggregations,
+}
+
+impl Aggregation for SamplerAggregation {
+    fn create_aggregator<'a>(&'a self) -> Box<Aggregator + 'a> {
+        Box::new(SamplerAggregator {
+            sample: SegmentSample::new(self.shard_size),
+            bucket: Bucket::new(&self.sub_aggregations),
+        })
+    }
+
+    fn needs_score(&self) -> bool {
+        true
+    }
+}
+
+struct SamplerAggregator<'a> {
+    sample: SegmentSample,
+
+    /// Receives the sampled documents
+    bucket: Bucket<'a>,
+}
+
+impl<'a> Aggregator for SamplerAggregator<'a> {
+    fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues) {
+        for sampled_doc in self.sample.push(doc) {
+            self.bucket.collect(sampled_doc, doc_values);
+        }
+    }
+
+    fn finish(&mut self, doc_values: &DocValues) {
+        for sampled_doc in self.sample.take() {
+            self.bucket.collect(sampled_doc, doc_values);
+        }
+
+        self.bucket.finish(doc_values);
+    }
+
+    fn result(&self) -> Json {
+        Json::Object(self.bucket.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::document::{DocId, FieldValue};
+    use search::segment::SegmentId;
+    use search::schema::FieldId;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::{Aggregations, AggregationsCollector};
+    use search::aggregations::date_histogram::DateHistogramAggregation;
+    use search::aggregations::date_interval::DateInterval;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{SamplerAggregation, SegmentSample};
+
+    fn doc(segment: u32, ord: u16, score: f32) -> DocumentMatch {
+        DocumentMatch::new_scored(DocId(SegmentId(segment), ord).as_u64(), score)
+    }
+
+    fn ords(docs: Vec<DocumentMatch>) -> Vec<u16> {
+        docs.into_iter().map(|doc| DocId::from_u64(doc.doc_id()).1).collect()
+    }
+
+    #[test]
+    fn test_segment_sample() {
+        let mut sample = SegmentSample::new(2);
+
+        assert!(sample.push(doc(1, 0, 0.5)).is_empty());
+        assert!(sample.push(doc(1, 1, 2.0)).is_empty());
+        assert!(sample.push(doc(1, 2, 1.0)).is_empty());
+
+        // The best two documents of the first segment are given back when the next segment starts
+        assert_eq!(ords(sample.push(doc(2, 0, 0.1))), vec![1, 2]);
+        assert_eq!(ords(sample.take()), vec![0]);
+    }
+
+    #[test]
+    fn test_sampler() {
+        let mut sub_aggregations = Aggregations::new();
+        sub_aggregations.push("per_day".to_string(), Box::new(DateHistogramAggregation {
+            field: Some(FieldId(1)),
+            interval: DateInterval::Fixed(86400000),
+            min_doc_count: 1,
+            sub_aggregations: Aggregations::new(),
+        }));
+
+        let mut aggregations = Aggregations::new();
+        aggregations.push("sample".to_string(), Box::new(SamplerAggregation {
+            shard_size: 1,
+            sub_aggregations: sub_aggregations,
+        }));
+
+        let dates = vec![Utc.ymd(2017, 1, 1).and_hms(0, 0, 0), Utc.ymd(2017, 1, 2).and_hms(0, 0, 0)];
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| vec![FieldValue::DateTime(dates[doc_id.1 as usize])]);
+        assert!(collector.needs_score());
+
+        // Only the best document of each segment is aggregated
+        collector.collect(doc(1, 0, 1.0));
+        collector.collect(doc(1, 1, 2.0));
+        collector.collect(doc(2, 1, 1.0));
+
+        assert_eq!(collector.results(), json!({
+            "sample": {
+                "doc_count": 2,
+                "per_day": {
+                    "buckets": [
+                        {"key": 1483315200000i64, "key_as_string": "2017-01-02T00:00:00.000Z", "doc_count": 2},
+                    ]
+                }
+            }
+        }));
+    }
+}
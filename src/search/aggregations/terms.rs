@@ -0,0 +1,277 @@
+//! Groups documents by the values of a field
+//!
+//! Documents with several values are put in the bucket of each value. The buckets with
+//! the most documents are returned first, the documents in the buckets that didn't make
+//! the cut are counted in "sum_other_doc_count".
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value as Json};
+
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
+use search::collectors::DocumentMatch;
+use search::aggregations::{Aggregation, Aggregator, Aggregations, Bucket, DocValues};
+use search::aggregations::date_interval::datetime_to_millis;
+use search::aggregations::date_histogram::format_key;
+
+/// The value of a field that a bucket is for
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum TermsKey {
+    Boolean(bool),
+    Integer(i64),
+
+    /// A date, in milliseconds since the epoch
+    Date(i64),
+
+    String(String),
+}
+
+impl TermsKey {
+    fn from_value(value: &FieldValue) -> Option<TermsKey> {
+        match *value {
+            FieldValue::Boolean(value) => Some(TermsKey::Boolean(value)),
+            FieldValue::Integer(value) => Some(TermsKey::Integer(value)),
+            FieldValue::DateTime(ref value) => Some(TermsKey::Date(datetime_to_millis(value))),
+            FieldValue::String(ref value) => Some(TermsKey::String(value.clone())),
+            _ => None,
+        }
+    }
+
+    /// Adds "key" (and "key_as_string" for booleans and dates) to the JSON of a bucket
+    fn add_to_json(&self, json: &mut Map<String, Json>) {
+        match *self {
+            TermsKey::Boolean(value) => {
+                json.insert("key".to_string(), Json::from(if value { 1 } else { 0 }));
+                json.insert("key_as_string".to_string(), Json::String(value.to_string()));
+            }
+            TermsKey::Integer(value) => {
+                json.insert("key".to_string(), Json::from(value));
+            }
+            TermsKey::Date(value) => {
+                json.insert("key".to_string(), Json::from(value));
+                json.insert("key_as_string".to_string(), Json::String(format_key(value)));
+            }
+            TermsKey::String(ref value) => {
+                json.insert("key".to_string(), Json::String(value.clone()));
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TermsAggregation {
+    /// The field, None if it isn't in the index
+    pub field: Option<FieldId>,
+
+    /// The maximum number of buckets to return
+    pub size: usize,
+
+    /// Buckets with fewer documents are left out
+    pub min_doc_count: u64,
+
+    pub sub_aggregations: Aggregations,
+}
+
+impl Aggregation for TermsAggregation {
+    fn create_aggregator<'a>(&'a self) -> Box<Aggregator + 'a> {
+        Box::new(TermsAggregator {
+            aggregation: self,
+            buckets: HashMap::new(),
+        })
+    }
+}
+
+struct TermsAggregator<'a> {
+    aggregation: &'a TermsAggregation,
+    buckets: HashMap<TermsKey, Bucket<'a>>,
+}
+
+impl<'a> Aggregator for TermsAggregator<'a> {
+    fn collect(&mut self, doc: DocumentMatch, doc_values: &DocValues) {
+        let aggregation = self.aggregation;
+        let field = match aggregation.field {
+            Some(field) => field,
+            None => return,
+        };
+
+        // Documents with the same value many times are only counted once
+        let mut keys = doc_values.doc_values(field, DocId::from_u64(doc.doc_id())).iter().filter_map(TermsKey::from_value).collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+
+        for key in keys {
+            self.buckets.entry(key).or_insert_with(|| Bucket::new(&aggregation.sub_aggregations)).collect(doc, doc_values);
+        }
+    }
+
+    fn finish(&mut self, doc_values: &DocValues) {
+        for bucket in self.buckets.values_mut() {
+            bucket.finish(doc_values);
+        }
+    }
+
+    fn result(&self) -> Json {
+        // The buckets with the most documents come first
+        let mut buckets = self.buckets.iter()
+            .filter(|&(_, bucket)| bucket.doc_count() >= self.aggregation.min_doc_count)
+            .collect::<Vec<_>>();
+        buckets.sort_by(|a, b| b.1.doc_count().cmp(&a.1.doc_count()).then_with(|| a.0.cmp(b.0)));
+
+        let sum_other_doc_count = buckets.iter().skip(self.aggregation.size).map(|&(_, bucket)| bucket.doc_count()).sum::<u64>();
+        buckets.truncate(self.aggregation.size);
+
+        let mut buckets_json = buckets.into_iter().map(|(key, bucket)| {
+            let mut json = bucket.to_json();
+            key.add_to_json(&mut json);
+            json
+        }).collect::<Vec<_>>();
+
+        self.aggregation.sub_aggregations.run_pipelines(&mut buckets_json);
+        json!({
+            "doc_count_error_upper_bound": 0,
+            "sum_other_doc_count": sum_other_doc_count,
+            "buckets": buckets_json,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use search::document::{DocId, FieldValue};
+    use search::schema::FieldId;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::aggregations::{Aggregations, AggregationsCollector};
+
+    use super::TermsAggregation;
+
+    fn string(value: &str) -> FieldValue {
+        FieldValue::String(value.to_string())
+    }
+
+    fn run(aggregation: TermsAggregation, docs: Vec<Vec<FieldValue>>) -> ::serde_json::Value {
+        let mut aggregations = Aggregations::new();
+        aggregations.push("tags".to_string(), Box::new(aggregation));
+
+        let mut collector = AggregationsCollector::new(&aggregations, |_, doc_id: DocId| docs[doc_id.1 as usize].clone());
+        for doc_id in 0..docs.len() {
+            collector.collect(DocumentMatch::new_unscored(doc_id as u64));
+        }
+
+        collector.results()
+    }
+
+    fn tags() -> Vec<Vec<FieldValue>> {
+        vec![
+            vec![string("rust"), string("search")],
+            vec![string("rust"), string("rust")],
+            vec![string("python")],
+            vec![string("search"), string("rust")],
+            vec![],
+        ]
+    }
+
+    #[test]
+    fn test_terms() {
+        let result = run(TermsAggregation {
+            field: Some(FieldId(1)),
+            size: 10,
+            min_doc_count: 1,
+            sub_aggregations: Aggregations::new(),
+        }, tags());
+
+        assert_eq!(result, json!({
+            "tags": {
+                "doc_count_error_upper_bound": 0,
+                "sum_other_doc_count": 0,
+                "buckets": [
+                    {"key": "rust", "doc_count": 3},
+                    {"key": "search", "doc_count": 2},
+                    {"key": "python", "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_terms_size() {
+        let result = run(TermsAggregation {
+            field: Some(FieldId(1)),
+            size: 1,
+            min_doc_count: 1,
+            sub_aggregations: Aggregations::new(),
+        }, tags());
+
+        assert_eq!(result["tags"]["buckets"], json!([{"key": "rust", "doc_count": 3}]));
+        assert_eq!(result["tags"]["sum_other_doc_count"], json!(3));
+    }
+
+    #[test]
+    fn test_terms_min_doc_count() {
+        let result = run(TermsAggregation {
+            field: Some(FieldId(1)),
+            size: 10,
+            min_doc_count: 2,
+            sub_aggregations: Aggregations::new(),
+        }, tags());
+
+        let keys = result["tags"]["buckets"].as_array().unwrap().iter().map(|bucket| bucket["key"].clone()).collect::<Vec<_>>();
+        assert_eq!(keys, vec![json!("rust"), json!("search")]);
+    }
+
+    #[test]
+    fn test_terms_keys() {
+        let result = run(TermsAggregation {
+            field: Some(FieldId(1)),
+            size: 10,
+            min_doc_count: 1,
+            sub_aggregations: Aggregations::new(),
+        }, vec![
+            vec![FieldValue::Integer(5), FieldValue::Boolean(true)],
+            vec![FieldValue::DateTime(Utc.ymd(2017, 1, 1).and_hms(0, 0, 0))],
+        ]);
+
+        assert_eq!(result["tags"]["buckets"], json!([
+            {"key": 1, "key_as_string": "true", "doc_count": 1},
+            {"key": 5, "doc_count": 1},
+            {"key": 1483228800000i64, "key_as_string": "2017-01-01T00:00:00.000Z", "doc_count": 1},
+        ]));
+    }
+
+    #[test]
+    fn test_terms_with_sub_aggregations() {
+        let mut sub_aggregations = Aggregations::new();
+        sub_aggregations.push("other_tags".to_string(), Box::new(TermsAggregation {
+            field: Some(FieldId(1)),
+            size: 10,
+            min_doc_count: 1,
+            sub_aggregations: Aggregations::new(),
+        }));
+
+        let result = run(TermsAggregation {
+            field: Some(FieldId(1)),
+            size: 1,
+            min_doc_count: 1,
+            sub_aggregations: sub_aggregations,
+        }, tags());
+
+        assert_eq!(result["tags"]["buckets"][0]["other_tags"]["buckets"], json!([
+            {"key": "rust", "doc_count": 3},
+            {"key": "search", "doc_count": 2},
+        ]));
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let result = run(TermsAggregation {
+            field: None,
+            size: 10,
+            min_doc_count: 1,
+            sub_aggregations: Aggregations::new(),
+        }, tags());
+
+        assert_eq!(result["tags"]["buckets"], json!([]));
+    }
+}
@@ -49,8 +49,10 @@ fn bench_insert_single_document(b: &mut Bencher) {
 
         store.insert_or_update_document(&Document {
             key: i.to_string(),
+            boost: 1.0f32,
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            doc_values: FnvHashMap::default(),
         });
     });
 }
@@ -83,8 +85,10 @@ fn bench_insert_documents_parallel(b: &mut Bencher) {
 
         docs.push(Document {
             key: (i + 1).to_string(),
+            boost: 1.0f32,
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            doc_values: FnvHashMap::default(),
         });
     }
 
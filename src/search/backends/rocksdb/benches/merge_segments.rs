@@ -45,8 +45,10 @@ fn bench_merge_segments(b: &mut Bencher) {
 
         store.insert_or_update_document(&Document {
             key: i.to_string(),
+            boost: 1.0f32,
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            doc_values: FnvHashMap::default(),
         });
     }
 
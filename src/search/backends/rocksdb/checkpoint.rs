@@ -0,0 +1,79 @@
+//! Consistent copies of a store
+//!
+//! The version of RocksDB that we link against doesn't have checkpoints, so the keys
+//! of every column family are copied from a snapshot into a new database. Unlike a
+//! real checkpoint, this doesn't hard link SST files so it takes as much space as the
+//! live data of the store.
+
+use std::path::Path;
+
+use rocksdb::{self, DB, DBRawIterator, WriteBatch};
+
+use super::RocksDBStore;
+use super::column_families;
+
+/// Number of keys to copy per write batch
+const CHECKPOINT_BATCH_SIZE: usize = 10000;
+
+/// Copies every key of an iterator into a column family of another database
+fn copy_keys(dest: &DB, cf_name: Option<&str>, mut iter: DBRawIterator) -> Result<u64, rocksdb::Error> {
+    let mut write_batch = WriteBatch::default();
+    let mut batch_size = 0;
+    let mut total = 0;
+
+    // Iterating from the first key doesn't use the prefix bloom filters, so this sees
+    // every key even in the column families that have them
+    iter.seek_to_first();
+    while iter.valid() {
+        let k = iter.key().unwrap();
+        let v = iter.value().unwrap();
+
+        match cf_name {
+            Some(cf_name) => try!(write_batch.put_cf(column_families::handle(dest, cf_name), &k, &v)),
+            None => try!(write_batch.put(&k, &v)),
+        }
+
+        batch_size += 1;
+        total += 1;
+
+        if batch_size >= CHECKPOINT_BATCH_SIZE {
+            try!(dest.write(write_batch));
+            write_batch = WriteBatch::default();
+            batch_size = 0;
+        }
+
+        iter.next();
+    }
+
+    try!(dest.write(write_batch));
+
+    Ok(total)
+}
+
+impl RocksDBStore {
+    /// Writes a copy of the store into a new directory, returns the number of keys copied
+    ///
+    /// Everything is read from one snapshot, so documents that are written while the copy
+    /// is made are either all in it or not at all. The copy can be opened like any other
+    /// store. Segments that are waiting to be purged are copied too, they're purged when
+    /// the copy is opened.
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<u64, String> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(format!("checkpoint directory already exists: {}", path.display()));
+        }
+
+        let mut opts = column_families::db_options();
+        opts.create_if_missing(true);
+        let dest = try!(DB::open_cf_descriptors(&opts, path, column_families::descriptors()));
+
+        let snapshot = self.db.snapshot();
+        let mut total = try!(copy_keys(&dest, None, snapshot.raw_iterator()));
+        for cf_name in column_families::NAMES.iter() {
+            let iter = try!(snapshot.raw_iterator_cf(column_families::handle(&self.db, cf_name)));
+            total += try!(copy_keys(&dest, Some(cf_name), iter));
+        }
+
+        Ok(total)
+    }
+}
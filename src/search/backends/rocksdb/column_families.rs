@@ -0,0 +1,175 @@
+//! Column family layout
+//!
+//! Each family of keys is stored in its own RocksDB column family so they can
+//! be tuned separately. The key prefixes from KeyBuilder are kept as they are,
+//! so keys look the same whichever column family they live in.
+//!
+//! | Column family | Keys                                                  |
+//! |---------------|-------------------------------------------------------|
+//! | default       | Metadata (.schema, etc), active segments, deletion lists, merge journal (j) |
+//! | terms         | Term dictionary (t)                                   |
+//! | postings      | Postings lists (d)                                    |
+//! | stored        | Stored field values (v)                               |
+//! | docindex      | Primary key index (k), document keys (i)              |
+//! | stats         | Segment statistics (s)                                |
+
+use rocksdb::{self, DB, Options, BlockBasedOptions, SliceTransform, ColumnFamily, ColumnFamilyDescriptor, WriteBatch};
+
+use super::merge_operator::{merge_keys, partial_merge_keys};
+
+pub const TERMS: &'static str = "terms";
+pub const POSTINGS: &'static str = "postings";
+pub const STORED: &'static str = "stored";
+pub const DOCINDEX: &'static str = "docindex";
+pub const STATS: &'static str = "stats";
+
+/// Every column family, other than the default one
+pub const NAMES: [&'static str; 5] = [TERMS, POSTINGS, STORED, DOCINDEX, STATS];
+
+/// The on-disk layout version written by this version of the store
+///
+/// Version 1 (which didn't write a version key) kept everything in the default column family
+/// Version 2 didn't have the reverse (doc id => key) entries in the document index
+/// Version 3 wrote the deletion lists of merged segments as serialized Roaring bitmaps
+pub const FORMAT_VERSION: u32 = 4;
+
+/// Number of keys to move per write batch when migrating from an old layout
+const MIGRATION_BATCH_SIZE: usize = 10000;
+
+/// Returns the column family a key with the given prefix byte belongs in
+fn column_family_for_prefix(prefix: u8) -> Option<&'static str> {
+    match prefix {
+        b't' => Some(TERMS),
+        b'd' => Some(POSTINGS),
+        b'v' => Some(STORED),
+        b'k' => Some(DOCINDEX),
+        b's' => Some(STATS),
+        _ => None,
+    }
+}
+
+/// Extracts the segment prefix from stored value and statistic keys
+///
+/// "v1/2/3/val" => "v1/"
+fn segment_prefix(key: &[u8]) -> Vec<u8> {
+    match key.iter().position(|b| *b == b'/') {
+        Some(pos) => key[..pos + 1].to_vec(),
+        None => key.to_vec(),
+    }
+}
+
+fn has_segment_prefix(key: &[u8]) -> bool {
+    key.iter().any(|b| *b == b'/')
+}
+
+fn base_options() -> Options {
+    let mut opts = Options::default();
+    opts.set_merge_operator("merge operator", merge_keys, Some(partial_merge_keys));
+    opts
+}
+
+fn column_family_options(name: &str) -> Options {
+    let mut opts = base_options();
+
+    match name {
+        POSTINGS | DOCINDEX => {
+            // These are only read by exact key. Whole-key bloom filters let us skip
+            // SST files that can't contain the key
+            let mut block_opts = BlockBasedOptions::default();
+            block_opts.set_bloom_filter(10, false);
+            block_opts.set_cache_index_and_filter_blocks(true);
+            opts.set_block_based_table_factory(&block_opts);
+        }
+        STORED | STATS => {
+            // These keys start with the segment id and are scanned one segment at a
+            // time when segments are merged and purged. A prefix bloom filter on the
+            // segment lets those scans skip files that don't have the segment
+            //
+            // Note: Iterators on these column families must not be relied upon to
+            // cross segment boundaries
+            let mut block_opts = BlockBasedOptions::default();
+            block_opts.set_bloom_filter(10, false);
+            block_opts.set_cache_index_and_filter_blocks(true);
+            opts.set_block_based_table_factory(&block_opts);
+            opts.set_prefix_extractor(SliceTransform::create("segment prefix", segment_prefix, Some(has_segment_prefix)));
+        }
+        _ => {}
+    }
+
+    opts
+}
+
+/// Options for opening the database
+pub fn db_options() -> Options {
+    let mut opts = base_options();
+    opts.create_missing_column_families(true);
+    opts
+}
+
+/// Descriptors for every column family in the store (including the default one)
+pub fn descriptors() -> Vec<ColumnFamilyDescriptor> {
+    vec![
+        ColumnFamilyDescriptor::new("default", base_options()),
+        ColumnFamilyDescriptor::new(TERMS, column_family_options(TERMS)),
+        ColumnFamilyDescriptor::new(POSTINGS, column_family_options(POSTINGS)),
+        ColumnFamilyDescriptor::new(STORED, column_family_options(STORED)),
+        ColumnFamilyDescriptor::new(DOCINDEX, column_family_options(DOCINDEX)),
+        ColumnFamilyDescriptor::new(STATS, column_family_options(STATS)),
+    ]
+}
+
+/// Retrieves the handle of a column family
+///
+/// Handles are looked up each time rather than being kept around as they cannot
+/// be shared between threads.
+pub fn handle(db: &DB, name: &str) -> ColumnFamily {
+    db.cf_handle(name).expect("column family missing from store")
+}
+
+/// Reads the format version of the store
+pub fn read_format_version(db: &DB) -> Result<u32, rocksdb::Error> {
+    match try!(db.get(b".format_version")) {
+        Some(version) => Ok(version.to_utf8().unwrap().parse::<u32>().unwrap()),
+        None => Ok(1),
+    }
+}
+
+pub fn write_format_version(db: &DB) -> Result<(), rocksdb::Error> {
+    db.put(b".format_version", FORMAT_VERSION.to_string().as_bytes())
+}
+
+/// Moves keys from a store that was written with everything in the default
+/// column family into their own column families
+///
+/// This is safe to re-run if it crashes half way through as keys are only
+/// deleted from the default column family in the same batch that writes them
+/// to their new home.
+pub fn migrate_from_default_column_family(db: &DB) -> Result<(), rocksdb::Error> {
+    let mut write_batch = WriteBatch::default();
+    let mut batch_size = 0;
+
+    let mut iter = db.raw_iterator();
+    iter.seek_to_first();
+    while iter.valid() {
+        let k = iter.key().unwrap();
+
+        if let Some(cf_name) = column_family_for_prefix(k[0]) {
+            let v = iter.value().unwrap();
+            try!(write_batch.put_cf(handle(db, cf_name), &k, &v));
+            try!(write_batch.delete(&k));
+            batch_size += 1;
+
+            if batch_size >= MIGRATION_BATCH_SIZE {
+                try!(db.write(write_batch));
+                write_batch = WriteBatch::default();
+                batch_size = 0;
+            }
+        }
+
+        iter.next();
+    }
+
+    try!(db.write(write_batch));
+
+    Ok(())
+}
@@ -0,0 +1,169 @@
+//! Analyses how much space each field takes up in a store
+//!
+//! Every key of the store is read and its size (the key plus the value) is counted
+//! against the field it belongs to. These are the sizes before RocksDB compresses
+//! them, so they're for comparing fields rather than adding up to the size on disk.
+
+use std::str;
+
+use rocksdb::{self, DBRawIterator};
+use fnv::FnvHashMap;
+
+use search::schema::FieldId;
+
+use super::RocksDBStore;
+use super::column_families;
+
+/// The space used by the data of a single field
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldDiskUsage {
+    /// Postings lists and term frequencies
+    pub postings: u64,
+    pub positions: u64,
+
+    /// Field lengths, used for scoring
+    pub norms: u64,
+    pub stored_fields: u64,
+
+    /// Doc values and the blocks of numeric values used to skip them
+    pub doc_values: u64,
+}
+
+impl FieldDiskUsage {
+    pub fn inverted_index(&self) -> u64 {
+        self.postings + self.positions
+    }
+
+    pub fn total(&self) -> u64 {
+        self.inverted_index() + self.norms + self.stored_fields + self.doc_values
+    }
+
+    fn add(&mut self, other: &FieldDiskUsage) {
+        self.postings += other.postings;
+        self.positions += other.positions;
+        self.norms += other.norms;
+        self.stored_fields += other.stored_fields;
+        self.doc_values += other.doc_values;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiskUsage {
+    pub fields: FnvHashMap<FieldId, FieldDiskUsage>,
+
+    /// The term dictionary is shared by every field
+    pub term_dictionary: u64,
+
+    /// Document keys and boosts, and the document index
+    pub documents: u64,
+
+    /// Segment statistics and metadata
+    pub other: u64,
+}
+
+impl DiskUsage {
+    /// The space used by the data of every field
+    pub fn all_fields(&self) -> FieldDiskUsage {
+        let mut all_fields = FieldDiskUsage::default();
+        for field_usage in self.fields.values() {
+            all_fields.add(field_usage);
+        }
+        all_fields
+    }
+
+    pub fn total(&self) -> u64 {
+        self.all_fields().total() + self.term_dictionary + self.documents + self.other
+    }
+}
+
+/// Reads the field id from postings list keys, "d{field}/{term}/{segment}"
+fn parse_postings_key(key: &[u8]) -> Option<FieldId> {
+    key[1..].split(|b| *b == b'/').next()
+        .and_then(|field_id| str::from_utf8(field_id).ok())
+        .and_then(|field_id| field_id.parse::<u32>().ok())
+        .map(FieldId)
+}
+
+/// Reads the field id and value type from stored value keys, "v{segment}/{doc}/{field}/{value_type}"
+fn parse_stored_value_key(key: &[u8]) -> Option<(FieldId, &[u8])> {
+    let mut parts_iter = key[1..].split(|b| *b == b'/').skip(2);
+    let field_id = match parts_iter.next().and_then(|field_id| str::from_utf8(field_id).ok()).and_then(|field_id| field_id.parse::<u32>().ok()) {
+        Some(field_id) => FieldId(field_id),
+        None => return None,
+    };
+
+    parts_iter.next().map(|value_type| (field_id, value_type))
+}
+
+/// Calls the function with the key and size of every key in the iterator
+fn for_each_key<F: FnMut(&[u8], u64)>(mut iter: DBRawIterator, mut f: F) {
+    // Iterating from the first key doesn't use the prefix bloom filters, so this sees
+    // every key even in the column families that have them
+    iter.seek_to_first();
+    while iter.valid() {
+        let key = iter.key().unwrap();
+        let value_size = unsafe { iter.value_inner() }.map(|value| value.len()).unwrap_or(0);
+        f(&key, (key.len() + value_size) as u64);
+
+        iter.next();
+    }
+}
+
+impl RocksDBStore {
+    /// Works out how much space each field takes up, by reading every key in the store
+    ///
+    /// This is slow on big indices. Everything is read from one snapshot, including
+    /// deleted documents and segments that are waiting to be purged since they're still
+    /// taking up space.
+    pub fn disk_usage(&self) -> Result<DiskUsage, rocksdb::Error> {
+        let snapshot = self.db.snapshot();
+        let mut usage = DiskUsage::default();
+
+        for_each_key(snapshot.raw_iterator(), |_key, size| usage.other += size);
+        for_each_key(try!(snapshot.raw_iterator_cf(column_families::handle(&self.db, column_families::TERMS))), |_key, size| usage.term_dictionary += size);
+        for_each_key(try!(snapshot.raw_iterator_cf(column_families::handle(&self.db, column_families::DOCINDEX))), |_key, size| usage.documents += size);
+        for_each_key(try!(snapshot.raw_iterator_cf(column_families::handle(&self.db, column_families::STATS))), |_key, size| usage.other += size);
+
+        {
+            let fields = &mut usage.fields;
+            for_each_key(try!(snapshot.raw_iterator_cf(column_families::handle(&self.db, column_families::POSTINGS))), |key, size| {
+                if let Some(field_id) = parse_postings_key(key) {
+                    fields.entry(field_id).or_insert_with(FieldDiskUsage::default).postings += size;
+                }
+            });
+        }
+
+        {
+            let fields = &mut usage.fields;
+            let documents = &mut usage.documents;
+            for_each_key(try!(snapshot.raw_iterator_cf(column_families::handle(&self.db, column_families::STORED))), |key, size| {
+                let (field_id, value_type) = match parse_stored_value_key(key) {
+                    Some(parsed) => parsed,
+                    None => return,
+                };
+
+                // Field 0 holds the boost of each document
+                if field_id == FieldId(0) {
+                    *documents += size;
+                    return;
+                }
+
+                // The other value types are the term frequencies ("tf{term}") of the field
+                let field_usage = fields.entry(field_id).or_insert_with(FieldDiskUsage::default);
+                if value_type == b"val" {
+                    field_usage.stored_fields += size;
+                } else if value_type == b"dv" || value_type == b"nblk" {
+                    field_usage.doc_values += size;
+                } else if value_type == b"len" {
+                    field_usage.norms += size;
+                } else if value_type.starts_with(b"pos") {
+                    field_usage.positions += size;
+                } else {
+                    field_usage.postings += size;
+                }
+            });
+        }
+
+        Ok(usage)
+    }
+}
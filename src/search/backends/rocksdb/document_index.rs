@@ -1,20 +1,123 @@
-use std::sync::RwLock;
+use std::sync::{RwLock, Mutex};
 use std::collections::HashMap;
 use std::io::Cursor;
 
-use rocksdb::{self, DB, WriteBatch};
+use rocksdb::{self, DB, WriteBatch, WriteOptions, Snapshot};
 use roaring::RoaringBitmap;
 use search::document::DocId;
 use search::segment::SegmentId;
+use search::statistic_key::StatisticKey;
 use byteorder::{ByteOrder, LittleEndian};
 use fnv::FnvHashMap;
 
 use super::key_builder::KeyBuilder;
+use super::column_families;
 use super::segment_ops::SegmentMergeError;
+use super::group_commit::{GroupCommitter, PendingWrites};
+use super::statistics_rollup::add_to_rollup;
+
+fn decode_doc_id(value: &[u8]) -> DocId {
+    let segment = LittleEndian::read_u32(&value[0..4]);
+    let ord = LittleEndian::read_u16(&value[4..6]);
+    DocId(SegmentId(segment), ord)
+}
+
+/// Reads a deletion list, a sequence of two byte document ids appended to by merges
+pub fn decode_deletion_list(value: &[u8]) -> RoaringBitmap {
+    value.chunks(2).filter(|ord| ord.len() == 2).map(|ord| LittleEndian::read_u16(ord) as u32).collect()
+}
+
+fn encode_deletion_list(deletion_list: &RoaringBitmap) -> Vec<u8> {
+    let mut value = vec![0; deletion_list.len() as usize * 2];
+    for (i, ord) in deletion_list.iter().enumerate() {
+        LittleEndian::write_u16(&mut value[i * 2..i * 2 + 2], ord as u16);
+    }
+    value
+}
+
+/// The first four bytes of a serialized Roaring bitmap
+const ROARING_SERIAL_COOKIE: u32 = 12346;
+
+fn doc_id_index_key(doc_id: DocId) -> KeyBuilder {
+    KeyBuilder::doc_id_index((doc_id.0).0, doc_id.1)
+}
+
+/// Writes the reverse (doc id => key) entries of every key in the document index
+///
+/// Stores written before these entries existed only have the key => doc id entries.
+pub fn build_doc_id_index(db: &DB) -> Result<(), rocksdb::Error> {
+    let docindex_cf = column_families::handle(db, column_families::DOCINDEX);
+    let mut write_batch = WriteBatch::default();
+
+    let mut iter = try!(db.raw_iterator_cf(docindex_cf));
+    iter.seek(b"k");
+    while iter.valid() {
+        let k = iter.key().unwrap();
+
+        if k[0] != b'k' {
+            break;
+        }
+
+        let doc_id = decode_doc_id(&iter.value().unwrap());
+        try!(write_batch.put_cf(docindex_cf, doc_id_index_key(doc_id).key(), &k[1..]));
+
+        iter.next();
+    }
+
+    db.write(write_batch)
+}
+
+/// Rewrites deletion lists that start with a serialized Roaring bitmap
+///
+/// Stores written before format version 4 wrote the deletion list of a merged segment
+/// as a Roaring bitmap, documents deleted after that were appended to it as two byte ids.
+pub fn upgrade_deletion_lists(db: &DB) -> Result<(), rocksdb::Error> {
+    let mut write_batch = WriteBatch::default();
+
+    let mut iter = db.raw_iterator();
+    iter.seek(b"x");
+    while iter.valid() {
+        let k = iter.key().unwrap();
+
+        if k[0] != b'x' {
+            break;
+        }
+
+        let v = iter.value().unwrap();
+        if v.len() >= 4 && LittleEndian::read_u32(&v[0..4]) == ROARING_SERIAL_COOKIE {
+            let mut reader = Cursor::new(&v[..]);
+            if let Ok(mut deletion_list) = RoaringBitmap::deserialize_from(&mut reader) {
+                // Document ids are sixteen bits, so anything larger means this wasn't a bitmap
+                if deletion_list.max().map_or(true, |ord| ord <= u16::max_value() as u32) {
+                    deletion_list.union_with(&decode_deletion_list(&v[reader.position() as usize..]));
+                    try!(write_batch.put(&k, &encode_deletion_list(&deletion_list)));
+                }
+            }
+        }
+
+        iter.next();
+    }
+
+    db.write(write_batch)
+}
+
+/// The keys changed by a group, with the document each key pointed at before the group
+/// and the one the group left it pointing at
+type KeyChanges = HashMap<Vec<u8>, (Option<DocId>, Option<DocId>)>;
 
 /// Manages the index's "document index"
+///
+/// Writers use an in-memory copy of the index which always has the latest version
+/// of each key. Readers look keys up in RocksDB through their snapshot instead so
+/// they don't see keys that were added or removed after they were created.
+///
+/// Changes to keys are written in groups, see group_commit.
 pub struct DocumentIndexManager {
     primary_key_index: RwLock<HashMap<Vec<u8>, DocId>>,
+    group_committer: GroupCommitter,
+
+    /// The keys changed by each group that hasn't been written yet
+    group_key_changes: Mutex<HashMap<u64, KeyChanges>>,
 }
 
 impl DocumentIndexManager {
@@ -22,6 +125,8 @@ impl DocumentIndexManager {
     pub fn new(_db: &DB) -> Result<DocumentIndexManager, rocksdb::Error> {
         Ok(DocumentIndexManager {
             primary_key_index: RwLock::new(HashMap::new()),
+            group_committer: GroupCommitter::default(),
+            group_key_changes: Mutex::new(HashMap::new()),
         })
     }
 
@@ -29,7 +134,7 @@ impl DocumentIndexManager {
     pub fn open(db: &DB) -> Result<DocumentIndexManager, rocksdb::Error> {
         // Read primary key index
         let mut primary_key_index = HashMap::new();
-        let mut iter = db.raw_iterator();
+        let mut iter = try!(db.raw_iterator_cf(column_families::handle(db, column_families::DOCINDEX)));
         iter.seek(b"k");
         while iter.valid() {
             let k = iter.key().unwrap();
@@ -39,80 +144,188 @@ impl DocumentIndexManager {
             }
 
             let v = iter.value().unwrap();
-            let segment = LittleEndian::read_u32(&v[0..4]);
-            let ord = LittleEndian::read_u16(&v[4..6]);
-            let doc_id = DocId(SegmentId(segment), ord);
-
-            primary_key_index.insert(k[1..].to_vec(), doc_id);
+            primary_key_index.insert(k[1..].to_vec(), decode_doc_id(&v));
 
             iter.next();
         }
 
         Ok(DocumentIndexManager {
             primary_key_index: RwLock::new(primary_key_index),
+            group_committer: GroupCommitter::default(),
+            group_key_changes: Mutex::new(HashMap::new()),
         })
     }
 
-    fn delete_document_by_id_unchecked(&self, write_batch: &mut WriteBatch, doc_id: DocId) -> Result<(), rocksdb::Error> {
+    fn delete_document_by_id_unchecked(&self, writes: &mut PendingWrites, doc_id: DocId) {
         let kb = KeyBuilder::segment_del_list((doc_id.0).0);
         let mut previous_doc_id_bytes = [0; 2];
         LittleEndian::write_u16(&mut previous_doc_id_bytes, doc_id.1);
-        try!(write_batch.merge(&kb.key(), &previous_doc_id_bytes));
+        writes.merge(&kb.key(), &previous_doc_id_bytes);
 
         // Increment deleted docs
-        let kb = KeyBuilder::segment_stat((doc_id.0).0, b"deleted_docs");
+        let kb = KeyBuilder::segment_stat((doc_id.0).0, &StatisticKey::DeletedDocs);
         let mut inc_bytes = [0; 8];
         LittleEndian::write_i64(&mut inc_bytes, 1);
-        try!(write_batch.merge(&kb.key(), &inc_bytes));
+        writes.merge_cf(column_families::STATS, &kb.key(), &inc_bytes);
+        add_to_rollup(writes, &StatisticKey::DeletedDocs, 1);
+    }
 
-        Ok(())
+    /// Runs a function while no documents can be inserted or deleted
+    ///
+    /// Any writes that are waiting in a group are written first, the function isn't run
+    /// if they couldn't be.
+    pub fn with_writes_blocked<T, F: FnOnce() -> Result<T, rocksdb::Error>>(&self, db: &DB, f: F) -> Result<T, rocksdb::Error> {
+        let _primary_key_index = self.primary_key_index.write().unwrap();
+        try!(self.group_committer.flush(db));
+
+        f()
     }
 
-    pub fn insert_or_replace_key(&self, db: &DB, key: &Vec<u8>, doc_id: DocId) -> Result<Option<DocId>, rocksdb::Error> {
-        // Update primary_key_index
-        let mut write_batch = WriteBatch::default();
-        let previous_doc_id = self.primary_key_index.write().unwrap().insert(key.clone(), doc_id);
+    /// Records that a group changed a key, must be called while the primary key index is locked
+    ///
+    /// Only the first change to each key in a group keeps the document it pointed at before.
+    fn record_key_change(&self, group: u64, key: &[u8], previous_doc_id: Option<DocId>, doc_id: Option<DocId>) {
+        let mut group_key_changes = self.group_key_changes.lock().unwrap();
+        let change = group_key_changes.entry(group).or_insert_with(HashMap::new).entry(key.to_vec()).or_insert((previous_doc_id, doc_id));
+        change.1 = doc_id;
+    }
+
+    /// Waits for a group of key changes to be written
+    ///
+    /// If the group couldn't be written, each key it changed is pointed back at the
+    /// document it pointed at before the group (unless it has been changed again since).
+    /// This is done by whichever writer of the group finds out first.
+    fn wait_for_group(&self, db: &DB, group: u64) -> Result<(), rocksdb::Error> {
+        match self.group_committer.wait(db, group) {
+            Ok(()) => {
+                self.group_key_changes.lock().unwrap().remove(&group);
+                Ok(())
+            }
+            Err(e) => {
+                let mut primary_key_index = self.primary_key_index.write().unwrap();
+
+                if let Some(key_changes) = self.group_key_changes.lock().unwrap().remove(&group) {
+                    for (key, (previous_doc_id, doc_id)) in key_changes {
+                        if primary_key_index.get(&key).cloned() == doc_id {
+                            match previous_doc_id {
+                                Some(previous_doc_id) => primary_key_index.insert(key, previous_doc_id),
+                                None => primary_key_index.remove(&key),
+                            };
+                        }
+                    }
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Points the key at a new document, deleting the document it previously pointed at
+    ///
+    /// The changes are written in the same group as the given writes. This allows the
+    /// new document's segment to be written in the same batch so a crash can't leave
+    /// both the old and new versions of the document visible.
+    ///
+    /// Doesn't return until the group has been written, so the new document can be
+    /// read straight away.
+    pub fn insert_or_replace_key(&self, db: &DB, writes: PendingWrites, key: &Vec<u8>, doc_id: DocId) -> Result<Option<DocId>, rocksdb::Error> {
+        let (group, previous_doc_id) = self.add_key_change(writes, key, doc_id);
+        try!(self.wait_for_group(db, group));
+
+        Ok(previous_doc_id)
+    }
+
+    /// Adds the change of a key to the current group, without waiting for it to be written
+    ///
+    /// Returns the group and the document the key pointed at before.
+    fn add_key_change(&self, mut writes: PendingWrites, key: &Vec<u8>, doc_id: DocId) -> (u64, Option<DocId>) {
+        // Lock the primary key index
+        // This must be held until the changes have been added to a group, so the previous
+        // document can't be replaced or deleted by another thread in the meantime and
+        // changes to the same key are written in the order they were made
+        let mut primary_key_index = self.primary_key_index.write().unwrap();
+        let previous_doc_id = primary_key_index.get(key).cloned();
 
         let kb = KeyBuilder::primary_key_index(key);
         let mut doc_id_bytes = [0; 6];
         LittleEndian::write_u32(&mut doc_id_bytes, (doc_id.0).0);
         LittleEndian::write_u16(&mut doc_id_bytes[4..], doc_id.1);
-        try!(write_batch.put(&kb.key(), &doc_id_bytes));
+        writes.put_cf(column_families::DOCINDEX, &kb.key(), &doc_id_bytes);
+        writes.put_cf(column_families::DOCINDEX, doc_id_index_key(doc_id).key(), key);
 
         // If there was a document there previously, delete it
         if let Some(previous_doc_id) = previous_doc_id {
-            try!(self.delete_document_by_id_unchecked(&mut write_batch, previous_doc_id));
+            writes.delete_cf(column_families::DOCINDEX, doc_id_index_key(previous_doc_id).key());
+            self.delete_document_by_id_unchecked(&mut writes, previous_doc_id);
         }
 
-        // Write document data
-        try!(db.write(write_batch));
+        let group = self.group_committer.add(writes);
+        self.record_key_change(group, key, previous_doc_id, Some(doc_id));
 
-        Ok(previous_doc_id)
+        // Update primary_key_index
+        primary_key_index.insert(key.clone(), doc_id);
+
+        (group, previous_doc_id)
     }
 
     pub fn delete_document_by_key(&self, db: &DB, key: &Vec<u8>) -> Result<Option<DocId>, rocksdb::Error> {
-        // Remove document from index
-        let doc_id = self.primary_key_index.write().unwrap().remove(key);
+        let (group, doc_id) = {
+            // Lock the primary key index
+            let mut primary_key_index = self.primary_key_index.write().unwrap();
+            let doc_id = match primary_key_index.get(key).cloned() {
+                Some(doc_id) => doc_id,
+                None => return Ok(None),
+            };
 
-        if let Some(doc_id) = doc_id {
-            let mut write_batch = WriteBatch::default();
+            let mut writes = PendingWrites::new();
+            let kb = KeyBuilder::primary_key_index(key);
+            writes.delete_cf(column_families::DOCINDEX, &kb.key());
+            writes.delete_cf(column_families::DOCINDEX, doc_id_index_key(doc_id).key());
+            self.delete_document_by_id_unchecked(&mut writes, doc_id);
 
-            try!(self.delete_document_by_id_unchecked(&mut write_batch, doc_id));
+            let group = self.group_committer.add(writes);
+            self.record_key_change(group, key, Some(doc_id), None);
 
-            try!(db.write(write_batch));
-        }
+            // Remove document from index
+            primary_key_index.remove(key);
+
+            (group, doc_id)
+        };
+
+        try!(self.wait_for_group(db, group));
+
+        Ok(Some(doc_id))
+    }
 
-        Ok(doc_id)
+    /// Finds the document that a key pointed at when the snapshot was taken
+    ///
+    /// Keys are written in the same batch as their documents, so this is consistent
+    /// with everything else that is read through the snapshot.
+    pub fn get_doc_id(&self, db: &DB, snapshot: &Snapshot, key: &Vec<u8>) -> Result<Option<DocId>, rocksdb::Error> {
+        let kb = KeyBuilder::primary_key_index(key);
+        let value = try!(snapshot.get_cf(column_families::handle(db, column_families::DOCINDEX), &kb.key()));
+
+        Ok(value.map(|value| decode_doc_id(&value)))
     }
 
-    pub fn contains_document_key(&self, key: &Vec<u8>) -> bool {
-        self.primary_key_index.read().unwrap().contains_key(key)
+    /// Finds the key that pointed at a document when the snapshot was taken
+    ///
+    /// Looked up through the reverse entry that is written in the same batch as the key.
+    pub fn find_key_by_doc_id(&self, db: &DB, snapshot: &Snapshot, doc_id: DocId) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+        let value = try!(snapshot.get_cf(column_families::handle(db, column_families::DOCINDEX), doc_id_index_key(doc_id).key()));
+
+        Ok(value.map(|value| value.to_vec()))
     }
 
     pub fn commit_segment_merge(&self, db: &DB, mut write_batch: WriteBatch, source_segments: &Vec<u32>, dest_segment: u32, doc_id_mapping: &FnvHashMap<DocId, u16>) -> Result<(), SegmentMergeError> {
         // Lock the primary key index
         let mut primary_key_index = self.primary_key_index.write().unwrap();
 
+        // Write any key changes that are waiting in a group
+        // Nothing can be added to a group while the primary key index is locked, so after
+        // this the deletion lists and keys on disk are up to date
+        try!(self.group_committer.flush(db));
+
         // Update primary keys to point to their new locations
         let mut keys_to_update: HashMap<Vec<u8>, DocId> = HashMap::with_capacity(doc_id_mapping.len());
         for (key, doc_id) in primary_key_index.iter() {
@@ -129,7 +342,10 @@ impl DocumentIndexManager {
             let mut doc_id_bytes = [0; 6];
             LittleEndian::write_u32(&mut doc_id_bytes, (new_doc_id.0).0);
             LittleEndian::write_u16(&mut doc_id_bytes[4..], new_doc_id.1);
-            try!(write_batch.put(&kb.key(), &doc_id_bytes));
+            let docindex_cf = column_families::handle(db, column_families::DOCINDEX);
+            try!(write_batch.put_cf(docindex_cf, &kb.key(), &doc_id_bytes));
+            try!(write_batch.delete_cf(docindex_cf, doc_id_index_key(doc_id).key()));
+            try!(write_batch.put_cf(docindex_cf, doc_id_index_key(new_doc_id).key(), &key));
 
             primary_key_index.insert(key, new_doc_id);
         }
@@ -140,9 +356,8 @@ impl DocumentIndexManager {
         for source_segment in source_segments {
             let kb = KeyBuilder::segment_del_list(*source_segment);
             match try!(db.get(&kb.key())) {
-                Some(bitmap) => {
-                    let bitmap = RoaringBitmap::deserialize_from(Cursor::new(&bitmap[..])).unwrap();
-                    for doc_id in bitmap.iter() {
+                Some(value) => {
+                    for doc_id in decode_deletion_list(&value).iter() {
                         let doc_id = DocId(SegmentId(*source_segment), doc_id as u16);
                         let new_doc_id = doc_id_mapping.get(&doc_id).unwrap();
                         deletion_list.insert(*new_doc_id as u32);
@@ -152,15 +367,85 @@ impl DocumentIndexManager {
             }
         }
 
-        let mut dl_vec = Vec::new();
-        deletion_list.serialize_into(&mut dl_vec).unwrap();
-
+        // Written in the same batch as the rest of the merge, so the new segment can't become
+        // active without its deletions
         let kb = KeyBuilder::segment_del_list(dest_segment);
-        try!(db.put(&kb.key(), &dl_vec));
+        try!(write_batch.put(&kb.key(), &encode_deletion_list(&deletion_list)));
+
+        // Correct the deleted docs statistic of the new segment
+        // This was copied from the old segments before the lock was taken, so it doesn't
+        // include documents that have been deleted since
+        let stats_cf = column_families::handle(db, column_families::STATS);
+        let mut source_deleted_docs = 0;
+        for source_segment in source_segments {
+            let kb = KeyBuilder::segment_stat(*source_segment, &StatisticKey::DeletedDocs);
+            if let Some(value) = try!(db.get_cf(stats_cf, &kb.key())) {
+                source_deleted_docs += LittleEndian::read_i64(&value);
+            }
+        }
+
+        let deleted_docs = deletion_list.len() as i64;
+        let kb = KeyBuilder::segment_stat(dest_segment, &StatisticKey::DeletedDocs);
+        let mut value_bytes = [0; 8];
+        LittleEndian::write_i64(&mut value_bytes, deleted_docs);
+        try!(write_batch.put_cf(stats_cf, &kb.key(), &value_bytes));
+
+        // The old segments are deactivated in the same write, so the rollup only
+        // changes by the difference
+        let kb = KeyBuilder::statistic_rollup(&StatisticKey::DeletedDocs);
+        LittleEndian::write_i64(&mut value_bytes, deleted_docs - source_deleted_docs);
+        try!(write_batch.merge_cf(stats_cf, &kb.key(), &value_bytes));
 
         // Commit!
-        try!(db.write_without_wal(write_batch));
+        // This is synced so the data of the new segment, which was written to the WAL before
+        // this, is also durable
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(true);
+        try!(db.write_opt(write_batch, &write_options));
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use rocksdb::DB;
+    use search::document::DocId;
+    use search::segment::SegmentId;
+
+    use super::DocumentIndexManager;
+    use super::super::column_families;
+    use super::super::group_commit::PendingWrites;
+
+    #[test]
+    fn test_failed_group_rolls_back_keys() {
+        let path = "test_indices/test_document_index_failed_group_rolls_back_keys";
+        let _ = remove_dir_all(path);
+        let mut opts = column_families::db_options();
+        opts.create_if_missing(true);
+        let mut db = DB::open_cf_descriptors(&opts, path, column_families::descriptors()).unwrap();
+
+        let document_index = DocumentIndexManager::new(&db).unwrap();
+        let key = b"test_doc".to_vec();
+        let doc_ids = vec![DocId(SegmentId(1), 0), DocId(SegmentId(2), 0), DocId(SegmentId(3), 0)];
+        document_index.insert_or_replace_key(&db, PendingWrites::new(), &key, doc_ids[0]).unwrap();
+
+        // Replacing a document updates the statistics of its segment, which fails once
+        // their column family has been dropped
+        db.drop_cf(column_families::STATS).unwrap();
+
+        // Two writers in the same group move the key along
+        let (group, _) = document_index.add_key_change(PendingWrites::new(), &key, doc_ids[1]);
+        assert_eq!(document_index.add_key_change(PendingWrites::new(), &key, doc_ids[2]).0, group);
+
+        // The key should go back to the document it pointed at before the group, not the
+        // one that the second writer replaced, which was never written
+        assert!(document_index.wait_for_group(&db, group).is_err());
+        assert_eq!(document_index.primary_key_index.read().unwrap().get(&key).cloned(), Some(doc_ids[0]));
+        assert!(document_index.wait_for_group(&db, group).is_err());
+        assert_eq!(document_index.primary_key_index.read().unwrap().get(&key).cloned(), Some(doc_ids[0]));
+        assert!(document_index.group_key_changes.lock().unwrap().is_empty());
+    }
+}
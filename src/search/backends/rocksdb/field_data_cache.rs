@@ -0,0 +1,246 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use search::document::FieldValue;
+use search::schema::FieldId;
+use fnv::FnvHashMap;
+
+/// The default amount of memory each index may use for field data
+pub const DEFAULT_FIELD_DATA_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The values of a field for every document in a segment, held in memory
+///
+/// Used for sorting and aggregations where we'd otherwise have to read the stored
+/// value of each matching document from the disk. Documents may have any number of
+/// values for a field.
+#[derive(Debug)]
+pub struct FieldData {
+    values: Vec<Vec<FieldValue>>,
+    memory_size: usize,
+}
+
+impl FieldData {
+    pub fn new(values: Vec<Vec<FieldValue>>) -> FieldData {
+        let mut memory_size = mem::size_of::<FieldData>() + values.capacity() * mem::size_of::<Vec<FieldValue>>();
+
+        for doc_values in values.iter() {
+            memory_size += doc_values.capacity() * mem::size_of::<FieldValue>();
+
+            for value in doc_values.iter() {
+                match *value {
+                    FieldValue::String(ref string) => memory_size += string.capacity(),
+                    FieldValue::Bytes(ref bytes) => memory_size += bytes.capacity(),
+                    FieldValue::Vector(ref vector) => memory_size += vector.capacity() * mem::size_of::<f32>(),
+                    _ => {}
+                }
+            }
+        }
+
+        FieldData {
+            values: values,
+            memory_size: memory_size,
+        }
+    }
+
+    /// Returns the first value of the field for a document in the segment
+    pub fn get(&self, doc_local_id: u16) -> Option<&FieldValue> {
+        self.get_all(doc_local_id).first()
+    }
+
+    /// Returns all values of the field for a document in the segment
+    pub fn get_all(&self, doc_local_id: u16) -> &[FieldValue] {
+        match self.values.get(doc_local_id as usize) {
+            Some(values) => values,
+            None => &[],
+        }
+    }
+
+    /// An estimate of the number of bytes of memory used
+    pub fn memory_size(&self) -> usize {
+        self.memory_size
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FieldDataCacheStats {
+    pub memory_size: usize,
+    pub evictions: u64,
+    pub hit_count: u64,
+    pub miss_count: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    data: Arc<FieldData>,
+    last_used: u64,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    entries: FnvHashMap<(u32, FieldId), CacheEntry>,
+    clock: u64,
+    stats: FieldDataCacheStats,
+}
+
+/// Caches field data for each segment/field combination
+///
+/// Segments never change after they've been written, so entries stay valid until
+/// the segment is purged. When the cache is full, the least recently used entries
+/// are evicted.
+#[derive(Debug)]
+pub struct FieldDataCache {
+    limit: usize,
+    state: Mutex<CacheState>,
+}
+
+impl FieldDataCache {
+    pub fn new(limit: usize) -> FieldDataCache {
+        FieldDataCache {
+            limit: limit,
+            state: Mutex::new(CacheState {
+                entries: FnvHashMap::default(),
+                clock: 0,
+                stats: FieldDataCacheStats::default(),
+            }),
+        }
+    }
+
+    /// Retrieves the field data for a segment, calling load to build it if it
+    /// isn't in the cache
+    ///
+    /// The cache isn't locked while loading, so two threads may load the same field
+    /// data at once. In this case, the first to finish is kept.
+    pub fn get_or_load<F, E>(&self, segment: u32, field_id: FieldId, load: F) -> Result<Arc<FieldData>, E>
+        where F: FnOnce() -> Result<FieldData, E>
+    {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.clock += 1;
+            let clock = state.clock;
+
+            if let Some(data) = state.entries.get_mut(&(segment, field_id)).map(|entry| {
+                entry.last_used = clock;
+                entry.data.clone()
+            }) {
+                state.stats.hit_count += 1;
+                return Ok(data);
+            }
+
+            state.stats.miss_count += 1;
+        }
+
+        let data = Arc::new(try!(load()));
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.entries.get(&(segment, field_id)) {
+            return Ok(entry.data.clone());
+        }
+
+        // Don't cache anything that would take up the whole cache by itself
+        if data.memory_size() > self.limit {
+            return Ok(data);
+        }
+
+        // Evict least recently used entries until there's enough space
+        while state.stats.memory_size + data.memory_size() > self.limit {
+            let lru_key = match state.entries.iter().min_by_key(|&(_, entry)| entry.last_used) {
+                Some((key, _)) => *key,
+                None => break,
+            };
+
+            if let Some(entry) = state.entries.remove(&lru_key) {
+                state.stats.memory_size -= entry.data.memory_size();
+                state.stats.evictions += 1;
+            }
+        }
+
+        state.clock += 1;
+        let clock = state.clock;
+        state.stats.memory_size += data.memory_size();
+        state.entries.insert((segment, field_id), CacheEntry {
+            data: data.clone(),
+            last_used: clock,
+        });
+
+        Ok(data)
+    }
+
+    /// Removes all field data for the given segments
+    pub fn invalidate_segments(&self, segments: &[u32]) {
+        let mut state = self.state.lock().unwrap();
+        let keys = state.entries.keys().filter(|&&(segment, _)| segments.contains(&segment)).cloned().collect::<Vec<_>>();
+
+        for key in keys {
+            if let Some(entry) = state.entries.remove(&key) {
+                state.stats.memory_size -= entry.data.memory_size();
+            }
+        }
+    }
+
+    pub fn stats(&self) -> FieldDataCacheStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::document::FieldValue;
+    use search::schema::FieldId;
+
+    use super::{FieldData, FieldDataCache};
+
+    fn make_field_data(num_docs: i64) -> Result<FieldData, ()> {
+        Ok(FieldData::new((0..num_docs).map(|i| vec![FieldValue::Integer(i)]).collect()))
+    }
+
+    #[test]
+    fn test_get_or_load() {
+        let cache = FieldDataCache::new(1024 * 1024);
+
+        let data = cache.get_or_load(1, FieldId(1), || make_field_data(10)).unwrap();
+        match data.get(5) {
+            Some(&FieldValue::Integer(5)) => {}
+            value => panic!("unexpected value {:?}", value),
+        }
+        assert!(data.get(10).is_none());
+
+        // Second load should be a hit
+        cache.get_or_load(1, FieldId(1), || -> Result<FieldData, ()> { panic!("field data loaded twice") }).unwrap();
+
+        let stats = cache.stats();
+        assert_eq!(stats.hit_count, 1);
+        assert_eq!(stats.miss_count, 1);
+        assert_eq!(stats.memory_size, data.memory_size());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let entry_size = make_field_data(100).unwrap().memory_size();
+        let cache = FieldDataCache::new(entry_size * 2);
+
+        cache.get_or_load(1, FieldId(1), || make_field_data(100)).unwrap();
+        cache.get_or_load(2, FieldId(1), || make_field_data(100)).unwrap();
+
+        // Use segment 1 so segment 2 becomes the least recently used
+        cache.get_or_load(1, FieldId(1), || make_field_data(100)).unwrap();
+        cache.get_or_load(3, FieldId(1), || make_field_data(100)).unwrap();
+
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.memory_size, entry_size * 2);
+
+        // Segment 1 should still be cached
+        cache.get_or_load(1, FieldId(1), || -> Result<FieldData, ()> { panic!("segment 1 was evicted") }).unwrap();
+    }
+
+    #[test]
+    fn test_invalidate_segments() {
+        let cache = FieldDataCache::new(1024 * 1024);
+
+        cache.get_or_load(1, FieldId(1), || make_field_data(10)).unwrap();
+        cache.get_or_load(1, FieldId(2), || make_field_data(10)).unwrap();
+        cache.invalidate_segments(&[1]);
+
+        assert_eq!(cache.stats().memory_size, 0);
+    }
+}
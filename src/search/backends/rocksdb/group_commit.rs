@@ -0,0 +1,415 @@
+//! Group commit of document index updates
+//!
+//! Inserting a document writes a new segment, points the document's key at it and
+//! deletes the previous version. Instead of writing each of these to RocksDB on their
+//! own, concurrent insertions add their writes to a group which is written in a single
+//! write batch.
+//!
+//! The first writer to wait on a group becomes its leader. If other writers have already
+//! joined the group, the leader waits a short time for more to join (or until the group
+//! is big enough). Then it closes the group and writes it. A writer on its own is written
+//! straight away. Writers don't return until their group has been written, so anything
+//! they wrote can be read as soon as they return.
+
+use std::mem;
+use std::time::{Duration, Instant};
+use std::sync::{Mutex, MutexGuard, Condvar};
+use std::collections::HashMap;
+
+use rocksdb::{self, DB, WriteBatch};
+
+use super::column_families;
+
+
+/// How long a leader waits for more writers to join a group that others have joined, in milliseconds
+pub const DEFAULT_GROUP_COMMIT_DELAY_MS: u64 = 1;
+
+/// Groups with at least this many writes are written without waiting any longer
+pub const DEFAULT_GROUP_COMMIT_MAX_WRITES: usize = 10000;
+
+
+#[derive(Debug)]
+enum WriteOp {
+    Put(Option<&'static str>, Vec<u8>, Vec<u8>),
+    Merge(Option<&'static str>, Vec<u8>, Vec<u8>),
+    Delete(Option<&'static str>, Vec<u8>),
+}
+
+
+/// Writes that haven't been added to a write batch yet
+///
+/// RocksDB write batches can't be shared between threads or appended to each other,
+/// so writes are collected here instead. Column families are referred to by name
+/// (eg, column_families::STORED), None is the default column family.
+#[derive(Debug, Default)]
+pub struct PendingWrites {
+    ops: Vec<WriteOp>,
+}
+
+
+impl PendingWrites {
+    pub fn new() -> PendingWrites {
+        PendingWrites::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.ops.push(WriteOp::Put(None, key.to_vec(), value.to_vec()));
+    }
+
+    pub fn put_cf(&mut self, cf: &'static str, key: &[u8], value: &[u8]) {
+        self.ops.push(WriteOp::Put(Some(cf), key.to_vec(), value.to_vec()));
+    }
+
+    pub fn merge(&mut self, key: &[u8], value: &[u8]) {
+        self.ops.push(WriteOp::Merge(None, key.to_vec(), value.to_vec()));
+    }
+
+    pub fn merge_cf(&mut self, cf: &'static str, key: &[u8], value: &[u8]) {
+        self.ops.push(WriteOp::Merge(Some(cf), key.to_vec(), value.to_vec()));
+    }
+
+    pub fn delete_cf(&mut self, cf: &'static str, key: &[u8]) {
+        self.ops.push(WriteOp::Delete(Some(cf), key.to_vec()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Adds the writes to a write batch
+    pub fn add_to_batch(self, db: &DB, write_batch: &mut WriteBatch) -> Result<(), rocksdb::Error> {
+        for op in self.ops {
+            match op {
+                WriteOp::Put(None, key, value) => try!(write_batch.put(&key, &value)),
+                WriteOp::Put(Some(cf), key, value) => try!(write_batch.put_cf(column_families::handle(db, cf), &key, &value)),
+                WriteOp::Merge(None, key, value) => try!(write_batch.merge(&key, &value)),
+                WriteOp::Merge(Some(cf), key, value) => try!(write_batch.merge_cf(column_families::handle(db, cf), &key, &value)),
+                WriteOp::Delete(None, key) => try!(write_batch.delete(&key)),
+                WriteOp::Delete(Some(cf), key) => try!(write_batch.delete_cf(column_families::handle(db, cf), &key)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes to the database straight away, outside of any group
+    pub fn write(self, db: &DB) -> Result<(), rocksdb::Error> {
+        let mut write_batch = WriteBatch::default();
+        try!(self.add_to_batch(db, &mut write_batch));
+        db.write(write_batch)
+    }
+}
+
+
+#[derive(Debug)]
+struct GroupCommitState {
+    /// Id of the group that writes are being added to, ids start at 1
+    current_group: u64,
+
+    /// Writes that have been added to the current group, and the number of writers that added them
+    pending: Vec<PendingWrites>,
+    pending_writes: usize,
+    pending_writers: usize,
+
+    /// Set while a leader is collecting or writing a group. Only one group is written at
+    /// a time so they are written in the order they were created
+    has_leader: bool,
+
+    /// Id of the last group that was written
+    committed_group: u64,
+
+    /// Number of flushes waiting on each group that hasn't been written yet. They're told
+    /// if the group fails, like its writers
+    flushes: HashMap<u64, usize>,
+
+    /// Errors of groups that failed to write, with the number of writers (and flushes) that haven't seen them yet
+    failures: HashMap<u64, (usize, rocksdb::Error)>,
+}
+
+
+#[derive(Debug)]
+pub struct GroupCommitter {
+    state: Mutex<GroupCommitState>,
+    changed: Condvar,
+    max_delay: Duration,
+    max_writes: usize,
+}
+
+
+impl GroupCommitter {
+    pub fn new(max_delay: Duration, max_writes: usize) -> GroupCommitter {
+        GroupCommitter {
+            state: Mutex::new(GroupCommitState {
+                current_group: 1,
+                pending: Vec::new(),
+                pending_writes: 0,
+                pending_writers: 0,
+                has_leader: false,
+                committed_group: 0,
+                flushes: HashMap::new(),
+                failures: HashMap::new(),
+            }),
+            changed: Condvar::new(),
+            max_delay: max_delay,
+            max_writes: max_writes,
+        }
+    }
+
+    /// Adds writes to the current group, returning the id of the group
+    ///
+    /// Groups are written in the order they're created. Callers that need writes to be
+    /// applied in a particular order must hold a lock while adding them.
+    pub fn add(&self, writes: PendingWrites) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.pending_writes += writes.len();
+        state.pending_writers += 1;
+        state.pending.push(writes);
+
+        // Let the leader know if the group is full
+        if state.pending_writes >= self.max_writes {
+            self.changed.notify_all();
+        }
+
+        state.current_group
+    }
+
+    /// Waits for a group that writes were added to, writing it if nobody else is
+    ///
+    /// Every writer that added to the group must call this once.
+    pub fn wait(&self, db: &DB, group: u64) -> Result<(), rocksdb::Error> {
+        let mut state = self.commit_group(db, group);
+
+        // Check if the group failed
+        let (result, seen_by_all) = match state.failures.get_mut(&group) {
+            Some(&mut (ref mut remaining_writers, ref error)) => {
+                *remaining_writers -= 1;
+                (Err(error.clone()), *remaining_writers == 0)
+            }
+            None => (Ok(()), false),
+        };
+
+        if seen_by_all {
+            state.failures.remove(&group);
+        }
+
+        result
+    }
+
+    /// Waits for all writes that have been added so far to be written
+    ///
+    /// Returns the error of the last group if it couldn't be written. Groups that were
+    /// written before this was called have already reported their errors to their writers.
+    pub fn flush(&self, db: &DB) -> Result<(), rocksdb::Error> {
+        let group = {
+            let mut state = self.state.lock().unwrap();
+
+            let group = if state.pending.is_empty() {
+                state.current_group - 1
+            } else {
+                state.current_group
+            };
+
+            if state.committed_group >= group {
+                return Ok(());
+            }
+
+            *state.flushes.entry(group).or_insert(0) += 1;
+            group
+        };
+
+        self.wait(db, group)
+    }
+
+    fn commit_group<'a>(&'a self, db: &DB, group: u64) -> MutexGuard<'a, GroupCommitState> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if state.committed_group >= group {
+                return state;
+            }
+
+            if state.has_leader || state.current_group != group {
+                // Another writer is leading, wait for it
+                state = self.changed.wait(state).unwrap();
+                continue;
+            }
+
+            // Lead this group
+            state.has_leader = true;
+
+            // If other writers are writing at the same time, give more of them a chance to join
+            // Writers that come along while this group is being written are added to the next
+            // group, so a writer on its own doesn't need to wait
+            if state.pending_writers > 1 {
+                let deadline = Instant::now() + self.max_delay;
+                while state.pending_writes < self.max_writes {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+
+                    state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+                }
+            }
+
+            // Close the group, writes are now added to the next one
+            let pending = mem::replace(&mut state.pending, Vec::new());
+            let writers = state.pending_writers;
+            state.pending_writes = 0;
+            state.pending_writers = 0;
+            state.current_group += 1;
+            drop(state);
+
+            // Write the group, without holding the lock so the next group can be collected meanwhile
+            let result = GroupCommitter::write_group(db, pending);
+
+            state = self.state.lock().unwrap();
+            state.has_leader = false;
+            state.committed_group = group;
+            let waiters = writers + state.flushes.remove(&group).unwrap_or(0);
+            if let Err(error) = result {
+                if waiters > 0 {
+                    state.failures.insert(group, (waiters, error));
+                }
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    fn write_group(db: &DB, pending: Vec<PendingWrites>) -> Result<(), rocksdb::Error> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut write_batch = WriteBatch::default();
+        for writes in pending {
+            try!(writes.add_to_batch(db, &mut write_batch));
+        }
+
+        db.write(write_batch)
+    }
+}
+
+
+impl Default for GroupCommitter {
+    fn default() -> GroupCommitter {
+        GroupCommitter::new(Duration::from_millis(DEFAULT_GROUP_COMMIT_DELAY_MS), DEFAULT_GROUP_COMMIT_MAX_WRITES)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use rocksdb::DB;
+
+    use super::{GroupCommitter, PendingWrites, DEFAULT_GROUP_COMMIT_MAX_WRITES};
+    use super::super::column_families;
+
+    fn open_db(path: &str) -> DB {
+        let _ = remove_dir_all(path);
+        let mut opts = column_families::db_options();
+        opts.create_if_missing(true);
+        DB::open_cf_descriptors(&opts, path, column_families::descriptors()).unwrap()
+    }
+
+    fn put(key: &[u8]) -> PendingWrites {
+        let mut writes = PendingWrites::new();
+        writes.put(key, b"value");
+        writes
+    }
+
+    #[test]
+    fn test_single_writer_doesnt_wait() {
+        let db = open_db("test_indices/test_group_commit_single_writer_doesnt_wait");
+        let group_committer = GroupCommitter::new(Duration::from_secs(10), DEFAULT_GROUP_COMMIT_MAX_WRITES);
+
+        let start = Instant::now();
+        let group = group_committer.add(put(b"a"));
+        group_committer.wait(&db, group).unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(db.get(b"a").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_concurrent_writers_share_a_group() {
+        let db = Arc::new(open_db("test_indices/test_group_commit_concurrent_writers_share_a_group"));
+        let group_committer = Arc::new(GroupCommitter::new(Duration::from_millis(10), DEFAULT_GROUP_COMMIT_MAX_WRITES));
+
+        // Both writers add their writes before either of them starts waiting
+        let groups = vec![group_committer.add(put(b"a")), group_committer.add(put(b"b"))];
+        assert_eq!(groups, vec![1, 1]);
+
+        let threads = groups.into_iter().map(|group| {
+            let db = db.clone();
+            let group_committer = group_committer.clone();
+            thread::spawn(move || group_committer.wait(&db, group))
+        }).collect::<Vec<_>>();
+
+        for thread in threads {
+            assert!(thread.join().unwrap().is_ok());
+        }
+
+        // Only one group was written
+        let state = group_committer.state.lock().unwrap();
+        assert_eq!(state.committed_group, 1);
+        assert_eq!(state.current_group, 2);
+        assert!(db.get(b"a").unwrap().is_some());
+        assert!(db.get(b"b").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_failed_group_is_reported_to_every_writer() {
+        let mut db = open_db("test_indices/test_group_commit_failed_group_is_reported_to_every_writer");
+        let group_committer = GroupCommitter::new(Duration::from_millis(10), DEFAULT_GROUP_COMMIT_MAX_WRITES);
+
+        // Writing to a column family that has been dropped makes the write batch fail
+        db.drop_cf(column_families::STATS).unwrap();
+        let mut writes = PendingWrites::new();
+        writes.put_cf(column_families::STATS, b"a", b"value");
+
+        let group = group_committer.add(writes);
+        assert_eq!(group_committer.add(put(b"b")), group);
+
+        assert!(group_committer.wait(&db, group).is_err());
+        assert!(group_committer.wait(&db, group).is_err());
+
+        // The other writer's key wasn't written and the error was forgotten once every writer had seen it
+        assert!(db.get(b"b").unwrap().is_none());
+        assert!(group_committer.state.lock().unwrap().failures.is_empty());
+
+        // Later groups aren't affected. RocksDB won't take any more writes after a batch fails
+        // part way through being applied, so they're written to another store
+        let db = open_db("test_indices/test_group_commit_failed_group_is_reported_to_every_writer_2");
+        let group = group_committer.add(put(b"c"));
+        assert!(group_committer.wait(&db, group).is_ok());
+        assert!(db.get(b"c").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_flush_reports_failed_group() {
+        let mut db = open_db("test_indices/test_group_commit_flush_reports_failed_group");
+        let group_committer = GroupCommitter::new(Duration::from_millis(10), DEFAULT_GROUP_COMMIT_MAX_WRITES);
+
+        // Nothing to write
+        assert!(group_committer.flush(&db).is_ok());
+
+        db.drop_cf(column_families::STATS).unwrap();
+        let mut writes = PendingWrites::new();
+        writes.put_cf(column_families::STATS, b"a", b"value");
+        let group = group_committer.add(writes);
+
+        // The flush writes the group, its writer still sees the error afterwards
+        assert!(group_committer.flush(&db).is_err());
+        assert!(group_committer.wait(&db, group).is_err());
+        assert!(group_committer.state.lock().unwrap().failures.is_empty());
+
+        // The failure has been reported, so it isn't reported again by the next flush
+        assert!(group_committer.flush(&db).is_ok());
+    }
+}
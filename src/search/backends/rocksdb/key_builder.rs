@@ -1,3 +1,5 @@
+use search::statistic_key::StatisticKey;
+
 pub struct KeyBuilder {
     key: Vec<u8>,
 }
@@ -43,6 +45,16 @@ impl KeyBuilder {
         kb
     }
 
+    /// Key of the reverse entry that maps a document back to its primary key
+    pub fn doc_id_index(segment: u32, doc_local_id: u16) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'i');
+        kb.push_string(segment.to_string().as_bytes());
+        kb.separator();
+        kb.push_string(doc_local_id.to_string().as_bytes());
+        kb
+    }
+
     pub fn term_dict_mapping(term: &[u8]) -> KeyBuilder {
         let mut kb = KeyBuilder::with_capacity(1 + term.len());
         kb.push_char(b't');
@@ -76,61 +88,18 @@ impl KeyBuilder {
         kb
     }
 
-    pub fn segment_stat(segment: u32, name: &[u8]) -> KeyBuilder {
+    pub fn segment_stat(segment: u32, stat_key: &StatisticKey) -> KeyBuilder {
         let mut kb = KeyBuilder::segment_stat_prefix(segment);
-        kb.push_string(name);
+        kb.push_string(&stat_key.encode());
         kb
     }
 
-    pub fn segment_stat_term_doc_frequency_stat_name(field_id: u32, term_id: u32) -> Vec<u8> {
-        let mut stat_name = Vec::new();
-        for c in b"tdf" {
-            stat_name.push(*c);
-        }
-
-        stat_name.push(b'-');
-
-        for c in field_id.to_string().as_bytes() {
-            stat_name.push(*c);
-        }
-
-        stat_name.push(b'-');
-
-        for c in term_id.to_string().as_bytes() {
-            stat_name.push(*c);
-        }
-
-        stat_name
-    }
-
-    pub fn segment_stat_total_field_tokens_stat_name(field_id: u32) -> Vec<u8> {
-        let mut stat_name = Vec::new();
-        for c in b"fttok" {
-            stat_name.push(*c);
-        }
-
-        stat_name.push(b'-');
-
-        for c in field_id.to_string().as_bytes() {
-            stat_name.push(*c);
-        }
-
-        stat_name
-    }
-
-    pub fn segment_stat_total_field_docs_stat_name(field_id: u32) -> Vec<u8> {
-        let mut stat_name = Vec::new();
-        for c in b"ftdoc" {
-            stat_name.push(*c);
-        }
-
-        stat_name.push(b'-');
-
-        for c in field_id.to_string().as_bytes() {
-            stat_name.push(*c);
-        }
-
-        stat_name
+    /// Key of a statistic summed across all of the active segments
+    pub fn statistic_rollup(stat_key: &StatisticKey) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'r');
+        kb.push_string(&stat_key.encode());
+        kb
     }
 
     pub fn segment_del_list(segment: u32) -> KeyBuilder {
@@ -140,6 +109,14 @@ impl KeyBuilder {
         kb
     }
 
+    /// Key of the merge journal entry of a merge into the segment
+    pub fn merge_journal(dest_segment: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'j');
+        kb.push_string(dest_segment.to_string().as_bytes());
+        kb
+    }
+
     #[inline]
     pub fn key(&self) -> &[u8] {
         &self.key[..]
@@ -0,0 +1,155 @@
+//! Records segment merges so they can be finished or undone after a crash
+//!
+//! Each merge has an entry, keyed by the segment being merged into:
+//!
+//! Merging: written before any of the new segment's data. If the store is opened with
+//! an entry in this state, the merge never committed and the new segment's data is
+//! deleted.
+//!
+//! Committed: written in the same batch that activates the new segment. If the store is
+//! opened with an entry in this state, the source segments weren't purged so this is
+//! done then.
+//!
+//! The entry is deleted once the source segments have been purged.
+
+use std::str;
+
+use rocksdb::{self, DB, WriteBatch};
+
+use super::key_builder::KeyBuilder;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeState {
+    Merging,
+    Committed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeJournalEntry {
+    pub dest_segment: u32,
+    pub source_segments: Vec<u32>,
+    pub state: MergeState,
+}
+
+impl MergeJournalEntry {
+    /// Encodes the entry as "m1,2,3" (merging) or "c1,2,3" (committed)
+    fn encode(&self) -> Vec<u8> {
+        let mut value = match self.state {
+            MergeState::Merging => b"m".to_vec(),
+            MergeState::Committed => b"c".to_vec(),
+        };
+
+        let source_segments = self.source_segments.iter().map(|segment| segment.to_string()).collect::<Vec<_>>();
+        value.extend(source_segments.join(",").as_bytes());
+        value
+    }
+
+    fn decode(dest_segment: u32, value: &[u8]) -> Option<MergeJournalEntry> {
+        let state = match value.first() {
+            Some(&b'm') => MergeState::Merging,
+            Some(&b'c') => MergeState::Committed,
+            _ => return None,
+        };
+
+        let source_segments = match str::from_utf8(&value[1..]) {
+            Ok(source_segments) => source_segments,
+            Err(_) => return None,
+        };
+
+        let source_segments = match source_segments.split(',').filter(|segment| !segment.is_empty()).map(|segment| segment.parse::<u32>()).collect::<Result<Vec<_>, _>>() {
+            Ok(source_segments) => source_segments,
+            Err(_) => return None,
+        };
+
+        Some(MergeJournalEntry {
+            dest_segment: dest_segment,
+            source_segments: source_segments,
+            state: state,
+        })
+    }
+}
+
+/// Records that a merge is about to start writing the new segment
+pub fn record_merging(db: &DB, dest_segment: u32, source_segments: &[u32]) -> Result<(), rocksdb::Error> {
+    let entry = MergeJournalEntry {
+        dest_segment: dest_segment,
+        source_segments: source_segments.to_vec(),
+        state: MergeState::Merging,
+    };
+
+    let kb = KeyBuilder::merge_journal(dest_segment);
+    db.put(&kb.key(), &entry.encode())
+}
+
+/// Adds marking the merge as committed to the batch that commits it
+pub fn record_committed(write_batch: &mut WriteBatch, dest_segment: u32, source_segments: &[u32]) -> Result<(), rocksdb::Error> {
+    let entry = MergeJournalEntry {
+        dest_segment: dest_segment,
+        source_segments: source_segments.to_vec(),
+        state: MergeState::Committed,
+    };
+
+    let kb = KeyBuilder::merge_journal(dest_segment);
+    write_batch.put(&kb.key(), &entry.encode())
+}
+
+pub fn remove(db: &DB, dest_segment: u32) -> Result<(), rocksdb::Error> {
+    let kb = KeyBuilder::merge_journal(dest_segment);
+    db.delete(&kb.key())
+}
+
+/// Reads every entry in the journal
+pub fn read_entries(db: &DB) -> Result<Vec<MergeJournalEntry>, rocksdb::Error> {
+    let mut entries = Vec::new();
+
+    let mut iter = db.raw_iterator();
+    iter.seek(b"j");
+    while iter.valid() {
+        let k = iter.key().unwrap();
+
+        if k[0] != b'j' {
+            // No more journal entries
+            break;
+        }
+
+        let dest_segment = str::from_utf8(&k[1..]).ok().and_then(|segment| segment.parse::<u32>().ok());
+        if let Some(entry) = dest_segment.and_then(|dest_segment| MergeJournalEntry::decode(dest_segment, &iter.value().unwrap())) {
+            entries.push(entry);
+        }
+
+        iter.next();
+    }
+
+    Ok(entries)
+}
+
+/// Removes the entries of committed merges whose source segments have all been purged
+pub fn remove_purged(db: &DB, purged_segments: &[u32]) -> Result<(), rocksdb::Error> {
+    for entry in try!(read_entries(db)) {
+        if entry.state == MergeState::Committed && entry.source_segments.iter().all(|segment| purged_segments.contains(segment)) {
+            try!(remove(db, entry.dest_segment));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MergeJournalEntry, MergeState};
+
+    #[test]
+    fn test_encode_decode() {
+        let entry = MergeJournalEntry {
+            dest_segment: 5,
+            source_segments: vec![1, 2, 4],
+            state: MergeState::Committed,
+        };
+
+        assert_eq!(entry.encode(), b"c1,2,4".to_vec());
+        assert_eq!(MergeJournalEntry::decode(5, b"c1,2,4"), Some(entry));
+        assert_eq!(MergeJournalEntry::decode(5, b"m").map(|entry| entry.state), Some(MergeState::Merging));
+        assert_eq!(MergeJournalEntry::decode(5, b"x1,2"), None);
+        assert_eq!(MergeJournalEntry::decode(5, b"m1,two"), None);
+    }
+}
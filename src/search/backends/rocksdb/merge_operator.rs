@@ -0,0 +1,250 @@
+//! The merge operator used by every column family
+//!
+//! Merges let values be appended to or incremented without reading them first. How
+//! the operands are merged depends on the first byte of the key:
+//!
+//! - "d" (postings lists) and "x" (deletion lists) are sequences of two byte document
+//!   ids, the ids in each operand are appended to the value
+//! - "s" (statistics) and "r" (statistics rolled up across segments) are eight byte
+//!   little endian integers, each operand is added to the value
+//!
+//! Any other key takes the value of its last operand, like a put.
+//!
+//! An operand with an invalid length is skipped and counted rather than failing the
+//! merge, as RocksDB treats a failed merge as corruption of the whole key. The count
+//! is shown in the node stats.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rocksdb::MergeOperands;
+use byteorder::{ByteOrder, LittleEndian};
+
+
+/// Number of corrupt values that have been skipped by merges in any store
+///
+/// RocksDB calls the merge operator without any way of getting back to the store, so
+/// this is shared by every store in the process.
+static CORRUPT_VALUES: AtomicUsize = AtomicUsize::new(0);
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValueKind {
+    DocIdList,
+    Statistic,
+    Other,
+}
+
+
+fn value_kind(key: &[u8]) -> ValueKind {
+    match key.first() {
+        Some(&b'd') | Some(&b'x') => ValueKind::DocIdList,
+        Some(&b's') | Some(&b'r') => ValueKind::Statistic,
+        _ => ValueKind::Other,
+    }
+}
+
+
+fn report_corrupt_value() {
+    CORRUPT_VALUES.fetch_add(1, Ordering::Relaxed);
+}
+
+
+/// Returns the number of corrupt values that have been skipped by merges
+pub fn corrupt_value_count() -> usize {
+    CORRUPT_VALUES.load(Ordering::Relaxed)
+}
+
+
+fn read_statistic(value: &[u8]) -> Option<i64> {
+    if value.len() == 8 {
+        Some(LittleEndian::read_i64(value))
+    } else {
+        None
+    }
+}
+
+
+fn merge_doc_id_lists<'a, I: Iterator<Item = &'a [u8]>>(existing_val: Option<&[u8]>, operands: I) -> Vec<u8> {
+    let mut new_val = Vec::with_capacity(existing_val.map(|v| v.len()).unwrap_or(0) + operands.size_hint().0 * 2);
+
+    // Push existing value, if it has a trailing byte only the ids before it are kept
+    if let Some(existing_val) = existing_val {
+        if existing_val.len() % 2 != 0 {
+            report_corrupt_value();
+        }
+
+        new_val.extend_from_slice(&existing_val[..existing_val.len() - existing_val.len() % 2]);
+    }
+
+    // Append new entries
+    for op in operands {
+        if op.len() % 2 != 0 {
+            report_corrupt_value();
+            continue;
+        }
+
+        new_val.extend_from_slice(op);
+    }
+
+    new_val
+}
+
+
+fn merge_statistics<'a, I: Iterator<Item = &'a [u8]>>(existing_val: Option<&[u8]>, operands: I) -> Vec<u8> {
+    let mut value = match existing_val {
+        Some(existing_val) => {
+            match read_statistic(existing_val) {
+                Some(value) => value,
+                None => {
+                    report_corrupt_value();
+                    0
+                }
+            }
+        }
+        None => 0,
+    };
+
+    for op in operands {
+        match read_statistic(op) {
+            Some(delta) => value = value.wrapping_add(delta),
+            None => report_corrupt_value(),
+        }
+    }
+
+    let mut buf = [0; 8];
+    LittleEndian::write_i64(&mut buf, value);
+    buf.to_vec()
+}
+
+
+/// Merges operands into the existing value of a key
+pub fn merge_values<'a, I: Iterator<Item = &'a [u8]>>(key: &[u8], existing_val: Option<&[u8]>, operands: I) -> Option<Vec<u8>> {
+    match value_kind(key) {
+        ValueKind::DocIdList => Some(merge_doc_id_lists(existing_val, operands)),
+        ValueKind::Statistic => Some(merge_statistics(existing_val, operands)),
+        ValueKind::Other => {
+            // Emulate a put operation by taking the last value
+            operands.last().or(existing_val).map(|value| value.to_vec())
+        }
+    }
+}
+
+
+/// Combines operands into a single operand, without the existing value
+///
+/// This lets compactions collapse long chains of operands before the existing value
+/// is known. Every kind of value can be combined this way: appending and adding are
+/// associative and puts only keep their last operand.
+pub fn combine_operands<'a, I: Iterator<Item = &'a [u8]>>(key: &[u8], operands: I) -> Option<Vec<u8>> {
+    merge_values(key, None, operands)
+}
+
+
+pub fn merge_keys(key: &[u8], existing_val: Option<&[u8]>, operands: &mut MergeOperands) -> Option<Vec<u8>> {
+    merge_values(key, existing_val, operands)
+}
+
+
+pub fn partial_merge_keys(key: &[u8], _existing_val: Option<&[u8]>, operands: &mut MergeOperands) -> Option<Vec<u8>> {
+    combine_operands(key, operands)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use super::{merge_values, combine_operands, corrupt_value_count};
+
+    fn statistic(value: i64) -> Vec<u8> {
+        let mut buf = [0; 8];
+        LittleEndian::write_i64(&mut buf, value);
+        buf.to_vec()
+    }
+
+    fn merge(key: &[u8], existing_val: Option<Vec<u8>>, operands: Vec<Vec<u8>>) -> Option<Vec<u8>> {
+        merge_values(key, existing_val.as_ref().map(|v| v.as_slice()), operands.iter().map(|op| op.as_slice()))
+    }
+
+    fn combine(key: &[u8], operands: Vec<Vec<u8>>) -> Option<Vec<u8>> {
+        combine_operands(key, operands.iter().map(|op| op.as_slice()))
+    }
+
+    #[test]
+    fn test_doc_id_lists_are_appended() {
+        assert_eq!(merge(b"d1/2/3", Some(vec![1, 0]), vec![vec![2, 0], vec![3, 0, 4, 0]]), Some(vec![1, 0, 2, 0, 3, 0, 4, 0]));
+        assert_eq!(merge(b"x1", None, vec![vec![5, 0]]), Some(vec![5, 0]));
+        assert_eq!(merge(b"x1", None, vec![]), Some(vec![]));
+    }
+
+    #[test]
+    fn test_doc_id_list_operands_with_odd_length_are_skipped() {
+        assert_eq!(merge(b"d1/2/3", Some(vec![1, 0]), vec![vec![2], vec![3, 0]]), Some(vec![1, 0, 3, 0]));
+    }
+
+    #[test]
+    fn test_doc_id_list_with_trailing_byte_is_truncated() {
+        assert_eq!(merge(b"d1/2/3", Some(vec![1, 0, 2]), vec![vec![3, 0]]), Some(vec![1, 0, 3, 0]));
+    }
+
+    #[test]
+    fn test_statistics_are_added() {
+        assert_eq!(merge(b"s1/total_docs", Some(statistic(10)), vec![statistic(5), statistic(-3)]), Some(statistic(12)));
+        assert_eq!(merge(b"s1/total_docs", None, vec![statistic(-1)]), Some(statistic(-1)));
+    }
+
+    #[test]
+    fn test_statistic_rollups_are_added() {
+        assert_eq!(merge(b"rtotal_docs", Some(statistic(10)), vec![statistic(5), statistic(-3)]), Some(statistic(12)));
+    }
+
+    #[test]
+    fn test_statistic_operands_with_wrong_length_are_skipped() {
+        assert_eq!(merge(b"s1/total_docs", Some(statistic(10)), vec![vec![1, 2, 3], statistic(5), vec![]]), Some(statistic(15)));
+    }
+
+    #[test]
+    fn test_skipped_values_are_counted() {
+        // Other tests skip values at the same time, so the count may go up by more
+        let count_before = corrupt_value_count();
+        merge(b"s1/total_docs", Some(vec![1, 2, 3]), vec![vec![1], statistic(5)]);
+        assert!(corrupt_value_count() >= count_before + 2);
+    }
+
+    #[test]
+    fn test_corrupt_statistic_is_reset() {
+        assert_eq!(merge(b"s1/total_docs", Some(vec![1, 2, 3]), vec![statistic(5)]), Some(statistic(5)));
+    }
+
+    #[test]
+    fn test_statistics_wrap_instead_of_overflowing() {
+        assert_eq!(merge(b"s1/total_docs", Some(statistic(i64::max_value())), vec![statistic(1)]), Some(statistic(i64::min_value())));
+    }
+
+    #[test]
+    fn test_other_keys_take_last_operand() {
+        assert_eq!(merge(b"k1", Some(b"foo".to_vec()), vec![b"bar".to_vec(), b"baz".to_vec()]), Some(b"baz".to_vec()));
+        assert_eq!(merge(b"k1", Some(b"foo".to_vec()), vec![]), Some(b"foo".to_vec()));
+        assert_eq!(merge(b"k1", None, vec![]), None);
+    }
+
+    #[test]
+    fn test_empty_key() {
+        assert_eq!(merge(b"", None, vec![b"foo".to_vec()]), Some(b"foo".to_vec()));
+    }
+
+    #[test]
+    fn test_combined_operands_merge_to_the_same_value() {
+        let operands = vec![vec![2, 0], vec![3], vec![4, 0]];
+        let combined = combine(b"d1/2/3", operands.clone()).unwrap();
+        assert_eq!(merge(b"d1/2/3", Some(vec![1, 0]), vec![combined]), merge(b"d1/2/3", Some(vec![1, 0]), operands));
+
+        let operands = vec![statistic(2), statistic(-7), vec![0]];
+        let combined = combine(b"s1/total_docs", operands.clone()).unwrap();
+        assert_eq!(merge(b"s1/total_docs", Some(statistic(10)), vec![combined]), merge(b"s1/total_docs", Some(statistic(10)), operands));
+
+        let operands = vec![b"foo".to_vec(), b"bar".to_vec()];
+        let combined = combine(b"k1", operands.clone()).unwrap();
+        assert_eq!(merge(b"k1", Some(b"baz".to_vec()), vec![combined]), merge(b"k1", Some(b"baz".to_vec()), operands));
+    }
+}
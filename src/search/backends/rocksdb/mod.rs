@@ -1,23 +1,38 @@
 mod key_builder;
+mod column_families;
 mod segment;
 mod segment_manager;
 mod segment_ops;
 mod segment_stats;
 mod segment_builder;
+mod numeric_blocks;
 mod term_dictionary;
 mod document_index;
+mod field_data_cache;
+mod merge_operator;
+mod merge_journal;
+mod checkpoint;
+mod disk_usage;
+mod group_commit;
+mod statistics_rollup;
 mod search;
 
 use std::str;
+use std::collections::HashMap;
 use std::fmt;
 use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use rocksdb::{self, DB, WriteBatch, Options, MergeOperands, Snapshot};
-use search::{Document, DocId, TermId};
+use rocksdb::{self, DB, Snapshot};
+use search::{Document, DocId, Term, TermId};
 use search::document::FieldValue;
+use search::geo::GeoPoint;
+use search::runtime::RuntimeField;
 use search::schema::{Schema, FieldType, FieldFlags, FieldId, AddFieldError};
-use search::segment::SegmentId;
+use search::segment::{SegmentId, Segment};
+use search::statistic_key::StatisticKey;
+use search::positions::EncodedPositions;
 use byteorder::{ByteOrder, LittleEndian};
 use chrono::{NaiveDateTime, DateTime, Utc};
 use fnv::FnvHashMap;
@@ -27,60 +42,17 @@ use self::key_builder::KeyBuilder;
 use self::segment_manager::SegmentManager;
 use self::term_dictionary::TermDictionaryManager;
 use self::document_index::DocumentIndexManager;
-
-fn merge_keys(key: &[u8], existing_val: Option<&[u8]>, operands: &mut MergeOperands) -> Option<Vec<u8>> {
-    match key[0] {
-        b'd' | b'x' => {
-            // Sequence of two byte document ids
-            // d = postings list
-            // x = deletion list
-
-            // Allocate vec for new Value
-            let new_size = match existing_val {
-                Some(existing_val) => existing_val.len(),
-                None => 0,
-            } + operands.size_hint().0 * 2;
-
-            let mut new_val = Vec::with_capacity(new_size);
-
-            // Push existing value
-            existing_val.map(|v| {
-                for b in v {
-                    new_val.push(*b);
-                }
-            });
-
-            // Append new entries
-            for op in operands {
-                for b in op {
-                    new_val.push(*b);
-                }
-            }
-
-            Some(new_val)
-        }
-        b's' => {
-            // Statistic
-            // An i64 number that can be incremented or decremented
-            let mut value = match existing_val {
-                Some(existing_val) => LittleEndian::read_i64(existing_val),
-                None => 0
-            };
-
-            for op in operands {
-                value += LittleEndian::read_i64(op);
-            }
-
-            let mut buf = [0; 8];
-            LittleEndian::write_i64(&mut buf, value);
-            Some(buf.iter().cloned().collect())
-        }
-        _ => {
-            // Unrecognised key, fallback to emulating a put operation (by taking the last value)
-            Some(operands.last().unwrap().iter().cloned().collect())
-        }
-    }
-}
+use self::field_data_cache::{FieldDataCache, DEFAULT_FIELD_DATA_CACHE_SIZE};
+use self::group_commit::PendingWrites;
+
+pub use self::segment::RocksDBSegment;
+pub use self::segment_manager::ActiveSegmentsIterator;
+pub use self::field_data_cache::{FieldData, FieldDataCacheStats};
+pub use self::statistics_rollup::RollupMismatch;
+pub use self::segment_stats::SegmentStatistics;
+pub use self::disk_usage::{DiskUsage, FieldDiskUsage};
+pub use self::merge_operator::corrupt_value_count;
+pub use self::search::{QueryLimits, QueryLimitError, SegmentFailure, DEFAULT_MAX_CLAUSE_COUNT, DEFAULT_MAX_EXPANSIONS};
 
 #[derive(Debug)]
 pub enum DocumentInsertError {
@@ -105,20 +77,33 @@ impl From<segment_builder::DocumentInsertError> for DocumentInsertError {
     }
 }
 
+/// Epochs are unique across all stores, so a store that is closed and opened again
+/// doesn't reuse the epochs it had before
+static NEXT_EPOCH: AtomicUsize = AtomicUsize::new(1);
+
+
 pub struct RocksDBStore {
     schema: Arc<Schema>,
     db: DB,
     term_dictionary: TermDictionaryManager,
     segments: SegmentManager,
     document_index: DocumentIndexManager,
+    field_data_cache: FieldDataCache,
+
+    /// Changes whenever the contents of the store change
+    epoch: AtomicUsize,
 }
 
 impl RocksDBStore {
     pub fn create<P: AsRef<Path>>(path: P) -> Result<RocksDBStore, String> {
-        let mut opts = Options::default();
-        opts.set_merge_operator("merge operator", merge_keys, None);
+        let mut opts = column_families::db_options();
         opts.create_if_missing(true);
-        let db = try!(DB::open(&opts, path));
+        let db = try!(DB::open_cf_descriptors(&opts, path, column_families::descriptors()));
+        try!(column_families::write_format_version(&db));
+
+        // Statistics rollup, total docs is always written so we can tell it has been built
+        let kb = KeyBuilder::statistic_rollup(&StatisticKey::TotalDocs);
+        try!(db.put_cf(column_families::handle(&db, column_families::STATS), &kb.key(), &[0; 8]));
 
         // Schema
         let schema = Schema::new();
@@ -143,13 +128,36 @@ impl RocksDBStore {
             term_dictionary: term_dictionary,
             segments: segments,
             document_index: document_index,
+            field_data_cache: FieldDataCache::new(DEFAULT_FIELD_DATA_CACHE_SIZE),
+            epoch: AtomicUsize::new(NEXT_EPOCH.fetch_add(1, Ordering::SeqCst)),
         })
     }
 
     pub fn open<P: AsRef<Path>>(path: P) -> Result<RocksDBStore, String> {
-        let mut opts = Options::default();
-        opts.set_merge_operator("merge operator", merge_keys, None);
-        let db = try!(DB::open(&opts, path));
+        let opts = column_families::db_options();
+        let db = try!(DB::open_cf_descriptors(&opts, path, column_families::descriptors()));
+
+        // Stores created before column families were introduced have all their
+        // keys in the default column family. Move them to where they belong
+        match try!(column_families::read_format_version(&db)) {
+            1 => {
+                try!(column_families::migrate_from_default_column_family(&db));
+                try!(document_index::build_doc_id_index(&db));
+                try!(document_index::upgrade_deletion_lists(&db));
+                try!(column_families::write_format_version(&db));
+            }
+            2 => {
+                try!(document_index::build_doc_id_index(&db));
+                try!(document_index::upgrade_deletion_lists(&db));
+                try!(column_families::write_format_version(&db));
+            }
+            3 => {
+                try!(document_index::upgrade_deletion_lists(&db));
+                try!(column_families::write_format_version(&db));
+            }
+            column_families::FORMAT_VERSION => {}
+            version => return Err(format!("unsupported store format version: {}", version)),
+        }
 
         let schema = match try!(db.get(b".schema")) {
             Some(schema) => {
@@ -171,13 +179,25 @@ impl RocksDBStore {
         // Document index
         let document_index = try!(DocumentIndexManager::open(&db));
 
-        Ok(RocksDBStore {
+        let store = RocksDBStore {
             schema: Arc::new(schema),
             db: db,
             term_dictionary: term_dictionary,
             segments: segments,
             document_index: document_index,
-        })
+            field_data_cache: FieldDataCache::new(DEFAULT_FIELD_DATA_CACHE_SIZE),
+            epoch: AtomicUsize::new(NEXT_EPOCH.fetch_add(1, Ordering::SeqCst)),
+        };
+
+        // Finish or undo any merges that were interrupted by a crash
+        try!(store.recover_merges());
+
+        // Build the statistics rollup if the store was written before it existed
+        if try!(store.reader().rolled_up_statistic(&StatisticKey::TotalDocs)).is_none() {
+            try!(store.repair_statistics_rollup());
+        }
+
+        Ok(store)
     }
 
     pub fn path(&self) -> &Path {
@@ -191,6 +211,7 @@ impl RocksDBStore {
 
         // FIXME: How do we throw this error?
         self.db.put(b".schema", serde_json::to_string(&*self.schema).unwrap().as_bytes()).unwrap();
+        self.bump_epoch();
 
         Ok(field_id)
     }
@@ -204,38 +225,80 @@ impl RocksDBStore {
 
             // FIXME: How do we throw this error?
             self.db.put(b".schema", serde_json::to_string(&*self.schema).unwrap().as_bytes()).unwrap();
+            self.bump_epoch();
         }
 
         field_removed
     }
 
+    /// Inserts a document, replacing the previous version of it (if any)
+    ///
+    /// The previous version is added to its segment's deletion list in the same write
+    /// batch that activates the new segment. Readers see the store through a snapshot,
+    /// so a key never has two live versions in search results, even before the
+    /// segments are merged.
     pub fn insert_or_update_document(&self, doc: &Document) -> Result<(), DocumentInsertError> {
         // Build segment in memory
         let mut builder = segment_builder::SegmentBuilder::new();
         let doc_key = doc.key.clone();
         try!(builder.add_document(doc));
 
-        // Write the segment
-        let segment = try!(self.write_segment(&builder));
+        // Collect the writes for the segment
+        let mut writes = PendingWrites::new();
+        let segment = try!(self.write_segment_to_batch(&builder, &mut writes));
 
-        // Update document index
+        // Update document index and commit
+        // The segment, the new primary key and the deletion of the previous version
+        // of the document are all written in the same write batch
         let doc_id = DocId(SegmentId(segment), 0);
-        try!(self.document_index.insert_or_replace_key(&self.db, &doc_key.as_bytes().iter().cloned().collect(), doc_id));
+        try!(self.document_index.insert_or_replace_key(&self.db, writes, &doc_key.as_bytes().iter().cloned().collect(), doc_id));
+        self.bump_epoch();
 
         Ok(())
     }
 
     pub fn write_segment(&self, builder: &segment_builder::SegmentBuilder) -> Result<u32, rocksdb::Error> {
+        let mut writes = PendingWrites::new();
+        let segment = try!(self.write_segment_to_batch(builder, &mut writes));
+
+        // Write data
+        try!(writes.write(&self.db));
+        self.bump_epoch();
+
+        Ok(segment)
+    }
+
+    /// Maps the term id at the end of a value type (eg, "tf12" or "pos12") from the
+    /// builder's term dictionary to the real one
+    fn remap_term_value_type(value_type: &[u8], term_dictionary_map: &FnvHashMap<TermId, TermId>) -> Vec<u8> {
+        for prefix in [&b"tf"[..], &b"pos"[..]].iter() {
+            if !value_type.starts_with(prefix) {
+                continue;
+            }
+
+            let term_id = str::from_utf8(&value_type[prefix.len()..]).ok().and_then(|term_id| term_id.parse::<u32>().ok());
+            if let Some(new_term_id) = term_id.and_then(|term_id| term_dictionary_map.get(&TermId(term_id))) {
+                let mut new_value_type = prefix.to_vec();
+                new_value_type.extend(new_term_id.0.to_string().as_bytes());
+                return new_value_type;
+            }
+        }
+
+        value_type.to_vec()
+    }
+
+    /// Allocates a segment and adds the contents of the builder to the pending writes
+    ///
+    /// The segment becomes active when the writes are written. Terms are added to
+    /// the term dictionary straight away.
+    fn write_segment_to_batch(&self, builder: &segment_builder::SegmentBuilder, writes: &mut PendingWrites) -> Result<u32, rocksdb::Error> {
         // Allocate a segment ID
         let segment = try!(self.segments.new_segment(&self.db));
 
-        // Start write batch
-        let mut write_batch = WriteBatch::default();
-
         // Set segment active flag, this will activate the segment as soon as the
-        // write batch is written
+        // writes are written
         let kb = KeyBuilder::segment_active(segment);
-        try!(write_batch.put(&kb.key(), b""));
+        writes.put(&kb.key(), b"");
 
         // Merge the term dictionary
         // Writes new terms to disk and generates mapping between the builder's term dictionary and the real one
@@ -255,51 +318,97 @@ impl RocksDBStore {
 
             // Write
             let kb = KeyBuilder::segment_postings_list(segment, field_id.0, new_term_id.0);
-            try!(write_batch.put(&kb.key(), &postings_bytes));
+            writes.put_cf(column_families::POSTINGS, &kb.key(), &postings_bytes);
         }
 
         // Write stored fields
+        // Term frequencies and positions are stored against the term id, so these need mapping too
         for (&(field_id, doc_id, ref value_type), value) in builder.stored_field_values.iter() {
-            let kb = KeyBuilder::stored_field_value(segment, doc_id, field_id.0, value_type);
-            try!(write_batch.put(&kb.key(), value));
+            let value_type = RocksDBStore::remap_term_value_type(value_type, &term_dictionary_map);
+            let kb = KeyBuilder::stored_field_value(segment, doc_id, field_id.0, &value_type);
+            writes.put_cf(column_families::STORED, &kb.key(), value);
         }
 
         // Write statistics
-        for (name, value) in builder.statistics.iter() {
-            let kb = KeyBuilder::segment_stat(segment, name);
+        // These are also added to the rollup, which becomes visible at the same time as the segment
+        for (stat_key, value) in builder.statistics.iter() {
+            let kb = KeyBuilder::segment_stat(segment, stat_key);
 
             let mut value_bytes = [0; 8];
             LittleEndian::write_i64(&mut value_bytes, *value);
-            try!(write_batch.put(&kb.key(), &value_bytes));
-        }
+            writes.put_cf(column_families::STATS, &kb.key(), &value_bytes);
 
-        // Write data
-        try!(self.db.write(write_batch));
+            if statistics_rollup::is_rolled_up(stat_key) {
+                statistics_rollup::add_to_rollup(writes, stat_key, *value);
+            }
+        }
 
         Ok(segment)
     }
 
     pub fn remove_document_by_key(&self, doc_key: &str) -> Result<bool, rocksdb::Error> {
         match try!(self.document_index.delete_document_by_key(&self.db, &doc_key.as_bytes().iter().cloned().collect())) {
-            Some(_doc_id) => Ok(true),
+            Some(_doc_id) => {
+                self.bump_epoch();
+                Ok(true)
+            }
             None => Ok(false),
         }
     }
 
+    /// Returns the current epoch of the store
+    ///
+    /// The epoch is changed after every write, so anything computed from the store can
+    /// be reused for as long as its epoch stays the same. Read it before taking the
+    /// reader the computation uses: a write that the reader can see may not have
+    /// changed the epoch yet, but a write that changed it is always visible.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst) as u64
+    }
+
+    fn bump_epoch(&self) {
+        let new_epoch = NEXT_EPOCH.fetch_add(1, Ordering::SeqCst);
+
+        // Concurrent writes may take their epochs in either order, keep the latest
+        let mut current_epoch = self.epoch.load(Ordering::SeqCst);
+        while current_epoch < new_epoch {
+            match self.epoch.compare_exchange(current_epoch, new_epoch, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(epoch) => current_epoch = epoch,
+            }
+        }
+    }
+
+    pub fn field_data_cache_stats(&self) -> FieldDataCacheStats {
+        self.field_data_cache.stats()
+    }
+
     pub fn reader<'a>(&'a self) -> RocksDBReader<'a> {
+        // Register before taking the snapshot, so segments the snapshot can see aren't purged
+        let generation = self.segments.register_reader();
+
         RocksDBReader {
             store: &self,
             snapshot: self.db.snapshot(),
+            generation: generation,
         }
     }
 }
 
+impl Drop for RocksDBStore {
+    fn drop(&mut self) {
+        // Readers borrow the store so none are open now, finish any purges that were waiting for them
+        let _ = self.purge_deferred_segments();
+    }
+}
+
 impl fmt::Debug for RocksDBStore {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "RocksDBStore {{ path: {:?} }}", self.db.path())
     }
 }
 
+#[derive(Debug)]
 pub enum StoredFieldReadError {
     /// The provided FieldId wasn't valid for this index
     InvalidFieldId(FieldId),
@@ -315,6 +424,15 @@ pub enum StoredFieldReadError {
 
     /// An integer/datetime field was read but the value wasn't 8 bytes
     IntegerFieldValueSizeError(usize),
+
+    /// A dense vector field was read but the value wasn't a multiple of 4 bytes
+    VectorFieldValueSizeError(usize),
+
+    /// A geo point field was read but the value wasn't 16 bytes
+    GeoPointFieldValueSizeError(usize),
+
+    /// A float field was read but the value wasn't 8 bytes
+    FloatFieldValueSizeError(usize),
 }
 
 impl From<rocksdb::Error> for StoredFieldReadError {
@@ -325,7 +443,16 @@ impl From<rocksdb::Error> for StoredFieldReadError {
 
 pub struct RocksDBReader<'a> {
     store: &'a RocksDBStore,
-    snapshot: Snapshot<'a>
+    snapshot: Snapshot<'a>,
+
+    /// The segment manager generation the reader was registered in
+    generation: u64,
+}
+
+impl<'a> Drop for RocksDBReader<'a> {
+    fn drop(&mut self) {
+        self.store.segments.unregister_reader(self.generation);
+    }
 }
 
 impl<'a> RocksDBReader<'a> {
@@ -333,11 +460,66 @@ impl<'a> RocksDBReader<'a> {
         &self.store.schema
     }
 
-    pub fn contains_document_key(&self, doc_key: &str) -> bool {
-        // TODO: use snapshot
-        self.store.document_index.contains_document_key(&doc_key.as_bytes().iter().cloned().collect())
+    /// Iterates the segments that were active when the reader was created
+    ///
+    /// This is the starting point for implementing custom retrieval. Each segment
+    /// gives access to its postings lists, stored values and statistics.
+    pub fn segments(&self) -> ActiveSegmentsIterator {
+        self.store.segments.iter_active(self)
     }
 
+    /// Counts the live and deleted documents in the active segments
+    ///
+    /// These are read from the statistics rollup so the segments don't need to be visited.
+    pub fn doc_counts(&self) -> Result<(u64, u64), rocksdb::Error> {
+        let total = try!(self.rolled_up_statistic(&StatisticKey::TotalDocs)).unwrap_or(0);
+        let deleted = try!(self.rolled_up_statistic(&StatisticKey::DeletedDocs)).unwrap_or(0);
+
+        Ok(((total - deleted) as u64, deleted as u64))
+    }
+
+    /// Retrieves the TermId for a term, if it exists in the index
+    ///
+    /// TermIds are needed to load postings lists from segments.
+    pub fn term_id(&self, term: &Term) -> Option<TermId> {
+        self.store.term_dictionary.get(term)
+    }
+
+    /// Finds the id of the document with the key, as it was when the reader was created
+    pub fn find_doc_id(&self, doc_key: &str) -> Result<Option<DocId>, rocksdb::Error> {
+        self.store.document_index.get_doc_id(&self.store.db, &self.snapshot, &doc_key.as_bytes().iter().cloned().collect())
+    }
+
+    /// Checks if a document with the key existed when the reader was created
+    pub fn contains_document_key(&self, doc_key: &str) -> Result<bool, rocksdb::Error> {
+        let doc_id = try!(self.find_doc_id(doc_key));
+        Ok(doc_id.is_some())
+    }
+
+    /// Retrieves the key of a document, as it was when the reader was created
+    ///
+    /// Only live documents have keys in the document index, so this returns None for
+    /// documents that had been replaced or deleted.
+    pub fn doc_key(&self, doc_id: DocId) -> Result<Option<String>, rocksdb::Error> {
+        let key = try!(self.store.document_index.find_key_by_doc_id(&self.store.db, &self.snapshot, doc_id));
+
+        Ok(key.map(|key| String::from_utf8_lossy(&key).into_owned()))
+    }
+
+    /// Loads the positions of a term in a field of a document
+    ///
+    /// Returns None if the term isn't in the field. The positions are decoded as
+    /// they're iterated.
+    pub fn positions(&self, field_id: FieldId, term: &Term, doc_id: DocId) -> Result<Option<EncodedPositions>, String> {
+        let term_id = match self.store.term_dictionary.get(term) {
+            Some(term_id) => term_id,
+            None => return Ok(None),
+        };
+
+        RocksDBSegment::new(self, (doc_id.0).0).load_positions(doc_id.1, field_id, term_id)
+    }
+
+    /// Reads and decodes the stored value of a field for a document
     pub fn read_stored_field(&self, field_id: FieldId, doc_id: DocId) -> Result<Option<FieldValue>, StoredFieldReadError> {
         let field_info = match self.schema().get(&field_id) {
             Some(field_info) => field_info,
@@ -346,50 +528,174 @@ impl<'a> RocksDBReader<'a> {
 
         let kb = KeyBuilder::stored_field_value((doc_id.0).0, doc_id.1, field_id.0, b"val");
 
-        match try!(self.snapshot.get(&kb.key())) {
-            Some(value) => {
-                match field_info.field_type {
-                    FieldType::Text | FieldType::PlainString => {
-                        match str::from_utf8(&value) {
-                            Ok(value_str) => {
-                                Ok(Some(FieldValue::String(value_str.to_string())))
-                            }
-                            Err(e) => {
-                                Err(StoredFieldReadError::TextFieldUTF8DecodeError(value.to_vec(), e))
-                            }
-                        }
-                    }
-                    FieldType::I64 => {
-                        if value.len() != 8 {
-                            return Err(StoredFieldReadError::IntegerFieldValueSizeError(value.len()));
-                        }
+        match try!(self.snapshot.get_cf(column_families::handle(&self.store.db, column_families::STORED), &kb.key())) {
+            Some(value) => Ok(Some(try!(decode_stored_field_value(&field_info.field_type, &value)))),
+            None => Ok(None),
+        }
+    }
 
-                        Ok(Some(FieldValue::Integer(LittleEndian::read_i64(&value))))
-                    }
-                    FieldType::Boolean => {
-                        if value[..] == [b't'] {
-                            Ok(Some(FieldValue::Boolean(true)))
-                        } else if value[..] == [b'f'] {
-                            Ok(Some(FieldValue::Boolean(false)))
-                        } else {
-                            Err(StoredFieldReadError::BooleanFieldDecodeError(value.to_vec()))
-                        }
-                    }
-                    FieldType::DateTime => {
-                        if value.len() != 8 {
-                            return Err(StoredFieldReadError::IntegerFieldValueSizeError(value.len()))
-                        }
-
-                        let timestamp_with_micros = LittleEndian::read_i64(&value);
-                        let timestamp = timestamp_with_micros / 1000000;
-                        let micros = timestamp_with_micros % 1000000;
-                        let nanos = micros * 1000;
-                        let datetime = NaiveDateTime::from_timestamp(timestamp, nanos as u32);
-                        Ok(Some(FieldValue::DateTime(DateTime::from_utc(datetime, Utc))))
-                    }
+    /// Loads the values of a field for every document in a segment into memory
+    ///
+    /// Field data is cached by the store and shared between readers, so this is cheap
+    /// to call again for the same segment and field.
+    pub fn field_data(&self, segment: &RocksDBSegment, field_id: FieldId) -> Result<Arc<FieldData>, StoredFieldReadError> {
+        let field_info = match self.schema().get(&field_id) {
+            Some(field_info) => field_info,
+            None => return Err(StoredFieldReadError::InvalidFieldId(field_id)),
+        };
+
+        self.store.field_data_cache.get_or_load(segment.id().0, field_id, || -> Result<FieldData, StoredFieldReadError> {
+            let total_docs = try!(segment.statistic(&StatisticKey::TotalDocs)).unwrap_or(0);
+            let mut values = Vec::with_capacity(total_docs as usize);
+
+            for doc_local_id in 0..total_docs {
+                // Multi-valued fields have all their values in "dv", otherwise use the stored value
+                if let Some(value) = try!(segment.stored_value_raw(doc_local_id as u16, field_id, b"dv")) {
+                    values.push(try!(decode_doc_values(&field_info.field_type, &value)));
+                    continue;
+                }
+
+                match try!(segment.stored_value_raw(doc_local_id as u16, field_id, b"val")) {
+                    Some(value) => values.push(vec![try!(decode_stored_field_value(&field_info.field_type, &value))]),
+                    None => values.push(Vec::new()),
                 }
             }
-            None => Ok(None),
+
+            Ok(FieldData::new(values))
+        })
+    }
+
+    /// Reads the value of a field for a document through the field data cache
+    ///
+    /// This is much faster than read_stored_field when reading the values of many
+    /// documents in the same segments, such as when sorting.
+    pub fn doc_value(&self, field_id: FieldId, doc_id: DocId) -> Result<Option<FieldValue>, StoredFieldReadError> {
+        let segment = RocksDBSegment::new(self, (doc_id.0).0);
+        let field_data = try!(self.field_data(&segment, field_id));
+        Ok(field_data.get(doc_id.1).cloned())
+    }
+
+    /// Reads all values of a multi-valued field for a document through the field data cache
+    pub fn doc_values(&self, field_id: FieldId, doc_id: DocId) -> Result<Vec<FieldValue>, StoredFieldReadError> {
+        let segment = RocksDBSegment::new(self, (doc_id.0).0);
+        let field_data = try!(self.field_data(&segment, field_id));
+        Ok(field_data.get_all(doc_id.1).to_vec())
+    }
+
+    /// Computes the values of a runtime field for a document
+    ///
+    /// The field reads the document's source and the doc values of any fields it needs,
+    /// fields that aren't in the index have no values.
+    pub fn runtime_values(&self, field: &RuntimeField, doc_id: DocId) -> Result<Vec<FieldValue>, String> {
+        let source_field = self.schema().get_field_by_name("_source");
+        let source = match source_field {
+            Some(source_field) => try!(self.read_stored_field(source_field, doc_id).map_err(|e| format!("failed to read source: {:?}", e))),
+            None => None,
+        };
+        let source = match source {
+            Some(FieldValue::String(source)) => {
+                match serde_json::from_str(&source) {
+                    Ok(serde_json::Value::Object(source)) => source,
+                    _ => serde_json::Map::new(),
+                }
+            }
+            _ => serde_json::Map::new(),
+        };
+
+        let mut doc_values = HashMap::new();
+        for name in field.doc_value_fields() {
+            let values = match self.schema().get_field_by_name(&name) {
+                Some(field_id) => try!(self.doc_values(field_id, doc_id).map_err(|e| format!("failed to read doc values: {:?}", e))),
+                None => Vec::new(),
+            };
+
+            doc_values.insert(name, values);
+        }
+
+        field.compute(&source, &doc_values)
+    }
+}
+
+/// Decodes all values of a multi-valued field (the "dv" value type)
+fn decode_doc_values(field_type: &FieldType, mut value: &[u8]) -> Result<Vec<FieldValue>, StoredFieldReadError> {
+    let mut values = Vec::new();
+
+    while value.len() >= 4 {
+        let length = LittleEndian::read_u32(&value[..4]) as usize;
+        if value.len() < 4 + length {
+            break;
+        }
+
+        values.push(try!(decode_stored_field_value(field_type, &value[4..4 + length])));
+        value = &value[4 + length..];
+    }
+
+    Ok(values)
+}
+
+fn decode_stored_field_value(field_type: &FieldType, value: &[u8]) -> Result<FieldValue, StoredFieldReadError> {
+    match *field_type {
+        FieldType::Text | FieldType::PlainString => {
+            match str::from_utf8(value) {
+                Ok(value_str) => {
+                    Ok(FieldValue::String(value_str.to_string()))
+                }
+                Err(e) => {
+                    Err(StoredFieldReadError::TextFieldUTF8DecodeError(value.to_vec(), e))
+                }
+            }
+        }
+        FieldType::I64 => {
+            if value.len() != 8 {
+                return Err(StoredFieldReadError::IntegerFieldValueSizeError(value.len()));
+            }
+
+            Ok(FieldValue::Integer(LittleEndian::read_i64(value)))
+        }
+        FieldType::Boolean => {
+            if value[..] == [b't'] {
+                Ok(FieldValue::Boolean(true))
+            } else if value[..] == [b'f'] {
+                Ok(FieldValue::Boolean(false))
+            } else {
+                Err(StoredFieldReadError::BooleanFieldDecodeError(value.to_vec()))
+            }
+        }
+        FieldType::DateTime => {
+            if value.len() != 8 {
+                return Err(StoredFieldReadError::IntegerFieldValueSizeError(value.len()))
+            }
+
+            let timestamp_with_micros = LittleEndian::read_i64(value);
+            let timestamp = timestamp_with_micros / 1000000;
+            let micros = timestamp_with_micros % 1000000;
+            let nanos = micros * 1000;
+            let datetime = NaiveDateTime::from_timestamp(timestamp, nanos as u32);
+            Ok(FieldValue::DateTime(DateTime::from_utc(datetime, Utc)))
+        }
+        FieldType::Binary => {
+            Ok(FieldValue::Bytes(value.to_vec()))
+        }
+        FieldType::DenseVector => {
+            if value.len() % 4 != 0 {
+                return Err(StoredFieldReadError::VectorFieldValueSizeError(value.len()));
+            }
+
+            Ok(FieldValue::Vector(value.chunks(4).map(LittleEndian::read_f32).collect()))
+        }
+        FieldType::GeoPoint => {
+            if value.len() != 16 {
+                return Err(StoredFieldReadError::GeoPointFieldValueSizeError(value.len()));
+            }
+
+            Ok(FieldValue::GeoPoint(GeoPoint::new(LittleEndian::read_f64(&value[..8]), LittleEndian::read_f64(&value[8..]))))
+        }
+        FieldType::F64 => {
+            if value.len() != 8 {
+                return Err(StoredFieldReadError::FloatFieldValueSizeError(value.len()));
+            }
+
+            Ok(FieldValue::Float(LittleEndian::read_f64(value)))
         }
     }
 }
@@ -398,17 +704,33 @@ impl<'a> RocksDBReader<'a> {
 mod tests {
     use std::fs::remove_dir_all;
     use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
 
-    use rocksdb::DB;
+    use rocksdb::{DB, Options};
+    use roaring::RoaringBitmap;
     use fnv::FnvHashMap;
-    use search::{Term, Token, Document};
+    use serde_json;
+    use search::{Term, Token, Document, DocId};
     use search::document::FieldValue;
-    use search::schema::{FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::similarity::VectorSimilarity;
+    use search::feature::FeatureFunction;
+    use search::geo::{self, GeoShape, GeoShapeRelation};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::segment::{Segment, SegmentId};
     use search::query::Query;
     use search::query::term_scorer::TermScorer;
     use search::collectors::top_score::TopScoreCollector;
+    use search::collectors::total_count::TotalCountCollector;
+
+    use search::statistic_key::StatisticKey;
 
-    use super::RocksDBStore;
+    use super::{RocksDBStore, RollupMismatch};
+    use super::column_families;
+    use super::key_builder::KeyBuilder;
+    use super::merge_journal;
+    use super::document_index::decode_deletion_list;
 
     fn remove_dir_all_ignore_error<P: AsRef<Path>>(path: P) {
         match remove_dir_all(&path) {
@@ -473,8 +795,10 @@ mod tests {
 
         store.insert_or_update_document(&Document {
             key: "test_doc".to_string(),
+            boost: 1.0f32,
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            doc_values: FnvHashMap::default(),
         }).unwrap();
 
         let mut indexed_fields = FnvHashMap::default();
@@ -502,8 +826,10 @@ mod tests {
 
         store.insert_or_update_document(&Document {
             key: "another_test_doc".to_string(),
+            boost: 1.0f32,
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            doc_values: FnvHashMap::default(),
         }).unwrap();
 
         store.merge_segments(&vec![1, 2]).unwrap();
@@ -530,13 +856,574 @@ mod tests {
             string
         }
 
-        let mut iter = db.raw_iterator();
-        iter.seek_to_first();
-        while iter.valid() {
-            println!("{} = {:?}", bytes_to_string(&iter.key().unwrap()), iter.value().unwrap());
+        for cf_name in &["default", column_families::TERMS, column_families::POSTINGS, column_families::STORED, column_families::DOCINDEX, column_families::STATS] {
+            println!("[{}]", cf_name);
+
+            let mut iter = db.raw_iterator_cf(column_families::handle(db, cf_name)).unwrap();
+            iter.seek_to_first();
+            while iter.valid() {
+                println!("{} = {:?}", bytes_to_string(&iter.key().unwrap()), iter.value().unwrap());
+
+                iter.next();
+            }
+        }
+    }
+
+    #[test]
+    fn test_positions() {
+        remove_dir_all_ignore_error("test_indices/test_positions");
+
+        let store = make_test_store("test_indices/test_positions");
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+        let index_reader = store.reader();
+
+        let mut collector = TopScoreCollector::new(10);
+        index_reader.search(&mut collector, &Query::term(body_field, Term::from_string("dolar"))).unwrap();
+        let doc_ids = collector.into_sorted_vec().iter().map(|doc| DocId::from_u64(doc.doc_id())).collect::<Vec<_>>();
+        assert_eq!(doc_ids.len(), 2);
+
+        // The term ids in the key must have been mapped to the ones in the term dictionary
+        for doc_id in doc_ids.iter() {
+            let positions = index_reader.positions(body_field, &Term::from_string("dolar"), *doc_id).unwrap().unwrap();
+            assert_eq!(positions.iter().collect::<Vec<_>>(), vec![3]);
+        }
+
+        // Terms that aren't in the field have no positions
+        assert_eq!(index_reader.positions(body_field, &Term::from_string("hello"), doc_ids[0]), Ok(None));
+        assert_eq!(index_reader.positions(body_field, &Term::from_string("missing"), doc_ids[0]), Ok(None));
+    }
+
+    #[test]
+    fn test_low_level_api() {
+        remove_dir_all_ignore_error("test_indices/test_low_level_api");
+
+        let store = make_test_store("test_indices/test_low_level_api");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let pk_field = store.schema.get_field_by_name("pk").unwrap();
+        let index_reader = store.reader();
+
+        // Both documents were merged into a single segment
+        let segments = index_reader.segments().collect::<Vec<_>>();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].live_docs().unwrap().len(), 2);
+
+        // Find the document with "hello" in the title
+        let term_id = index_reader.term_id(&Term::from_string("hello")).unwrap();
+        let postings = segments[0].postings(title_field, term_id).unwrap().unwrap();
+        assert_eq!(postings.len(), 1);
+
+        let doc_local_id = postings.iter().next().unwrap() as u16;
+        let pk = index_reader.read_stored_field(pk_field, segments[0].doc_id(doc_local_id)).ok().unwrap();
+        match pk {
+            Some(FieldValue::Integer(1)) => {}
+            _ => panic!("unexpected pk value {:?}", pk),
+        }
+
+        // Documents can be mapped back to their keys
+        assert_eq!(index_reader.doc_key(segments[0].doc_id(doc_local_id)).unwrap(), Some("test_doc".to_string()));
+
+        // Stored values can also be read as raw bytes
+        let pk_raw = segments[0].stored_value_raw(doc_local_id, pk_field, b"val").unwrap().unwrap();
+        assert_eq!(&pk_raw[..], &[1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_replace_document() {
+        remove_dir_all_ignore_error("test_indices/test_replace_document");
+
+        let mut store = RocksDBStore::create("test_indices/test_replace_document").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for term in &["hello", "world"] {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string(term), position: 1 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: "test_doc".to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        // Only the latest version of the document should be visible, including after reopening
+        drop(store);
+        let store = RocksDBStore::open("test_indices/test_replace_document").unwrap();
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::All { score: 1.0f32 }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+
+        // Deleting the document should also persist
+        assert!(store.remove_document_by_key("test_doc").unwrap());
+        drop(store);
+        let store = RocksDBStore::open("test_indices/test_replace_document").unwrap();
+        assert!(!store.reader().contains_document_key("test_doc").unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_inserts() {
+        remove_dir_all_ignore_error("test_indices/test_concurrent_inserts");
+
+        let mut store = RocksDBStore::create("test_indices/test_concurrent_inserts").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let store = Arc::new(store);
+
+        let threads = (0..4).map(|thread_num| {
+            let store = store.clone();
+
+            thread::spawn(move || {
+                for i in 0..25 {
+                    let mut indexed_fields = FnvHashMap::default();
+                    indexed_fields.insert(
+                        title_field,
+                        vec![
+                            Token { term: Term::from_string("hello"), position: 1 },
+                        ].into()
+                    );
+
+                    // Every thread writes to the same keys, replacing each other's documents
+                    let key = format!("doc_{}", i);
+                    store.insert_or_update_document(&Document {
+                        key: key.clone(),
+                        boost: 1.0f32,
+                        indexed_fields: indexed_fields,
+                        stored_fields: FnvHashMap::default(),
+                        doc_values: FnvHashMap::default(),
+                    }).unwrap();
+
+                    // The document must be readable as soon as the insert returns
+                    assert!(store.reader().contains_document_key(&key).unwrap(), "thread {} can't read {}", thread_num, key);
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Only one version of each document should be visible, including after reopening
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::All { score: 1.0f32 }).unwrap();
+        assert_eq!(collector.get_total_count(), 25);
+
+        drop(store);
+        let store = RocksDBStore::open("test_indices/test_concurrent_inserts").unwrap();
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::All { score: 1.0f32 }).unwrap();
+        assert_eq!(collector.get_total_count(), 25);
+    }
+
+    #[test]
+    fn test_updates_never_give_duplicate_hits() {
+        remove_dir_all_ignore_error("test_indices/test_updates_never_give_duplicate_hits");
+
+        let mut store = RocksDBStore::create("test_indices/test_updates_never_give_duplicate_hits").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let store = Arc::new(store);
+
+        let insert = move |store: &RocksDBStore, key: &str| {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string("hello"), position: 1 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        };
+
+        let keys = (0..10).map(|i| format!("doc_{}", i)).collect::<Vec<_>>();
+        for key in keys.iter() {
+            insert(&store, key);
+        }
+
+        // Keep replacing the documents, merging the segments now and then
+        let finished = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let store = store.clone();
+            let keys = keys.clone();
+            let finished = finished.clone();
+
+            thread::spawn(move || {
+                for round in 0..20 {
+                    for key in keys.iter() {
+                        insert(&store, key);
+                    }
+
+                    if round % 5 == 4 {
+                        let segments = store.get_segment_statistics().unwrap().into_iter().map(|(segment, _)| segment).collect::<Vec<_>>();
+                        store.merge_segments(&segments).unwrap();
+                        store.purge_segments(&segments).unwrap();
+                    }
+                }
+
+                finished.store(true, Ordering::SeqCst);
+            })
+        };
+
+        // Every search must find exactly one version of each document
+        while !finished.load(Ordering::SeqCst) {
+            let reader = store.reader();
+            let mut collector = TopScoreCollector::new(100);
+            reader.search(&mut collector, &Query::All { score: 1.0f32 }).unwrap();
+
+            let mut hit_keys = collector.into_sorted_vec().into_iter().map(|doc| reader.doc_key(DocId::from_u64(doc.doc_id())).unwrap().unwrap()).collect::<Vec<_>>();
+            hit_keys.sort();
+            assert_eq!(hit_keys, keys);
+        }
+
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_statistics_rollup() {
+        remove_dir_all_ignore_error("test_indices/test_statistics_rollup");
+
+        let mut store = RocksDBStore::create("test_indices/test_statistics_rollup").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for key in &["a", "b", "c", "a"] {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string("hello"), position: 1 },
+                    Token { term: Term::from_string("world"), position: 2 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+        store.remove_document_by_key("b").unwrap();
+
+        // Replacing "a" and deleting "b" both leave a deleted document behind
+        assert_eq!(store.reader().doc_counts().unwrap(), (2, 2));
+        assert_eq!(store.reader().rolled_up_statistic(&StatisticKey::TotalFieldTokens(title_field)).unwrap(), Some(8));
+        assert!(store.check_statistics_rollup().unwrap().is_empty());
+
+        // Merging segments shouldn't change the totals
+        let segments = store.get_segment_statistics().unwrap().into_iter().map(|(segment, _)| segment).collect::<Vec<_>>();
+        store.merge_segments(&segments).unwrap();
+        store.purge_segments(&segments).unwrap();
+        assert_eq!(store.reader().doc_counts().unwrap(), (2, 2));
+        assert!(store.check_statistics_rollup().unwrap().is_empty());
+
+        // Corrupt the rollup, it should be found and repaired
+        let kb = KeyBuilder::statistic_rollup(&StatisticKey::TotalDocs);
+        store.db.put_cf(column_families::handle(&store.db, column_families::STATS), &kb.key(), &[1, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(store.check_statistics_rollup().unwrap(), vec![
+            RollupMismatch {
+                stat_key: StatisticKey::TotalDocs,
+                expected: 4,
+                actual: Some(1),
+            },
+        ]);
+        assert_eq!(store.repair_statistics_rollup().unwrap().len(), 1);
+        assert!(store.check_statistics_rollup().unwrap().is_empty());
+        assert_eq!(store.reader().doc_counts().unwrap(), (2, 2));
+
+        // Stores without a rollup have it built when they're opened
+        let kb = KeyBuilder::statistic_rollup(&StatisticKey::TotalDocs);
+        store.db.delete_cf(column_families::handle(&store.db, column_families::STATS), &kb.key()).unwrap();
+        drop(store);
+        let store = RocksDBStore::open("test_indices/test_statistics_rollup").unwrap();
+        assert_eq!(store.reader().doc_counts().unwrap(), (2, 2));
+    }
+
+    #[test]
+    fn test_purge_waits_for_readers() {
+        remove_dir_all_ignore_error("test_indices/test_purge_waits_for_readers");
+
+        let mut store = RocksDBStore::create("test_indices/test_purge_waits_for_readers").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
 
-            iter.next();
+        for key in &["a", "b"] {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string("hello"), position: 1 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
         }
+
+        let segment_exists = |store: &RocksDBStore, segment: u32| {
+            let kb = KeyBuilder::segment_stat(segment, &StatisticKey::TotalDocs);
+            store.db.get_cf(column_families::handle(&store.db, column_families::STATS), &kb.key()).unwrap().is_some()
+        };
+
+        // A reader created before the merge can still see the source segments
+        let reader = store.reader();
+        store.merge_segments(&vec![1, 2]).unwrap();
+        store.purge_segments(&vec![1, 2]).unwrap();
+        assert!(segment_exists(&store, 1));
+        assert!(segment_exists(&store, 2));
+
+        let mut collector = TotalCountCollector::new();
+        reader.search(&mut collector, &Query::Term { field: title_field, term: Term::from_string("hello"), scorer: TermScorer::default() }).unwrap();
+        assert_eq!(collector.get_total_count(), 2);
+
+        // Readers created after the merge don't stop the purge
+        let new_reader = store.reader();
+        assert_eq!(store.purge_deferred_segments().unwrap(), 0);
+
+        drop(reader);
+        assert_eq!(store.purge_deferred_segments().unwrap(), 1);
+        assert!(!segment_exists(&store, 1));
+        assert!(!segment_exists(&store, 2));
+
+        let mut collector = TotalCountCollector::new();
+        new_reader.search(&mut collector, &Query::Term { field: title_field, term: Term::from_string("hello"), scorer: TermScorer::default() }).unwrap();
+        assert_eq!(collector.get_total_count(), 2);
+    }
+
+    #[test]
+    fn test_requeued_purges_are_retried() {
+        remove_dir_all_ignore_error("test_indices/test_requeued_purges_are_retried");
+
+        let store = RocksDBStore::create("test_indices/test_requeued_purges_are_retried").unwrap();
+
+        // Purges that were taken but failed to run are put back in the queue ahead of the others
+        let reader = store.reader();
+        store.segments.advance_generation();
+        assert!(store.segments.defer_purge(&[3]));
+        store.segments.requeue_purges(vec![vec![1], vec![2]]);
+        assert_eq!(store.deferred_purge_count(), 3);
+
+        // They're purgeable straight away, unlike the purge still waiting for the reader
+        assert_eq!(store.segments.take_purgeable_segments(), vec![vec![1], vec![2]]);
+        assert_eq!(store.deferred_purge_count(), 1);
+
+        drop(reader);
+        assert_eq!(store.segments.take_purgeable_segments(), vec![vec![3]]);
+    }
+
+    #[test]
+    fn test_recover_merges() {
+        remove_dir_all_ignore_error("test_indices/test_recover_merges");
+
+        let mut store = RocksDBStore::create("test_indices/test_recover_merges").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for key in &["a", "b"] {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string("hello"), position: 1 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        let segment_exists = |store: &RocksDBStore, segment: u32| {
+            let kb = KeyBuilder::segment_stat(segment, &StatisticKey::TotalDocs);
+            store.db.get_cf(column_families::handle(&store.db, column_families::STATS), &kb.key()).unwrap().is_some()
+        };
+
+        // A merge that crashed before it was committed, the new segment is deleted
+        merge_journal::record_merging(&store.db, 100, &[1, 2]).unwrap();
+        let kb = KeyBuilder::segment_stat(100, &StatisticKey::TotalDocs);
+        store.db.put_cf(column_families::handle(&store.db, column_families::STATS), &kb.key(), &[2, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        drop(store);
+
+        let store = RocksDBStore::open("test_indices/test_recover_merges").unwrap();
+        assert!(!segment_exists(&store, 100));
+        assert!(segment_exists(&store, 1));
+        assert!(merge_journal::read_entries(&store.db).unwrap().is_empty());
+
+        // A merge that crashed before the source segments were purged, they're purged now
+        store.merge_segments(&vec![1, 2]).unwrap();
+        assert_eq!(merge_journal::read_entries(&store.db).unwrap().len(), 1);
+        drop(store);
+
+        let store = RocksDBStore::open("test_indices/test_recover_merges").unwrap();
+        assert!(!segment_exists(&store, 1));
+        assert!(!segment_exists(&store, 2));
+        assert!(merge_journal::read_entries(&store.db).unwrap().is_empty());
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::Term { field: title_field, term: Term::from_string("hello"), scorer: TermScorer::default() }).unwrap();
+        assert_eq!(collector.get_total_count(), 2);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        remove_dir_all_ignore_error("test_indices/test_checkpoint");
+        remove_dir_all_ignore_error("test_indices/test_checkpoint_copy");
+
+        let store = make_test_store("test_indices/test_checkpoint");
+        assert!(store.checkpoint("test_indices/test_checkpoint_copy").unwrap() > 0);
+
+        // The checkpoint doesn't see changes made afterwards
+        assert!(store.remove_document_by_key("test_doc").unwrap());
+
+        let copy = RocksDBStore::open("test_indices/test_checkpoint_copy").unwrap();
+        assert!(copy.reader().contains_document_key("test_doc").unwrap());
+        assert_eq!(copy.reader().doc_counts().unwrap(), (2, 0));
+        assert_eq!(copy.schema.get_field_by_name("title"), store.schema.get_field_by_name("title"));
+
+        // The directory must not already exist
+        assert!(store.checkpoint("test_indices/test_checkpoint_copy").is_err());
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        remove_dir_all_ignore_error("test_indices/test_disk_usage");
+
+        let store = make_test_store("test_indices/test_disk_usage");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let pk_field = store.schema.get_field_by_name("pk").unwrap();
+        let usage = store.disk_usage().unwrap();
+
+        // Indexed fields have postings but nothing stored
+        let title_usage = usage.fields.get(&title_field).unwrap();
+        assert!(title_usage.postings > 0);
+        assert!(title_usage.positions > 0);
+        assert_eq!(title_usage.stored_fields, 0);
+
+        // Stored fields don't have any postings
+        let pk_usage = usage.fields.get(&pk_field).unwrap();
+        assert!(pk_usage.stored_fields > 0);
+        assert_eq!(pk_usage.inverted_index(), 0);
+
+        assert!(usage.term_dictionary > 0);
+        assert!(usage.documents > 0);
+        assert_eq!(usage.total(), usage.all_fields().total() + usage.term_dictionary + usage.documents + usage.other);
+    }
+
+    #[test]
+    fn test_contains_document_key_uses_snapshot() {
+        remove_dir_all_ignore_error("test_indices/test_contains_document_key_uses_snapshot");
+
+        let store = make_test_store("test_indices/test_contains_document_key_uses_snapshot");
+        let index_reader = store.reader();
+        assert!(index_reader.contains_document_key("test_doc").unwrap());
+
+        // Deleting the document shouldn't affect readers that were created beforehand
+        assert!(store.remove_document_by_key("test_doc").unwrap());
+        assert!(index_reader.contains_document_key("test_doc").unwrap());
+        assert!(!store.reader().contains_document_key("test_doc").unwrap());
+    }
+
+    #[test]
+    fn test_doc_key() {
+        remove_dir_all_ignore_error("test_indices/test_doc_key");
+
+        let store = make_test_store("test_indices/test_doc_key");
+        let index_reader = store.reader();
+        let segment = index_reader.segments().next().unwrap();
+
+        let mut keys = Vec::new();
+        for doc_local_id in segment.live_docs().unwrap().iter() {
+            keys.push(index_reader.doc_key(segment.doc_id(doc_local_id as u16)).unwrap().unwrap());
+        }
+        keys.sort();
+        assert_eq!(keys, vec!["another_test_doc".to_string(), "test_doc".to_string()]);
+
+        // Keys shouldn't be stored alongside the documents as well as in the document index
+        let doc_id = segment.doc_id(0);
+        let kb = KeyBuilder::stored_field_value((doc_id.0).0, doc_id.1, 0, b"key");
+        assert!(store.db.get_cf(column_families::handle(&store.db, column_families::STORED), &kb.key()).unwrap().is_none());
+
+        // Deleted documents no longer have a key
+        let key = index_reader.doc_key(doc_id).unwrap().unwrap();
+        store.remove_document_by_key(&key).unwrap();
+        assert_eq!(store.reader().doc_key(doc_id).unwrap(), None);
+    }
+
+    #[test]
+    fn test_open_migrates_legacy_layout() {
+        remove_dir_all_ignore_error("test_indices/test_open_migrates_legacy_layout");
+
+        // Write a store in the old layout, with everything in the default column family
+        {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            let db = DB::open(&opts, "test_indices/test_open_migrates_legacy_layout").unwrap();
+            db.put(b".schema", serde_json::to_string(&Schema::new()).unwrap().as_bytes()).unwrap();
+            db.put(b".next_segment", b"2").unwrap();
+            db.put(b".next_term_id", b"2").unwrap();
+            db.put(b"thello", b"1").unwrap();
+            db.put(b"ktest_doc", &[1, 0, 0, 0, 0, 0]).unwrap();
+        }
+
+        let store = RocksDBStore::open("test_indices/test_open_migrates_legacy_layout").unwrap();
+        assert_eq!(column_families::read_format_version(&store.db).unwrap(), column_families::FORMAT_VERSION);
+        assert!(store.reader().contains_document_key("test_doc").unwrap());
+
+        // Keys should have been moved out of the default column family
+        assert!(store.db.get(b"ktest_doc").unwrap().is_none());
+        assert!(store.db.get_cf(column_families::handle(&store.db, column_families::DOCINDEX), b"ktest_doc").unwrap().is_some());
+        assert!(store.db.get_cf(column_families::handle(&store.db, column_families::TERMS), b"thello").unwrap().is_some());
+
+        // The document index should have been given its reverse entries
+        assert_eq!(store.reader().doc_key(DocId(SegmentId(1), 0)).unwrap(), Some("test_doc".to_string()));
+    }
+
+    #[test]
+    fn test_open_upgrades_deletion_lists() {
+        remove_dir_all_ignore_error("test_indices/test_open_upgrades_deletion_lists");
+
+        // Write a version 3 store, where a merge wrote a Roaring bitmap and a deletion was appended after it
+        {
+            let store = RocksDBStore::create("test_indices/test_open_upgrades_deletion_lists").unwrap();
+            store.db.put(b".format_version", b"3").unwrap();
+
+            let mut bitmap = RoaringBitmap::new();
+            bitmap.insert(1);
+            bitmap.insert(5);
+            let mut value = Vec::new();
+            bitmap.serialize_into(&mut value).unwrap();
+            value.extend_from_slice(&[7, 0]);
+            store.db.put(&KeyBuilder::segment_del_list(1).key(), &value).unwrap();
+
+            // Lists that were only ever appended to are left as they are
+            store.db.put(&KeyBuilder::segment_del_list(2).key(), &[3, 0, 4, 0]).unwrap();
+        }
+
+        let store = RocksDBStore::open("test_indices/test_open_upgrades_deletion_lists").unwrap();
+        assert_eq!(column_families::read_format_version(&store.db).unwrap(), column_families::FORMAT_VERSION);
+
+        let value = store.db.get(&KeyBuilder::segment_del_list(1).key()).unwrap().unwrap();
+        assert_eq!(decode_deletion_list(&value).iter().collect::<Vec<_>>(), vec![1, 5, 7]);
+
+        let value = store.db.get(&KeyBuilder::segment_del_list(2).key()).unwrap().unwrap();
+        assert_eq!(&value[..], &[3, 0, 4, 0]);
     }
 
     #[test]
@@ -579,4 +1466,278 @@ mod tests {
         let docs = collector.into_sorted_vec();
         println!("{:?}", docs);
     }
+
+    #[test]
+    fn test_vector_score() {
+        remove_dir_all_ignore_error("test_indices/test_vector_score");
+
+        let mut store = RocksDBStore::create("test_indices/test_vector_score").unwrap();
+        let vector_field = store.add_field("embedding".to_string(), FieldType::DenseVector, FIELD_STORED).unwrap();
+
+        for &(key, ref vector) in &[("east", vec![1.0f32, 0.0]), ("north", vec![0.0f32, 1.0])] {
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(vector_field, FieldValue::Vector(vector.clone()));
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                boost: 1.0f32,
+                indexed_fields: FnvHashMap::default(),
+                stored_fields: stored_fields,
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let query = Query::VectorScore {
+            field: vector_field,
+            vector: vec![0.1, 0.9],
+            similarity: VectorSimilarity::Cosine,
+            query: Box::new(Query::all()),
+            boost: 1.0f32,
+        };
+
+        let mut collector = TopScoreCollector::new(10);
+        index_reader.search(&mut collector, &query).unwrap();
+
+        let docs = collector.into_sorted_vec().into_iter().map(|doc| DocId::from_u64(doc.doc_id())).collect::<Vec<_>>();
+        let keys = docs.iter().map(|doc_id| index_reader.doc_key(*doc_id).unwrap().unwrap()).collect::<Vec<_>>();
+        assert_eq!(keys, vec!["north".to_string(), "east".to_string()]);
+
+        // The stored vector can be read back
+        let vector = index_reader.read_stored_field(vector_field, docs[1]).unwrap();
+        assert_eq!(vector, Some(FieldValue::Vector(vec![1.0, 0.0])));
+    }
+
+    #[test]
+    fn test_random_score() {
+        remove_dir_all_ignore_error("test_indices/test_random_score");
+
+        let store = RocksDBStore::create("test_indices/test_random_score").unwrap();
+
+        for i in 0..20 {
+            store.insert_or_update_document(&Document {
+                key: format!("doc_{}", i),
+                boost: 1.0f32,
+                indexed_fields: FnvHashMap::default(),
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let search_keys = |seed: u64| {
+            let query = Query::RandomScore {
+                seed: seed,
+                field: None,
+                query: Box::new(Query::all()),
+                boost: 1.0f32,
+            };
+
+            let mut collector = TopScoreCollector::new(20);
+            index_reader.search(&mut collector, &query).unwrap();
+
+            collector.into_sorted_vec().into_iter().map(|doc| index_reader.doc_key(DocId::from_u64(doc.doc_id())).unwrap().unwrap()).collect::<Vec<_>>()
+        };
+
+        // The same seed always gives the same order
+        let keys = search_keys(1);
+        assert_eq!(keys.len(), 20);
+        assert_eq!(search_keys(1), keys);
+
+        // A different seed shuffles the documents differently
+        assert!(search_keys(2) != keys);
+    }
+
+    #[test]
+    fn test_feature_score() {
+        remove_dir_all_ignore_error("test_indices/test_feature_score");
+
+        let mut store = RocksDBStore::create("test_indices/test_feature_score").unwrap();
+        let popularity_field = store.add_field("popularity".to_string(), FieldType::F64, FIELD_STORED).unwrap();
+
+        let docs = vec![("quiet", Some(2.0)), ("popular", Some(50.0)), ("unknown", None), ("average", Some(10.0))];
+        for &(key, popularity) in docs.iter() {
+            let mut stored_fields = FnvHashMap::default();
+            if let Some(popularity) = popularity {
+                stored_fields.insert(popularity_field, FieldValue::Float(popularity));
+            }
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                boost: 1.0f32,
+                indexed_fields: FnvHashMap::default(),
+                stored_fields: stored_fields,
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let query = Query::FeatureScore {
+            field: popularity_field,
+            function: FeatureFunction::Saturation{pivot: 10.0},
+            boost: 2.0f32,
+        };
+
+        let mut collector = TopScoreCollector::new(10);
+        index_reader.search(&mut collector, &query).unwrap();
+        let hits = collector.into_sorted_vec().into_iter().map(|doc| {
+            (index_reader.doc_key(DocId::from_u64(doc.doc_id())).unwrap().unwrap(), doc.score().unwrap())
+        }).collect::<Vec<_>>();
+
+        // Documents without the feature don't match, the rest are ordered by their value
+        let keys = hits.iter().map(|&(ref key, _)| key.as_ref()).collect::<Vec<&str>>();
+        assert_eq!(keys, vec!["popular", "average", "quiet"]);
+        assert_eq!(hits[1].1, 1.0f32);
+    }
+
+    #[test]
+    fn test_geo_shape_query() {
+        remove_dir_all_ignore_error("test_indices/test_geo_shape_query");
+
+        let mut store = RocksDBStore::create("test_indices/test_geo_shape_query").unwrap();
+        let shape_field = store.add_field("location".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let shapes = vec![
+            ("inside", json!({"type": "point", "coordinates": [1.0, 1.0]})),
+            ("crossing", json!({"type": "linestring", "coordinates": [[5.0, 5.0], [15.0, 5.0]]})),
+
+            // In a cell that covers the edge of the query shape, but outside of the shape itself
+            ("near", json!({"type": "point", "coordinates": [10.001, 10.001]})),
+        ];
+
+        for &(key, ref shape_json) in shapes.iter() {
+            let shape = GeoShape::from_geojson(shape_json).unwrap();
+            let tokens = geo::index_terms(&shape).into_iter().map(|term| Token { term: term, position: 1 }).collect::<Vec<_>>();
+
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(shape_field, tokens.into());
+
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(shape_field, FieldValue::String(shape_json.to_string()));
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: stored_fields,
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let search = |relation: GeoShapeRelation| {
+            let shape = GeoShape::from_geojson(&json!({"type": "envelope", "coordinates": [[0.0, 10.0], [10.0, 0.0]]})).unwrap();
+            let query = Query::GeoShape {
+                field: shape_field,
+                terms: geo::query_terms(&shape),
+                shape: shape,
+                relation: relation,
+                score: 1.0f32,
+            };
+
+            let mut collector = TopScoreCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+
+            let mut keys = collector.into_sorted_vec().into_iter().map(|doc| index_reader.doc_key(DocId::from_u64(doc.doc_id())).unwrap().unwrap()).collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(search(GeoShapeRelation::Intersects), vec!["crossing".to_string(), "inside".to_string()]);
+        assert_eq!(search(GeoShapeRelation::Within), vec!["inside".to_string()]);
+    }
+
+    #[test]
+    fn test_numeric_range_query() {
+        remove_dir_all_ignore_error("test_indices/test_numeric_range_query");
+
+        let store = make_test_store("test_indices/test_numeric_range_query");
+        let index_reader = store.reader();
+        let pk_field = index_reader.schema().get_field_by_name("pk").unwrap();
+
+        // The block metadata is rebuilt when segments are merged
+        let search = |gte: i64, lte: i64| {
+            let query = Query::NumericRange {
+                field: pk_field,
+                gte: gte,
+                lte: lte,
+                score: 1.0f32,
+            };
+
+            let mut collector = TopScoreCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+
+            let mut keys = collector.into_sorted_vec().into_iter().map(|doc| index_reader.doc_key(DocId::from_u64(doc.doc_id())).unwrap().unwrap()).collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(search(2, 10), vec!["another_test_doc".to_string()]);
+        assert_eq!(search(1, 2), vec!["another_test_doc".to_string(), "test_doc".to_string()]);
+        assert_eq!(search(3, 10), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_phrase_query() {
+        remove_dir_all_ignore_error("test_indices/test_phrase_query");
+
+        let store = make_test_store("test_indices/test_phrase_query");
+        let index_reader = store.reader();
+        let title_field = index_reader.schema().get_field_by_name("title").unwrap();
+        let body_field = index_reader.schema().get_field_by_name("body").unwrap();
+
+        let search = |field, terms: Vec<&str>, prefix: Option<&str>| {
+            let query = Query::Phrase {
+                field: field,
+                terms: terms.iter().map(|term| Term::from_string(term)).collect(),
+                prefix: prefix.map(|prefix| prefix.to_string()),
+                max_expansions: 50,
+                scorer: TermScorer::default(),
+            };
+
+            let mut collector = TopScoreCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+
+            let mut keys = collector.into_sorted_vec().into_iter().map(|doc| index_reader.doc_key(DocId::from_u64(doc.doc_id())).unwrap().unwrap()).collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+
+        // The terms must be next to each other, in order
+        assert_eq!(search(body_field, vec!["lorem", "ipsum"], None), vec!["another_test_doc".to_string(), "test_doc".to_string()]);
+        assert_eq!(search(body_field, vec!["ipsum", "lorem"], None), Vec::<String>::new());
+        assert_eq!(search(body_field, vec!["lorem", "dolar"], None), Vec::<String>::new());
+
+        // The final term can be a prefix
+        assert_eq!(search(title_field, vec!["hello"], Some("wo")), vec!["test_doc".to_string()]);
+        assert_eq!(search(title_field, vec![], Some("par")), vec!["another_test_doc".to_string()]);
+        assert_eq!(search(title_field, vec!["hello"], Some("pa")), Vec::<String>::new());
+        assert_eq!(search(title_field, vec!["hello"], Some("xyz")), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_epoch_changes_on_write() {
+        remove_dir_all_ignore_error("test_indices/test_epoch_changes_on_write");
+
+        let store = make_test_store("test_indices/test_epoch_changes_on_write");
+        let epoch = store.epoch();
+
+        // Reading doesn't change the epoch
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::all()).unwrap();
+        assert_eq!(store.epoch(), epoch);
+
+        // Nor does removing a document that doesn't exist
+        assert!(!store.remove_document_by_key("missing_doc").unwrap());
+        assert_eq!(store.epoch(), epoch);
+
+        assert!(store.remove_document_by_key("test_doc").unwrap());
+        assert!(store.epoch() > epoch);
+
+        // Epochs aren't reused when a store is opened again
+        let epoch = store.epoch();
+        drop(store);
+        let store = RocksDBStore::open("test_indices/test_epoch_changes_on_write").unwrap();
+        assert!(store.epoch() > epoch);
+    }
 }
@@ -0,0 +1,262 @@
+//! Min/max metadata for blocks of documents in integer and date fields
+//!
+//! The documents of a segment are split into blocks of BLOCK_SIZE. For each block with
+//! values in an integer or date field, the lowest and highest values and the number of
+//! documents with a value are stored against the first document of the block (with the
+//! value type "nblk"). Numeric range queries read the doc values of a field directly,
+//! using these to skip blocks that can't match and to match whole blocks without
+//! reading their values.
+//!
+//! Blocks without metadata (none of their documents have a value, or the segment was
+//! written before this was added) have all of their values read.
+
+use std::cmp;
+
+use roaring::RoaringBitmap;
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+use search::document::FieldValue;
+use search::schema::FieldId;
+use search::segment::Segment;
+use search::statistic_key::StatisticKey;
+use search::term::datetime_to_micros;
+
+/// The number of documents in each block
+pub const BLOCK_SIZE: u16 = 128;
+
+/// The value type the metadata of a block is stored with
+pub const VALUE_TYPE: &'static [u8] = b"nblk";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NumericBlock {
+    pub min: i64,
+    pub max: i64,
+
+    /// The number of documents in the block with at least one value
+    pub doc_count: u16,
+}
+
+impl NumericBlock {
+    /// The metadata of a block before any documents are added
+    pub fn empty() -> NumericBlock {
+        NumericBlock {
+            min: i64::max_value(),
+            max: i64::min_value(),
+            doc_count: 0,
+        }
+    }
+
+    /// Adds the values of a document in the block, documents without values aren't counted
+    pub fn add_doc(&mut self, values: &[i64]) {
+        if values.is_empty() {
+            return;
+        }
+
+        for value in values.iter() {
+            self.min = cmp::min(self.min, *value);
+            self.max = cmp::max(self.max, *value);
+        }
+
+        self.doc_count += 1;
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(18);
+        bytes.write_i64::<LittleEndian>(self.min).unwrap();
+        bytes.write_i64::<LittleEndian>(self.max).unwrap();
+        bytes.write_u16::<LittleEndian>(self.doc_count).unwrap();
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<NumericBlock> {
+        if bytes.len() != 18 {
+            return None;
+        }
+
+        Some(NumericBlock {
+            min: LittleEndian::read_i64(&bytes[0..8]),
+            max: LittleEndian::read_i64(&bytes[8..16]),
+            doc_count: LittleEndian::read_u16(&bytes[16..18]),
+        })
+    }
+}
+
+/// The first document of the block that a document is in
+pub fn block_start(doc_local_id: u16) -> u16 {
+    doc_local_id - doc_local_id % BLOCK_SIZE
+}
+
+/// The value of an integer or date, as it's encoded in stored values (dates are in microseconds)
+pub fn numeric_value(value: &FieldValue) -> Option<i64> {
+    match *value {
+        FieldValue::Integer(value) => Some(value),
+        FieldValue::DateTime(ref value) => Some(datetime_to_micros(value)),
+        _ => None,
+    }
+}
+
+/// Decodes a stored integer or date value
+pub fn decode_numeric_value(bytes: &[u8]) -> Option<i64> {
+    if bytes.len() == 8 {
+        Some(LittleEndian::read_i64(bytes))
+    } else {
+        None
+    }
+}
+
+/// Decodes all integer or date values of a multi-valued field (the "dv" value type)
+pub fn decode_numeric_doc_values(mut bytes: &[u8]) -> Vec<i64> {
+    let mut values = Vec::new();
+
+    while bytes.len() >= 4 {
+        let length = LittleEndian::read_u32(&bytes[..4]) as usize;
+        if bytes.len() < 4 + length {
+            break;
+        }
+
+        if let Some(value) = decode_numeric_value(&bytes[4..4 + length]) {
+            values.push(value);
+        }
+        bytes = &bytes[4 + length..];
+    }
+
+    values
+}
+
+/// Reads the values of an integer or date field for a document
+///
+/// Multi-valued fields have all their values in "dv", otherwise the stored value is used.
+fn load_numeric_values<S: Segment>(segment: &S, doc_local_id: u16, field_id: FieldId) -> Result<Vec<i64>, String> {
+    if let Some(bytes) = try!(segment.load_stored_field_value_raw(doc_local_id, field_id, b"dv")) {
+        return Ok(decode_numeric_doc_values(&bytes));
+    }
+
+    match try!(segment.load_stored_field_value_raw(doc_local_id, field_id, b"val")) {
+        Some(bytes) => Ok(decode_numeric_value(&bytes).into_iter().collect()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Finds the documents in a segment with a value of an integer or date field between gte and lte (inclusive)
+pub fn numeric_range_matches<S: Segment>(segment: &S, field_id: FieldId, gte: i64, lte: i64) -> Result<RoaringBitmap, String> {
+    let total_docs = try!(segment.load_statistic(&StatisticKey::TotalDocs)).unwrap_or(0);
+    let mut matches = RoaringBitmap::new();
+
+    let mut start = 0;
+    while start < total_docs {
+        let end = cmp::min(start + BLOCK_SIZE as i64, total_docs);
+        let block = match try!(segment.load_stored_field_value_raw(start as u16, field_id, VALUE_TYPE)) {
+            Some(bytes) => NumericBlock::from_bytes(&bytes),
+            None => None,
+        };
+
+        match block {
+            // None of the values in the block are in the range
+            Some(block) if block.max < gte || block.min > lte => {}
+
+            // Every document in the block has a value and they're all in the range
+            Some(block) if block.min >= gte && block.max <= lte && block.doc_count as i64 == end - start => {
+                for doc_local_id in start..end {
+                    matches.insert(doc_local_id as u32);
+                }
+            }
+
+            _ => {
+                for doc_local_id in start..end {
+                    let values = try!(load_numeric_values(segment, doc_local_id as u16, field_id));
+
+                    if values.iter().any(|value| *value >= gte && *value <= lte) {
+                        matches.insert(doc_local_id as u32);
+                    }
+                }
+            }
+        }
+
+        start = end;
+    }
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use fnv::FnvHashMap;
+
+    use search::Document;
+    use search::document::FieldValue;
+    use search::schema::FieldId;
+    use search::segment::Segment;
+
+    use super::super::segment_builder::SegmentBuilder;
+    use super::{NumericBlock, BLOCK_SIZE, VALUE_TYPE, numeric_range_matches};
+
+    /// A segment where each document has its number in field 1, except every tenth
+    /// document, which has no value. The second block has the values 1000 and 1001.
+    fn make_segment() -> SegmentBuilder {
+        let mut builder = SegmentBuilder::new();
+
+        for i in 0..300i64 {
+            let mut stored_fields = FnvHashMap::default();
+            let mut doc_values = FnvHashMap::default();
+
+            if i >= BLOCK_SIZE as i64 && i < 2 * BLOCK_SIZE as i64 {
+                doc_values.insert(FieldId(1), vec![FieldValue::Integer(1000), FieldValue::Integer(1001)]);
+            } else if i % 10 != 0 {
+                stored_fields.insert(FieldId(1), FieldValue::Integer(i));
+            }
+
+            builder.add_document(&Document {
+                key: i.to_string(),
+                boost: 1.0f32,
+                indexed_fields: FnvHashMap::default(),
+                stored_fields: stored_fields,
+                doc_values: doc_values,
+            }).unwrap();
+        }
+
+        builder
+    }
+
+    #[test]
+    fn test_block_metadata() {
+        let segment = make_segment();
+        let block = |start: u16| segment.load_stored_field_value_raw(start, FieldId(1), VALUE_TYPE).unwrap().and_then(|bytes| NumericBlock::from_bytes(&bytes));
+
+        assert_eq!(block(0), Some(NumericBlock { min: 1, max: 127, doc_count: 115 }));
+        assert_eq!(block(BLOCK_SIZE), Some(NumericBlock { min: 1000, max: 1001, doc_count: BLOCK_SIZE }));
+        assert_eq!(block(2 * BLOCK_SIZE), Some(NumericBlock { min: 256, max: 299, doc_count: 40 }));
+        assert_eq!(block(1), None);
+    }
+
+    #[test]
+    fn test_numeric_range_matches() {
+        let segment = make_segment();
+
+        let matches = numeric_range_matches(&segment, FieldId(1), 5, 12).unwrap();
+        assert_eq!(matches.iter().collect::<Vec<_>>(), vec![5, 6, 7, 8, 9, 11, 12]);
+
+        // The whole of the second block matches
+        let matches = numeric_range_matches(&segment, FieldId(1), 1000, 2000).unwrap();
+        assert_eq!(matches.len(), BLOCK_SIZE as u64);
+        assert!(matches.contains(BLOCK_SIZE as u32));
+
+        // Stops below the values of the second block
+        let matches = numeric_range_matches(&segment, FieldId(1), 295, 999).unwrap();
+        assert_eq!(matches.iter().collect::<Vec<_>>(), vec![295, 296, 297, 298, 299]);
+
+        let matches = numeric_range_matches(&segment, FieldId(1), 2000, 3000).unwrap();
+        assert!(matches.is_empty());
+    }
+
+    #[test]
+    fn test_blocks_without_metadata_are_read() {
+        let mut segment = make_segment();
+        let keys = segment.stored_field_values.keys().filter(|key| key.2 == VALUE_TYPE).cloned().collect::<Vec<_>>();
+        for key in keys {
+            segment.stored_field_values.remove(&key);
+        }
+
+        let matches = numeric_range_matches(&segment, FieldId(1), 5, 12).unwrap();
+        assert_eq!(matches.iter().collect::<Vec<_>>(), vec![5, 6, 7, 8, 9, 11, 12]);
+    }
+}
@@ -0,0 +1,258 @@
+//! Runs join queries (has_child and has_parent)
+//!
+//! Joins can't be planned like other queries as what they match depends on the
+//! results of their inner query. So the inner query is run first and the keys of
+//! the related documents are read from each match. The join is then replaced with
+//! a query on those keys, which can be planned as usual.
+
+use std::collections::BTreeSet;
+
+use search::{Query, JoinType, Term};
+use search::document::{DocId, FieldValue};
+use search::collectors::{Collector, DocumentMatch};
+
+use super::super::RocksDBReader;
+
+/// Collects the keys of the documents that each match is related to
+struct JoinKeyCollector<F: FnMut(DocId) -> Option<String>> {
+    read_key: F,
+    keys: BTreeSet<String>,
+}
+
+impl<F: FnMut(DocId) -> Option<String>> JoinKeyCollector<F> {
+    fn new(read_key: F) -> JoinKeyCollector<F> {
+        JoinKeyCollector {
+            read_key: read_key,
+            keys: BTreeSet::new(),
+        }
+    }
+}
+
+impl<F: FnMut(DocId) -> Option<String>> Collector for JoinKeyCollector<F> {
+    fn needs_score(&self) -> bool {
+        false
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        if let Some(key) = (self.read_key)(DocId::from_u64(doc.doc_id())) {
+            self.keys.insert(key);
+        }
+    }
+}
+
+impl<'a> RocksDBReader<'a> {
+    fn resolve_joins_in_list(&self, queries: &[Query]) -> Result<Vec<Query>, String> {
+        queries.iter().map(|query| self.resolve_joins(query)).collect()
+    }
+
+    /// Replaces join queries with queries on the keys of the related documents
+    pub fn resolve_joins(&self, query: &Query) -> Result<Query, String> {
+        Ok(match *query {
+            Query::Conjunction{ref queries} => Query::Conjunction { queries: try!(self.resolve_joins_in_list(queries)) },
+            Query::Disjunction{ref queries} => Query::Disjunction { queries: try!(self.resolve_joins_in_list(queries)) },
+            Query::DisjunctionMax{ref queries, tie_breaker} => Query::DisjunctionMax { queries: try!(self.resolve_joins_in_list(queries)), tie_breaker: tie_breaker },
+            Query::Filter{ref query, ref filter} => {
+                Query::Filter {
+                    query: Box::new(try!(self.resolve_joins(query))),
+                    filter: Box::new(try!(self.resolve_joins(filter))),
+                }
+            }
+            Query::Exclude{ref query, ref exclude} => {
+                Query::Exclude {
+                    query: Box::new(try!(self.resolve_joins(query))),
+                    exclude: Box::new(try!(self.resolve_joins(exclude))),
+                }
+            }
+            Query::VectorScore{field, ref vector, similarity, ref query, boost} => {
+                Query::VectorScore {
+                    field: field,
+                    vector: vector.clone(),
+                    similarity: similarity,
+                    query: Box::new(try!(self.resolve_joins(query))),
+                    boost: boost,
+                }
+            }
+            Query::RandomScore{seed, field, ref query, boost} => {
+                Query::RandomScore {
+                    seed: seed,
+                    field: field,
+                    query: Box::new(try!(self.resolve_joins(query))),
+                    boost: boost,
+                }
+            }
+            Query::Named{ref name, ref query} => {
+                Query::Named {
+                    name: name.clone(),
+                    query: Box::new(try!(self.resolve_joins(query))),
+                }
+            }
+            Query::Join{field, key_field, ref join_type, ref query, score} => {
+                let related_docs = match *join_type {
+                    JoinType::HasChild{ref parent_relation, ref child_relation} => {
+                        // Children store the key of their parent in the join field
+                        let children_query = query.as_ref().clone().filter(Query::term(field, Term::from_string(child_relation)));
+                        let mut collector = JoinKeyCollector::new(|doc_id| {
+                            match self.doc_value(field, doc_id) {
+                                Ok(Some(FieldValue::String(parent_key))) => Some(parent_key),
+                                _ => None,
+                            }
+                        });
+                        try!(self.search(&mut collector, &children_query));
+
+                        // Find the parents by their key
+                        let parents = collector.keys.iter().map(|parent_key| {
+                            Query::term(key_field, Term::from_string(parent_key))
+                        }).collect();
+
+                        Query::Conjunction {
+                            queries: vec![
+                                Query::term(field, Term::from_string(parent_relation)),
+                                Query::Disjunction { queries: parents },
+                            ],
+                        }
+                    }
+                    JoinType::HasParent{ref parent_relation} => {
+                        let parents_query = query.as_ref().clone().filter(Query::term(field, Term::from_string(parent_relation)));
+                        let mut collector = JoinKeyCollector::new(|doc_id| self.doc_key(doc_id).unwrap_or(None));
+                        try!(self.search(&mut collector, &parents_query));
+
+                        // Children index a term linking them to the key of their parent
+                        let children = collector.keys.iter().map(|parent_key| {
+                            Query::term(field, Term::from_join_parent(parent_relation, parent_key))
+                        }).collect();
+
+                        Query::Disjunction { queries: children }
+                    }
+                };
+
+                // Every related document gets the same score
+                Query::All{ score: score }.filter(related_docs)
+            }
+            _ => query.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use fnv::FnvHashMap;
+    use search::{Term, Token, Document, Query, JoinType};
+    use search::document::FieldValue;
+    use search::schema::{FieldType, FieldId, FIELD_INDEXED, FIELD_STORED};
+    use search::backends::rocksdb::RocksDBStore;
+
+    use super::JoinKeyCollector;
+
+    /// Creates a store with two questions, each with one answer
+    ///
+    /// The answer to question "q1" contains "good", the answer to "q2" contains "bad"
+    fn make_join_store(path: &str) -> (RocksDBStore, FieldId, FieldId, FieldId) {
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        let id_field = store.add_field("_id".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+        let join_field = store.add_field("join".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+        let text_field = store.add_field("text".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let docs = vec![
+            ("q1", None, "what"),
+            ("q2", None, "why"),
+            ("a1", Some("q1"), "good"),
+            ("a2", Some("q2"), "bad"),
+        ];
+
+        for (key, parent, text) in docs {
+            let mut indexed_fields = FnvHashMap::default();
+            let mut stored_fields = FnvHashMap::default();
+
+            indexed_fields.insert(id_field, vec![Token { term: Term::from_string(key), position: 1 }].into());
+            indexed_fields.insert(text_field, vec![Token { term: Term::from_string(text), position: 1 }].into());
+
+            match parent {
+                Some(parent) => {
+                    indexed_fields.insert(join_field, vec![
+                        Token { term: Term::from_string("answer"), position: 1 },
+                        Token { term: Term::from_join_parent("question", parent), position: 2 },
+                    ].into());
+                    stored_fields.insert(join_field, FieldValue::String(parent.to_string()));
+                }
+                None => {
+                    indexed_fields.insert(join_field, vec![Token { term: Term::from_string("question"), position: 1 }].into());
+                }
+            }
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: stored_fields,
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        (store, id_field, join_field, text_field)
+    }
+
+    fn search_keys(store: &RocksDBStore, query: &Query) -> Vec<String> {
+        let index_reader = store.reader();
+        let mut collector = JoinKeyCollector::new(|doc_id| index_reader.doc_key(doc_id).unwrap());
+        index_reader.search(&mut collector, query).unwrap();
+        collector.keys.into_iter().collect()
+    }
+
+    #[test]
+    fn test_has_child() {
+        let (store, id_field, join_field, text_field) = make_join_store("test_indices/test_join_has_child");
+
+        let query = Query::Join {
+            field: join_field,
+            key_field: id_field,
+            join_type: JoinType::HasChild {
+                parent_relation: "question".to_string(),
+                child_relation: "answer".to_string(),
+            },
+            query: Box::new(Query::term(text_field, Term::from_string("good"))),
+            score: 1.0f32,
+        };
+
+        assert_eq!(search_keys(&store, &query), vec!["q1".to_string()]);
+    }
+
+    #[test]
+    fn test_has_parent() {
+        let (store, id_field, join_field, _) = make_join_store("test_indices/test_join_has_parent");
+
+        let query = Query::Join {
+            field: join_field,
+            key_field: id_field,
+            join_type: JoinType::HasParent {
+                parent_relation: "question".to_string(),
+            },
+            query: Box::new(Query::term(id_field, Term::from_string("q2"))),
+            score: 1.0f32,
+        };
+
+        assert_eq!(search_keys(&store, &query), vec!["a2".to_string()]);
+    }
+
+    #[test]
+    fn test_has_child_no_matches() {
+        let (store, id_field, join_field, text_field) = make_join_store("test_indices/test_join_has_child_no_matches");
+
+        let query = Query::Join {
+            field: join_field,
+            key_field: id_field,
+            join_type: JoinType::HasChild {
+                parent_relation: "question".to_string(),
+                child_relation: "answer".to_string(),
+            },
+            query: Box::new(Query::term(text_field, Term::from_string("what"))),
+            score: 1.0f32,
+        };
+
+        // "what" is in a question, not an answer
+        assert_eq!(search_keys(&store, &query), Vec::<String>::new());
+    }
+}
@@ -1,69 +1,240 @@
 mod statistics;
 mod planner;
+mod join;
+mod named;
+mod terms_enum;
+mod two_phase;
+#[cfg(test)]
+mod testing;
+
+pub use self::planner::limits::{QueryLimits, QueryLimitError, DEFAULT_MAX_CLAUSE_COUNT, DEFAULT_MAX_EXPANSIONS};
+
+use std::collections::HashMap;
 
 use roaring::RoaringBitmap;
 use search::segment::Segment;
+use search::statistic_key::StatisticKey;
+use search::document::DocId;
+use search::schema::FieldId;
 use search::query::Query;
 use search::collectors::{Collector, DocumentMatch};
+use search::geo::GeoShape;
+use search::random_score::random_score;
+use trace::Span;
 use byteorder::{ByteOrder, LittleEndian};
+use serde_json;
 
-use super::RocksDBReader;
-use self::statistics::{StatisticsReader, RocksDBStatisticsReader};
+use super::{RocksDBReader, decode_stored_field_value, decode_doc_values};
+use super::numeric_blocks::numeric_range_matches;
+use self::statistics::{RocksDBStatisticsReader, TermStatistics, load_score_function_statistics};
 use self::planner::{SearchPlan, plan_query};
 use self::planner::boolean_query::BooleanQueryOp;
 use self::planner::score_function::{CombinatorScorer, ScoreFunctionOp};
+use self::two_phase::TwoPhaseDocSet;
 
-fn run_boolean_query<S: Segment>(boolean_query: &Vec<BooleanQueryOp>, is_negated: bool, segment: &S) -> Result<RoaringBitmap, String> {
+/// Pops the top value off an executor stack
+///
+/// The planner should never produce a plan that underflows the stack but, if it
+/// does, the search fails with an error rather than taking down the request thread.
+fn pop_stack<T>(stack: &mut Vec<T>, executor: &str) -> Result<T, String> {
+    stack.pop().ok_or_else(|| format!("{}: stack underflow", executor))
+}
+
+/// Checks that a plan left exactly one value on the stack and returns it
+fn finish_stack<T>(mut stack: Vec<T>, executor: &str) -> Result<T, String> {
+    if stack.len() > 1 {
+        return Err(format!("{}: stack size too big ({})", executor, stack.len()));
+    }
+
+    pop_stack(&mut stack, executor)
+}
+
+fn run_boolean_query<'a, S: Segment>(boolean_query: &'a Vec<BooleanQueryOp>, is_negated: bool, segment: &'a S) -> Result<RoaringBitmap, String> {
     // Execute boolean query
+    // Queries that can't be matched exactly from the index push an approximation, these are
+    // verified once the whole query has been combined
     let mut stack = Vec::new();
     for op in boolean_query.iter() {
         match *op {
             BooleanQueryOp::PushEmpty => {
-                stack.push(RoaringBitmap::new());
+                stack.push(TwoPhaseDocSet::exact(RoaringBitmap::new()));
             }
             BooleanQueryOp::PushPostingsList(field_id, term_id) => {
                 match try!(segment.load_postings_list(field_id, term_id)) {
-                    Some(doc_id_set) => stack.push(doc_id_set),
-                    None => stack.push(RoaringBitmap::new()),
+                    Some(doc_id_set) => stack.push(TwoPhaseDocSet::exact(doc_id_set)),
+                    None => stack.push(TwoPhaseDocSet::exact(RoaringBitmap::new())),
+                }
+            }
+            BooleanQueryOp::PushTermSet(field_id, ref term_ids) => {
+                // Union all of the postings lists into one bitmap
+                let mut doc_id_set = RoaringBitmap::new();
+                for term_id in term_ids.iter() {
+                    if let Some(postings) = try!(segment.load_postings_list(field_id, *term_id)) {
+                        doc_id_set.union_with(&postings);
+                    }
+                }
+
+                stack.push(TwoPhaseDocSet::exact(doc_id_set));
+            }
+            BooleanQueryOp::PushGeoShapeMatches(field_id, ref term_ids, ref shape, relation) => {
+                // The cells only find the documents that might match, the stored shape of
+                // each one is checked in the second phase
+                let mut candidates = RoaringBitmap::new();
+                for term_id in term_ids.iter() {
+                    if let Some(postings) = try!(segment.load_postings_list(field_id, *term_id)) {
+                        candidates.union_with(&postings);
+                    }
+                }
+
+                stack.push(TwoPhaseDocSet::approximate(candidates, Box::new(move |doc_id: u32| {
+                    match try!(segment.load_stored_field_value_raw(doc_id as u16, field_id, b"val")) {
+                        Some(value) => {
+                            let json: serde_json::Value = try!(serde_json::from_slice(&value).map_err(|e| format!("failed to read stored shape: {}", e)));
+                            let doc_shape = try!(GeoShape::from_geojson(&json));
+
+                            Ok(relation.matches(&doc_shape, shape))
+                        }
+                        None => Ok(false),
+                    }
+                })));
+            }
+            BooleanQueryOp::PushPhraseMatches(field_id, ref slots) => {
+                // Documents that contain a term from every slot might match, the positions
+                // of each one are checked in the second phase
+                let mut candidates: Option<RoaringBitmap> = None;
+                for term_ids in slots.iter() {
+                    let mut slot_docs = RoaringBitmap::new();
+                    for term_id in term_ids.iter() {
+                        if let Some(postings) = try!(segment.load_postings_list(field_id, *term_id)) {
+                            slot_docs.union_with(&postings);
+                        }
+                    }
+
+                    candidates = Some(match candidates {
+                        Some(mut candidates) => {
+                            candidates.intersect_with(&slot_docs);
+                            candidates
+                        }
+                        None => slot_docs,
+                    });
+                }
+
+                stack.push(TwoPhaseDocSet::approximate(candidates.unwrap_or_else(RoaringBitmap::new), Box::new(move |doc_id: u32| {
+                    let mut slot_positions = Vec::with_capacity(slots.len());
+                    for term_ids in slots.iter() {
+                        let mut positions = Vec::new();
+                        for term_id in term_ids.iter() {
+                            if let Some(encoded) = try!(segment.load_positions(doc_id as u16, field_id, *term_id)) {
+                                positions.extend(encoded.iter());
+                            }
+                        }
+
+                        positions.sort();
+                        slot_positions.push(positions);
+                    }
+
+                    // Look for an occurrence of the first term that's followed by the rest
+                    Ok(slot_positions[0].iter().any(|&start| {
+                        slot_positions.iter().enumerate().skip(1).all(|(offset, positions)| {
+                            positions.binary_search(&(start + offset as u32)).is_ok()
+                        })
+                    }))
+                })));
+            }
+            BooleanQueryOp::PushNumericRange(field_id, gte, lte) => {
+                stack.push(TwoPhaseDocSet::exact(try!(numeric_range_matches(segment, field_id, gte, lte))));
+            }
+            BooleanQueryOp::PushHasValue(field_id) => {
+                // Any document could have a value, so each one is checked in the second phase
+                let total_docs = try!(segment.load_statistic(&StatisticKey::TotalDocs)).unwrap_or(0);
+                let mut candidates = RoaringBitmap::new();
+                for doc_id in 0..total_docs {
+                    candidates.insert(doc_id as u32);
+                }
+
+                stack.push(TwoPhaseDocSet::approximate(candidates, Box::new(move |doc_id: u32| {
+                    if try!(segment.load_stored_field_value_raw(doc_id as u16, field_id, b"dv")).is_some() {
+                        return Ok(true);
+                    }
+
+                    Ok(try!(segment.load_stored_field_value_raw(doc_id as u16, field_id, b"val")).is_some())
+                })));
+            }
+            BooleanQueryOp::PushRuntimeMatches(ref runtime) => {
+                // Runtime fields aren't indexed, so every document is checked in the second phase
+                let total_docs = try!(segment.load_statistic(&StatisticKey::TotalDocs)).unwrap_or(0);
+                let mut candidates = RoaringBitmap::new();
+                for doc_id in 0..total_docs {
+                    candidates.insert(doc_id as u32);
                 }
+
+                stack.push(TwoPhaseDocSet::approximate(candidates, Box::new(move |doc_id: u32| {
+                    let source = match runtime.source_field {
+                        Some(source_field) => try!(segment.load_stored_field_value_raw(doc_id as u16, source_field, b"val")),
+                        None => None,
+                    };
+                    let source = match source {
+                        Some(value) => {
+                            match try!(serde_json::from_slice(&value).map_err(|e| format!("failed to read source: {}", e))) {
+                                serde_json::Value::Object(source) => source,
+                                _ => serde_json::Map::new(),
+                            }
+                        }
+                        None => serde_json::Map::new(),
+                    };
+
+                    // Multi-valued fields have all their values in "dv", otherwise use the stored value
+                    let mut doc_values = HashMap::new();
+                    for &(ref name, field_id, ref field_type) in runtime.doc_value_fields.iter() {
+                        let values = match try!(segment.load_stored_field_value_raw(doc_id as u16, field_id, b"dv")) {
+                            Some(value) => try!(decode_doc_values(field_type, &value).map_err(|e| format!("failed to read doc values: {:?}", e))),
+                            None => {
+                                match try!(segment.load_stored_field_value_raw(doc_id as u16, field_id, b"val")) {
+                                    Some(value) => vec![try!(decode_stored_field_value(field_type, &value).map_err(|e| format!("failed to read doc values: {:?}", e)))],
+                                    None => Vec::new(),
+                                }
+                            }
+                        };
+
+                        doc_values.insert(name.clone(), values);
+                    }
+
+                    let values = try!(runtime.field.compute(&source, &doc_values));
+                    Ok(runtime.condition.matches(&values))
+                })));
             }
             BooleanQueryOp::PushDeletionList => {
-                    match try!(segment.load_deletion_list()) {
-                    Some(doc_id_set) => stack.push(doc_id_set),
-                    None => stack.push(RoaringBitmap::new()),
+                match try!(segment.load_deletion_list()) {
+                    Some(doc_id_set) => stack.push(TwoPhaseDocSet::exact(doc_id_set)),
+                    None => stack.push(TwoPhaseDocSet::exact(RoaringBitmap::new())),
                 }
             }
             BooleanQueryOp::And => {
-                let b = stack.pop().expect("boolean query executor: stack underflow");
-                let a = stack.last_mut().expect("boolean query executor: stack underflow");
+                let b = try!(pop_stack(&mut stack, "boolean query executor"));
+                let a = try!(pop_stack(&mut stack, "boolean query executor"));
 
-                a.intersect_with(&b);
+                stack.push(a.intersect(b));
             }
             BooleanQueryOp::Or => {
-                let b = stack.pop().expect("boolean query executor: stack underflow");
-                let a = stack.last_mut().expect("boolean query executor: stack underflow");
+                let b = try!(pop_stack(&mut stack, "boolean query executor"));
+                let a = try!(pop_stack(&mut stack, "boolean query executor"));
 
-                a.union_with(&b);
+                stack.push(a.union(b));
             }
             BooleanQueryOp::AndNot => {
-                let b = stack.pop().expect("boolean query executor: stack underflow");
-                let a = stack.last_mut().expect("boolean query executor: stack underflow");
+                let b = try!(pop_stack(&mut stack, "boolean query executor"));
+                let a = try!(pop_stack(&mut stack, "boolean query executor"));
 
-                a.difference_with(&b);
+                stack.push(a.difference(b));
             }
         }
     }
 
-    if !stack.len() == 1 {
-        // This shouldn't be possible unless there's a bug in the planner
-        panic!("boolean query executor: stack size too big ({})", stack.len());
-    }
-
-    let mut matches = stack.pop().unwrap();
+    let mut matches = try!(try!(finish_stack(stack, "boolean query executor")).into_bitmap());
 
     if is_negated {
         // Query returns a negated result so we need to correct this by inverting the returned bitmap
-        let total_docs = try!(segment.load_statistic(b"total_docs")).unwrap_or(0);
+        let total_docs = try!(segment.load_statistic(&StatisticKey::TotalDocs)).unwrap_or(0);
         let mut all_docs = RoaringBitmap::new();
         for doc_id in 0..total_docs {
             all_docs.insert(doc_id as u32);
@@ -76,10 +247,10 @@ fn run_boolean_query<S: Segment>(boolean_query: &Vec<BooleanQueryOp>, is_negated
     Ok(matches)
 }
 
-fn score_doc<S: Segment, R: StatisticsReader>(doc_id: u16, score_function: &Vec<ScoreFunctionOp>, segment: &S, stats: &mut R) -> Result<f32, String> {
+fn score_doc<S: Segment>(doc_id: u16, score_function: &Vec<ScoreFunctionOp>, statistics: &[Option<TermStatistics>], segment: &S) -> Result<f32, String> {
     // Execute score function
     let mut stack = Vec::new();
-    for op in score_function.iter() {
+    for (op, op_statistics) in score_function.iter().zip(statistics.iter()) {
         match *op {
             ScoreFunctionOp::Literal(val) => stack.push(val),
             ScoreFunctionOp::TermScorer(field_id, term_id, ref scorer) => {
@@ -107,7 +278,8 @@ fn score_doc<S: Segment, R: StatisticsReader>(doc_id: u16, score_function: &Vec<
                                 None => 1,
                             };
 
-                            let score = scorer.similarity_model.score(term_frequency as u32, field_length, try!(stats.total_tokens(field_id)) as u64, try!(stats.total_docs(field_id)) as u64, try!(stats.term_document_frequency(field_id, term_id)) as u64);
+                            let term_statistics = try!(op_statistics.ok_or("document scorer: missing term statistics"));
+                            let score = scorer.similarity_model.score(term_frequency as u32, field_length, term_statistics.total_tokens as u64, term_statistics.total_docs as u64, term_statistics.term_document_frequency as u64);
                             stack.push(score * scorer.boost);
                         } else {
                             stack.push(0.0f32);
@@ -122,47 +294,111 @@ fn score_doc<S: Segment, R: StatisticsReader>(doc_id: u16, score_function: &Vec<
                         let mut total_score = 0.0f32;
 
                         for _ in 0..num_vals {
-                            total_score += stack.pop().expect("document scorer: stack underflow");
+                            total_score += try!(pop_stack(&mut stack, "document scorer"));
                         }
 
                         total_score / num_vals as f32
                     }
-                    CombinatorScorer::Max => {
+                    CombinatorScorer::Max(tie_breaker) | CombinatorScorer::BlendedMax(tie_breaker) => {
                         let mut max_score = 0.0f32;
+                        let mut total_score = 0.0f32;
 
                         for _ in 0..num_vals {
-                            let score = stack.pop().expect("document scorer: stack underflow");
+                            let score = try!(pop_stack(&mut stack, "document scorer"));
                             if score > max_score {
                                 max_score = score
                             }
+                            total_score += score;
                         }
 
-                        max_score
+                        max_score + (total_score - max_score) * tie_breaker
                     }
                 };
 
                 stack.push(score);
             }
+            ScoreFunctionOp::VectorSimilarity(field_id, ref vector, similarity, boost) => {
+                // Read the vector of the document, each value is a little endian f32
+                match try!(segment.load_stored_field_value_raw(doc_id, field_id, b"val")) {
+                    Some(value) => {
+                        let doc_vector = value.chunks(4).filter(|chunk| chunk.len() == 4).map(LittleEndian::read_f32).collect::<Vec<f32>>();
+                        stack.push(similarity.score(vector, &doc_vector) * boost);
+                    }
+                    None => stack.push(0.0f32),
+                }
+            }
+            ScoreFunctionOp::RandomScore(seed, field, boost) => {
+                // The document's key doesn't change when it's merged into another segment, unlike its id
+                let value = match field {
+                    Some(field_id) => {
+                        match try!(segment.load_stored_field_value_raw(doc_id, field_id, b"dv")) {
+                            Some(value) => Some(value),
+                            None => try!(segment.load_stored_field_value_raw(doc_id, field_id, b"val")),
+                        }
+                    }
+                    None => try!(segment.load_doc_key(doc_id)),
+                };
+
+                match value {
+                    Some(value) => stack.push(random_score(seed, &value) * boost),
+                    None => stack.push(0.0f32),
+                }
+            }
+            ScoreFunctionOp::FeatureScore(field_id, ref field_type, ref function, boost) => {
+                // Multi-valued fields have all their values in "dv", otherwise use the stored value
+                let values = match try!(segment.load_stored_field_value_raw(doc_id, field_id, b"dv")) {
+                    Some(value) => try!(decode_doc_values(field_type, &value).map_err(|e| format!("failed to read feature: {:?}", e))),
+                    None => {
+                        match try!(segment.load_stored_field_value_raw(doc_id, field_id, b"val")) {
+                            Some(value) => vec![try!(decode_stored_field_value(field_type, &value).map_err(|e| format!("failed to read feature: {:?}", e)))],
+                            None => Vec::new(),
+                        }
+                    }
+                };
+
+                stack.push(function.score(&values).unwrap_or(0.0f32) * boost);
+            }
         }
     }
 
-    if !stack.len() == 1 {
-        // This shouldn't be possible unless there's a bug in the planner
-        panic!("document scorer: stack size too big ({})", stack.len());
+    finish_stack(stack, "document scorer")
+}
+
+fn apply_doc_boost<S: Segment>(doc_id: u16, score: f32, segment: &S) -> Result<f32, String> {
+    match try!(segment.load_stored_field_value_raw(doc_id, FieldId(0), b"boost")) {
+        Some(boost) => Ok(score * LittleEndian::read_f32(&boost)),
+        None => Ok(score),
     }
+}
 
-    Ok(stack.pop().expect("document scorer: stack underflow"))
+/// A segment that couldn't be searched, none of its matches are in the results
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentFailure {
+    pub segment: u32,
+    pub reason: String,
 }
 
-fn search_segment<C: Collector, S: Segment, R: StatisticsReader>(collector: &mut C, plan: &SearchPlan, segment: &S, stats: &mut R) -> Result<(), String> {
+fn search_segment<C: Collector, S: Segment>(collector: &mut C, plan: &SearchPlan, statistics: &[Option<TermStatistics>], segment: &S) -> Result<(), String> {
     let matches = try!(run_boolean_query(&plan.boolean_query, plan.boolean_query_is_negated, segment));
 
-    // Score documents and pass to collector
+    // Score documents
+    // They're all scored before any are passed to the collector, so a segment that
+    // fails part of the way through doesn't leave some of its matches in the results
+    let needs_score = collector.needs_score();
+    let mut doc_matches = Vec::new();
     for doc in matches.iter() {
-        let score = try!(score_doc(doc as u16, &plan.score_function, segment, stats));
+        let mut score = try!(score_doc(doc as u16, &plan.score_function, statistics, segment));
+
+        // Apply document boost
+        if needs_score {
+            score = try!(apply_doc_boost(doc as u16, score, segment));
+        }
 
         let doc_id = segment.doc_id(doc as u16);
-        let doc_match = DocumentMatch::new_scored(doc_id.as_u64(), score);
+        doc_matches.push(DocumentMatch::new_scored(doc_id.as_u64(), score));
+    }
+
+    for doc_match in doc_matches {
         collector.collect(doc_match);
     }
 
@@ -170,18 +406,224 @@ fn search_segment<C: Collector, S: Segment, R: StatisticsReader>(collector: &mut
 }
 
 impl<'a> RocksDBReader<'a> {
+    /// Runs a search, failing if any segment can't be searched
     pub fn search<C: Collector>(&self, collector: &mut C, query: &Query) -> Result<(), String> {
-        // Plan query
-        let plan = plan_query(&self, query, collector.needs_score());
+        let failures = try!(self.search_with_segment_failures(collector, query));
 
-        // Initialise statistics reader
-        let mut stats = RocksDBStatisticsReader::new(&self);
+        match failures.into_iter().next() {
+            Some(failure) => Err(format!("segment {}: {}", failure.segment, failure.reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs a search, carrying on with the other segments if one can't be searched
+    ///
+    /// Errors that stop the whole search (like failing to load the statistics) are
+    /// returned as an error. Otherwise, the collector gets the matches of every segment
+    /// that was searched and the segments that failed are returned.
+    pub fn search_with_segment_failures<C: Collector>(&self, collector: &mut C, query: &Query) -> Result<Vec<SegmentFailure>, String> {
+        self.search_traced(collector, query, None)
+    }
+
+    /// Like `search_with_segment_failures`, recording the planning and the search of
+    /// each segment as child spans of the given span
+    pub fn search_traced<C: Collector>(&self, collector: &mut C, query: &Query, span: Option<&Span>) -> Result<Vec<SegmentFailure>, String> {
+        let (plan, statistics) = {
+            let _plan_span = span.map(|span| span.child("plan"));
+
+            // Run the inner queries of any joins first
+            let query = try!(self.resolve_joins(query));
+
+            // Plan query
+            let plan = plan_query(&self, &query, collector.needs_score());
+
+            // Load statistics
+            // These are the same for every document so they're loaded once up front
+            let mut stats = RocksDBStatisticsReader::new(&self);
+            let statistics = try!(load_score_function_statistics(&plan.score_function, &mut stats));
+
+            (plan, statistics)
+        };
 
         // Run query on each segment
+        let mut failures = Vec::new();
         for segment in self.store.segments.iter_active(&self) {
-            try!(search_segment(collector, &plan, &segment, &mut stats));
+            let mut segment_span = span.map(|span| span.child("execute_segment"));
+            let result = search_segment(collector, &plan, &statistics, &segment);
+
+            if let Some(ref mut segment_span) = segment_span {
+                segment_span.set_attribute("segment", json!(segment.id().0));
+                segment_span.set_attribute("failed", json!(result.is_err()));
+            }
+
+            if let Err(reason) = result {
+                failures.push(SegmentFailure {
+                    segment: segment.id().0,
+                    reason: reason,
+                });
+            }
         }
 
-        Ok(())
+        Ok(failures)
+    }
+
+    /// Scores the given documents with a query, without searching the whole index
+    ///
+    /// Documents that don't match the query get None. This is used for rescoring
+    /// the top hits of a search.
+    pub fn score_documents(&self, query: &Query, doc_ids: &[DocId]) -> Result<Vec<Option<f32>>, String> {
+        let mut scores = vec![None; doc_ids.len()];
+
+        let query = try!(self.resolve_joins(query));
+        let plan = plan_query(&self, &query, true);
+        let mut stats = RocksDBStatisticsReader::new(&self);
+        let statistics = try!(load_score_function_statistics(&plan.score_function, &mut stats));
+
+        for segment in self.segments() {
+            let docs = doc_ids.iter().enumerate().filter(|&(_, doc_id)| doc_id.0 == segment.id()).collect::<Vec<_>>();
+            if docs.is_empty() {
+                continue;
+            }
+
+            let matches = try!(run_boolean_query(&plan.boolean_query, plan.boolean_query_is_negated, &segment));
+            for &(i, doc_id) in docs.iter() {
+                if matches.contains(doc_id.1 as u32) {
+                    let score = try!(score_doc(doc_id.1, &plan.score_function, &statistics, &segment));
+                    scores[i] = Some(try!(apply_doc_boost(doc_id.1, score, &segment)));
+                }
+            }
+        }
+
+        Ok(scores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use roaring::RoaringBitmap;
+    use search::segment::{Segment, SegmentId};
+    use search::statistic_key::StatisticKey;
+    use search::schema::FieldId;
+    use search::term::TermId;
+    use search::collectors::top_score::TopScoreCollector;
+
+    use super::super::segment_builder::SegmentBuilder;
+    use super::planner::SearchPlan;
+    use super::planner::boolean_query::BooleanQueryOp;
+    use super::planner::score_function::{CombinatorScorer, ScoreFunctionOp};
+    use super::{run_boolean_query, score_doc, search_segment};
+
+    /// A segment with three documents, reading the stored values of one of them fails
+    struct FailingSegment {
+        failing_doc: Option<u16>,
+    }
+
+    impl Segment for FailingSegment {
+        fn load_statistic(&self, stat_key: &StatisticKey) -> Result<Option<i64>, String> {
+            match *stat_key {
+                StatisticKey::TotalDocs => Ok(Some(3)),
+                _ => Ok(None),
+            }
+        }
+
+        fn load_stored_field_value_raw(&self, doc_local_id: u16, _field_id: FieldId, _value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
+            if self.failing_doc == Some(doc_local_id) {
+                return Err("read error".to_string());
+            }
+
+            Ok(None)
+        }
+
+        fn load_postings_list(&self, _field_id: FieldId, _term_id: TermId) -> Result<Option<RoaringBitmap>, String> {
+            Ok(None)
+        }
+
+        fn load_deletion_list(&self) -> Result<Option<RoaringBitmap>, String> {
+            Ok(None)
+        }
+
+        fn load_doc_key(&self, _doc_local_id: u16) -> Result<Option<Vec<u8>>, String> {
+            Ok(None)
+        }
+
+        fn id(&self) -> SegmentId {
+            SegmentId(1)
+        }
+    }
+
+    fn match_all_plan() -> SearchPlan {
+        let mut plan = SearchPlan::new();
+        plan.boolean_query = vec![BooleanQueryOp::PushEmpty];
+        plan.boolean_query_is_negated = true;
+        plan.score_function = vec![ScoreFunctionOp::Literal(1.0f32)];
+        plan
+    }
+
+    #[test]
+    fn test_search_segment() {
+        let mut collector = TopScoreCollector::new(10);
+        let result = search_segment(&mut collector, &match_all_plan(), &[None], &FailingSegment { failing_doc: None });
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(collector.into_sorted_vec().len(), 3);
+    }
+
+    #[test]
+    fn test_failed_segment_collects_nothing() {
+        let mut collector = TopScoreCollector::new(10);
+        let result = search_segment(&mut collector, &match_all_plan(), &[None], &FailingSegment { failing_doc: Some(2) });
+
+        // The first two documents were scored before the failure, but shouldn't be collected
+        assert_eq!(result, Err("read error".to_string()));
+        assert!(collector.into_sorted_vec().is_empty());
+    }
+
+    #[test]
+    fn test_boolean_query_stack_underflow() {
+        let segment = SegmentBuilder::new();
+        let boolean_query = vec![
+            BooleanQueryOp::PushEmpty,
+            BooleanQueryOp::And,
+        ];
+
+        let result = run_boolean_query(&boolean_query, false, &segment);
+        assert_eq!(result.err(), Some("boolean query executor: stack underflow".to_string()));
+    }
+
+    #[test]
+    fn test_boolean_query_stack_too_big() {
+        let segment = SegmentBuilder::new();
+        let boolean_query = vec![
+            BooleanQueryOp::PushEmpty,
+            BooleanQueryOp::PushEmpty,
+        ];
+
+        let result = run_boolean_query(&boolean_query, false, &segment);
+        assert_eq!(result.err(), Some("boolean query executor: stack size too big (2)".to_string()));
+    }
+
+    #[test]
+    fn test_score_function_stack_underflow() {
+        let segment = SegmentBuilder::new();
+        let score_function = vec![
+            ScoreFunctionOp::Literal(1.0f32),
+            ScoreFunctionOp::CombinatorScorer(2, CombinatorScorer::Avg),
+        ];
+
+        let result = score_doc(0, &score_function, &[None, None], &segment);
+        assert_eq!(result, Err("document scorer: stack underflow".to_string()));
+    }
+    #[test]
+    fn test_max_with_tie_breaker() {
+        let segment = SegmentBuilder::new();
+        let score_function = vec![
+            ScoreFunctionOp::Literal(1.0f32),
+            ScoreFunctionOp::Literal(4.0f32),
+            ScoreFunctionOp::Literal(2.0f32),
+            ScoreFunctionOp::CombinatorScorer(3, CombinatorScorer::Max(0.5f32)),
+        ];
+
+        let result = score_doc(0, &score_function, &[None, None, None, None], &segment);
+        assert_eq!(result, Ok(5.5f32));
     }
 }
@@ -0,0 +1,123 @@
+//! Finds which named queries matched each hit
+//!
+//! Named queries don't change what the query matches so they're not tracked while
+//! searching. Instead, once the hits have been collected, each named query is run
+//! as a boolean query on the segments of the hits to find which of them it matches.
+
+use search::Query;
+use search::document::DocId;
+use search::segment::Segment;
+
+use super::super::RocksDBReader;
+use super::run_boolean_query;
+use super::planner::plan_named_queries;
+
+impl<'a> RocksDBReader<'a> {
+    /// Returns the names of the named queries that match each of the documents
+    ///
+    /// This runs every named query on each segment that contains one of the
+    /// documents, so it should only be used on a page of hits.
+    pub fn matched_queries(&self, query: &Query, doc_ids: &[DocId]) -> Result<Vec<Vec<String>>, String> {
+        let mut matched_queries = vec![Vec::new(); doc_ids.len()];
+
+        if query.named_queries().is_empty() {
+            return Ok(matched_queries);
+        }
+
+        let query = try!(self.resolve_joins(query));
+        let plans = plan_named_queries(self, &query);
+
+        for segment in self.segments() {
+            let docs = doc_ids.iter().enumerate().filter(|&(_, doc_id)| doc_id.0 == segment.id()).collect::<Vec<_>>();
+            if docs.is_empty() {
+                continue;
+            }
+
+            for plan in plans.iter() {
+                let matches = try!(run_boolean_query(&plan.boolean_query, plan.boolean_query_is_negated, &segment));
+
+                for &(i, doc_id) in docs.iter() {
+                    // The same name could be given to more than one query
+                    if matches.contains(doc_id.1 as u32) && !matched_queries[i].contains(&plan.name) {
+                        matched_queries[i].push(plan.name.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(matched_queries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use fnv::FnvHashMap;
+    use search::{Term, Token, Document, Query};
+    use search::document::DocId;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::schema::{FieldType, FIELD_INDEXED};
+    use search::backends::rocksdb::RocksDBStore;
+
+    struct DocIdCollector {
+        doc_ids: Vec<DocId>,
+    }
+
+    impl Collector for DocIdCollector {
+        fn needs_score(&self) -> bool {
+            false
+        }
+
+        fn collect(&mut self, doc: DocumentMatch) {
+            self.doc_ids.push(DocId::from_u64(doc.doc_id()));
+        }
+    }
+
+    #[test]
+    fn test_matched_queries() {
+        let path = "test_indices/test_matched_queries";
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for (key, words) in vec![("a", vec!["hello"]), ("b", vec!["world"]), ("c", vec!["hello", "world"])] {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(title_field, words.iter().enumerate().map(|(position, word)| {
+                Token { term: Term::from_string(word), position: position as u32 + 1 }
+            }).collect::<Vec<_>>().into());
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        let query = Query::Disjunction {
+            queries: vec![
+                Query::term(title_field, Term::from_string("hello")).named("hello".to_string()),
+                Query::term(title_field, Term::from_string("world")).named("world".to_string()),
+            ],
+        };
+
+        let index_reader = store.reader();
+        let mut collector = DocIdCollector { doc_ids: Vec::new() };
+        index_reader.search(&mut collector, &query).unwrap();
+
+        let matched_queries = index_reader.matched_queries(&query, &collector.doc_ids).unwrap();
+        let mut matched_queries_by_key = collector.doc_ids.iter().zip(matched_queries.into_iter()).map(|(doc_id, names)| {
+            (index_reader.doc_key(*doc_id).unwrap().unwrap(), names)
+        }).collect::<Vec<_>>();
+        matched_queries_by_key.sort();
+
+        assert_eq!(matched_queries_by_key, vec![
+            ("a".to_string(), vec!["hello".to_string()]),
+            ("b".to_string(), vec!["world".to_string()]),
+            ("c".to_string(), vec!["hello".to_string(), "world".to_string()]),
+        ]);
+    }
+}
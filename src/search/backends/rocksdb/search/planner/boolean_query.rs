@@ -1,15 +1,55 @@
 use std::rc::Rc;
 
-use search::schema::FieldId;
-use search::term::TermId;
+use fnv::FnvHashSet;
+use search::schema::{FieldId, FieldType};
+use search::term::{Term, TermId};
 use search::Query;
+use search::query::multi_term_selector::MultiTermSelector;
+use search::geo::{GeoShape, GeoShapeRelation};
+use search::runtime::{RuntimeField, RuntimeCondition};
 
 use super::super::RocksDBReader;
 
+/// Disjunctions of at least this many term queries on the same field are run as a term set
+pub const TERM_SET_THRESHOLD: usize = 16;
+
+/// A runtime field query, with the fields that the values are computed from
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeMatches {
+    pub field: RuntimeField,
+    pub condition: RuntimeCondition,
+
+    /// The field that the source of each document is stored in
+    pub source_field: Option<FieldId>,
+
+    /// The fields whose doc values the runtime field reads, by name
+    pub doc_value_fields: Vec<(String, FieldId, FieldType)>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum BooleanQueryOp {
     PushEmpty,
     PushPostingsList(FieldId, TermId),
+
+    /// Pushes the documents that contain any of the terms in the field
+    PushTermSet(FieldId, Vec<TermId>),
+
+    /// Pushes the documents that contain any of the terms in a geo shape field, and
+    /// whose stored shape has the relation to the shape
+    PushGeoShapeMatches(FieldId, Vec<TermId>, GeoShape, GeoShapeRelation),
+
+    /// Pushes the documents that contain a term from each slot at consecutive positions in the field,
+    /// the positions are checked in the second phase
+    PushPhraseMatches(FieldId, Vec<Vec<TermId>>),
+
+    /// Pushes the documents with a value in an integer or date field between two values (inclusive)
+    PushNumericRange(FieldId, i64, i64),
+
+    /// Pushes the documents that have a value in the field, checked in the second phase
+    PushHasValue(FieldId),
+
+    /// Pushes the documents whose runtime field values meet the condition, checked in the second phase
+    PushRuntimeMatches(RuntimeMatches),
     PushDeletionList,
     And,
     Or,
@@ -117,6 +157,92 @@ impl BooleanQueryBuilder {
         }));
     }
 
+    pub fn push_term_set(&mut self, field_id: FieldId, term_ids: Vec<TermId>) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        if term_ids.is_empty() {
+            self.push_empty();
+            return;
+        }
+
+        self.stack.push(Rc::new(Leaf{
+            op: PushTermSet(field_id, term_ids),
+            return_type: Sparse,
+        }));
+    }
+
+    pub fn push_geo_shape_matches(&mut self, field_id: FieldId, term_ids: Vec<TermId>, shape: GeoShape, relation: GeoShapeRelation) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        if term_ids.is_empty() {
+            self.push_empty();
+            return;
+        }
+
+        self.stack.push(Rc::new(Leaf{
+            op: PushGeoShapeMatches(field_id, term_ids, shape, relation),
+            return_type: Sparse,
+        }));
+    }
+
+    pub fn push_phrase_matches(&mut self, field_id: FieldId, slots: Vec<Vec<TermId>>) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        if slots.is_empty() || slots.iter().any(|term_ids| term_ids.is_empty()) {
+            self.push_empty();
+            return;
+        }
+
+        self.stack.push(Rc::new(Leaf{
+            op: PushPhraseMatches(field_id, slots),
+            return_type: Sparse,
+        }));
+    }
+
+    pub fn push_numeric_range(&mut self, field_id: FieldId, gte: i64, lte: i64) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        if gte > lte {
+            self.push_empty();
+            return;
+        }
+
+        self.stack.push(Rc::new(Leaf{
+            op: PushNumericRange(field_id, gte, lte),
+            return_type: Sparse,
+        }));
+    }
+
+    pub fn push_has_value(&mut self, field_id: FieldId) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        self.stack.push(Rc::new(Leaf{
+            op: PushHasValue(field_id),
+            return_type: Sparse,
+        }));
+    }
+
+    pub fn push_runtime_matches(&mut self, runtime: RuntimeMatches) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        self.stack.push(Rc::new(Leaf{
+            op: PushRuntimeMatches(runtime),
+            return_type: Sparse,
+        }));
+    }
+
     pub fn push_deletion_list(&mut self) {
         use self::BooleanQueryOp::*;
         use self::BooleanQueryBlock::*;
@@ -353,6 +479,45 @@ fn plan_boolean_query_combinator<J: Fn(&mut BooleanQueryBuilder) -> ()> (index_r
     }
 }
 
+/// Finds the field and terms of a disjunction that only contains term queries on one field
+pub fn as_term_set(queries: &[Query]) -> Option<(FieldId, Vec<&Term>)> {
+    let mut set_field = None;
+    let mut terms = Vec::with_capacity(queries.len());
+
+    for query in queries.iter() {
+        match *query {
+            Query::Term{field, ref term, ..} => {
+                if set_field.is_some() && set_field != Some(field) {
+                    return None;
+                }
+
+                set_field = Some(field);
+                terms.push(term);
+            }
+            _ => return None,
+        }
+    }
+
+    set_field.map(|field| (field, terms))
+}
+
+/// Finds the term ids at each position of a phrase query
+///
+/// The final slot has the terms that the prefix expands into, if there is one. Slots
+/// are empty if their term isn't in the index.
+pub fn phrase_slots(index_reader: &RocksDBReader, terms: &[Term], prefix: &Option<String>, max_expansions: usize) -> Vec<Vec<TermId>> {
+    let mut slots = terms.iter()
+        .map(|term| index_reader.store.term_dictionary.get(term).into_iter().collect())
+        .collect::<Vec<Vec<TermId>>>();
+
+    if let Some(ref prefix) = *prefix {
+        let term_selector = MultiTermSelector::Prefix(prefix.clone());
+        slots.push(index_reader.store.term_dictionary.select(&term_selector).into_iter().take(max_expansions).collect());
+    }
+
+    slots
+}
+
 pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut BooleanQueryBuilder, query: &Query) {
     match *query {
         Query::All{..} => {
@@ -382,15 +547,50 @@ pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut Boolea
                 builder.or_combinator();
             }
         }
+        Query::Phrase{field, ref terms, ref prefix, max_expansions, ..} => {
+            builder.push_phrase_matches(field, phrase_slots(index_reader, terms, prefix, max_expansions));
+        }
         Query::Conjunction{ref queries} => {
             plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.and_combinator());
         }
         Query::Disjunction{ref queries} => {
+            // Long lists of terms (eg, from a "terms" query with lots of ids) are run as a
+            // single set rather than as a deep tree of unions
+            if queries.len() >= TERM_SET_THRESHOLD {
+                if let Some((field, terms)) = as_term_set(queries) {
+                    let mut seen = FnvHashSet::default();
+                    let term_ids = terms.into_iter()
+                        .filter_map(|term| index_reader.store.term_dictionary.get(term))
+                        .filter(|term_id| seen.insert(*term_id))
+                        .collect();
+
+                    builder.push_term_set(field, term_ids);
+                    return;
+                }
+            }
+
             plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator());
         }
-        Query::DisjunctionMax{ref queries} => {
+        Query::DisjunctionMax{ref queries, ..} => {
             plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator());
         }
+        Query::BlendedTerm{ref fields, ref term, ..} => {
+            // Get term
+            let term_id = match index_reader.store.term_dictionary.get(term) {
+                Some(term_id) => term_id,
+                None => {
+                    // Term doesn't exist, so will never match
+                    builder.push_empty();
+                    return
+                }
+            };
+
+            builder.push_empty();
+            for &(field, _) in fields.iter() {
+                builder.push_postings_list(field, term_id);
+                builder.or_combinator();
+            }
+        }
         Query::Filter{ref query, ref filter} => {
             plan_boolean_query(index_reader, &mut builder, query);
             plan_boolean_query(index_reader, &mut builder, filter);
@@ -401,6 +601,58 @@ pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut Boolea
             plan_boolean_query(index_reader, &mut builder, exclude);
             builder.andnot_combinator();
         }
+        Query::Join{..} => {
+            // Joins are replaced with term queries before the query is planned (see
+            // resolve_joins). If one gets here, there's nothing it can match
+            builder.push_empty();
+        }
+        Query::VectorScore{ref query, ..} | Query::RandomScore{ref query, ..} => {
+            plan_boolean_query(index_reader, &mut builder, query);
+        }
+        Query::GeoShape{field, ref terms, ref shape, relation, ..} => {
+            let mut seen = FnvHashSet::default();
+            let term_ids = terms.iter()
+                .filter_map(|term| index_reader.store.term_dictionary.get(term))
+                .filter(|term_id| seen.insert(*term_id))
+                .collect();
+
+            builder.push_geo_shape_matches(field, term_ids, shape.clone(), relation);
+        }
+        Query::NumericRange{field, gte, lte, ..} => {
+            builder.push_numeric_range(field, gte, lte);
+        }
+        Query::FeatureScore{field, ..} => {
+            // Integer and date fields have numeric blocks that find the documents with a
+            // value, other fields need their stored values checked
+            let is_numeric = match index_reader.schema().get(&field) {
+                Some(field_info) => field_info.field_type == FieldType::I64 || field_info.field_type == FieldType::DateTime,
+                None => false,
+            };
+
+            if is_numeric {
+                builder.push_numeric_range(field, i64::min_value(), i64::max_value());
+            } else {
+                builder.push_has_value(field);
+            }
+        }
+        Query::Runtime{ref field, ref condition, ..} => {
+            let schema = index_reader.schema();
+            let doc_value_fields = field.doc_value_fields().into_iter().filter_map(|name| {
+                schema.get_field_by_name(&name).and_then(|field_id| {
+                    schema.get(&field_id).map(|field_info| (name.clone(), field_id, field_info.field_type.clone()))
+                })
+            }).collect();
+
+            builder.push_runtime_matches(RuntimeMatches {
+                field: field.clone(),
+                condition: condition.clone(),
+                source_field: schema.get_field_by_name("_source"),
+                doc_value_fields: doc_value_fields,
+            });
+        }
+        Query::Named{ref query, ..} => {
+            plan_boolean_query(index_reader, &mut builder, query);
+        }
     }
 }
 
@@ -454,6 +706,34 @@ mod builder_tests {
         assert_eq!(negated, false);
     }
 
+    #[test]
+    fn test_push_term_set() {
+        let mut builder = BooleanQueryBuilder::new();
+
+        builder.push_term_set(FieldId(1), vec![TermId(1), TermId(2)]);
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushTermSet(FieldId(1), vec![TermId(1), TermId(2)]),
+        ]);
+        assert_eq!(negated, false);
+    }
+
+    #[test]
+    fn test_push_empty_term_set() {
+        let mut builder = BooleanQueryBuilder::new();
+
+        builder.push_term_set(FieldId(1), vec![]);
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushEmpty,
+        ]);
+        assert_eq!(negated, false);
+    }
+
     #[test]
     fn test_push_deletion_list() {
         let mut builder = BooleanQueryBuilder::new();
@@ -0,0 +1,269 @@
+//! Limits on how many clauses a query can expand into
+//!
+//! Multi term queries (prefix, fuzzy, regex) are expanded into a clause for every term
+//! in the index that they match, which can easily be thousands. Queries are checked
+//! against these limits before they're run so they fail with a "too_many_clauses"
+//! error instead of doing an unbounded amount of work.
+
+use search::Query;
+use search::query::multi_term_selector::MultiTermSelector;
+use search::schema::FieldId;
+
+use super::super::super::RocksDBReader;
+use super::boolean_query::{TERM_SET_THRESHOLD, as_term_set};
+
+
+/// The most term clauses a query can have, unless configured otherwise ("indices.query.bool.max_clause_count")
+pub const DEFAULT_MAX_CLAUSE_COUNT: usize = 1024;
+
+/// The most terms a single multi term query can match, unless configured otherwise
+pub const DEFAULT_MAX_EXPANSIONS: usize = 1024;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryLimits {
+    /// The most term clauses a query can have, once multi term queries have been expanded
+    pub max_clause_count: usize,
+
+    /// The most terms that a single multi term query can match
+    pub max_expansions: usize,
+}
+
+
+impl Default for QueryLimits {
+    fn default() -> QueryLimits {
+        QueryLimits {
+            max_clause_count: DEFAULT_MAX_CLAUSE_COUNT,
+            max_expansions: DEFAULT_MAX_EXPANSIONS,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryLimitError {
+    TooManyClauses {
+        max_clause_count: usize,
+    },
+    TooManyExpansions {
+        field: FieldId,
+        max_expansions: usize,
+    },
+}
+
+
+impl QueryLimitError {
+    pub fn message(&self) -> String {
+        match *self {
+            QueryLimitError::TooManyClauses{max_clause_count} => {
+                format!("query has more than {} clauses", max_clause_count)
+            }
+            QueryLimitError::TooManyExpansions{field, max_expansions} => {
+                format!("multi term query on field {} matches more than {} terms", field.0, max_expansions)
+            }
+        }
+    }
+}
+
+
+impl From<QueryLimitError> for String {
+    fn from(error: QueryLimitError) -> String {
+        error.message()
+    }
+}
+
+
+impl<'a> RocksDBReader<'a> {
+    /// Checks that a query doesn't expand into more clauses than the limits allow
+    pub fn check_query_limits(&self, query: &Query, limits: &QueryLimits) -> Result<(), QueryLimitError> {
+        let mut clause_count = 0;
+        self.count_clauses(query, limits, &mut clause_count)
+    }
+
+    fn count_clauses(&self, query: &Query, limits: &QueryLimits, clause_count: &mut usize) -> Result<(), QueryLimitError> {
+        let num_clauses = match *query {
+            Query::All{..} | Query::None => 0,
+            Query::Term{..} => 1,
+            Query::BlendedTerm{ref fields, ..} => fields.len(),
+            // The cells are run as a single term set
+            Query::GeoShape{..} => 1,
+            // The doc values are scanned, there are no terms to expand into
+            Query::NumericRange{..} | Query::FeatureScore{..} => 1,
+            // Every document is checked, there are no terms to expand into
+            Query::Runtime{..} => 1,
+            Query::MultiTerm{field, ref term_selector, ..} => {
+                // Stop counting terms as soon as there are too many
+                let num_terms = self.store.term_dictionary.count(term_selector, limits.max_expansions + 1);
+                if num_terms > limits.max_expansions {
+                    return Err(QueryLimitError::TooManyExpansions {
+                        field: field,
+                        max_expansions: limits.max_expansions,
+                    });
+                }
+
+                num_terms
+            }
+            Query::Phrase{ref terms, ref prefix, max_expansions, ..} => {
+                // The prefix is never expanded into more than max_expansions terms
+                let num_expansions = match *prefix {
+                    Some(ref prefix) => self.store.term_dictionary.count(&MultiTermSelector::Prefix(prefix.clone()), max_expansions),
+                    None => 0,
+                };
+
+                terms.len() + num_expansions
+            }
+            Query::Disjunction{ref queries} if queries.len() >= TERM_SET_THRESHOLD && as_term_set(queries).is_some() => {
+                // Run as a single term set by the planner
+                1
+            }
+            Query::Conjunction{ref queries} | Query::Disjunction{ref queries} | Query::DisjunctionMax{ref queries, ..} => {
+                for query in queries.iter() {
+                    try!(self.count_clauses(query, limits, clause_count));
+                }
+
+                0
+            }
+            Query::Filter{ref query, filter: ref other} | Query::Exclude{ref query, exclude: ref other} => {
+                try!(self.count_clauses(query, limits, clause_count));
+                try!(self.count_clauses(other, limits, clause_count));
+                0
+            }
+            Query::Join{ref query, ..} | Query::VectorScore{ref query, ..} | Query::RandomScore{ref query, ..} | Query::Named{ref query, ..} => {
+                try!(self.count_clauses(query, limits, clause_count));
+                0
+            }
+        };
+
+        *clause_count += num_clauses;
+        if *clause_count > limits.max_clause_count {
+            return Err(QueryLimitError::TooManyClauses {
+                max_clause_count: limits.max_clause_count,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use fnv::FnvHashMap;
+    use search::{Term, Token, Document, Query, MultiTermSelector, TermScorer};
+    use search::schema::{FieldType, FieldId, FIELD_INDEXED};
+    use search::backends::rocksdb::RocksDBStore;
+
+    use super::{QueryLimits, QueryLimitError};
+
+    /// Creates a store with ten documents, containing the terms "word0" to "word9"
+    fn make_store(path: &str) -> (RocksDBStore, FieldId) {
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for i in 0..10 {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(title_field, vec![Token { term: Term::from_string(&format!("word{}", i)), position: 1 }].into());
+
+            store.insert_or_update_document(&Document {
+                key: i.to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        (store, title_field)
+    }
+
+    fn prefix(field: FieldId, prefix: &str) -> Query {
+        Query::MultiTerm {
+            field: field,
+            term_selector: MultiTermSelector::Prefix(prefix.to_string()),
+            scorer: TermScorer::default(),
+        }
+    }
+
+    #[test]
+    fn test_too_many_expansions() {
+        let (store, title_field) = make_store("test_indices/test_query_limits_too_many_expansions");
+        let reader = store.reader();
+        let limits = QueryLimits { max_clause_count: 100, max_expansions: 5 };
+
+        assert_eq!(reader.check_query_limits(&prefix(title_field, "word"), &limits), Err(QueryLimitError::TooManyExpansions {
+            field: title_field,
+            max_expansions: 5,
+        }));
+        assert_eq!(reader.check_query_limits(&prefix(title_field, "word1"), &limits), Ok(()));
+    }
+
+    #[test]
+    fn test_case_insensitive_expansions() {
+        let (store, title_field) = make_store("test_indices/test_query_limits_case_insensitive_expansions");
+
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(title_field, vec![
+            Token { term: Term::from_string("WORD1"), position: 1 },
+            Token { term: Term::from_string("Word1"), position: 2 },
+        ].into());
+        store.insert_or_update_document(&Document {
+            key: "upper".to_string(),
+            boost: 1.0f32,
+            indexed_fields: indexed_fields,
+            stored_fields: FnvHashMap::default(),
+            doc_values: FnvHashMap::default(),
+        }).unwrap();
+
+        let reader = store.reader();
+        let limits = QueryLimits { max_clause_count: 100, max_expansions: 2 };
+        let case_insensitive = |value| Query::MultiTerm {
+            field: title_field,
+            term_selector: MultiTermSelector::case_insensitive(value),
+            scorer: TermScorer::default(),
+        };
+
+        assert_eq!(reader.check_query_limits(&case_insensitive("word1"), &limits), Err(QueryLimitError::TooManyExpansions {
+            field: title_field,
+            max_expansions: 2,
+        }));
+        assert_eq!(reader.check_query_limits(&case_insensitive("WORD2"), &limits), Ok(()));
+    }
+
+    #[test]
+    fn test_too_many_clauses() {
+        let (store, title_field) = make_store("test_indices/test_query_limits_too_many_clauses");
+        let reader = store.reader();
+        let limits = QueryLimits { max_clause_count: 12, max_expansions: 100 };
+
+        // The prefix expands to ten clauses
+        let query = Query::Disjunction {
+            queries: vec![
+                prefix(title_field, "word"),
+                Query::term(title_field, Term::from_string("foo")),
+                Query::term(title_field, Term::from_string("bar")),
+            ],
+        };
+        assert_eq!(reader.check_query_limits(&query, &limits), Ok(()));
+
+        let query = query.filter(Query::term(title_field, Term::from_string("baz")));
+        assert_eq!(reader.check_query_limits(&query, &limits), Err(QueryLimitError::TooManyClauses {
+            max_clause_count: 12,
+        }));
+    }
+
+    #[test]
+    fn test_term_set_is_one_clause() {
+        let (store, title_field) = make_store("test_indices/test_query_limits_term_set_is_one_clause");
+        let reader = store.reader();
+        let limits = QueryLimits { max_clause_count: 1, max_expansions: 100 };
+
+        let query = Query::Disjunction {
+            queries: (0..20).map(|i| Query::term(title_field, Term::from_string(&format!("word{}", i)))).collect(),
+        };
+        assert_eq!(reader.check_query_limits(&query, &limits), Ok(()));
+    }
+}
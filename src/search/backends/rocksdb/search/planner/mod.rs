@@ -1,5 +1,6 @@
 pub mod boolean_query;
 pub mod score_function;
+pub mod limits;
 
 use search::Query;
 
@@ -24,6 +25,29 @@ impl SearchPlan {
     }
 }
 
+/// The boolean query of a named query, for finding which hits it matched
+#[derive(Debug)]
+pub struct NamedQueryPlan {
+    pub name: String,
+    pub boolean_query: Vec<BooleanQueryOp>,
+    pub boolean_query_is_negated: bool,
+}
+
+/// Plans a boolean query for each named query in the query
+pub fn plan_named_queries(index_reader: &RocksDBReader, query: &Query) -> Vec<NamedQueryPlan> {
+    query.named_queries().into_iter().map(|(name, query)| {
+        let mut builder = BooleanQueryBuilder::new();
+        plan_boolean_query(index_reader, &mut builder, query);
+        let (boolean_query, boolean_query_is_negated) = builder.build();
+
+        NamedQueryPlan {
+            name: name.to_string(),
+            boolean_query: boolean_query,
+            boolean_query_is_negated: boolean_query_is_negated,
+        }
+    }).collect()
+}
+
 pub fn plan_query(index_reader: &RocksDBReader, query: &Query, score: bool) -> SearchPlan {
     let mut plan = SearchPlan::new();
 
@@ -1,14 +1,23 @@
-use search::schema::FieldId;
+use search::schema::{FieldId, FieldType};
 use search::term::TermId;
 use search::Query;
 use search::query::term_scorer::TermScorer;
+use search::similarity::VectorSimilarity;
+use search::feature::FeatureFunction;
 
 use super::super::RocksDBReader;
+use super::boolean_query::phrase_slots;
 
 #[derive(Debug, Clone)]
 pub enum CombinatorScorer {
     Avg,
-    Max,
+
+    /// The highest score, plus the other scores multiplied by the tie breaker
+    Max(f32),
+
+    /// Same as Max, but the arguments are all term scorers for the same term (or literals
+    /// if the term doesn't exist). Their statistics are blended before scoring
+    BlendedMax(f32),
 }
 
 #[derive(Debug, Clone)]
@@ -16,12 +25,23 @@ pub enum ScoreFunctionOp {
     Literal(f32),
     TermScorer(FieldId, TermId, TermScorer),
     CombinatorScorer(u32, CombinatorScorer),
+
+    /// Compares the vector stored in a field with the query vector, the score is multiplied by the boost
+    VectorSimilarity(FieldId, Vec<f32>, VectorSimilarity, f32),
+
+    /// Hashes the seed with the value of the field (or the document's key), the score is multiplied by the boost
+    RandomScore(u64, Option<FieldId>, f32),
+
+    /// Scores the values of a field with a feature function, the score is multiplied by the boost
+    FeatureScore(FieldId, FieldType, FeatureFunction, f32),
 }
 
 fn plan_score_function_combinator(index_reader: &RocksDBReader, mut score_function: &mut Vec<ScoreFunctionOp>, queries: &Vec<Query>, scorer: CombinatorScorer) {
     match queries.len() {
         0 => {
+            // Nothing to combine
             score_function.push(ScoreFunctionOp::Literal(0.0f32));
+            return;
         }
         1 =>  plan_score_function(index_reader, &mut score_function, &queries[0]),
         _ => {
@@ -77,14 +97,53 @@ pub fn plan_score_function(index_reader: &RocksDBReader, mut score_function: &mu
                 _ => score_function.push(ScoreFunctionOp::CombinatorScorer(total_terms, CombinatorScorer::Avg)),
             }
         }
+        Query::Phrase{field, ref terms, ref prefix, max_expansions, ref scorer} => {
+            // Each term is scored on its own, then the scores are averaged. A document only
+            // contains some of the terms that the prefix expands into, so these take the highest
+            let slots = phrase_slots(index_reader, terms, prefix, max_expansions);
+            for term_ids in slots.iter() {
+                for term_id in term_ids.iter() {
+                    score_function.push(ScoreFunctionOp::TermScorer(field, *term_id, scorer.clone()));
+                }
+
+                match term_ids.len() {
+                    0 => score_function.push(ScoreFunctionOp::Literal(0.0f32)),
+                    1 => {},
+                    num_terms => score_function.push(ScoreFunctionOp::CombinatorScorer(num_terms as u32, CombinatorScorer::Max(0.0f32))),
+                }
+            }
+
+            match slots.len() {
+                0 => score_function.push(ScoreFunctionOp::Literal(0.0f32)),
+                1 => {},
+                num_slots => score_function.push(ScoreFunctionOp::CombinatorScorer(num_slots as u32, CombinatorScorer::Avg)),
+            }
+        }
         Query::Conjunction{ref queries} => {
             plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg);
         }
         Query::Disjunction{ref queries} => {
             plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg);
         }
-        Query::DisjunctionMax{ref queries} => {
-            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Max);
+        Query::DisjunctionMax{ref queries, tie_breaker} => {
+            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Max(tie_breaker));
+        }
+        Query::BlendedTerm{ref fields, ref term, tie_breaker} => {
+            // Get term
+            let term_id = index_reader.store.term_dictionary.get(term);
+
+            for &(field, ref scorer) in fields.iter() {
+                match term_id {
+                    Some(term_id) => score_function.push(ScoreFunctionOp::TermScorer(field, term_id, scorer.clone())),
+                    None => score_function.push(ScoreFunctionOp::Literal(0.0f32)),
+                }
+            }
+
+            if fields.is_empty() {
+                score_function.push(ScoreFunctionOp::Literal(0.0f32));
+            } else {
+                score_function.push(ScoreFunctionOp::CombinatorScorer(fields.len() as u32, CombinatorScorer::BlendedMax(tie_breaker)));
+            }
         }
         Query::Filter{ref query, ..} => {
             plan_score_function(index_reader, &mut score_function, query);
@@ -92,5 +151,30 @@ pub fn plan_score_function(index_reader: &RocksDBReader, mut score_function: &mu
         Query::Exclude{ref query, ..} => {
             plan_score_function(index_reader, &mut score_function, query);
         }
+        Query::Join{..} => {
+            // Joins are replaced before planning, see plan_boolean_query
+            score_function.push(ScoreFunctionOp::Literal(0.0f32));
+        }
+        Query::VectorScore{field, ref vector, similarity, boost, ..} => {
+            // The inner query only selects the documents to score
+            score_function.push(ScoreFunctionOp::VectorSimilarity(field, vector.clone(), similarity, boost));
+        }
+        Query::RandomScore{seed, field, boost, ..} => {
+            // The inner query only selects the documents to score
+            score_function.push(ScoreFunctionOp::RandomScore(seed, field, boost));
+        }
+        Query::FeatureScore{field, function, boost} => {
+            // The type of the field is needed to decode its values
+            match index_reader.schema().get(&field) {
+                Some(field_info) => score_function.push(ScoreFunctionOp::FeatureScore(field, field_info.field_type.clone(), function, boost)),
+                None => score_function.push(ScoreFunctionOp::Literal(0.0f32)),
+            }
+        }
+        Query::GeoShape{score, ..} | Query::NumericRange{score, ..} | Query::Runtime{score, ..} => {
+            score_function.push(ScoreFunctionOp::Literal(score));
+        }
+        Query::Named{ref query, ..} => {
+            plan_score_function(index_reader, &mut score_function, query);
+        }
     }
 }
@@ -3,9 +3,10 @@ use fnv::FnvHashMap;
 use search::schema::FieldId;
 use search::term::TermId;
 use search::segment::Segment;
+use search::statistic_key::StatisticKey;
 
 use super::super::RocksDBReader;
-use super::super::key_builder::KeyBuilder;
+use super::planner::score_function::{CombinatorScorer, ScoreFunctionOp};
 
 pub trait StatisticsReader {
     fn total_docs(&mut self, field_id: FieldId) -> Result<i64, String>;
@@ -13,6 +14,56 @@ pub trait StatisticsReader {
     fn term_document_frequency(&mut self, field_id: FieldId, term_id: TermId) -> Result<i64, String>;
 }
 
+/// The statistics needed to score a term
+#[derive(Debug, Clone, Copy)]
+pub struct TermStatistics {
+    pub total_docs: i64,
+    pub total_tokens: i64,
+    pub term_document_frequency: i64,
+}
+
+/// Loads the statistics for every term scorer in a score function
+///
+/// This is done once per search, before any documents are scored. The result has
+/// one entry for each operation in the score function so they can be looked up by
+/// position while scoring.
+pub fn load_score_function_statistics<R: StatisticsReader>(score_function: &[ScoreFunctionOp], stats: &mut R) -> Result<Vec<Option<TermStatistics>>, String> {
+    let mut statistics = Vec::with_capacity(score_function.len());
+
+    for op in score_function.iter() {
+        match *op {
+            ScoreFunctionOp::TermScorer(field_id, term_id, _) => {
+                statistics.push(Some(TermStatistics {
+                    total_docs: try!(stats.total_docs(field_id)),
+                    total_tokens: try!(stats.total_tokens(field_id)),
+                    term_document_frequency: try!(stats.term_document_frequency(field_id, term_id)),
+                }));
+            }
+            ScoreFunctionOp::CombinatorScorer(num_vals, CombinatorScorer::BlendedMax(_)) => {
+                // Score the term as if the fields were one field, by giving every field
+                // the highest document frequency of any of them
+                let start = statistics.len() - num_vals as usize;
+                let term_document_frequency = statistics[start..].iter()
+                    .filter_map(|term_statistics| term_statistics.map(|term_statistics| term_statistics.term_document_frequency))
+                    .max();
+
+                if let Some(term_document_frequency) = term_document_frequency {
+                    for term_statistics in statistics[start..].iter_mut() {
+                        if let Some(ref mut term_statistics) = *term_statistics {
+                            term_statistics.term_document_frequency = term_document_frequency;
+                        }
+                    }
+                }
+
+                statistics.push(None);
+            }
+            _ => statistics.push(None),
+        }
+    }
+
+    Ok(statistics)
+}
+
 pub struct RocksDBStatisticsReader<'a> {
     index_reader: &'a RocksDBReader<'a>,
     total_docs: FnvHashMap<FieldId, i64>,
@@ -30,11 +81,11 @@ impl<'a> RocksDBStatisticsReader<'a> {
         }
     }
 
-    fn get_statistic(&self, name: &[u8]) -> Result<i64, String> {
+    fn get_statistic(&self, stat_key: &StatisticKey) -> Result<i64, String> {
         let mut val = 0;
 
         for segment in self.index_reader.store.segments.iter_active(&self.index_reader) {
-            if let Some(new_val) = try!(segment.load_statistic(name)) {
+            if let Some(new_val) = try!(segment.load_statistic(stat_key)) {
                 val += new_val;
             }
         }
@@ -49,8 +100,7 @@ impl<'a> StatisticsReader for RocksDBStatisticsReader<'a> {
             return Ok(*val);
         }
 
-        let stat_name = KeyBuilder::segment_stat_total_field_docs_stat_name(field_id.0);
-        let val = try!(self.get_statistic(&stat_name));
+        let val = try!(self.index_reader.rolled_up_statistic(&StatisticKey::TotalFieldDocs(field_id))).unwrap_or(0);
         self.total_docs.insert(field_id, val);
         Ok(val)
     }
@@ -60,8 +110,7 @@ impl<'a> StatisticsReader for RocksDBStatisticsReader<'a> {
             return Ok(*val);
         }
 
-        let stat_name = KeyBuilder::segment_stat_total_field_tokens_stat_name(field_id.0);
-        let val = try!(self.get_statistic(&stat_name));
+        let val = try!(self.index_reader.rolled_up_statistic(&StatisticKey::TotalFieldTokens(field_id))).unwrap_or(0);
         self.total_tokens.insert(field_id, val);
         Ok(val)
     }
@@ -71,8 +120,7 @@ impl<'a> StatisticsReader for RocksDBStatisticsReader<'a> {
             return Ok(*val);
         }
 
-        let stat_name = KeyBuilder::segment_stat_term_doc_frequency_stat_name(field_id.0, term_id.0);
-        let val = try!(self.get_statistic(&stat_name));
+        let val = try!(self.get_statistic(&StatisticKey::TermDocumentFrequency(field_id, term_id)));
         self.term_document_frequencies.insert((field_id, term_id), val);
         Ok(val)
     }
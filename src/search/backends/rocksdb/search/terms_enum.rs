@@ -0,0 +1,113 @@
+//! Lists the terms of a field, for autocomplete and filter UIs
+//!
+//! The term dictionary is shared by all fields, so terms are read from it in order and
+//! each one is checked for live documents in the field.
+
+use search::Term;
+use search::schema::FieldId;
+use search::segment::Segment;
+
+use super::super::RocksDBReader;
+
+impl<'a> RocksDBReader<'a> {
+    /// Finds the terms of a field that start with a prefix, in byte order
+    ///
+    /// Returns up to `size` terms, with the number of live documents that contain each
+    /// one in the field.
+    pub fn terms_enum(&self, field_id: FieldId, prefix: &[u8], size: usize) -> Result<Vec<(Term, u64)>, String> {
+        let mut terms = Vec::new();
+        if size == 0 {
+            return Ok(terms);
+        }
+
+        // Load the deletion list of each segment once
+        let mut segments = Vec::new();
+        for segment in self.segments() {
+            let deleted_docs = try!(segment.load_deletion_list());
+            segments.push((segment, deleted_docs));
+        }
+
+        try!(self.store.term_dictionary.visit_prefix(&self.store.db, &self.snapshot, prefix, |term, term_id| {
+            let mut doc_count = 0;
+            for &(ref segment, ref deleted_docs) in segments.iter() {
+                if let Some(mut postings) = try!(segment.load_postings_list(field_id, term_id)) {
+                    if let Some(ref deleted_docs) = *deleted_docs {
+                        postings.difference_with(deleted_docs);
+                    }
+
+                    doc_count += postings.len() as u64;
+                }
+            }
+
+            // Terms of other fields (or only in deleted documents) aren't included
+            if doc_count > 0 {
+                terms.push((term, doc_count));
+            }
+
+            Ok(terms.len() < size)
+        }));
+
+        Ok(terms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use fnv::FnvHashMap;
+
+    use search::{Term, Token, Document};
+    use search::schema::{FieldType, FIELD_INDEXED};
+    use search::backends::rocksdb::RocksDBStore;
+
+    #[test]
+    fn test_terms_enum() {
+        let path = "test_indices/test_terms_enum";
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let body_field = store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let docs = vec![
+            ("a", vec!["hello", "world"], vec!["helium"]),
+            ("b", vec!["help"], vec![]),
+            ("c", vec!["hello", "helmet"], vec![]),
+            ("d", vec!["hero"], vec![]),
+        ];
+
+        for (key, title_words, body_words) in docs {
+            let mut indexed_fields = FnvHashMap::default();
+            for &(field, ref words) in [(title_field, title_words), (body_field, body_words)].iter() {
+                indexed_fields.insert(field, words.iter().enumerate().map(|(position, word)| {
+                    Token { term: Term::from_string(word), position: position as u32 + 1 }
+                }).collect::<Vec<_>>().into());
+            }
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        // "helmet" is only in a deleted document
+        store.remove_document_by_key("c").unwrap();
+
+        let index_reader = store.reader();
+        let terms = |prefix: &str, size: usize| {
+            index_reader.terms_enum(title_field, prefix.as_bytes(), size).unwrap().into_iter().map(|(term, doc_count)| {
+                (String::from_utf8(term.as_bytes().to_vec()).unwrap(), doc_count)
+            }).collect::<Vec<_>>()
+        };
+
+        // "helium" is in another field
+        assert_eq!(terms("hel", 10), vec![("hello".to_string(), 1), ("help".to_string(), 1)]);
+        assert_eq!(terms("he", 2), vec![("hello".to_string(), 1), ("help".to_string(), 1)]);
+        assert_eq!(terms("", 10).len(), 4);
+        assert_eq!(terms("x", 10), vec![]);
+    }
+}
@@ -0,0 +1,476 @@
+//! Testing utilities for the query planner and executor
+//!
+//! There are two kinds of test here:
+//!
+//!  - Property tests generate random queries and check that the executor matches
+//!    exactly the same documents as a naive implementation that evaluates the query
+//!    against an in-memory copy of the corpus. Scores are checked for properties
+//!    that must hold whatever the similarity model (eg, boosting a query by 2 doubles
+//!    every score).
+//!
+//!  - Golden tests plan a set of known queries and compare the plans with the ones
+//!    saved in `planner/golden`. Set `UPDATE_GOLDEN_PLANS=1` to rewrite the saved
+//!    plans after an intentional change to the planner.
+
+use std::env;
+use std::fs::{self, File, remove_dir_all};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use search::{Term, Token, Document};
+use search::schema::{FieldId, FieldType, FIELD_INDEXED};
+use search::query::Query;
+use search::collectors::{Collector, DocumentMatch};
+use search::document::DocId;
+
+use super::super::RocksDBStore;
+use super::planner::SearchPlan;
+use super::planner::boolean_query::BooleanQueryOp;
+use super::planner::score_function::{CombinatorScorer, ScoreFunctionOp};
+
+
+/// A small, deterministic random number generator (xorshift64)
+///
+/// Failing cases must be reproducible, so every test uses a fixed seed.
+pub struct Rng(u64);
+
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Picks a number from 0 to n - 1
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+
+const WORDS: &'static [&'static str] = &["alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel"];
+
+
+/// A store along with an in-memory copy of every document in it
+pub struct TestCorpus {
+    pub store: RocksDBStore,
+    pub fields: Vec<FieldId>,
+    pub docs: FnvHashMap<String, FnvHashMap<FieldId, Vec<Term>>>,
+}
+
+
+impl TestCorpus {
+    /// Creates a store with randomly generated documents in it
+    ///
+    /// Some of the documents are merged into a single segment and some are deleted
+    /// so the executor has to deal with a realistic mix of segments.
+    pub fn generate(path: &str, rng: &mut Rng, num_docs: usize) -> TestCorpus {
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        let fields = vec![
+            store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap(),
+            store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap(),
+        ];
+
+        let mut docs = FnvHashMap::default();
+        for i in 0..num_docs {
+            let key = format!("doc{}", i);
+            let mut indexed_fields = FnvHashMap::default();
+            let mut doc_terms = FnvHashMap::default();
+
+            for field in fields.iter() {
+                let mut tokens = Vec::new();
+                let mut terms = Vec::new();
+
+                for position in 0..rng.below(4) {
+                    let term = Term::from_string(WORDS[rng.below(WORDS.len())]);
+                    tokens.push(Token { term: term.clone(), position: position as u32 + 1 });
+                    terms.push(term);
+                }
+
+                if !tokens.is_empty() {
+                    indexed_fields.insert(*field, tokens.into());
+                    doc_terms.insert(*field, terms);
+                }
+            }
+
+            store.insert_or_update_document(&Document {
+                key: key.clone(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+
+            docs.insert(key, doc_terms);
+        }
+
+        // Merge the first half of the segments
+        let mut segments = store.get_segment_statistics().unwrap().into_iter().map(|(segment, _)| segment).collect::<Vec<_>>();
+        segments.sort();
+        let to_merge = segments[..segments.len() / 2].to_vec();
+        if to_merge.len() > 1 {
+            store.merge_segments(&to_merge).unwrap();
+            store.purge_segments(&to_merge).unwrap();
+        }
+
+        // Delete some documents, from both merged and unmerged segments
+        for i in (0..num_docs).filter(|i| i % 7 == 3) {
+            let key = format!("doc{}", i);
+            store.remove_document_by_key(&key).unwrap();
+            docs.remove(&key);
+        }
+
+        TestCorpus {
+            store: store,
+            fields: fields,
+            docs: docs,
+        }
+    }
+
+    /// Generates a random query tree
+    pub fn random_query(&self, rng: &mut Rng, depth: u32) -> Query {
+        let choice = if depth == 0 { rng.below(3) } else { rng.below(9) };
+
+        match choice {
+            0 => {
+                let field = self.fields[rng.below(self.fields.len())];
+
+                // Occasionally pick a term that isn't in the index
+                let term = if rng.below(10) == 0 {
+                    Term::from_string("missing")
+                } else {
+                    Term::from_string(WORDS[rng.below(WORDS.len())])
+                };
+
+                Query::term(field, term)
+            }
+            1 => Query::All { score: (rng.below(4) + 1) as f32 },
+            2 => Query::None,
+            3 | 4 | 5 => {
+                let queries = (0..rng.below(4)).map(|_| self.random_query(rng, depth - 1)).collect();
+
+                match choice {
+                    3 => Query::Conjunction { queries: queries },
+                    4 => Query::Disjunction { queries: queries },
+                    _ => Query::DisjunctionMax { queries: queries, tie_breaker: 0.0 },
+                }
+            }
+            6 | 7 => {
+                let query = self.random_query(rng, depth - 1);
+                let other = self.random_query(rng, depth - 1);
+
+                if choice == 6 {
+                    query.filter(other)
+                } else {
+                    query.exclude(other)
+                }
+            }
+            _ => self.random_query(rng, depth - 1).boost((rng.below(3) + 1) as f32),
+        }
+    }
+
+    /// Works out which documents match the query without using the planner
+    pub fn naive_matches(&self, query: &Query) -> FnvHashSet<String> {
+        self.docs.iter()
+            .filter(|&(_, doc)| naive_match_doc(query, doc))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Runs the query through the planner and executor, returning the score of each
+    /// matching document
+    pub fn search(&self, query: &Query) -> FnvHashMap<String, f32> {
+        let reader = self.store.reader();
+        let mut collector = AllMatchesCollector { matches: Vec::new() };
+        reader.search(&mut collector, query).unwrap();
+
+        collector.matches.iter().map(|doc_match| {
+            let key = reader.doc_key(DocId::from_u64(doc_match.doc_id())).unwrap().unwrap();
+            (key, doc_match.score().unwrap())
+        }).collect()
+    }
+}
+
+
+/// Whether the document matches the query
+///
+/// Queries that combine zero subqueries never match, this is how the planner
+/// treats them too.
+fn naive_match_doc(query: &Query, doc: &FnvHashMap<FieldId, Vec<Term>>) -> bool {
+    match *query {
+        Query::All { .. } => true,
+        Query::None => false,
+        Query::Term { field, ref term, .. } => {
+            doc.get(&field).map(|terms| terms.contains(term)).unwrap_or(false)
+        }
+        Query::MultiTerm { .. } => panic!("naive_match_doc: MultiTerm queries aren't supported"),
+        Query::Phrase { .. } => panic!("naive_match_doc: Phrase queries aren't supported"),
+        Query::Conjunction { ref queries } => {
+            !queries.is_empty() && queries.iter().all(|query| naive_match_doc(query, doc))
+        }
+        Query::Disjunction { ref queries } | Query::DisjunctionMax { ref queries, .. } => {
+            queries.iter().any(|query| naive_match_doc(query, doc))
+        }
+        Query::BlendedTerm { ref fields, ref term, .. } => {
+            fields.iter().any(|&(field, _)| doc.get(&field).map(|terms| terms.contains(term)).unwrap_or(false))
+        }
+        Query::Filter { ref query, ref filter } => {
+            naive_match_doc(query, doc) && naive_match_doc(filter, doc)
+        }
+        Query::Exclude { ref query, ref exclude } => {
+            naive_match_doc(query, doc) && !naive_match_doc(exclude, doc)
+        }
+        Query::Join { .. } => panic!("naive_match_doc: Join queries aren't supported"),
+        Query::GeoShape { .. } => panic!("naive_match_doc: GeoShape queries aren't supported"),
+        Query::NumericRange { .. } => panic!("naive_match_doc: NumericRange queries aren't supported"),
+        Query::FeatureScore { .. } => panic!("naive_match_doc: FeatureScore queries aren't supported"),
+        Query::Runtime { .. } => panic!("naive_match_doc: Runtime queries aren't supported"),
+        Query::VectorScore { ref query, .. } | Query::RandomScore { ref query, .. } => naive_match_doc(query, doc),
+        Query::Named { ref query, .. } => naive_match_doc(query, doc),
+    }
+}
+
+
+/// Collects every match
+struct AllMatchesCollector {
+    matches: Vec<DocumentMatch>,
+}
+
+
+impl Collector for AllMatchesCollector {
+    fn needs_score(&self) -> bool {
+        true
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        self.matches.push(doc);
+    }
+}
+
+
+/// Formats a plan in a stable, human readable format for golden tests
+pub fn format_plan(plan: &SearchPlan) -> String {
+    let mut lines = Vec::new();
+
+    lines.push("boolean query:".to_string());
+    for op in plan.boolean_query.iter() {
+        lines.push(match *op {
+            BooleanQueryOp::PushEmpty => "  push_empty".to_string(),
+            BooleanQueryOp::PushPostingsList(field, term) => format!("  push_postings_list field={} term={}", field.0, term.0),
+            BooleanQueryOp::PushTermSet(field, ref terms) => format!("  push_term_set field={} terms={:?}", field.0, terms.iter().map(|term| term.0).collect::<Vec<_>>()),
+            BooleanQueryOp::PushGeoShapeMatches(field, ref terms, _, relation) => format!("  push_geo_shape_matches field={} terms={:?} relation={:?}", field.0, terms.iter().map(|term| term.0).collect::<Vec<_>>(), relation),
+            BooleanQueryOp::PushPhraseMatches(field, ref slots) => format!("  push_phrase_matches field={} slots={:?}", field.0, slots.iter().map(|terms| terms.iter().map(|term| term.0).collect::<Vec<_>>()).collect::<Vec<_>>()),
+            BooleanQueryOp::PushNumericRange(field, gte, lte) => format!("  push_numeric_range field={} gte={} lte={}", field.0, gte, lte),
+            BooleanQueryOp::PushHasValue(field) => format!("  push_has_value field={}", field.0),
+            BooleanQueryOp::PushRuntimeMatches(ref runtime) => format!("  push_runtime_matches field={} condition={:?}", runtime.field.name(), runtime.condition),
+            BooleanQueryOp::PushDeletionList => "  push_deletion_list".to_string(),
+            BooleanQueryOp::And => "  and".to_string(),
+            BooleanQueryOp::Or => "  or".to_string(),
+            BooleanQueryOp::AndNot => "  andnot".to_string(),
+        });
+    }
+    lines.push(format!("  negated={}", plan.boolean_query_is_negated));
+
+    lines.push("score function:".to_string());
+    for op in plan.score_function.iter() {
+        lines.push(match *op {
+            ScoreFunctionOp::Literal(value) => format!("  literal {}", value),
+            ScoreFunctionOp::TermScorer(field, term, ref scorer) => format!("  term_scorer field={} term={} boost={}", field.0, term.0, scorer.boost),
+            ScoreFunctionOp::CombinatorScorer(num_args, CombinatorScorer::Avg) => format!("  avg {}", num_args),
+            ScoreFunctionOp::CombinatorScorer(num_args, CombinatorScorer::Max(tie_breaker)) => format!("  max {} tie_breaker={}", num_args, tie_breaker),
+            ScoreFunctionOp::CombinatorScorer(num_args, CombinatorScorer::BlendedMax(tie_breaker)) => format!("  blended_max {} tie_breaker={}", num_args, tie_breaker),
+            ScoreFunctionOp::VectorSimilarity(field, ref vector, similarity, boost) => format!("  vector_similarity field={} vector={:?} similarity={:?} boost={}", field.0, vector, similarity, boost),
+            ScoreFunctionOp::RandomScore(seed, field, boost) => format!("  random_score seed={} field={:?} boost={}", seed, field.map(|field| field.0), boost),
+            ScoreFunctionOp::FeatureScore(field, ref field_type, ref function, boost) => format!("  feature_score field={} type={:?} function={:?} boost={}", field.0, field_type, function, boost),
+        });
+    }
+
+    let mut plan_string = lines.join("\n");
+    plan_string.push('\n');
+    plan_string
+}
+
+
+fn golden_plan_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("src/search/backends/rocksdb/search/planner/golden");
+    path.push(format!("{}.plan", name));
+    path
+}
+
+
+/// Checks a plan against the saved golden plan
+pub fn check_golden_plan(name: &str, plan: &SearchPlan) {
+    let path = golden_plan_path(name);
+    let actual = format_plan(plan);
+
+    if env::var("UPDATE_GOLDEN_PLANS").is_ok() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path).unwrap().write_all(actual.as_bytes()).unwrap();
+        return;
+    }
+
+    let mut expected = String::new();
+    File::open(&path).expect("golden plan missing, run with UPDATE_GOLDEN_PLANS=1 to create it")
+        .read_to_string(&mut expected).unwrap();
+
+    assert!(expected == actual, "plan for {:?} doesn't match {}\n\nexpected:\n{}\nactual:\n{}", name, path.display(), expected, actual);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use fnv::{FnvHashMap, FnvHashSet};
+    use search::{Term, Token, Document};
+    use search::schema::{FieldType, FIELD_INDEXED};
+    use search::query::Query;
+    use search::query::term_scorer::TermScorer;
+
+    use super::super::super::RocksDBStore;
+    use super::super::planner::plan_query;
+    use super::super::planner::boolean_query::TERM_SET_THRESHOLD;
+    use super::{Rng, TestCorpus, WORDS, check_golden_plan};
+
+    #[test]
+    fn test_executor_matches_naive_implementation() {
+        let mut rng = Rng::new(1234);
+        let corpus = TestCorpus::generate("test_indices/test_executor_matches_naive_implementation", &mut rng, 60);
+
+        for _ in 0..300 {
+            let query = corpus.random_query(&mut rng, 3);
+
+            let expected = corpus.naive_matches(&query);
+            let actual = corpus.search(&query).keys().cloned().collect::<FnvHashSet<String>>();
+
+            assert!(expected == actual, "results don't match for query {:#?}\n\nexpected: {:?}\nactual: {:?}", query, expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_scores_are_valid() {
+        let mut rng = Rng::new(5678);
+        let corpus = TestCorpus::generate("test_indices/test_scores_are_valid", &mut rng, 40);
+
+        for _ in 0..100 {
+            let query = corpus.random_query(&mut rng, 3);
+
+            for (key, score) in corpus.search(&query) {
+                assert!(score.is_finite() && score >= 0.0, "invalid score {} for {:?} with query {:#?}", score, key, query);
+            }
+        }
+    }
+
+    #[test]
+    fn test_boost_scales_scores() {
+        let mut rng = Rng::new(9012);
+        let corpus = TestCorpus::generate("test_indices/test_boost_scales_scores", &mut rng, 40);
+
+        for _ in 0..100 {
+            let query = corpus.random_query(&mut rng, 3);
+            let scores = corpus.search(&query);
+
+            let boosted_scores = corpus.search(&query_with_boost(&query, 2.0));
+            assert_eq!(scores.len(), boosted_scores.len());
+
+            for (key, score) in scores.iter() {
+                let boosted_score = boosted_scores[key];
+                assert!((boosted_score - score * 2.0).abs() <= 0.0001 * boosted_score.abs().max(1.0),
+                        "boosting by 2 changed score of {:?} from {} to {} with query {:#?}", key, score, boosted_score, query);
+            }
+        }
+    }
+
+    #[test]
+    fn test_term_set_matches_naive_implementation() {
+        let mut rng = Rng::new(3456);
+        let corpus = TestCorpus::generate("test_indices/test_term_set_matches_naive_implementation", &mut rng, 60);
+
+        for _ in 0..50 {
+            // Enough terms for the disjunction to be run as a term set, with repeats and missing terms
+            let field = corpus.fields[rng.below(corpus.fields.len())];
+            let queries = (0..TERM_SET_THRESHOLD + rng.below(8)).map(|_| {
+                match rng.below(WORDS.len() * 2) {
+                    i if i < WORDS.len() => Query::term(field, Term::from_string(WORDS[i])),
+                    _ => Query::term(field, Term::from_string("missing")),
+                }
+            }).collect();
+            let query = Query::Disjunction { queries: queries };
+
+            let expected = corpus.naive_matches(&query);
+            let actual = corpus.search(&query).keys().cloned().collect::<FnvHashSet<String>>();
+
+            assert!(expected == actual, "results don't match for query {:#?}\n\nexpected: {:?}\nactual: {:?}", query, expected, actual);
+        }
+    }
+
+    /// Copies a query and applies a boost to the copy
+    fn query_with_boost(query: &Query, boost: f32) -> Query {
+        query.clone().boost(boost)
+    }
+
+    /// Creates a store where each term is inserted in its own document so term ids are stable
+    ///
+    /// title is field 1. "hello" is term 1, "world" is term 2
+    fn make_golden_store(path: &str) -> RocksDBStore {
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for (i, word) in ["hello", "world"].iter().enumerate() {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(title_field, vec![Token { term: Term::from_string(word), position: 1 }].into());
+
+            store.insert_or_update_document(&Document {
+                key: i.to_string(),
+                boost: 1.0f32,
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                doc_values: FnvHashMap::default(),
+            }).unwrap();
+        }
+
+        store
+    }
+
+    #[test]
+    fn test_golden_plans() {
+        let store = make_golden_store("test_indices/test_golden_plans");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+        let hello = || Query::term(title_field, Term::from_string("hello"));
+        let world = || Query::term(title_field, Term::from_string("world"));
+        let missing = || Query::term(title_field, Term::from_string("missing"));
+
+        let queries = vec![
+            ("term", hello()),
+            ("all", Query::all()),
+            ("conjunction", Query::Conjunction { queries: vec![hello(), world()] }),
+            ("disjunction_with_missing_term", Query::Disjunction { queries: vec![hello(), missing()] }),
+            ("disjunction_max", Query::DisjunctionMax { queries: vec![hello(), world().boost(2.0)], tie_breaker: 0.0 }),
+            ("disjunction_max_with_tie_breaker", Query::DisjunctionMax { queries: vec![hello(), world()], tie_breaker: 0.3 }),
+            ("blended_term", Query::BlendedTerm { fields: vec![(title_field, TermScorer::default()), (body_field, TermScorer::default_with_boost(2.0))], term: Term::from_string("hello"), tie_breaker: 0.0 }),
+            ("filter_all", Query::all().filter(world())),
+            ("exclude", hello().exclude(world())),
+            ("exclude_from_all", Query::all().exclude(hello())),
+            ("term_set", Query::Disjunction { queries: (0..16).map(|i| if i % 2 == 0 { hello() } else { world() }).collect() }),
+        ];
+
+        let reader = store.reader();
+        for (name, query) in queries {
+            check_golden_plan(name, &plan_query(&reader, &query, true));
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Sets of documents that are found in two phases
+//!
+//! Some queries can't be answered from postings lists alone (eg, a geo shape query
+//! finds candidates with the geohash cells that cover the shape, but the stored
+//! shape of each candidate still needs to be checked). These produce a cheap
+//! approximation of the matching documents along with a verifier that checks one
+//! document exactly.
+//!
+//! Verification is deferred until the boolean query has been combined, so the
+//! expensive checks only run on documents that passed all of the cheap ones.
+
+use roaring::RoaringBitmap;
+
+/// Checks whether a document from an approximation really matches
+pub type Verifier<'a> = Box<Fn(u32) -> Result<bool, String> + 'a>;
+
+pub struct TwoPhaseDocSet<'a> {
+    /// A superset of the matching documents
+    approximation: RoaringBitmap,
+
+    /// None if every document in the approximation matches
+    verifier: Option<Verifier<'a>>,
+}
+
+impl<'a> TwoPhaseDocSet<'a> {
+    /// A set of documents that doesn't need verifying
+    pub fn exact(docs: RoaringBitmap) -> TwoPhaseDocSet<'a> {
+        TwoPhaseDocSet {
+            approximation: docs,
+            verifier: None,
+        }
+    }
+
+    pub fn approximate(approximation: RoaringBitmap, verifier: Verifier<'a>) -> TwoPhaseDocSet<'a> {
+        TwoPhaseDocSet {
+            approximation: approximation,
+            verifier: Some(verifier),
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.verifier.is_none()
+    }
+
+    /// Checks a document that is known to be in the approximation
+    fn verify(&self, doc: u32) -> Result<bool, String> {
+        match self.verifier {
+            Some(ref verifier) => verifier(doc),
+            None => Ok(true),
+        }
+    }
+
+    /// Checks whether a document is in the set
+    fn matches(&self, doc: u32) -> Result<bool, String> {
+        if !self.approximation.contains(doc) {
+            return Ok(false);
+        }
+
+        self.verify(doc)
+    }
+
+    pub fn intersect(self, other: TwoPhaseDocSet<'a>) -> TwoPhaseDocSet<'a> {
+        if self.is_exact() && other.is_exact() {
+            let mut docs = self.approximation;
+            docs.intersect_with(&other.approximation);
+            return TwoPhaseDocSet::exact(docs);
+        }
+
+        let mut approximation = self.approximation;
+        approximation.intersect_with(&other.approximation);
+
+        // Documents in the intersection are in both approximations, so only the verifiers need checking
+        let verifiers = vec![self.verifier, other.verifier].into_iter().filter_map(|verifier| verifier).collect::<Vec<_>>();
+        TwoPhaseDocSet::approximate(approximation, Box::new(move |doc: u32| {
+            for verifier in verifiers.iter() {
+                if !try!(verifier(doc)) {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        }))
+    }
+
+    pub fn union(self, other: TwoPhaseDocSet<'a>) -> TwoPhaseDocSet<'a> {
+        if self.is_exact() && other.is_exact() {
+            let mut docs = self.approximation;
+            docs.union_with(&other.approximation);
+            return TwoPhaseDocSet::exact(docs);
+        }
+
+        // The approximations are kept by the verifier, to check which side a document came from
+        let mut approximation = self.approximation.clone();
+        approximation.union_with(&other.approximation);
+
+        TwoPhaseDocSet::approximate(approximation, Box::new(move |doc: u32| {
+            Ok(try!(self.matches(doc)) || try!(other.matches(doc)))
+        }))
+    }
+
+    pub fn difference(self, other: TwoPhaseDocSet<'a>) -> TwoPhaseDocSet<'a> {
+        let TwoPhaseDocSet { mut approximation, verifier } = self;
+
+        if other.is_exact() {
+            // The excluded documents are known exactly so they can be removed up front
+            approximation.difference_with(&other.approximation);
+
+            return TwoPhaseDocSet {
+                approximation: approximation,
+                verifier: verifier,
+            };
+        }
+
+        // A document is only excluded once it's been verified to match the other set
+        TwoPhaseDocSet::approximate(approximation, Box::new(move |doc: u32| {
+            let is_match = match verifier {
+                Some(ref verifier) => try!(verifier(doc)),
+                None => true,
+            };
+
+            Ok(is_match && !try!(other.matches(doc)))
+        }))
+    }
+
+    /// Verifies each document in the approximation, returning the ones that match
+    pub fn into_bitmap(self) -> Result<RoaringBitmap, String> {
+        let verifier = match self.verifier {
+            Some(verifier) => verifier,
+            None => return Ok(self.approximation),
+        };
+
+        let mut docs = RoaringBitmap::new();
+        for doc in self.approximation.iter() {
+            if try!(verifier(doc)) {
+                docs.insert(doc);
+            }
+        }
+
+        Ok(docs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use roaring::RoaringBitmap;
+
+    use super::TwoPhaseDocSet;
+
+    fn bitmap(docs: &[u32]) -> RoaringBitmap {
+        let mut bitmap = RoaringBitmap::new();
+        for doc in docs.iter() {
+            bitmap.insert(*doc);
+        }
+        bitmap
+    }
+
+    fn docs(doc_set: TwoPhaseDocSet) -> Vec<u32> {
+        doc_set.into_bitmap().unwrap().iter().collect()
+    }
+
+    #[test]
+    fn test_exact_sets_stay_exact() {
+        let doc_set = TwoPhaseDocSet::exact(bitmap(&[1, 2, 3]))
+            .intersect(TwoPhaseDocSet::exact(bitmap(&[2, 3, 4])))
+            .union(TwoPhaseDocSet::exact(bitmap(&[5])));
+
+        assert!(doc_set.is_exact());
+        assert_eq!(docs(doc_set), vec![2, 3, 5]);
+    }
+
+    #[test]
+    fn test_only_candidates_are_verified() {
+        let verified = RefCell::new(Vec::new());
+
+        {
+            // Only even documents really match
+            let even = TwoPhaseDocSet::approximate(bitmap(&[1, 2, 3, 4, 5, 6]), Box::new(|doc: u32| {
+                verified.borrow_mut().push(doc);
+                Ok(doc % 2 == 0)
+            }));
+
+            let doc_set = even.intersect(TwoPhaseDocSet::exact(bitmap(&[3, 4, 6, 7])));
+            assert_eq!(docs(doc_set), vec![4, 6]);
+        }
+
+        // Documents that didn't match the other side of the conjunction weren't checked
+        assert_eq!(*verified.borrow(), vec![3, 4, 6]);
+    }
+
+    #[test]
+    fn test_union() {
+        let even = TwoPhaseDocSet::approximate(bitmap(&[1, 2, 3, 4]), Box::new(|doc: u32| Ok(doc % 2 == 0)));
+        let doc_set = even.union(TwoPhaseDocSet::exact(bitmap(&[3, 7])));
+
+        assert_eq!(docs(doc_set), vec![2, 3, 4, 7]);
+    }
+
+    #[test]
+    fn test_difference() {
+        let even = || TwoPhaseDocSet::approximate(bitmap(&[1, 2, 3, 4]), Box::new(|doc: u32| Ok(doc % 2 == 0)));
+
+        // Excluding an approximation only removes the documents that are verified
+        let doc_set = TwoPhaseDocSet::exact(bitmap(&[1, 2, 3, 4, 5])).difference(even());
+        assert_eq!(docs(doc_set), vec![1, 3, 5]);
+
+        let doc_set = even().difference(TwoPhaseDocSet::exact(bitmap(&[4])));
+        assert_eq!(docs(doc_set), vec![2]);
+    }
+
+    #[test]
+    fn test_verifier_error() {
+        let doc_set = TwoPhaseDocSet::approximate(bitmap(&[1]), Box::new(|_: u32| Err("failed".to_string())));
+
+        assert_eq!(doc_set.into_bitmap().err(), Some("failed".to_string()));
+    }
+}
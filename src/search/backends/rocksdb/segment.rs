@@ -1,6 +1,8 @@
 use std::io::Cursor;
 
+use rocksdb::{self, DBVector};
 use search::segment::{SegmentId, Segment};
+use search::statistic_key::StatisticKey;
 use search::schema::FieldId;
 use search::term::TermId;
 use roaring::RoaringBitmap;
@@ -8,7 +10,13 @@ use byteorder::{ByteOrder, LittleEndian};
 
 use super::RocksDBReader;
 use super::key_builder::KeyBuilder;
+use super::column_families;
+use super::document_index::decode_deletion_list;
 
+/// A segment, as seen by a reader
+///
+/// All data is read from the reader's snapshot so it stays consistent for as
+/// long as the reader is alive, even if the segment is merged away in the meantime.
 pub struct RocksDBSegment<'a> {
     reader: &'a RocksDBReader<'a>,
     id: u32,
@@ -21,6 +29,54 @@ impl<'a> RocksDBSegment<'a> {
             id: id,
         }
     }
+
+    /// Reads a statistic of this segment (eg, StatisticKey::TotalDocs)
+    pub fn statistic(&self, stat_key: &StatisticKey) -> Result<Option<i64>, rocksdb::Error> {
+        let kb = KeyBuilder::segment_stat(self.id, stat_key);
+        let cf = column_families::handle(&self.reader.store.db, column_families::STATS);
+        let val = try!(self.reader.snapshot.get_cf(cf, &kb.key())).map(|val| LittleEndian::read_i64(&val));
+        Ok(val)
+    }
+
+    /// Reads a raw value stored against a document in this segment
+    ///
+    /// Stored field values use the value type "val". The value is returned as it
+    /// is in RocksDB so it is not copied.
+    pub fn stored_value_raw(&self, doc_local_id: u16, field_id: FieldId, value_type: &[u8]) -> Result<Option<DBVector>, rocksdb::Error> {
+        let kb = KeyBuilder::stored_field_value(self.id, doc_local_id, field_id.0, value_type);
+        let cf = column_families::handle(&self.reader.store.db, column_families::STORED);
+        self.reader.snapshot.get_cf(cf, &kb.key())
+    }
+
+    /// Loads the set of documents in this segment that contain the term in the field
+    pub fn postings(&self, field_id: FieldId, term_id: TermId) -> Result<Option<RoaringBitmap>, rocksdb::Error> {
+        let kb = KeyBuilder::segment_postings_list(self.id, field_id.0, term_id.0);
+        let cf = column_families::handle(&self.reader.store.db, column_families::POSTINGS);
+        let doc_id_set = try!(self.reader.snapshot.get_cf(cf, &kb.key())).map(|doc_id_set| RoaringBitmap::deserialize_from(Cursor::new(&doc_id_set[..])).unwrap());
+        Ok(doc_id_set)
+    }
+
+    /// Loads the set of documents in this segment that have been deleted
+    pub fn deleted_docs(&self) -> Result<Option<RoaringBitmap>, rocksdb::Error> {
+        let kb = KeyBuilder::segment_del_list(self.id);
+        let doc_id_set = try!(self.reader.snapshot.get(&kb.key())).map(|doc_id_set| decode_deletion_list(&doc_id_set));
+        Ok(doc_id_set)
+    }
+
+    /// Loads the set of documents in this segment that haven't been deleted
+    pub fn live_docs(&self) -> Result<RoaringBitmap, rocksdb::Error> {
+        let total_docs = try!(self.statistic(&StatisticKey::TotalDocs)).unwrap_or(0);
+        let mut live_docs = RoaringBitmap::new();
+        for doc_id in 0..total_docs {
+            live_docs.insert(doc_id as u32);
+        }
+
+        if let Some(deleted_docs) = try!(self.deleted_docs()) {
+            live_docs.difference_with(&deleted_docs);
+        }
+
+        Ok(live_docs)
+    }
 }
 
 impl<'a> Segment for RocksDBSegment<'a> {
@@ -28,27 +84,24 @@ impl<'a> Segment for RocksDBSegment<'a> {
         SegmentId(self.id)
     }
 
-    fn load_statistic(&self, stat_name: &[u8]) -> Result<Option<i64>, String> {
-        let kb = KeyBuilder::segment_stat(self.id, stat_name);
-        let val = try!(self.reader.snapshot.get(&kb.key())).map(|val| LittleEndian::read_i64(&val));
-        Ok(val)
+    fn load_statistic(&self, stat_key: &StatisticKey) -> Result<Option<i64>, String> {
+        Ok(try!(self.statistic(stat_key)))
     }
 
     fn load_stored_field_value_raw(&self, doc_local_id: u16, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
-        let kb = KeyBuilder::stored_field_value(self.id, doc_local_id, field_id.0, value_type);
-        let val = try!(self.reader.snapshot.get(&kb.key()));
+        let val = try!(self.stored_value_raw(doc_local_id, field_id, value_type));
         Ok(val.map(|v| v.to_vec()))
     }
 
     fn load_postings_list(&self, field_id: FieldId, term_id: TermId) -> Result<Option<RoaringBitmap>, String> {
-        let kb = KeyBuilder::segment_postings_list(self.id, field_id.0, term_id.0);
-        let doc_id_set = try!(self.reader.snapshot.get(&kb.key())).map(|doc_id_set| RoaringBitmap::deserialize_from(Cursor::new(&doc_id_set[..])).unwrap());
-        Ok(doc_id_set)
+        Ok(try!(self.postings(field_id, term_id)))
     }
 
     fn load_deletion_list(&self) -> Result<Option<RoaringBitmap>, String> {
-        let kb = KeyBuilder::segment_del_list(self.id);
-        let doc_id_set = try!(self.reader.snapshot.get(&kb.key())).map(|doc_id_set| RoaringBitmap::deserialize_from(Cursor::new(&doc_id_set[..])).unwrap());
-        Ok(doc_id_set)
+        Ok(try!(self.deleted_docs()))
+    }
+
+    fn load_doc_key(&self, doc_local_id: u16) -> Result<Option<Vec<u8>>, String> {
+        Ok(try!(self.reader.store.document_index.find_key_by_doc_id(&self.reader.store.db, &self.reader.snapshot, self.doc_id(doc_local_id))))
     }
 }
@@ -3,11 +3,14 @@ use std::collections::HashMap;
 use search::{Document, Term, TermId};
 use search::schema::FieldId;
 use search::segment::{SegmentId, Segment};
+use search::statistic_key::StatisticKey;
+use search::positions::encode_positions;
 use byteorder::{LittleEndian, WriteBytesExt};
 use roaring::RoaringBitmap;
 use fnv::FnvHashMap;
 
-use super::key_builder::KeyBuilder;
+use super::numeric_blocks::{self, NumericBlock};
+
 
 #[derive(Debug)]
 pub struct SegmentBuilder {
@@ -15,8 +18,9 @@ pub struct SegmentBuilder {
     pub term_dictionary: HashMap<Term, TermId>,
     current_term_id: u32,
     pub postings_lists: FnvHashMap<(FieldId, TermId), RoaringBitmap>,
-    pub statistics: FnvHashMap<Vec<u8>, i64>,
+    pub statistics: FnvHashMap<StatisticKey, i64>,
     pub stored_field_values: FnvHashMap<(FieldId, u16, Vec<u8>), Vec<u8>>,
+    numeric_blocks: FnvHashMap<(FieldId, u16), NumericBlock>,
 }
 
 #[derive(Debug)]
@@ -34,6 +38,7 @@ impl SegmentBuilder {
             postings_lists: FnvHashMap::default(),
             statistics: FnvHashMap::default(),
             stored_field_values: FnvHashMap::default(),
+            numeric_blocks: FnvHashMap::default(),
         }
     }
 
@@ -88,9 +93,17 @@ impl SegmentBuilder {
                     self.stored_field_values.insert((*field_id, doc_id, value_type), frequency_bytes);
                 }
 
+                // Write positions
+                {
+                    let mut value_type = vec![b'p', b'o', b's'];
+                    value_type.extend(term_id.0.to_string().as_bytes());
+
+                    let positions = positions.iter().collect::<Vec<u32>>();
+                    self.stored_field_values.insert((*field_id, doc_id, value_type), encode_positions(&positions));
+                }
+
                 // Increment term document frequency
-                let stat_name = KeyBuilder::segment_stat_term_doc_frequency_stat_name(field_id.0, term_id.0);
-                let stat = self.statistics.entry(stat_name).or_insert(0);
+                let stat = self.statistics.entry(StatisticKey::TermDocumentFrequency(*field_id, term_id)).or_insert(0);
                 *stat += 1;
             }
 
@@ -104,15 +117,13 @@ impl SegmentBuilder {
 
             // Increment total field docs
             {
-                let stat_name = KeyBuilder::segment_stat_total_field_docs_stat_name(field_id.0);
-                let stat = self.statistics.entry(stat_name).or_insert(0);
+                let stat = self.statistics.entry(StatisticKey::TotalFieldDocs(*field_id)).or_insert(0);
                 *stat += 1;
             }
 
             // Increment total field tokens
             {
-                let stat_name = KeyBuilder::segment_stat_total_field_tokens_stat_name(field_id.0);
-                let stat = self.statistics.entry(stat_name).or_insert(0);
+                let stat = self.statistics.entry(StatisticKey::TotalFieldTokens(*field_id)).or_insert(0);
                 *stat += field_token_count as i64;
             }
         }
@@ -122,9 +133,56 @@ impl SegmentBuilder {
             self.stored_field_values.insert((*field, doc_id, b"val".to_vec()), value.to_bytes());
         }
 
+        // Insert doc values
+        // Each value is prefixed with its length
+        for (field, values) in doc.doc_values.iter() {
+            let mut bytes = Vec::new();
+
+            for value in values.iter() {
+                let value_bytes = value.to_bytes();
+                bytes.write_u32::<LittleEndian>(value_bytes.len() as u32).unwrap();
+                bytes.extend(value_bytes);
+            }
+
+            self.stored_field_values.insert((*field, doc_id, b"dv".to_vec()), bytes);
+        }
+
+        // Update the min/max metadata of the document's block for integer and date fields
+        // Lets range queries skip blocks that can't match. Like field data, this uses the
+        // doc values of a field if it has any, otherwise the stored value
+        let mut numeric_values = doc.doc_values.iter().map(|(field, values)| {
+            (*field, values.iter().filter_map(numeric_blocks::numeric_value).collect::<Vec<_>>())
+        }).collect::<Vec<_>>();
+        for (field, value) in doc.stored_fields.iter() {
+            if !doc.doc_values.contains_key(field) {
+                numeric_values.push((*field, numeric_blocks::numeric_value(value).into_iter().collect()));
+            }
+        }
+
+        for (field, values) in numeric_values {
+            if values.is_empty() {
+                continue;
+            }
+
+            let block_start = numeric_blocks::block_start(doc_id);
+            let block = self.numeric_blocks.entry((field, block_start)).or_insert_with(NumericBlock::empty);
+            block.add_doc(&values);
+            self.stored_field_values.insert((field, block_start, numeric_blocks::VALUE_TYPE.to_vec()), block.to_bytes());
+        }
+
+        // Document boost
+        // This isn't attached to any field so it's stored against field 0. Most documents
+        // aren't boosted, so we interpret a missing value as a boost of 1.0 at search time
+        if doc.boost != 1.0f32 {
+            let mut boost_bytes: Vec<u8> = Vec::new();
+            boost_bytes.write_f32::<LittleEndian>(doc.boost).unwrap();
+
+            self.stored_field_values.insert((FieldId(0), doc_id, b"boost".to_vec()), boost_bytes);
+        }
+
         // Increment total docs
         {
-            let stat = self.statistics.entry(b"total_docs".to_vec()).or_insert(0);
+            let stat = self.statistics.entry(StatisticKey::TotalDocs).or_insert(0);
             *stat += 1;
         }
 
@@ -137,8 +195,8 @@ impl Segment for SegmentBuilder {
         SegmentId(0)
     }
 
-    fn load_statistic(&self, stat_name: &[u8]) -> Result<Option<i64>, String> {
-        Ok(self.statistics.get(stat_name).cloned())
+    fn load_statistic(&self, stat_key: &StatisticKey) -> Result<Option<i64>, String> {
+        Ok(self.statistics.get(stat_key).cloned())
     }
 
     fn load_stored_field_value_raw(&self, doc_local_id: u16, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
@@ -152,4 +210,9 @@ impl Segment for SegmentBuilder {
     fn load_deletion_list(&self) -> Result<Option<RoaringBitmap>, String> {
         Ok(None)
     }
+
+    fn load_doc_key(&self, _doc_local_id: u16) -> Result<Option<Vec<u8>>, String> {
+        // Keys are added to the document index when the segment is written
+        Ok(None)
+    }
 }
@@ -1,4 +1,6 @@
 use std::str;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use rocksdb::{self, DB, DBRawIterator};
@@ -6,6 +8,22 @@ use rocksdb::{self, DB, DBRawIterator};
 use super::RocksDBReader;
 use super::segment::RocksDBSegment;
 
+/// Keeps track of open readers so segments aren't purged while they can still be read
+///
+/// Each reader is registered with the current generation, which is advanced whenever
+/// segments are deactivated. A reader registered after the segments were deactivated
+/// can't see them, so they can be purged once all older readers have been dropped.
+#[derive(Debug, Default)]
+struct ReaderTracker {
+    generation: u64,
+
+    /// The number of open readers registered in each generation
+    open_readers: BTreeMap<u64, usize>,
+
+    /// Segments waiting to be purged, with the generation they were deferred in
+    deferred_purges: Vec<(u64, Vec<u32>)>,
+}
+
 /// Manages "segments" within the index
 ///
 /// The index is partitioned into immutable segments. This manager is responsible
@@ -13,6 +31,7 @@ use super::segment::RocksDBSegment;
 /// controlling routine tasks such as merging and vacuuming
 pub struct SegmentManager {
     next_segment: AtomicUsize,
+    readers: Mutex<ReaderTracker>,
 }
 
 impl SegmentManager {
@@ -24,6 +43,7 @@ impl SegmentManager {
 
         Ok(SegmentManager {
             next_segment: AtomicUsize::new(1),
+            readers: Mutex::new(ReaderTracker::default()),
         })
     }
 
@@ -38,6 +58,7 @@ impl SegmentManager {
 
         Ok(SegmentManager {
             next_segment: AtomicUsize::new(next_segment as usize),
+            readers: Mutex::new(ReaderTracker::default()),
         })
     }
 
@@ -48,6 +69,86 @@ impl SegmentManager {
         Ok(next_segment)
     }
 
+    /// Records that a reader has been opened, returns the generation it was registered in
+    ///
+    /// This must be called before the reader's snapshot is taken.
+    pub fn register_reader(&self) -> u64 {
+        let mut readers = self.readers.lock().unwrap();
+        let generation = readers.generation;
+        *readers.open_readers.entry(generation).or_insert(0) += 1;
+        generation
+    }
+
+    /// Records that a reader has been dropped
+    pub fn unregister_reader(&self, generation: u64) {
+        let mut readers = self.readers.lock().unwrap();
+        let remove = match readers.open_readers.get_mut(&generation) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+
+        if remove {
+            readers.open_readers.remove(&generation);
+        }
+    }
+
+    /// Starts a new generation, must be called after segments are deactivated
+    pub fn advance_generation(&self) {
+        self.readers.lock().unwrap().generation += 1;
+    }
+
+    /// Defers purging the segments if a reader that could still see them is open
+    ///
+    /// Returns true if the purge was deferred, the segments will be returned by
+    /// `take_purgeable_segments` once those readers have been dropped.
+    pub fn defer_purge(&self, segments: &[u32]) -> bool {
+        let mut readers = self.readers.lock().unwrap();
+        let generation = readers.generation;
+
+        let in_use = match readers.open_readers.keys().next() {
+            Some(oldest_generation) => *oldest_generation < generation,
+            None => false,
+        };
+
+        if in_use {
+            readers.deferred_purges.push((generation, segments.to_vec()));
+        }
+
+        in_use
+    }
+
+    /// Takes the deferred purges that no open reader can see any more
+    pub fn take_purgeable_segments(&self) -> Vec<Vec<u32>> {
+        let mut readers = self.readers.lock().unwrap();
+        let oldest_generation = readers.open_readers.keys().next().cloned();
+
+        let (purgeable, deferred) = readers.deferred_purges.drain(..).partition::<Vec<_>, _>(|&(generation, _)| {
+            oldest_generation.map(|oldest_generation| oldest_generation >= generation).unwrap_or(true)
+        });
+        readers.deferred_purges = deferred;
+
+        purgeable.into_iter().map(|(_, segments)| segments).collect()
+    }
+
+    /// Puts purges that were taken by `take_purgeable_segments` but not run back in the queue
+    ///
+    /// No reader could see these segments when they were taken, so they're queued
+    /// with the first generation to be returned by the next call.
+    pub fn requeue_purges(&self, purges: Vec<Vec<u32>>) {
+        let mut readers = self.readers.lock().unwrap();
+        let requeued = purges.into_iter().map(|segments| (0, segments));
+        let deferred = requeued.chain(readers.deferred_purges.drain(..)).collect();
+        readers.deferred_purges = deferred;
+    }
+
+    /// The number of purges waiting for readers to be dropped
+    pub fn deferred_purge_count(&self) -> usize {
+        self.readers.lock().unwrap().deferred_purges.len()
+    }
+
     /// Iterates currently active segments
     pub fn iter_active<'a>(&self, reader: &'a RocksDBReader) -> ActiveSegmentsIterator<'a> {
         let mut iter = reader.snapshot.raw_iterator();
@@ -5,11 +5,16 @@ use rocksdb::{self, WriteBatch, WriteOptions};
 use roaring::RoaringBitmap;
 use search::document::DocId;
 use search::segment::SegmentId;
+use search::schema::{FieldId, FieldType};
+use search::statistic_key::StatisticKey;
 use byteorder::{ByteOrder, LittleEndian};
 use fnv::{FnvHashMap, FnvHashSet};
 
 use super::RocksDBStore;
 use super::key_builder::KeyBuilder;
+use super::column_families;
+use super::numeric_blocks::{self, NumericBlock};
+use super::merge_journal::{self, MergeState};
 
 #[derive(Debug)]
 pub enum SegmentMergeError {
@@ -32,15 +37,24 @@ impl From<SegmentMergeError> for String {
     }
 }
 
+/// Splits a statistic key "s1/total_docs" into the segment id and the statistic name (1, b"total_docs")
+fn parse_statistic_key(key: &[u8]) -> (u32, &[u8]) {
+    let mut parts_iter = key[1..].splitn(2, |b| *b == b'/');
+    let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
+    let statistic_name = parts_iter.next().unwrap_or(&[]);
+
+    (segment, statistic_name)
+}
+
 impl RocksDBStore {
     fn merge_segment_data(&self, source_segments: &Vec<u32>, dest_segment: u32, doc_id_mapping: &FnvHashMap<DocId, u16>) -> Result<(), SegmentMergeError> {
         // Put source_segments in a FnvHashSet as this is much faster for performing contains queries against
         let source_segments_btree = source_segments.iter().collect::<FnvHashSet<_>>();
 
-        // Since we're merging existing data, there's no need to recover if it crashes half way through
+        // The data is written to the WAL without syncing, it's synced along with the commit. If
+        // this crashes half way through, the merge journal is used to delete what was written
         let mut write_options = WriteOptions::default();
         write_options.set_sync(false);
-        write_options.disable_wal(true);
 
         // Merge the term directories
         // The postings lists keys are ordered to be most convenient for retrieving all the segments
@@ -57,7 +71,8 @@ impl RocksDBStore {
         let mut current_td_key: Option<(u32, u32)> = None;
         let mut current_td = RoaringBitmap::new();
 
-        let mut iter = self.db.raw_iterator();
+        let postings_cf = column_families::handle(&self.db, column_families::POSTINGS);
+        let mut iter = try!(self.db.raw_iterator_cf(postings_cf));
         iter.seek(b"d");
         while iter.valid() {
             let k = iter.key().unwrap();
@@ -77,7 +92,7 @@ impl RocksDBStore {
                         current_td.serialize_into(&mut current_td_vec).unwrap();
 
                         let kb = KeyBuilder::segment_postings_list(dest_segment, field, term);
-                        try!(self.db.put_opt(&kb.key(), &current_td_vec, &write_options));
+                        try!(self.db.put_cf_opt(postings_cf, &kb.key(), &current_td_vec, &write_options));
                         current_td.clear();
                     }
 
@@ -102,7 +117,7 @@ impl RocksDBStore {
             current_td.serialize_into(&mut current_td_vec).unwrap();
 
             let kb = KeyBuilder::segment_postings_list(dest_segment, field, term);
-            try!(self.db.put_opt(&kb.key(), &current_td_vec, &write_options));
+            try!(self.db.put_cf_opt(postings_cf, &kb.key(), &current_td_vec, &write_options));
             current_td.clear();
         }
 
@@ -123,9 +138,14 @@ impl RocksDBStore {
             (segment, doc_id, field_id, value_type)
         }
 
+        // The values of integer and date fields in the new segment, to rebuild their block
+        // metadata from (the blocks are different after remapping)
+        let mut numeric_values: FnvHashMap<(FieldId, u16), Vec<i64>> = FnvHashMap::default();
+
+        let stored_cf = column_families::handle(&self.db, column_families::STORED);
         for source_segment in source_segments.iter() {
             let kb = KeyBuilder::segment_stored_values_prefix(*source_segment);
-            let mut iter = self.db.raw_iterator();
+            let mut iter = try!(self.db.raw_iterator_cf(stored_cf));
             iter.seek(&kb.key());
             while iter.valid() {
                 let k = iter.key().unwrap();
@@ -142,37 +162,62 @@ impl RocksDBStore {
                     break;
                 }
 
+                if value_type == numeric_blocks::VALUE_TYPE {
+                    // Block metadata is rebuilt below
+                    iter.next();
+                    continue;
+                }
+
                 // Remap doc id
                 let doc_id = DocId(SegmentId(segment), doc_id as u16);
                 let new_doc_id = doc_id_mapping.get(&doc_id).unwrap();
 
                 // Write value into new segment
                 let kb = KeyBuilder::stored_field_value(dest_segment, *new_doc_id, field, &value_type);
-                try!(self.db.put_opt(&kb.key(), unsafe { &iter.value_inner().unwrap() }, &write_options));
+                try!(self.db.put_cf_opt(stored_cf, &kb.key(), unsafe { &iter.value_inner().unwrap() }, &write_options));
+
+                // Keep the values of integer and date fields, doc values take priority over the stored value
+                let is_numeric = match self.schema.get(&FieldId(field)).map(|field_info| &field_info.field_type) {
+                    Some(&FieldType::I64) | Some(&FieldType::DateTime) => true,
+                    _ => false,
+                };
+
+                if is_numeric && value_type == b"dv" {
+                    let values = numeric_blocks::decode_numeric_doc_values(unsafe { &iter.value_inner().unwrap() });
+                    numeric_values.insert((FieldId(field), *new_doc_id), values);
+                } else if is_numeric && value_type == b"val" {
+                    let values = numeric_blocks::decode_numeric_value(unsafe { &iter.value_inner().unwrap() }).into_iter().collect();
+                    numeric_values.entry((FieldId(field), *new_doc_id)).or_insert(values);
+                }
 
                 iter.next();
             }
         }
 
+        // Write the block metadata of integer and date fields
+        let mut blocks = FnvHashMap::default();
+        for (&(field, doc_id), values) in numeric_values.iter() {
+            blocks.entry((field, numeric_blocks::block_start(doc_id))).or_insert_with(NumericBlock::empty).add_doc(values);
+        }
+
+        for (&(field, block_start), block) in blocks.iter() {
+            if block.doc_count > 0 {
+                let kb = KeyBuilder::stored_field_value(dest_segment, block_start, field.0, numeric_blocks::VALUE_TYPE);
+                try!(self.db.put_cf_opt(stored_cf, &kb.key(), &block.to_bytes(), &write_options));
+            }
+        }
+
         // Merge the statistics
         // Like stored values, these start with segment ids. But instead of just rewriting the
         // key, we need to sum up all the statistics across the segments being merged.
 
         let mut statistics = FnvHashMap::default();
 
-        /// Converts statistic key strings "s1/total_docs" into tuples of 1 i32 and a Vec<u8> (1, ['t', 'o', 't', ...])
-        fn parse_statistic_key(key: &[u8]) -> (u32, Vec<u8>) {
-            let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let statistic_name = parts_iter.next().unwrap().to_vec();
-
-            (segment, statistic_name)
-        }
-
         // Fetch and merge statistics
+        let stats_cf = column_families::handle(&self.db, column_families::STATS);
         for source_segment in source_segments.iter() {
             let kb = KeyBuilder::segment_stat_prefix(*source_segment);
-            let mut iter = self.db.raw_iterator();
+            let mut iter = try!(self.db.raw_iterator_cf(stats_cf));
             iter.seek(&kb.key());
             while iter.valid() {
                 let k = iter.key().unwrap();
@@ -189,8 +234,16 @@ impl RocksDBStore {
                     break;
                 }
 
+                // Statistics with unrecognised names are dropped, nothing can read them
+                let stat_key = match StatisticKey::decode(statistic_name) {
+                    Some(stat_key) => stat_key,
+                    None => {
+                        iter.next();
+                        continue;
+                    }
+                };
 
-                let stat = statistics.entry(statistic_name).or_insert(0);
+                let stat = statistics.entry(stat_key).or_insert(0);
                 *stat += LittleEndian::read_i64(unsafe { &iter.value_inner().unwrap() });
 
                 iter.next();
@@ -198,11 +251,11 @@ impl RocksDBStore {
         }
 
         // Write merged statistics to new segment
-        for (stat_name, stat_value) in statistics {
-            let kb = KeyBuilder::segment_stat(dest_segment, &stat_name);
+        for (stat_key, stat_value) in statistics {
+            let kb = KeyBuilder::segment_stat(dest_segment, &stat_key);
             let mut val_bytes = [0; 8];
             LittleEndian::write_i64(&mut val_bytes, stat_value);
-            try!(self.db.put_opt(&kb.key(), &val_bytes, &write_options));
+            try!(self.db.put_cf_opt(stats_cf, &kb.key(), &val_bytes, &write_options));
         }
 
         // Note: Don't merge the deletion lists
@@ -227,6 +280,9 @@ impl RocksDBStore {
             try!(write_batch.delete(&kb.key()));
         }
 
+        // The source segments must be purged, even if this crashes before that's done
+        try!(merge_journal::record_committed(&mut write_batch, dest_segment, source_segments));
+
         // Update document index and commit
         // This will write the write batch
         try!(self.document_index.commit_segment_merge(&self.db, write_batch, source_segments, dest_segment, doc_id_mapping));
@@ -248,8 +304,8 @@ impl RocksDBStore {
         let mut current_doc_id: u32 = 0;
 
         for source_segment in source_segments.iter() {
-            let kb = KeyBuilder::segment_stat(*source_segment, b"total_docs");
-            let total_docs = match try!(self.db.get(&kb.key())) {
+            let kb = KeyBuilder::segment_stat(*source_segment, &StatisticKey::TotalDocs);
+            let total_docs = match try!(self.db.get_cf(column_families::handle(&self.db, column_families::STATS), &kb.key())) {
                 Some(total_docs_bytes) => {
                     LittleEndian::read_i64(&total_docs_bytes)
                 }
@@ -267,13 +323,17 @@ impl RocksDBStore {
             }
         }
 
+        // Record the merge before anything is written, so the new segment can be cleaned up
+        // if this crashes half way through
+        try!(merge_journal::record_merging(&self.db, dest_segment, source_segments));
+
         // Merge segment data
         // Most of the heavy lifting happens here. This merges all the immutable parts of
         // the segment (which is everything but the deletion list). It does not activate the
         // segment.
         // This means that nothing bad will happen if it crashes half way through -- the
-        // worst that could happen is we're left with a partially-written segment that we
-        // have to clean up.
+        // worst that could happen is we're left with a partially-written segment, which is
+        // deleted by `recover_merges` when the store is next opened.
         try!(self.merge_segment_data(&source_segments, dest_segment, &doc_id_mapping));
 
         // Commit the merge
@@ -285,16 +345,63 @@ impl RocksDBStore {
         // the new segment).
         try!(self.commit_segment_merge(&source_segments, dest_segment, &doc_id_mapping));
 
+        // Readers created from now on can't see the source segments
+        self.segments.advance_generation();
+
+        // The merge corrects the statistics of deleted documents, which changes scores
+        self.bump_epoch();
+
         Ok(dest_segment)
     }
 
+    /// Deletes the data of segments that have been merged into another segment
+    ///
+    /// If a reader that was created before the segments were deactivated is still
+    /// open, the purge is deferred until `purge_deferred_segments` is called after
+    /// it has been dropped.
     pub fn purge_segments(&self, segments: &Vec<u32>) -> Result<(), rocksdb::Error> {
+        if self.segments.defer_purge(segments) {
+            return Ok(());
+        }
+
+        self.purge_segments_unchecked(segments)
+    }
+
+    /// Purges the deferred segments that can no longer be read, returns how many purges were run
+    ///
+    /// If a purge fails, it and the purges after it are put back in the queue so they
+    /// are retried by the next call.
+    pub fn purge_deferred_segments(&self) -> Result<usize, rocksdb::Error> {
+        let mut purgeable = self.segments.take_purgeable_segments().into_iter();
+        let mut purged = 0;
+
+        while let Some(segments) = purgeable.next() {
+            if let Err(e) = self.purge_segments_unchecked(&segments) {
+                let mut unpurged = vec![segments];
+                unpurged.extend(purgeable);
+                self.segments.requeue_purges(unpurged);
+                return Err(e);
+            }
+
+            purged += 1;
+        }
+
+        Ok(purged)
+    }
+
+    /// The number of purges waiting for readers to be dropped
+    pub fn deferred_purge_count(&self) -> usize {
+        self.segments.deferred_purge_count()
+    }
+
+    fn purge_segments_unchecked(&self, segments: &Vec<u32>) -> Result<(), rocksdb::Error> {
         // Put segments in a FnvHashSet as this is much faster for performing contains queries against
         let segments_btree = segments.iter().collect::<FnvHashSet<_>>();
 
+        // The deletes go through the WAL so they can't be lost after the journal entry of
+        // the merge is removed
         let mut write_options = WriteOptions::default();
         write_options.set_sync(false);
-        write_options.disable_wal(true);
 
         // Purge term directories
 
@@ -304,7 +411,8 @@ impl RocksDBStore {
             (nums_iter.next().unwrap(), nums_iter.next().unwrap(), nums_iter.next().unwrap())
         }
 
-        let mut iter = self.db.raw_iterator();
+        let postings_cf = column_families::handle(&self.db, column_families::POSTINGS);
+        let mut iter = try!(self.db.raw_iterator_cf(postings_cf));
         iter.seek(b"d");
         while iter.valid() {
             let k = iter.key().unwrap();
@@ -317,7 +425,7 @@ impl RocksDBStore {
             let (_, _, segment) = parse_postings_list_key(&k);
 
             if segments_btree.contains(&segment) {
-                try!(self.db.delete(&k));
+                try!(self.db.delete_cf(postings_cf, &k));
             }
 
             iter.next();
@@ -336,9 +444,10 @@ impl RocksDBStore {
             (segment, doc_id, field_id, value_type)
         }
 
+        let stored_cf = column_families::handle(&self.db, column_families::STORED);
         for source_segment in segments.iter() {
             let kb = KeyBuilder::segment_stored_values_prefix(*source_segment);
-            let mut iter = self.db.raw_iterator();
+            let mut iter = try!(self.db.raw_iterator_cf(stored_cf));
             iter.seek(&kb.key());
             while iter.valid() {
                 let k = iter.key().unwrap();
@@ -355,7 +464,7 @@ impl RocksDBStore {
                     break;
                 }
 
-                try!(self.db.delete_opt(&k, &write_options));
+                try!(self.db.delete_cf_opt(stored_cf, &k, &write_options));
 
                 iter.next();
             }
@@ -363,18 +472,10 @@ impl RocksDBStore {
 
         // Purge the statistics
 
-        /// Converts statistic key strings "s1/total_docs" into tuples of 1 i32 and a Vec<u8> (1, ['t', 'o', 't', ...])
-        fn parse_statistic_key(key: &[u8]) -> (u32, Vec<u8>) {
-            let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let statistic_name = parts_iter.next().unwrap().to_vec();
-
-            (segment, statistic_name)
-        }
-
+        let stats_cf = column_families::handle(&self.db, column_families::STATS);
         for source_segment in segments.iter() {
             let kb = KeyBuilder::segment_stat_prefix(*source_segment);
-            let mut iter = self.db.raw_iterator();
+            let mut iter = try!(self.db.raw_iterator_cf(stats_cf));
             iter.seek(&kb.key());
             while iter.valid() {
                 let k = iter.key().unwrap();
@@ -391,7 +492,7 @@ impl RocksDBStore {
                     break;
                 }
 
-                try!(self.db.delete_opt(&k, &write_options));
+                try!(self.db.delete_cf_opt(stats_cf, &k, &write_options));
 
                 iter.next();
             }
@@ -403,6 +504,36 @@ impl RocksDBStore {
             try!(self.db.delete_opt(&kb.key(), &write_options));
         }
 
+        // Drop any field data that was loaded for these segments
+        self.field_data_cache.invalidate_segments(segments);
+
+        // The merges that these segments were in are finished
+        try!(merge_journal::remove_purged(&self.db, segments));
+
         Ok(())
     }
+
+    /// Finishes or undoes the merges that were interrupted by a crash
+    ///
+    /// The source segments of committed merges are purged, merges that didn't commit have
+    /// the data of their new segment deleted. This must be called before any readers are
+    /// created. Returns how many merges were recovered.
+    pub fn recover_merges(&self) -> Result<usize, rocksdb::Error> {
+        let entries = try!(merge_journal::read_entries(&self.db));
+
+        for entry in entries.iter() {
+            match entry.state {
+                MergeState::Merging => {
+                    try!(self.purge_segments_unchecked(&vec![entry.dest_segment]));
+                    try!(merge_journal::remove(&self.db, entry.dest_segment));
+                }
+                MergeState::Committed => {
+                    // This removes the journal entry
+                    try!(self.purge_segments_unchecked(&entry.source_segments));
+                }
+            }
+        }
+
+        Ok(entries.len())
+    }
 }
@@ -1,4 +1,5 @@
 use search::segment::Segment;
+use search::statistic_key::StatisticKey;
 
 use super::RocksDBStore;
 
@@ -9,9 +10,16 @@ pub struct SegmentStatistics {
 }
 
 impl SegmentStatistics {
+    pub fn new(total_docs: i64, deleted_docs: i64) -> SegmentStatistics {
+        SegmentStatistics {
+            total_docs: total_docs,
+            deleted_docs: deleted_docs,
+        }
+    }
+
     fn read<S: Segment>(segment: &S) -> Result<SegmentStatistics, String> {
-        let total_docs = try!(segment.load_statistic(b"total_docs")).unwrap_or(0);
-        let deleted_docs = try!(segment.load_statistic(b"deleted_docs")).unwrap_or(0);
+        let total_docs = try!(segment.load_statistic(&StatisticKey::TotalDocs)).unwrap_or(0);
+        let deleted_docs = try!(segment.load_statistic(&StatisticKey::DeletedDocs)).unwrap_or(0);
 
         Ok(SegmentStatistics {
             total_docs: total_docs,
@@ -0,0 +1,142 @@
+//! Statistics summed across all of the active segments
+//!
+//! Counting the documents in a store would otherwise mean reading the statistics of
+//! every segment. The store keeps a running total of each statistic (except term
+//! document frequencies, there are far too many of those) which is updated in the
+//! same write as the segment statistics it sums:
+//!
+//! - writing a segment adds its statistics
+//! - deleting a document increments the deleted docs
+//! - merging segments corrects the deleted docs of the new segment, the others don't
+//!   change as the new segment has the sum of the old ones
+//!
+//! The rollup is built when a store that was written before it existed is opened.
+
+use byteorder::{ByteOrder, LittleEndian};
+use fnv::FnvHashMap;
+use rocksdb;
+
+use search::statistic_key::StatisticKey;
+
+use super::{RocksDBStore, RocksDBReader};
+use super::key_builder::KeyBuilder;
+use super::column_families;
+use super::group_commit::PendingWrites;
+
+
+/// Checks if a statistic is rolled up
+pub fn is_rolled_up(stat_key: &StatisticKey) -> bool {
+    match *stat_key {
+        StatisticKey::TermDocumentFrequency(..) => false,
+        _ => true,
+    }
+}
+
+
+/// Adds to a rolled up statistic when the writes are written
+pub fn add_to_rollup(writes: &mut PendingWrites, stat_key: &StatisticKey, delta: i64) {
+    let kb = KeyBuilder::statistic_rollup(stat_key);
+    let mut delta_bytes = [0; 8];
+    LittleEndian::write_i64(&mut delta_bytes, delta);
+    writes.merge_cf(column_families::STATS, &kb.key(), &delta_bytes);
+}
+
+
+/// A rolled up statistic that doesn't match the sum of the statistics of the active segments
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollupMismatch {
+    pub stat_key: StatisticKey,
+    pub expected: i64,
+    pub actual: Option<i64>,
+}
+
+
+impl<'a> RocksDBReader<'a> {
+    /// Reads a statistic summed across all active segments
+    pub fn rolled_up_statistic(&self, stat_key: &StatisticKey) -> Result<Option<i64>, rocksdb::Error> {
+        let kb = KeyBuilder::statistic_rollup(stat_key);
+        let cf = column_families::handle(&self.store.db, column_families::STATS);
+        let val = try!(self.snapshot.get_cf(cf, &kb.key())).map(|val| LittleEndian::read_i64(&val));
+        Ok(val)
+    }
+}
+
+
+impl RocksDBStore {
+    fn rolled_up_statistic_keys(&self) -> Vec<StatisticKey> {
+        let mut stat_keys = vec![StatisticKey::TotalDocs, StatisticKey::DeletedDocs];
+
+        for field_id in self.schema.keys() {
+            stat_keys.push(StatisticKey::TotalFieldDocs(*field_id));
+            stat_keys.push(StatisticKey::TotalFieldTokens(*field_id));
+        }
+
+        stat_keys
+    }
+
+    /// Compares the rollup with the statistics of the active segments
+    ///
+    /// Must be called while writes are blocked.
+    fn find_rollup_mismatches(&self) -> Result<Vec<RollupMismatch>, rocksdb::Error> {
+        let reader = self.reader();
+        let stat_keys = self.rolled_up_statistic_keys();
+
+        // Sum up the statistics of every segment
+        let mut expected = FnvHashMap::default();
+        for segment in reader.segments() {
+            for stat_key in stat_keys.iter() {
+                if let Some(value) = try!(segment.statistic(stat_key)) {
+                    *expected.entry(*stat_key).or_insert(0) += value;
+                }
+            }
+        }
+
+        let mut mismatches = Vec::new();
+        for stat_key in stat_keys {
+            let expected = expected.get(&stat_key).cloned().unwrap_or(0);
+            let actual = try!(reader.rolled_up_statistic(&stat_key));
+
+            // Missing statistics are zero, except total docs which is always written
+            // so we can tell that the rollup has been built
+            let is_mismatch = match actual {
+                Some(actual) => actual != expected,
+                None => expected != 0 || stat_key == StatisticKey::TotalDocs,
+            };
+
+            if is_mismatch {
+                mismatches.push(RollupMismatch {
+                    stat_key: stat_key,
+                    expected: expected,
+                    actual: actual,
+                });
+            }
+        }
+
+        Ok(mismatches)
+    }
+
+    /// Checks that every rolled up statistic is the sum of the statistics of the active segments
+    pub fn check_statistics_rollup(&self) -> Result<Vec<RollupMismatch>, rocksdb::Error> {
+        self.document_index.with_writes_blocked(&self.db, || self.find_rollup_mismatches())
+    }
+
+    /// Rewrites any rolled up statistics that don't match the statistics of the active segments
+    ///
+    /// Returns the statistics that were repaired.
+    pub fn repair_statistics_rollup(&self) -> Result<Vec<RollupMismatch>, rocksdb::Error> {
+        self.document_index.with_writes_blocked(&self.db, || {
+            let mismatches = try!(self.find_rollup_mismatches());
+
+            let mut writes = PendingWrites::new();
+            for mismatch in mismatches.iter() {
+                let kb = KeyBuilder::statistic_rollup(&mismatch.stat_key);
+                let mut value_bytes = [0; 8];
+                LittleEndian::write_i64(&mut value_bytes, mismatch.expected);
+                writes.put_cf(column_families::STATS, &kb.key(), &value_bytes);
+            }
+            try!(writes.write(&self.db));
+
+            Ok(mismatches)
+        })
+    }
+}
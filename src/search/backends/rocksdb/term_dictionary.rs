@@ -1,13 +1,15 @@
 use std::str;
+use std::cmp;
 use std::sync::{Mutex, RwLock};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::collections::HashMap;
 
-use rocksdb::{self, DB};
+use rocksdb::{self, DB, Snapshot};
 use search::{Term, TermId};
-use search::query::multi_term_selector::MultiTermSelector;
+use search::query::multi_term_selector::{MultiTermSelector, normalize_case};
 
 use super::key_builder::KeyBuilder;
+use super::column_families;
 
 /// Manages the index's "term dictionary"
 ///
@@ -16,12 +18,32 @@ use super::key_builder::KeyBuilder;
 ///
 /// The term dictionary is a mapping between terms and their internal IDs
 /// (aka. TermId). It is entirely held in memory and persisted to the disk.
+///
+/// Terms that change when their case is normalized are also kept under their
+/// normalized form, so case insensitive terms can be looked up without scanning
+/// the whole dictionary.
 pub struct TermDictionaryManager {
     next_term_id: AtomicUsize,
     terms: RwLock<HashMap<Term, TermId>>,
+    case_variants: RwLock<HashMap<String, Vec<TermId>>>,
     write_lock: Mutex<i32>,
 }
 
+/// Returns the normalized form of a term, if normalizing changes it
+fn normalized_case_variant(term: &Term) -> Option<String> {
+    let term = match str::from_utf8(term.as_bytes()) {
+        Ok(term) => term,
+        Err(_) => return None,
+    };
+
+    let normalized = normalize_case(term);
+    if normalized != term {
+        Some(normalized)
+    } else {
+        None
+    }
+}
+
 impl TermDictionaryManager {
     /// Generates a new term dictionary
     pub fn new(db: &DB) -> Result<TermDictionaryManager, rocksdb::Error> {
@@ -32,6 +54,7 @@ impl TermDictionaryManager {
         Ok(TermDictionaryManager {
             next_term_id: AtomicUsize::new(1),
             terms: RwLock::new(HashMap::new()),
+            case_variants: RwLock::new(HashMap::new()),
             write_lock: Mutex::new(0),
         })
     }
@@ -47,7 +70,8 @@ impl TermDictionaryManager {
 
         // Read dictionary
         let mut terms = HashMap::new();
-        let mut iter = db.raw_iterator();
+        let mut case_variants = HashMap::new();
+        let mut iter = try!(db.raw_iterator_cf(column_families::handle(db, column_families::TERMS)));
         iter.seek(b"t");
         while iter.valid() {
             let k = iter.key().unwrap();
@@ -57,7 +81,11 @@ impl TermDictionaryManager {
             }
 
             let term_id = TermId(str::from_utf8(unsafe { &iter.value_inner().unwrap() }).unwrap().parse::<u32>().unwrap());
-            terms.insert(Term::from_bytes(&k[1..]), term_id);
+            let term = Term::from_bytes(&k[1..]);
+            if let Some(normalized) = normalized_case_variant(&term) {
+                case_variants.entry(normalized).or_insert_with(Vec::new).push(term_id);
+            }
+            terms.insert(term, term_id);
 
             iter.next();
         }
@@ -65,6 +93,7 @@ impl TermDictionaryManager {
         Ok(TermDictionaryManager {
             next_term_id: AtomicUsize::new(next_term_id as usize),
             terms: RwLock::new(terms),
+            case_variants: RwLock::new(case_variants),
             write_lock: Mutex::new(0),
         })
     }
@@ -74,8 +103,45 @@ impl TermDictionaryManager {
         self.terms.read().unwrap().get(term).cloned()
     }
 
+    /// Finds the terms that are equal to a value once their case is normalized
+    ///
+    /// The value must already be normalized.
+    fn select_case_insensitive(&self, value: &str) -> Vec<TermId> {
+        let mut term_ids = Vec::new();
+
+        // The term that is already normalized
+        if let Some(term_id) = self.get(&Term::from_string(value)) {
+            term_ids.push(term_id);
+        }
+
+        // Terms that are only equal once normalized
+        if let Some(variants) = self.case_variants.read().unwrap().get(value) {
+            term_ids.extend(variants.iter().cloned());
+        }
+
+        term_ids
+    }
+
+    /// Counts the terms in the dictionary which match the selector, stopping once `limit` have been found
+    pub fn count(&self, term_selector: &MultiTermSelector, limit: usize) -> usize {
+        if let MultiTermSelector::CaseInsensitive(ref value) = *term_selector {
+            return cmp::min(self.select_case_insensitive(value).len(), limit);
+        }
+
+        self.terms.read().unwrap().iter()
+            .filter(|&(term, _term_id)| {
+                term_selector.matches(term)
+            })
+            .take(limit)
+            .count()
+    }
+
     /// Iterates over terms in the dictionary which match the selector
     pub fn select(&self, term_selector: &MultiTermSelector) -> Vec<TermId> {
+        if let MultiTermSelector::CaseInsensitive(ref value) = *term_selector {
+            return self.select_case_insensitive(value);
+        }
+
         self.terms.read().unwrap().iter()
             .filter(|&(term, _term_id)| {
                 term_selector.matches(term)
@@ -84,6 +150,36 @@ impl TermDictionaryManager {
             .collect()
     }
 
+    /// Visits the terms that start with a prefix in byte order, as they were when the snapshot was taken
+    ///
+    /// The in-memory dictionary isn't ordered, so this reads the terms from RocksDB. The
+    /// visitor returns false to stop early.
+    pub fn visit_prefix<F: FnMut(Term, TermId) -> Result<bool, String>>(&self, db: &DB, snapshot: &Snapshot, prefix: &[u8], mut visitor: F) -> Result<(), String> {
+        let kb = KeyBuilder::term_dict_mapping(prefix);
+        let mut iter = try!(snapshot.raw_iterator_cf(column_families::handle(db, column_families::TERMS)));
+        iter.seek(kb.key());
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if !k.starts_with(kb.key()) {
+                break;
+            }
+
+            let term_id = match str::from_utf8(unsafe { &iter.value_inner().unwrap() }).ok().and_then(|term_id| term_id.parse::<u32>().ok()) {
+                Some(term_id) => TermId(term_id),
+                None => return Err("invalid term id in term dictionary".to_string()),
+            };
+
+            if !try!(visitor(Term::from_bytes(&k[1..]), term_id)) {
+                break;
+            }
+
+            iter.next();
+        }
+
+        Ok(())
+    }
+
     /// Retrieves the TermId for the given term, adding the term to the
     /// dictionary if it doesn't exist
     pub fn get_or_create(&self, db: &DB, term: &Term) -> Result<TermId, rocksdb::Error> {
@@ -115,11 +211,64 @@ impl TermDictionaryManager {
 
         // Write it to the on-disk term dictionary
         let kb = KeyBuilder::term_dict_mapping(term.as_bytes());
-        try!(db.put(kb.key(), next_term_id.to_string().as_bytes()));
+        try!(db.put_cf(column_families::handle(db, column_families::TERMS), kb.key(), next_term_id.to_string().as_bytes()));
 
         // Write it to the term dictionary
         self.terms.write().unwrap().insert(term.clone(), term_id);;
+        if let Some(normalized) = normalized_case_variant(term) {
+            self.case_variants.write().unwrap().entry(normalized).or_insert_with(Vec::new).push(term_id);
+        }
 
         Ok(term_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use rocksdb::DB;
+    use search::Term;
+    use search::query::multi_term_selector::MultiTermSelector;
+
+    use super::TermDictionaryManager;
+    use super::super::column_families;
+
+    #[test]
+    fn test_case_insensitive_lookup() {
+        let path = "test_indices/test_term_dictionary_case_insensitive_lookup";
+        let _ = remove_dir_all(path);
+        let mut opts = column_families::db_options();
+        opts.create_if_missing(true);
+        let db = DB::open_cf_descriptors(&opts, path, column_families::descriptors()).unwrap();
+        let term_dictionary = TermDictionaryManager::new(&db).unwrap();
+
+        // Lots of terms that don't match, some in upper case
+        for i in 0..10000 {
+            term_dictionary.get_or_create(&db, &Term::from_string(&format!("term{}", i))).unwrap();
+            term_dictionary.get_or_create(&db, &Term::from_string(&format!("TERM{}", i))).unwrap();
+        }
+
+        let mut expected = vec![
+            term_dictionary.get_or_create(&db, &Term::from_string("hello")).unwrap(),
+            term_dictionary.get_or_create(&db, &Term::from_string("Hello")).unwrap(),
+            term_dictionary.get_or_create(&db, &Term::from_string("HELLO")).unwrap(),
+        ];
+        expected.sort_by_key(|term_id| term_id.0);
+
+        let selector = MultiTermSelector::case_insensitive("hELLo");
+        let mut term_ids = term_dictionary.select(&selector);
+        term_ids.sort_by_key(|term_id| term_id.0);
+        assert_eq!(term_ids, expected);
+        assert_eq!(term_dictionary.count(&selector, 100), 3);
+        assert_eq!(term_dictionary.count(&selector, 2), 2);
+
+        // The variants are found again when the dictionary is loaded from the disk
+        let term_dictionary = TermDictionaryManager::open(&db).unwrap();
+        let mut term_ids = term_dictionary.select(&selector);
+        term_ids.sort_by_key(|term_id| term_id.0);
+        assert_eq!(term_ids, expected);
+        assert_eq!(term_dictionary.count(&MultiTermSelector::case_insensitive("Term5"), 100), 2);
+        assert_eq!(term_dictionary.count(&MultiTermSelector::case_insensitive("missing"), 100), 0);
+    }
+}
@@ -0,0 +1,53 @@
+use search::collectors::{Collector, DocumentMatch};
+
+/// Collects the ids of every matching document, in the order they're matched
+#[derive(Debug)]
+pub struct DocIdsCollector {
+    doc_ids: Vec<u64>,
+}
+
+impl DocIdsCollector {
+    pub fn new() -> DocIdsCollector {
+        DocIdsCollector {
+            doc_ids: Vec::new(),
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<u64> {
+        self.doc_ids
+    }
+}
+
+impl Collector for DocIdsCollector {
+    fn needs_score(&self) -> bool {
+        false
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        self.doc_ids.push(doc.doc_id());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::collectors::{Collector, DocumentMatch};
+    use super::DocIdsCollector;
+
+    #[test]
+    fn test_doc_ids_collector_needs_score() {
+        let collector = DocIdsCollector::new();
+
+        assert_eq!(collector.needs_score(), false);
+    }
+
+    #[test]
+    fn test_doc_ids_collector_collect() {
+        let mut collector = DocIdsCollector::new();
+
+        collector.collect(DocumentMatch::new_unscored(2));
+        collector.collect(DocumentMatch::new_unscored(0));
+        collector.collect(DocumentMatch::new_unscored(1));
+
+        assert_eq!(collector.into_vec(), vec![2, 0, 1]);
+    }
+}
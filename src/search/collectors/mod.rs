@@ -1,7 +1,11 @@
 pub mod total_count;
+pub mod doc_ids;
 pub mod top_score;
+pub mod sorted;
+pub mod multi;
+pub mod registry;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct DocumentMatch {
     id: u64,
     score: Option<f32>,
@@ -33,7 +37,33 @@ impl DocumentMatch {
     }
 }
 
+/// Receives the documents that match a search
+///
+/// Implement this to do something with every match of a search, then pass it to the
+/// search method of the reader. Collectors that should be usable from the search API
+/// are registered with the collector registry (see registry.rs).
 pub trait Collector {
+    /// If false, the matches aren't scored
     fn needs_score(&self) -> bool;
     fn collect(&mut self, doc: DocumentMatch);
 }
+
+impl<'a, C: Collector + ?Sized> Collector for &'a mut C {
+    fn needs_score(&self) -> bool {
+        (**self).needs_score()
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        (**self).collect(doc)
+    }
+}
+
+impl<C: Collector + ?Sized> Collector for Box<C> {
+    fn needs_score(&self) -> bool {
+        (**self).needs_score()
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        (**self).collect(doc)
+    }
+}
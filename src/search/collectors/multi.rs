@@ -0,0 +1,77 @@
+use search::collectors::{Collector, DocumentMatch};
+
+/// Passes every match to each of a list of collectors
+///
+/// This lets a search fill in several collectors at once, for example the top hits and
+/// any collectors requested through the collector registry.
+pub struct MultiCollector<'a> {
+    collectors: Vec<&'a mut Collector>,
+}
+
+impl<'a> MultiCollector<'a> {
+    pub fn new() -> MultiCollector<'a> {
+        MultiCollector {
+            collectors: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, collector: &'a mut Collector) {
+        self.collectors.push(collector);
+    }
+}
+
+impl<'a> Collector for MultiCollector<'a> {
+    fn needs_score(&self) -> bool {
+        self.collectors.iter().any(|collector| collector.needs_score())
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        for collector in self.collectors.iter_mut() {
+            collector.collect(doc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::collectors::{Collector, DocumentMatch};
+    use search::collectors::total_count::TotalCountCollector;
+    use search::collectors::top_score::TopScoreCollector;
+    use super::MultiCollector;
+
+    #[test]
+    fn test_multi_collector_collect() {
+        let mut total_count = TotalCountCollector::new();
+        let mut top_score = TopScoreCollector::new(1);
+
+        {
+            let mut collector = MultiCollector::new();
+            collector.push(&mut total_count);
+            collector.push(&mut top_score);
+
+            collector.collect(DocumentMatch::new_scored(0, 1.0f32));
+            collector.collect(DocumentMatch::new_scored(1, 2.0f32));
+        }
+
+        assert_eq!(total_count.get_total_count(), 2);
+
+        let docs = top_score.into_sorted_vec();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].doc_id(), 1);
+    }
+
+    #[test]
+    fn test_multi_collector_needs_score() {
+        let mut total_count = TotalCountCollector::new();
+        let mut top_score = TopScoreCollector::new(1);
+
+        let mut collector = MultiCollector::new();
+        assert_eq!(collector.needs_score(), false);
+
+        collector.push(&mut total_count);
+        assert_eq!(collector.needs_score(), false);
+
+        collector.push(&mut top_score);
+        assert_eq!(collector.needs_score(), true);
+    }
+}
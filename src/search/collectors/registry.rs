@@ -0,0 +1,244 @@
+//! Collectors that can be requested by name in a search request
+//!
+//! Embedders register a factory for each of their collectors with the registry on the
+//! system. A search request can then ask for any number of them in its "collectors"
+//! section:
+//!
+//! ```json
+//! {
+//!     "query": {"match_all": {}},
+//!     "collectors": {
+//!         "dedup": {"field": "title"}
+//!     }
+//! }
+//! ```
+//!
+//! The factory is called with the parameters of the collector, every match of the
+//! search is passed to the collector it creates and its result is returned in the
+//! "collectors" section of the response.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_json::Value as Json;
+
+use search::collectors::Collector;
+
+
+/// A collector that was created through the registry
+pub trait ExtensionCollector: Collector {
+    /// Called once all matches have been collected, the result is added to the response
+    fn finish(self: Box<Self>) -> Json;
+}
+
+
+pub type CollectorFactory = Box<Fn(&Json) -> Result<Box<ExtensionCollector>, String> + Send + Sync>;
+
+
+#[derive(Debug, PartialEq)]
+pub enum CollectorRegistryError {
+    UnrecognisedCollector(String),
+
+    /// The factory rejected the parameters, with the reason it gave
+    InvalidParameters(String, String),
+}
+
+
+impl CollectorRegistryError {
+    pub fn message(&self) -> String {
+        match *self {
+            CollectorRegistryError::UnrecognisedCollector(ref name) => {
+                format!("Unrecognised collector: {}", name)
+            }
+            CollectorRegistryError::InvalidParameters(ref name, ref reason) => {
+                format!("Invalid parameters for collector {}: {}", name, reason)
+            }
+        }
+    }
+}
+
+
+#[derive(Default)]
+pub struct CollectorRegistry {
+    factories: HashMap<String, CollectorFactory>,
+}
+
+
+impl CollectorRegistry {
+    pub fn new() -> CollectorRegistry {
+        CollectorRegistry::default()
+    }
+
+    /// Registers a collector factory, replacing any that was registered with the same name
+    pub fn register<F>(&mut self, name: &str, factory: F) where F: Fn(&Json) -> Result<Box<ExtensionCollector>, String> + Send + Sync + 'static {
+        self.factories.insert(name.to_string(), Box::new(factory));
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// The names of the registered collectors, in order
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.factories.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Creates a collector from its name and parameters
+    pub fn create(&self, name: &str, parameters: &Json) -> Result<Box<ExtensionCollector>, CollectorRegistryError> {
+        let factory = match self.factories.get(name) {
+            Some(factory) => factory,
+            None => return Err(CollectorRegistryError::UnrecognisedCollector(name.to_string())),
+        };
+
+        factory(parameters).map_err(|reason| CollectorRegistryError::InvalidParameters(name.to_string(), reason))
+    }
+
+    /// Creates every collector in the "collectors" section of a search request
+    pub fn create_from_json(&self, json: &Json) -> Result<Vec<(String, Box<ExtensionCollector>)>, CollectorRegistryError> {
+        let object = match json.as_object() {
+            Some(object) => object,
+            None => return Err(CollectorRegistryError::InvalidParameters("collectors".to_string(), "expected an object".to_string())),
+        };
+
+        let mut collectors = Vec::with_capacity(object.len());
+        for (name, parameters) in object.iter() {
+            collectors.push((name.clone(), try!(self.create(name, parameters))));
+        }
+
+        Ok(collectors)
+    }
+}
+
+
+impl fmt::Debug for CollectorRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = self.factories.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_struct("CollectorRegistry").field("collectors", &names).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::Mutex;
+
+    use serde_json::Value as Json;
+
+    use search::collectors::{Collector, DocumentMatch};
+    use super::{CollectorRegistry, CollectorRegistryError, ExtensionCollector};
+
+    /// Sends the id of every match to a channel
+    struct ExportCollector {
+        sender: Sender<u64>,
+        exported: u64,
+    }
+
+    impl Collector for ExportCollector {
+        fn needs_score(&self) -> bool {
+            false
+        }
+
+        fn collect(&mut self, doc: DocumentMatch) {
+            self.sender.send(doc.doc_id()).unwrap();
+            self.exported += 1;
+        }
+    }
+
+    impl ExtensionCollector for ExportCollector {
+        fn finish(self: Box<Self>) -> Json {
+            json!({"exported": self.exported})
+        }
+    }
+
+    /// Counts the distinct documents, some document ids may be collected more than once
+    struct DedupCollector {
+        seen: HashSet<u64>,
+    }
+
+    impl Collector for DedupCollector {
+        fn needs_score(&self) -> bool {
+            false
+        }
+
+        fn collect(&mut self, doc: DocumentMatch) {
+            self.seen.insert(doc.doc_id());
+        }
+    }
+
+    impl ExtensionCollector for DedupCollector {
+        fn finish(self: Box<Self>) -> Json {
+            json!({"distinct": self.seen.len()})
+        }
+    }
+
+    #[test]
+    fn test_create_registered_collector() {
+        let (sender, receiver) = channel();
+        let sender = Mutex::new(sender);
+
+        let mut registry = CollectorRegistry::new();
+        registry.register("export", move |_parameters| {
+            Ok(Box::new(ExportCollector {
+                sender: sender.lock().unwrap().clone(),
+                exported: 0,
+            }))
+        });
+
+        let mut collector = registry.create("export", &json!({})).unwrap();
+        collector.collect(DocumentMatch::new_unscored(3));
+        collector.collect(DocumentMatch::new_unscored(5));
+
+        assert_eq!(collector.finish(), json!({"exported": 2}));
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![3, 5]);
+    }
+
+    #[test]
+    fn test_create_from_json() {
+        let mut registry = CollectorRegistry::new();
+        registry.register("dedup", |_parameters| {
+            Ok(Box::new(DedupCollector {
+                seen: HashSet::new(),
+            }))
+        });
+
+        assert_eq!(registry.names(), vec!["dedup".to_string()]);
+
+        let collectors = registry.create_from_json(&json!({"dedup": {}})).unwrap();
+        assert_eq!(collectors.len(), 1);
+        assert_eq!(collectors[0].0, "dedup");
+
+        let mut collector = collectors.into_iter().next().unwrap().1;
+        collector.collect(DocumentMatch::new_unscored(1));
+        collector.collect(DocumentMatch::new_unscored(1));
+        collector.collect(DocumentMatch::new_unscored(2));
+        assert_eq!(collector.finish(), json!({"distinct": 2}));
+    }
+
+    #[test]
+    fn test_unrecognised_collector() {
+        let registry = CollectorRegistry::new();
+
+        assert_eq!(registry.create("foo", &json!({})).err(), Some(CollectorRegistryError::UnrecognisedCollector("foo".to_string())));
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        let mut registry = CollectorRegistry::new();
+        registry.register("dedup", |parameters| {
+            if !parameters.is_object() {
+                return Err("expected an object".to_string());
+            }
+
+            Ok(Box::new(DedupCollector {
+                seen: HashSet::new(),
+            }))
+        });
+
+        assert_eq!(registry.create("dedup", &json!(1)).err(), Some(CollectorRegistryError::InvalidParameters("dedup".to_string(), "expected an object".to_string())));
+    }
+}
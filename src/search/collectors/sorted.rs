@@ -0,0 +1,397 @@
+use std::cmp::Ordering;
+
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
+use search::collectors::{Collector, DocumentMatch};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// What to do with documents that don't have a value for the field being sorted on
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortMissing {
+    /// Put them after all other documents (whatever the sort order)
+    Last,
+
+    /// Put them before all other documents (whatever the sort order)
+    First,
+
+    /// Sort them as if they had this value
+    Value(FieldValue),
+}
+
+/// How to pick a single sort value from a field with multiple values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortMode {
+    Min,
+    Max,
+
+    /// Only applies to integer fields, others use the minimum value
+    Sum,
+
+    /// Only applies to integer fields, others use the minimum value. Rounds down.
+    Avg,
+}
+
+impl SortMode {
+    /// Reduces the values of a field to a single value
+    ///
+    /// Kept separate from the collector so numeric aggregations can reuse it.
+    pub fn reduce(&self, values: &[FieldValue]) -> Option<FieldValue> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let integers = || values.iter().filter_map(|value| {
+            match *value {
+                FieldValue::Integer(value) => Some(value),
+                _ => None,
+            }
+        });
+        let all_integers = integers().count() == values.len();
+
+        match *self {
+            SortMode::Sum if all_integers => {
+                Some(FieldValue::Integer(integers().fold(0i64, |sum, value| sum.saturating_add(value))))
+            }
+            SortMode::Avg if all_integers => {
+                let sum = integers().fold(0i64, |sum, value| sum.saturating_add(value));
+                Some(FieldValue::Integer((sum as f64 / values.len() as f64).floor() as i64))
+            }
+            SortMode::Max => values.iter().max_by(|a, b| compare_field_values(a, b)).cloned(),
+            _ => values.iter().min_by(|a, b| compare_field_values(a, b)).cloned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortBy {
+    Score,
+    Field(FieldId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortField {
+    pub by: SortBy,
+    pub order: SortOrder,
+    pub missing: SortMissing,
+
+    /// How to handle multi-valued fields. Defaults to min for ascending and max for descending
+    pub mode: Option<SortMode>,
+}
+
+impl SortField {
+    pub fn score() -> SortField {
+        SortField {
+            by: SortBy::Score,
+            order: SortOrder::Desc,
+            missing: SortMissing::Last,
+            mode: None,
+        }
+    }
+
+    pub fn field(field_id: FieldId, order: SortOrder) -> SortField {
+        SortField {
+            by: SortBy::Field(field_id),
+            order: order,
+            missing: SortMissing::Last,
+            mode: None,
+        }
+    }
+
+    /// The mode used to pick the sort value of a multi-valued field
+    pub fn effective_mode(&self) -> SortMode {
+        match (self.mode, self.order) {
+            (Some(mode), _) => mode,
+            (None, SortOrder::Asc) => SortMode::Min,
+            (None, SortOrder::Desc) => SortMode::Max,
+        }
+    }
+
+    /// Compares the sort values of two documents for this field
+    fn compare(&self, a: &Option<FieldValue>, b: &Option<FieldValue>) -> Ordering {
+        match (a, b) {
+            (&Some(ref a), &Some(ref b)) => {
+                let ordering = compare_field_values(a, b);
+
+                match self.order {
+                    SortOrder::Asc => ordering,
+                    SortOrder::Desc => ordering.reverse(),
+                }
+            }
+            (&None, &None) => Ordering::Equal,
+
+            // Missing values are placed the same way whatever the order
+            (&None, &Some(_)) => {
+                match self.missing {
+                    SortMissing::First => Ordering::Less,
+                    _ => Ordering::Greater,
+                }
+            }
+            (&Some(_), &None) => {
+                match self.missing {
+                    SortMissing::First => Ordering::Greater,
+                    _ => Ordering::Less,
+                }
+            }
+        }
+    }
+}
+
+/// Orders two field values
+///
+/// Values of different types shouldn't be in the same field, but they're given a
+/// consistent order in case they are.
+pub fn compare_field_values(a: &FieldValue, b: &FieldValue) -> Ordering {
+    fn type_rank(value: &FieldValue) -> u8 {
+        match *value {
+            FieldValue::Boolean(_) => 0,
+            FieldValue::Integer(_) => 1,
+            FieldValue::DateTime(_) => 2,
+            FieldValue::String(_) => 3,
+            FieldValue::Bytes(_) => 4,
+            FieldValue::Vector(_) => 5,
+            FieldValue::GeoPoint(_) => 6,
+            FieldValue::Float(_) => 7,
+        }
+    }
+
+    match (a, b) {
+        (&FieldValue::Boolean(a), &FieldValue::Boolean(b)) => a.cmp(&b),
+        (&FieldValue::Integer(a), &FieldValue::Integer(b)) => a.cmp(&b),
+        (&FieldValue::DateTime(ref a), &FieldValue::DateTime(ref b)) => a.cmp(b),
+        (&FieldValue::String(ref a), &FieldValue::String(ref b)) => a.cmp(b),
+        (&FieldValue::Bytes(ref a), &FieldValue::Bytes(ref b)) => a.cmp(b),
+        (&FieldValue::Float(a), &FieldValue::Float(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+#[derive(Debug)]
+pub struct SortedDocument {
+    pub doc_match: DocumentMatch,
+
+    /// The value of each sort field for this document (None for score and missing values)
+    pub sort_values: Vec<Option<FieldValue>>,
+}
+
+/// Collects the top documents ordered by a list of sort fields
+///
+/// The values of the fields are read with the load_values function, which should be
+/// backed by something fast, like the field data cache. Fields with multiple values
+/// are reduced to one using the mode of the sort field. Documents that tie on all
+/// sort fields are ordered by id so results are stable.
+pub struct SortedCollector<F: FnMut(FieldId, DocId) -> Vec<FieldValue>> {
+    sort: Vec<SortField>,
+    max_docs: usize,
+    load_values: F,
+    docs: Vec<SortedDocument>,
+}
+
+impl<F: FnMut(FieldId, DocId) -> Vec<FieldValue>> SortedCollector<F> {
+    pub fn new(sort: Vec<SortField>, max_docs: usize, load_values: F) -> SortedCollector<F> {
+        SortedCollector {
+            sort: sort,
+            max_docs: max_docs,
+            load_values: load_values,
+            docs: Vec::new(),
+        }
+    }
+
+    fn compare(sort: &[SortField], a: &SortedDocument, b: &SortedDocument) -> Ordering {
+        for (i, sort_field) in sort.iter().enumerate() {
+            let ordering = match sort_field.by {
+                SortBy::Score => {
+                    let a_score = a.doc_match.score().unwrap_or(0.0);
+                    let b_score = b.doc_match.score().unwrap_or(0.0);
+                    let ordering = a_score.partial_cmp(&b_score).unwrap_or(Ordering::Equal);
+
+                    match sort_field.order {
+                        SortOrder::Asc => ordering,
+                        SortOrder::Desc => ordering.reverse(),
+                    }
+                }
+                SortBy::Field(_) => sort_field.compare(&a.sort_values[i], &b.sort_values[i]),
+            };
+
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        a.doc_match.doc_id().cmp(&b.doc_match.doc_id())
+    }
+
+    /// Sorts the collected documents and drops any that can't make it into the results
+    fn truncate(&mut self) {
+        let sort = &self.sort;
+        self.docs.sort_by(|a, b| SortedCollector::<F>::compare(sort, a, b));
+        self.docs.truncate(self.max_docs);
+    }
+
+    pub fn into_sorted_vec(mut self) -> Vec<SortedDocument> {
+        self.truncate();
+        self.docs
+    }
+}
+
+impl<F: FnMut(FieldId, DocId) -> Vec<FieldValue>> Collector for SortedCollector<F> {
+    fn needs_score(&self) -> bool {
+        self.sort.iter().any(|sort_field| sort_field.by == SortBy::Score)
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        let doc_id = DocId::from_u64(doc.doc_id());
+        let mut sort_values = Vec::with_capacity(self.sort.len());
+
+        for sort_field in self.sort.iter() {
+            let value = match sort_field.by {
+                SortBy::Score => None,
+                SortBy::Field(field_id) => {
+                    let values = (self.load_values)(field_id, doc_id);
+
+                    match sort_field.effective_mode().reduce(&values) {
+                        Some(value) => Some(value),
+                        None => {
+                            match sort_field.missing {
+                                SortMissing::Value(ref value) => Some(value.clone()),
+                                _ => None,
+                            }
+                        }
+                    }
+                }
+            };
+
+            sort_values.push(value);
+        }
+
+        self.docs.push(SortedDocument {
+            doc_match: doc,
+            sort_values: sort_values,
+        });
+
+        // Don't let the list of documents grow too much larger than needed
+        if self.docs.len() >= self.max_docs.max(16) * 2 {
+            self.truncate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::document::{DocId, FieldValue};
+    use search::schema::FieldId;
+    use search::segment::SegmentId;
+    use search::collectors::{Collector, DocumentMatch};
+
+    use super::{SortedCollector, SortField, SortOrder, SortMissing, SortMode};
+
+    /// Documents 0 - 3 have values, document 4 doesn't
+    ///
+    /// Field 2 has multiple values for each document
+    fn load_values(field_id: FieldId, doc_id: DocId) -> Vec<FieldValue> {
+        let values: &[i64] = match (field_id.0, doc_id.1) {
+            (1, 0) => &[30],
+            (1, 1) => &[10],
+            (1, 2) => &[40],
+            (1, 3) => &[20],
+            (2, 0) => &[5, 50],
+            (2, 1) => &[10, 20, 30],
+            (2, 2) => &[1, 2],
+            (2, 3) => &[25],
+            _ => &[],
+        };
+
+        values.iter().map(|value| FieldValue::Integer(*value)).collect()
+    }
+
+    fn collect_sorted(sort: Vec<SortField>, max_docs: usize) -> Vec<u16> {
+        let mut collector = SortedCollector::new(sort, max_docs, load_values);
+
+        for doc in 0..5 {
+            collector.collect(DocumentMatch::new_scored(DocId(SegmentId(1), doc).as_u64(), doc as f32));
+        }
+
+        collector.into_sorted_vec().iter().map(|doc| DocId::from_u64(doc.doc_match.doc_id()).1).collect()
+    }
+
+    #[test]
+    fn test_sort_asc() {
+        assert_eq!(collect_sorted(vec![SortField::field(FieldId(1), SortOrder::Asc)], 10), vec![1, 3, 0, 2, 4]);
+    }
+
+    #[test]
+    fn test_sort_desc() {
+        // Missing values go last by default, whatever the order
+        assert_eq!(collect_sorted(vec![SortField::field(FieldId(1), SortOrder::Desc)], 10), vec![2, 0, 3, 1, 4]);
+    }
+
+    #[test]
+    fn test_missing_first() {
+        let sort = SortField {
+            missing: SortMissing::First,
+            .. SortField::field(FieldId(1), SortOrder::Desc)
+        };
+
+        assert_eq!(collect_sorted(vec![sort], 10), vec![4, 2, 0, 3, 1]);
+    }
+
+    #[test]
+    fn test_missing_value() {
+        let sort = SortField {
+            missing: SortMissing::Value(FieldValue::Integer(25)),
+            .. SortField::field(FieldId(1), SortOrder::Asc)
+        };
+
+        assert_eq!(collect_sorted(vec![sort], 10), vec![1, 3, 4, 0, 2]);
+    }
+
+    #[test]
+    fn test_sort_by_score() {
+        assert_eq!(collect_sorted(vec![SortField::score()], 10), vec![4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn test_max_docs() {
+        assert_eq!(collect_sorted(vec![SortField::field(FieldId(1), SortOrder::Asc)], 2), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_multi_valued_default_modes() {
+        // Ascending sorts on the minimum value, descending on the maximum
+        assert_eq!(collect_sorted(vec![SortField::field(FieldId(2), SortOrder::Asc)], 10), vec![2, 0, 1, 3, 4]);
+        assert_eq!(collect_sorted(vec![SortField::field(FieldId(2), SortOrder::Desc)], 10), vec![0, 1, 3, 2, 4]);
+    }
+
+    #[test]
+    fn test_multi_valued_sum_and_avg() {
+        let sort = SortField {
+            mode: Some(SortMode::Sum),
+            .. SortField::field(FieldId(2), SortOrder::Asc)
+        };
+        assert_eq!(collect_sorted(vec![sort], 10), vec![2, 3, 0, 1, 4]);
+
+        let sort = SortField {
+            mode: Some(SortMode::Avg),
+            .. SortField::field(FieldId(2), SortOrder::Asc)
+        };
+        assert_eq!(collect_sorted(vec![sort], 10), vec![2, 1, 3, 0, 4]);
+    }
+
+    #[test]
+    fn test_reduce() {
+        let values = vec![FieldValue::Integer(3), FieldValue::Integer(-1), FieldValue::Integer(4)];
+        assert_eq!(SortMode::Min.reduce(&values), Some(FieldValue::Integer(-1)));
+        assert_eq!(SortMode::Max.reduce(&values), Some(FieldValue::Integer(4)));
+        assert_eq!(SortMode::Sum.reduce(&values), Some(FieldValue::Integer(6)));
+        assert_eq!(SortMode::Avg.reduce(&values), Some(FieldValue::Integer(2)));
+        assert_eq!(SortMode::Min.reduce(&[]), None);
+
+        // Sum of non-numeric values falls back to min
+        let values = vec![FieldValue::String("b".to_string()), FieldValue::String("a".to_string())];
+        assert_eq!(SortMode::Sum.reduce(&values), Some(FieldValue::String("a".to_string())));
+    }
+}
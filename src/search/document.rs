@@ -5,6 +5,7 @@ use fnv::FnvHashMap;
 use search::term_vector::TermVector;
 use search::schema::FieldId;
 use search::segment::SegmentId;
+use search::geo::GeoPoint;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct DocId(pub SegmentId, pub u16);
@@ -21,12 +22,16 @@ impl DocId {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FieldValue {
     String(String),
     Integer(i64),
     Boolean(bool),
     DateTime(DateTime<Utc>),
+    Bytes(Vec<u8>),
+    Vector(Vec<f32>),
+    GeoPoint(GeoPoint),
+    Float(f64),
 }
 
 impl FieldValue {
@@ -61,6 +66,27 @@ impl FieldValue {
                 bytes.write_i64::<LittleEndian>(timestamp_with_micros).unwrap();
                 bytes
             }
+            FieldValue::Bytes(ref bytes) => bytes.clone(),
+            FieldValue::Vector(ref vector) => {
+                let mut bytes = Vec::with_capacity(vector.len() * 4);
+
+                for value in vector.iter() {
+                    bytes.write_f32::<LittleEndian>(*value).unwrap();
+                }
+
+                bytes
+            }
+            FieldValue::GeoPoint(ref point) => {
+                let mut bytes = Vec::with_capacity(16);
+                bytes.write_f64::<LittleEndian>(point.lon).unwrap();
+                bytes.write_f64::<LittleEndian>(point.lat).unwrap();
+                bytes
+            }
+            FieldValue::Float(value) => {
+                let mut bytes = Vec::with_capacity(8);
+                bytes.write_f64::<LittleEndian>(value).unwrap();
+                bytes
+            }
         }
     }
 }
@@ -68,6 +94,13 @@ impl FieldValue {
 #[derive(Debug, Clone)]
 pub struct Document {
     pub key: String,
+
+    /// Multiplied into the score of the document whenever it matches a query
+    pub boost: f32,
     pub indexed_fields: FnvHashMap<FieldId, TermVector>,
     pub stored_fields: FnvHashMap<FieldId, FieldValue>,
+
+    /// All values of multi-valued fields, used for sorting and aggregations
+    /// Fields with a single value only need to be in stored_fields
+    pub doc_values: FnvHashMap<FieldId, Vec<FieldValue>>,
 }
@@ -0,0 +1,161 @@
+//! Functions that turn a static value of a document into a score
+//!
+//! These let signals like popularity (rank_feature queries) or recency and nearness
+//! (distance_feature queries) influence the ranking without any postings lists. Each
+//! function gives a score between 0 and 1 (except linear and log), that grows with
+//! the value or shrinks with its distance from an origin.
+
+use search::document::FieldValue;
+use search::geo::GeoPoint;
+use search::term::datetime_to_micros;
+
+/// What the distance of a document's value is measured from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeatureOrigin {
+    /// An integer, or a date in microseconds since the epoch
+    Number(i64),
+
+    /// A point, the distance is the arc distance in meters
+    Geo(GeoPoint),
+}
+
+impl FeatureOrigin {
+    /// The distance between the origin and a value, None if they can't be compared
+    pub fn distance(&self, value: &FieldValue) -> Option<f64> {
+        match (*self, value) {
+            (FeatureOrigin::Number(origin), &FieldValue::Integer(value)) => Some((value as f64 - origin as f64).abs()),
+            (FeatureOrigin::Number(origin), &FieldValue::DateTime(ref value)) => Some((datetime_to_micros(value) as f64 - origin as f64).abs()),
+            (FeatureOrigin::Geo(ref origin), &FieldValue::GeoPoint(ref value)) => Some(origin.arc_distance(value)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeatureFunction {
+    /// value / (value + pivot)
+    Saturation {
+        pivot: f64,
+    },
+
+    /// ln(scaling_factor + value)
+    Log {
+        scaling_factor: f64,
+    },
+
+    /// value^exponent / (value^exponent + pivot^exponent)
+    Sigmoid {
+        pivot: f64,
+        exponent: f64,
+    },
+
+    /// The value itself
+    Linear,
+
+    /// pivot / (pivot + distance), so documents at the pivot distance from the origin score 0.5
+    Distance {
+        origin: FeatureOrigin,
+        pivot: f64,
+    },
+}
+
+/// Reads a numeric value of a rank feature
+fn feature_value(value: &FieldValue) -> Option<f64> {
+    match *value {
+        FieldValue::Float(value) => Some(value),
+        FieldValue::Integer(value) => Some(value as f64),
+        _ => None,
+    }
+}
+
+impl FeatureFunction {
+    fn score_value(&self, value: &FieldValue) -> Option<f64> {
+        match *self {
+            FeatureFunction::Saturation{pivot} => feature_value(value).map(|value| value / (value + pivot)),
+            FeatureFunction::Log{scaling_factor} => feature_value(value).map(|value| (scaling_factor + value).ln()),
+            FeatureFunction::Sigmoid{pivot, exponent} => {
+                feature_value(value).map(|value| {
+                    let value_pow = value.powf(exponent);
+                    value_pow / (value_pow + pivot.powf(exponent))
+                })
+            }
+            FeatureFunction::Linear => feature_value(value),
+            FeatureFunction::Distance{ref origin, pivot} => origin.distance(value).map(|distance| pivot / (pivot + distance)),
+        }
+    }
+
+    /// Scores a document by the values of its field
+    ///
+    /// Documents with several values get the score of their best value. Returns None if
+    /// none of the values can be scored.
+    pub fn score(&self, values: &[FieldValue]) -> Option<f32> {
+        values.iter()
+            .filter_map(|value| self.score_value(value))
+            .filter(|score| score.is_finite())
+            .fold(None, |best, score| {
+                match best {
+                    Some(best) if best >= score => Some(best),
+                    _ => Some(score),
+                }
+            })
+            .map(|score| score as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use search::document::FieldValue;
+    use search::geo::GeoPoint;
+    use search::term::datetime_to_micros;
+
+    use super::{FeatureFunction, FeatureOrigin};
+
+    #[test]
+    fn test_saturation() {
+        let function = FeatureFunction::Saturation{pivot: 10.0};
+
+        assert_eq!(function.score(&[FieldValue::Float(10.0)]), Some(0.5));
+        assert_eq!(function.score(&[FieldValue::Float(30.0)]), Some(0.75));
+        assert_eq!(function.score(&[]), None);
+    }
+
+    #[test]
+    fn test_log_and_sigmoid() {
+        assert_eq!(FeatureFunction::Log{scaling_factor: 1.0}.score(&[FieldValue::Float(0.0)]), Some(0.0));
+        assert_eq!(FeatureFunction::Sigmoid{pivot: 2.0, exponent: 2.0}.score(&[FieldValue::Float(2.0)]), Some(0.5));
+        assert_eq!(FeatureFunction::Linear.score(&[FieldValue::Float(3.5)]), Some(3.5));
+    }
+
+    #[test]
+    fn test_distance() {
+        let origin = Utc.ymd(2017, 1, 10).and_hms(0, 0, 0);
+        let function = FeatureFunction::Distance {
+            origin: FeatureOrigin::Number(datetime_to_micros(&origin)),
+            pivot: 86400000000.0,
+        };
+
+        // A day either side of the origin scores half, the nearest value is used
+        let day_before = FieldValue::DateTime(Utc.ymd(2017, 1, 9).and_hms(0, 0, 0));
+        let long_before = FieldValue::DateTime(Utc.ymd(2016, 1, 9).and_hms(0, 0, 0));
+        assert_eq!(function.score(&[long_before, day_before]), Some(0.5));
+        assert_eq!(function.score(&[FieldValue::DateTime(origin)]), Some(1.0));
+
+        // Values that can't be compared with the origin aren't scored
+        assert_eq!(function.score(&[FieldValue::GeoPoint(GeoPoint::new(0.0, 0.0))]), None);
+    }
+
+    #[test]
+    fn test_geo_distance() {
+        let function = FeatureFunction::Distance {
+            origin: FeatureOrigin::Geo(GeoPoint::new(4.8952, 52.3702)),
+            pivot: 1000.0,
+        };
+
+        let near = function.score(&[FieldValue::GeoPoint(GeoPoint::new(4.8960, 52.3710))]).unwrap();
+        let far = function.score(&[FieldValue::GeoPoint(GeoPoint::new(13.4050, 52.5200))]).unwrap();
+        assert!(near > 0.9);
+        assert!(far < 0.01);
+    }
+}
@@ -0,0 +1,703 @@
+//! Geometry for geo shape and geo point fields
+//!
+//! Shapes are indexed as the geohash cells that cover them. The cells only find the
+//! documents that might match a query quickly, each of these is then checked against
+//! the exact shape that was stored with the document.
+//!
+//! Coordinates are treated as planar, so shapes that cross the dateline aren't supported.
+//! Distances between points are measured over the surface of the earth.
+
+use std::collections::BTreeSet;
+
+use serde_json::Value as Json;
+
+use search::term::Term;
+
+/// The length of the smallest geohash cells used to cover a shape (about 38m x 19m)
+pub const MAX_PRECISION: usize = 8;
+
+/// Cells aren't split any further once a cover would have more than this many
+pub const MAX_CELLS: usize = 256;
+
+const GEOHASH_ALPHABET: &'static [u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Points closer than this to a line are on it
+const EPSILON: f64 = 1e-12;
+
+/// The mean radius of the earth, in meters
+pub const EARTH_RADIUS: f64 = 6371008.7714;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub lon: f64,
+    pub lat: f64,
+}
+
+impl GeoPoint {
+    pub fn new(lon: f64, lat: f64) -> GeoPoint {
+        GeoPoint {
+            lon: lon,
+            lat: lat,
+        }
+    }
+
+    /// Reads the value of a geo point field, or a point in a query
+    ///
+    /// Points can be an object ({"lat": 52.37, "lon": 4.89}), a "lat,lon" string or
+    /// an array in GeoJSON order ([4.89, 52.37]).
+    pub fn from_json(json: &Json) -> Result<GeoPoint, String> {
+        match *json {
+            Json::Object(ref object) => {
+                let lat = try!(object.get("lat").and_then(|lat| lat.as_f64()).ok_or("expected a number for \"lat\""));
+                let lon = try!(object.get("lon").and_then(|lon| lon.as_f64()).ok_or("expected a number for \"lon\""));
+
+                check_point(lon, lat)
+            }
+            Json::String(ref string) => {
+                let parts = string.split(',').map(|part| part.trim().parse::<f64>()).collect::<Vec<_>>();
+
+                if parts.len() == 2 {
+                    if let (&Ok(lat), &Ok(lon)) = (&parts[0], &parts[1]) {
+                        return check_point(lon, lat);
+                    }
+                }
+
+                Err(format!("expected \"lat,lon\", got \"{}\"", string))
+            }
+            Json::Array(_) => parse_point(json),
+            _ => Err("expected a point".to_string()),
+        }
+    }
+
+    /// The geohash of the cell that contains the point
+    pub fn geohash(&self, precision: usize) -> String {
+        let (mut min_lon, mut max_lon) = (-180.0f64, 180.0f64);
+        let (mut min_lat, mut max_lat) = (-90.0f64, 90.0f64);
+        let mut is_lon = true;
+        let mut geohash = String::with_capacity(precision);
+
+        for _ in 0..precision {
+            // Each character holds five bits, alternating between longitude and latitude
+            let mut index = 0;
+            for _ in 0..5 {
+                index <<= 1;
+
+                if is_lon {
+                    let mid = (min_lon + max_lon) / 2.0;
+                    if self.lon >= mid { index |= 1; min_lon = mid } else { max_lon = mid }
+                } else {
+                    let mid = (min_lat + max_lat) / 2.0;
+                    if self.lat >= mid { index |= 1; min_lat = mid } else { max_lat = mid }
+                }
+
+                is_lon = !is_lon;
+            }
+
+            geohash.push(GEOHASH_ALPHABET[index] as char);
+        }
+
+        geohash
+    }
+
+    /// The great-circle distance to another point in meters (the haversine formula)
+    pub fn arc_distance(&self, other: &GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let half_dlat = (lat2 - lat1) / 2.0;
+        let half_dlon = (other.lon - self.lon).to_radians() / 2.0;
+
+        let h = half_dlat.sin().powi(2) + lat1.cos() * lat2.cos() * half_dlon.sin().powi(2);
+        2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+    }
+
+    /// The distance to another point in meters, treating the earth as flat around them
+    ///
+    /// This is quicker than arc_distance but is only accurate over short distances.
+    pub fn plane_distance(&self, other: &GeoPoint) -> f64 {
+        let mean_lat = ((self.lat + other.lat) / 2.0).to_radians();
+        let x = (other.lon - self.lon).to_radians() * mean_lat.cos();
+        let y = (other.lat - self.lat).to_radians();
+
+        EARTH_RADIUS * (x * x + y * y).sqrt()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoShape {
+    Point(GeoPoint),
+    LineString(Vec<GeoPoint>),
+
+    /// The first ring is the outside of the polygon, the others are holes
+    /// Rings are closed, their first and last points are the same
+    Polygon(Vec<Vec<GeoPoint>>),
+
+    /// Several shapes (MultiPoint, MultiLineString, MultiPolygon and GeometryCollection)
+    Collection(Vec<GeoShape>),
+}
+
+/// How the shape of a document must relate to the shape of a query
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoShapeRelation {
+    Intersects,
+    Within,
+}
+
+impl GeoShapeRelation {
+    pub fn matches(&self, doc_shape: &GeoShape, query_shape: &GeoShape) -> bool {
+        match *self {
+            GeoShapeRelation::Intersects => doc_shape.intersects(query_shape),
+            GeoShapeRelation::Within => doc_shape.is_within(query_shape),
+        }
+    }
+}
+
+/// The number of meters in a unit of distance ("km", "mi", etc)
+pub fn distance_unit_in_meters(unit: &str) -> Option<f64> {
+    match unit {
+        "mm" | "millimeters" => Some(0.001),
+        "cm" | "centimeters" => Some(0.01),
+        "m" | "meters" => Some(1.0),
+        "km" | "kilometers" => Some(1000.0),
+        "in" | "inch" => Some(0.0254),
+        "ft" | "feet" => Some(0.3048),
+        "yd" | "yards" => Some(0.9144),
+        "mi" | "miles" => Some(1609.344),
+        "nmi" | "NM" => Some(1852.0),
+        _ => None,
+    }
+}
+
+fn parse_point(json: &Json) -> Result<GeoPoint, String> {
+    let coordinates = try!(json.as_array().ok_or("expected a position"));
+    if coordinates.len() < 2 {
+        return Err("expected a position".to_string());
+    }
+
+    let lon = try!(coordinates[0].as_f64().ok_or("expected a number"));
+    let lat = try!(coordinates[1].as_f64().ok_or("expected a number"));
+
+    check_point(lon, lat)
+}
+
+fn check_point(lon: f64, lat: f64) -> Result<GeoPoint, String> {
+    if lon < -180.0 || lon > 180.0 {
+        return Err(format!("longitude {} is out of range", lon));
+    }
+
+    if lat < -90.0 || lat > 90.0 {
+        return Err(format!("latitude {} is out of range", lat));
+    }
+
+    Ok(GeoPoint::new(lon, lat))
+}
+
+fn parse_points(json: &Json) -> Result<Vec<GeoPoint>, String> {
+    let array = try!(json.as_array().ok_or("expected an array of positions"));
+    array.iter().map(parse_point).collect()
+}
+
+fn parse_line(json: &Json) -> Result<Vec<GeoPoint>, String> {
+    let points = try!(parse_points(json));
+    if points.len() < 2 {
+        return Err("a line must have at least two positions".to_string());
+    }
+
+    Ok(points)
+}
+
+fn parse_polygon(json: &Json) -> Result<Vec<Vec<GeoPoint>>, String> {
+    let array = try!(json.as_array().ok_or("expected an array of rings"));
+    if array.is_empty() {
+        return Err("a polygon must have at least one ring".to_string());
+    }
+
+    let mut rings = Vec::with_capacity(array.len());
+    for ring in array.iter() {
+        let points = try!(parse_points(ring));
+        if points.len() < 4 || points.first() != points.last() {
+            return Err("a ring must have at least four positions and be closed".to_string());
+        }
+
+        rings.push(points);
+    }
+
+    Ok(rings)
+}
+
+fn parse_list<T, F: Fn(&Json) -> Result<T, String>>(json: &Json, parse_item: F) -> Result<Vec<T>, String> {
+    let array = try!(json.as_array().ok_or("expected an array"));
+    array.iter().map(parse_item).collect()
+}
+
+/// Converts a bounding box into a polygon
+fn envelope(min_lon: f64, min_lat: f64, max_lon: f64, max_lat: f64) -> GeoShape {
+    GeoShape::Polygon(vec![vec![
+        GeoPoint::new(min_lon, min_lat),
+        GeoPoint::new(max_lon, min_lat),
+        GeoPoint::new(max_lon, max_lat),
+        GeoPoint::new(min_lon, max_lat),
+        GeoPoint::new(min_lon, min_lat),
+    ]])
+}
+
+/// The area covered by a geohash
+fn cell_shape(geohash: &str) -> GeoShape {
+    let (mut min_lon, mut max_lon) = (-180.0f64, 180.0f64);
+    let (mut min_lat, mut max_lat) = (-90.0f64, 90.0f64);
+    let mut is_lon = true;
+
+    for byte in geohash.bytes() {
+        let index = GEOHASH_ALPHABET.iter().position(|c| *c == byte).unwrap_or(0);
+
+        // Each character holds five bits, alternating between longitude and latitude
+        for bit in (0..5).rev() {
+            let is_set = index & (1 << bit) != 0;
+
+            if is_lon {
+                let mid = (min_lon + max_lon) / 2.0;
+                if is_set { min_lon = mid } else { max_lon = mid }
+            } else {
+                let mid = (min_lat + max_lat) / 2.0;
+                if is_set { min_lat = mid } else { max_lat = mid }
+            }
+
+            is_lon = !is_lon;
+        }
+    }
+
+    envelope(min_lon, min_lat, max_lon, max_lat)
+}
+
+fn child_cells(geohash: &str) -> Vec<String> {
+    GEOHASH_ALPHABET.iter().map(|c| format!("{}{}", geohash, *c as char)).collect()
+}
+
+/// Finds the geohash cells that cover a shape
+///
+/// All the cells are split one level at a time, until they're MAX_PRECISION long or
+/// there would be more than MAX_CELLS of them. Cells that are inside the shape aren't
+/// split, as their children would all be in the cover too.
+pub fn covering_cells(shape: &GeoShape) -> Vec<String> {
+    let mut cells = Vec::new();
+
+    // The empty geohash covers the whole world
+    let mut edge_cells = vec![String::new()];
+
+    for _ in 0..MAX_PRECISION {
+        let mut next_cells = Vec::new();
+        for cell in edge_cells.iter() {
+            for child in child_cells(cell) {
+                if shape.intersects(&cell_shape(&child)) {
+                    next_cells.push(child);
+                }
+            }
+        }
+
+        if cells.len() + next_cells.len() > MAX_CELLS {
+            break;
+        }
+
+        edge_cells = Vec::new();
+        for cell in next_cells {
+            if cell_shape(&cell).is_within(shape) {
+                cells.push(cell);
+            } else {
+                edge_cells.push(cell);
+            }
+        }
+    }
+
+    cells.extend(edge_cells);
+    cells.sort();
+    cells
+}
+
+/// The terms a shape is indexed with
+///
+/// Each cell that covers the shape is indexed, as well as each of its parents with a
+/// "+" on the end.
+pub fn index_terms(shape: &GeoShape) -> Vec<Term> {
+    let mut terms = BTreeSet::new();
+
+    for cell in covering_cells(shape) {
+        for length in 1..cell.len() {
+            terms.insert(format!("{}+", &cell[..length]));
+        }
+
+        terms.insert(cell);
+    }
+
+    terms.iter().map(|term| Term::from_string(term)).collect()
+}
+
+/// The terms of the indexed shapes that might intersect a shape
+///
+/// These are the indexed cells that contain one of the cells that cover the shape,
+/// and the ones that are inside one of them.
+pub fn query_terms(shape: &GeoShape) -> Vec<Term> {
+    let mut terms = BTreeSet::new();
+
+    for cell in covering_cells(shape) {
+        for length in 1..cell.len() + 1 {
+            terms.insert(cell[..length].to_string());
+        }
+
+        terms.insert(format!("{}+", cell));
+    }
+
+    terms.iter().map(|term| Term::from_string(term)).collect()
+}
+
+/// A shape that isn't made of other shapes
+#[derive(Debug, Clone, Copy)]
+enum Primitive<'a> {
+    Point(&'a GeoPoint),
+    Line(&'a [GeoPoint]),
+    Polygon(&'a [Vec<GeoPoint>]),
+}
+
+/// Which side of the line through a and b the point c is on (0 if it's on the line)
+fn orientation(a: &GeoPoint, b: &GeoPoint, c: &GeoPoint) -> f64 {
+    (b.lon - a.lon) * (c.lat - a.lat) - (b.lat - a.lat) * (c.lon - a.lon)
+}
+
+fn point_on_segment(point: &GeoPoint, a: &GeoPoint, b: &GeoPoint) -> bool {
+    orientation(a, b, point).abs() <= EPSILON
+        && point.lon >= a.lon.min(b.lon) - EPSILON && point.lon <= a.lon.max(b.lon) + EPSILON
+        && point.lat >= a.lat.min(b.lat) - EPSILON && point.lat <= a.lat.max(b.lat) + EPSILON
+}
+
+/// Whether the segments cross each other, touching doesn't count
+fn segments_cross(a1: &GeoPoint, a2: &GeoPoint, b1: &GeoPoint, b2: &GeoPoint) -> bool {
+    let d1 = orientation(b1, b2, a1);
+    let d2 = orientation(b1, b2, a2);
+    let d3 = orientation(a1, a2, b1);
+    let d4 = orientation(a1, a2, b2);
+
+    ((d1 > EPSILON && d2 < -EPSILON) || (d1 < -EPSILON && d2 > EPSILON))
+        && ((d3 > EPSILON && d4 < -EPSILON) || (d3 < -EPSILON && d4 > EPSILON))
+}
+
+fn segments_intersect(a1: &GeoPoint, a2: &GeoPoint, b1: &GeoPoint, b2: &GeoPoint) -> bool {
+    segments_cross(a1, a2, b1, b2)
+        || point_on_segment(a1, b1, b2) || point_on_segment(a2, b1, b2)
+        || point_on_segment(b1, a1, a2) || point_on_segment(b2, a1, a2)
+}
+
+fn point_on_line(point: &GeoPoint, line: &[GeoPoint]) -> bool {
+    line.windows(2).any(|segment| point_on_segment(point, &segment[0], &segment[1]))
+}
+
+/// Whether the point is inside the ring, points on the ring itself may go either way
+fn point_in_ring(point: &GeoPoint, ring: &[GeoPoint]) -> bool {
+    let mut inside = false;
+
+    for segment in ring.windows(2) {
+        let (a, b) = (&segment[0], &segment[1]);
+
+        if (a.lat > point.lat) != (b.lat > point.lat) {
+            let crossing_lon = a.lon + (point.lat - a.lat) / (b.lat - a.lat) * (b.lon - a.lon);
+            if point.lon < crossing_lon {
+                inside = !inside;
+            }
+        }
+    }
+
+    inside
+}
+
+/// Whether the point is inside the polygon, points on its edges are inside
+fn polygon_contains_point(polygon: &[Vec<GeoPoint>], point: &GeoPoint) -> bool {
+    for (i, ring) in polygon.iter().enumerate() {
+        if point_on_line(point, ring) {
+            return true;
+        }
+
+        let in_ring = point_in_ring(point, ring);
+        if i == 0 && !in_ring {
+            // Outside the polygon
+            return false;
+        }
+
+        if i > 0 && in_ring {
+            // In a hole
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Whether any segment of the first list of lines intersects a segment of the second
+fn lines_intersect(a: &[&[GeoPoint]], b: &[&[GeoPoint]]) -> bool {
+    a.iter().any(|a| a.windows(2).any(|a| {
+        b.iter().any(|b| b.windows(2).any(|b| segments_intersect(&a[0], &a[1], &b[0], &b[1])))
+    }))
+}
+
+/// Whether any segment of the first list of lines crosses a segment of the second
+fn lines_cross(a: &[&[GeoPoint]], b: &[&[GeoPoint]]) -> bool {
+    a.iter().any(|a| a.windows(2).any(|a| {
+        b.iter().any(|b| b.windows(2).any(|b| segments_cross(&a[0], &a[1], &b[0], &b[1])))
+    }))
+}
+
+fn rings(polygon: &[Vec<GeoPoint>]) -> Vec<&[GeoPoint]> {
+    polygon.iter().map(|ring| &ring[..]).collect()
+}
+
+fn midpoints(line: &[GeoPoint]) -> Vec<GeoPoint> {
+    line.windows(2).map(|segment| GeoPoint::new((segment[0].lon + segment[1].lon) / 2.0, (segment[0].lat + segment[1].lat) / 2.0)).collect()
+}
+
+fn primitives_intersect(a: Primitive, b: Primitive) -> bool {
+    match (a, b) {
+        (Primitive::Point(a), Primitive::Point(b)) => a == b,
+        (Primitive::Point(point), Primitive::Line(line)) | (Primitive::Line(line), Primitive::Point(point)) => {
+            point_on_line(point, line)
+        }
+        (Primitive::Point(point), Primitive::Polygon(polygon)) | (Primitive::Polygon(polygon), Primitive::Point(point)) => {
+            polygon_contains_point(polygon, point)
+        }
+        (Primitive::Line(a), Primitive::Line(b)) => lines_intersect(&[a], &[b]),
+        (Primitive::Line(line), Primitive::Polygon(polygon)) | (Primitive::Polygon(polygon), Primitive::Line(line)) => {
+            // If the line doesn't touch an edge, it's either all inside or all outside
+            polygon_contains_point(polygon, &line[0]) || lines_intersect(&[line], &rings(polygon))
+        }
+        (Primitive::Polygon(a), Primitive::Polygon(b)) => {
+            // If no edges touch, the polygons are either apart or one is inside the other
+            polygon_contains_point(b, &a[0][0]) || polygon_contains_point(a, &b[0][0]) || lines_intersect(&rings(a), &rings(b))
+        }
+    }
+}
+
+fn primitive_within(a: Primitive, b: Primitive) -> bool {
+    match (a, b) {
+        (Primitive::Point(_), _) => primitives_intersect(a, b),
+        (Primitive::Line(a), Primitive::Line(b)) => {
+            a.iter().all(|point| point_on_line(point, b)) && midpoints(a).iter().all(|point| point_on_line(point, b))
+        }
+        (Primitive::Line(line), Primitive::Polygon(polygon)) => {
+            line.iter().all(|point| polygon_contains_point(polygon, point))
+                && midpoints(line).iter().all(|point| polygon_contains_point(polygon, point))
+                && !lines_cross(&[line], &rings(polygon))
+        }
+        (Primitive::Polygon(a), Primitive::Polygon(b)) => {
+            let outside = &a[0][..];
+
+            // The outside of a must be inside b, and b mustn't have a hole inside a
+            outside.iter().all(|point| polygon_contains_point(b, point))
+                && midpoints(outside).iter().all(|point| polygon_contains_point(b, point))
+                && !lines_cross(&[outside], &rings(b))
+                && !b[1..].iter().any(|hole| hole.iter().any(|point| polygon_contains_point(a, point) && !point_on_line(point, outside)))
+        }
+
+        // Lines and polygons can't be inside a point, and polygons can't be inside a line
+        _ => false,
+    }
+}
+
+impl GeoShape {
+    /// Parses a GeoJSON geometry
+    ///
+    /// As well as the GeoJSON types, "envelope" is accepted for bounding boxes. Its
+    /// coordinates are the top left and bottom right corners.
+    pub fn from_geojson(json: &Json) -> Result<GeoShape, String> {
+        let object = try!(json.as_object().ok_or("expected a GeoJSON object"));
+        let shape_type = try!(object.get("type").and_then(|shape_type| shape_type.as_str()).ok_or("expected a \"type\""));
+
+        if shape_type.to_lowercase() == "geometrycollection" {
+            let geometries = try!(object.get("geometries").ok_or("expected \"geometries\""));
+            return Ok(GeoShape::Collection(try!(parse_list(geometries, GeoShape::from_geojson))));
+        }
+
+        let coordinates = try!(object.get("coordinates").ok_or("expected \"coordinates\""));
+
+        match shape_type.to_lowercase().as_ref() {
+            "point" => Ok(GeoShape::Point(try!(parse_point(coordinates)))),
+            "linestring" => Ok(GeoShape::LineString(try!(parse_line(coordinates)))),
+            "polygon" => Ok(GeoShape::Polygon(try!(parse_polygon(coordinates)))),
+            "multipoint" => Ok(GeoShape::Collection(try!(parse_points(coordinates)).into_iter().map(GeoShape::Point).collect())),
+            "multilinestring" => Ok(GeoShape::Collection(try!(parse_list(coordinates, parse_line)).into_iter().map(GeoShape::LineString).collect())),
+            "multipolygon" => Ok(GeoShape::Collection(try!(parse_list(coordinates, parse_polygon)).into_iter().map(GeoShape::Polygon).collect())),
+            "envelope" => {
+                let corners = try!(parse_points(coordinates));
+                if corners.len() != 2 {
+                    return Err("an envelope must have two positions".to_string());
+                }
+
+                let (top_left, bottom_right) = (corners[0], corners[1]);
+                if top_left.lon > bottom_right.lon || top_left.lat < bottom_right.lat {
+                    return Err("expected the top left and bottom right corners of the envelope".to_string());
+                }
+
+                Ok(envelope(top_left.lon, bottom_right.lat, bottom_right.lon, top_left.lat))
+            }
+            _ => Err(format!("unrecognised shape type \"{}\"", shape_type)),
+        }
+    }
+
+    fn collect_primitives<'a>(&'a self, primitives: &mut Vec<Primitive<'a>>) {
+        match *self {
+            GeoShape::Point(ref point) => primitives.push(Primitive::Point(point)),
+            GeoShape::LineString(ref line) => primitives.push(Primitive::Line(line)),
+            GeoShape::Polygon(ref polygon) => primitives.push(Primitive::Polygon(polygon)),
+            GeoShape::Collection(ref shapes) => {
+                for shape in shapes.iter() {
+                    shape.collect_primitives(primitives);
+                }
+            }
+        }
+    }
+
+    fn primitives(&self) -> Vec<Primitive> {
+        let mut primitives = Vec::new();
+        self.collect_primitives(&mut primitives);
+        primitives
+    }
+
+    /// Whether the shapes have any point in common (touching counts)
+    pub fn intersects(&self, other: &GeoShape) -> bool {
+        let other_primitives = other.primitives();
+
+        self.primitives().into_iter().any(|a| other_primitives.iter().any(|b| primitives_intersect(a, *b)))
+    }
+
+    /// Whether this shape is inside the other shape
+    ///
+    /// Each part of this shape must be inside a single part of the other shape.
+    pub fn is_within(&self, other: &GeoShape) -> bool {
+        let primitives = self.primitives();
+        let other_primitives = other.primitives();
+
+        !primitives.is_empty() && primitives.into_iter().all(|a| other_primitives.iter().any(|b| primitive_within(a, *b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::term::Term;
+
+    use super::{GeoShape, GeoPoint, GeoShapeRelation, cell_shape, covering_cells, index_terms, query_terms, MAX_CELLS};
+
+    fn square(min: f64, max: f64) -> GeoShape {
+        GeoShape::from_geojson(&json!({
+            "type": "polygon",
+            "coordinates": [[[min, min], [max, min], [max, max], [min, max], [min, min]]]
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_parse_geojson() {
+        assert_eq!(GeoShape::from_geojson(&json!({"type": "Point", "coordinates": [10.0, 20.0]})), Ok(GeoShape::Point(GeoPoint::new(10.0, 20.0))));
+        assert_eq!(GeoShape::from_geojson(&json!({"type": "envelope", "coordinates": [[0.0, 1.0], [1.0, 0.0]]})), Ok(square(0.0, 1.0)));
+
+        assert!(GeoShape::from_geojson(&json!({"type": "Point", "coordinates": [200.0, 20.0]})).is_err());
+        assert!(GeoShape::from_geojson(&json!({"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1]]]})).is_err());
+        assert!(GeoShape::from_geojson(&json!({"type": "Circle", "coordinates": [0, 0]})).is_err());
+    }
+
+    #[test]
+    fn test_parse_point() {
+        let point = GeoPoint::new(4.89, 52.37);
+
+        assert_eq!(GeoPoint::from_json(&json!({"lat": 52.37, "lon": 4.89})), Ok(point));
+        assert_eq!(GeoPoint::from_json(&json!("52.37, 4.89")), Ok(point));
+        assert_eq!(GeoPoint::from_json(&json!([4.89, 52.37])), Ok(point));
+
+        assert!(GeoPoint::from_json(&json!({"lat": 52.37})).is_err());
+        assert!(GeoPoint::from_json(&json!("52.37")).is_err());
+        assert!(GeoPoint::from_json(&json!("152.37,4.89")).is_err());
+        assert!(GeoPoint::from_json(&json!(52.37)).is_err());
+    }
+
+    #[test]
+    fn test_geohash() {
+        let point = GeoPoint::new(10.40744, 57.64911);
+
+        assert_eq!(point.geohash(11), "u4pruydqqvj");
+        assert_eq!(point.geohash(1), "u");
+        assert!(GeoShape::Point(point).is_within(&cell_shape(&point.geohash(8))));
+    }
+
+    #[test]
+    fn test_distance() {
+        let amsterdam = GeoPoint::new(4.8952, 52.3702);
+        let berlin = GeoPoint::new(13.4050, 52.5200);
+
+        assert_eq!(amsterdam.arc_distance(&amsterdam), 0.0);
+        assert!((amsterdam.arc_distance(&berlin) - 576664.5).abs() < 1.0);
+        assert!((amsterdam.plane_distance(&berlin) - 576998.8).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_intersects() {
+        let shape = square(0.0, 10.0);
+
+        assert!(GeoShape::Point(GeoPoint::new(5.0, 5.0)).intersects(&shape));
+        assert!(GeoShape::Point(GeoPoint::new(10.0, 5.0)).intersects(&shape));
+        assert!(!GeoShape::Point(GeoPoint::new(11.0, 5.0)).intersects(&shape));
+        assert!(GeoShape::LineString(vec![GeoPoint::new(-5.0, 5.0), GeoPoint::new(15.0, 5.0)]).intersects(&shape));
+        assert!(square(9.0, 20.0).intersects(&shape));
+        assert!(square(2.0, 3.0).intersects(&shape));
+        assert!(!square(11.0, 20.0).intersects(&shape));
+    }
+
+    #[test]
+    fn test_within() {
+        let shape = square(0.0, 10.0);
+
+        assert!(square(2.0, 3.0).is_within(&shape));
+        assert!(!square(9.0, 20.0).is_within(&shape));
+        assert!(!shape.is_within(&square(2.0, 3.0)));
+        assert!(GeoShapeRelation::Within.matches(&GeoShape::Point(GeoPoint::new(1.0, 1.0)), &shape));
+
+        // Shapes in a hole aren't within the polygon
+        let with_hole = GeoShape::from_geojson(&json!({
+            "type": "polygon",
+            "coordinates": [
+                [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]],
+                [[4, 4], [6, 4], [6, 6], [4, 6], [4, 4]]
+            ]
+        })).unwrap();
+        assert!(!GeoShape::Point(GeoPoint::new(5.0, 5.0)).is_within(&with_hole));
+        assert!(!square(3.0, 7.0).is_within(&with_hole));
+        assert!(square(1.0, 2.0).is_within(&with_hole));
+    }
+
+    #[test]
+    fn test_cell_shape() {
+        // "u4pruydqqvj" is at about 57.64911, 10.40744
+        let point = GeoShape::Point(GeoPoint::new(10.40744, 57.64911));
+        assert!(point.is_within(&cell_shape("u4pruydqqvj")));
+        assert!(!point.intersects(&cell_shape("u4pruydqqvk")));
+    }
+
+    #[test]
+    fn test_covering_cells() {
+        let point = GeoShape::Point(GeoPoint::new(10.40744, 57.64911));
+        assert_eq!(covering_cells(&point), vec!["u4pruydq".to_string()]);
+
+        // Large shapes are covered by fewer, larger cells
+        let cells = covering_cells(&square(-40.0, 40.0));
+        assert!(cells.len() <= MAX_CELLS);
+        assert!(cells.iter().all(|cell| cell.len() < 8));
+    }
+
+    #[test]
+    fn test_query_terms_find_intersecting_shapes() {
+        // A small shape inside a large one, they're covered by cells of different sizes
+        let large = square(-40.0, 40.0);
+        let small = GeoShape::Point(GeoPoint::new(1.0, 1.0));
+        let far = GeoShape::Point(GeoPoint::new(100.0, 60.0));
+
+        let shares_term = |indexed: &GeoShape, query: &GeoShape| {
+            let indexed_terms = index_terms(indexed);
+            query_terms(query).iter().any(|term| indexed_terms.contains(term))
+        };
+
+        assert!(shares_term(&large, &small));
+        assert!(shares_term(&small, &large));
+        assert!(!shares_term(&far, &large));
+        assert!(index_terms(&small).contains(&Term::from_string("s0+")));
+    }
+}
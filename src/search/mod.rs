@@ -1,17 +1,25 @@
 pub mod term;
 pub mod token;
 pub mod term_vector;
+pub mod positions;
 pub mod schema;
 pub mod document;
 pub mod segment;
+pub mod statistic_key;
 pub mod similarity;
+pub mod geo;
+pub mod random_score;
+pub mod feature;
+pub mod runtime;
 pub mod query;
 pub mod collectors;
+pub mod aggregations;
+pub mod rescore;
 pub mod backends;
 
-pub use search::term::{Term, TermId};
-pub use search::token::Token;
+pub use search::term::{Term, TermId, RangeBound};
+pub use search::token::{Token, AnalysisToken};
 pub use search::document::{Document, DocId};
 pub use search::query::multi_term_selector::MultiTermSelector;
 pub use search::query::term_scorer::TermScorer;
-pub use search::query::Query;
+pub use search::query::{Query, JoinType};
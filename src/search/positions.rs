@@ -0,0 +1,284 @@
+//! Encoding for the positions of a term in a field
+//!
+//! Positions are stored in blocks of up to 128. Each block starts with a header of
+//! three variable-byte integers: the number of positions in the block, the first
+//! position and the length of the rest of the block in bytes. The rest of the block
+//! contains the gaps between consecutive positions, also as variable-byte integers.
+//!
+//! Gaps are usually small so most positions take a single byte. The header allows
+//! a decoder to skip over a whole block without decoding its gaps.
+
+/// The maximum number of positions in each block
+pub const POSITIONS_BLOCK_SIZE: usize = 128;
+
+
+fn write_vbyte(mut value: u32, bytes: &mut Vec<u8>) {
+    // Seven bits per byte, the high bit is set on all but the last byte
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+
+    bytes.push(value as u8);
+}
+
+
+/// Reads a variable-byte integer from the start of data, returning it with the number of bytes read
+fn read_vbyte(data: &[u8]) -> Option<(u32, usize)> {
+    let mut value = 0u32;
+
+    for (i, byte) in data.iter().enumerate().take(5) {
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+
+    None
+}
+
+
+/// Encodes a list of positions, these must be in ascending order
+pub fn encode_positions(positions: &[u32]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut gaps = Vec::new();
+
+    for block in positions.chunks(POSITIONS_BLOCK_SIZE) {
+        gaps.clear();
+        for pair in block.windows(2) {
+            write_vbyte(pair[1] - pair[0], &mut gaps);
+        }
+
+        write_vbyte(block.len() as u32, &mut bytes);
+        write_vbyte(block[0], &mut bytes);
+        write_vbyte(gaps.len() as u32, &mut bytes);
+        bytes.extend_from_slice(&gaps);
+    }
+
+    bytes
+}
+
+
+/// Encoded positions, as loaded from a segment
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedPositions(Vec<u8>);
+
+
+impl EncodedPositions {
+    pub fn new(bytes: Vec<u8>) -> EncodedPositions {
+        EncodedPositions(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Decodes the positions one at a time
+    pub fn iter(&self) -> PositionsDecoder {
+        PositionsDecoder::new(&self.0)
+    }
+}
+
+
+/// Header of a block of positions
+#[derive(Debug, Clone, Copy)]
+struct BlockHeader {
+    num_positions: usize,
+    first_position: u32,
+
+    /// Where the gaps start
+    gaps_start: usize,
+
+    /// Where the next block starts
+    end: usize,
+}
+
+
+fn read_block_header(data: &[u8], start: usize) -> Option<BlockHeader> {
+    let mut header = [0u32; 3];
+    let mut offset = start;
+
+    for value in header.iter_mut() {
+        match read_vbyte(&data[offset..]) {
+            Some((header_value, read)) => {
+                *value = header_value;
+                offset += read;
+            }
+            None => return None,
+        }
+    }
+
+    let (num_positions, first_position, gaps_length) = (header[0], header[1], header[2]);
+
+    let end = offset + gaps_length as usize;
+    if num_positions == 0 || end > data.len() {
+        return None;
+    }
+
+    Some(BlockHeader {
+        num_positions: num_positions as usize,
+        first_position: first_position,
+        gaps_start: offset,
+        end: end,
+    })
+}
+
+
+/// Decodes positions without loading them all into memory
+///
+/// Decoding stops at the first invalid block, so corrupt data yields fewer positions
+/// rather than an error.
+pub struct PositionsDecoder<'a> {
+    data: &'a [u8],
+
+    /// The block currently being decoded and the number of its positions that have been returned
+    block: Option<BlockHeader>,
+    block_returned: usize,
+
+    /// Offset of the next gap to read in the current block
+    offset: usize,
+    last_position: u32,
+}
+
+
+impl<'a> PositionsDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> PositionsDecoder<'a> {
+        PositionsDecoder {
+            data: data,
+            block: read_block_header(data, 0),
+            block_returned: 0,
+            offset: 0,
+            last_position: 0,
+        }
+    }
+
+    /// Moves to the first position that is greater than or equal to target and returns it
+    ///
+    /// Whole blocks are skipped without being decoded if the next block starts at or
+    /// before the target.
+    pub fn advance_to(&mut self, target: u32) -> Option<u32> {
+        // Skip blocks, only possible if nothing has been read from the current one yet
+        while let Some(block) = self.block {
+            if self.block_returned != 0 {
+                break;
+            }
+
+            match read_block_header(self.data, block.end) {
+                Some(next_block) if next_block.first_position <= target => {
+                    self.block = Some(next_block);
+                }
+                _ => break,
+            }
+        }
+
+        while let Some(position) = self.next() {
+            if position >= target {
+                return Some(position);
+            }
+        }
+
+        None
+    }
+}
+
+
+impl<'a> Iterator for PositionsDecoder<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let block = match self.block {
+            Some(block) => block,
+            None => return None,
+        };
+
+        let position = if self.block_returned == 0 {
+            self.offset = block.gaps_start;
+            block.first_position
+        } else {
+            match read_vbyte(&self.data[self.offset..block.end]) {
+                Some((gap, read)) => {
+                    self.offset += read;
+                    self.last_position + gap
+                }
+                None => {
+                    self.block = None;
+                    return None;
+                }
+            }
+        };
+
+        self.last_position = position;
+        self.block_returned += 1;
+
+        // Move on to the next block
+        if self.block_returned == block.num_positions {
+            self.block = read_block_header(self.data, block.end);
+            self.block_returned = 0;
+        }
+
+        Some(position)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_positions, EncodedPositions, PositionsDecoder};
+
+    #[test]
+    fn test_encode_decode() {
+        let positions = vec![1, 2, 5, 300, 301, 100000];
+        let encoded = EncodedPositions::new(encode_positions(&positions));
+
+        assert_eq!(encoded.iter().collect::<Vec<_>>(), positions);
+    }
+
+    #[test]
+    fn test_small_gaps_use_one_byte() {
+        let positions = (1..101).collect::<Vec<u32>>();
+        let encoded = encode_positions(&positions);
+
+        // Three bytes of header, then one byte for each gap
+        assert_eq!(encoded.len(), 3 + 99);
+    }
+
+    #[test]
+    fn test_multiple_blocks() {
+        let positions = (0..1000).map(|i| i * 3 + 1).collect::<Vec<u32>>();
+        let encoded = encode_positions(&positions);
+
+        assert_eq!(PositionsDecoder::new(&encoded).collect::<Vec<_>>(), positions);
+    }
+
+    #[test]
+    fn test_advance_to() {
+        let positions = (0..1000).map(|i| i * 3 + 1).collect::<Vec<u32>>();
+        let encoded = encode_positions(&positions);
+        let mut decoder = PositionsDecoder::new(&encoded);
+
+        assert_eq!(decoder.advance_to(0), Some(1));
+        assert_eq!(decoder.advance_to(500), Some(502));
+        assert_eq!(decoder.next(), Some(505));
+        assert_eq!(decoder.advance_to(2000), Some(2002));
+        assert_eq!(decoder.advance_to(5000), None);
+    }
+
+    #[test]
+    fn test_empty() {
+        let encoded = encode_positions(&[]);
+
+        assert!(encoded.is_empty());
+        assert_eq!(PositionsDecoder::new(&encoded).next(), None);
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let positions = (1..300).collect::<Vec<u32>>();
+        let encoded = encode_positions(&positions);
+
+        // The first two blocks are still readable
+        let decoded = PositionsDecoder::new(&encoded[..encoded.len() - 1]).collect::<Vec<_>>();
+        assert_eq!(decoded, (1..257).collect::<Vec<u32>>());
+    }
+}
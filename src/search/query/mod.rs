@@ -3,10 +3,29 @@ pub mod term_scorer;
 
 use search::term::Term;
 use search::schema::FieldId;
+use search::similarity::VectorSimilarity;
+use search::geo::{GeoShape, GeoShapeRelation};
+use search::feature::FeatureFunction;
+use search::runtime::{RuntimeField, RuntimeCondition};
 use search::query::multi_term_selector::MultiTermSelector;
 use search::query::term_scorer::TermScorer;
 
-#[derive(Debug, PartialEq)]
+/// Which side of a join relation a join query matches
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinType {
+    /// Matches parents of the documents that match the inner query
+    HasChild {
+        parent_relation: String,
+        child_relation: String,
+    },
+
+    /// Matches children of the documents that match the inner query
+    HasParent {
+        parent_relation: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Query {
     /// Matches all documents, assigning the specified score to each one
     All {
@@ -42,6 +61,26 @@ pub enum Query {
         scorer: TermScorer,
     },
 
+    /// Matches documents that contain the terms next to each other, in order, in the field
+    /// If there is a prefix, the terms must be directly followed by a term that starts with it.
+    /// Used for match_phrase_prefix queries
+    Phrase {
+        /// The field being searched
+        field: FieldId,
+
+        /// The terms to search for, in the order they must appear
+        terms: Vec<Term>,
+
+        /// The prefix of the final term
+        prefix: Option<String>,
+
+        /// The most terms that the prefix is expanded into
+        max_expansions: usize,
+
+        /// The method of scoring each term
+        scorer: TermScorer,
+    },
+
     /// Joins two queries with an AND operator
     /// This intersects the results of the queries. The scores are combined by average
     Conjunction {
@@ -59,6 +98,23 @@ pub enum Query {
     /// Unlike a regular Disjunction query, this takes the highest score of each query for a particular match
     DisjunctionMax {
         queries: Vec<Query>,
+
+        /// The scores of the other queries are multiplied by this and added to the highest score
+        tie_breaker: f32,
+    },
+
+    /// Matches documents that contain the specified term in any of the specified fields
+    /// The fields are scored as if they were one field: the term's document frequency is the
+    /// highest of any field, so a term that's rare in one field isn't favoured over a common one
+    BlendedTerm {
+        /// The fields being searched, with the method of scoring matches in each
+        fields: Vec<(FieldId, TermScorer)>,
+
+        /// The term to search for
+        term: Term,
+
+        /// The scores of the other fields are multiplied by this and added to the highest score
+        tie_breaker: f32,
     },
 
     /// Removes documents that do not match the "filter" query from the results
@@ -73,6 +129,105 @@ pub enum Query {
         query: Box<Query>,
         exclude: Box<Query>
     },
+
+    /// Matches documents that are related through a join field to documents that match the inner query
+    /// The inner query is run first, then this is replaced by a query on the keys of the related documents
+    Join {
+        /// The join field
+        field: FieldId,
+
+        /// The field that contains the key of each document (_id)
+        key_field: FieldId,
+
+        join_type: JoinType,
+        query: Box<Query>,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches documents that match the inner query, scoring them by how similar the
+    /// vector in a dense vector field is to the query vector
+    /// Documents without a vector get a score of 0
+    VectorScore {
+        /// The dense vector field
+        field: FieldId,
+
+        vector: Vec<f32>,
+        similarity: VectorSimilarity,
+        query: Box<Query>,
+
+        /// Multiplied into the similarity score
+        boost: f32,
+    },
+
+    /// Matches documents that match the inner query, giving each a reproducible random score
+    /// The score is a hash of the seed and the value of the field (or the document's key if
+    /// there is no field), multiplied by the boost. Documents without a value get a score of 0
+    RandomScore {
+        seed: u64,
+        field: Option<FieldId>,
+        query: Box<Query>,
+        boost: f32,
+    },
+
+    /// Matches documents with a shape in a geo shape field that has the relation to the query shape
+    /// The terms find the documents that might match, each of these is then checked against the stored shape
+    GeoShape {
+        /// The geo shape field
+        field: FieldId,
+
+        /// The terms of the cells that cover the query shape (see search::geo::query_terms)
+        terms: Vec<Term>,
+
+        shape: GeoShape,
+        relation: GeoShapeRelation,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches documents with a value in an integer or date field between gte and lte (inclusive)
+    /// The doc values are scanned directly, rather than expanding the range into terms.
+    /// Dates are in microseconds since the epoch
+    NumericRange {
+        /// The integer or date field
+        field: FieldId,
+
+        gte: i64,
+        lte: i64,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches documents with a value in the field, scoring them with a function of the value
+    /// Used by rank_feature and distance_feature queries. Documents with several values
+    /// get the score of their best value
+    FeatureScore {
+        field: FieldId,
+        function: FeatureFunction,
+
+        /// Multiplied into the score of the function
+        boost: f32,
+    },
+
+    /// Matches documents whose values of a runtime field meet the condition
+    /// The values are computed from each document when the query runs, so every document is checked
+    Runtime {
+        field: RuntimeField,
+        condition: RuntimeCondition,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches the same documents as the inner query, and with the same scores
+    /// The name is reported in the "matched_queries" of each hit that the inner query matches
+    Named {
+        name: String,
+        query: Box<Query>,
+    },
 }
 
 impl Query {
@@ -101,6 +256,43 @@ impl Query {
         }
     }
 
+    /// Gives the query a name, so hits can report whether they matched it
+    pub fn named(self, name: String) -> Query {
+        Query::Named {
+            name: name,
+            query: Box::new(self),
+        }
+    }
+
+    /// Finds the named queries in this query, in the order they appear
+    pub fn named_queries(&self) -> Vec<(&str, &Query)> {
+        let mut named_queries = Vec::new();
+        self.collect_named_queries(&mut named_queries);
+        named_queries
+    }
+
+    fn collect_named_queries<'a>(&'a self, named_queries: &mut Vec<(&'a str, &'a Query)>) {
+        match *self {
+            Query::All{..} | Query::None | Query::Term{..} | Query::MultiTerm{..} | Query::Phrase{..} | Query::BlendedTerm{..} | Query::GeoShape{..} | Query::NumericRange{..} | Query::FeatureScore{..} | Query::Runtime{..} => {}
+            Query::Conjunction{ref queries} | Query::Disjunction{ref queries} | Query::DisjunctionMax{ref queries, ..} => {
+                for query in queries {
+                    query.collect_named_queries(named_queries);
+                }
+            }
+            Query::Filter{ref query, filter: ref other} | Query::Exclude{ref query, exclude: ref other} => {
+                query.collect_named_queries(named_queries);
+                other.collect_named_queries(named_queries);
+            }
+            Query::Join{ref query, ..} | Query::VectorScore{ref query, ..} | Query::RandomScore{ref query, ..} => {
+                query.collect_named_queries(named_queries);
+            }
+            Query::Named{ref name, ref query} => {
+                named_queries.push((name.as_str(), &**query));
+                query.collect_named_queries(named_queries);
+            }
+        }
+    }
+
     /// Filters the query to exclude documents that match the other query
     pub fn exclude(self, exclude: Query) -> Query {
         Query::Exclude {
@@ -133,6 +325,9 @@ impl Query {
             Query::MultiTerm{ref mut scorer, ..} => {
                 scorer.boost *= add_boost;
             }
+            Query::Phrase{ref mut scorer, ..} => {
+                scorer.boost *= add_boost;
+            }
             Query::Conjunction{ref mut queries} => {
                 for query in queries {
                     query.add_boost(add_boost);
@@ -143,17 +338,34 @@ impl Query {
                     query.add_boost(add_boost);
                 }
             }
-            Query::DisjunctionMax{ref mut queries} => {
+            Query::DisjunctionMax{ref mut queries, ..} => {
                 for query in queries {
                     query.add_boost(add_boost);
                 }
             }
+            Query::BlendedTerm{ref mut fields, ..} => {
+                for &mut (_, ref mut scorer) in fields {
+                    scorer.boost *= add_boost;
+                }
+            }
             Query::Filter{ref mut query, ..} => {
                 query.add_boost(add_boost);
             }
             Query::Exclude{ref mut query, ..} => {
                 query.add_boost(add_boost);
             }
+            Query::Join{ref mut score, ..} => {
+                *score *= add_boost;
+            }
+            Query::VectorScore{ref mut boost, ..} | Query::RandomScore{ref mut boost, ..} | Query::FeatureScore{ref mut boost, ..} => {
+                *boost *= add_boost;
+            }
+            Query::GeoShape{ref mut score, ..} | Query::NumericRange{ref mut score, ..} | Query::Runtime{ref mut score, ..} => {
+                *score *= add_boost;
+            }
+            Query::Named{ref mut query, ..} => {
+                query.add_boost(add_boost);
+            }
         }
     }
 }
@@ -1,16 +1,162 @@
-use search::term::Term;
+use std::str;
 
-#[derive(Debug, PartialEq)]
+use search::term::{Term, RangeBound};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MultiTermSelector {
     Prefix(String),
+
+    /// Selects terms that are equal to the value once both are normalized
+    ///
+    /// The value must already be normalized, see `normalize_case`.
+    CaseInsensitive(String),
+
+    /// Selects terms that start with the prefix once both are normalized
+    CaseInsensitivePrefix(String),
+
+    /// Selects terms matching a pattern, where "*" matches any number of characters
+    /// and "?" matches one character
+    Wildcard {
+        pattern: String,
+        case_insensitive: bool,
+    },
+
+    /// Selects terms of a range field for one end of the range that lie within gte..lte (inclusive)
+    RangeBound {
+        bound: RangeBound,
+        gte: i64,
+        lte: i64,
+    },
+
+    /// Selects IP address terms between gte and lte (inclusive)
+    IpRange {
+        gte: Term,
+        lte: Term,
+    },
+}
+
+/// The normalizer that case insensitive selectors apply to both the value and each term
+pub fn normalize_case(value: &str) -> String {
+    value.to_lowercase()
+}
+
+/// Checks a string against a wildcard pattern
+fn matches_wildcard(pattern: &[char], string: &[char]) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+
+    // Where to resume from if the characters after the last "*" stop matching
+    let mut backtrack = None;
+
+    while s < string.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == string[s]) {
+            p += 1;
+            s += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, s));
+            p += 1;
+        } else if let Some((star_p, star_s)) = backtrack {
+            // Let the "*" match one more character
+            backtrack = Some((star_p, star_s + 1));
+            p = star_p + 1;
+            s = star_s + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
 impl MultiTermSelector {
+    pub fn case_insensitive(value: &str) -> MultiTermSelector {
+        MultiTermSelector::CaseInsensitive(normalize_case(value))
+    }
+
+    pub fn case_insensitive_prefix(prefix: &str) -> MultiTermSelector {
+        MultiTermSelector::CaseInsensitivePrefix(normalize_case(prefix))
+    }
+
+    pub fn wildcard(pattern: &str, case_insensitive: bool) -> MultiTermSelector {
+        MultiTermSelector::Wildcard {
+            pattern: if case_insensitive { normalize_case(pattern) } else { pattern.to_string() },
+            case_insensitive: case_insensitive,
+        }
+    }
+
     pub fn matches(&self, term: &Term) -> bool {
         match *self {
             MultiTermSelector::Prefix(ref prefix) => {
                 return term.as_bytes().starts_with(prefix.as_bytes());
             }
+            MultiTermSelector::CaseInsensitive(ref value) => {
+                match str::from_utf8(term.as_bytes()) {
+                    Ok(term) => normalize_case(term) == *value,
+                    Err(_) => false,
+                }
+            }
+            MultiTermSelector::CaseInsensitivePrefix(ref prefix) => {
+                match str::from_utf8(term.as_bytes()) {
+                    Ok(term) => normalize_case(term).starts_with(prefix.as_str()),
+                    Err(_) => false,
+                }
+            }
+            MultiTermSelector::Wildcard{ref pattern, case_insensitive} => {
+                let term = match str::from_utf8(term.as_bytes()) {
+                    Ok(term) => term,
+                    Err(_) => return false,
+                };
+
+                let term = if case_insensitive { normalize_case(term) } else { term.to_string() };
+                matches_wildcard(&pattern.chars().collect::<Vec<_>>(), &term.chars().collect::<Vec<_>>())
+            }
+            MultiTermSelector::RangeBound{bound, gte, lte} => {
+                match term.as_range_bound() {
+                    Some((term_bound, value)) => term_bound == bound && value >= gte && value <= lte,
+                    None => false,
+                }
+            }
+            MultiTermSelector::IpRange{ref gte, ref lte} => {
+                term.as_bytes().len() == 16 && term >= gte && term <= lte
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use search::term::Term;
+
+    use super::MultiTermSelector;
+
+    #[test]
+    fn test_case_insensitive() {
+        let selector = MultiTermSelector::case_insensitive("Hello");
+
+        assert!(selector.matches(&Term::from_string("hello")));
+        assert!(selector.matches(&Term::from_string("HELLO")));
+        assert!(!selector.matches(&Term::from_string("hello world")));
+
+        let selector = MultiTermSelector::case_insensitive_prefix("Hel");
+
+        assert!(selector.matches(&Term::from_string("HELLO")));
+        assert!(selector.matches(&Term::from_string("help")));
+        assert!(!selector.matches(&Term::from_string("world")));
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let selector = MultiTermSelector::wildcard("ki*y", false);
+
+        assert!(selector.matches(&Term::from_string("kiy")));
+        assert!(selector.matches(&Term::from_string("kitty")));
+        assert!(!selector.matches(&Term::from_string("Kitty")));
+        assert!(!selector.matches(&Term::from_string("kitten")));
+
+        let selector = MultiTermSelector::wildcard("K?t*", true);
+
+        assert!(selector.matches(&Term::from_string("kitten")));
+        assert!(selector.matches(&Term::from_string("KAT")));
+        assert!(!selector.matches(&Term::from_string("kt")));
+    }
+}
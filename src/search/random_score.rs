@@ -0,0 +1,55 @@
+//! Reproducible pseudo-random scores
+//!
+//! Each document's score is a hash of the seed and a value that doesn't change when
+//! the document is moved between segments (its key, or the value of a field). So the
+//! same seed always puts the documents in the same order, which is useful for sampling
+//! and A/B bucketing.
+
+use std::hash::Hasher;
+
+use fnv::FnvHasher;
+use byteorder::{ByteOrder, LittleEndian};
+
+
+/// Returns a score between 0 (inclusive) and 1 (exclusive) for the value
+pub fn random_score(seed: u64, value: &[u8]) -> f32 {
+    let mut seed_bytes = [0; 8];
+    LittleEndian::write_u64(&mut seed_bytes, seed);
+
+    let mut hasher = FnvHasher::default();
+    hasher.write(&seed_bytes);
+    hasher.write(value);
+
+    // FNV doesn't mix the bits well, so finish with a round of xorshift-multiply
+    let mut hash = hasher.finish();
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+
+    // f32s have 24 bits of precision
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::random_score;
+
+    #[test]
+    fn test_random_score_is_reproducible() {
+        assert_eq!(random_score(42, b"doc1"), random_score(42, b"doc1"));
+        assert!(random_score(42, b"doc1") != random_score(43, b"doc1"));
+        assert!(random_score(42, b"doc1") != random_score(42, b"doc2"));
+    }
+
+    #[test]
+    fn test_random_score_range() {
+        let scores = (0..1000).map(|i| random_score(1, format!("doc{}", i).as_bytes())).collect::<Vec<_>>();
+
+        assert!(scores.iter().all(|score| *score >= 0.0 && *score < 1.0));
+
+        // The scores should be spread out
+        let low = scores.iter().filter(|score| **score < 0.5).count();
+        assert!(low > 400 && low < 600, "{} of 1000 scores are below 0.5", low);
+    }
+}
@@ -0,0 +1,124 @@
+//! Re-scores the top hits of a search with a second query
+//!
+//! The second query is only run on the top "window_size" hits so it can be much
+//! more expensive than the main query (eg, a phrase query).
+
+use std::cmp::Ordering;
+
+use search::query::Query;
+use search::collectors::DocumentMatch;
+
+
+/// How the score of the original query is combined with the score of the rescore query
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RescoreMode {
+    Total,
+    Multiply,
+    Avg,
+    Max,
+    Min,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rescorer {
+    pub window_size: usize,
+    pub query: Query,
+    pub query_weight: f32,
+    pub rescore_query_weight: f32,
+    pub score_mode: RescoreMode,
+}
+
+
+impl Rescorer {
+    pub fn new(query: Query) -> Rescorer {
+        Rescorer {
+            window_size: 10,
+            query: query,
+            query_weight: 1.0f32,
+            rescore_query_weight: 1.0f32,
+            score_mode: RescoreMode::Total,
+        }
+    }
+
+    /// Combines the original score of a document with its score from the rescore query
+    ///
+    /// Documents that don't match the rescore query only get the weighted original score.
+    pub fn combine(&self, score: f32, rescore_score: Option<f32>) -> f32 {
+        let score = score * self.query_weight;
+        let rescore_score = match rescore_score {
+            Some(rescore_score) => rescore_score * self.rescore_query_weight,
+            None => return score,
+        };
+
+        match self.score_mode {
+            RescoreMode::Total => score + rescore_score,
+            RescoreMode::Multiply => score * rescore_score,
+            RescoreMode::Avg => (score + rescore_score) / 2.0,
+            RescoreMode::Max => score.max(rescore_score),
+            RescoreMode::Min => score.min(rescore_score),
+        }
+    }
+
+    /// Replaces the scores of the top hits and moves them into their new order
+    ///
+    /// "rescore_scores" contains the scores of the rescore query for the first
+    /// "window_size" hits. The hits after the window keep their order and stay
+    /// below the rescored ones.
+    pub fn apply(&self, hits: &mut Vec<DocumentMatch>, rescore_scores: &[Option<f32>]) {
+        let window_size = self.window_size.min(hits.len());
+
+        for (hit, rescore_score) in hits[..window_size].iter_mut().zip(rescore_scores.iter()) {
+            let score = self.combine(hit.score().unwrap_or(0.0f32), *rescore_score);
+            *hit = DocumentMatch::new_scored(hit.doc_id(), score);
+        }
+
+        hits[..window_size].sort_by(|a, b| {
+            b.score().partial_cmp(&a.score()).unwrap_or(Ordering::Equal)
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::query::Query;
+    use search::collectors::DocumentMatch;
+
+    use super::{Rescorer, RescoreMode};
+
+    #[test]
+    fn test_combine() {
+        let rescorer = Rescorer {
+            query_weight: 0.5f32,
+            rescore_query_weight: 2.0f32,
+            .. Rescorer::new(Query::all())
+        };
+
+        assert_eq!(rescorer.combine(2.0f32, Some(3.0f32)), 7.0f32);
+        assert_eq!(rescorer.combine(2.0f32, None), 1.0f32);
+        assert_eq!(Rescorer { score_mode: RescoreMode::Multiply, .. rescorer.clone() }.combine(2.0f32, Some(3.0f32)), 6.0f32);
+        assert_eq!(Rescorer { score_mode: RescoreMode::Avg, .. rescorer.clone() }.combine(2.0f32, Some(3.0f32)), 3.5f32);
+        assert_eq!(Rescorer { score_mode: RescoreMode::Max, .. rescorer.clone() }.combine(2.0f32, Some(3.0f32)), 6.0f32);
+        assert_eq!(Rescorer { score_mode: RescoreMode::Min, .. rescorer.clone() }.combine(2.0f32, Some(3.0f32)), 1.0f32);
+    }
+
+    #[test]
+    fn test_apply() {
+        let rescorer = Rescorer {
+            window_size: 2,
+            .. Rescorer::new(Query::all())
+        };
+
+        let mut hits = vec![
+            DocumentMatch::new_scored(1, 3.0f32),
+            DocumentMatch::new_scored(2, 2.0f32),
+            DocumentMatch::new_scored(3, 1.0f32),
+        ];
+        rescorer.apply(&mut hits, &[None, Some(5.0f32)]);
+
+        // Only the first two are rescored, the third stays at the bottom
+        let hits = hits.iter().map(|hit| (hit.doc_id(), hit.score())).collect::<Vec<_>>();
+        assert_eq!(hits, vec![(2, Some(7.0f32)), (1, Some(3.0f32)), (3, Some(1.0f32))]);
+    }
+}
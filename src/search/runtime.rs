@@ -0,0 +1,135 @@
+//! Fields whose values are computed from each document when a search runs
+//!
+//! Runtime fields aren't indexed, so queries on them check every document. Their
+//! values are computed from the document's source and doc values by a `RuntimeValues`
+//! implementation, which is given to the search inside the query.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde_json::{Map, Value as Json};
+
+use search::document::FieldValue;
+
+/// Computes the values of a runtime field
+pub trait RuntimeValues: fmt::Debug + Send + Sync {
+    /// The names of the fields whose doc values are needed to compute the values
+    fn doc_value_fields(&self) -> Vec<String>;
+
+    /// Computes the values of a document from its source and the doc values of the
+    /// fields returned by `doc_value_fields`
+    fn compute(&self, source: &Map<String, Json>, doc_values: &HashMap<String, Vec<FieldValue>>) -> Result<Vec<FieldValue>, String>;
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeField {
+    name: String,
+    values: Arc<RuntimeValues>,
+}
+
+impl RuntimeField {
+    pub fn new(name: String, values: Arc<RuntimeValues>) -> RuntimeField {
+        RuntimeField {
+            name: name,
+            values: values,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn doc_value_fields(&self) -> Vec<String> {
+        self.values.doc_value_fields()
+    }
+
+    pub fn compute(&self, source: &Map<String, Json>, doc_values: &HashMap<String, Vec<FieldValue>>) -> Result<Vec<FieldValue>, String> {
+        self.values.compute(source, doc_values)
+    }
+}
+
+/// Runtime fields are only equal if they share their definition
+impl PartialEq for RuntimeField {
+    fn eq(&self, other: &RuntimeField) -> bool {
+        self.name == other.name && Arc::ptr_eq(&self.values, &other.values)
+    }
+}
+
+/// Compares two values of a runtime field, integers and floats can be compared with each other
+pub fn compare_values(a: &FieldValue, b: &FieldValue) -> Option<Ordering> {
+    match (a, b) {
+        (&FieldValue::String(ref a), &FieldValue::String(ref b)) => Some(a.cmp(b)),
+        (&FieldValue::Integer(a), &FieldValue::Integer(b)) => Some(a.cmp(&b)),
+        (&FieldValue::Integer(a), &FieldValue::Float(b)) => (a as f64).partial_cmp(&b),
+        (&FieldValue::Float(a), &FieldValue::Integer(b)) => a.partial_cmp(&(b as f64)),
+        (&FieldValue::Float(a), &FieldValue::Float(b)) => a.partial_cmp(&b),
+        (&FieldValue::Boolean(a), &FieldValue::Boolean(b)) => Some(a.cmp(&b)),
+        (&FieldValue::DateTime(ref a), &FieldValue::DateTime(ref b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// What the values of a runtime field are checked against
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeCondition {
+    /// Any of the values equals any of these
+    Terms(Vec<FieldValue>),
+
+    /// Any of the values is within the bounds
+    Range {
+        gte: Option<FieldValue>,
+        gt: Option<FieldValue>,
+        lte: Option<FieldValue>,
+        lt: Option<FieldValue>,
+    },
+
+    /// The field has a value
+    Exists,
+}
+
+impl RuntimeCondition {
+    pub fn matches(&self, values: &[FieldValue]) -> bool {
+        match *self {
+            RuntimeCondition::Terms(ref terms) => {
+                values.iter().any(|value| terms.iter().any(|term| compare_values(value, term) == Some(Ordering::Equal)))
+            }
+            RuntimeCondition::Range{ref gte, ref gt, ref lte, ref lt} => {
+                values.iter().any(|value| {
+                    let check = |bound: &Option<FieldValue>, allowed: &[Ordering]| {
+                        match *bound {
+                            Some(ref bound) => compare_values(value, bound).map(|ordering| allowed.contains(&ordering)).unwrap_or(false),
+                            None => true,
+                        }
+                    };
+
+                    check(gte, &[Ordering::Greater, Ordering::Equal]) && check(gt, &[Ordering::Greater]) &&
+                        check(lte, &[Ordering::Less, Ordering::Equal]) && check(lt, &[Ordering::Less])
+                })
+            }
+            RuntimeCondition::Exists => !values.is_empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::document::FieldValue;
+
+    use super::RuntimeCondition;
+
+    #[test]
+    fn test_conditions() {
+        let values = vec![FieldValue::Integer(5), FieldValue::Float(7.5)];
+
+        assert!(RuntimeCondition::Terms(vec![FieldValue::Float(5.0)]).matches(&values));
+        assert!(!RuntimeCondition::Terms(vec![FieldValue::String("5".to_string())]).matches(&values));
+
+        assert!(RuntimeCondition::Range{gte: Some(FieldValue::Integer(6)), gt: None, lte: None, lt: Some(FieldValue::Integer(8))}.matches(&values));
+        assert!(!RuntimeCondition::Range{gte: None, gt: Some(FieldValue::Float(7.5)), lte: None, lt: None}.matches(&values));
+
+        assert!(RuntimeCondition::Exists.matches(&values));
+        assert!(!RuntimeCondition::Exists.matches(&[]));
+    }
+}
@@ -75,6 +75,18 @@ pub enum FieldType {
     I64,
     Boolean,
     DateTime,
+
+    /// Raw bytes, can only be stored
+    Binary,
+
+    /// A list of f32s, used for scoring by vector similarity
+    DenseVector,
+
+    /// A longitude and latitude, stored as two f64s
+    GeoPoint,
+
+    /// A 64 bit float, used for scoring by rank features
+    F64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -92,6 +104,10 @@ impl FieldInfo {
             field_flags: field_flags,
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
@@ -3,18 +3,32 @@ use roaring::RoaringBitmap;
 use search::schema::FieldId;
 use search::term::TermId;
 use search::document::DocId;
+use search::positions::EncodedPositions;
+use search::statistic_key::StatisticKey;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct SegmentId(pub u32);
 
 pub trait Segment {
-    fn load_statistic(&self, stat_name: &[u8]) -> Result<Option<i64>, String>;
+    fn load_statistic(&self, stat_key: &StatisticKey) -> Result<Option<i64>, String>;
     fn load_stored_field_value_raw(&self, doc_local_id: u16, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String>;
     fn load_postings_list(&self, field_id: FieldId, term_id: TermId) -> Result<Option<RoaringBitmap>, String>;
     fn load_deletion_list(&self) -> Result<Option<RoaringBitmap>, String>;
+    fn load_doc_key(&self, doc_local_id: u16) -> Result<Option<Vec<u8>>, String>;
     fn id(&self) -> SegmentId;
 
     fn doc_id(&self, local_id: u16) -> DocId {
         DocId(self.id(), local_id)
     }
+
+    /// Loads the positions of a term in a field of a document
+    ///
+    /// These are stored with the value type "pos" followed by the term id.
+    fn load_positions(&self, doc_local_id: u16, field_id: FieldId, term_id: TermId) -> Result<Option<EncodedPositions>, String> {
+        let mut value_type = vec![b'p', b'o', b's'];
+        value_type.extend(term_id.0.to_string().as_bytes());
+
+        let positions = try!(self.load_stored_field_value_raw(doc_local_id, field_id, &value_type));
+        Ok(positions.map(EncodedPositions::new))
+    }
 }
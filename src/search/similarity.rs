@@ -36,9 +36,49 @@ impl SimilarityModel {
     }
 }
 
+/// How the similarity of two dense vectors is measured
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VectorSimilarity {
+    Cosine,
+
+    /// Faster than cosine, but the vectors must have a length of 1
+    DotProduct,
+}
+
+impl Default for VectorSimilarity {
+    fn default() -> VectorSimilarity {
+        VectorSimilarity::Cosine
+    }
+}
+
+impl VectorSimilarity {
+    /// Scores how similar two vectors are, from 0.0 (opposite) to 1.0 (same direction)
+    pub fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        let dot_product = a.iter().zip(b.iter()).map(|(a, b)| a * b).sum::<f32>();
+
+        let similarity = match *self {
+            VectorSimilarity::Cosine => {
+                let a_norm = a.iter().map(|a| a * a).sum::<f32>().sqrt();
+                let b_norm = b.iter().map(|b| b * b).sum::<f32>().sqrt();
+
+                // Zero length vectors don't have a direction
+                if a_norm == 0.0 || b_norm == 0.0 {
+                    return 0.0;
+                }
+
+                dot_product / (a_norm * b_norm)
+            }
+            VectorSimilarity::DotProduct => dot_product,
+        };
+
+        // Scores can't be negative
+        ((1.0 + similarity) / 2.0).max(0.0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::SimilarityModel;
+    use super::{SimilarityModel, VectorSimilarity};
 
     #[test]
     fn test_tf_idf_higher_term_freq_increases_score() {
@@ -124,4 +164,28 @@ mod tests {
 
         assert!(similarity.score(0, 0.0, 0, 0, 0).is_finite());
     }
+
+    #[test]
+    fn test_cosine_similarity() {
+        let similarity = VectorSimilarity::Cosine;
+
+        assert_eq!(similarity.score(&[1.0, 0.0], &[2.0, 0.0]), 1.0);
+        assert_eq!(similarity.score(&[1.0, 0.0], &[0.0, 3.0]), 0.5);
+        assert_eq!(similarity.score(&[1.0, 0.0], &[-1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_cosine_similarity_handles_zeros() {
+        let similarity = VectorSimilarity::Cosine;
+
+        assert_eq!(similarity.score(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_dot_product_similarity() {
+        let similarity = VectorSimilarity::DotProduct;
+
+        assert_eq!(similarity.score(&[1.0, 0.0], &[1.0, 0.0]), 1.0);
+        assert_eq!(similarity.score(&[1.0, 0.0], &[0.0, 1.0]), 0.5);
+    }
 }
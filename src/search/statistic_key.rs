@@ -0,0 +1,110 @@
+//! Names of the statistics kept for each segment
+//!
+//! Segments store their statistics against a name, these are encoded as:
+//!
+//! - "total_docs": the number of documents inserted into the segment
+//! - "deleted_docs": the number of documents that have since been deleted
+//! - "ftdoc-{field}": the number of documents with a value in the field
+//! - "fttok-{field}": the number of tokens in the field across all documents
+//! - "tdf-{field}-{term}": the number of documents containing the term in the field
+
+use std::str;
+
+use search::schema::FieldId;
+use search::term::TermId;
+
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum StatisticKey {
+    TotalDocs,
+    DeletedDocs,
+    TotalFieldDocs(FieldId),
+    TotalFieldTokens(FieldId),
+    TermDocumentFrequency(FieldId, TermId),
+}
+
+
+impl StatisticKey {
+    /// Encodes the key into the name the statistic is stored against
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            StatisticKey::TotalDocs => b"total_docs".to_vec(),
+            StatisticKey::DeletedDocs => b"deleted_docs".to_vec(),
+            StatisticKey::TotalFieldDocs(field_id) => format!("ftdoc-{}", field_id.0).into_bytes(),
+            StatisticKey::TotalFieldTokens(field_id) => format!("fttok-{}", field_id.0).into_bytes(),
+            StatisticKey::TermDocumentFrequency(field_id, term_id) => format!("tdf-{}-{}", field_id.0, term_id.0).into_bytes(),
+        }
+    }
+
+    /// Decodes a statistic name, returns None if it isn't recognised
+    pub fn decode(name: &[u8]) -> Option<StatisticKey> {
+        let name = match str::from_utf8(name) {
+            Ok(name) => name,
+            Err(_) => return None,
+        };
+
+        match name {
+            "total_docs" => return Some(StatisticKey::TotalDocs),
+            "deleted_docs" => return Some(StatisticKey::DeletedDocs),
+            _ => {}
+        }
+
+        let mut parts = name.split('-');
+        let kind = parts.next();
+        let ids = parts.map(|part| part.parse::<u32>()).collect::<Result<Vec<u32>, _>>();
+        let ids = match ids {
+            Ok(ids) => ids,
+            Err(_) => return None,
+        };
+
+        match (kind, ids.len()) {
+            (Some("ftdoc"), 1) => Some(StatisticKey::TotalFieldDocs(FieldId(ids[0]))),
+            (Some("fttok"), 1) => Some(StatisticKey::TotalFieldTokens(FieldId(ids[0]))),
+            (Some("tdf"), 2) => Some(StatisticKey::TermDocumentFrequency(FieldId(ids[0]), TermId(ids[1]))),
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::schema::FieldId;
+    use search::term::TermId;
+
+    use super::StatisticKey;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(StatisticKey::TotalDocs.encode(), b"total_docs".to_vec());
+        assert_eq!(StatisticKey::DeletedDocs.encode(), b"deleted_docs".to_vec());
+        assert_eq!(StatisticKey::TotalFieldDocs(FieldId(3)).encode(), b"ftdoc-3".to_vec());
+        assert_eq!(StatisticKey::TotalFieldTokens(FieldId(3)).encode(), b"fttok-3".to_vec());
+        assert_eq!(StatisticKey::TermDocumentFrequency(FieldId(3), TermId(12)).encode(), b"tdf-3-12".to_vec());
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        let keys = vec![
+            StatisticKey::TotalDocs,
+            StatisticKey::DeletedDocs,
+            StatisticKey::TotalFieldDocs(FieldId(1)),
+            StatisticKey::TotalFieldTokens(FieldId(42)),
+            StatisticKey::TermDocumentFrequency(FieldId(7), TermId(100000)),
+        ];
+
+        for key in keys {
+            assert_eq!(StatisticKey::decode(&key.encode()), Some(key));
+        }
+    }
+
+    #[test]
+    fn test_decode_unrecognised() {
+        assert_eq!(StatisticKey::decode(b"foo"), None);
+        assert_eq!(StatisticKey::decode(b"ftdoc"), None);
+        assert_eq!(StatisticKey::decode(b"ftdoc-x"), None);
+        assert_eq!(StatisticKey::decode(b"ftdoc-1-2"), None);
+        assert_eq!(StatisticKey::decode(b"tdf-1"), None);
+        assert_eq!(StatisticKey::decode(&[0xff, 0xfe]), None);
+    }
+}
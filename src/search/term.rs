@@ -1,11 +1,51 @@
+use std::net::IpAddr;
+
 use chrono::{DateTime, Utc, Timelike};
-use byteorder::{WriteBytesExt, LittleEndian};
+use byteorder::{ByteOrder, WriteBytesExt, LittleEndian};
 
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct TermId(pub u32);
 
 
+/// Which end of a range a term of a range field represents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeBound {
+    Lower,
+    Upper,
+}
+
+
+impl RangeBound {
+    fn prefix(&self) -> u8 {
+        match *self {
+            RangeBound::Lower => b'l',
+            RangeBound::Upper => b'u',
+        }
+    }
+}
+
+
+/// Converts a datetime into microseconds since the epoch, this is how datetimes are represented in terms
+pub fn datetime_to_micros(value: &DateTime<Utc>) -> i64 {
+    let timestamp = value.timestamp();
+    let micros = value.nanosecond() / 1000;
+    timestamp * 1000000 + micros as i64
+}
+
+
+/// Converts an IP address into the 16 byte representation used in terms
+///
+/// IPv4 addresses are mapped into the IPv6 address space (::ffff:0:0/96) so both
+/// kinds of address sort together when compared byte by byte.
+pub fn ip_to_bytes(value: &IpAddr) -> [u8; 16] {
+    match *value {
+        IpAddr::V4(ref ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ref ip) => ip.octets(),
+    }
+}
+
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub struct Term(Vec<u8>);
 
@@ -15,13 +55,12 @@ impl Term {
     }
 
     pub fn from_string(string: &str) -> Term {
-        let mut bytes = Vec::with_capacity(string.len());
-
-        for byte in string.as_bytes() {
-            bytes.push(*byte);
-        }
+        Term(string.as_bytes().to_vec())
+    }
 
-        Term(bytes)
+    /// Creates a term from a string without copying it
+    pub fn from_owned_string(string: String) -> Term {
+        Term(string.into_bytes())
     }
 
     pub fn from_boolean(value: bool) -> Term {
@@ -40,13 +79,45 @@ impl Term {
 
     pub fn from_datetime(value: &DateTime<Utc>) -> Term {
         let mut bytes = Vec::with_capacity(0);
-        let timestamp = value.timestamp();
-        let micros = value.nanosecond() / 1000;
-        let timestamp_with_micros = timestamp * 1000000 + micros as i64;
-        bytes.write_i64::<LittleEndian>(timestamp_with_micros).unwrap();
+        bytes.write_i64::<LittleEndian>(datetime_to_micros(value)).unwrap();
         Term(bytes)
     }
 
+    pub fn from_ip(value: &IpAddr) -> Term {
+        Term(ip_to_bytes(value).to_vec())
+    }
+
+    /// Creates the term that links a child document in a join field to its parent
+    pub fn from_join_parent(parent_relation: &str, parent_key: &str) -> Term {
+        Term::from_string(&format!("{}#{}", parent_relation, parent_key))
+    }
+
+    /// Creates a term for one end of the range in a range field
+    ///
+    /// Both ends of the range are indexed into the same field, the prefix byte
+    /// tells them apart.
+    pub fn from_range_bound(bound: RangeBound, value: i64) -> Term {
+        let mut bytes = Vec::with_capacity(9);
+        bytes.push(bound.prefix());
+        bytes.write_i64::<LittleEndian>(value).unwrap();
+        Term(bytes)
+    }
+
+    /// Reads a term created by from_range_bound
+    pub fn as_range_bound(&self) -> Option<(RangeBound, i64)> {
+        if self.0.len() != 9 {
+            return None;
+        }
+
+        let bound = match self.0[0] {
+            b'l' => RangeBound::Lower,
+            b'u' => RangeBound::Upper,
+            _ => return None,
+        };
+
+        Some((bound, LittleEndian::read_i64(&self.0[1..])))
+    }
+
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
     }
@@ -55,7 +126,9 @@ impl Term {
 #[cfg(test)]
 mod tests {
     use chrono::{DateTime, Utc, Timelike};
-    use super::Term;
+    use std::net::IpAddr;
+
+    use super::{Term, RangeBound};
 
     #[test]
     fn test_string_to_bytes() {
@@ -134,4 +207,41 @@ mod tests {
         // This is exactly 3_600_000_000 lower than the result of "test_datetime_to_bytes"
         assert_eq!(term.as_bytes().to_vec(), vec![0, 193, 43, 45, 78, 56, 5, 0])
     }
+
+    #[test]
+    fn test_range_bound_to_bytes() {
+        let term = Term::from_range_bound(RangeBound::Upper, 123);
+
+        assert_eq!(term.as_bytes().to_vec(), vec![b'u', 123, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(term.as_range_bound(), Some((RangeBound::Upper, 123)));
+    }
+
+    #[test]
+    fn test_as_range_bound_other_term() {
+        assert_eq!(Term::from_integer(123).as_range_bound(), None);
+        assert_eq!(Term::from_string("xyz123456").as_range_bound(), None);
+        assert_eq!(Term::from_string("foo").as_range_bound(), None);
+    }
+
+    #[test]
+    fn test_ipv4_to_bytes() {
+        let term = Term::from_ip(&"192.168.0.1".parse::<IpAddr>().unwrap());
+
+        assert_eq!(term.as_bytes().to_vec(), vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 192, 168, 0, 1])
+    }
+
+    #[test]
+    fn test_ipv6_to_bytes() {
+        let term = Term::from_ip(&"2001:db8::1".parse::<IpAddr>().unwrap());
+
+        assert_eq!(term.as_bytes().to_vec(), vec![32, 1, 13, 184, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1])
+    }
+
+    #[test]
+    fn test_ip_terms_sort_by_address() {
+        let low = Term::from_ip(&"10.0.0.9".parse::<IpAddr>().unwrap());
+        let high = Term::from_ip(&"10.0.0.10".parse::<IpAddr>().unwrap());
+
+        assert!(low < high);
+    }
 }
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use search::term::Term;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -5,3 +7,36 @@ pub struct Token {
     pub term: Term,
     pub position: u32,
 }
+
+/// A token as it passes through the tokenizer and filters of an analyzer
+///
+/// Tokenizers borrow the text of each token from the input, and filters only copy it
+/// when they change it. It's converted into a `Token`, which owns its term, when it
+/// comes out of the analyzer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalysisToken<'a> {
+    pub text: Cow<'a, str>,
+    pub position: u32,
+}
+
+impl<'a> AnalysisToken<'a> {
+    pub fn new<T: Into<Cow<'a, str>>>(text: T, position: u32) -> AnalysisToken<'a> {
+        AnalysisToken {
+            text: text.into(),
+            position: position,
+        }
+    }
+
+    /// Converts into a `Token`, text that a filter has already copied isn't copied again
+    pub fn into_token(self) -> Token {
+        let term = match self.text {
+            Cow::Borrowed(text) => Term::from_string(text),
+            Cow::Owned(text) => Term::from_owned_string(text),
+        };
+
+        Token {
+            term: term,
+            position: self.position,
+        }
+    }
+}
@@ -0,0 +1,592 @@
+//! Node settings that can be changed while the node is running
+//!
+//! Settings are changed through the "_cluster/settings" API as either "persistent" or
+//! "transient". Persistent settings are saved to "cluster_settings.json" in the data
+//! directory, with the node's other configuration files, and are loaded again when it
+//! starts. Transient settings are lost on restart.
+//!
+//! A transient setting takes the place of a persistent one, which takes the place of
+//! the default. Setting a value to null resets it.
+//!
+//! ```json
+//! {
+//!     "persistent": {"indices.query.bool.max_clause_count": 2048},
+//!     "transient": {"search": {"slowlog": {"threshold": {"query": {"warn": "2s"}}}}}
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use serde_json::{self, Value as Json};
+use url::Url;
+use atomicwrites::{AtomicFile, AllowOverwrite};
+
+use bulk_queue::DEFAULT_MAX_REQUESTS;
+use search::backends::rocksdb::QueryLimits;
+
+
+/// The most term clauses that a query can expand into
+pub const MAX_CLAUSE_COUNT: &'static str = "indices.query.bool.max_clause_count";
+
+/// The most terms that a single multi-term query (eg, prefix) can expand into
+pub const MAX_EXPANSIONS: &'static str = "indices.query.max_expansions";
+
+/// The most bulk requests that can be processed at the same time
+pub const WRITE_QUEUE_SIZE: &'static str = "thread_pool.write.queue_size";
+
+/// Searches that take longer than these are logged, "-1" turns them off
+pub const SEARCH_SLOWLOG_WARN: &'static str = "search.slowlog.threshold.query.warn";
+pub const SEARCH_SLOWLOG_INFO: &'static str = "search.slowlog.threshold.query.info";
+
+/// How full the disk can get, as a percentage of its size or the number of bytes
+/// that must be left free
+///
+/// These are checked and stored, but aren't enforced yet.
+pub const DISK_WATERMARK_LOW: &'static str = "cluster.routing.allocation.disk.watermark.low";
+pub const DISK_WATERMARK_HIGH: &'static str = "cluster.routing.allocation.disk.watermark.high";
+pub const DISK_WATERMARK_FLOOD_STAGE: &'static str = "cluster.routing.allocation.disk.watermark.flood_stage";
+
+/// Where the traces of requests are sent: "none", "log" or "otlp"
+pub const TRACING_EXPORTER: &'static str = "tracing.exporter";
+
+/// The URL that traces are posted to when they're exported with OTLP
+pub const TRACING_OTLP_ENDPOINT: &'static str = "tracing.otlp.endpoint";
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SettingType {
+    PositiveInteger,
+    TimeValue,
+    DiskWatermark,
+    TracingExporter,
+    HttpUrl,
+}
+
+
+fn setting_type(name: &str) -> Option<SettingType> {
+    match name {
+        MAX_CLAUSE_COUNT | MAX_EXPANSIONS | WRITE_QUEUE_SIZE => Some(SettingType::PositiveInteger),
+        SEARCH_SLOWLOG_WARN | SEARCH_SLOWLOG_INFO => Some(SettingType::TimeValue),
+        DISK_WATERMARK_LOW | DISK_WATERMARK_HIGH | DISK_WATERMARK_FLOOD_STAGE => Some(SettingType::DiskWatermark),
+        TRACING_EXPORTER => Some(SettingType::TracingExporter),
+        TRACING_OTLP_ENDPOINT => Some(SettingType::HttpUrl),
+        _ => None,
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiskWatermark {
+    /// How full the disk can get, between 0 and 100
+    Percentage(f64),
+
+    /// How many bytes must be left free
+    Bytes(u64),
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TracingExporter {
+    None,
+    Log,
+    Otlp,
+}
+
+
+fn parse_tracing_exporter(value: &Json) -> Option<TracingExporter> {
+    match value.as_str() {
+        Some("none") => Some(TracingExporter::None),
+        Some("log") => Some(TracingExporter::Log),
+        Some("otlp") => Some(TracingExporter::Otlp),
+        _ => None,
+    }
+}
+
+
+/// Only plain HTTP is supported, see `remote::http`
+fn parse_http_url(value: &Json) -> Option<Url> {
+    value.as_str().and_then(|url| Url::parse(url).ok()).and_then(|url| if url.scheme() == "http" { Some(url) } else { None })
+}
+
+
+fn parse_positive_integer(value: &Json) -> Option<usize> {
+    let value = match *value {
+        Json::Number(ref number) => number.as_u64().map(|number| number as usize),
+        Json::String(ref string) => string.parse().ok(),
+        _ => None,
+    };
+
+    value.and_then(|value| if value > 0 { Some(value) } else { None })
+}
+
+
+/// Parses a duration like "500ms" or "2s", the outer None means it's invalid and the
+/// inner None means it's turned off ("-1")
+///
+/// Numbers are in milliseconds.
+fn parse_time_value(value: &Json) -> Option<Option<Duration>> {
+    let string = match *value {
+        Json::Number(ref number) => {
+            if number.as_i64() == Some(-1) {
+                return Some(None);
+            }
+
+            return number.as_u64().map(|millis| Some(Duration::from_millis(millis)));
+        }
+        Json::String(ref string) => string.trim(),
+        _ => return None,
+    };
+
+    if string == "-1" {
+        return Some(None);
+    }
+
+    let (number, unit_millis) = if string.ends_with("ms") {
+        (&string[..string.len() - 2], 1)
+    } else if string.ends_with('s') {
+        (&string[..string.len() - 1], 1000)
+    } else if string.ends_with('m') {
+        (&string[..string.len() - 1], 60 * 1000)
+    } else if string.ends_with('h') {
+        (&string[..string.len() - 1], 60 * 60 * 1000)
+    } else if string.ends_with('d') {
+        (&string[..string.len() - 1], 24 * 60 * 60 * 1000)
+    } else {
+        return None;
+    };
+
+    number.trim().parse::<u64>().ok().map(|number| Some(Duration::from_millis(number * unit_millis)))
+}
+
+
+/// Parses a size like "500mb" or "10gb", numbers without a unit are in bytes
+fn parse_byte_size(string: &str) -> Option<u64> {
+    let string = string.trim().to_lowercase();
+    let units: [(&str, u64); 5] = [("tb", 1 << 40), ("gb", 1 << 30), ("mb", 1 << 20), ("kb", 1 << 10), ("b", 1)];
+
+    for &(suffix, multiplier) in units.iter() {
+        if string.ends_with(suffix) {
+            return string[..string.len() - suffix.len()].trim().parse::<u64>().ok().map(|number| number * multiplier);
+        }
+    }
+
+    string.parse().ok()
+}
+
+
+fn parse_disk_watermark(value: &Json) -> Option<DiskWatermark> {
+    match *value {
+        Json::String(ref string) => {
+            let string = string.trim();
+            if string.ends_with('%') {
+                match string[..string.len() - 1].trim().parse::<f64>() {
+                    Ok(percentage) if percentage >= 0.0 && percentage <= 100.0 => Some(DiskWatermark::Percentage(percentage)),
+                    _ => None,
+                }
+            } else {
+                parse_byte_size(string).map(DiskWatermark::Bytes)
+            }
+        }
+        Json::Number(ref number) => number.as_u64().map(DiskWatermark::Bytes),
+        _ => None,
+    }
+}
+
+
+fn is_valid(setting_type: SettingType, value: &Json) -> bool {
+    match setting_type {
+        SettingType::PositiveInteger => parse_positive_integer(value).is_some(),
+        SettingType::TimeValue => parse_time_value(value).is_some(),
+        SettingType::DiskWatermark => parse_disk_watermark(value).is_some(),
+        SettingType::TracingExporter => parse_tracing_exporter(value).is_some(),
+        SettingType::HttpUrl => parse_http_url(value).is_some(),
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum SettingsError {
+    /// "persistent" or "transient" wasn't an object
+    ExpectedObject(String),
+
+    UnknownSetting(String),
+    InvalidValue(String, Json),
+
+    /// The low, high and flood stage disk watermarks must be in that order
+    WatermarksOutOfOrder,
+
+    /// The persistent settings couldn't be saved
+    SaveFailed(String),
+}
+
+
+impl SettingsError {
+    pub fn message(&self) -> String {
+        match *self {
+            SettingsError::ExpectedObject(ref key) => format!("\"{}\" must be an object", key),
+            SettingsError::UnknownSetting(ref name) => format!("unknown setting [{}]", name),
+            SettingsError::InvalidValue(ref name, ref value) => format!("invalid value {} for setting [{}]", value, name),
+            SettingsError::WatermarksOutOfOrder => "the low disk watermark must be below the high watermark, which must be below the flood stage".to_string(),
+            SettingsError::SaveFailed(ref error) => format!("failed to save settings: {}", error),
+        }
+    }
+}
+
+
+/// Turns nested objects into dotted names, so {"search": {"slowlog": ...}} is the same
+/// as {"search.slowlog": ...}
+fn flatten(prefix: &str, json: &Json, settings: &mut BTreeMap<String, Json>) {
+    match *json {
+        Json::Object(ref object) => {
+            for (key, value) in object.iter() {
+                let name = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&name, value, settings);
+            }
+        }
+        ref value => {
+            settings.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+
+/// Reads the settings in a "persistent" or "transient" object and checks them
+///
+/// Null values are kept, they reset the setting.
+fn parse_settings(key: &str, json: &Json) -> Result<BTreeMap<String, Json>, SettingsError> {
+    if !json.is_object() {
+        return Err(SettingsError::ExpectedObject(key.to_string()));
+    }
+
+    let mut settings = BTreeMap::new();
+    flatten("", json, &mut settings);
+
+    for (name, value) in settings.iter() {
+        let setting_type = setting_type(name).ok_or_else(|| SettingsError::UnknownSetting(name.clone()))?;
+        if !value.is_null() && !is_valid(setting_type, value) {
+            return Err(SettingsError::InvalidValue(name.clone(), value.clone()));
+        }
+    }
+
+    Ok(settings)
+}
+
+
+fn apply_changes(settings: &mut BTreeMap<String, Json>, changes: &BTreeMap<String, Json>) {
+    for (name, value) in changes.iter() {
+        if value.is_null() {
+            settings.remove(name);
+        } else {
+            settings.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+
+fn read_json_file(path: &Path) -> Result<Option<String>, String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("failed to open {}: {}", path.display(), e)),
+    };
+
+    let mut s = String::new();
+    file.read_to_string(&mut s).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    Ok(Some(s))
+}
+
+
+#[derive(Debug, Clone, Default)]
+struct SettingValues {
+    persistent: BTreeMap<String, Json>,
+    transient: BTreeMap<String, Json>,
+}
+
+
+impl SettingValues {
+    fn get(&self, name: &str) -> Option<&Json> {
+        self.transient.get(name).or_else(|| self.persistent.get(name))
+    }
+}
+
+
+#[derive(Debug)]
+pub struct NodeSettings {
+    defaults: BTreeMap<String, Json>,
+    values: RwLock<SettingValues>,
+
+    /// Where the persistent settings are saved, None if they aren't
+    path: Option<PathBuf>,
+}
+
+
+impl NodeSettings {
+    pub fn new(defaults: BTreeMap<String, Json>, persistent: BTreeMap<String, Json>, path: Option<PathBuf>) -> NodeSettings {
+        NodeSettings {
+            defaults: defaults,
+            values: RwLock::new(SettingValues {
+                persistent: persistent,
+                transient: BTreeMap::new(),
+            }),
+            path: path,
+        }
+    }
+
+    /// The value of each setting when it hasn't been set, the query limits are given
+    /// when the node starts
+    pub fn default_values(query_limits: &QueryLimits) -> BTreeMap<String, Json> {
+        let mut defaults = BTreeMap::new();
+        defaults.insert(MAX_CLAUSE_COUNT.to_string(), json!(query_limits.max_clause_count));
+        defaults.insert(MAX_EXPANSIONS.to_string(), json!(query_limits.max_expansions));
+        defaults.insert(WRITE_QUEUE_SIZE.to_string(), json!(DEFAULT_MAX_REQUESTS));
+        defaults.insert(SEARCH_SLOWLOG_WARN.to_string(), json!("-1"));
+        defaults.insert(SEARCH_SLOWLOG_INFO.to_string(), json!("-1"));
+        defaults.insert(DISK_WATERMARK_LOW.to_string(), json!("85%"));
+        defaults.insert(DISK_WATERMARK_HIGH.to_string(), json!("90%"));
+        defaults.insert(DISK_WATERMARK_FLOOD_STAGE.to_string(), json!("95%"));
+        defaults.insert(TRACING_EXPORTER.to_string(), json!("none"));
+        defaults.insert(TRACING_OTLP_ENDPOINT.to_string(), json!("http://localhost:4318/v1/traces"));
+        defaults
+    }
+
+    /// Loads the persistent settings saved in the data directory
+    pub fn load(data_dir: &Path, query_limits: &QueryLimits) -> Result<NodeSettings, String> {
+        let path = data_dir.join("cluster_settings.json");
+        let persistent = match read_json_file(&path)? {
+            Some(s) => {
+                let json: Json = serde_json::from_str(&s).map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+                parse_settings("persistent", &json).map_err(|e| format!("invalid settings in {}: {}", path.display(), e.message()))?
+            }
+            None => BTreeMap::new(),
+        };
+
+        Ok(NodeSettings::new(NodeSettings::default_values(query_limits), persistent, Some(path)))
+    }
+
+    pub fn defaults(&self) -> BTreeMap<String, Json> {
+        self.defaults.clone()
+    }
+
+    pub fn persistent(&self) -> BTreeMap<String, Json> {
+        self.values.read().unwrap().persistent.clone()
+    }
+
+    pub fn transient(&self) -> BTreeMap<String, Json> {
+        self.values.read().unwrap().transient.clone()
+    }
+
+    /// The value that's used for each setting
+    pub fn effective(&self) -> BTreeMap<String, Json> {
+        let values = self.values.read().unwrap();
+        let mut settings = self.defaults.clone();
+        apply_changes(&mut settings, &values.persistent);
+        apply_changes(&mut settings, &values.transient);
+        settings
+    }
+
+    /// Where the persistent settings are saved
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_ref().map(|path| path.as_path())
+    }
+
+    /// The value that's used for a setting
+    pub fn get(&self, name: &str) -> Json {
+        let values = self.values.read().unwrap();
+        values.get(name).or_else(|| self.defaults.get(name)).cloned().unwrap_or(Json::Null)
+    }
+
+    /// Falls back to the default if the value can't be read, which only happens if the
+    /// setting isn't a positive integer
+    pub fn get_positive_integer(&self, name: &str) -> usize {
+        parse_positive_integer(&self.get(name))
+            .or_else(|| self.defaults.get(name).and_then(parse_positive_integer))
+            .unwrap_or(1)
+    }
+
+    /// Returns None if the setting is turned off
+    pub fn get_time_value(&self, name: &str) -> Option<Duration> {
+        parse_time_value(&self.get(name)).unwrap_or(None)
+    }
+
+    pub fn get_disk_watermark(&self, name: &str) -> Option<DiskWatermark> {
+        parse_disk_watermark(&self.get(name))
+    }
+
+    pub fn get_tracing_exporter(&self) -> TracingExporter {
+        parse_tracing_exporter(&self.get(TRACING_EXPORTER)).unwrap_or(TracingExporter::None)
+    }
+
+    pub fn get_otlp_endpoint(&self) -> Option<Url> {
+        parse_http_url(&self.get(TRACING_OTLP_ENDPOINT))
+    }
+
+    /// The query limits that searches are checked against
+    pub fn query_limits(&self) -> QueryLimits {
+        QueryLimits {
+            max_clause_count: self.get_positive_integer(MAX_CLAUSE_COUNT),
+            max_expansions: self.get_positive_integer(MAX_EXPANSIONS),
+        }
+    }
+
+    /// Checks that the watermarks are in order, this can only be done if they're all
+    /// the same kind
+    fn check_watermarks(&self, values: &SettingValues) -> Result<(), SettingsError> {
+        let get = |name: &str| values.get(name).or_else(|| self.defaults.get(name)).and_then(parse_disk_watermark);
+
+        match (get(DISK_WATERMARK_LOW), get(DISK_WATERMARK_HIGH), get(DISK_WATERMARK_FLOOD_STAGE)) {
+            (Some(DiskWatermark::Percentage(low)), Some(DiskWatermark::Percentage(high)), Some(DiskWatermark::Percentage(flood_stage))) => {
+                if low > high || high > flood_stage {
+                    return Err(SettingsError::WatermarksOutOfOrder);
+                }
+            }
+            (Some(DiskWatermark::Bytes(low)), Some(DiskWatermark::Bytes(high)), Some(DiskWatermark::Bytes(flood_stage))) => {
+                // These are the bytes left free, so they go the other way
+                if low < high || high < flood_stage {
+                    return Err(SettingsError::WatermarksOutOfOrder);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Changes the settings in the "persistent" and "transient" objects of a request
+    ///
+    /// Nothing is changed unless every setting is valid. Returns the settings that
+    /// were changed, with dotted names.
+    pub fn update(&self, persistent: Option<&Json>, transient: Option<&Json>) -> Result<(BTreeMap<String, Json>, BTreeMap<String, Json>), SettingsError> {
+        let persistent_changes = match persistent {
+            Some(persistent) => parse_settings("persistent", persistent)?,
+            None => BTreeMap::new(),
+        };
+        let transient_changes = match transient {
+            Some(transient) => parse_settings("transient", transient)?,
+            None => BTreeMap::new(),
+        };
+
+        let mut values = self.values.write().unwrap();
+        let mut new_values = values.clone();
+        apply_changes(&mut new_values.persistent, &persistent_changes);
+        apply_changes(&mut new_values.transient, &transient_changes);
+        self.check_watermarks(&new_values)?;
+
+        // Save the persistent settings before they're used, so they're never lost on restart
+        if new_values.persistent != values.persistent {
+            if let Some(ref path) = self.path {
+                let s = serde_json::to_string_pretty(&new_values.persistent).unwrap();
+                let file = AtomicFile::new(path, AllowOverwrite);
+                file.write(|f| f.write_all(s.as_bytes())).map_err(|e| SettingsError::SaveFailed(format!("{}", e)))?;
+            }
+        }
+
+        *values = new_values;
+        Ok((persistent_changes, transient_changes))
+    }
+}
+
+
+impl Default for NodeSettings {
+    fn default() -> NodeSettings {
+        NodeSettings::new(NodeSettings::default_values(&QueryLimits::default()), BTreeMap::new(), None)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use super::{NodeSettings, SettingsError, DiskWatermark, TracingExporter, parse_byte_size, MAX_CLAUSE_COUNT, WRITE_QUEUE_SIZE,
+                SEARCH_SLOWLOG_WARN, DISK_WATERMARK_LOW, DISK_WATERMARK_HIGH};
+
+    #[test]
+    fn test_defaults_are_valid() {
+        let settings = NodeSettings::default();
+        let defaults = settings.defaults();
+
+        for (name, value) in defaults.iter() {
+            let setting_type = super::setting_type(name).unwrap();
+            assert!(super::is_valid(setting_type, value), "invalid default for {}", name);
+        }
+    }
+
+    #[test]
+    fn test_transient_overrides_persistent() {
+        let settings = NodeSettings::default();
+        settings.update(Some(&json!({"indices.query.bool.max_clause_count": 100})), None).unwrap();
+        assert_eq!(settings.query_limits().max_clause_count, 100);
+
+        settings.update(None, Some(&json!({"indices": {"query": {"bool": {"max_clause_count": "50"}}}}))).unwrap();
+        assert_eq!(settings.get_positive_integer(MAX_CLAUSE_COUNT), 50);
+
+        // Resetting the transient setting goes back to the persistent one
+        settings.update(None, Some(&json!({"indices.query.bool.max_clause_count": null}))).unwrap();
+        assert_eq!(settings.get_positive_integer(MAX_CLAUSE_COUNT), 100);
+        assert_eq!(settings.transient(), BTreeMap::new());
+        assert_eq!(settings.effective()[MAX_CLAUSE_COUNT], json!(100));
+    }
+
+    #[test]
+    fn test_time_values() {
+        let settings = NodeSettings::default();
+        assert_eq!(settings.get_time_value(SEARCH_SLOWLOG_WARN), None);
+
+        settings.update(None, Some(&json!({"search.slowlog.threshold.query.warn": "2s"}))).unwrap();
+        assert_eq!(settings.get_time_value(SEARCH_SLOWLOG_WARN), Some(Duration::from_secs(2)));
+
+        settings.update(None, Some(&json!({"search.slowlog.threshold.query.warn": "500ms"}))).unwrap();
+        assert_eq!(settings.get_time_value(SEARCH_SLOWLOG_WARN), Some(Duration::from_millis(500)));
+
+        settings.update(None, Some(&json!({"search.slowlog.threshold.query.warn": -1}))).unwrap();
+        assert_eq!(settings.get_time_value(SEARCH_SLOWLOG_WARN), None);
+    }
+
+    #[test]
+    fn test_disk_watermarks() {
+        assert_eq!(parse_byte_size("10gb"), Some(10 << 30));
+        assert_eq!(parse_byte_size("512"), Some(512));
+        assert_eq!(parse_byte_size("ten"), None);
+
+        let settings = NodeSettings::default();
+        assert_eq!(settings.get_disk_watermark(DISK_WATERMARK_LOW), Some(DiskWatermark::Percentage(85.0)));
+
+        settings.update(Some(&json!({"cluster.routing.allocation.disk.watermark.low": "80%"})), None).unwrap();
+        assert_eq!(settings.get_disk_watermark(DISK_WATERMARK_LOW), Some(DiskWatermark::Percentage(80.0)));
+
+        assert_eq!(settings.update(Some(&json!({"cluster.routing.allocation.disk.watermark.high": "70%"})), None), Err(SettingsError::WatermarksOutOfOrder));
+        assert_eq!(settings.get_disk_watermark(DISK_WATERMARK_HIGH), Some(DiskWatermark::Percentage(90.0)));
+    }
+
+    #[test]
+    fn test_tracing() {
+        let settings = NodeSettings::default();
+        assert_eq!(settings.get_tracing_exporter(), TracingExporter::None);
+
+        settings.update(None, Some(&json!({"tracing": {"exporter": "otlp", "otlp.endpoint": "http://collector:4318/v1/traces"}}))).unwrap();
+        assert_eq!(settings.get_tracing_exporter(), TracingExporter::Otlp);
+        assert_eq!(settings.get_otlp_endpoint().map(|url| url.to_string()), Some("http://collector:4318/v1/traces".to_string()));
+
+        assert_eq!(settings.update(None, Some(&json!({"tracing.otlp.endpoint": "https://collector:4318"}))), Err(SettingsError::InvalidValue("tracing.otlp.endpoint".to_string(), json!("https://collector:4318"))));
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let settings = NodeSettings::default();
+
+        assert_eq!(settings.update(None, Some(&json!({"thread_pool.search.size": 4}))), Err(SettingsError::UnknownSetting("thread_pool.search.size".to_string())));
+        assert_eq!(settings.update(None, Some(&json!({"thread_pool.write.queue_size": 0}))), Err(SettingsError::InvalidValue("thread_pool.write.queue_size".to_string(), json!(0))));
+        assert_eq!(settings.update(None, Some(&json!({"search.slowlog.threshold.query.warn": "soon"}))), Err(SettingsError::InvalidValue("search.slowlog.threshold.query.warn".to_string(), json!("soon"))));
+        assert_eq!(settings.update(None, Some(&json!("fast"))), Err(SettingsError::ExpectedObject("transient".to_string())));
+
+        // Nothing is changed if any of the settings are invalid
+        assert_eq!(settings.update(Some(&json!({"thread_pool.write.queue_size": 10})), Some(&json!({"foo": 1}))), Err(SettingsError::UnknownSetting("foo".to_string())));
+        assert_eq!(settings.get_positive_integer(WRITE_QUEUE_SIZE), 50);
+    }
+}
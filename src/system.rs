@@ -1,51 +1,330 @@
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 use std::path::{Path, PathBuf};
+use std::collections::HashSet;
 use std::fs;
+use std::io;
+use std::thread;
 
 use slog::Logger;
-use search::backends::rocksdb::RocksDBStore;
 use uuid::Uuid;
 
 use index::Index;
 use index::metadata::IndexMetadata;
+use index::store_cache::{StoreCache, IndexStore};
+use index::request_cache::{RequestCache, DEFAULT_REQUEST_CACHE_SIZE};
+use index::inflight::InflightSearches;
 use cluster::metadata::ClusterMetadata;
+use cluster::metadata::name_registry::NameCache;
+use cluster::metadata::state::ClusterStateStore;
+use bulk_queue::BulkQueue;
+use tenancy::Tenancy;
+use watcher::Watcher;
+use tasks::{TaskRegistry, Task};
+use search::backends::rocksdb::QueryLimits;
+use settings::{NodeSettings, TracingExporter, WRITE_QUEUE_SIZE};
+use trace::{Trace, TraceExporter};
+use search::collectors::registry::CollectorRegistry;
+use analysis::registry::AnalysisRegistry;
 
 
 pub struct System {
     pub log: Logger,
     data_dir: PathBuf,
     pub metadata: RwLock<ClusterMetadata>,
+
+    /// The names of the indices and aliases in the metadata, readable without locking it
+    pub names: NameCache,
+    pub bulk_queue: BulkQueue,
+
+    /// Limits how many index stores are open at the same time
+    pub store_cache: Arc<StoreCache>,
+
+    /// Responses of requests that asked for them to be cached
+    pub request_cache: RequestCache,
+
+    /// Searches that are running, so identical searches can share their responses
+    pub inflight_searches: InflightSearches,
+
+    /// Settings that can be changed while the node is running, call `apply_settings`
+    /// after changing them
+    pub settings: NodeSettings,
+
+    /// Collectors that search requests can ask for by name. Register them before
+    /// the system is shared between threads
+    pub collectors: CollectorRegistry,
+
+    /// Token filter and tokenizer types that index settings can use. Register them
+    /// before the indices are loaded
+    pub analysis: Arc<AnalysisRegistry>,
+
+    /// API keys and the quotas of their tenants
+    pub tenancy: Tenancy,
+
+    /// Queries that call a webhook when they match
+    pub watcher: Watcher,
+
+    /// Work that carries on in the background, like deleting the data of an index
+    pub tasks: TaskRegistry,
+
+    /// Sends traces to an OTLP collector, if the settings ask for it
+    pub trace_exporter: TraceExporter,
+}
+
+
+/// Deletes a directory one file at a time, recording the progress in a task
+fn remove_dir_with_progress(path: &Path, task: &Task) -> io::Result<()> {
+    let mut files = Vec::new();
+    let mut total_bytes = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        total_bytes += metadata.len();
+        files.push((entry.path(), metadata.is_dir(), metadata.len()));
+    }
+
+    let mut deleted_bytes = 0;
+    for (i, &(ref file_path, is_dir, size)) in files.iter().enumerate() {
+        if is_dir {
+            fs::remove_dir_all(file_path)?;
+        } else {
+            fs::remove_file(file_path)?;
+        }
+
+        deleted_bytes += size;
+        task.set_progress(json!({
+            "total_files": files.len(),
+            "deleted_files": i + 1,
+            "total_bytes": total_bytes,
+            "deleted_bytes": deleted_bytes,
+        }));
+    }
+
+    fs::remove_dir(path)
+}
+
+
+/// Deletes the directory of an index and finishes its task
+fn remove_index_dir(log: &Logger, task: Task, path: &Path, index_name: &str) {
+    match remove_dir_with_progress(path, &task) {
+        Ok(()) => {
+            info!(log, "deleted index data"; "index" => index_name, "task" => task.id());
+            task.complete();
+        }
+        Err(e) => {
+            warn!(log, "failed to delete index data"; "index" => index_name, "error" => format!("{}", e));
+            task.fail(format!("{}", e));
+        }
+    }
 }
 
 
 impl System {
-    pub fn new(log: Logger, data_dir: PathBuf) -> System {
-        System {
+    pub fn new(log: Logger, data_dir: PathBuf, max_open_indices: usize, settings: NodeSettings, tenancy: Tenancy, watcher: Watcher) -> System {
+        let metadata = ClusterMetadata::new();
+        let names = metadata.names.cache();
+
+        let trace_exporter = TraceExporter::new(log.clone());
+
+        let system = System {
             log: log,
             data_dir: data_dir,
-            metadata: RwLock::new(ClusterMetadata::new()),
+            metadata: RwLock::new(metadata),
+            names: names,
+            bulk_queue: BulkQueue::default(),
+            store_cache: Arc::new(StoreCache::new(max_open_indices)),
+            request_cache: RequestCache::new(DEFAULT_REQUEST_CACHE_SIZE),
+            inflight_searches: InflightSearches::new(),
+            settings: settings,
+            collectors: CollectorRegistry::new(),
+            analysis: Arc::new(AnalysisRegistry::new()),
+            tenancy: tenancy,
+            watcher: watcher,
+            tasks: TaskRegistry::new(),
+            trace_exporter: trace_exporter,
+        };
+
+        system.apply_settings();
+        system
+    }
+
+    /// Makes the services of the system use the current settings
+    ///
+    /// Settings that are read each time they're used, like the query limits, don't
+    /// need this.
+    pub fn apply_settings(&self) {
+        self.bulk_queue.set_max_requests(self.settings.get_positive_integer(WRITE_QUEUE_SIZE));
+    }
+
+    /// Limits on how many clauses the queries of a search can expand into
+    pub fn query_limits(&self) -> QueryLimits {
+        self.settings.query_limits()
+    }
+
+    /// Exports a finished trace to wherever the "tracing.exporter" setting says
+    pub fn export_trace(&self, log: &Logger, trace: &Trace) {
+        match self.settings.get_tracing_exporter() {
+            TracingExporter::None => {}
+            TracingExporter::Log => {
+                info!(log, "trace"; "trace_id" => trace.trace_id(), "spans" => trace.to_json().to_string());
+            }
+            TracingExporter::Otlp => {
+                if let Some(endpoint) = self.settings.get_otlp_endpoint() {
+                    self.trace_exporter.export_otlp(endpoint, trace);
+                }
+            }
         }
     }
 
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
     pub fn get_indices_dir(&self) -> PathBuf {
         let mut dir = self.data_dir.clone();
         dir.push("indices");
         dir
     }
 
-    fn load_index(&self, id: Uuid, name: String, path: &Path) -> Result<Index, String> {
-        let store = RocksDBStore::open(path)?;
-
+    fn load_index(&self, name: String, path: &Path) -> Result<Index, String> {
         // Load metadata
         let mut metadata_path = path.to_path_buf();
         metadata_path.push("metadata.json");
-        let metadata = IndexMetadata::load(metadata_path)?;
+        let metadata = IndexMetadata::load_with_analysis_registry(metadata_path, self.analysis.clone())?;
+
+        // The store isn't opened until the index is used
+        let store = IndexStore::new(path.to_path_buf(), self.store_cache.clone());
+
+        // Indices created before the uuid was saved get a new one each time they're loaded
+        let id = metadata.uuid.unwrap_or_else(Uuid::new_v4);
 
         Ok(Index::new(id, name, metadata, store))
     }
 
+    /// Loads an index from a directory outside of the data directory, without copying it
+    ///
+    /// The index is blocked from being written to, so the directory can be a checkpoint or
+    /// a restored backup that's shared with other nodes. The directory may be a copy of an
+    /// index that's loaded already, so the index is given the id that the cluster state has
+    /// for it rather than the one in its metadata.
+    pub fn load_attached_index(&self, name: String, id: Uuid, path: &Path) -> Result<Index, String> {
+        if !path.is_dir() {
+            return Err(format!("directory not found: {}", path.display()));
+        }
+
+        let mut metadata_path = path.to_path_buf();
+        metadata_path.push("metadata.json");
+        let metadata = IndexMetadata::load_with_analysis_registry(metadata_path, self.analysis.clone())?;
+        let store = IndexStore::new(path.to_path_buf(), self.store_cache.clone());
+
+        let mut index = Index::new(id, name, metadata, store);
+        index.set_read_only(true);
+        Ok(index)
+    }
+
+    /// Releases an attached index that has been removed from the cluster metadata
+    ///
+    /// The store is closed once nothing is reading from it, its files are left as they are.
+    pub fn detach_index(&self, index: Index) {
+        let log = self.log.clone();
+
+        thread::spawn(move || {
+            index.close_store();
+            info!(log, "released attached index"; "index" => index.canonical_name(), "path" => format!("{}", index.path().display()));
+        });
+    }
+
+    /// Where checkpoints of indices are written to
+    pub fn get_checkpoints_dir(&self) -> PathBuf {
+        let mut dir = self.data_dir.clone();
+        dir.push("checkpoints");
+        dir
+    }
+
+    /// Where the directories of deleted indices are moved to while their files are deleted
+    pub fn get_deleted_indices_dir(&self) -> PathBuf {
+        let mut dir = self.data_dir.clone();
+        dir.push("deleted_indices");
+        dir
+    }
+
+    /// Deletes the data of an index in a background task, returns the id of the task
+    ///
+    /// The index must already be removed from the cluster metadata. Its store is closed
+    /// first, which waits for any requests that are still reading from it. Then the
+    /// directory is moved out of the way, so an index with the same name can be created
+    /// while the files are being deleted.
+    pub fn delete_index_data(&self, index: Index) -> u64 {
+        let task = self.tasks.start("indices:delete", format!("delete index [{}]", index.canonical_name()), Some(index.canonical_name().to_string()));
+        let task_id = task.id();
+        let log = self.log.clone();
+        let mut deleted_path = self.get_deleted_indices_dir();
+        deleted_path.push(index.id().to_string());
+
+        thread::spawn(move || {
+            index.close_store();
+
+            let index_name = index.canonical_name().to_string();
+            let moved = fs::create_dir_all(deleted_path.parent().unwrap()).and_then(|_| fs::rename(index.path(), &deleted_path));
+            let path = match moved {
+                Ok(()) => deleted_path,
+                Err(e) => {
+                    warn!(log, "failed to move index data, deleting it in place"; "index" => &index_name, "error" => format!("{}", e));
+                    index.path().to_path_buf()
+                }
+            };
+
+            drop(index);
+            remove_index_dir(&log, task, &path, &index_name);
+        });
+
+        task_id
+    }
+
+    /// Finishes deleting the data of indices that were being deleted when the node stopped
+    fn resume_index_deletions(&self) {
+        let files = match fs::read_dir(self.get_deleted_indices_dir()) {
+            Ok(files) => files,
+            Err(_) => return,
+        };
+
+        for file in files {
+            let path = match file {
+                Ok(file) => file.path(),
+                Err(_) => continue,
+            };
+
+            // The directories are named after the uuid of the index, its name isn't known any more
+            let index_id = path.file_name().and_then(|name| name.to_str()).unwrap_or("").to_string();
+            let task = self.tasks.start("indices:delete", format!("delete index data [{}]", index_id), None);
+            let log = self.log.clone();
+
+            thread::spawn(move || {
+                remove_index_dir(&log, task, &path, &index_id);
+            });
+        }
+    }
+
+    pub fn get_cluster_state_path(&self) -> PathBuf {
+        let mut path = self.data_dir.clone();
+        path.push("cluster_state.json");
+        path
+    }
+
     pub fn load_indices(&self) {
+        let mut cluster_metadata = self.metadata.write().unwrap();
+
+        // Load the cluster state, if it can't be read it's kept in memory so it isn't overwritten
+        match ClusterStateStore::open(self.get_cluster_state_path()) {
+            Ok(state) => cluster_metadata.state = state,
+            Err(e) => {
+                crit!(self.log, "could not load cluster state, changes will not be saved"; "error" => String::from(e));
+            }
+        }
+
+        self.resume_index_deletions();
+
         let indices_dir = self.get_indices_dir();
+        let mut failed_indices = HashSet::new();
         match fs::read_dir(indices_dir.clone()) {
             Ok(files) => {
                 for file in files {
@@ -53,16 +332,16 @@ impl System {
                     if path.is_dir() {
                         let index_name: String = path.file_name().unwrap().to_str().unwrap().to_owned();
 
-                        match self.load_index(Uuid::new_v4(), index_name.clone().to_owned(), path.as_path()) {
+                        match self.load_index(index_name.clone().to_owned(), path.as_path()) {
                             Ok(index) => {
-                                let mut cluster_metadata = self.metadata.write().unwrap();
                                 let index_ref = cluster_metadata.insert_index(index);
                                 cluster_metadata.names.insert_canonical(index_name.clone(), index_ref).unwrap();
 
                                 info!(self.log, "loaded index"; "index" => index_name);
                             }
                             Err(e) => {
-                                error!(self.log, "load index failed"; "index" => index_name, "error" => e);
+                                error!(self.log, "load index failed"; "index" => index_name.clone(), "error" => e);
+                                failed_indices.insert(index_name);
                             }
                         }
                     }
@@ -72,5 +351,75 @@ impl System {
                 error!(self.log, "could not open indices directory"; "dir" => indices_dir.to_str().unwrap(), "error" => format!("{}", error));
             }
         }
+
+        // Load the indices that were attached from other directories
+        let attached_indices = {
+            let state = cluster_metadata.state.state();
+            state.attached.iter().filter_map(|(name, path)| state.indices.get(name).map(|id| (name.clone(), *id, path.clone()))).collect::<Vec<_>>()
+        };
+        for (index_name, id, path) in attached_indices {
+            match self.load_attached_index(index_name.clone(), id, &path) {
+                Ok(index) => {
+                    let index_ref = cluster_metadata.insert_index(index);
+                    if cluster_metadata.names.insert_canonical(index_name.clone(), index_ref).is_err() {
+                        // An index in the data directory has taken the name
+                        error!(self.log, "attached index has the same name as an index"; "index" => index_name.clone());
+                        cluster_metadata.indices.remove(&index_ref);
+                        failed_indices.insert(index_name);
+                        continue;
+                    }
+
+                    info!(self.log, "loaded attached index"; "index" => index_name, "path" => format!("{}", path.display()));
+                }
+                Err(e) => {
+                    // Kept in the cluster state, the directory may be back next time
+                    error!(self.log, "load attached index failed"; "index" => index_name.clone(), "error" => e);
+                    failed_indices.insert(index_name);
+                }
+            }
+        }
+
+        // Bring the cluster state up to date with the indices that were loaded
+        // Indices created before the cluster state existed are added to it and indices
+        // that no longer have any files are removed. Indices that failed to load are
+        // left as they are, so their aliases aren't lost
+        let loaded_indices = cluster_metadata.indices.values().map(|index| (index.canonical_name().to_string(), *index.id(), index.is_read_only())).collect::<Vec<_>>();
+        let result = cluster_metadata.state.update(|state| {
+            let missing_indices = state.indices.keys().filter(|name| {
+                !failed_indices.contains(*name) && !loaded_indices.iter().any(|&(ref loaded_name, _, _)| loaded_name == *name)
+            }).cloned().collect::<Vec<_>>();
+
+            for name in missing_indices {
+                state.remove_index(&name)?;
+            }
+
+            for &(ref name, id, attached) in loaded_indices.iter() {
+                state.indices.insert(name.clone(), id);
+
+                // An index in the data directory replaces an attached one with the same name
+                if !attached {
+                    state.attached.remove(name);
+                }
+            }
+
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            error!(self.log, "could not update cluster state"; "error" => String::from(e));
+        }
+
+        // Register the aliases
+        let aliases = cluster_metadata.state.state().aliases.clone();
+        for (alias_name, index_names) in aliases {
+            let index_refs = index_names.iter().filter_map(|index_name| cluster_metadata.names.find_canonical(index_name)).collect::<Vec<_>>();
+            if index_refs.is_empty() {
+                continue;
+            }
+
+            if cluster_metadata.names.insert_alias(alias_name.clone(), index_refs).is_err() {
+                warn!(self.log, "alias has the same name as an index"; "alias" => alias_name);
+            }
+        }
     }
 }
@@ -0,0 +1,221 @@
+//! Long running tasks that carry on after the request that started them
+//!
+//! Each task records its progress here so it can be checked through the "_tasks"
+//! API. Finished tasks are kept for a while so their results can still be seen.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde_json::Value as Json;
+
+
+/// The number of finished tasks to keep
+pub const MAX_FINISHED_TASKS: usize = 100;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskState {
+    Running,
+    Completed,
+    Failed(String),
+}
+
+
+#[derive(Debug, Clone)]
+pub struct TaskStatus {
+    pub id: u64,
+
+    /// What the task does (eg, "indices:delete")
+    pub action: String,
+
+    pub description: String,
+
+    /// The index the task works on, if any
+    pub index: Option<String>,
+
+    pub start_time: DateTime<Utc>,
+    pub state: TaskState,
+
+    /// How far the task has got, the contents depend on the action
+    pub progress: Json,
+}
+
+
+impl TaskStatus {
+    pub fn is_finished(&self) -> bool {
+        self.state != TaskState::Running
+    }
+
+    pub fn to_json(&self) -> Json {
+        let mut json = json!({
+            "id": self.id,
+            "action": self.action,
+            "description": self.description,
+            "index": self.index,
+            "start_time_in_millis": self.start_time.timestamp() * 1000 + self.start_time.timestamp_subsec_millis() as i64,
+            "completed": self.is_finished(),
+            "status": self.progress,
+        });
+
+        if let TaskState::Failed(ref error) = self.state {
+            json["error"] = json!({"reason": error});
+        }
+
+        json
+    }
+}
+
+
+/// Used by a running task to report its progress
+///
+/// If this is dropped before the task is completed (eg, the task panicked), the
+/// task is marked as failed.
+#[derive(Debug)]
+pub struct Task {
+    status: Arc<Mutex<TaskStatus>>,
+}
+
+
+impl Task {
+    pub fn id(&self) -> u64 {
+        self.status.lock().unwrap().id
+    }
+
+    pub fn set_progress(&self, progress: Json) {
+        self.status.lock().unwrap().progress = progress;
+    }
+
+    pub fn complete(self) {
+        self.status.lock().unwrap().state = TaskState::Completed;
+    }
+
+    pub fn fail(self, error: String) {
+        self.status.lock().unwrap().state = TaskState::Failed(error);
+    }
+}
+
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        let mut status = match self.status.lock() {
+            Ok(status) => status,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if status.state == TaskState::Running {
+            status.state = TaskState::Failed("task stopped before it finished".to_string());
+        }
+    }
+}
+
+
+#[derive(Debug, Default)]
+struct Tasks {
+    next_id: u64,
+    tasks: BTreeMap<u64, Arc<Mutex<TaskStatus>>>,
+}
+
+
+#[derive(Debug, Default)]
+pub struct TaskRegistry {
+    tasks: Mutex<Tasks>,
+}
+
+
+impl TaskRegistry {
+    pub fn new() -> TaskRegistry {
+        TaskRegistry::default()
+    }
+
+    /// Records a new task, the returned handle is used to update it
+    pub fn start(&self, action: &str, description: String, index: Option<String>) -> Task {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.next_id += 1;
+
+        let status = Arc::new(Mutex::new(TaskStatus {
+            id: tasks.next_id,
+            action: action.to_string(),
+            description: description,
+            index: index,
+            start_time: Utc::now(),
+            state: TaskState::Running,
+            progress: json!({}),
+        }));
+
+        let id = tasks.next_id;
+        tasks.tasks.insert(id, status.clone());
+
+        // Forget the oldest finished tasks
+        let finished_ids = tasks.tasks.iter().filter(|&(_, status)| status.lock().unwrap().is_finished()).map(|(id, _)| *id).collect::<Vec<_>>();
+        if finished_ids.len() > MAX_FINISHED_TASKS {
+            for id in finished_ids[..finished_ids.len() - MAX_FINISHED_TASKS].iter() {
+                tasks.tasks.remove(id);
+            }
+        }
+
+        Task {
+            status: status,
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<TaskStatus> {
+        self.tasks.lock().unwrap().tasks.get(&id).map(|status| status.lock().unwrap().clone())
+    }
+
+    /// Lists the running tasks and the recently finished ones, oldest first
+    pub fn list(&self) -> Vec<TaskStatus> {
+        self.tasks.lock().unwrap().tasks.values().map(|status| status.lock().unwrap().clone()).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{TaskRegistry, TaskState, MAX_FINISHED_TASKS};
+
+    #[test]
+    fn test_task_progress() {
+        let registry = TaskRegistry::new();
+        let task = registry.start("indices:delete", "delete index [foo]".to_string(), Some("foo".to_string()));
+        let id = task.id();
+
+        assert_eq!(registry.get(id).unwrap().state, TaskState::Running);
+
+        task.set_progress(json!({"deleted_files": 1}));
+        assert_eq!(registry.get(id).unwrap().progress, json!({"deleted_files": 1}));
+
+        task.complete();
+        assert_eq!(registry.get(id).unwrap().state, TaskState::Completed);
+    }
+
+    #[test]
+    fn test_dropped_task_fails() {
+        let registry = TaskRegistry::new();
+        let id = registry.start("indices:delete", "delete index [foo]".to_string(), Some("foo".to_string())).id();
+
+        assert!(match registry.get(id).unwrap().state {
+            TaskState::Failed(_) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_old_finished_tasks_are_forgotten() {
+        let registry = TaskRegistry::new();
+        let running_task = registry.start("indices:delete", "delete index [foo]".to_string(), Some("foo".to_string()));
+
+        for _ in 0..MAX_FINISHED_TASKS + 1 {
+            registry.start("indices:delete", "delete index [bar]".to_string(), Some("bar".to_string())).complete();
+        }
+
+        // Finished tasks are forgotten when the next task starts
+        let _new_task = registry.start("indices:delete", "delete index [baz]".to_string(), Some("baz".to_string()));
+
+        // The running tasks are kept, as well as the newest finished tasks
+        let tasks = registry.list();
+        assert_eq!(tasks.len(), MAX_FINISHED_TASKS + 2);
+        assert_eq!(tasks[0].id, running_task.id());
+        assert_eq!(tasks[1].id, running_task.id() + 2);
+    }
+}
@@ -0,0 +1,457 @@
+//! API keys, tenants and quotas
+//!
+//! Tenants are configured in "tenants.json" in the data directory:
+//!
+//! ```json
+//! [
+//!     {
+//!         "name": "acme",
+//!         "api_keys": ["secret"],
+//!         "index_patterns": ["acme-*"],
+//!         "quotas": {"max_indices": 10, "max_docs": 1000000, "max_storage": 1073741824}
+//!     }
+//! ]
+//! ```
+//!
+//! Requests authenticate with an "Authorization: ApiKey <key>" header and may only
+//! use indices that match one of their tenant's patterns. If the file doesn't exist,
+//! tenancy is disabled and every request is allowed.
+//!
+//! Usage is tracked per index so deleting an index gives back its quota. It's saved
+//! to "tenant_usage.json" after every change. Storage is counted as the size of the
+//! source of each document indexed, which is cheaper to track than the size on disk.
+//! It's only given back when the index is deleted.
+
+use std::collections::{HashMap, BTreeMap};
+use std::path::{Path, PathBuf};
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::sync::Mutex;
+
+use serde_json;
+use atomicwrites::{AtomicFile, AllowOverwrite};
+
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Quotas {
+    pub max_indices: Option<u64>,
+    pub max_docs: Option<u64>,
+
+    /// Maximum total size of the documents indexed, in bytes
+    pub max_storage: Option<u64>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tenant {
+    pub name: String,
+    pub api_keys: Vec<String>,
+
+    /// Index names the tenant can use, "*" matches any number of characters
+    #[serde(default)]
+    pub index_patterns: Vec<String>,
+
+    #[serde(default)]
+    pub quotas: Quotas,
+}
+
+
+impl Tenant {
+    pub fn can_access_index(&self, index_name: &str) -> bool {
+        self.index_patterns.iter().any(|pattern| pattern_matches(pattern, index_name))
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct IndexUsage {
+    pub docs: u64,
+    pub storage: u64,
+}
+
+
+/// Usage of a tenant, keyed by index name
+pub type TenantUsage = BTreeMap<String, IndexUsage>;
+
+
+#[derive(Debug, PartialEq)]
+pub enum TenancyError {
+    /// The request didn't have an API key
+    MissingApiKey,
+
+    /// The API key doesn't belong to any tenant
+    InvalidApiKey,
+
+    /// The index doesn't match any of the tenant's patterns
+    IndexNotAllowed(String),
+
+    /// The request would take the tenant over one of its quotas
+    QuotaExceeded(&'static str),
+}
+
+
+impl From<TenancyError> for String {
+    fn from(e: TenancyError) -> String {
+        match e {
+            TenancyError::MissingApiKey => "API key is required".to_string(),
+            TenancyError::InvalidApiKey => "API key is invalid".to_string(),
+            TenancyError::IndexNotAllowed(index_name) => format!("API key can't be used with index \"{}\"", index_name),
+            TenancyError::QuotaExceeded(quota) => format!("quota \"{}\" exceeded", quota),
+        }
+    }
+}
+
+
+/// Matches a name against a pattern where "*" matches any number of characters
+fn pattern_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+
+    // The first part must be at the start of the name
+    let first = parts.next().unwrap_or("");
+    if !name.starts_with(first) {
+        return false;
+    }
+    let mut remaining = &name[first.len()..];
+
+    let parts = parts.collect::<Vec<_>>();
+    let last = match parts.last() {
+        Some(last) => *last,
+        None => return remaining.is_empty(),
+    };
+
+    // Middle parts are matched as early as possible, the last must be at the end
+    for part in parts[..parts.len() - 1].iter() {
+        match remaining.find(part) {
+            Some(position) => remaining = &remaining[position + part.len()..],
+            None => return false,
+        }
+    }
+
+    remaining.ends_with(last)
+}
+
+
+fn read_json_file(path: &Path) -> Result<Option<String>, String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("failed to open {}: {}", path.display(), e)),
+    };
+
+    let mut s = String::new();
+    file.read_to_string(&mut s).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    Ok(Some(s))
+}
+
+
+#[derive(Debug)]
+pub struct Tenancy {
+    /// None if tenancy is disabled
+    tenants: Option<Vec<Tenant>>,
+
+    /// Usage of each tenant, keyed by tenant name
+    usage: Mutex<HashMap<String, TenantUsage>>,
+
+    /// Where usage is saved, usage isn't saved if this is None
+    usage_path: Option<PathBuf>,
+}
+
+
+impl Tenancy {
+    /// Every request is allowed and nothing is tracked
+    pub fn disabled() -> Tenancy {
+        Tenancy {
+            tenants: None,
+            usage: Mutex::new(HashMap::new()),
+            usage_path: None,
+        }
+    }
+
+    pub fn new(tenants: Vec<Tenant>, usage: HashMap<String, TenantUsage>, usage_path: Option<PathBuf>) -> Tenancy {
+        Tenancy {
+            tenants: Some(tenants),
+            usage: Mutex::new(usage),
+            usage_path: usage_path,
+        }
+    }
+
+    /// Loads tenants and their usage from the data directory
+    pub fn load(data_dir: &Path) -> Result<Tenancy, String> {
+        let tenants = match read_json_file(&data_dir.join("tenants.json"))? {
+            Some(s) => serde_json::from_str::<Vec<Tenant>>(&s).map_err(|e| format!("failed to parse tenants.json: {}", e))?,
+            None => return Ok(Tenancy::disabled()),
+        };
+
+        let usage_path = data_dir.join("tenant_usage.json");
+        let usage = match read_json_file(&usage_path)? {
+            Some(s) => serde_json::from_str(&s).map_err(|e| format!("failed to parse tenant_usage.json: {}", e))?,
+            None => HashMap::new(),
+        };
+
+        Ok(Tenancy::new(tenants, usage, Some(usage_path)))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.tenants.is_some()
+    }
+
+    /// Finds the tenant of an API key
+    ///
+    /// Returns None if tenancy is disabled.
+    pub fn authenticate(&self, api_key: Option<&str>) -> Result<Option<Tenant>, TenancyError> {
+        let tenants = match self.tenants {
+            Some(ref tenants) => tenants,
+            None => return Ok(None),
+        };
+
+        let api_key = api_key.ok_or(TenancyError::MissingApiKey)?;
+        match tenants.iter().find(|tenant| tenant.api_keys.iter().any(|key| key == api_key)) {
+            Some(tenant) => Ok(Some(tenant.clone())),
+            None => Err(TenancyError::InvalidApiKey),
+        }
+    }
+
+    pub fn check_index_access(&self, tenant: Option<&Tenant>, index_name: &str) -> Result<(), TenancyError> {
+        match tenant {
+            Some(tenant) if !tenant.can_access_index(index_name) => Err(TenancyError::IndexNotAllowed(index_name.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the tenant can create an index
+    pub fn check_create_index(&self, tenant: Option<&Tenant>, index_name: &str) -> Result<(), TenancyError> {
+        let tenant = match tenant {
+            Some(tenant) => tenant,
+            None => return Ok(()),
+        };
+
+        self.check_index_access(Some(tenant), index_name)?;
+
+        if let Some(max_indices) = tenant.quotas.max_indices {
+            let usage = self.usage.lock().unwrap();
+            let num_indices = usage.get(&tenant.name).map(|usage| usage.len()).unwrap_or(0);
+            if num_indices as u64 >= max_indices {
+                return Err(TenancyError::QuotaExceeded("max_indices"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the tenant can index new documents
+    ///
+    /// "docs" is the number of documents that will be added and "storage" is the
+    /// size of their source.
+    pub fn check_index_documents(&self, tenant: Option<&Tenant>, index_name: &str, docs: u64, storage: u64) -> Result<(), TenancyError> {
+        let tenant = match tenant {
+            Some(tenant) => tenant,
+            None => return Ok(()),
+        };
+
+        self.check_index_access(Some(tenant), index_name)?;
+
+        let usage = self.usage.lock().unwrap();
+        let (total_docs, total_storage) = usage.get(&tenant.name).map(|usage| {
+            usage.values().fold((0, 0), |(total_docs, total_storage), index_usage| {
+                (total_docs + index_usage.docs, total_storage + index_usage.storage)
+            })
+        }).unwrap_or((0, 0));
+
+        if let Some(max_docs) = tenant.quotas.max_docs {
+            if total_docs + docs > max_docs {
+                return Err(TenancyError::QuotaExceeded("max_docs"));
+            }
+        }
+
+        if let Some(max_storage) = tenant.quotas.max_storage {
+            if total_storage + storage > max_storage {
+                return Err(TenancyError::QuotaExceeded("max_storage"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the usage of a tenant
+    pub fn usage(&self, tenant_name: &str) -> TenantUsage {
+        self.usage.lock().unwrap().get(tenant_name).cloned().unwrap_or_default()
+    }
+
+    fn update_usage<F: FnOnce(&mut TenantUsage)>(&self, tenant_name: &str, f: F) -> Result<(), String> {
+        let mut usage = self.usage.lock().unwrap();
+        f(usage.entry(tenant_name.to_string()).or_insert_with(TenantUsage::new));
+
+        // Save while the lock is held so saves can't be written out of order
+        if let Some(ref usage_path) = self.usage_path {
+            let s = serde_json::to_string(&*usage).map_err(|e| format!("failed to save tenant usage: {}", e))?;
+            let file = AtomicFile::new(usage_path, AllowOverwrite);
+            file.write(|f| f.write_all(s.as_bytes())).map_err(|e| format!("failed to save tenant usage: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn record_index_created(&self, tenant: Option<&Tenant>, index_name: &str) -> Result<(), String> {
+        let tenant = match tenant {
+            Some(tenant) => tenant,
+            None => return Ok(()),
+        };
+
+        self.update_usage(&tenant.name, |usage| {
+            usage.entry(index_name.to_string()).or_insert_with(IndexUsage::default);
+        })
+    }
+
+    /// Gives back everything that was used by an index
+    ///
+    /// The index is removed from the usage of every tenant as indices can be
+    /// deleted by a tenant that didn't create them.
+    pub fn record_index_deleted(&self, index_name: &str) -> Result<(), String> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let tenant_names = self.usage.lock().unwrap().iter()
+            .filter(|&(_, usage)| usage.contains_key(index_name))
+            .map(|(tenant_name, _)| tenant_name.clone())
+            .collect::<Vec<_>>();
+
+        for tenant_name in tenant_names {
+            self.update_usage(&tenant_name, |usage| {
+                usage.remove(index_name);
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Records documents being added to (or, with negative numbers, removed from) an index
+    pub fn record_documents(&self, tenant: Option<&Tenant>, index_name: &str, docs: i64, storage: i64) -> Result<(), String> {
+        let tenant = match tenant {
+            Some(tenant) => tenant,
+            None => return Ok(()),
+        };
+
+        self.update_usage(&tenant.name, |usage| {
+            let index_usage = usage.entry(index_name.to_string()).or_insert_with(IndexUsage::default);
+            index_usage.docs = (index_usage.docs as i64 + docs).max(0) as u64;
+            index_usage.storage = (index_usage.storage as i64 + storage).max(0) as u64;
+        })
+    }
+}
+
+
+impl Default for Tenancy {
+    fn default() -> Tenancy {
+        Tenancy::disabled()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{create_dir_all, remove_dir_all, File};
+    use std::io::Write;
+    use std::path::Path;
+
+    use super::{Tenancy, Tenant, Quotas, TenancyError, pattern_matches};
+
+    fn make_tenant() -> Tenant {
+        Tenant {
+            name: "acme".to_string(),
+            api_keys: vec!["secret".to_string()],
+            index_patterns: vec!["acme-*".to_string()],
+            quotas: Quotas {
+                max_indices: Some(1),
+                max_docs: Some(2),
+                max_storage: Some(100),
+            },
+        }
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches("acme", "acme"));
+        assert!(!pattern_matches("acme", "acme-products"));
+        assert!(pattern_matches("acme-*", "acme-products"));
+        assert!(pattern_matches("acme-*", "acme-"));
+        assert!(!pattern_matches("acme-*", "other-products"));
+        assert!(pattern_matches("*-products", "acme-products"));
+        assert!(pattern_matches("acme-*-v*", "acme-products-v2"));
+        assert!(!pattern_matches("acme-*-v*", "acme-products"));
+        assert!(pattern_matches("*", "anything"));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let tenancy = Tenancy::disabled();
+
+        assert_eq!(tenancy.authenticate(None), Ok(None));
+        assert_eq!(tenancy.check_create_index(None, "anything"), Ok(()));
+        assert_eq!(tenancy.check_index_documents(None, "anything", 1000, 1000), Ok(()));
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let tenancy = Tenancy::new(vec![make_tenant()], HashMap::new(), None);
+
+        assert_eq!(tenancy.authenticate(Some("secret")), Ok(Some(make_tenant())));
+        assert_eq!(tenancy.authenticate(Some("wrong")), Err(TenancyError::InvalidApiKey));
+        assert_eq!(tenancy.authenticate(None), Err(TenancyError::MissingApiKey));
+    }
+
+    #[test]
+    fn test_quotas() {
+        let tenant = make_tenant();
+        let tenancy = Tenancy::new(vec![tenant.clone()], HashMap::new(), None);
+
+        assert_eq!(tenancy.check_create_index(Some(&tenant), "other"), Err(TenancyError::IndexNotAllowed("other".to_string())));
+        assert_eq!(tenancy.check_create_index(Some(&tenant), "acme-products"), Ok(()));
+        tenancy.record_index_created(Some(&tenant), "acme-products").unwrap();
+        assert_eq!(tenancy.check_create_index(Some(&tenant), "acme-users"), Err(TenancyError::QuotaExceeded("max_indices")));
+
+        tenancy.record_documents(Some(&tenant), "acme-products", 2, 50).unwrap();
+        assert_eq!(tenancy.check_index_documents(Some(&tenant), "acme-products", 1, 10), Err(TenancyError::QuotaExceeded("max_docs")));
+
+        tenancy.record_documents(Some(&tenant), "acme-products", -1, 0).unwrap();
+        assert_eq!(tenancy.check_index_documents(Some(&tenant), "acme-products", 1, 10), Ok(()));
+        assert_eq!(tenancy.check_index_documents(Some(&tenant), "acme-products", 1, 60), Err(TenancyError::QuotaExceeded("max_storage")));
+
+        // Deleting the index gives everything back
+        tenancy.record_index_deleted("acme-products").unwrap();
+        assert_eq!(tenancy.check_create_index(Some(&tenant), "acme-users"), Ok(()));
+        assert!(tenancy.usage("acme").is_empty());
+    }
+
+    #[test]
+    fn test_usage_is_saved() {
+        let data_dir = Path::new("test_indices/test_tenancy_usage_is_saved");
+        let _ = remove_dir_all(data_dir);
+        create_dir_all(data_dir).unwrap();
+
+        File::create(data_dir.join("tenants.json")).unwrap().write_all(json!([{
+            "name": "acme",
+            "api_keys": ["secret"],
+            "index_patterns": ["acme-*"]
+        }]).to_string().as_bytes()).unwrap();
+
+        let tenancy = Tenancy::load(data_dir).unwrap();
+        let tenant = tenancy.authenticate(Some("secret")).unwrap().unwrap();
+        tenancy.record_index_created(Some(&tenant), "acme-products").unwrap();
+        tenancy.record_documents(Some(&tenant), "acme-products", 3, 30).unwrap();
+
+        let tenancy = Tenancy::load(data_dir).unwrap();
+        let usage = tenancy.usage("acme");
+        assert_eq!(usage.get("acme-products").map(|usage| (usage.docs, usage.storage)), Some((3, 30)));
+    }
+
+    #[test]
+    fn test_load_without_tenants_file() {
+        let data_dir = Path::new("test_indices/test_tenancy_load_without_tenants_file");
+        let _ = remove_dir_all(data_dir);
+        create_dir_all(data_dir).unwrap();
+
+        assert!(!Tenancy::load(data_dir).unwrap().is_enabled());
+    }
+}
@@ -0,0 +1,297 @@
+//! Lightweight tracing of the phases of a request
+//!
+//! A `Trace` is started for a request (keyed by its request id) and each phase is
+//! timed with a `Span`, which is recorded when it's dropped. Spans can have child
+//! spans, like a span for each segment inside the span that executes a search.
+//!
+//! Finished traces can be written to the log or sent to an OpenTelemetry collector
+//! with OTLP over HTTP (JSON encoding), see the "tracing.*" node settings.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde_json::Value as Json;
+use slog::Logger;
+use url::Url;
+use uuid::Uuid;
+
+use remote::http::request;
+
+
+/// How long to wait for the collector when exporting a trace
+const EXPORT_TIMEOUT_SECS: u64 = 10;
+
+
+fn duration_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+
+fn duration_millis_f64(duration: Duration) -> f64 {
+    duration_nanos(duration) as f64 / 1_000_000.0
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanRecord {
+    /// 16 hex characters, as OTLP expects
+    pub span_id: String,
+
+    /// The position of the parent span in the trace
+    pub parent: Option<usize>,
+
+    pub name: String,
+
+    /// When the span started, relative to the start of the trace
+    pub start_offset: Duration,
+
+    /// Zero until the span has finished
+    pub duration: Duration,
+
+    pub attributes: BTreeMap<String, Json>,
+}
+
+
+#[derive(Debug)]
+pub struct Trace {
+    trace_id: String,
+    started_at: DateTime<Utc>,
+    start: Instant,
+    spans: Mutex<Vec<SpanRecord>>,
+}
+
+
+impl Trace {
+    /// Starts a trace, the id should be the 32 hex characters of the request id
+    pub fn new(trace_id: String) -> Trace {
+        Trace {
+            trace_id: trace_id,
+            started_at: Utc::now(),
+            start: Instant::now(),
+            spans: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    fn start_span(&self, name: &str, parent: Option<usize>) -> Span {
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(SpanRecord {
+            span_id: Uuid::new_v4().simple().to_string()[..16].to_string(),
+            parent: parent,
+            name: name.to_string(),
+            start_offset: self.start.elapsed(),
+            duration: Duration::new(0, 0),
+            attributes: BTreeMap::new(),
+        });
+
+        Span {
+            trace: self,
+            index: spans.len() - 1,
+            start: Instant::now(),
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    /// Starts a span that doesn't have a parent
+    pub fn span(&self, name: &str) -> Span {
+        self.start_span(name, None)
+    }
+
+    /// The spans that have been started, in the order they started
+    pub fn spans(&self) -> Vec<SpanRecord> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    /// The spans as JSON, for writing to the log
+    pub fn to_json(&self) -> Json {
+        let spans = self.spans();
+
+        Json::Array(spans.iter().map(|span| {
+            json!({
+                "name": span.name,
+                "parent": span.parent.map(|parent| spans[parent].name.clone()),
+                "start_ms": duration_millis_f64(span.start_offset),
+                "duration_ms": duration_millis_f64(span.duration),
+                "attributes": span.attributes,
+            })
+        }).collect())
+    }
+
+    /// The trace as an OTLP "ExportTraceServiceRequest" in its JSON encoding
+    pub fn to_otlp_json(&self, service_name: &str) -> Json {
+        let started_at_nanos = self.started_at.timestamp() as u64 * 1_000_000_000 + self.started_at.timestamp_subsec_nanos() as u64;
+        let spans = self.spans();
+
+        let otlp_spans = spans.iter().map(|span| {
+            let start_nanos = started_at_nanos + duration_nanos(span.start_offset);
+            let attributes = span.attributes.iter().map(|(key, value)| {
+                json!({"key": key, "value": otlp_value(value)})
+            }).collect::<Vec<_>>();
+
+            let mut otlp_span = json!({
+                "traceId": self.trace_id,
+                "spanId": span.span_id,
+                "name": span.name,
+                "kind": 1,
+                "startTimeUnixNano": start_nanos.to_string(),
+                "endTimeUnixNano": (start_nanos + duration_nanos(span.duration)).to_string(),
+                "attributes": attributes,
+            });
+
+            if let Some(parent) = span.parent {
+                otlp_span["parentSpanId"] = Json::String(spans[parent].span_id.clone());
+            }
+
+            otlp_span
+        }).collect::<Vec<_>>();
+
+        json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{"key": "service.name", "value": {"stringValue": service_name}}],
+                },
+                "scopeSpans": [{
+                    "scope": {"name": "rusticsearch"},
+                    "spans": otlp_spans,
+                }],
+            }],
+        })
+    }
+}
+
+
+/// Converts an attribute value into an OTLP "AnyValue"
+fn otlp_value(value: &Json) -> Json {
+    match *value {
+        Json::Bool(value) => json!({"boolValue": value}),
+        Json::Number(ref number) if number.is_i64() || number.is_u64() => json!({"intValue": number.to_string()}),
+        Json::Number(ref number) => json!({"doubleValue": number.as_f64()}),
+        Json::String(ref string) => json!({"stringValue": string}),
+        ref value => json!({"stringValue": value.to_string()}),
+    }
+}
+
+
+/// Times a phase of a request, it's recorded in the trace when dropped
+#[derive(Debug)]
+pub struct Span<'a> {
+    trace: &'a Trace,
+    index: usize,
+    start: Instant,
+    attributes: BTreeMap<String, Json>,
+}
+
+
+impl<'a> Span<'a> {
+    pub fn child(&self, name: &str) -> Span<'a> {
+        self.trace.start_span(name, Some(self.index))
+    }
+
+    pub fn set_attribute(&mut self, key: &str, value: Json) {
+        self.attributes.insert(key.to_string(), value);
+    }
+}
+
+
+impl<'a> Drop for Span<'a> {
+    fn drop(&mut self) {
+        let mut spans = self.trace.spans.lock().unwrap();
+        let record = &mut spans[self.index];
+        record.duration = self.start.elapsed();
+        record.attributes = mem::replace(&mut self.attributes, BTreeMap::new());
+    }
+}
+
+
+/// Sends traces to an OTLP collector from a background thread, so requests don't
+/// wait for it
+pub struct TraceExporter {
+    log: Logger,
+
+    /// The thread is started when the first trace is exported
+    sender: Mutex<Option<Sender<(Url, Json)>>>,
+}
+
+
+impl TraceExporter {
+    pub fn new(log: Logger) -> TraceExporter {
+        TraceExporter {
+            log: log,
+            sender: Mutex::new(None),
+        }
+    }
+
+    pub fn export_otlp(&self, endpoint: Url, trace: &Trace) {
+        let mut sender = self.sender.lock().unwrap();
+
+        if sender.is_none() {
+            let (new_sender, receiver) = channel::<(Url, Json)>();
+            let log = self.log.clone();
+
+            thread::spawn(move || {
+                for (endpoint, body) in receiver {
+                    if let Err(error) = request("POST", &endpoint, None, Some(&body), Duration::from_secs(EXPORT_TIMEOUT_SECS)) {
+                        warn!(log, "failed to export trace"; "endpoint" => endpoint.as_str(), "error" => format!("{:?}", error));
+                    }
+                }
+            });
+
+            *sender = Some(new_sender);
+        }
+
+        if let Some(ref sender) = *sender {
+            let _ = sender.send((endpoint, trace.to_otlp_json("rusticsearch")));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Trace;
+
+    #[test]
+    fn test_spans() {
+        let trace = Trace::new("0af7651916cd43dd8448eb211c80319c".to_string());
+        {
+            let _parse = trace.span("parse");
+        }
+        {
+            let execute = trace.span("execute");
+            let mut segment = execute.child("segment");
+            segment.set_attribute("segment", json!(3));
+        }
+
+        let spans = trace.spans();
+        assert_eq!(spans.iter().map(|span| span.name.as_str()).collect::<Vec<_>>(), vec!["parse", "execute", "segment"]);
+        assert_eq!(spans[2].parent, Some(1));
+        assert_eq!(spans[2].attributes.get("segment"), Some(&json!(3)));
+        assert_eq!(spans[0].span_id.len(), 16);
+    }
+
+    #[test]
+    fn test_otlp_json() {
+        let trace = Trace::new("0af7651916cd43dd8448eb211c80319c".to_string());
+        {
+            let execute = trace.span("execute");
+            let mut segment = execute.child("segment");
+            segment.set_attribute("failed", json!(false));
+        }
+
+        let json = trace.to_otlp_json("rusticsearch");
+        let spans = json["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0]["traceId"], json!("0af7651916cd43dd8448eb211c80319c"));
+        assert_eq!(spans[0].get("parentSpanId"), None);
+        assert_eq!(spans[1]["parentSpanId"], spans[0]["spanId"]);
+        assert_eq!(spans[1]["attributes"], json!([{"key": "failed", "value": {"boolValue": false}}]));
+    }
+}
@@ -0,0 +1,186 @@
+//! Updates every document that matches a query
+//!
+//! The matching documents are found in a snapshot of the index, then updated in
+//! batches. A document that was changed or deleted after the snapshot was taken is a
+//! version conflict, which either stops the update ("conflicts": "abort") or is
+//! counted and skipped ("conflicts": "proceed").
+
+use serde_json::{Map, Value as Json};
+
+use super::{Update, UpdateResult, UpdateError, UpdateParseError};
+
+
+/// The number of documents updated in each batch, unless the request says otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictsPolicy {
+    Abort,
+    Proceed,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateByQuery {
+    /// The change to make to each document, if there isn't one the documents are
+    /// reindexed as they are (eg, to pick up changes to the mapping)
+    update: Option<Update>,
+
+    pub conflicts: ConflictsPolicy,
+
+    /// The most matching documents to update
+    pub max_docs: Option<usize>,
+}
+
+
+impl UpdateByQuery {
+    /// Parses the body of an update by query request, the "query" is left to the caller
+    ///
+    /// The documents can be changed with a "script" or a partial document ("doc") that's
+    /// merged into each of them.
+    pub fn parse(json: &Json) -> Result<UpdateByQuery, UpdateParseError> {
+        let object = json.as_object().ok_or(UpdateParseError::ExpectedObject)?;
+
+        let mut update_object = Map::new();
+        let mut conflicts = ConflictsPolicy::Abort;
+        let mut max_docs = None;
+
+        for (key, value) in object.iter() {
+            match key.as_ref() {
+                "query" => {}
+                "script" | "doc" => {
+                    update_object.insert(key.clone(), value.clone());
+                }
+                "conflicts" => {
+                    conflicts = match value.as_str() {
+                        Some("abort") => ConflictsPolicy::Abort,
+                        Some("proceed") => ConflictsPolicy::Proceed,
+                        _ => return Err(UpdateParseError::InvalidValue(key.clone())),
+                    };
+                }
+                "max_docs" => {
+                    max_docs = match value.as_u64() {
+                        Some(max_docs) => Some(max_docs as usize),
+                        None => return Err(UpdateParseError::InvalidValue(key.clone())),
+                    };
+                }
+                _ => return Err(UpdateParseError::UnrecognisedKey(key.clone())),
+            }
+        }
+
+        let update = if update_object.is_empty() {
+            None
+        } else {
+            Some(Update::parse(&Json::Object(update_object))?)
+        };
+
+        Ok(UpdateByQuery {
+            update: update,
+            conflicts: conflicts,
+            max_docs: max_docs,
+        })
+    }
+
+    /// Works out the new source of a matching document
+    pub fn apply(&self, source: Map<String, Json>) -> Result<UpdateResult, UpdateError> {
+        match self.update {
+            Some(ref update) => update.apply(Some(source)),
+            None => Ok(UpdateResult::Updated(source)),
+        }
+    }
+}
+
+
+/// How far an update by query has got, this is reported as the progress of its task
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UpdateByQueryProgress {
+    /// The number of documents that matched the query
+    pub total: u64,
+
+    pub updated: u64,
+    pub noops: u64,
+    pub version_conflicts: u64,
+    pub batches: u64,
+
+    /// The documents that couldn't be updated
+    pub failures: Vec<Json>,
+}
+
+
+impl UpdateByQueryProgress {
+    pub fn new(total: u64) -> UpdateByQueryProgress {
+        UpdateByQueryProgress {
+            total: total,
+            ..UpdateByQueryProgress::default()
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        json!({
+            "total": self.total,
+            "updated": self.updated,
+            "noops": self.noops,
+            "version_conflicts": self.version_conflicts,
+            "batches": self.batches,
+            "failures": self.failures,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Map, Value as Json};
+
+    use update::{UpdateResult, UpdateParseError};
+    use update::script::ScriptParseError;
+
+    use super::{UpdateByQuery, ConflictsPolicy};
+
+    fn object(json: Json) -> Map<String, Json> {
+        match json {
+            Json::Object(object) => object,
+            _ => panic!("expected an object"),
+        }
+    }
+
+    #[test]
+    fn test_script() {
+        let update_by_query = UpdateByQuery::parse(&json!({
+            "query": {"term": {"tag": "sale"}},
+            "script": {"source": "ctx._source.price *= params.discount", "params": {"discount": 0.5}},
+            "conflicts": "proceed",
+        })).unwrap();
+        assert_eq!(update_by_query.conflicts, ConflictsPolicy::Proceed);
+        assert_eq!(update_by_query.max_docs, None);
+
+        assert_eq!(update_by_query.apply(object(json!({"price": 10}))), Ok(UpdateResult::Updated(object(json!({"price": 5.0})))));
+    }
+
+    #[test]
+    fn test_doc() {
+        let update_by_query = UpdateByQuery::parse(&json!({"doc": {"archived": true}, "max_docs": 10})).unwrap();
+        assert_eq!(update_by_query.conflicts, ConflictsPolicy::Abort);
+        assert_eq!(update_by_query.max_docs, Some(10));
+
+        assert_eq!(update_by_query.apply(object(json!({"archived": true}))), Ok(UpdateResult::Noop));
+    }
+
+    #[test]
+    fn test_without_update() {
+        // Documents are reindexed with the source they already have
+        let update_by_query = UpdateByQuery::parse(&json!({"query": {"match_all": {}}})).unwrap();
+
+        assert_eq!(update_by_query.apply(object(json!({"title": "foo"}))), Ok(UpdateResult::Updated(object(json!({"title": "foo"})))));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(UpdateByQuery::parse(&json!({"conflicts": "ignore"})), Err(UpdateParseError::InvalidValue("conflicts".to_string())));
+        assert_eq!(UpdateByQuery::parse(&json!({"max_docs": -1})), Err(UpdateParseError::InvalidValue("max_docs".to_string())));
+        assert_eq!(UpdateByQuery::parse(&json!({"upsert": {}})), Err(UpdateParseError::UnrecognisedKey("upsert".to_string())));
+        assert_eq!(UpdateByQuery::parse(&json!({"doc": {}, "script": "ctx._source.x = 1"})), Err(UpdateParseError::DocAndScript));
+        assert_eq!(UpdateByQuery::parse(&json!({"script": {"lang": "groovy", "source": "x"}})), Err(UpdateParseError::ScriptParseError(ScriptParseError::UnsupportedLanguage("\"groovy\"".to_string()))));
+    }
+}
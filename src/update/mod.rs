@@ -0,0 +1,259 @@
+//! Partial updates to documents
+//!
+//! An update changes the source of a document that's already indexed, either by
+//! merging a partial document into it ("doc") or by running a script. If the
+//! document doesn't exist, it can be created from "upsert" (or from "doc" when
+//! "doc_as_upsert" is set) instead.
+//!
+//! The new source must be indexed by the caller. To stop concurrent updates to the
+//! same document from overwriting each other, read the current source and index the
+//! new one while holding the index's update lock (see `Index::lock_updates`).
+
+pub mod script;
+pub mod by_query;
+
+use serde_json::{Map, Value as Json};
+
+use self::script::{Script, ScriptParseError, ScriptError};
+
+
+#[derive(Debug, PartialEq)]
+pub enum UpdateParseError {
+    ExpectedObject,
+    ExpectedDocumentObject(String),
+    ExpectedBoolean(String),
+    ScriptParseError(ScriptParseError),
+
+    /// The update has neither "doc" nor "script"
+    NothingToUpdate,
+
+    /// The update has both "doc" and "script"
+    DocAndScript,
+
+    UnrecognisedKey(String),
+
+    /// The value of the key isn't one of the allowed values
+    InvalidValue(String),
+}
+
+
+impl From<ScriptParseError> for UpdateParseError {
+    fn from(e: ScriptParseError) -> UpdateParseError {
+        UpdateParseError::ScriptParseError(e)
+    }
+}
+
+
+impl UpdateParseError {
+    pub fn message(&self) -> String {
+        match *self {
+            UpdateParseError::ExpectedObject => "expected the update to be an object".to_string(),
+            UpdateParseError::ExpectedDocumentObject(ref key) => format!("expected \"{}\" to be an object", key),
+            UpdateParseError::ExpectedBoolean(ref key) => format!("expected \"{}\" to be a boolean", key),
+            UpdateParseError::ScriptParseError(ref e) => format!("failed to parse script: {}", e.message()),
+            UpdateParseError::NothingToUpdate => "the update must have either \"doc\" or \"script\"".to_string(),
+            UpdateParseError::DocAndScript => "the update can't have both \"doc\" and \"script\"".to_string(),
+            UpdateParseError::UnrecognisedKey(ref key) => format!("unrecognised key \"{}\"", key),
+            UpdateParseError::InvalidValue(ref key) => format!("invalid value for \"{}\"", key),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum UpdateError {
+    /// The document doesn't exist and the update has nothing to create it from
+    DocumentMissing,
+
+    ScriptError(ScriptError),
+}
+
+
+impl From<ScriptError> for UpdateError {
+    fn from(e: ScriptError) -> UpdateError {
+        UpdateError::ScriptError(e)
+    }
+}
+
+
+impl UpdateError {
+    pub fn message(&self) -> String {
+        match *self {
+            UpdateError::DocumentMissing => "document missing".to_string(),
+            UpdateError::ScriptError(ref e) => format!("failed to execute script: {}", e.message()),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum UpdateResult {
+    /// The document didn't exist, it should be indexed with this source
+    Created(Map<String, Json>),
+
+    /// The document should be reindexed with this source
+    Updated(Map<String, Json>),
+
+    /// The update wouldn't change the document
+    Noop,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    doc: Option<Map<String, Json>>,
+    script: Option<Script>,
+    upsert: Option<Map<String, Json>>,
+    doc_as_upsert: bool,
+}
+
+
+impl Update {
+    /// Parses the body of an update action
+    pub fn parse(json: &Json) -> Result<Update, UpdateParseError> {
+        let object = json.as_object().ok_or(UpdateParseError::ExpectedObject)?;
+
+        let read_document = |key: &str| {
+            match object.get(key) {
+                Some(&Json::Object(ref document)) => Ok(Some(document.clone())),
+                Some(_) => Err(UpdateParseError::ExpectedDocumentObject(key.to_string())),
+                None => Ok(None),
+            }
+        };
+
+        let doc = read_document("doc")?;
+        let upsert = read_document("upsert")?;
+
+        let script = match object.get("script") {
+            Some(script) => Some(Script::parse(script)?),
+            None => None,
+        };
+
+        let doc_as_upsert = match object.get("doc_as_upsert") {
+            Some(&Json::Bool(doc_as_upsert)) => doc_as_upsert,
+            Some(_) => return Err(UpdateParseError::ExpectedBoolean("doc_as_upsert".to_string())),
+            None => false,
+        };
+
+        match (&doc, &script) {
+            (&None, &None) => return Err(UpdateParseError::NothingToUpdate),
+            (&Some(_), &Some(_)) => return Err(UpdateParseError::DocAndScript),
+            _ => {}
+        }
+
+        Ok(Update {
+            doc: doc,
+            script: script,
+            upsert: upsert,
+            doc_as_upsert: doc_as_upsert,
+        })
+    }
+
+    /// Works out the new source of the document from its current source
+    pub fn apply(&self, source: Option<Map<String, Json>>) -> Result<UpdateResult, UpdateError> {
+        let mut source = match source {
+            Some(source) => source,
+            None => {
+                // The script isn't run when the document is created from "upsert"
+                if let Some(ref upsert) = self.upsert {
+                    return Ok(UpdateResult::Created(upsert.clone()));
+                }
+
+                match self.doc {
+                    Some(ref doc) if self.doc_as_upsert => return Ok(UpdateResult::Created(doc.clone())),
+                    _ => return Err(UpdateError::DocumentMissing),
+                }
+            }
+        };
+
+        let original_source = source.clone();
+
+        if let Some(ref doc) = self.doc {
+            merge_objects(&mut source, doc);
+        }
+
+        if let Some(ref script) = self.script {
+            script.execute(&mut source)?;
+        }
+
+        if source == original_source {
+            Ok(UpdateResult::Noop)
+        } else {
+            Ok(UpdateResult::Updated(source))
+        }
+    }
+}
+
+
+/// Merges a partial document into a source, objects are merged recursively
+fn merge_objects(target: &mut Map<String, Json>, partial: &Map<String, Json>) {
+    for (key, value) in partial.iter() {
+        if let Json::Object(ref partial_inner) = *value {
+            if let Some(&mut Json::Object(ref mut target_inner)) = target.get_mut(key) {
+                merge_objects(target_inner, partial_inner);
+                continue;
+            }
+        }
+
+        target.insert(key.clone(), value.clone());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Map, Value as Json};
+
+    use super::{Update, UpdateResult, UpdateError, UpdateParseError};
+
+    fn object(json: Json) -> Map<String, Json> {
+        match json {
+            Json::Object(object) => object,
+            _ => panic!("expected an object"),
+        }
+    }
+
+    #[test]
+    fn test_merge_doc() {
+        let update = Update::parse(&json!({"doc": {"title": "bar", "stats": {"views": 2}}})).unwrap();
+        let source = object(json!({"title": "foo", "stats": {"views": 1, "likes": 3}}));
+
+        assert_eq!(update.apply(Some(source)), Ok(UpdateResult::Updated(object(json!({"title": "bar", "stats": {"views": 2, "likes": 3}})))));
+    }
+
+    #[test]
+    fn test_unchanged_doc_is_noop() {
+        let update = Update::parse(&json!({"doc": {"title": "foo"}})).unwrap();
+
+        assert_eq!(update.apply(Some(object(json!({"title": "foo", "views": 1})))), Ok(UpdateResult::Noop));
+    }
+
+    #[test]
+    fn test_missing_document() {
+        let update = Update::parse(&json!({"doc": {"title": "foo"}})).unwrap();
+        assert_eq!(update.apply(None), Err(UpdateError::DocumentMissing));
+
+        let update = Update::parse(&json!({"doc": {"title": "foo"}, "doc_as_upsert": true})).unwrap();
+        assert_eq!(update.apply(None), Ok(UpdateResult::Created(object(json!({"title": "foo"})))));
+    }
+
+    #[test]
+    fn test_scripted_increment_with_upsert() {
+        let update = Update::parse(&json!({
+            "script": {"source": "ctx._source.counter += params.count", "params": {"count": 2}},
+            "upsert": {"counter": 1},
+        })).unwrap();
+
+        // The first update creates the document from "upsert", without running the script
+        assert_eq!(update.apply(None), Ok(UpdateResult::Created(object(json!({"counter": 1})))));
+        assert_eq!(update.apply(Some(object(json!({"counter": 1})))), Ok(UpdateResult::Updated(object(json!({"counter": 3})))));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Update::parse(&json!({"upsert": {}})), Err(UpdateParseError::NothingToUpdate));
+        assert_eq!(Update::parse(&json!({"doc": {}, "script": "ctx._source.x = 1"})), Err(UpdateParseError::DocAndScript));
+        assert_eq!(Update::parse(&json!({"doc": "foo"})), Err(UpdateParseError::ExpectedDocumentObject("doc".to_string())));
+        assert_eq!(Update::parse(&json!({"doc": {}, "doc_as_upsert": "yes"})), Err(UpdateParseError::ExpectedBoolean("doc_as_upsert".to_string())));
+    }
+}
@@ -0,0 +1,865 @@
+//! A small expression language for update scripts
+//!
+//! This supports the subset of Painless that counter-style updates use. A script is
+//! a list of assignments to fields of the document, separated by semicolons:
+//!
+//! ```text
+//! ctx._source.counter += params.count; ctx._source.last_seen = params.now
+//! ```
+//!
+//! Fields can be assigned with `=`, `+=`, `-=`, `*=` and `/=`, or incremented and
+//! decremented with `++` and `--`. Expressions can use numbers, strings, `true`,
+//! `false`, `null`, fields of the document (`ctx._source.field`), parameters
+//! (`params.name`), parentheses and the `+`, `-`, `*`, `/` and `%` operators.
+//! Adding a string to anything concatenates them.
+//!
+//! Runtime fields use the same expressions, but compute values rather than assigning
+//! them. Each value is given to `emit`, and the document is read through
+//! `params._source.field` or its doc values (`doc['field'].value`):
+//!
+//! ```text
+//! emit(params._source.price * (1 + doc['tax_rate'].value))
+//! ```
+
+use std::fmt;
+
+use serde_json::{Map, Number, Value as Json};
+
+
+#[derive(Debug, PartialEq)]
+pub enum ScriptParseError {
+    ExpectedString,
+    ExpectedObject,
+    MissingSource,
+    UnsupportedLanguage(String),
+    UnexpectedCharacter(usize, char),
+    UnterminatedString(usize),
+    InvalidNumber(String),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+
+    /// Only fields of "ctx._source" can be assigned to
+    InvalidAssignmentTarget,
+}
+
+
+impl ScriptParseError {
+    pub fn message(&self) -> String {
+        match *self {
+            ScriptParseError::ExpectedString => "expected the script source to be a string".to_string(),
+            ScriptParseError::ExpectedObject => "expected the script to be a string or an object".to_string(),
+            ScriptParseError::MissingSource => "script has no \"source\"".to_string(),
+            ScriptParseError::UnsupportedLanguage(ref lang) => format!("unsupported script language: {}", lang),
+            ScriptParseError::UnexpectedCharacter(position, character) => format!("unexpected character {:?} at position {}", character, position),
+            ScriptParseError::UnterminatedString(position) => format!("unterminated string starting at position {}", position),
+            ScriptParseError::InvalidNumber(ref number) => format!("invalid number: {}", number),
+            ScriptParseError::UnexpectedToken(ref token) => format!("unexpected {}", token),
+            ScriptParseError::UnexpectedEnd => "unexpected end of script".to_string(),
+            ScriptParseError::InvalidAssignmentTarget => "only fields of ctx._source can be assigned to".to_string(),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum ScriptError {
+    /// The operator can't be applied to these values
+    InvalidOperands(BinaryOperator, Json, Json),
+    Overflow,
+    DivideByZero,
+
+    /// A field along the path is set to something that isn't an object
+    NotAnObject(String),
+}
+
+
+impl ScriptError {
+    pub fn message(&self) -> String {
+        match *self {
+            ScriptError::InvalidOperands(operator, ref left, ref right) => format!("cannot apply {} to {} and {}", operator, left, right),
+            ScriptError::Overflow => "arithmetic overflow".to_string(),
+            ScriptError::DivideByZero => "division by zero".to_string(),
+            ScriptError::NotAnObject(ref path) => format!("{} is not an object", path),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match *self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Remainder => "%",
+        };
+
+        write!(f, "{}", symbol)
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expression {
+    Literal(Json),
+    SourceField(Vec<String>),
+    Param(Vec<String>),
+
+    /// The first doc value of a field, only in runtime scripts
+    DocValue(String),
+    Negate(Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+struct Assignment {
+    /// Path of the field in the source
+    target: Vec<String>,
+
+    /// Combined with the field's current value, for compound assignments (eg, "+=")
+    operator: Option<BinaryOperator>,
+    value: Expression,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    statements: Vec<Assignment>,
+    params: Map<String, Json>,
+}
+
+
+impl Script {
+    /// Parses a script from an update request
+    ///
+    /// This can either be a string or an object with "source" (or "inline"),
+    /// "params" and "lang" keys.
+    pub fn parse(json: &Json) -> Result<Script, ScriptParseError> {
+        let (source, params) = read_script_json(json)?;
+
+        let tokens = tokenise(source)?;
+        let mut parser = Parser { tokens: tokens, position: 0, is_runtime: false };
+        let statements = parser.parse_statements()?;
+
+        Ok(Script {
+            statements: statements,
+            params: params,
+        })
+    }
+
+    /// Runs the script against the source of a document
+    ///
+    /// If an error occurs part way through, the statements before it are left applied.
+    pub fn execute(&self, source: &mut Map<String, Json>) -> Result<(), ScriptError> {
+        let doc = Map::new();
+
+        for statement in self.statements.iter() {
+            let mut value = evaluate(&statement.value, &self.params, source, &doc)?;
+
+            if let Some(operator) = statement.operator {
+                let current_value = lookup(source, &statement.target).cloned().unwrap_or(Json::Null);
+                value = apply_operator(operator, current_value, value)?;
+            }
+
+            assign(source, &statement.target, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// A script that computes the values of a runtime field
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeScript {
+    /// The expression given to each "emit"
+    emits: Vec<Expression>,
+    params: Map<String, Json>,
+}
+
+
+impl RuntimeScript {
+    /// Parses the script of a runtime field, this takes the same forms as `Script::parse`
+    pub fn parse(json: &Json) -> Result<RuntimeScript, ScriptParseError> {
+        let (source, params) = read_script_json(json)?;
+
+        let tokens = tokenise(source)?;
+        let mut parser = Parser { tokens: tokens, position: 0, is_runtime: true };
+        let emits = parser.parse_emits()?;
+
+        Ok(RuntimeScript {
+            emits: emits,
+            params: params,
+        })
+    }
+
+    /// The fields whose doc values the script reads
+    pub fn doc_fields(&self) -> Vec<String> {
+        let mut fields = Vec::new();
+        for expression in self.emits.iter() {
+            collect_doc_fields(expression, &mut fields);
+        }
+
+        fields
+    }
+
+    /// Computes the values of a document
+    ///
+    /// The doc values are given as a list of values for each field. Emitted nulls are
+    /// left out, so a document without the fields the script reads has no values.
+    pub fn execute(&self, source: &Map<String, Json>, doc: &Map<String, Json>) -> Result<Vec<Json>, ScriptError> {
+        let mut values = Vec::with_capacity(self.emits.len());
+
+        for expression in self.emits.iter() {
+            match evaluate(expression, &self.params, source, doc) {
+                Ok(Json::Null) => {}
+                Ok(value) => values.push(value),
+
+                // Arithmetic on a missing value, the same as emitting null
+                Err(ScriptError::InvalidOperands(_, Json::Null, _)) | Err(ScriptError::InvalidOperands(_, _, Json::Null)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(values)
+    }
+}
+
+
+/// Reads a script that's either a string or an object with "source" (or "inline"),
+/// "params" and "lang" keys
+fn read_script_json(json: &Json) -> Result<(&str, Map<String, Json>), ScriptParseError> {
+    match *json {
+        Json::String(ref source) => Ok((source.as_str(), Map::new())),
+        Json::Object(ref object) => {
+            if let Some(lang) = object.get("lang") {
+                match lang.as_str() {
+                    Some("painless") | Some("expression") => {}
+                    _ => return Err(ScriptParseError::UnsupportedLanguage(lang.to_string())),
+                }
+            }
+
+            let source = match object.get("source").or_else(|| object.get("inline")) {
+                Some(&Json::String(ref source)) => source.as_str(),
+                Some(_) => return Err(ScriptParseError::ExpectedString),
+                None => return Err(ScriptParseError::MissingSource),
+            };
+
+            let params = match object.get("params") {
+                Some(&Json::Object(ref params)) => params.clone(),
+                Some(_) => return Err(ScriptParseError::ExpectedObject),
+                None => Map::new(),
+            };
+
+            Ok((source, params))
+        }
+        _ => Err(ScriptParseError::ExpectedObject),
+    }
+}
+
+
+fn evaluate(expression: &Expression, params: &Map<String, Json>, source: &Map<String, Json>, doc: &Map<String, Json>) -> Result<Json, ScriptError> {
+    match *expression {
+        Expression::Literal(ref value) => Ok(value.clone()),
+        Expression::SourceField(ref path) => Ok(lookup(source, path).cloned().unwrap_or(Json::Null)),
+        Expression::Param(ref path) => Ok(lookup(params, path).cloned().unwrap_or(Json::Null)),
+        Expression::DocValue(ref field) => {
+            match doc.get(field) {
+                Some(&Json::Array(ref values)) => Ok(values.first().cloned().unwrap_or(Json::Null)),
+                Some(value) => Ok(value.clone()),
+                None => Ok(Json::Null),
+            }
+        }
+        Expression::Negate(ref expression) => {
+            let value = evaluate(expression, params, source, doc)?;
+            apply_operator(BinaryOperator::Subtract, json!(0), value)
+        }
+        Expression::Binary(operator, ref left, ref right) => {
+            let left = evaluate(left, params, source, doc)?;
+            let right = evaluate(right, params, source, doc)?;
+            apply_operator(operator, left, right)
+        }
+    }
+}
+
+
+fn collect_doc_fields(expression: &Expression, fields: &mut Vec<String>) {
+    match *expression {
+        Expression::DocValue(ref field) => {
+            if !fields.contains(field) {
+                fields.push(field.clone());
+            }
+        }
+        Expression::Negate(ref expression) => collect_doc_fields(expression, fields),
+        Expression::Binary(_, ref left, ref right) => {
+            collect_doc_fields(left, fields);
+            collect_doc_fields(right, fields);
+        }
+        Expression::Literal(_) | Expression::SourceField(_) | Expression::Param(_) => {}
+    }
+}
+
+
+fn lookup<'a>(object: &'a Map<String, Json>, path: &[String]) -> Option<&'a Json> {
+    let (first, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return None,
+    };
+
+    let value = object.get(first);
+    if rest.is_empty() {
+        return value;
+    }
+
+    match value {
+        Some(&Json::Object(ref inner)) => lookup(inner, rest),
+        _ => None,
+    }
+}
+
+
+/// Sets a field of the source, creating any objects along the way
+fn assign(object: &mut Map<String, Json>, path: &[String], value: Json) -> Result<(), ScriptError> {
+    let mut object = object;
+    for (i, name) in path[..path.len() - 1].iter().enumerate() {
+        let inner = object.entry(name.clone()).or_insert_with(|| Json::Object(Map::new()));
+
+        object = match *inner {
+            Json::Object(ref mut inner) => inner,
+            _ => return Err(ScriptError::NotAnObject(path[..i + 1].join("."))),
+        };
+    }
+
+    object.insert(path[path.len() - 1].clone(), value);
+    Ok(())
+}
+
+
+fn apply_operator(operator: BinaryOperator, left: Json, right: Json) -> Result<Json, ScriptError> {
+    // Adding a string to anything concatenates them
+    if operator == BinaryOperator::Add && (left.is_string() || right.is_string()) && !left.is_null() && !right.is_null() {
+        let mut result = to_concatenated_string(&left);
+        result.push_str(&to_concatenated_string(&right));
+        return Ok(Json::String(result));
+    }
+
+    let invalid_operands = || ScriptError::InvalidOperands(operator, left.clone(), right.clone());
+
+    // Integer arithmetic, if both sides are integers
+    if let (Some(a), Some(b)) = (left.as_i64(), right.as_i64()) {
+        let result = match operator {
+            BinaryOperator::Add => a.checked_add(b),
+            BinaryOperator::Subtract => a.checked_sub(b),
+            BinaryOperator::Multiply => a.checked_mul(b),
+            BinaryOperator::Divide | BinaryOperator::Remainder if b == 0 => return Err(ScriptError::DivideByZero),
+            BinaryOperator::Divide => a.checked_div(b),
+            BinaryOperator::Remainder => a.checked_rem(b),
+        };
+
+        return result.map(Json::from).ok_or(ScriptError::Overflow);
+    }
+
+    let (a, b) = match (left.as_f64(), right.as_f64()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return Err(invalid_operands()),
+    };
+
+    let result = match operator {
+        BinaryOperator::Add => a + b,
+        BinaryOperator::Subtract => a - b,
+        BinaryOperator::Multiply => a * b,
+        BinaryOperator::Divide | BinaryOperator::Remainder if b == 0.0 => return Err(ScriptError::DivideByZero),
+        BinaryOperator::Divide => a / b,
+        BinaryOperator::Remainder => a % b,
+    };
+
+    Number::from_f64(result).map(Json::Number).ok_or(ScriptError::Overflow)
+}
+
+
+fn to_concatenated_string(value: &Json) -> String {
+    match *value {
+        Json::String(ref string) => string.clone(),
+        ref value => value.to_string(),
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Json),
+    String(String),
+    Identifier(String),
+    Symbol(&'static str),
+}
+
+
+impl Token {
+    fn describe(&self) -> String {
+        match *self {
+            Token::Number(ref number) => format!("number {}", number),
+            Token::String(ref string) => format!("string {:?}", string),
+            Token::Identifier(ref identifier) => format!("identifier {}", identifier),
+            Token::Symbol(symbol) => format!("\"{}\"", symbol),
+        }
+    }
+}
+
+
+/// Symbols, longest first so "+=" isn't read as "+" followed by "="
+const SYMBOLS: &'static [&'static str] = &["++", "--", "+=", "-=", "*=", "/=", "+", "-", "*", "/", "%", "=", ".", "[", "]", "(", ")", ";"];
+
+
+fn tokenise(source: &str) -> Result<Vec<Token>, ScriptParseError> {
+    let chars = source.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    'outer: while position < chars.len() {
+        let c = chars[position];
+
+        if c.is_whitespace() {
+            position += 1;
+            continue;
+        }
+
+        // Numbers
+        if c.is_digit(10) {
+            let start = position;
+            while position < chars.len() && (chars[position].is_digit(10) || chars[position] == '.') {
+                position += 1;
+            }
+
+            let number = chars[start..position].iter().collect::<String>();
+            let value = if number.contains('.') {
+                number.parse::<f64>().ok().and_then(Number::from_f64).map(Json::Number)
+            } else {
+                number.parse::<i64>().ok().map(Json::from)
+            };
+
+            match value {
+                Some(value) => tokens.push(Token::Number(value)),
+                None => return Err(ScriptParseError::InvalidNumber(number)),
+            }
+            continue;
+        }
+
+        // Strings, which may be quoted with either kind of quote
+        if c == '"' || c == '\'' {
+            let start = position;
+            let mut string = String::new();
+            position += 1;
+
+            loop {
+                match chars.get(position) {
+                    Some(&'\\') => {
+                        match chars.get(position + 1) {
+                            Some(&escaped) => string.push(escaped),
+                            None => return Err(ScriptParseError::UnterminatedString(start)),
+                        }
+                        position += 2;
+                    }
+                    Some(&end) if end == c => {
+                        position += 1;
+                        break;
+                    }
+                    Some(&other) => {
+                        string.push(other);
+                        position += 1;
+                    }
+                    None => return Err(ScriptParseError::UnterminatedString(start)),
+                }
+            }
+
+            tokens.push(Token::String(string));
+            continue;
+        }
+
+        // Identifiers
+        if c.is_alphabetic() || c == '_' {
+            let start = position;
+            while position < chars.len() && (chars[position].is_alphanumeric() || chars[position] == '_') {
+                position += 1;
+            }
+
+            tokens.push(Token::Identifier(chars[start..position].iter().collect()));
+            continue;
+        }
+
+        for symbol in SYMBOLS.iter() {
+            let length = symbol.len();
+            if position + length <= chars.len() && chars[position..position + length].iter().cloned().eq(symbol.chars()) {
+                tokens.push(Token::Symbol(*symbol));
+                position += length;
+                continue 'outer;
+            }
+        }
+
+        return Err(ScriptParseError::UnexpectedCharacter(position, c));
+    }
+
+    Ok(tokens)
+}
+
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+
+    /// Runtime scripts can read doc values and the source through "params._source"
+    is_runtime: bool,
+}
+
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, ScriptParseError> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            }
+            None => Err(ScriptParseError::UnexpectedEnd),
+        }
+    }
+
+    fn next_is_symbol(&self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Symbol(s)) => s == symbol,
+            _ => false,
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), ScriptParseError> {
+        match self.next()? {
+            Token::Symbol(s) if s == symbol => Ok(()),
+            token => Err(ScriptParseError::UnexpectedToken(token.describe())),
+        }
+    }
+
+    fn parse_statements(&mut self) -> Result<Vec<Assignment>, ScriptParseError> {
+        let mut statements = Vec::new();
+
+        while self.peek().is_some() {
+            // Allow empty statements, like a trailing semicolon
+            if self.next_is_symbol(";") {
+                self.position += 1;
+                continue;
+            }
+
+            statements.push(self.parse_assignment()?);
+
+            match self.peek() {
+                Some(&Token::Symbol(";")) | None => {}
+                Some(token) => return Err(ScriptParseError::UnexpectedToken(token.describe())),
+            }
+        }
+
+        Ok(statements)
+    }
+
+    /// Parses the "emit(...)" statements of a runtime script
+    fn parse_emits(&mut self) -> Result<Vec<Expression>, ScriptParseError> {
+        let mut emits = Vec::new();
+
+        while self.peek().is_some() {
+            if self.next_is_symbol(";") {
+                self.position += 1;
+                continue;
+            }
+
+            match self.next()? {
+                Token::Identifier(ref name) if name == "emit" => {}
+                token => return Err(ScriptParseError::UnexpectedToken(token.describe())),
+            }
+
+            self.expect_symbol("(")?;
+            emits.push(self.parse_expression()?);
+            self.expect_symbol(")")?;
+
+            match self.peek() {
+                Some(&Token::Symbol(";")) | None => {}
+                Some(token) => return Err(ScriptParseError::UnexpectedToken(token.describe())),
+            }
+        }
+
+        Ok(emits)
+    }
+
+    fn parse_assignment(&mut self) -> Result<Assignment, ScriptParseError> {
+        let target = match self.parse_variable()? {
+            Expression::SourceField(ref path) if !path.is_empty() => path.clone(),
+            _ => return Err(ScriptParseError::InvalidAssignmentTarget),
+        };
+
+        let (operator, value) = match self.next()? {
+            Token::Symbol("=") => (None, self.parse_expression()?),
+            Token::Symbol("+=") => (Some(BinaryOperator::Add), self.parse_expression()?),
+            Token::Symbol("-=") => (Some(BinaryOperator::Subtract), self.parse_expression()?),
+            Token::Symbol("*=") => (Some(BinaryOperator::Multiply), self.parse_expression()?),
+            Token::Symbol("/=") => (Some(BinaryOperator::Divide), self.parse_expression()?),
+            Token::Symbol("++") => (Some(BinaryOperator::Add), Expression::Literal(json!(1))),
+            Token::Symbol("--") => (Some(BinaryOperator::Subtract), Expression::Literal(json!(1))),
+            token => return Err(ScriptParseError::UnexpectedToken(token.describe())),
+        };
+
+        Ok(Assignment {
+            target: target,
+            operator: operator,
+            value: value,
+        })
+    }
+
+    /// Parses "doc['field'].value"
+    fn parse_doc_value(&mut self) -> Result<Expression, ScriptParseError> {
+        self.expect_symbol("[")?;
+        let field = match self.next()? {
+            Token::String(field) => field,
+            token => return Err(ScriptParseError::UnexpectedToken(token.describe())),
+        };
+        self.expect_symbol("]")?;
+
+        self.expect_symbol(".")?;
+        match self.next()? {
+            Token::Identifier(ref name) if name == "value" => Ok(Expression::DocValue(field)),
+            token => Err(ScriptParseError::UnexpectedToken(token.describe())),
+        }
+    }
+
+    /// Parses "ctx._source" or "params", followed by a path
+    fn parse_variable(&mut self) -> Result<Expression, ScriptParseError> {
+        let is_doc = match self.peek() {
+            Some(&Token::Identifier(ref name)) => self.is_runtime && name == "doc",
+            _ => false,
+        };
+
+        if is_doc {
+            self.position += 1;
+            return self.parse_doc_value();
+        }
+
+        let is_source = match self.next()? {
+            Token::Identifier(ref name) if name == "ctx" => {
+                self.expect_symbol(".")?;
+                match self.next()? {
+                    Token::Identifier(ref name) if name == "_source" => true,
+                    token => return Err(ScriptParseError::UnexpectedToken(token.describe())),
+                }
+            }
+            Token::Identifier(ref name) if name == "params" => false,
+            token => return Err(ScriptParseError::UnexpectedToken(token.describe())),
+        };
+
+        // Fields can be accessed with "a.b" or "a['b']"
+        let mut path = Vec::new();
+        loop {
+            if self.next_is_symbol(".") {
+                self.position += 1;
+                match self.next()? {
+                    Token::Identifier(name) => path.push(name),
+                    token => return Err(ScriptParseError::UnexpectedToken(token.describe())),
+                }
+            } else if self.next_is_symbol("[") {
+                self.position += 1;
+                match self.next()? {
+                    Token::String(name) => path.push(name),
+                    token => return Err(ScriptParseError::UnexpectedToken(token.describe())),
+                }
+                self.expect_symbol("]")?;
+            } else {
+                break;
+            }
+        }
+
+        if is_source {
+            Ok(Expression::SourceField(path))
+        } else if self.is_runtime && path.first().map(|name| name == "_source").unwrap_or(false) {
+            Ok(Expression::SourceField(path[1..].to_vec()))
+        } else {
+            Ok(Expression::Param(path))
+        }
+    }
+
+    fn parse_expression(&mut self) -> Result<Expression, ScriptParseError> {
+        let mut expression = self.parse_term()?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(&Token::Symbol("+")) => BinaryOperator::Add,
+                Some(&Token::Symbol("-")) => BinaryOperator::Subtract,
+                _ => return Ok(expression),
+            };
+
+            self.position += 1;
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(self.parse_term()?));
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Expression, ScriptParseError> {
+        let mut expression = self.parse_factor()?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(&Token::Symbol("*")) => BinaryOperator::Multiply,
+                Some(&Token::Symbol("/")) => BinaryOperator::Divide,
+                Some(&Token::Symbol("%")) => BinaryOperator::Remainder,
+                _ => return Ok(expression),
+            };
+
+            self.position += 1;
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(self.parse_factor()?));
+        }
+    }
+
+    fn parse_factor(&mut self) -> Result<Expression, ScriptParseError> {
+        match self.peek().cloned() {
+            Some(Token::Number(number)) => {
+                self.position += 1;
+                Ok(Expression::Literal(number))
+            }
+            Some(Token::String(string)) => {
+                self.position += 1;
+                Ok(Expression::Literal(Json::String(string)))
+            }
+            Some(Token::Identifier(ref name)) if name == "true" || name == "false" || name == "null" => {
+                self.position += 1;
+                Ok(Expression::Literal(match name.as_str() {
+                    "true" => Json::Bool(true),
+                    "false" => Json::Bool(false),
+                    _ => Json::Null,
+                }))
+            }
+            Some(Token::Identifier(_)) => self.parse_variable(),
+            Some(Token::Symbol("(")) => {
+                self.position += 1;
+                let expression = self.parse_expression()?;
+                self.expect_symbol(")")?;
+                Ok(expression)
+            }
+            Some(Token::Symbol("-")) => {
+                self.position += 1;
+                Ok(Expression::Negate(Box::new(self.parse_factor()?)))
+            }
+            Some(token) => Err(ScriptParseError::UnexpectedToken(token.describe())),
+            None => Err(ScriptParseError::UnexpectedEnd),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Map, Value as Json};
+
+    use super::{Script, RuntimeScript, ScriptParseError, ScriptError, BinaryOperator};
+
+    fn run(script: Json, source: Json) -> Result<Json, ScriptError> {
+        let script = Script::parse(&script).unwrap();
+        let mut source = match source {
+            Json::Object(source) => source,
+            _ => Map::new(),
+        };
+
+        script.execute(&mut source)?;
+        Ok(Json::Object(source))
+    }
+
+    #[test]
+    fn test_increment() {
+        assert_eq!(run(json!("ctx._source.counter += 1"), json!({"counter": 4, "title": "foo"})), Ok(json!({"counter": 5, "title": "foo"})));
+        assert_eq!(run(json!("ctx._source.counter++"), json!({"counter": 4})), Ok(json!({"counter": 5})));
+        assert_eq!(run(json!("ctx._source.counter--"), json!({"counter": 4})), Ok(json!({"counter": 3})));
+    }
+
+    #[test]
+    fn test_params() {
+        let script = json!({
+            "source": "ctx._source.counter += params.count; ctx._source.stats['last'] = params.count * 2",
+            "lang": "painless",
+            "params": {"count": 3},
+        });
+
+        assert_eq!(run(script, json!({"counter": 1})), Ok(json!({"counter": 4, "stats": {"last": 6}})));
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(run(json!("ctx._source.x = 1 + 2 * 3 - (4 - 2) / 2"), json!({})), Ok(json!({"x": 6})));
+        assert_eq!(run(json!("ctx._source.x = -ctx._source.y % 4"), json!({"y": 7})), Ok(json!({"x": -3, "y": 7})));
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(run(json!("ctx._source.price *= 1.5"), json!({"price": 10})), Ok(json!({"price": 15.0})));
+    }
+
+    #[test]
+    fn test_string_concatenation() {
+        assert_eq!(run(json!("ctx._source.name = ctx._source.name + ' ' + 2"), json!({"name": "foo"})), Ok(json!({"name": "foo 2"})));
+    }
+
+    #[test]
+    fn test_missing_field() {
+        assert_eq!(run(json!("ctx._source.counter += 1"), json!({})), Err(ScriptError::InvalidOperands(BinaryOperator::Add, Json::Null, json!(1))));
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        assert_eq!(run(json!("ctx._source.x = 1 / 0"), json!({})), Err(ScriptError::DivideByZero));
+        assert_eq!(run(json!("ctx._source.x += 1"), json!({"x": 9223372036854775807i64})), Err(ScriptError::Overflow));
+        assert_eq!(run(json!("ctx._source.x.y = 1"), json!({"x": 1})), Err(ScriptError::NotAnObject("x".to_string())));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Script::parse(&json!("params.x = 1")), Err(ScriptParseError::InvalidAssignmentTarget));
+        assert_eq!(Script::parse(&json!("ctx._source.x = ")), Err(ScriptParseError::UnexpectedEnd));
+        assert_eq!(Script::parse(&json!("ctx._source.x = 1 2")), Err(ScriptParseError::UnexpectedToken("number 2".to_string())));
+        assert_eq!(Script::parse(&json!("ctx._source.x = 'foo")), Err(ScriptParseError::UnterminatedString(16)));
+        assert_eq!(Script::parse(&json!("ctx._source.x = 1 # 2")), Err(ScriptParseError::UnexpectedCharacter(18, '#')));
+        assert_eq!(Script::parse(&json!({"source": "ctx._source.x = 1", "lang": "groovy"})), Err(ScriptParseError::UnsupportedLanguage("\"groovy\"".to_string())));
+        assert_eq!(Script::parse(&json!({"params": {}})), Err(ScriptParseError::MissingSource));
+    }
+
+    #[test]
+    fn test_runtime_script() {
+        let script = RuntimeScript::parse(&json!({
+            "source": "emit(params._source.price * (1 + doc['tax_rate'].value)); emit(params.label)",
+            "params": {"label": "gross"},
+        })).unwrap();
+        assert_eq!(script.doc_fields(), vec!["tax_rate".to_string()]);
+
+        let source = json!({"price": 10, "tax_rate": 1});
+        let doc = json!({"tax_rate": [0.5]});
+        assert_eq!(script.execute(source.as_object().unwrap(), doc.as_object().unwrap()), Ok(vec![json!(15.0), json!("gross")]));
+
+        // Missing values aren't emitted
+        let source = json!({});
+        let doc = json!({});
+        assert_eq!(script.execute(source.as_object().unwrap(), doc.as_object().unwrap()), Ok(vec![json!("gross")]));
+    }
+
+    #[test]
+    fn test_runtime_script_parse_errors() {
+        assert_eq!(RuntimeScript::parse(&json!("params._source.x")), Err(ScriptParseError::UnexpectedToken("identifier params".to_string())));
+        assert_eq!(RuntimeScript::parse(&json!("emit(doc['x'])")), Err(ScriptParseError::UnexpectedToken("\")\"".to_string())));
+
+        // Doc values can only be read by runtime scripts
+        assert_eq!(Script::parse(&json!("ctx._source.x = doc['x'].value")), Err(ScriptParseError::UnexpectedToken("identifier doc".to_string())));
+    }
+}
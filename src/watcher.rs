@@ -0,0 +1,406 @@
+//! Watches, which call a webhook when a query matches enough documents
+//!
+//! A watch is registered with the "_watcher" API:
+//!
+//! ```json
+//! {
+//!     "index": "logs",
+//!     "query": {"match": {"level": "error"}},
+//!     "threshold": 10,
+//!     "webhook": "http://alerts.example.com/hook",
+//!     "interval": 60
+//! }
+//! ```
+//!
+//! Watches are run by the maintenance thread every "interval" seconds and also on the
+//! next tick after documents are bulk indexed into their index. If the query matches
+//! at least "threshold" documents, the webhook is called with a POST request containing
+//! the id of the watch and the number of hits.
+//!
+//! Watches are saved to "watches.json" in the data directory after every change.
+
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::path::{Path, PathBuf};
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use serde_json::{self, Value as Json};
+use url::Url;
+use atomicwrites::{AtomicFile, AllowOverwrite};
+
+use search::collectors::total_count::TotalCountCollector;
+use query_parser::{QueryBuildContext, parse as parse_query};
+use cluster::terms_lookup::ClusterTermsLookup;
+use remote::http::request;
+use system::System;
+
+
+/// How long to wait for a webhook to respond
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+
+fn default_threshold() -> u64 {
+    1
+}
+
+
+fn default_interval() -> u64 {
+    60
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Watch {
+    /// Name or alias of the index to search
+    pub index: String,
+
+    pub query: Json,
+
+    /// The minimum number of hits for the webhook to be called
+    #[serde(default = "default_threshold")]
+    pub threshold: u64,
+
+    pub webhook: String,
+
+    /// Seconds between runs
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum WatchParseError {
+    InvalidJson(String),
+    InvalidQuery,
+    InvalidWebhook(String),
+    InvalidInterval,
+}
+
+
+impl From<WatchParseError> for String {
+    fn from(e: WatchParseError) -> String {
+        match e {
+            WatchParseError::InvalidJson(message) => format!("invalid watch: {}", message),
+            WatchParseError::InvalidQuery => "invalid query".to_string(),
+            WatchParseError::InvalidWebhook(url) => format!("invalid webhook url: {}", url),
+            WatchParseError::InvalidInterval => "interval must be at least 1 second".to_string(),
+        }
+    }
+}
+
+
+impl Watch {
+    pub fn parse(json: Json) -> Result<Watch, WatchParseError> {
+        let watch: Watch = serde_json::from_value(json).map_err(|e| WatchParseError::InvalidJson(e.to_string()))?;
+
+        if parse_query(&watch.query).is_err() {
+            return Err(WatchParseError::InvalidQuery);
+        }
+
+        // Only plain HTTP is supported by the client
+        match Url::parse(&watch.webhook) {
+            Ok(ref url) if url.scheme() == "http" => {}
+            _ => return Err(WatchParseError::InvalidWebhook(watch.webhook.clone())),
+        }
+
+        if watch.interval == 0 {
+            return Err(WatchParseError::InvalidInterval);
+        }
+
+        Ok(watch)
+    }
+
+    /// Checks if the watch should run, either because it hasn't run for "interval"
+    /// seconds or because documents were indexed since it last ran
+    fn is_due(&self, last_run: Option<Instant>, now: Instant, has_new_documents: bool) -> bool {
+        match last_run {
+            Some(last_run) => has_new_documents || now.duration_since(last_run) >= Duration::from_secs(self.interval),
+            None => true,
+        }
+    }
+}
+
+
+fn read_json_file(path: &Path) -> Result<Option<String>, String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("failed to open {}: {}", path.display(), e)),
+    };
+
+    let mut s = String::new();
+    file.read_to_string(&mut s).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    Ok(Some(s))
+}
+
+
+#[derive(Debug, Default)]
+pub struct Watcher {
+    watches: RwLock<BTreeMap<String, Watch>>,
+
+    /// When each watch last ran, keyed by watch id
+    last_runs: Mutex<HashMap<String, Instant>>,
+
+    /// Canonical names of indices that have had documents indexed since the last tick
+    ingested: Mutex<HashSet<String>>,
+
+    /// Where watches are saved, watches aren't saved if this is None
+    path: Option<PathBuf>,
+}
+
+
+impl Watcher {
+    pub fn new(watches: BTreeMap<String, Watch>, path: Option<PathBuf>) -> Watcher {
+        Watcher {
+            watches: RwLock::new(watches),
+            last_runs: Mutex::new(HashMap::new()),
+            ingested: Mutex::new(HashSet::new()),
+            path: path,
+        }
+    }
+
+    /// Loads watches from the data directory
+    pub fn load(data_dir: &Path) -> Result<Watcher, String> {
+        let path = data_dir.join("watches.json");
+        let watches = match read_json_file(&path)? {
+            Some(s) => serde_json::from_str(&s).map_err(|e| format!("failed to parse watches.json: {}", e))?,
+            None => BTreeMap::new(),
+        };
+
+        Ok(Watcher::new(watches, Some(path)))
+    }
+
+    fn save(&self, watches: &BTreeMap<String, Watch>) -> Result<(), String> {
+        if let Some(ref path) = self.path {
+            let s = serde_json::to_string(watches).map_err(|e| format!("failed to save watches: {}", e))?;
+            let file = AtomicFile::new(path, AllowOverwrite);
+            file.write(|f| f.write_all(s.as_bytes())).map_err(|e| format!("failed to save watches: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_watch(&self, id: &str) -> Option<Watch> {
+        self.watches.read().unwrap().get(id).cloned()
+    }
+
+    /// Adds or replaces a watch, returns true if it was created
+    pub fn put_watch(&self, id: &str, watch: Watch) -> Result<bool, String> {
+        let mut watches = self.watches.write().unwrap();
+        let previous = watches.insert(id.to_string(), watch);
+
+        if let Err(e) = self.save(&watches) {
+            // Put the previous watch back so the watches match what was saved
+            match previous {
+                Some(previous) => watches.insert(id.to_string(), previous),
+                None => watches.remove(id),
+            };
+
+            return Err(e);
+        }
+
+        // Run the new watch on the next tick
+        self.last_runs.lock().unwrap().remove(id);
+
+        Ok(previous.is_none())
+    }
+
+    /// Removes a watch, returns false if it didn't exist
+    pub fn delete_watch(&self, id: &str) -> Result<bool, String> {
+        let mut watches = self.watches.write().unwrap();
+        let previous = match watches.remove(id) {
+            Some(previous) => previous,
+            None => return Ok(false),
+        };
+
+        if let Err(e) = self.save(&watches) {
+            watches.insert(id.to_string(), previous);
+            return Err(e);
+        }
+
+        self.last_runs.lock().unwrap().remove(id);
+
+        Ok(true)
+    }
+
+    /// Records that documents were indexed, so the watches on the index run on the next tick
+    pub fn record_ingest(&self, index_name: &str) {
+        if self.watches.read().unwrap().is_empty() {
+            return;
+        }
+
+        self.ingested.lock().unwrap().insert(index_name.to_string());
+    }
+
+    /// Runs every watch that is due, this is called by the maintenance thread
+    pub fn run_due_watches(&self, system: &System) {
+        let now = Instant::now();
+        let ingested = ::std::mem::replace(&mut *self.ingested.lock().unwrap(), HashSet::new());
+        let watches = self.watches.read().unwrap().clone();
+
+        for (id, watch) in watches {
+            let canonical_name = {
+                let cluster_metadata = system.metadata.read().unwrap();
+                cluster_metadata.names.find_canonical(&watch.index)
+                    .and_then(|index_ref| cluster_metadata.indices.get(&index_ref))
+                    .map(|index| index.canonical_name().to_string())
+            };
+            let has_new_documents = canonical_name.map(|name| ingested.contains(&name)).unwrap_or(false);
+
+            let last_run = self.last_runs.lock().unwrap().get(&id).cloned();
+            if !watch.is_due(last_run, now, has_new_documents) {
+                continue;
+            }
+            self.last_runs.lock().unwrap().insert(id.clone(), now);
+
+            match run_watch(system, &id, &watch) {
+                Ok(Some(hits)) => {
+                    info!(system.log, "watch triggered"; "watch" => &id, "index" => &watch.index, "hits" => hits);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(system.log, "watch failed"; "watch" => &id, "index" => &watch.index, "error" => e);
+                }
+            }
+        }
+    }
+}
+
+
+/// Counts the documents that match the query of a watch
+fn count_hits(system: &System, watch: &Watch) -> Result<u64, String> {
+    let query = parse_query(&watch.query).map_err(|e| format!("query error: {:?}", e))?;
+
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = match cluster_metadata.names.find_canonical(&watch.index).and_then(|index_ref| cluster_metadata.indices.get(&index_ref)) {
+        Some(index) => index,
+        None => return Err(format!("index not found: {}", watch.index)),
+    };
+
+    let store = index.store()?;
+    let index_reader = store.reader();
+    let index_metadata = index.metadata.read().unwrap();
+
+    let terms_lookup = ClusterTermsLookup::new(&cluster_metadata, None);
+    let build_context = QueryBuildContext::new().set_index_metadata(&index_metadata).set_terms_lookup(&terms_lookup).no_score();
+
+    let query = query.build(&build_context, &index_reader.schema());
+    index_reader.check_query_limits(&query, &system.query_limits())?;
+
+    let mut collector = TotalCountCollector::new();
+    index_reader.search(&mut collector, &query)?;
+    Ok(collector.get_total_count())
+}
+
+
+/// Runs a watch, calling its webhook if the threshold is reached
+///
+/// Returns the number of hits if the webhook was called.
+fn run_watch(system: &System, id: &str, watch: &Watch) -> Result<Option<u64>, String> {
+    let hits = count_hits(system, watch)?;
+    if hits < watch.threshold {
+        return Ok(None);
+    }
+
+    let url = Url::parse(&watch.webhook).map_err(|_| format!("invalid webhook url: {}", watch.webhook))?;
+    let body = json!({
+        "watch_id": id,
+        "index": watch.index,
+        "threshold": watch.threshold,
+        "hits": hits,
+    });
+    request("POST", &url, None, Some(&body), Duration::from_secs(WEBHOOK_TIMEOUT_SECS))?;
+
+    Ok(Some(hits))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use super::{Watch, Watcher, WatchParseError};
+
+    fn make_watch() -> Watch {
+        Watch::parse(json!({
+            "index": "logs",
+            "query": {"match": {"level": "error"}},
+            "webhook": "http://localhost:9999/hook"
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let watch = make_watch();
+
+        assert_eq!(watch.threshold, 1);
+        assert_eq!(watch.interval, 60);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Watch::parse(json!({
+            "index": "logs",
+            "query": {"foo": {}},
+            "webhook": "http://localhost:9999/hook"
+        })), Err(WatchParseError::InvalidQuery));
+
+        assert_eq!(Watch::parse(json!({
+            "index": "logs",
+            "query": {"match_all": {}},
+            "webhook": "https://localhost:9999/hook"
+        })), Err(WatchParseError::InvalidWebhook("https://localhost:9999/hook".to_string())));
+
+        assert_eq!(Watch::parse(json!({
+            "index": "logs",
+            "query": {"match_all": {}},
+            "webhook": "http://localhost:9999/hook",
+            "interval": 0
+        })), Err(WatchParseError::InvalidInterval));
+    }
+
+    #[test]
+    fn test_is_due() {
+        let watch = make_watch();
+        let now = Instant::now();
+        let last_run = now - Duration::from_secs(10);
+
+        assert!(watch.is_due(None, now, false));
+        assert!(!watch.is_due(Some(last_run), now, false));
+        assert!(watch.is_due(Some(last_run), now, true));
+        assert!(Watch { interval: 5, .. watch.clone() }.is_due(Some(last_run), now, false));
+    }
+
+    #[test]
+    fn test_watches_are_saved() {
+        let data_dir = Path::new("test_indices/test_watcher_watches_are_saved");
+        let _ = remove_dir_all(data_dir);
+        create_dir_all(data_dir).unwrap();
+
+        let watcher = Watcher::load(data_dir).unwrap();
+        assert_eq!(watcher.put_watch("errors", make_watch()), Ok(true));
+        assert_eq!(watcher.put_watch("errors", make_watch()), Ok(false));
+        assert_eq!(watcher.put_watch("other", make_watch()), Ok(true));
+        assert_eq!(watcher.delete_watch("other"), Ok(true));
+        assert_eq!(watcher.delete_watch("other"), Ok(false));
+
+        let watcher = Watcher::load(data_dir).unwrap();
+        assert_eq!(watcher.get_watch("errors"), Some(make_watch()));
+        assert_eq!(watcher.get_watch("other"), None);
+
+        remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_record_ingest_without_watches() {
+        let watcher = Watcher::new(BTreeMap::new(), None);
+        watcher.record_ingest("logs");
+
+        assert!(watcher.ingested.lock().unwrap().is_empty());
+    }
+}